@@ -0,0 +1,168 @@
+use std::collections::{BTreeSet, HashMap};
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rustengan::transport::Transport;
+use rustengan::wal::Wal;
+use rustengan::{Body, Event, Init, Message, Node, Output};
+
+// the broadcast workload's node, built from the same source as its own binary
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+use broadcast::{BroadcastNode, Gossip, InjectedPayload, Payload};
+
+// messages a node has by the time its gossip and reads get big
+const MESSAGES: usize = 10_000;
+const NEIGHBOURS: usize = 24;
+
+// somewhere for a node's messages to go without costing anything but the routing
+struct Discard;
+
+impl Transport for Discard {
+    fn send(&mut self, _dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        black_box(frame);
+        Ok(())
+    }
+}
+
+fn output() -> Output {
+    Output::routed(Box::new(Discard))
+}
+
+// n0 of a cluster of n0 and its neighbours, with every one of them as a neighbour
+fn node() -> BroadcastNode {
+    let node_ids: Vec<String> = (0..=NEIGHBOURS).map(|i| format!("n{}", i)).collect();
+    let (tx, _rx) = std::sync::mpsc::channel();
+    let init = Init {
+        node_id: node_ids[0].clone(),
+        node_ids: node_ids.clone(),
+    };
+    let mut node = BroadcastNode::from_init(Gossip::default(), init, tx).expect("node starts");
+    let topology = HashMap::from([(node_ids[0].clone(), node_ids[1..].to_vec())]);
+    node.step(request(Payload::Topology { topology }), &mut output())
+        .expect("node takes the topology");
+    node
+}
+
+fn request(payload: Payload) -> Event<Payload, InjectedPayload> {
+    Event::Message(Message {
+        src: "c1".to_string(),
+        dst: "n0".to_string(),
+        body: Body {
+            id: Some(1),
+            in_reply_to: None,
+            correlation_id: None,
+            payload,
+        },
+    })
+}
+
+fn parse_and_dispatch(c: &mut Criterion) {
+    let lines: Vec<String> = (0..1_000)
+        .map(|i| {
+            format!(
+                r#"{{"src":"c1","dest":"n0","body":{{"type":"broadcast","message":{},"msg_id":{}}}}}"#,
+                i, i
+            )
+        })
+        .collect();
+    let mut group = c.benchmark_group("parse_and_dispatch");
+    group.throughput(Throughput::Elements(lines.len() as u64));
+    group.bench_function("broadcast", |b| {
+        b.iter_batched(
+            || (node(), output()),
+            |(mut node, mut output)| {
+                for line in &lines {
+                    let message = rustengan::parse(line).expect("parses");
+                    node.step(Event::Message(message), &mut output)
+                        .expect("steps");
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn gossip(c: &mut Criterion) {
+    let mut node = node();
+    let mut output = output();
+    for message in 0..MESSAGES {
+        node.step(request(Payload::Broadcast { message }), &mut output)
+            .expect("node takes the broadcast");
+    }
+    // each neighbour has told us about a different half of what we have
+    for n in 1..=NEIGHBOURS {
+        let seen: BTreeSet<usize> = (0..MESSAGES).filter(|m| (m + n) % 2 == 0).collect();
+        let gossip = Message {
+            src: format!("n{}", n),
+            dst: "n0".to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload: Payload::Gossip { seen },
+            },
+        };
+        node.step(Event::Message(gossip), &mut output)
+            .expect("node takes the gossip");
+    }
+    let mut group = c.benchmark_group("gossip");
+    group.throughput(Throughput::Elements((MESSAGES * NEIGHBOURS) as u64));
+    group.bench_function("set_difference", |b| {
+        b.iter(|| {
+            node.step(Event::Injected(InjectedPayload::Gossip), &mut output)
+                .expect("node gossips")
+        })
+    });
+    group.finish();
+}
+
+fn read_ok(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize_read_ok");
+    for messages in [1_000, MESSAGES, 100_000] {
+        let reply = Message {
+            src: "n0".to_string(),
+            dst: "c1".to_string(),
+            body: Body {
+                id: Some(2),
+                in_reply_to: Some(1),
+                correlation_id: None,
+                payload: Payload::ReadOk {
+                    messages: (0..messages).collect(),
+                },
+            },
+        };
+        let mut out = Vec::new();
+        reply.send(&mut out).expect("serializes");
+        group.throughput(Throughput::Bytes(out.len() as u64));
+        group.bench_function(messages.to_string(), |b| {
+            b.iter(|| {
+                out.clear();
+                reply.send(&mut out).expect("serializes");
+            })
+        });
+    }
+    group.finish();
+}
+
+fn wal_append(c: &mut Criterion) {
+    let dir = std::env::temp_dir().join(format!("rustengan-bench-wal-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (mut wal, _) = Wal::open(dir.join("wal.jsonl")).expect("wal opens");
+    let record = serde_json::json!({ "key": 42, "value": "a value of some size", "version": 7 });
+    let mut group = c.benchmark_group("wal");
+    // every append waits on the disk, so there's time for fewer of them
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("append", |b| {
+        b.iter(|| wal.append(&record).expect("appends"))
+    });
+    group.finish();
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+criterion_group!(benches, parse_and_dispatch, gossip, read_ok, wal_append);
+criterion_main!(benches);
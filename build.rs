@@ -0,0 +1,21 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "protobuf")]
+    {
+        println!("cargo:rerun-if-changed=proto");
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+        std::env::set_var("PROTOC", protoc);
+        // the service and the envelope it carries are only any use with the bridge
+        let grpc = cfg!(feature = "grpc");
+        let protos: &[&str] = if grpc {
+            &["proto/rustengan.proto", "proto/payloads.proto"]
+        } else {
+            &["proto/payloads.proto"]
+        };
+        tonic_prost_build::configure()
+            .build_server(grpc)
+            .build_client(grpc)
+            .compile_protos(protos, &["proto"])
+            .expect("compile protos");
+    }
+}
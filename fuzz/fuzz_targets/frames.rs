@@ -0,0 +1,17 @@
+#![no_main]
+
+// arbitrary bytes as what a peer sends down a connection: JSON lines, or a codec handshake and
+// the framed, possibly compressed, lanes after it. Run with -dict=frames.dict to get past the
+// handshake sooner.
+
+use libfuzzer_sys::fuzz_target;
+use rustengan::transport::codec;
+use rustengan_fuzz::Payload;
+
+fuzz_target!(|data: &[u8]| {
+    let mut from = std::io::Cursor::new(data);
+    codec::read_frames(&mut from, &mut std::io::sink(), |frame| {
+        let _ = rustengan::parse::<Payload>(&frame);
+        true
+    });
+});
@@ -0,0 +1,17 @@
+#![no_main]
+
+// arbitrary bytes as a line of input, which a node has to survive whatever they are
+
+use libfuzzer_sys::fuzz_target;
+use rustengan::kv::KvRequest;
+use rustengan_fuzz::Payload;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = rustengan::metrics::message_type(data);
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    let _ = rustengan::parse::<Payload>(line);
+    let _ = rustengan::parse::<KvRequest>(line);
+    let _ = rustengan::parse::<serde_json::Value>(line);
+});
@@ -0,0 +1,104 @@
+#![no_main]
+
+// lines that are almost messages: the right envelope, with fields missing, of the wrong type,
+// unknown, or cut off partway, which get much further into deserializing than random bytes do
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rustengan::kv::KvRequest;
+use rustengan_fuzz::Payload;
+use serde_json::{json, Map, Value};
+
+#[derive(Debug, Arbitrary)]
+enum Field {
+    Missing,
+    Null,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(String),
+    List(Vec<u64>),
+    Ops(Vec<(bool, u64, Option<u64>)>),
+}
+
+impl Field {
+    fn value(self) -> Option<Value> {
+        Some(match self {
+            Field::Missing => return None,
+            Field::Null => Value::Null,
+            Field::Bool(b) => b.into(),
+            Field::Int(n) => n.into(),
+            Field::UInt(n) => n.into(),
+            Field::Float(n) => n.into(),
+            Field::Str(s) => s.into(),
+            Field::List(items) => items.into(),
+            Field::Ops(ops) => ops
+                .into_iter()
+                .map(|(write, key, value)| json!([if write { "w" } else { "r" }, key, value]))
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Arbitrary)]
+struct NearlyAMessage {
+    src: Field,
+    dest: Field,
+    msg_id: Field,
+    in_reply_to: Field,
+    kind: Field,
+    // named after the workloads' fields, so some of them land
+    fields: Vec<(u8, Field)>,
+    // where to cut the line short, if anywhere
+    cut: Option<u16>,
+}
+
+const NAMES: [&str; 12] = [
+    "type", "echo", "id", "message", "messages", "value", "topology", "txn", "code", "text", "key",
+    "from",
+];
+
+fuzz_target!(|nearly: NearlyAMessage| {
+    let mut body = Map::new();
+    let mut envelope = Map::new();
+    for (name, field) in [
+        ("msg_id", nearly.msg_id),
+        ("in_reply_to", nearly.in_reply_to),
+        ("type", nearly.kind),
+    ] {
+        if let Some(value) = field.value() {
+            body.insert(name.to_string(), value);
+        }
+    }
+    for (name, field) in nearly.fields {
+        let name = NAMES
+            .get(name as usize)
+            .map_or(format!("x{}", name), |n| n.to_string());
+        if let Some(value) = field.value() {
+            body.insert(name, value);
+        }
+    }
+    for (name, field) in [("src", nearly.src), ("dest", nearly.dest)] {
+        if let Some(value) = field.value() {
+            envelope.insert(name.to_string(), value);
+        }
+    }
+    envelope.insert("body".to_string(), Value::Object(body));
+    let mut line = Value::Object(envelope).to_string();
+    if let Some(cut) = nearly.cut {
+        let mut at = cut as usize % (line.len() + 1);
+        while !line.is_char_boundary(at) {
+            at -= 1;
+        }
+        line.truncate(at);
+    }
+
+    // and whatever does parse has to come back out as the same message
+    if let Ok(message) = rustengan::parse::<Payload>(&line) {
+        let again = serde_json::to_string(&message).expect("a parsed message serializes");
+        let parsed = rustengan::parse::<Payload>(&again).expect("a serialized message parses");
+        assert_eq!(parsed, message);
+    }
+    let _ = rustengan::parse::<KvRequest>(&line);
+});
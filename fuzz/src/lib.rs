@@ -0,0 +1,37 @@
+use rustengan::txn::Op;
+use serde::{Deserialize, Serialize};
+
+/// Payloads in the shapes the workloads use, whose own payload types are private to their
+/// binaries: tagged by `type`, with unit and struct variants, renamed and optional fields, lists,
+/// maps and txn ops.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Echo {
+        echo: String,
+    },
+    Generate,
+    GenerateOk {
+        #[serde(rename = "id")]
+        guid: String,
+    },
+    Broadcast {
+        message: u64,
+    },
+    ReadOk {
+        messages: Vec<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<i64>,
+    },
+    Topology {
+        topology: std::collections::BTreeMap<String, Vec<String>>,
+    },
+    Txn {
+        txn: Vec<Op>,
+    },
+    Error {
+        code: u32,
+        text: String,
+    },
+}
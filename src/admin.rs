@@ -0,0 +1,202 @@
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde_json::{json, Map, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::transport::quality;
+use crate::{config, metrics, Init, Node};
+
+// how long the event loop waits for something to step before looking for admin requests
+pub(crate) const POLL: Duration = Duration::from_millis(50);
+// how long a request waits for the node to get around to it
+const ANSWER_WITHIN: Duration = Duration::from_secs(5);
+
+enum Command {
+    Status,
+    State,
+    Snapshot,
+}
+
+struct Request {
+    command: Command,
+    reply: Sender<Result<Value, String>>,
+}
+
+/// An HTTP server for looking into a running node, on `RUSTENGAN_ADMIN_ADDR`:
+///
+/// - `GET /status`: who the node is, how long it's been up and how many events it's handled
+/// - `GET /state`: whatever summary of its state the node gives ([`Node::status`])
+/// - `GET /config`: the environment it was configured with
+/// - `GET /metrics`: everything in [`metrics`], for Prometheus to scrape
+/// - `GET /links`: what probing has measured of the link to each peer ([`quality`])
+/// - `POST /snapshot`: has the node compact what it keeps on disk ([`Node::snapshot`])
+/// - `POST /profile?seconds=<n>`: starts a CPU profile, built with the `pprof` feature, as
+///   SIGUSR2 would ([`profiling`](crate::profiling)), and says where it'll be written
+/// - `GET /log-level`, `PUT /log-level`: the log level, with the new one as the request body
+///
+/// The server runs on a thread of its own, but anything that needs the node is handed to the
+/// event loop and answered between steps, so it sees the node the way `step` leaves it.
+pub(crate) struct Admin {
+    requests: Receiver<Request>,
+    node_id: String,
+    node_ids: Vec<String>,
+    started: Instant,
+    pub(crate) events: u64,
+}
+
+impl Admin {
+    /// Starts the server if `RUSTENGAN_ADMIN_ADDR` asks for one.
+    pub(crate) fn from_env(init: &Init) -> anyhow::Result<Option<Self>> {
+        let Some(addr) = config::var::<String>("RUSTENGAN_ADMIN_ADDR")? else {
+            return Ok(None);
+        };
+        let server = Server::http(&addr)
+            .map_err(|e| anyhow::anyhow!("{}", e))
+            .with_context(|| format!("admin server on {}", addr))?;
+        log::info!("admin api on http://{}", addr);
+        metrics::enable();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for mut request in server.incoming_requests() {
+                let (code, content_type, body) = if request.url() == "/metrics" {
+                    (200, "text/plain; version=0.0.4", metrics::render())
+                } else {
+                    let (code, body) = handle(&mut request, &tx);
+                    (code, "application/json", body.to_string())
+                };
+                let content_type =
+                    Header::from_bytes("Content-Type", content_type).expect("valid header");
+                let response = Response::from_string(body)
+                    .with_status_code(code)
+                    .with_header(content_type);
+                let _ = request.respond(response);
+            }
+        });
+        Ok(Some(Self {
+            requests: rx,
+            node_id: init.node_id.clone(),
+            node_ids: init.node_ids.clone(),
+            started: Instant::now(),
+            events: 0,
+        }))
+    }
+
+    /// Answers every request that's waiting on the node.
+    pub(crate) fn serve<S, P, IP>(&mut self, node: &mut impl Node<S, P, IP>) {
+        while let Ok(request) = self.requests.try_recv() {
+            let answer = match request.command {
+                Command::Status => Ok(json!({
+                    "node_id": self.node_id,
+                    "node_ids": self.node_ids,
+                    "uptime_ms": self.started.elapsed().as_millis() as u64,
+                    "events": self.events,
+                })),
+                Command::State => Ok(node.status()),
+                Command::Snapshot => node
+                    .snapshot()
+                    .map(|()| json!({ "snapshot": "done" }))
+                    .map_err(|e| format!("{:#}", e)),
+            };
+            let _ = request.reply.send(answer);
+        }
+    }
+}
+
+fn handle(request: &mut tiny_http::Request, node: &Sender<Request>) -> (u16, Value) {
+    let path = request
+        .url()
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    match (request.method(), path.as_str()) {
+        (Method::Get, "/status") => {
+            let (code, mut status) = ask(node, Command::Status);
+            if let Value::Object(status) = &mut status {
+                status.insert("log_level".to_string(), log_level());
+            }
+            (code, status)
+        }
+        (Method::Get, "/state") => ask(node, Command::State),
+        (Method::Post, "/snapshot") => ask(node, Command::Snapshot),
+        #[cfg(feature = "pprof")]
+        (Method::Post, "/profile") => {
+            let seconds = request
+                .url()
+                .split_once('?')
+                .and_then(|(_, query)| query.split('&').find_map(|q| q.strip_prefix("seconds=")))
+                .map(str::parse)
+                .transpose();
+            let Ok(seconds) = seconds else {
+                return (400, json!({ "error": "seconds isn't a number" }));
+            };
+            match crate::profiling::take(seconds.map(Duration::from_secs)) {
+                Ok(path) => (202, json!({ "profile": path })),
+                Err(e) => (500, json!({ "error": format!("{:#}", e) })),
+            }
+        }
+        (Method::Get, "/config") => (200, environment()),
+        (Method::Get, "/links") => (200, links()),
+        (Method::Get, "/log-level") => (200, json!({ "log_level": log_level() })),
+        (Method::Put | Method::Post, "/log-level") => {
+            let mut body = String::new();
+            if request.as_reader().read_to_string(&mut body).is_err() {
+                return (400, json!({ "error": "unreadable body" }));
+            }
+            match body.trim().parse::<log::LevelFilter>() {
+                Ok(level) => {
+                    log::set_max_level(level);
+                    (200, json!({ "log_level": log_level() }))
+                }
+                Err(e) => (400, json!({ "error": e.to_string() })),
+            }
+        }
+        _ => (
+            404,
+            json!({ "error": format!("nothing at {} {}", request.method(), path) }),
+        ),
+    }
+}
+
+fn ask(node: &Sender<Request>, command: Command) -> (u16, Value) {
+    let (reply, answer) = std::sync::mpsc::channel();
+    if node.send(Request { command, reply }).is_err() {
+        return (503, json!({ "error": "node has stopped" }));
+    }
+    match answer.recv_timeout(ANSWER_WITHIN) {
+        Ok(Ok(answer)) => (200, answer),
+        Ok(Err(e)) => (500, json!({ "error": e })),
+        Err(_) => (503, json!({ "error": "node didn't answer in time" })),
+    }
+}
+
+fn log_level() -> Value {
+    log::max_level().to_string().to_lowercase().into()
+}
+
+// every knob the crate reads from the environment
+fn environment() -> Value {
+    let vars: Map<String, Value> = std::env::vars()
+        .filter(|(name, _)| name.starts_with("RUSTENGAN_") || name.starts_with("TXN_"))
+        .map(|(name, value)| (name, value.into()))
+        .collect();
+    Value::Object(vars)
+}
+
+fn links() -> Value {
+    let links: Map<String, Value> = quality::all()
+        .into_iter()
+        .map(|(peer, link)| {
+            let link = json!({
+                "rtt_ms": link.rtt.as_secs_f64() * 1000.0,
+                "rtt_var_ms": link.rtt_var.as_secs_f64() * 1000.0,
+                "loss": link.loss,
+                "answered_ms_ago": link.answered.elapsed().as_millis() as u64,
+            });
+            (peer, link)
+        })
+        .collect();
+    Value::Object(links)
+}
@@ -0,0 +1,387 @@
+use anyhow::{Context, Ok};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+const TICK: Duration = Duration::from_millis(100);
+// a replica that hasn't answered a phase in this long is asked again
+const RETRY_AFTER: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: usize,
+    },
+    ReadOk {
+        value: usize,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    // not something a quorum register can do: it would need consensus
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // phase one: what's the newest value you have?
+    Get {
+        op: u64,
+        key: usize,
+    },
+    GetOk {
+        op: u64,
+        current: Option<(Tag, usize)>,
+    },
+    // phase two: store this unless you have something newer
+    Set {
+        op: u64,
+        key: usize,
+        tag: Tag,
+        value: usize,
+    },
+    SetOk {
+        op: u64,
+    },
+}
+
+// orders writes: the counter goes up with every write, and the writer's id breaks ties between
+// writes that picked the same counter at the same time. So does the op, between writes the same
+// node was coordinating at once, which would otherwise leave replicas disagreeing on the value
+// under one tag.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Tag {
+    counter: u64,
+    writer: String,
+    op: u64,
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+enum Kind {
+    Read,
+    Write(usize),
+}
+
+enum Phase {
+    Query,
+    Propagate { tag: Tag, value: usize },
+}
+
+struct Op {
+    client: String,
+    msg_id: Option<usize>,
+    key: usize,
+    kind: Kind,
+    phase: Phase,
+    // replicas that answered the current phase
+    answered: HashSet<String>,
+    newest: Option<(Tag, usize)>,
+    sent: Instant,
+}
+
+/// An atomic register per key, after Attiya, Bar-Noy and Dolev: no leader and no log, just a
+/// majority of replicas for every operation. Both reads and writes take two round trips. A write
+/// first asks a majority for the newest tag they have and then stores its value under a higher
+/// one on a majority. A read asks a majority for their newest value and then stores that value
+/// back on a majority before returning it, so no later read can see anything older. Any two
+/// majorities overlap, which is what makes it linearizable; it keeps working as long as a
+/// majority of nodes is up, whichever ones they are. Registers live in memory only, though, and
+/// a node that restarts comes back empty: that assumes crash-stop replicas, since a restarted
+/// one can make a majority miss a write it was part of.
+///
+/// Compare-and-set needs the nodes to agree on an order of operations, which a quorum register
+/// doesn't give, so `cas` is refused.
+pub struct AbdNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    // our replica of every register
+    registers: HashMap<usize, (Tag, usize)>,
+    // operations we're coordinating
+    ops: HashMap<u64, Op>,
+    next_op: u64,
+}
+
+impl Node<(), Payload, InjectedPayload> for AbdNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        Ok(Self {
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            registers: HashMap::new(),
+            ops: HashMap::new(),
+            next_op: 1,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => {
+                let stale: Vec<u64> = self
+                    .ops
+                    .iter()
+                    .filter(|(_, op)| clock::since(op.sent) > RETRY_AFTER)
+                    .map(|(&id, _)| id)
+                    .collect();
+                for op in stale {
+                    self.broadcast(op, output)?;
+                }
+            }
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    Payload::Read { key } => {
+                        self.begin(src, client_msg_id, key, Kind::Read, output)?;
+                    }
+                    Payload::Write { key, value } => {
+                        self.begin(src, client_msg_id, key, Kind::Write(value), output)?;
+                    }
+                    Payload::Cas { .. } => {
+                        let error = Payload::Error {
+                            code: error::NOT_SUPPORTED,
+                            text: "an abd register can't compare-and-set".to_string(),
+                        };
+                        self.reply_client(&src, client_msg_id, error, output)?;
+                    }
+                    Payload::Get { op, key } => {
+                        let current = self.registers.get(&key).cloned();
+                        self.send(&src, Payload::GetOk { op, current }, output)?;
+                    }
+                    Payload::Set {
+                        op,
+                        key,
+                        tag,
+                        value,
+                    } => {
+                        self.store(key, tag, value);
+                        self.send(&src, Payload::SetOk { op }, output)?;
+                    }
+                    Payload::GetOk { op, current } => self.got(op, src, current, output)?,
+                    Payload::SetOk { op } => self.was_set(op, src, output)?,
+                    Payload::ReadOk { .. } | Payload::WriteOk | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AbdNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        client: &str,
+        msg_id: Option<usize>,
+        payload: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client.to_string(),
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn majority(&self) -> usize {
+        self.nodes.len() / 2 + 1
+    }
+
+    fn store(&mut self, key: usize, tag: Tag, value: usize) {
+        let newer = self.registers.get(&key).is_none_or(|(t, _)| *t < tag);
+        if newer {
+            self.registers.insert(key, (tag, value));
+        }
+    }
+
+    fn begin(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        key: usize,
+        kind: Kind,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let op = self.next_op;
+        self.next_op += 1;
+        self.ops.insert(
+            op,
+            Op {
+                client,
+                msg_id,
+                key,
+                kind,
+                phase: Phase::Query,
+                answered: HashSet::new(),
+                newest: None,
+                sent: clock::now(),
+            },
+        );
+        self.broadcast(op, output)
+    }
+
+    // sends the current phase to every replica that hasn't answered it yet, ourselves included
+    fn broadcast(&mut self, op: u64, output: &mut Output) -> anyhow::Result<()> {
+        let Some(state) = self.ops.get_mut(&op) else {
+            return Ok(());
+        };
+        state.sent = clock::now();
+        let request = match &state.phase {
+            Phase::Query => Payload::Get { op, key: state.key },
+            Phase::Propagate { tag, value } => Payload::Set {
+                op,
+                key: state.key,
+                tag: tag.clone(),
+                value: *value,
+            },
+        };
+        let waiting: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|n| !state.answered.contains(*n))
+            .cloned()
+            .collect();
+        for n in waiting {
+            if n != self.node {
+                self.send(&n, request.clone(), output)?;
+                continue;
+            }
+            match &request {
+                Payload::Get { key, .. } => {
+                    let current = self.registers.get(key).cloned();
+                    self.got(op, n, current, output)?;
+                }
+                Payload::Set {
+                    key, tag, value, ..
+                } => {
+                    self.store(*key, tag.clone(), *value);
+                    self.was_set(op, n, output)?;
+                }
+                _ => unreachable!("only phase messages are broadcast"),
+            }
+        }
+        Ok(())
+    }
+
+    fn got(
+        &mut self,
+        op: u64,
+        replica: String,
+        current: Option<(Tag, usize)>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let majority = self.majority();
+        let Some(state) = self.ops.get_mut(&op) else {
+            return Ok(());
+        };
+        if !matches!(state.phase, Phase::Query) {
+            return Ok(());
+        }
+        state.answered.insert(replica);
+        if current.as_ref().map(|(t, _)| t) > state.newest.as_ref().map(|(t, _)| t) {
+            state.newest = current;
+        }
+        if state.answered.len() < majority {
+            return Ok(());
+        }
+        let (tag, value) = match (&state.kind, state.newest.clone()) {
+            (Kind::Write(value), newest) => {
+                let counter = newest.map_or(0, |(t, _)| t.counter) + 1;
+                let tag = Tag {
+                    counter,
+                    writer: self.node.clone(),
+                    op,
+                };
+                (tag, *value)
+            }
+            (Kind::Read, Some(newest)) => newest,
+            // nobody in a majority has ever seen a write, so there's nothing to write back
+            (Kind::Read, None) => {
+                let state = self.ops.remove(&op).expect("just had it");
+                let error = Payload::Error {
+                    code: error::KEY_DOES_NOT_EXIST,
+                    text: format!("key {} does not exist", state.key),
+                };
+                return self.reply_client(&state.client, state.msg_id, error, output);
+            }
+        };
+        state.phase = Phase::Propagate { tag, value };
+        state.answered.clear();
+        self.broadcast(op, output)
+    }
+
+    fn was_set(&mut self, op: u64, replica: String, output: &mut Output) -> anyhow::Result<()> {
+        let majority = self.majority();
+        let Some(state) = self.ops.get_mut(&op) else {
+            return Ok(());
+        };
+        if !matches!(state.phase, Phase::Propagate { .. }) {
+            return Ok(());
+        }
+        state.answered.insert(replica);
+        if state.answered.len() < majority {
+            return Ok(());
+        }
+        let state = self.ops.remove(&op).expect("just had it");
+        let reply = match (state.kind, state.phase) {
+            (Kind::Read, Phase::Propagate { value, .. }) => Payload::ReadOk { value },
+            (Kind::Write(_), _) => Payload::WriteOk,
+            (Kind::Read, Phase::Query) => unreachable!("checked the phase above"),
+        };
+        self.reply_client(&state.client, state.msg_id, reply, output)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, AbdNode, _, _>(())
+}
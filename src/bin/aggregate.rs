@@ -0,0 +1,272 @@
+use anyhow::{Context, Ok};
+use rustengan::ddsketch::DdSketch;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::Duration,
+};
+
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(200);
+// every this many rounds a node sends all of its partials, not just the ones that changed, in
+// case a round went missing
+const FULL_GOSSIP_EVERY: u64 = 10;
+const DEFAULT_WINDOW_MS: u64 = 1000;
+// windows kept, counting back from the newest anyone has seen an event for
+const RETAINED_WINDOWS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Ingest {
+        ts_ms: u64,
+        value: f64,
+    },
+    IngestOk,
+    // the window holding ts_ms, or the newest one if there's none
+    Query {
+        #[serde(default)]
+        ts_ms: Option<u64>,
+    },
+    QueryOk {
+        window_start_ms: u64,
+        window_ms: u64,
+        count: u64,
+        sum: f64,
+        min: Option<f64>,
+        max: Option<f64>,
+        p50: Option<f64>,
+        p90: Option<f64>,
+        p99: Option<f64>,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // the sender's own partials, by window
+    Partials {
+        partials: Vec<(u64, Partial)>,
+    },
+}
+
+// what one node has seen of one window
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Partial {
+    count: u64,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+    sketch: DdSketch,
+}
+
+impl Partial {
+    fn insert(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |m| m.min(value)));
+        self.max = Some(self.max.map_or(value, |m| m.max(value)));
+        self.sketch.insert(value);
+    }
+
+    fn merge(&mut self, other: &Partial) {
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = match (self.min, other.min) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        self.max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.sketch.merge(&other.sketch);
+    }
+}
+
+pub enum InjectedPayload {
+    Gossip,
+}
+
+/// Windowed aggregates over a stream of timestamped numbers. Events go into tumbling windows of
+/// `RUSTENGAN_WINDOW_MS` by their own timestamp, so one that turns up late still lands in the
+/// window it belongs to as long as that window is kept. Each node aggregates only the events
+/// sent to it, into a count, sum, min, max and a [`DdSketch`] for percentiles per window, and
+/// gossips those partials to every other node. A query merges every node's partial for the
+/// window, so it answers for the whole cluster, with whatever has been heard of so far.
+///
+/// A node only ever sends its own partials, and only ever adds to them, so a peer keeps
+/// whichever copy of a partial has counted the most events.
+pub struct AggregateNode {
+    node: String,
+    id: usize,
+    peers: Vec<String>,
+    window_ms: u64,
+    // window -> node -> partial
+    windows: BTreeMap<u64, HashMap<String, Partial>>,
+    // windows of ours that changed since the last round
+    dirty: BTreeSet<u64>,
+    rounds: u64,
+}
+
+impl Node<(), Payload, InjectedPayload> for AggregateNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(GOSSIP_INTERVAL, tx, || {
+            Event::Injected(InjectedPayload::Gossip)
+        });
+        Ok(Self {
+            peers: init
+                .node_ids
+                .into_iter()
+                .filter(|n| *n != init.node_id)
+                .collect(),
+            node: init.node_id,
+            id: 1,
+            window_ms: config::var_or("RUSTENGAN_WINDOW_MS", DEFAULT_WINDOW_MS)?.max(1),
+            windows: BTreeMap::new(),
+            dirty: BTreeSet::new(),
+            rounds: 0,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Gossip) => {
+                self.rounds += 1;
+                let windows: Vec<u64> = if self.rounds.is_multiple_of(FULL_GOSSIP_EVERY) {
+                    self.windows.keys().copied().collect()
+                } else {
+                    self.dirty.iter().copied().collect()
+                };
+                self.dirty.clear();
+                let partials: Vec<(u64, Partial)> = windows
+                    .into_iter()
+                    .filter_map(|w| Some((w, self.windows.get(&w)?.get(&self.node)?.clone())))
+                    .collect();
+                if partials.is_empty() {
+                    return Ok(());
+                }
+                for peer in &self.peers {
+                    let gossip = Payload::Partials {
+                        partials: partials.clone(),
+                    };
+                    self.send(peer, gossip, output)?;
+                }
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Ingest { ts_ms, value } => {
+                        let window = ts_ms / self.window_ms;
+                        reply.body.payload = if self.oldest().is_some_and(|w| window < w) {
+                            Payload::Error {
+                                code: error::PRECONDITION_FAILED,
+                                text: format!("the window for {} is no longer kept", ts_ms),
+                            }
+                        } else {
+                            self.windows
+                                .entry(window)
+                                .or_default()
+                                .entry(self.node.clone())
+                                .or_default()
+                                .insert(value);
+                            self.dirty.insert(window);
+                            self.prune();
+                            Payload::IngestOk
+                        };
+                        reply.send(&mut *output).context("reply to ingest")?;
+                    }
+                    Payload::Query { ts_ms } => {
+                        let window = match ts_ms {
+                            Some(ts_ms) => ts_ms / self.window_ms,
+                            None => self.windows.keys().next_back().copied().unwrap_or(0),
+                        };
+                        let mut total = Partial::default();
+                        for partial in self
+                            .windows
+                            .get(&window)
+                            .into_iter()
+                            .flat_map(|w| w.values())
+                        {
+                            total.merge(partial);
+                        }
+                        reply.body.payload = Payload::QueryOk {
+                            window_start_ms: window * self.window_ms,
+                            window_ms: self.window_ms,
+                            count: total.count,
+                            sum: total.sum,
+                            min: total.min,
+                            max: total.max,
+                            p50: total.sketch.quantile(0.5),
+                            p90: total.sketch.quantile(0.9),
+                            p99: total.sketch.quantile(0.99),
+                        };
+                        reply.send(&mut *output).context("reply to query")?;
+                    }
+                    Payload::Partials { partials } => {
+                        for (window, partial) in partials {
+                            if self.oldest().is_some_and(|w| window < w) {
+                                continue;
+                            }
+                            let known = self.windows.entry(window).or_default();
+                            let newer = known.get(&src).is_none_or(|p| p.count < partial.count);
+                            if newer {
+                                known.insert(src.clone(), partial);
+                            }
+                        }
+                        self.prune();
+                    }
+                    Payload::IngestOk | Payload::QueryOk { .. } | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AggregateNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    // the oldest window still kept, once there are more than we keep
+    fn oldest(&self) -> Option<u64> {
+        let newest = *self.windows.keys().next_back()?;
+        newest.checked_sub(RETAINED_WINDOWS - 1)
+    }
+
+    fn prune(&mut self) {
+        if let Some(oldest) = self.oldest() {
+            self.windows = self.windows.split_off(&oldest);
+            self.dirty = self.dirty.split_off(&oldest);
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, AggregateNode, _, _>(())
+}
@@ -0,0 +1,373 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use rustengan::history::{self, linearizable, Op, Type, F};
+use serde_json::Value;
+
+#[path = "run_maelstrom/edn.rs"]
+mod edn;
+
+const USAGE: &str = "usage: analyze [<store dir>]";
+
+// what one node's log had in it
+#[derive(Default)]
+struct NodeLog {
+    lines: usize,
+    // by level, the lines logged through rustengan::logging
+    levels: BTreeMap<String, usize>,
+}
+
+// by node, what it sent and received, as Maelstrom's network logged it
+#[derive(Default)]
+struct Traffic {
+    sent: BTreeMap<String, usize>,
+    received: BTreeMap<String, usize>,
+}
+
+// sums up what a Maelstrom run left in its store directory: what Maelstrom made of it, how
+// much each node sent, received and logged, how long each kind of operation took, what went
+// wrong with the ones that didn't go through, and, if the run was of a kv workload, whether
+// its history is linearizable:
+//
+//     analyze store/lin-kv/20240101T120000.000Z
+//
+// with no directory, it's store/latest. Per-node message counts come from jepsen.log, so
+// they're only there if the test was run with --log-net-send and --log-net-recv. Fails only
+// if what's there can't be read, or the history isn't linearizable.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let store = match args.next() {
+        Some(arg) if arg.starts_with('-') => anyhow::bail!(USAGE),
+        Some(arg) => PathBuf::from(arg),
+        None => Path::new("store").join("latest"),
+    };
+    anyhow::ensure!(args.next().is_none(), USAGE);
+    anyhow::ensure!(store.is_dir(), "no store directory at {}", store.display());
+
+    let results = store.join("results.edn");
+    if results.is_file() {
+        let summary =
+            edn::parse(&read(&results)?).with_context(|| format!("parse {}", results.display()))?;
+        report_results(&summary);
+    } else {
+        println!("no results.edn: maelstrom didn't finish");
+    }
+
+    let traffic = traffic(&store.join("jepsen.log"))?;
+    let logs = node_logs(&store.join("node-logs"))?;
+    report_nodes(&traffic, &logs);
+
+    let path = store.join("history.edn");
+    let ops = ops(&path)?;
+    println!();
+    println!("{} history entries", ops.len());
+    report_latencies(&ops);
+    report_errors(&ops);
+    let kv: Vec<Op> = ops
+        .iter()
+        .enumerate()
+        .filter_map(|(at, op)| kv_op(at, op))
+        .collect();
+    if kv.is_empty() {
+        return Ok(());
+    }
+    let kv_ops = kv.iter().filter(|op| op.kind == Type::Invoke).count();
+    println!();
+    match linearizable::check(&kv) {
+        Ok(()) => {
+            println!("{} kv ops, linearizable", kv_ops);
+            Ok(())
+        }
+        Err(violation) => {
+            println!("{} kv ops, NOT linearizable: {}", kv_ops, violation);
+            Err(violation.into())
+        }
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("read {}", path.display()))
+}
+
+fn report_results(summary: &Value) {
+    let valid = |v: &Value| v.get("valid?").cloned().unwrap_or(Value::Null);
+    let verdict = match valid(summary) {
+        Value::Bool(true) => "valid".to_string(),
+        Value::Bool(false) => "INVALID".to_string(),
+        other => format!("valid? {}", other),
+    };
+    let invalid: Vec<&str> = summary
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, checker)| checker.is_object() && valid(checker) != Value::Bool(true))
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let invalid = match invalid.as_slice() {
+        [] => String::new(),
+        invalid => format!(", invalid: {}", invalid.join(" ")),
+    };
+    let count = |key| summary["stats"][key].as_u64().unwrap_or(0);
+    println!(
+        "maelstrom: {}, {}/{} ok, {} failed, {} indefinite{}",
+        verdict,
+        count("ok-count"),
+        count("count"),
+        count("fail-count"),
+        count("info-count"),
+        invalid
+    );
+    let net = &summary["net"];
+    for side in ["clients", "servers"] {
+        let stats = &net[side];
+        let Some(msgs) = stats["msg-count"].as_u64() else {
+            continue;
+        };
+        let per_op = stats["msgs-per-op"]
+            .as_f64()
+            .map(|m| format!(", {:.1} msgs/op", m))
+            .unwrap_or_default();
+        println!("  {}: {} msgs{}", side, msgs, per_op);
+    }
+}
+
+// what jepsen.log says went over the network, by node: Maelstrom logs `:send` and `:recv`
+// followed by the message, in edn, for every message, if it's asked to
+fn traffic(path: &Path) -> anyhow::Result<Traffic> {
+    let mut traffic = Traffic::default();
+    if !path.is_file() {
+        return Ok(traffic);
+    }
+    for line in read(path)?.lines() {
+        let (counts, key, message) = if let Some((_, message)) = line.split_once(":send ") {
+            (&mut traffic.sent, "src", message)
+        } else if let Some((_, message)) = line.split_once(":recv ") {
+            (&mut traffic.received, "dest", message)
+        } else {
+            continue;
+        };
+        let Ok(message) = edn::parse(message) else {
+            continue;
+        };
+        if let Some(node) = message[key].as_str() {
+            *counts.entry(node.to_string()).or_default() += 1;
+        }
+    }
+    Ok(traffic)
+}
+
+// each node's stderr, by node, from the logs Maelstrom keeps of them
+fn node_logs(dir: &Path) -> anyhow::Result<BTreeMap<String, NodeLog>> {
+    let mut logs = BTreeMap::new();
+    if !dir.is_dir() {
+        return Ok(logs);
+    }
+    let entries = std::fs::read_dir(dir).with_context(|| format!("list {}", dir.display()))?;
+    for entry in entries {
+        let path = entry.context("list node logs")?.path();
+        if path.extension().is_none_or(|ext| ext != "log") {
+            continue;
+        }
+        let Some(node) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let mut log = NodeLog::default();
+        for line in read(&path)?.lines() {
+            log.lines += 1;
+            let level = line.split_whitespace().next().unwrap_or_default();
+            if ["ERROR", "WARN", "INFO", "DEBUG", "TRACE"].contains(&level) {
+                *log.levels.entry(level.to_string()).or_default() += 1;
+            }
+        }
+        logs.insert(node.to_string(), log);
+    }
+    Ok(logs)
+}
+
+fn report_nodes(traffic: &Traffic, logs: &BTreeMap<String, NodeLog>) {
+    let mut nodes: Vec<&String> = logs
+        .keys()
+        .chain(traffic.sent.keys())
+        .chain(traffic.received.keys())
+        // clients are on the network too, but they aren't nodes
+        .filter(|node| logs.contains_key(*node) || node.starts_with('n'))
+        .collect();
+    nodes.sort();
+    nodes.dedup();
+    if nodes.is_empty() {
+        return;
+    }
+    println!();
+    if traffic.sent.is_empty() && traffic.received.is_empty() {
+        println!("no messages in jepsen.log: run with --log-net-send --log-net-recv to count them");
+    }
+    println!(
+        "{:<8} {:>8} {:>8} {:>8}  log levels",
+        "node", "sent", "received", "log lines"
+    );
+    for node in nodes {
+        let count = |counts: &BTreeMap<String, usize>| counts.get(node).copied().unwrap_or(0);
+        let log = logs.get(node);
+        let levels = log
+            .map(|log| {
+                log.levels
+                    .iter()
+                    .map(|(level, n)| format!("{} {}", level, n))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default();
+        println!(
+            "{:<8} {:>8} {:>8} {:>8}  {}",
+            node,
+            count(&traffic.sent),
+            count(&traffic.received),
+            log.map_or(0, |log| log.lines),
+            levels
+        );
+    }
+}
+
+// the entries of history.edn, whether they're written one to a line or as one vector
+fn ops(path: &Path) -> anyhow::Result<Vec<Value>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let edn = read(path)?;
+    let parsed =
+        edn::parse(&format!("[{}]", edn)).with_context(|| format!("parse {}", path.display()))?;
+    let mut ops = match parsed {
+        Value::Array(ops) => ops,
+        _ => unreachable!("parsed a vector"),
+    };
+    if let [Value::Array(_)] = ops.as_slice() {
+        let Some(Value::Array(inner)) = ops.pop() else {
+            unreachable!("just matched");
+        };
+        ops = inner;
+    }
+    Ok(ops)
+}
+
+fn kind(op: &Value) -> Option<Type> {
+    match op["type"].as_str()? {
+        "invoke" => Some(Type::Invoke),
+        "ok" => Some(Type::Ok),
+        "fail" => Some(Type::Fail),
+        "info" => Some(Type::Info),
+        _ => None,
+    }
+}
+
+// the f an op's entries have, as the report names it
+fn f(op: &Value) -> String {
+    match &op["f"] {
+        Value::String(f) => f.clone(),
+        f => f.to_string(),
+    }
+}
+
+// in milliseconds, by f, how long each op that completed took from its invocation, and how
+// many never completed
+fn report_latencies(ops: &[Value]) {
+    let mut took: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut open: HashMap<String, u64> = HashMap::new();
+    for op in ops {
+        // nemesis entries have a process of "nemesis", and no latency worth reporting
+        let (Some(kind), Some(process), Some(time)) =
+            (kind(op), op["process"].as_u64(), op["time"].as_u64())
+        else {
+            continue;
+        };
+        let process = format!("{}", process);
+        if kind == Type::Invoke {
+            open.insert(process, time);
+        } else if let Some(invoked) = open.remove(&process) {
+            let ms = time.saturating_sub(invoked) as f64 / 1e6;
+            took.entry(f(op)).or_default().push(ms);
+        }
+    }
+    if took.is_empty() {
+        return;
+    }
+    println!(
+        "{:<12} {:>6} {:>9} {:>9} {:>9} {:>9}  (ms)",
+        "latency", "count", "p50", "p90", "p99", "max"
+    );
+    for (f, mut took) in took {
+        took.sort_by(f64::total_cmp);
+        let at = |q: f64| took[((took.len() as f64 * q).ceil() as usize).clamp(1, took.len()) - 1];
+        println!(
+            "{:<12} {:>6} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+            f,
+            took.len(),
+            at(0.5),
+            at(0.9),
+            at(0.99),
+            at(1.0)
+        );
+    }
+    if !open.is_empty() {
+        println!("{} ops never completed", open.len());
+    }
+}
+
+// the ops that failed, or might have, counted by f and what went wrong
+fn report_errors(ops: &[Value]) {
+    let mut errors: BTreeMap<(String, String, String), usize> = BTreeMap::new();
+    for op in ops {
+        let kind = match kind(op) {
+            Some(Type::Fail) => "fail",
+            Some(Type::Info) if op["process"].is_u64() => "info",
+            _ => continue,
+        };
+        let error = match &op["error"] {
+            Value::Null => "no error given".to_string(),
+            Value::String(error) => error.clone(),
+            // Maelstrom's errors are [:name "text"], or [:error code "text"], and the text
+            // differs from op to op
+            Value::Array(parts) => parts
+                .iter()
+                .take_while(|part| !matches!(part, Value::String(s) if s.contains(' ')))
+                .map(|part| match part {
+                    Value::String(s) => s.clone(),
+                    part => part.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+            error => error.to_string(),
+        };
+        *errors.entry((kind.to_string(), f(op), error)).or_default() += 1;
+    }
+    if errors.is_empty() {
+        return;
+    }
+    println!("errors:");
+    for ((kind, f, error), n) in errors {
+        println!("  {:>6} {} {}: {}", n, kind, f, error);
+    }
+}
+
+// the `at`th entry as the linearizability checker has it, if it's a read, write or cas of a
+// key, keeping the index history.edn gave it, so a violation points back into the file
+fn kv_op(at: usize, op: &Value) -> Option<Op> {
+    let f = match op["f"].as_str()? {
+        "read" => F::Read,
+        "write" => F::Write,
+        "cas" => F::Cas,
+        _ => return None,
+    };
+    let value = op.get("value")?;
+    if value.as_array().is_none_or(|kv| kv.len() != 2) {
+        return None;
+    }
+    Some(history::Op {
+        index: op["index"].as_u64().map_or(at, |index| index as usize),
+        kind: kind(op)?,
+        f,
+        value: value.clone(),
+        process: op["process"].as_u64()? as usize,
+        time: op["time"].as_u64()?,
+    })
+}
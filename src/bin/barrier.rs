@@ -0,0 +1,550 @@
+use anyhow::{Context, Ok};
+use rustengan::failure_detector::{FailureDetector, FdEvent, Strategy};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+// fail-stop, as in chain replication: a node declared failed stays out for good
+const FAIL_AFTER: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    // blocks until `count` participants, this one included, have entered the barrier. the
+    // barrier then resets, so the next `count` entries make up its next generation.
+    Enter {
+        barrier: String,
+        count: usize,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    EnterOk {
+        generation: u64,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a participant waiting at its node, on its way to the leader
+    Arrive {
+        barrier: String,
+        count: usize,
+        arrival: Arrival,
+    },
+    // leader -> the participant's node, when the participant won't be released
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+    // flooded to every node, each of which releases its own participants
+    Release {
+        barrier: String,
+        generation: u64,
+        arrivals: Vec<(String, usize)>,
+    },
+    // new leader -> everyone: who is waiting at your node, and what has been released so far
+    Recover,
+    Recovered {
+        arrivals: Vec<(String, usize, Arrival)>,
+        generations: Vec<(String, u64)>,
+    },
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Arrival {
+    origin: String,
+    req_id: usize,
+    deadline_ms: Option<u64>,
+}
+
+struct Waiter {
+    client: String,
+    msg_id: Option<usize>,
+    barrier: String,
+    count: usize,
+    deadline_ms: Option<u64>,
+}
+
+struct Barrier {
+    count: usize,
+    arrivals: Vec<Arrival>,
+}
+
+pub enum InjectedPayload {
+    Fd(FdEvent),
+}
+
+impl From<FdEvent> for InjectedPayload {
+    fn from(event: FdEvent) -> Self {
+        Self::Fd(event)
+    }
+}
+
+struct Leader {
+    barriers: HashMap<String, Barrier>,
+    // nodes that haven't yet told a new leader who is waiting at them. until they have, the
+    // leader can't know how many participants have arrived, so it neither releases nor expires.
+    recovering: HashSet<String>,
+}
+
+/// Reusable barriers. A client entering a barrier waits at the node it talked to, which tells the
+/// leader (the first node not declared failed) about it; once the barrier has as many
+/// participants as it was entered with the leader releases them all, and the release is flooded
+/// to every node so that it reaches every participant's node even if the leader dies halfway
+/// through sending it. A participant that entered with a timeout and is still waiting when it
+/// runs out is told so, and no longer counts.
+///
+/// Which participants are waiting is known to the nodes they wait at, not just to the leader,
+/// so a new leader rebuilds the barriers by asking every node, and learns where each barrier's
+/// generations got to along the way. Participants waiting at a node that fails are dropped,
+/// since they can never be released.
+pub struct BarrierNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    failed: HashSet<String>,
+    fd: FailureDetector<Payload, InjectedPayload>,
+    // our own participants, by request id
+    waiting: HashMap<usize, Waiter>,
+    // the last generation of each barrier released
+    generations: HashMap<String, u64>,
+    // every participant released so far, by node and request id, so that an arrival repeated
+    // after its release doesn't count towards the next generation
+    released: HashSet<(String, usize)>,
+    role: Option<Leader>,
+}
+
+impl Node<(), Payload, InjectedPayload> for BarrierNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut node = Self {
+            fd: FailureDetector::start(
+                &init.node_id,
+                init.node_ids.clone(),
+                HEARTBEAT_INTERVAL,
+                Strategy::from_env(FAIL_AFTER)?,
+                tx,
+            ),
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            failed: HashSet::new(),
+            waiting: HashMap::new(),
+            generations: HashMap::new(),
+            released: HashSet::new(),
+            role: None,
+        };
+        if node.leader() == node.node {
+            node.role = Some(Leader {
+                barriers: HashMap::new(),
+                recovering: HashSet::new(),
+            });
+        }
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Fd(FdEvent::Heartbeat)) => {
+                self.fd.heartbeat(&mut *output)?;
+                self.expire(output)?;
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerDown(n))) => {
+                if !self.failed.contains(&n) {
+                    self.declare_failed(n, output)?;
+                }
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerUp(_))) => {}
+            Event::Message(input) => {
+                self.fd.heard_from(&input.src);
+                let src = input.src.clone();
+                match input.body.payload {
+                    Payload::Enter {
+                        barrier,
+                        count,
+                        timeout_ms,
+                    } => {
+                        let req_id = self.id;
+                        self.id += 1;
+                        let deadline_ms = timeout_ms.map(|t| clock::wall_ms() + t);
+                        let waiter = Waiter {
+                            client: src,
+                            msg_id: input.body.id,
+                            barrier,
+                            count,
+                            deadline_ms,
+                        };
+                        self.waiting.insert(req_id, waiter);
+                        self.arrive(req_id, output)?;
+                    }
+                    Payload::Arrive {
+                        barrier,
+                        count,
+                        arrival,
+                    } => self.handle(barrier, count, arrival, output)?,
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    Payload::Release {
+                        barrier,
+                        generation,
+                        arrivals,
+                    } => self.release(barrier, generation, arrivals, output)?,
+                    Payload::Recover => {
+                        let arrivals = self
+                            .waiting
+                            .iter()
+                            .map(|(&req_id, w)| {
+                                let arrival = Arrival {
+                                    origin: self.node.clone(),
+                                    req_id,
+                                    deadline_ms: w.deadline_ms,
+                                };
+                                (w.barrier.clone(), w.count, arrival)
+                            })
+                            .collect();
+                        let generations = self
+                            .generations
+                            .iter()
+                            .map(|(b, &g)| (b.clone(), g))
+                            .collect();
+                        let recovered = Payload::Recovered {
+                            arrivals,
+                            generations,
+                        };
+                        self.send(&src, recovered, output)?;
+                    }
+                    Payload::Recovered {
+                        arrivals,
+                        generations,
+                    } => {
+                        for (barrier, generation) in generations {
+                            let known = self.generations.entry(barrier).or_default();
+                            *known = (*known).max(generation);
+                        }
+                        for (barrier, count, arrival) in arrivals {
+                            self.handle(barrier, count, arrival, output)?;
+                        }
+                        if let Some(leader) = &mut self.role {
+                            leader.recovering.remove(&src);
+                        }
+                        self.check_all(output)?;
+                    }
+                    Payload::Heartbeat | Payload::EnterOk { .. } | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl BarrierNode {
+    fn members(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|n| !self.failed.contains(*n))
+            .cloned()
+            .collect()
+    }
+
+    fn leader(&self) -> String {
+        self.members().first().cloned().expect("we're a member")
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    // tells the leader about one of our participants
+    fn arrive(&mut self, req_id: usize, output: &mut Output) -> anyhow::Result<()> {
+        let waiter = &self.waiting[&req_id];
+        let (barrier, count) = (waiter.barrier.clone(), waiter.count);
+        let arrival = Arrival {
+            origin: self.node.clone(),
+            req_id,
+            deadline_ms: waiter.deadline_ms,
+        };
+        let leader = self.leader();
+        if leader == self.node {
+            return self.handle(barrier, count, arrival, output);
+        }
+        let arrive = Payload::Arrive {
+            barrier,
+            count,
+            arrival,
+        };
+        self.send(&leader, arrive, output)
+    }
+
+    fn handle(
+        &mut self,
+        barrier: String,
+        count: usize,
+        arrival: Arrival,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(leader) = &mut self.role else {
+            // the sender still takes us for the leader
+            let leader = self.leader();
+            let arrive = Payload::Arrive {
+                barrier,
+                count,
+                arrival,
+            };
+            return self.send(&leader, arrive, output);
+        };
+        let entry = leader.barriers.entry(barrier.clone()).or_insert(Barrier {
+            count,
+            arrivals: Vec::new(),
+        });
+        if entry.arrivals.is_empty() {
+            entry.count = count;
+        }
+        let refusal = if count == 0 {
+            Some(format!(
+                "barrier {} needs at least one participant",
+                barrier
+            ))
+        } else if count != entry.count {
+            Some(format!(
+                "barrier {} was entered with {} participants, not {}",
+                barrier, entry.count, count
+            ))
+        } else {
+            None
+        };
+        if let Some(text) = refusal {
+            let error = Payload::Error {
+                code: error::PRECONDITION_FAILED,
+                text,
+            };
+            return self.respond(&arrival.origin, arrival.req_id, error, output);
+        }
+        // a participant's node tells every new leader about it, and the network may repeat an
+        // arrival, so it may have heard already, or even released it
+        let known = self
+            .released
+            .contains(&(arrival.origin.clone(), arrival.req_id))
+            || entry
+                .arrivals
+                .iter()
+                .any(|a| a.origin == arrival.origin && a.req_id == arrival.req_id);
+        if !known {
+            entry.arrivals.push(arrival);
+        }
+        self.check(&barrier, output)
+    }
+
+    // releases the barrier if enough participants have arrived
+    fn check(&mut self, barrier: &str, output: &mut Output) -> anyhow::Result<()> {
+        let Some(leader) = &mut self.role else {
+            return Ok(());
+        };
+        if !leader.recovering.is_empty() {
+            return Ok(());
+        }
+        let Some(entry) = leader.barriers.get_mut(barrier) else {
+            return Ok(());
+        };
+        if entry.arrivals.len() < entry.count {
+            return Ok(());
+        }
+        let arrivals = entry
+            .arrivals
+            .drain(..entry.count)
+            .map(|a| (a.origin, a.req_id))
+            .collect();
+        let generation = self.generations.get(barrier).copied().unwrap_or(0) + 1;
+        self.release(barrier.to_string(), generation, arrivals, output)?;
+        // whoever arrived past the count starts the next generation
+        self.check(barrier, output)
+    }
+
+    fn check_all(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Some(leader) = &self.role else {
+            return Ok(());
+        };
+        let barriers: Vec<String> = leader.barriers.keys().cloned().collect();
+        for barrier in barriers {
+            self.check(&barrier, output)?;
+        }
+        Ok(())
+    }
+
+    fn release(
+        &mut self,
+        barrier: String,
+        generation: u64,
+        arrivals: Vec<(String, usize)>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let known = self.generations.get(&barrier).copied().unwrap_or(0);
+        if generation <= known {
+            return Ok(());
+        }
+        self.generations.insert(barrier.clone(), generation);
+        self.released.extend(arrivals.iter().cloned());
+        for member in self.members() {
+            if member != self.node {
+                let release = Payload::Release {
+                    barrier: barrier.clone(),
+                    generation,
+                    arrivals: arrivals.clone(),
+                };
+                self.send(&member, release, output)?;
+            }
+        }
+        // a release from the old leader can reach a new one that has already rebuilt the
+        // barrier, with these participants in it
+        if let Some(leader) = &mut self.role {
+            if let Some(entry) = leader.barriers.get_mut(&barrier) {
+                entry
+                    .arrivals
+                    .retain(|a| !arrivals.contains(&(a.origin.clone(), a.req_id)));
+            }
+        }
+        for (origin, req_id) in arrivals {
+            if origin == self.node {
+                self.reply_client(req_id, Payload::EnterOk { generation }, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the leader gives up on participants whose timeout has run out
+    fn expire(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Some(leader) = &mut self.role else {
+            return Ok(());
+        };
+        if !leader.recovering.is_empty() {
+            return Ok(());
+        }
+        let now = clock::wall_ms();
+        let mut expired = Vec::new();
+        for (barrier, entry) in &mut leader.barriers {
+            entry.arrivals.retain(|a| {
+                let over = a.deadline_ms.is_some_and(|d| d <= now);
+                if over {
+                    expired.push((barrier.clone(), a.origin.clone(), a.req_id));
+                }
+                !over
+            });
+        }
+        for (barrier, origin, req_id) in expired {
+            let error = Payload::Error {
+                code: error::TIMEOUT,
+                text: format!("timed out waiting at barrier {}", barrier),
+            };
+            self.respond(&origin, req_id, error, output)?;
+        }
+        Ok(())
+    }
+
+    fn respond(
+        &mut self,
+        origin: &str,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if origin == self.node {
+            return self.reply_client(req_id, reply, output);
+        }
+        let done = Payload::Done {
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.send(origin, done, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(waiter) = self.waiting.remove(&req_id) else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: waiter.client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: waiter.msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn declare_failed(&mut self, n: String, output: &mut Output) -> anyhow::Result<()> {
+        log::warn!("declaring {} failed", n);
+        let was_leader = self.leader() == n;
+        self.failed.insert(n.clone());
+        if let Some(leader) = &mut self.role {
+            leader.recovering.remove(&n);
+            for entry in leader.barriers.values_mut() {
+                entry.arrivals.retain(|a| a.origin != n);
+            }
+            return self.check_all(output);
+        }
+        if !was_leader {
+            return Ok(());
+        }
+        if self.leader() == self.node {
+            log::info!("taking over from {}", n);
+            let others: Vec<String> = self
+                .members()
+                .into_iter()
+                .filter(|m| *m != self.node)
+                .collect();
+            self.role = Some(Leader {
+                barriers: HashMap::new(),
+                recovering: others.iter().cloned().collect(),
+            });
+            for other in others {
+                self.send(&other, Payload::Recover, output)?;
+            }
+        }
+        let req_ids: Vec<usize> = self.waiting.keys().copied().collect();
+        for req_id in req_ids {
+            self.arrive(req_id, output)?;
+        }
+        self.check_all(output)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, BarrierNode, _, _>(())
+}
@@ -3,97 +3,153 @@ use rand::prelude::*;
 use rustengan::*;
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{HashMap, HashSet},
-    io::StdoutLock,
+    collections::{BTreeMap, BTreeSet, HashMap},
     time::Duration,
 };
 
+pub(crate) const GOSSIP_EVERY: Duration = Duration::from_millis(300);
+
+/// How a node gossips: every `every`, to `fanout` of its neighbours picked at random, telling
+/// each of at most `batch` of the messages it doesn't know we have. A fanout or batch of 0 is
+/// no limit, which is how a node gossips unless RUSTENGAN_GOSSIP_MS, RUSTENGAN_GOSSIP_FANOUT
+/// or RUSTENGAN_GOSSIP_BATCH say otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Gossip {
+    pub(crate) every: Duration,
+    pub(crate) fanout: usize,
+    pub(crate) batch: usize,
+}
+
+impl Default for Gossip {
+    fn default() -> Self {
+        Self {
+            every: GOSSIP_EVERY,
+            fanout: 0,
+            batch: 0,
+        }
+    }
+}
+
+impl Gossip {
+    pub(crate) fn from_env() -> anyhow::Result<Self> {
+        let every = config::var("RUSTENGAN_GOSSIP_MS")?.map(Duration::from_millis);
+        Ok(Self {
+            every: every.unwrap_or(GOSSIP_EVERY),
+            fanout: config::var_or("RUSTENGAN_GOSSIP_FANOUT", 0)?,
+            batch: config::var_or("RUSTENGAN_GOSSIP_BATCH", 0)?,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
-enum Payload {
+pub(crate) enum Payload {
     Broadcast {
         message: usize,
     },
     BroadcastOk,
     Read,
     ReadOk {
-        messages: HashSet<usize>,
+        messages: BTreeSet<usize>,
     },
     Topology {
         topology: HashMap<String, Vec<String>>,
     },
     TopologyOk,
     Gossip {
-        seen: HashSet<usize>,
+        seen: BTreeSet<usize>,
     },
 }
 
-enum InjectedPayload {
+pub(crate) enum InjectedPayload {
     Gossip,
 }
 
-struct BroadcastNode {
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct BroadcastNode {
     node: String,
     id: usize,
-    messages: HashSet<usize>,
-    known: HashMap<String, HashSet<usize>>,
+    messages: BTreeSet<usize>,
+    known: BTreeMap<String, BTreeSet<usize>>,
     neighborhood: Vec<String>,
+    gossip: Gossip,
 }
 
-impl Node<(), Payload, InjectedPayload> for BroadcastNode {
+impl Node<Gossip, Payload, InjectedPayload> for BroadcastNode {
     fn from_init(
-        _init_state: (),
+        gossip: Gossip,
         init: Init,
         tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
     ) -> anyhow::Result<Self>
     where
         Self: Sized,
     {
-        std::thread::spawn(move || loop {
-            // generate gossip events
-            // TODO: handle EOF signal
-            std::thread::sleep(Duration::from_millis(300));
-            if let Err(_) = tx.send(Event::Injected(InjectedPayload::Gossip)) {
-                break;
-            }
+        // generate gossip events
+        // TODO: handle EOF signal
+        ticks::every(gossip.every, tx, || {
+            Event::Injected(InjectedPayload::Gossip)
         });
         Ok(Self {
             id: 1,
             node: init.node_id,
-            messages: HashSet::new(),
+            messages: BTreeSet::new(),
             known: init
                 .node_ids
                 .into_iter()
-                .map(|nid| (nid, HashSet::new()))
+                .map(|nid| (nid, BTreeSet::new()))
                 .collect(),
             neighborhood: Vec::new(),
+            gossip,
         })
     }
     fn step(
         &mut self,
         input: Event<Payload, InjectedPayload>,
-        output: &mut StdoutLock,
+        output: &mut Output,
     ) -> anyhow::Result<()> {
         match input {
             Event::EOF => {}
             Event::Injected(payload) => match payload {
                 InjectedPayload::Gossip => {
-                    for n in &self.neighborhood {
+                    let mut rng = rng::thread();
+                    let fanout = self.gossip.fanout;
+                    let to: Vec<&String> = if fanout == 0 || fanout >= self.neighborhood.len() {
+                        self.neighborhood.iter().collect()
+                    } else {
+                        self.neighborhood
+                            .choose_multiple(&mut rng, fanout)
+                            .collect()
+                    };
+                    for n in to {
                         let know_to_n = &self.known[n];
-                        let (already_known, mut notify_of): (HashSet<_>, HashSet<_>) = self
+                        let (already_known, mut notify_of): (BTreeSet<_>, BTreeSet<_>) = self
                             .messages
                             .iter()
                             .copied()
-                            .partition(|m| !know_to_n.contains(m));
+                            .partition(|m| know_to_n.contains(m));
                         // if we know that n know m, we don't tell n that _we_ know m
                         // so n will send us m for all eternity. so, we include a couple of
                         // extra m's so they gradually know all the things that we know without
                         // sending lots of extra stuff each time.
                         // include a couple of extra messages to let them know that we know them
 
-                        eprint!("notify of: {}/|{}", notify_of.len(), self.messages.len());
-                        let mut rng = rand::thread_rng();
+                        // what doesn't fit in this batch goes in one of the next, and which
+                        // does is picked at random, so no message is stuck behind the same
+                        // few until n tells us it has them
+                        if self.gossip.batch > 0 {
+                            notify_of = notify_of
+                                .into_iter()
+                                .choose_multiple(&mut rng, self.gossip.batch)
+                                .into_iter()
+                                .collect();
+                        }
+                        log::trace!(
+                            "notify {} of {}/{}",
+                            n,
+                            notify_of.len(),
+                            self.messages.len()
+                        );
                         notify_of.extend(already_known.iter().filter(|_| {
                             rng.gen_ratio(
                                 10.min(already_known.len() as u32),
@@ -106,19 +162,25 @@ impl Node<(), Payload, InjectedPayload> for BroadcastNode {
                             body: Body {
                                 id: None,
                                 in_reply_to: None,
+                                correlation_id: None,
                                 payload: Payload::Gossip { seen: notify_of },
                             },
                         }
                         .send(&mut *output)
                         .with_context(|| format!("gossip to {}", n))?;
-                        self.id += 1;
                     }
                 }
             },
             Event::Message(input) => {
-                let mut reply = input.into_reply(Some(&mut self.id));
+                // gossip's never answered, so it doesn't use up an id
+                let id = match input.body.payload {
+                    Payload::Gossip { .. } => None,
+                    _ => Some(&mut self.id),
+                };
+                let mut reply = input.into_reply(id);
                 match reply.body.payload {
                     Payload::Gossip { seen } => {
+                        count_gossip(&reply.dst, &seen, &self.messages);
                         self.known
                             .get_mut(&reply.dst)
                             .expect("got gossip of unknow node")
@@ -150,8 +212,62 @@ impl Node<(), Payload, InjectedPayload> for BroadcastNode {
 
         Ok(())
     }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({ "messages": &self.messages })
+    }
+
+    // whatever a neighbour told us about, we took in ourselves
+    fn invariants(&self) -> anyhow::Result<()> {
+        for (n, known) in &self.known {
+            if let Some(m) = known.difference(&self.messages).next() {
+                anyhow::bail!("{} knows {}, but we don't", n, m);
+            }
+        }
+        Ok(())
+    }
+}
+
+// how much of what `from` gossiped we knew already, of everything it's gossiped, which is how
+// much of the gossip could have gone unsent: the number to watch when tuning RUSTENGAN_GOSSIP_*
+fn count_gossip(from: &str, seen: &BTreeSet<usize>, messages: &BTreeSet<usize>) {
+    if !metrics::enabled() {
+        return;
+    }
+    let known = seen.intersection(messages).count() as u64;
+    let name = "rustengan_gossip_elements_total";
+    metrics::count(name, &[("from", from), ("new", "false")], known);
+    metrics::count(
+        name,
+        &[("from", from), ("new", "true")],
+        seen.len() as u64 - known,
+    );
+    let (mut known, mut total) = (0, 0);
+    for (labels, n) in metrics::counters(name) {
+        if !labels
+            .iter()
+            .any(|(key, value)| key == "from" && value == from)
+        {
+            continue;
+        }
+        total += n;
+        if labels
+            .iter()
+            .any(|(key, value)| key == "new" && value == "false")
+        {
+            known += n;
+        }
+    }
+    if total > 0 {
+        let ratio = known as f64 / total as f64;
+        metrics::gauge(
+            "rustengan_gossip_redundancy_ratio",
+            &[("from", from)],
+            ratio,
+        );
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    main_loop::<_, BroadcastNode, _, _>(())
+    main_loop::<_, BroadcastNode, _, _>(Gossip::from_env()?)
 }
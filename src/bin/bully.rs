@@ -0,0 +1,247 @@
+use anyhow::{Context, Ok};
+use rustengan::failure_detector::{FailureDetector, FdEvent, Strategy};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+const FAIL_AFTER: Duration = Duration::from_millis(1000);
+// how long higher nodes get to answer an election before we take over ourselves
+const ELECTION_TIMEOUT: Duration = Duration::from_millis(500);
+// how long a higher node that answered gets to announce itself before we try again
+const COORDINATOR_TIMEOUT: Duration = Duration::from_millis(1500);
+// how often the leader announces itself again, for nodes that missed it or elected someone else
+// while they were cut off from it
+const ANNOUNCE_EVERY: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read,
+    ReadOk {
+        leader: Option<String>,
+    },
+    Election,
+    // a higher node is alive and takes the election over from here
+    #[serde(rename = "ok")]
+    ElectionOk,
+    Coordinator {
+        leader: String,
+    },
+    Heartbeat,
+}
+
+pub enum InjectedPayload {
+    Fd(FdEvent),
+}
+
+impl From<FdEvent> for InjectedPayload {
+    fn from(event: FdEvent) -> Self {
+        Self::Fd(event)
+    }
+}
+
+enum State {
+    // everyone holds an election as soon as the first heartbeat comes around, which is also how a
+    // restarted node bullies its way back into the lead
+    Starting,
+    Following,
+    // we've called an election and are waiting to hear from a higher node
+    Electing { started: Instant },
+    // a higher node answered and is now running its own election
+    AwaitingCoordinator { since: Instant },
+}
+
+/// Bully election: the highest ranked node that's alive leads, rank being the position in the
+/// node list maelstrom gives us. A node calls an election by sending `election` to everyone
+/// ranked above it; if none of them answers, it announces itself to everyone as coordinator.
+/// Whoever does answer runs an election of its own, so the highest live node always ends up
+/// winning. The leader keeps announcing itself every so often, so that nodes that lost the
+/// announcement, or elected a leader of their own while cut off, come round to it.
+pub struct BullyNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    fd: FailureDetector<Payload, InjectedPayload>,
+    leader: Option<String>,
+    state: State,
+    // when we last announced ourselves as leader
+    announced: Instant,
+}
+
+impl Node<(), Payload, InjectedPayload> for BullyNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            fd: FailureDetector::start(
+                &init.node_id,
+                init.node_ids.clone(),
+                HEARTBEAT_INTERVAL,
+                Strategy::from_env(FAIL_AFTER)?,
+                tx,
+            ),
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            leader: None,
+            state: State::Starting,
+            announced: clock::now(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Fd(FdEvent::Heartbeat)) => {
+                self.fd.heartbeat(&mut *output)?;
+                match self.state {
+                    State::Starting => self.start_election(output)?,
+                    // nobody above us answered
+                    State::Electing { started } if clock::since(started) >= ELECTION_TIMEOUT => {
+                        self.become_leader(output)?;
+                    }
+                    State::AwaitingCoordinator { since }
+                        if clock::since(since) >= COORDINATOR_TIMEOUT =>
+                    {
+                        self.start_election(output)?;
+                    }
+                    State::Following
+                        if self.leader.as_ref() == Some(&self.node)
+                            && clock::since(self.announced) >= ANNOUNCE_EVERY =>
+                    {
+                        self.announce(output)?;
+                    }
+                    _ => {}
+                }
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerDown(n))) => {
+                if self.leader.as_ref() == Some(&n) {
+                    log::warn!("leader {} is down", n);
+                    self.leader = None;
+                    self.start_election(output)?;
+                }
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerUp(_))) => {}
+            Event::Message(input) => {
+                self.fd.heard_from(&input.src);
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            leader: self.leader.clone(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::Election => {
+                        // it's below us, so we outrank it: tell it to stand down and run our own
+                        self.send(&src, Payload::ElectionOk, output)?;
+                        if self.leader.as_ref() == Some(&self.node) {
+                            // we already lead, it just hasn't heard
+                            let coordinator = Payload::Coordinator {
+                                leader: self.node.clone(),
+                            };
+                            self.send(&src, coordinator, output)?;
+                        } else if matches!(self.state, State::Starting | State::Following) {
+                            self.start_election(output)?;
+                        }
+                    }
+                    Payload::ElectionOk => {
+                        if matches!(self.state, State::Electing { .. }) {
+                            self.state = State::AwaitingCoordinator {
+                                since: clock::now(),
+                            };
+                        }
+                    }
+                    Payload::Coordinator { leader } => {
+                        if self.rank(&leader) < self.rank(&self.node) {
+                            // a lower node thinks it leads, so it didn't hear from us in time
+                            self.start_election(output)?;
+                        } else {
+                            log::info!("{} is the leader", leader);
+                            self.leader = Some(leader);
+                            self.state = State::Following;
+                        }
+                    }
+                    Payload::ReadOk { .. } | Payload::Heartbeat => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({ "leader": self.leader })
+    }
+}
+
+impl BullyNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn rank(&self, node: &str) -> usize {
+        self.nodes
+            .iter()
+            .position(|n| n == node)
+            .expect("only cluster members are ranked")
+    }
+
+    fn start_election(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let higher = &self.nodes[self.rank(&self.node) + 1..];
+        if higher.is_empty() {
+            return self.become_leader(output);
+        }
+        for n in higher {
+            self.send(n, Payload::Election, output)?;
+        }
+        self.state = State::Electing {
+            started: clock::now(),
+        };
+        Ok(())
+    }
+
+    fn become_leader(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        log::info!("taking over as leader");
+        self.leader = Some(self.node.clone());
+        self.state = State::Following;
+        self.announce(output)
+    }
+
+    fn announce(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        self.announced = clock::now();
+        for n in self.nodes.iter().filter(|n| **n != self.node) {
+            let coordinator = Payload::Coordinator {
+                leader: self.node.clone(),
+            };
+            self.send(n, coordinator, output)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, BullyNode, _, _>(())
+}
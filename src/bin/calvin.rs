@@ -0,0 +1,271 @@
+use anyhow::{Context, Ok};
+use rustengan::txn::Op;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+// how long a node collects transactions before sealing them into a batch
+const EPOCH: Duration = Duration::from_millis(10);
+const RETRANSMIT_AFTER: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Txn { txn: Vec<Op> },
+    TxnOk { txn: Vec<Op> },
+    // every transaction the sender sequenced during an epoch, possibly none
+    Batch { epoch: u64, txns: Vec<Vec<Op>> },
+    BatchOk { epoch: u64 },
+}
+
+pub enum InjectedPayload {
+    Epoch,
+}
+
+// one of our batches that not every peer has acknowledged yet
+struct Outgoing {
+    txns: Vec<Vec<Op>>,
+    unacked: HashSet<String>,
+    sent_at: Instant,
+}
+
+/// Deterministic transactions, Calvin style. Where the txn binary has the participants of every
+/// transaction agree on its fate through two (or three) phase commit, here the agreeing happens
+/// once, up front, on the order of transactions, and then there's nothing left to agree on.
+///
+/// Every node is a sequencer: it collects the transactions clients send it into a batch per
+/// epoch and replicates the batch to every other node. The log is epoch after epoch, and within
+/// an epoch each node's batch in node order. A replica executes an epoch once it has every
+/// node's batch for it, one transaction at a time, against its own full copy of the store. Since
+/// every replica runs the same transactions in the same order from the same state, they all end
+/// up in the same state without ever talking about individual transactions, and a transaction
+/// can't abort: whatever it reads is whatever the log order says it reads.
+///
+/// The price is that an epoch can't run until every node's batch is in, so one slow or
+/// partitioned node holds everyone up, and a transaction waits for the epoch it lands in.
+pub struct CalvinNode {
+    node: String,
+    id: usize,
+    // the same on every node, which is what makes the order within an epoch agree
+    nodes: Vec<String>,
+    // the epoch we're currently collecting transactions into
+    epoch: u64,
+    current: Vec<Vec<Op>>,
+    // who to answer once our transactions in each epoch have run
+    clients: HashMap<u64, Vec<(String, Option<usize>)>>,
+    outgoing: BTreeMap<u64, Outgoing>,
+    // batches by epoch and sequencer, for epochs that haven't run yet
+    batches: BTreeMap<u64, HashMap<String, Vec<Vec<Op>>>>,
+    // the next epoch to execute
+    executed: u64,
+    store: HashMap<usize, usize>,
+}
+
+impl Node<(), Payload, InjectedPayload> for CalvinNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(EPOCH, tx, || Event::Injected(InjectedPayload::Epoch));
+        Ok(Self {
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            epoch: 0,
+            current: Vec::new(),
+            clients: HashMap::new(),
+            outgoing: BTreeMap::new(),
+            batches: BTreeMap::new(),
+            executed: 0,
+            store: HashMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Epoch) => {
+                self.seal(output)?;
+                let mut resend = Vec::new();
+                for (&epoch, out) in &mut self.outgoing {
+                    if clock::since(out.sent_at) < RETRANSMIT_AFTER {
+                        continue;
+                    }
+                    out.sent_at = clock::now();
+                    for n in &out.unacked {
+                        resend.push((n.clone(), epoch, out.txns.clone()));
+                    }
+                }
+                for (n, epoch, txns) in resend {
+                    self.send(&n, Payload::Batch { epoch, txns }, output)?;
+                }
+            }
+            Event::Message(input) => {
+                let reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Txn { txn } => {
+                        self.current.push(txn);
+                        self.clients
+                            .entry(self.epoch)
+                            .or_default()
+                            .push((src, reply.body.in_reply_to));
+                    }
+                    Payload::Batch { epoch, txns } => {
+                        // anything before `executed` is a retransmission of a batch we've run
+                        if epoch >= self.executed {
+                            self.batches
+                                .entry(epoch)
+                                .or_default()
+                                .entry(src.clone())
+                                .or_insert(txns);
+                        }
+                        self.send(&src, Payload::BatchOk { epoch }, output)?;
+                        self.execute(output)?;
+                    }
+                    Payload::BatchOk { epoch } => {
+                        if let Some(out) = self.outgoing.get_mut(&epoch) {
+                            out.unacked.remove(&src);
+                            if out.unacked.is_empty() {
+                                self.outgoing.remove(&epoch);
+                            }
+                        }
+                    }
+                    Payload::TxnOk { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl CalvinNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        payload: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to txn")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    // closes the current epoch and replicates its batch. empty batches go out too: the others
+    // can't run the epoch until they know we had nothing in it.
+    fn seal(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let epoch = self.epoch;
+        self.epoch += 1;
+        let txns = std::mem::take(&mut self.current);
+        let peers: HashSet<String> = self
+            .nodes
+            .iter()
+            .filter(|n| **n != self.node)
+            .cloned()
+            .collect();
+        for n in &peers {
+            let batch = Payload::Batch {
+                epoch,
+                txns: txns.clone(),
+            };
+            self.send(n, batch, output)?;
+        }
+        if !peers.is_empty() {
+            self.outgoing.insert(
+                epoch,
+                Outgoing {
+                    txns: txns.clone(),
+                    unacked: peers,
+                    sent_at: clock::now(),
+                },
+            );
+        }
+        self.batches
+            .entry(epoch)
+            .or_default()
+            .insert(self.node.clone(), txns);
+        self.execute(output)
+    }
+
+    // runs every epoch we have all batches for, in log order
+    fn execute(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        loop {
+            let complete = self
+                .batches
+                .get(&self.executed)
+                .is_some_and(|b| b.len() == self.nodes.len());
+            if !complete {
+                return Ok(());
+            }
+            let epoch = self.executed;
+            let mut batches = self.batches.remove(&epoch).expect("just checked");
+            self.executed += 1;
+            let mut clients = self.clients.remove(&epoch).unwrap_or_default().into_iter();
+            for n in self.nodes.clone() {
+                for mut txn in batches.remove(&n).expect("every node's batch is in") {
+                    self.apply(&mut txn);
+                    if n == self.node {
+                        let (client, msg_id) =
+                            clients.next().expect("a client for each of our txns");
+                        self.reply_client(client, msg_id, Payload::TxnOk { txn }, output)?;
+                    }
+                }
+            }
+        }
+    }
+
+    fn apply(&mut self, txn: &mut [Op]) {
+        for op in txn {
+            if op.is_write() {
+                if let Some(value) = op.2 {
+                    self.store.insert(op.key(), value);
+                }
+            } else {
+                op.2 = self.store.get(&op.key()).copied();
+            }
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, CalvinNode, _, _>(())
+}
@@ -0,0 +1,621 @@
+use anyhow::{Context, Ok};
+use rand::Rng;
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+const TICK: Duration = Duration::from_millis(50);
+// an acceptor that hasn't answered a phase in this long is asked again
+const RETRY_AFTER: Duration = Duration::from_millis(300);
+// after losing a round to a competing proposer, wait up to this long before the next, so two
+// proposers for one key don't keep preempting each other
+const MAX_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Read {
+        key: usize,
+    },
+    ReadOk {
+        value: usize,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    CasOk,
+    Error {
+        code: usize,
+        text: String,
+    },
+    Prepare {
+        op: u64,
+        key: usize,
+        ballot: Ballot,
+    },
+    // the acceptor won't accept anything below the ballot any more, and this is what it last
+    // accepted
+    Promise {
+        op: u64,
+        ballot: Ballot,
+        accepted: Option<(Ballot, Option<usize>)>,
+    },
+    Accept {
+        op: u64,
+        key: usize,
+        ballot: Ballot,
+        value: Option<usize>,
+    },
+    Accepted {
+        op: u64,
+        ballot: Ballot,
+    },
+    // the acceptor has promised a higher ballot than the one it was asked about
+    Reject {
+        op: u64,
+        ballot: Ballot,
+        promised: Ballot,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct Ballot {
+    counter: u64,
+    proposer: String,
+}
+
+// acceptor state changes, logged before they're answered: an acceptor that forgot a promise or
+// an accepted value after a restart would break the majorities it was part of
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Promised {
+        key: usize,
+        ballot: Ballot,
+    },
+    Accepted {
+        key: usize,
+        ballot: Ballot,
+        value: Option<usize>,
+    },
+}
+
+#[derive(Default)]
+struct Acceptor {
+    promised: Ballot,
+    accepted: Option<(Ballot, Option<usize>)>,
+}
+
+// what an operation does to the current value of its key. `None` is a key that was never
+// written.
+#[derive(Clone, Copy)]
+enum Change {
+    Read,
+    Write(usize),
+    Cas { from: usize, to: usize },
+}
+
+impl Change {
+    // the new value, and what to tell the client once it's chosen
+    fn apply(self, key: usize, current: Option<usize>) -> (Option<usize>, Payload) {
+        match (self, current) {
+            (Change::Read, Some(value)) => (current, Payload::ReadOk { value }),
+            (Change::Write(value), _) => (Some(value), Payload::WriteOk),
+            (Change::Cas { from, to }, Some(value)) if value == from => (Some(to), Payload::CasOk),
+            (Change::Cas { from, .. }, Some(value)) => (
+                current,
+                Payload::Error {
+                    code: error::PRECONDITION_FAILED,
+                    text: format!("expected {}, had {}", from, value),
+                },
+            ),
+            (Change::Read | Change::Cas { .. }, None) => (
+                None,
+                Payload::Error {
+                    code: error::KEY_DOES_NOT_EXIST,
+                    text: format!("key {} does not exist", key),
+                },
+            ),
+        }
+    }
+}
+
+enum Phase {
+    Prepare {
+        highest: Option<(Ballot, Option<usize>)>,
+    },
+    Accept {
+        value: Option<usize>,
+        reply: Payload,
+    },
+    // lost the round, waiting to try again
+    Backoff {
+        until: Instant,
+    },
+}
+
+struct Proposal {
+    client: String,
+    msg_id: Option<usize>,
+    key: usize,
+    change: Change,
+    ballot: Ballot,
+    phase: Phase,
+    answered: HashSet<String>,
+    sent: Instant,
+}
+
+enum InjectedPayload {
+    Tick,
+}
+
+/// A KV store where every key is its own CASPaxos register: single-decree Paxos run over and over
+/// on the key's current value, with no log. Any node can propose. It prepares a ballot higher
+/// than any it has seen on a majority of acceptors, applies the operation's change function to
+/// the newest value they accepted, and has a majority accept the result. Reads and failed
+/// compare-and-sets go through the accept phase too, with the value unchanged, so that whatever
+/// they saw is chosen before they answer.
+///
+/// A proposer preempted by a higher ballot backs off for a random while and starts over with a
+/// higher one. Acceptors log their promises and accepted values, so a node that restarts still
+/// honours them.
+struct CasPaxosNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    wal: Option<Wal<Record>>,
+    acceptors: HashMap<usize, Acceptor>,
+    proposals: HashMap<u64, Proposal>,
+    next_op: u64,
+    // the highest ballot counter we've seen, so ours can go above it
+    counter: u64,
+}
+
+impl Node<(), Payload, InjectedPayload> for CasPaxosNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        let mut node = Self {
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            wal: None,
+            acceptors: HashMap::new(),
+            proposals: HashMap::new(),
+            next_op: 1,
+            counter: 0,
+        };
+        let (wal, records) = Wal::open(wal::data_dir().join(format!("{}.caspaxos.wal", node.node)))
+            .context("open acceptor wal")?;
+        for record in records {
+            node.apply(record);
+        }
+        node.wal = Some(wal);
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => {
+                let due: Vec<u64> = self
+                    .proposals
+                    .iter()
+                    .filter(|(_, p)| match p.phase {
+                        Phase::Backoff { until } => until <= clock::now(),
+                        _ => clock::since(p.sent) > RETRY_AFTER,
+                    })
+                    .map(|(&op, _)| op)
+                    .collect();
+                for op in due {
+                    let proposal = self.proposals.get(&op).expect("just listed it");
+                    if matches!(proposal.phase, Phase::Backoff { .. }) {
+                        self.prepare(op, output)?;
+                    } else {
+                        self.broadcast(op, output)?;
+                    }
+                }
+            }
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    Payload::Read { key } => {
+                        self.propose(src, client_msg_id, key, Change::Read, output)?;
+                    }
+                    Payload::Write { key, value } => {
+                        let change = Change::Write(value);
+                        self.propose(src, client_msg_id, key, change, output)?;
+                    }
+                    Payload::Cas { key, from, to } => {
+                        let change = Change::Cas { from, to };
+                        self.propose(src, client_msg_id, key, change, output)?;
+                    }
+                    Payload::Prepare { op, key, ballot } => {
+                        let reply = self.on_prepare(op, key, ballot)?;
+                        self.send(&src, reply, output)?;
+                    }
+                    Payload::Accept {
+                        op,
+                        key,
+                        ballot,
+                        value,
+                    } => {
+                        let reply = self.on_accept(op, key, ballot, value)?;
+                        self.send(&src, reply, output)?;
+                    }
+                    Payload::Promise {
+                        op,
+                        ballot,
+                        accepted,
+                    } => self.promised(op, src, ballot, accepted, output)?,
+                    Payload::Accepted { op, ballot } => self.accepted(op, src, ballot, output)?,
+                    Payload::Reject {
+                        op,
+                        ballot,
+                        promised,
+                    } => self.rejected(op, ballot, promised),
+                    Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::CasOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({
+            "keys": self.acceptors.len(),
+            "proposals": self.proposals.len(),
+            "ballot_counter": self.counter,
+        })
+    }
+
+    // one accept and, if it's promised higher since, one promise per key is all a restart needs
+    fn snapshot(&mut self) -> anyhow::Result<()> {
+        let mut records = Vec::new();
+        for (&key, acceptor) in &self.acceptors {
+            if let Some((ballot, value)) = &acceptor.accepted {
+                records.push(Record::Accepted {
+                    key,
+                    ballot: ballot.clone(),
+                    value: *value,
+                });
+            }
+            if acceptor
+                .accepted
+                .as_ref()
+                .is_none_or(|(ballot, _)| *ballot < acceptor.promised)
+            {
+                records.push(Record::Promised {
+                    key,
+                    ballot: acceptor.promised.clone(),
+                });
+            }
+        }
+        self.wal
+            .as_mut()
+            .expect("wal is open")
+            .rewrite(&records)
+            .context("rewrite acceptor wal")
+    }
+}
+
+impl CasPaxosNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        client: &str,
+        msg_id: Option<usize>,
+        payload: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client.to_string(),
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn majority(&self) -> usize {
+        self.nodes.len() / 2 + 1
+    }
+
+    fn log(&mut self, record: Record) -> anyhow::Result<()> {
+        self.wal
+            .as_mut()
+            .expect("wal is open")
+            .append(&record)
+            .context("append to acceptor wal")?;
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Promised { key, ballot } => {
+                self.counter = self.counter.max(ballot.counter);
+                self.acceptors.entry(key).or_default().promised = ballot;
+            }
+            Record::Accepted { key, ballot, value } => {
+                self.counter = self.counter.max(ballot.counter);
+                let acceptor = self.acceptors.entry(key).or_default();
+                acceptor.promised = ballot.clone();
+                acceptor.accepted = Some((ballot, value));
+            }
+        }
+    }
+
+    fn on_prepare(&mut self, op: u64, key: usize, ballot: Ballot) -> anyhow::Result<Payload> {
+        let acceptor = self.acceptors.entry(key).or_default();
+        if ballot <= acceptor.promised {
+            let promised = acceptor.promised.clone();
+            return Ok(Payload::Reject {
+                op,
+                ballot,
+                promised,
+            });
+        }
+        let accepted = acceptor.accepted.clone();
+        self.log(Record::Promised {
+            key,
+            ballot: ballot.clone(),
+        })?;
+        Ok(Payload::Promise {
+            op,
+            ballot,
+            accepted,
+        })
+    }
+
+    fn on_accept(
+        &mut self,
+        op: u64,
+        key: usize,
+        ballot: Ballot,
+        value: Option<usize>,
+    ) -> anyhow::Result<Payload> {
+        let acceptor = self.acceptors.entry(key).or_default();
+        // the proposer that prepared it may have moved on to a higher ballot already, which is
+        // fine: accepting is what it asked for
+        if ballot < acceptor.promised {
+            let promised = acceptor.promised.clone();
+            return Ok(Payload::Reject {
+                op,
+                ballot,
+                promised,
+            });
+        }
+        let already = acceptor
+            .accepted
+            .as_ref()
+            .is_some_and(|(b, _)| *b == ballot);
+        if !already {
+            self.log(Record::Accepted {
+                key,
+                ballot: ballot.clone(),
+                value,
+            })?;
+        }
+        Ok(Payload::Accepted { op, ballot })
+    }
+
+    fn propose(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        key: usize,
+        change: Change,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let op = self.next_op;
+        self.next_op += 1;
+        self.proposals.insert(
+            op,
+            Proposal {
+                client,
+                msg_id,
+                key,
+                change,
+                ballot: Ballot::default(),
+                phase: Phase::Prepare { highest: None },
+                answered: HashSet::new(),
+                sent: clock::now(),
+            },
+        );
+        self.prepare(op, output)
+    }
+
+    // starts a round with a ballot higher than any we've seen
+    fn prepare(&mut self, op: u64, output: &mut Output) -> anyhow::Result<()> {
+        self.counter += 1;
+        let ballot = Ballot {
+            counter: self.counter,
+            proposer: self.node.clone(),
+        };
+        let proposal = self.proposals.get_mut(&op).expect("preparing a proposal");
+        proposal.ballot = ballot;
+        proposal.phase = Phase::Prepare { highest: None };
+        proposal.answered.clear();
+        self.broadcast(op, output)
+    }
+
+    // sends the current phase to every acceptor that hasn't answered it, ourselves included
+    fn broadcast(&mut self, op: u64, output: &mut Output) -> anyhow::Result<()> {
+        let Some(proposal) = self.proposals.get_mut(&op) else {
+            return Ok(());
+        };
+        proposal.sent = clock::now();
+        let (key, ballot) = (proposal.key, proposal.ballot.clone());
+        let request = match &proposal.phase {
+            Phase::Prepare { .. } => Payload::Prepare { op, key, ballot },
+            Phase::Accept { value, .. } => Payload::Accept {
+                op,
+                key,
+                ballot,
+                value: *value,
+            },
+            Phase::Backoff { .. } => return Ok(()),
+        };
+        let waiting: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|n| !proposal.answered.contains(*n))
+            .cloned()
+            .collect();
+        for n in waiting {
+            if n != self.node {
+                self.send(&n, request.clone(), output)?;
+                continue;
+            }
+            let reply = match request.clone() {
+                Payload::Prepare { op, key, ballot } => self.on_prepare(op, key, ballot)?,
+                Payload::Accept {
+                    op,
+                    key,
+                    ballot,
+                    value,
+                } => self.on_accept(op, key, ballot, value)?,
+                _ => unreachable!("only phase messages are broadcast"),
+            };
+            match reply {
+                Payload::Promise {
+                    op,
+                    ballot,
+                    accepted,
+                } => self.promised(op, n, ballot, accepted, output)?,
+                Payload::Accepted { op, ballot } => self.accepted(op, n, ballot, output)?,
+                Payload::Reject {
+                    op,
+                    ballot,
+                    promised,
+                } => self.rejected(op, ballot, promised),
+                _ => unreachable!("acceptors only promise, accept or reject"),
+            }
+        }
+        Ok(())
+    }
+
+    fn promised(
+        &mut self,
+        op: u64,
+        acceptor: String,
+        ballot: Ballot,
+        accepted: Option<(Ballot, Option<usize>)>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let majority = self.majority();
+        let Some(proposal) = self.proposals.get_mut(&op) else {
+            return Ok(());
+        };
+        let Phase::Prepare { highest } = &mut proposal.phase else {
+            return Ok(());
+        };
+        if ballot != proposal.ballot {
+            return Ok(());
+        }
+        proposal.answered.insert(acceptor);
+        if accepted.as_ref().map(|(b, _)| b) > highest.as_ref().map(|(b, _)| b) {
+            *highest = accepted;
+        }
+        if proposal.answered.len() < majority {
+            return Ok(());
+        }
+        let current = highest.as_ref().and_then(|(_, value)| *value);
+        let (value, reply) = proposal.change.apply(proposal.key, current);
+        proposal.phase = Phase::Accept { value, reply };
+        proposal.answered.clear();
+        self.broadcast(op, output)
+    }
+
+    fn accepted(
+        &mut self,
+        op: u64,
+        acceptor: String,
+        ballot: Ballot,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let majority = self.majority();
+        let Some(proposal) = self.proposals.get_mut(&op) else {
+            return Ok(());
+        };
+        if !matches!(proposal.phase, Phase::Accept { .. }) || ballot != proposal.ballot {
+            return Ok(());
+        }
+        proposal.answered.insert(acceptor);
+        if proposal.answered.len() < majority {
+            return Ok(());
+        }
+        let proposal = self.proposals.remove(&op).expect("just had it");
+        let Phase::Accept { reply, .. } = proposal.phase else {
+            unreachable!("checked the phase above");
+        };
+        self.reply_client(&proposal.client, proposal.msg_id, reply, output)
+    }
+
+    fn rejected(&mut self, op: u64, ballot: Ballot, promised: Ballot) {
+        self.counter = self.counter.max(promised.counter);
+        let Some(proposal) = self.proposals.get_mut(&op) else {
+            return;
+        };
+        if ballot != proposal.ballot || matches!(proposal.phase, Phase::Backoff { .. }) {
+            return;
+        }
+        let backoff = rng::thread().gen_range(Duration::ZERO..MAX_BACKOFF);
+        proposal.phase = Phase::Backoff {
+            until: clock::now() + backoff,
+        };
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, CasPaxosNode, _, _>(())
+}
@@ -0,0 +1,227 @@
+use anyhow::{Context, Ok};
+use rustengan::vclock::VClock;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
+
+pub(crate) const RETRANSMIT_EVERY: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Payload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    // in the order they were delivered here, which respects causality
+    ReadOk {
+        messages: Vec<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    Causal(Causal),
+    CausalOk {
+        origin: String,
+        seq: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Causal {
+    origin: String,
+    // everything the origin had delivered when it broadcast this, including this message itself
+    clock: VClock,
+    message: usize,
+}
+
+impl Causal {
+    fn seq(&self) -> u64 {
+        self.clock.get(&self.origin)
+    }
+}
+
+pub(crate) enum InjectedPayload {
+    Retransmit,
+}
+
+pub(crate) struct CausalNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    // how many messages from each origin we've delivered
+    delivered: VClock,
+    log: Vec<usize>,
+    // received, but waiting on something that happened before it
+    pending: Vec<Causal>,
+    // our own broadcasts each peer hasn't confirmed yet, by sequence number
+    unacked: HashMap<String, BTreeMap<u64, Causal>>,
+}
+
+impl Node<(), Payload, InjectedPayload> for CausalNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(RETRANSMIT_EVERY, tx, || {
+            Event::Injected(InjectedPayload::Retransmit)
+        });
+        Ok(Self {
+            id: 1,
+            unacked: init
+                .node_ids
+                .iter()
+                .filter(|n| **n != init.node_id)
+                .map(|n| (n.clone(), BTreeMap::new()))
+                .collect(),
+            node: init.node_id,
+            nodes: init.node_ids,
+            delivered: VClock::new(),
+            log: Vec::new(),
+            pending: Vec::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Retransmit) => {
+                for (n, unacked) in &self.unacked {
+                    for causal in unacked.values() {
+                        self.send(n, Payload::Causal(causal.clone()), output)?;
+                    }
+                }
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Broadcast { message } => {
+                        self.delivered.increment(&self.node);
+                        self.log.push(message);
+                        let causal = Causal {
+                            origin: self.node.clone(),
+                            clock: self.delivered.clone(),
+                            message,
+                        };
+                        for n in self.nodes.iter().filter(|n| **n != self.node) {
+                            self.unacked
+                                .get_mut(n)
+                                .expect("every peer has an unacked entry")
+                                .insert(causal.seq(), causal.clone());
+                            self.send(n, Payload::Causal(causal.clone()), output)?;
+                        }
+                        reply.body.payload = Payload::BroadcastOk;
+                        reply.send(&mut *output).context("reply to broadcast")?;
+                    }
+                    Payload::Causal(causal) => {
+                        self.send(
+                            &reply.dst,
+                            Payload::CausalOk {
+                                origin: causal.origin.clone(),
+                                seq: causal.seq(),
+                            },
+                            output,
+                        )?;
+                        if causal.seq() > self.delivered.get(&causal.origin)
+                            && !self
+                                .pending
+                                .iter()
+                                .any(|p| p.origin == causal.origin && p.seq() == causal.seq())
+                        {
+                            self.pending.push(causal);
+                            self.deliver_pending();
+                        }
+                    }
+                    Payload::CausalOk { origin, seq } => {
+                        if origin == self.node {
+                            if let Some(unacked) = self.unacked.get_mut(&reply.dst) {
+                                unacked.remove(&seq);
+                            }
+                        }
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            messages: self.log.clone(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    // every broadcast goes straight to every node, so there's nothing to route
+                    Payload::Topology { .. } => {
+                        reply.body.payload = Payload::TopologyOk;
+                        reply.send(&mut *output).context("reply to topology")?;
+                    }
+                    Payload::BroadcastOk | Payload::ReadOk { .. } | Payload::TopologyOk => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({ "log": self.log, "pending": self.pending.len() })
+    }
+
+    fn invariants(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.log.len() as u64 == self.delivered.total(),
+            "{} messages in the log, but {} delivered",
+            self.log.len(),
+            self.delivered.total()
+        );
+        Ok(())
+    }
+}
+
+impl CausalNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    // a message can be delivered once it's the origin's next one and we've delivered everything
+    // the origin had when it sent it. every delivery may unblock others, so go until we're stuck.
+    fn deliver_pending(&mut self) {
+        loop {
+            let ready = self.pending.iter().position(|causal| {
+                let mut next = self.delivered.clone();
+                next.increment(&causal.origin);
+                causal.seq() == next.get(&causal.origin) && causal.clock <= next
+            });
+            let Some(ready) = ready else {
+                break;
+            };
+            let causal = self.pending.swap_remove(ready);
+            self.delivered.increment(&causal.origin);
+            self.log.push(causal.message);
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, CausalNode, _, _>(())
+}
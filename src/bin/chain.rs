@@ -0,0 +1,712 @@
+use anyhow::{Context, Ok};
+use rustengan::failure_detector::{FailureDetector, FdEvent, Strategy};
+use rustengan::kv::{KvRequest, LIN_KV};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+// a node we haven't heard from in this long is proposed for splicing out of the chain (unless
+// RUSTENGAN_FD picks another detector). chain replication assumes fail-stop, so a node that's
+// been spliced out never comes back, even if it turns out to have been alive.
+const FAIL_AFTER: Duration = Duration::from_millis(1000);
+// where lin-kv keeps the chain's configuration
+const CONFIG_KEY: &str = "chain-config";
+// how often a node rereads the configuration, in case it missed a change
+const CONFIG_REFRESH: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: usize,
+    },
+    // carries either a value for a client or, coming from lin-kv, the chain's configuration
+    ReadOk {
+        value: serde_json::Value,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    CasOk,
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a client request on its way to the head (writes) or the tail (reads)
+    Forward {
+        origin: String,
+        req_id: usize,
+        request: Box<Payload>,
+    },
+    // the reply to a forwarded request, on its way back to the node the client talked to
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+    // an update passed down the chain by a member of configuration `epoch`
+    Update {
+        epoch: u64,
+        update: Update,
+    },
+    // everything up to and including seq has reached the tail
+    Ack {
+        seq: u64,
+    },
+    // the configuration changed
+    Reconfigure {
+        config: Config,
+    },
+    Heartbeat,
+}
+
+/// Which nodes make up the chain, in order, as of `epoch`. lin-kv holds the current one, and
+/// every change is a cas from one epoch to the next, so however the nodes' failure detectors
+/// disagree, there's only ever one configuration for each epoch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Config {
+    pub epoch: u64,
+    pub chain: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Update {
+    seq: u64,
+    key: usize,
+    value: usize,
+    origin: String,
+    req_id: usize,
+    reply: Box<Payload>,
+    // the client operation it's for, which it goes down the chain under however it gets sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    correlation_id: Option<String>,
+}
+
+pub enum InjectedPayload {
+    Fd(FdEvent),
+}
+
+impl From<FdEvent> for InjectedPayload {
+    fn from(event: FdEvent) -> Self {
+        Self::Fd(event)
+    }
+}
+
+enum KvCall {
+    // rereading the configuration, in case we missed a change
+    Refresh,
+    // checking that we're still the tail before answering these reads
+    Confirm(Vec<Read>),
+    // reading the configuration to splice this node out of it
+    Propose(String),
+    // swapping in a configuration without a failed node
+    Cas(Config),
+}
+
+// a read waiting on the tail, for `origin`'s request `req_id`
+struct Read {
+    origin: String,
+    req_id: usize,
+    key: usize,
+    correlation_id: Option<String>,
+}
+
+/// Chain replication: writes go to the head and are passed down the chain, and are committed,
+/// and answered, once they reach the tail. Reads are served by the tail, which has everything
+/// that's committed and nothing that isn't. Any node takes requests from clients and forwards
+/// them to wherever they go.
+///
+/// The chain is a [`Config`] held in lin-kv, which is the one authority on who's in it: a node
+/// whose failure detector says a member is down proposes splicing it out, and every member
+/// follows whatever configuration lin-kv has, not its own detector. Updates carry the epoch
+/// they were sent in and one from an older configuration is turned away, so a node that's been
+/// spliced out can't get a write committed behind the new chain's back, and the tail checks its
+/// configuration is still current before answering reads, so one that's been spliced out doesn't
+/// hand out stale values.
+pub struct ChainNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    config: Config,
+    fd: FailureDetector<Payload, InjectedPayload>,
+    // outstanding lin-kv requests, by msg_id
+    kv: HashMap<usize, KvCall>,
+    // nodes we're proposing to splice out
+    proposing: HashSet<String>,
+    last_refresh: Instant,
+    // reads waiting for the tail to confirm its configuration, once the one in flight is back
+    unconfirmed: Vec<Read>,
+    confirming: bool,
+
+    store: HashMap<usize, usize>,
+    applied: u64,
+    // updates that arrived ahead of one we haven't seen yet
+    buffered: BTreeMap<u64, Update>,
+    // updates we've passed down the chain that the tail hasn't acknowledged yet. if our successor
+    // is spliced out, these are exactly what the new one may be missing.
+    sent: BTreeMap<u64, Update>,
+
+    // requests from our own clients we're waiting on the rest of the chain for
+    pending: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for ChainNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            fd: FailureDetector::start(
+                &init.node_id,
+                init.node_ids.clone(),
+                HEARTBEAT_INTERVAL,
+                Strategy::from_env(FAIL_AFTER)?,
+                tx,
+            ),
+            node: init.node_id,
+            id: 1,
+            // until lin-kv has one, every node is in the chain, in the order maelstrom gave us
+            config: Config {
+                epoch: 0,
+                chain: init.node_ids.clone(),
+            },
+            nodes: init.node_ids,
+            kv: HashMap::new(),
+            proposing: HashSet::new(),
+            last_refresh: clock::now(),
+            unconfirmed: Vec::new(),
+            confirming: false,
+            store: HashMap::new(),
+            applied: 0,
+            buffered: BTreeMap::new(),
+            sent: BTreeMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Fd(FdEvent::Heartbeat)) => {
+                self.fd.heartbeat(&mut *output)?;
+                self.propose_failed(output)?;
+                if clock::since(self.last_refresh) >= CONFIG_REFRESH {
+                    self.last_refresh = clock::now();
+                    self.read_config(KvCall::Refresh, output)?;
+                }
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerDown(_))) => {
+                self.propose_failed(output)?;
+            }
+            // a proposal still in flight gives up on it when it gets to the cas
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerUp(_))) => {}
+            Event::Message(input) if input.src == LIN_KV => {
+                let Some(call) = input.body.in_reply_to.and_then(|id| self.kv.remove(&id)) else {
+                    log::warn!("reply from lin-kv to nothing we asked: {:?}", input.body);
+                    return Ok(());
+                };
+                self.kv_reply(call, input.body.payload, output)?;
+            }
+            Event::Message(input) => {
+                self.fd.heard_from(&input.src);
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    request @ (Payload::Read { .. }
+                    | Payload::Write { .. }
+                    | Payload::Cas { .. }) => {
+                        let req_id = self.id;
+                        self.id += 1;
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let me = self.node.clone();
+                        self.handle(me, req_id, request, output)?;
+                    }
+                    Payload::Forward {
+                        origin,
+                        req_id,
+                        request,
+                    } => self.handle(origin, req_id, *request, output)?,
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    // from a node that's since been spliced out. our predecessor may just not have
+                    // heard about the new configuration yet, and what it sends is still good.
+                    Payload::Update { epoch, .. }
+                        if !self.in_chain()
+                            || (epoch < self.config.epoch
+                                && self.neighbour(-1).as_ref() != Some(&src)) =>
+                    {
+                        log::debug!("turning away an update from {} in epoch {}", src, epoch);
+                    }
+                    Payload::Update { epoch, update } => {
+                        if epoch > self.config.epoch {
+                            // our predecessor's a step ahead of us
+                            self.read_config(KvCall::Refresh, output)?;
+                        }
+                        self.buffered.insert(update.seq, update);
+                        self.apply_buffered(output)?;
+                        if self.is_tail() && self.applied > 0 {
+                            // (re-)acknowledge, in case this was a resend after a failure
+                            self.ack(self.applied, output)?;
+                        }
+                    }
+                    Payload::Ack { seq } => self.ack(seq, output)?,
+                    Payload::Reconfigure { config } => self.install(config, output)?,
+                    Payload::Heartbeat
+                    | Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::CasOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({ "epoch": self.config.epoch, "chain": self.config.chain })
+    }
+}
+
+impl ChainNode {
+    fn in_chain(&self) -> bool {
+        self.config.chain.contains(&self.node)
+    }
+
+    fn head(&self) -> Option<&String> {
+        self.config.chain.first()
+    }
+
+    fn tail(&self) -> Option<&String> {
+        self.config.chain.last()
+    }
+
+    fn is_tail(&self) -> bool {
+        self.tail() == Some(&self.node)
+    }
+
+    fn neighbour(&self, offset: isize) -> Option<String> {
+        let chain = &self.config.chain;
+        let me = chain.iter().position(|n| *n == self.node)? as isize;
+        chain.get(usize::try_from(me + offset).ok()?).cloned()
+    }
+
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn handle(
+        &mut self,
+        origin: String,
+        req_id: usize,
+        request: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let target = match request {
+            Payload::Read { .. } => self.tail(),
+            _ => self.head(),
+        };
+        let Some(target) = target.cloned() else {
+            let reply = Payload::Error {
+                code: error::TEMPORARILY_UNAVAILABLE,
+                text: "there's nobody left in the chain".to_string(),
+            };
+            return self.respond(&origin, req_id, reply, output);
+        };
+        if target != self.node {
+            let forward = Payload::Forward {
+                origin,
+                req_id,
+                request: Box::new(request),
+            };
+            return self.send(&target, forward, output);
+        }
+
+        let (key, value, reply) = match request {
+            Payload::Read { key } => {
+                self.unconfirmed.push(Read {
+                    origin,
+                    req_id,
+                    key,
+                    correlation_id: correlation::current(),
+                });
+                return self.confirm(output);
+            }
+            Payload::Write { key, value } => (key, value, Payload::WriteOk),
+            Payload::Cas { key, from, to } => match self.store.get(&key) {
+                // the head has seen every write before anybody else, so it can decide cas on
+                // its own, even for writes that haven't made it to the tail yet
+                Some(&current) if current == from => (key, to, Payload::CasOk),
+                Some(&current) => {
+                    let reply = Payload::Error {
+                        code: error::PRECONDITION_FAILED,
+                        text: format!("expected {}, had {}", from, current),
+                    };
+                    return self.respond(&origin, req_id, reply, output);
+                }
+                None => return self.respond(&origin, req_id, not_found(key), output),
+            },
+            _ => unreachable!("only client requests are forwarded"),
+        };
+        let update = Update {
+            seq: self.applied + 1,
+            key,
+            value,
+            origin,
+            req_id,
+            reply: Box::new(reply),
+            correlation_id: correlation::current(),
+        };
+        self.buffered.insert(update.seq, update);
+        self.apply_buffered(output)
+    }
+
+    fn apply_buffered(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        while let Some(entry) = self.buffered.first_entry() {
+            if *entry.key() <= self.applied {
+                entry.remove();
+                continue;
+            }
+            if *entry.key() != self.applied + 1 {
+                break;
+            }
+            let update = entry.remove();
+            self.applied = update.seq;
+            self.store.insert(update.key, update.value);
+            // it may have been waiting on one that came in under another operation's id, so
+            // it goes on under its own
+            match self.neighbour(1) {
+                Some(successor) => {
+                    self.pass_on(&successor, &update, output)?;
+                    self.sent.insert(update.seq, update);
+                }
+                // we're the tail, so it's committed
+                None if self.is_tail() => self.commit(update, output)?,
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    // sends `update` on down the chain, under its own operation's correlation id
+    fn pass_on(&self, successor: &str, update: &Update, output: &mut Output) -> anyhow::Result<()> {
+        let forward = Payload::Update {
+            epoch: self.config.epoch,
+            update: update.clone(),
+        };
+        correlation::stepping(update.correlation_id.clone(), || {
+            self.send(successor, forward, output)
+        })
+    }
+
+    // `update` has reached the tail, so it's committed and its client can hear about it
+    fn commit(&mut self, update: Update, output: &mut Output) -> anyhow::Result<()> {
+        correlation::stepping(update.correlation_id, || {
+            self.respond(&update.origin, update.req_id, *update.reply, output)
+        })
+    }
+
+    fn ack(&mut self, seq: u64, output: &mut Output) -> anyhow::Result<()> {
+        self.sent.retain(|s, _| *s > seq);
+        if let Some(predecessor) = self.neighbour(-1) {
+            self.send(&predecessor, Payload::Ack { seq }, output)?;
+        }
+        Ok(())
+    }
+
+    fn respond(
+        &mut self,
+        origin: &str,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if origin == self.node {
+            return self.reply_client(req_id, reply, output);
+        }
+        let done = Payload::Done {
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.send(origin, done, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            // a duplicate after a resend
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    // answer reads once we know we're still the tail. a tail that's been spliced out, but
+    // hasn't heard yet, would otherwise answer from a store the new chain has moved on from.
+    fn confirm(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        if self.confirming || self.unconfirmed.is_empty() {
+            return Ok(());
+        }
+        self.confirming = true;
+        let reads = std::mem::take(&mut self.unconfirmed);
+        self.read_config(KvCall::Confirm(reads), output)
+    }
+
+    fn read_config(&mut self, call: KvCall, output: &mut Output) -> anyhow::Result<()> {
+        let msg_id = self.next_id();
+        self.kv.insert(msg_id, call);
+        KvRequest::<&str, Config>::Read { key: CONFIG_KEY }.send(
+            &self.node,
+            LIN_KV,
+            msg_id,
+            &mut *output,
+        )
+    }
+
+    fn kv_reply(
+        &mut self,
+        call: KvCall,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        // what lin-kv has as the configuration, if that's what we asked for and it answered
+        let current = match (&call, reply) {
+            (KvCall::Cas(_), reply) => {
+                return self.swapped(call, matches!(reply, Payload::CasOk), output)
+            }
+            (_, Payload::ReadOk { value }) => {
+                Some(serde_json::from_value(value).context("parse configuration")?)
+            }
+            // nobody's changed it yet
+            (_, Payload::Error { code, .. }) if code == error::KEY_DOES_NOT_EXIST => {
+                Some(self.initial())
+            }
+            (_, reply) => {
+                log::warn!("lin-kv couldn't give us the configuration: {:?}", reply);
+                None
+            }
+        };
+        if let Some(current) = &current {
+            self.install(current.clone(), output)?;
+        }
+
+        match call {
+            KvCall::Refresh | KvCall::Cas(_) => Ok(()),
+            KvCall::Confirm(reads) => {
+                self.confirming = false;
+                if current.is_none() {
+                    // try again with everything that's come in since
+                    self.unconfirmed.splice(0..0, reads);
+                    return self.confirm(output);
+                }
+                for read in reads {
+                    correlation::stepping(read.correlation_id, || {
+                        if !self.is_tail() {
+                            let request = Payload::Read { key: read.key };
+                            return self.handle(read.origin, read.req_id, request, output);
+                        }
+                        let reply = match self.store.get(&read.key) {
+                            Some(&value) => Payload::ReadOk {
+                                value: serde_json::json!(value),
+                            },
+                            None => not_found(read.key),
+                        };
+                        self.respond(&read.origin, read.req_id, reply, output)
+                    })?;
+                }
+                self.confirm(output)
+            }
+            KvCall::Propose(peer) => {
+                let Some(current) = current else {
+                    self.proposing.remove(&peer);
+                    return Ok(());
+                };
+                let still_down = self.fd.is_down(&peer);
+                if !self.in_chain()
+                    || !current.chain.contains(&peer)
+                    || !still_down
+                    || current.chain.len() < 2
+                {
+                    self.proposing.remove(&peer);
+                    return Ok(());
+                }
+                let next = Config {
+                    epoch: current.epoch + 1,
+                    chain: current
+                        .chain
+                        .iter()
+                        .filter(|n| **n != peer)
+                        .cloned()
+                        .collect(),
+                };
+                log::warn!("proposing to splice {} out, for epoch {}", peer, next.epoch);
+                let msg_id = self.next_id();
+                self.kv.insert(msg_id, KvCall::Cas(next.clone()));
+                self.proposing.remove(&peer);
+                KvRequest::Cas {
+                    key: CONFIG_KEY,
+                    from: current.clone(),
+                    to: next,
+                    // nobody's written the first configuration yet
+                    create_if_not_exists: current.epoch == 0,
+                }
+                .send(&self.node, LIN_KV, msg_id, &mut *output)
+            }
+        }
+    }
+
+    // lin-kv's answer to our cas of `call`. if someone else got in first, the next heartbeat
+    // proposes again against whatever they put there.
+    fn swapped(&mut self, call: KvCall, ok: bool, output: &mut Output) -> anyhow::Result<()> {
+        let KvCall::Cas(config) = call else {
+            return Ok(());
+        };
+        if !ok {
+            return self.read_config(KvCall::Refresh, output);
+        }
+        self.install(config.clone(), output)?;
+        for peer in self.nodes.clone() {
+            if peer != self.node {
+                let reconfigure = Payload::Reconfigure {
+                    config: config.clone(),
+                };
+                self.send(&peer, reconfigure, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    // the configuration before anyone's changed it
+    fn initial(&self) -> Config {
+        Config {
+            epoch: 0,
+            chain: self.nodes.clone(),
+        }
+    }
+
+    fn propose_failed(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        if !self.in_chain() {
+            return Ok(());
+        }
+        let down: Vec<_> = self
+            .config
+            .chain
+            .iter()
+            .filter(|n| self.fd.is_down(n) && !self.proposing.contains(*n))
+            .cloned()
+            .collect();
+        for peer in down {
+            self.proposing.insert(peer.clone());
+            self.read_config(KvCall::Propose(peer), output)?;
+        }
+        Ok(())
+    }
+
+    fn install(&mut self, config: Config, output: &mut Output) -> anyhow::Result<()> {
+        if config.epoch <= self.config.epoch {
+            return Ok(());
+        }
+        log::info!("epoch {}: {:?}", config.epoch, config.chain);
+        let was_head = self.head() == Some(&self.node);
+        let was_tail = self.is_tail();
+        let successor = self.neighbour(1);
+        self.config = config;
+        if !self.in_chain() {
+            // spliced out: from here on we only forward
+            self.buffered.clear();
+            self.sent.clear();
+            return Ok(());
+        }
+
+        if !was_head && self.head() == Some(&self.node) {
+            // whatever the old head sent us out of order, it'll never fill in the gaps now
+            self.buffered.clear();
+        }
+        let new_successor = self.neighbour(1);
+        if new_successor == successor {
+            return Ok(());
+        }
+        match new_successor {
+            // fill in whatever the node spliced out hadn't passed on yet
+            Some(successor) => {
+                for update in self.sent.values() {
+                    self.pass_on(&successor, update, output)?;
+                }
+            }
+            // the tail was spliced out and we're the new one: everything we've sent is now
+            // committed
+            None if !was_tail => {
+                let sent = std::mem::take(&mut self.sent);
+                for update in sent.into_values() {
+                    self.commit(update, output)?;
+                }
+                if self.applied > 0 {
+                    self.ack(self.applied, output)?;
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+fn not_found(key: usize) -> Payload {
+    Payload::Error {
+        code: error::KEY_DOES_NOT_EXIST,
+        text: format!("key {} does not exist", key),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, ChainNode, _, _>(())
+}
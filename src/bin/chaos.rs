@@ -0,0 +1,422 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Split, Target};
+use rustengan::sim::{self, Sim};
+use rustengan::{error, Message};
+use serde::{de::DeserializeOwned, Serialize};
+
+// the workloads' nodes, built from the same sources as their own binaries
+#[allow(dead_code)]
+#[path = "lww_kv.rs"]
+mod lww_kv;
+#[allow(dead_code)]
+#[path = "unique_ids.rs"]
+mod unique_ids;
+
+const USAGE: &str = "usage: chaos <unique-ids|lww-kv> [--seed <n>] [--for <seconds>] [--nodes <n>] [--trace <path>]";
+
+// how much virtual time an epoch spends under faults, a round at a time, before it's let settle
+const EPOCH: Duration = Duration::from_secs(60);
+const ROUND: Duration = Duration::from_millis(100);
+const SETTLE: Duration = Duration::from_secs(10);
+// how many of the last steps the nodes took to dump as the trace of a violation
+const TRACE_KEPT: usize = 10_000;
+
+// soaks a workload's nodes in the simulator, under faults and load picked at random from a
+// seed, until it's been at it for --for seconds of real time (an hour unless it's told), or
+// an invariant breaks:
+//
+//     chaos lww-kv --for 14400 --trace /tmp/chaos.jsonl
+//
+// the run is a series of epochs, each a fresh cluster simulated from a seed of its own, one on
+// from the last's: a minute of virtual time with links losing, duplicating, delaying and
+// reordering messages, the cluster partitioning, and nodes pausing and crashing, while clients
+// make requests, then a quiet spell to let it settle. Invariants are checked as replies come
+// in, and again once it's settled. On the first violation, the seed of the epoch it happened
+// in goes to stderr, and the last steps the nodes took to --trace, as the simulator's trace
+// has them. Since an epoch's seed is the first seed of a run, --seed with it replays the
+// failing epoch first, exactly.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let workload = args.next().context(USAGE)?;
+    let mut seed = sim::seed()?;
+    let mut budget = Duration::from_secs(3600);
+    let mut nodes = 5;
+    let mut trace = "chaos-trace.jsonl".to_string();
+    while let Some(arg) = args.next() {
+        let value = args.next().context(USAGE)?;
+        match arg.as_str() {
+            "--seed" => {
+                seed = value
+                    .parse()
+                    .with_context(|| format!("invalid --seed {:?}", value))?
+            }
+            "--for" => {
+                let seconds: f64 = value
+                    .parse()
+                    .with_context(|| format!("invalid --for {:?}", value))?;
+                budget = Duration::from_secs_f64(seconds);
+            }
+            "--nodes" => {
+                nodes = value
+                    .parse()
+                    .with_context(|| format!("invalid --nodes {:?}", value))?;
+                anyhow::ensure!(nodes > 0, "--nodes has to be at least 1");
+            }
+            "--trace" => trace = value,
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    let soak = Soak {
+        seed,
+        budget,
+        node_ids: (0..nodes).map(|n| format!("n{}", n)).collect(),
+        trace,
+    };
+    match workload.as_str() {
+        "unique-ids" => soak.run::<UniqueIds>(),
+        "lww-kv" => soak.run::<LwwKv>(),
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+// a workload: a cluster of nodes to start, the load to put on it and what has to hold of what
+// its clients hear back
+trait Workload: Default {
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+    type Injected: Send + 'static;
+    // whether the nodes are meant to survive crashing, losing what they kept in memory
+    const KILLS: bool;
+
+    fn start(sim: &mut Sim<Self::Payload, Self::Injected>) -> anyhow::Result<()>;
+
+    // sends a request or two from the workload's clients
+    fn load(
+        &mut self,
+        sim: &mut Sim<Self::Payload, Self::Injected>,
+        rng: &mut StdRng,
+    ) -> anyhow::Result<()>;
+
+    // checks what's come back since the last check, and once the cluster's settled, whatever
+    // should hold once it has
+    fn check(
+        &mut self,
+        sim: &mut Sim<Self::Payload, Self::Injected>,
+        settled: bool,
+    ) -> anyhow::Result<Result<(), String>>;
+}
+
+struct Soak {
+    seed: u64,
+    budget: Duration,
+    node_ids: Vec<String>,
+    trace: String,
+}
+
+impl Soak {
+    fn run<W: Workload>(&self) -> anyhow::Result<()> {
+        let started = Instant::now();
+        let mut epoch = 0;
+        while started.elapsed() < self.budget {
+            let seed = self.seed.wrapping_add(epoch);
+            let recent = Recent::default();
+            if let Err(violation) = self.epoch::<W>(seed, recent.clone())? {
+                recent.dump(&self.trace)?;
+                eprintln!(
+                    "epoch {} broke an invariant: {}\nreplay it with --seed {} (its last {} steps are in {})",
+                    epoch, violation, seed, TRACE_KEPT, self.trace
+                );
+                std::process::exit(1);
+            }
+            epoch += 1;
+        }
+        eprintln!(
+            "{} epochs from seed {} in {:?}, no violations",
+            epoch,
+            self.seed,
+            started.elapsed()
+        );
+        Ok(())
+    }
+
+    fn epoch<W: Workload>(&self, seed: u64, recent: Recent) -> anyhow::Result<Result<(), String>> {
+        let node_ids: Vec<&str> = self.node_ids.iter().map(String::as_str).collect();
+        let mut sim = Sim::new(seed, &node_ids);
+        sim.trace(recent);
+        W::start(&mut sim)?;
+        let mut workload = W::default();
+        // the load and the faults are picked by a generator of their own, so that what the
+        // nodes do doesn't change them
+        let mut rng = StdRng::seed_from_u64(seed);
+        while sim.now() < EPOCH {
+            if rng.gen_bool(0.05) {
+                sim.faults(faults(&mut rng));
+            }
+            if rng.gen_bool(0.05) {
+                sim.disrupt(disruption::<W>(&mut rng))?;
+            }
+            for _ in 0..rng.gen_range(0..4) {
+                workload.load(&mut sim, &mut rng)?;
+            }
+            sim.run_for(ROUND.mul_f64(rng.gen_range(0.1..2.0)))?;
+            if let Err(violation) = workload.check(&mut sim, false)? {
+                return Ok(Err(format!("at {:?}: {}", sim.now(), violation)));
+            }
+        }
+        sim.faults(Faults::default());
+        for disruption in [Disruption::Heal, Disruption::Resume, Disruption::Restart] {
+            sim.disrupt(disruption)?;
+        }
+        sim.run_for(SETTLE)?;
+        workload
+            .check(&mut sim, true)
+            .map(|checked| checked.map_err(|violation| format!("once settled: {}", violation)))
+    }
+}
+
+fn faults(rng: &mut StdRng) -> Faults {
+    if rng.gen_bool(0.3) {
+        return Faults::default();
+    }
+    Faults {
+        drop: rng.gen_range(0.0..0.3),
+        duplicate: rng.gen_range(0.0..0.1),
+        delay: rng.gen_range(0.0..0.3),
+        delay_by: Duration::from_millis(rng.gen_range(1..500)),
+        reorder: rng.gen_range(0.0..0.2),
+    }
+}
+
+fn disruption<W: Workload>(rng: &mut StdRng) -> Disruption {
+    let mut disruptions = vec![
+        Disruption::Partition(Split::Halves),
+        Disruption::Partition(Split::Isolate),
+        Disruption::Heal,
+        Disruption::Pause(Target::Random),
+        Disruption::Resume,
+    ];
+    if W::KILLS {
+        disruptions.extend([Disruption::Kill(Target::Random), Disruption::Restart]);
+    }
+    disruptions.choose(rng).expect("some disruptions").clone()
+}
+
+// the last TRACE_KEPT lines of the simulator's trace
+#[derive(Clone, Default)]
+struct Recent {
+    lines: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl Write for Recent {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut lines = self.lines.lock().expect("not poisoned");
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            match lines.back_mut() {
+                Some(last) if !last.ends_with(b"\n") => last.extend_from_slice(line),
+                _ => lines.push_back(line.to_vec()),
+            }
+        }
+        while lines.len() > TRACE_KEPT {
+            lines.pop_front();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Recent {
+    fn dump(&self, path: &str) -> anyhow::Result<()> {
+        let mut file = std::fs::File::create(path).with_context(|| format!("create {}", path))?;
+        for line in self.lines.lock().expect("not poisoned").iter() {
+            file.write_all(line)
+                .with_context(|| format!("write {}", path))?;
+        }
+        Ok(())
+    }
+}
+
+// unique ids: no two ids handed out are ever the same
+#[derive(Default)]
+struct UniqueIds {
+    seen: BTreeMap<String, String>,
+}
+
+impl Workload for UniqueIds {
+    type Payload = unique_ids::Payload;
+    type Injected = ();
+    // the counter behind the ids is in memory, so a node that crashes starts again from 1
+    const KILLS: bool = false;
+
+    fn start(sim: &mut Sim<Self::Payload>) -> anyhow::Result<()> {
+        sim.start::<(), unique_ids::UniqueNode>(())
+    }
+
+    fn load(&mut self, sim: &mut Sim<Self::Payload>, rng: &mut StdRng) -> anyhow::Result<()> {
+        let dst = format!("n{}", rng.gen_range(0..sim.node_ids().len()));
+        sim.send("c0", &dst, unique_ids::Payload::Generate)?;
+        Ok(())
+    }
+
+    fn check(
+        &mut self,
+        sim: &mut Sim<Self::Payload>,
+        _settled: bool,
+    ) -> anyhow::Result<Result<(), String>> {
+        for reply in sim.take_replies("c0")? {
+            let unique_ids::Payload::GenerateOk { guid } = reply.body.payload else {
+                continue;
+            };
+            if let Some(first) = self.seen.insert(guid.clone(), reply.src.clone()) {
+                return Ok(Err(format!(
+                    "{} handed out {}, which {} already had",
+                    reply.src, guid, first
+                )));
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+const KEYS: usize = 5;
+
+// a last-write-wins key/value store: a read only ever sees a value that was written to the
+// key, and once the cluster's settled every node reads the same for every key
+#[derive(Default)]
+struct LwwKv {
+    written: BTreeMap<usize, BTreeSet<usize>>,
+    next: usize,
+    // which key each read was of, by the request that asked
+    reads: BTreeMap<usize, usize>,
+}
+
+impl Workload for LwwKv {
+    type Payload = lww_kv::Payload;
+    type Injected = lww_kv::InjectedPayload;
+    const KILLS: bool = true;
+
+    fn start(sim: &mut Sim<Self::Payload, Self::Injected>) -> anyhow::Result<()> {
+        sim.start::<(), lww_kv::LwwKvNode>(())?;
+        sim.every(lww_kv::GOSSIP_EVERY, || lww_kv::InjectedPayload::Gossip);
+        Ok(())
+    }
+
+    fn load(
+        &mut self,
+        sim: &mut Sim<Self::Payload, Self::Injected>,
+        rng: &mut StdRng,
+    ) -> anyhow::Result<()> {
+        let dst = format!("n{}", rng.gen_range(0..sim.node_ids().len()));
+        let key = rng.gen_range(0..KEYS);
+        let mut read = None;
+        let request = match rng.gen_range(0..3) {
+            0 => {
+                // every value written is a new one, so a read says which write it saw
+                self.next += 1;
+                self.written.entry(key).or_default().insert(self.next);
+                lww_kv::Payload::Write {
+                    key,
+                    value: self.next,
+                }
+            }
+            1 => lww_kv::Payload::Delete { key },
+            _ => {
+                read = Some(key);
+                lww_kv::Payload::Read { key }
+            }
+        };
+        let client = format!("c{}", rng.gen_range(0..3));
+        let id = sim.send(&client, &dst, request)?;
+        if let Some(key) = read {
+            self.reads.insert(id, key);
+        }
+        Ok(())
+    }
+
+    fn check(
+        &mut self,
+        sim: &mut Sim<Self::Payload, Self::Injected>,
+        settled: bool,
+    ) -> anyhow::Result<Result<(), String>> {
+        for client in ["c0", "c1", "c2"] {
+            for reply in sim.take_replies(client)? {
+                if let Err(violation) = self.made_up(&reply) {
+                    return Ok(Err(violation));
+                }
+            }
+        }
+        if !settled {
+            return Ok(Ok(()));
+        }
+        let node_ids: Vec<String> = sim.node_ids().to_vec();
+        for node in &node_ids {
+            for key in 0..KEYS {
+                let id = sim.send("reader", node, lww_kv::Payload::Read { key })?;
+                self.reads.insert(id, key);
+            }
+        }
+        sim.run_for(Duration::from_secs(1))?;
+        // by key, what each node read
+        let mut seen: BTreeMap<usize, BTreeMap<String, Option<usize>>> = BTreeMap::new();
+        for reply in sim.take_replies("reader")? {
+            if let Err(violation) = self.made_up(&reply) {
+                return Ok(Err(violation));
+            }
+            let node = reply.src.clone();
+            let Some(&key) = reply.body.in_reply_to.and_then(|id| self.reads.get(&id)) else {
+                continue;
+            };
+            let value = match reply.body.payload {
+                lww_kv::Payload::ReadOk { value } => Some(value),
+                lww_kv::Payload::Error {
+                    code: error::KEY_DOES_NOT_EXIST,
+                    ..
+                } => None,
+                other => return Ok(Err(format!("{} answered a read with {:?}", node, other))),
+            };
+            seen.entry(key).or_default().insert(node, value);
+        }
+        for key in 0..KEYS {
+            let reads = seen.remove(&key).unwrap_or_default();
+            if reads.len() < node_ids.len() {
+                return Ok(Err(format!("only {:?} answered reads of {}", reads, key)));
+            }
+            if reads.values().collect::<BTreeSet<_>>().len() > 1 {
+                return Ok(Err(format!("nodes disagree about {}: {:?}", key, reads)));
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+impl LwwKv {
+    fn made_up(&self, reply: &Message<lww_kv::Payload>) -> Result<(), String> {
+        let lww_kv::Payload::ReadOk { value } = reply.body.payload else {
+            return Ok(());
+        };
+        let Some(&key) = reply.body.in_reply_to.and_then(|id| self.reads.get(&id)) else {
+            return Ok(());
+        };
+        if self
+            .written
+            .get(&key)
+            .is_some_and(|values| values.contains(&value))
+        {
+            return Ok(());
+        }
+        Err(format!(
+            "{} read {} from {}, which was never written to it",
+            reply.src, value, key
+        ))
+    }
+}
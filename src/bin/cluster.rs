@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+// in a directory of its own, where cargo won't take it for a binary
+#[path = "cluster/soak.rs"]
+mod soak;
+
+use soak::{Load, Mix, Soak};
+
+const USAGE: &str = "usage: cluster [--nodes <n>] [--transport <transport>] [--base-port <port>] \
+                     [--soak <seconds> [--rate <ops/s>] [--mix <op>=<weight>,...] \
+                     [--report-every <seconds>]] -- <node binary> [args...]";
+
+// how often the router looks in on a soak when there's nothing to route
+const SOAK_TICK: Duration = Duration::from_millis(100);
+
+// how long nodes get to exit on their own once their input is closed
+const STOP_WITHIN: Duration = Duration::from_millis(1000);
+
+#[derive(Deserialize)]
+struct Routed {
+    dest: String,
+}
+
+// a line for the router to deliver, and which node wrote it, if one did
+type Line = (Option<String>, String);
+
+struct Args {
+    nodes: usize,
+    transport: String,
+    base_port: u16,
+    soak: Option<Load>,
+    binary: String,
+    args: Vec<String>,
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut parsed = Args {
+        nodes: 3,
+        transport: "stdio".to_string(),
+        base_port: 7000,
+        soak: None,
+        binary: String::new(),
+        args: Vec::new(),
+    };
+    let mut soak_for = None;
+    let mut rate = 100.0;
+    let mut mix = "echo".to_string();
+    let mut report_every = 10;
+    loop {
+        match args.next().as_deref() {
+            Some("--nodes" | "-n") => {
+                let n = args.next().context(USAGE)?;
+                parsed.nodes = n
+                    .parse()
+                    .with_context(|| format!("invalid --nodes {:?}", n))?;
+                anyhow::ensure!(parsed.nodes > 0, "a cluster needs a node");
+            }
+            Some("--transport") => parsed.transport = args.next().context(USAGE)?,
+            Some("--base-port") => {
+                let port = args.next().context(USAGE)?;
+                parsed.base_port = port
+                    .parse()
+                    .with_context(|| format!("invalid --base-port {:?}", port))?;
+            }
+            Some("--soak") => {
+                let secs = args.next().context(USAGE)?;
+                let secs: u64 = secs
+                    .parse()
+                    .with_context(|| format!("invalid --soak {:?}", secs))?;
+                soak_for = Some(Duration::from_secs(secs));
+            }
+            Some("--rate") => {
+                let ops = args.next().context(USAGE)?;
+                rate = ops
+                    .parse()
+                    .with_context(|| format!("invalid --rate {:?}", ops))?;
+                anyhow::ensure!(rate > 0.0, "--rate has to be more than 0");
+            }
+            Some("--mix") => mix = args.next().context(USAGE)?,
+            Some("--report-every") => {
+                let secs = args.next().context(USAGE)?;
+                report_every = secs
+                    .parse()
+                    .with_context(|| format!("invalid --report-every {:?}", secs))?;
+                anyhow::ensure!(report_every > 0, "--report-every has to be more than 0");
+            }
+            Some("--") => break,
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    if let Some(run_for) = soak_for {
+        anyhow::ensure!(
+            parsed.transport == "stdio",
+            "a soak routes its requests itself, so it needs --transport stdio"
+        );
+        parsed.soak = Some(Load {
+            run_for,
+            rate,
+            mix: Mix::parse(&mix)?,
+            report_every: Duration::from_secs(report_every),
+        });
+    }
+    parsed.binary = args.next().context(USAGE)?;
+    parsed.args = args.collect();
+    Ok(parsed)
+}
+
+// prints every line `from` the node `name` writes, after its name
+fn prefix_lines(
+    name: String,
+    from: impl Read + Send + 'static,
+    mut to: impl Write + Send + 'static,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(from).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let _ = writeln!(to, "{}| {}", name, line);
+        }
+    });
+}
+
+// hands every line `from` the node `name` writes to the router
+fn route_lines(name: Option<String>, from: impl Read + Send + 'static, tx: Sender<Line>) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(from).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.send((name.clone(), line)).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// the addresses nodes are reached at on `transport`, as RUSTENGAN_NODES lists them
+fn addresses(args: &Args, ids: &[String]) -> anyhow::Result<String> {
+    let dir = std::env::temp_dir().join(format!("rustengan-cluster-{}", std::process::id()));
+    let mut nodes = Vec::new();
+    for (i, id) in ids.iter().enumerate() {
+        let port = args
+            .base_port
+            .checked_add(i as u16)
+            .context("--base-port too high for that many nodes")?;
+        nodes.push(match args.transport.as_str() {
+            // nodes are named, not addressed, on nats
+            "nats" => id.clone(),
+            "uds" => {
+                std::fs::create_dir_all(&dir)
+                    .with_context(|| format!("create {}", dir.display()))?;
+                format!("{}={}/{}.sock", id, dir.display(), id)
+            }
+            _ => format!("{}=127.0.0.1:{}", id, port),
+        });
+    }
+    Ok(nodes.join(","))
+}
+
+fn stop(nodes: &mut [(String, Child)]) {
+    let deadline = Instant::now() + STOP_WITHIN;
+    for (id, node) in nodes.iter_mut() {
+        // nodes that have something going on in the background don't stop at the end of their
+        // input, so they get killed once they've had their chance
+        while node.try_wait().ok().flatten().is_none() && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        if node.try_wait().ok().flatten().is_none() {
+            let _ = node.kill();
+            let _ = node.wait();
+            continue;
+        }
+        match node.wait() {
+            Ok(status) if !status.success() => eprintln!("{} exited with {}", id, status),
+            _ => {}
+        }
+    }
+}
+
+// runs a local cluster of any node binary, without Maelstrom:
+//
+//     cluster --nodes 3 -- target/debug/broadcast
+//
+// on stdio (the default) the nodes run just as they do under Maelstrom: each gets an init, and
+// every line one writes goes to the node its `dest` names. Lines on our stdin go in the same
+// way, and whatever is for anyone who isn't a node comes out on our stdout, so a client can
+// be typed, or piped in. On any other --transport the nodes are told about each other through
+// the environment and wire up themselves, and clients connect to them directly; node i listens
+// on --base-port + i. Either way every line a node writes to stderr comes out on ours, after
+// its id, and the cluster runs until our stdin closes.
+//
+// --soak runs the cluster for that many seconds under a load of its own instead of whatever's on
+// our stdin: --rate requests a second (100 by default) to random nodes, picked from --mix by
+// weight (just echo by default), where an op is one of echo, generate, broadcast, read, add,
+// write or cas, with whatever fields its workload expects. Every --report-every seconds (10 by
+// default), and once more at the end, it says on stderr how many requests failed or went
+// unanswered and how much memory each node is using, so a leak shows up as a node that keeps
+// growing under a steady load.
+fn main() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    let ids: Vec<String> = (0..args.nodes).map(|i| format!("n{}", i)).collect();
+    let routed = args.transport == "stdio";
+    let cluster = if routed {
+        None
+    } else {
+        Some(addresses(&args, &ids)?)
+    };
+
+    let (tx, rx) = std::sync::mpsc::channel::<Line>();
+    let mut nodes = Vec::new();
+    let mut inputs: HashMap<String, ChildStdin> = HashMap::new();
+    for id in &ids {
+        let mut command = Command::new(&args.binary);
+        command
+            .args(&args.args)
+            .env("RUSTENGAN_TRANSPORT", &args.transport)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if let Some(cluster) = &cluster {
+            command
+                .env("RUSTENGAN_NODE_ID", id)
+                .env("RUSTENGAN_NODES", cluster);
+        }
+        let mut node = command
+            .spawn()
+            .with_context(|| format!("start {} as {}", args.binary, id))?;
+        prefix_lines(
+            id.clone(),
+            node.stderr.take().expect("piped"),
+            std::io::stderr(),
+        );
+        let stdout = node.stdout.take().expect("piped");
+        if routed {
+            route_lines(Some(id.clone()), stdout, tx.clone());
+        } else {
+            // networked nodes have nothing to say on stdout, but if they do it shouldn't be lost
+            prefix_lines(id.clone(), stdout, std::io::stdout());
+        }
+        inputs.insert(id.clone(), node.stdin.take().expect("piped"));
+        nodes.push((id.clone(), node));
+    }
+    if let Some(cluster) = &cluster {
+        eprintln!("cluster on {}: {}", args.transport, cluster);
+    }
+
+    // our input, which ends the cluster when it does, unless there's a soak to end it instead
+    let mut soak = args.soak.as_ref().map(Soak::new);
+    let stdin_tx = tx.clone();
+    if let Some(load) = &args.soak {
+        soak::generate(load, ids.clone(), tx.clone());
+    } else {
+        std::thread::spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if stdin_tx.send((None, line)).is_err() {
+                    return;
+                }
+            }
+            let _ = stdin_tx.send((None, String::new()));
+        });
+    }
+    drop(tx);
+
+    if routed {
+        for (i, id) in ids.iter().enumerate() {
+            let init = serde_json::json!({
+                "src": "c0",
+                "dest": id,
+                "body": {"type": "init", "msg_id": i + 1, "node_id": id, "node_ids": ids},
+            });
+            if let Some(input) = inputs.get_mut(id) {
+                writeln!(input, "{}", init).with_context(|| format!("init {}", id))?;
+            }
+        }
+    }
+    let mut stdout = std::io::stdout().lock();
+    loop {
+        if let Some(soak) = &mut soak {
+            soak.check(&nodes);
+        }
+        let (from, line) = match rx.recv_timeout(SOAK_TICK) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if from.is_none() && line.is_empty() {
+            break;
+        }
+        if !routed {
+            continue;
+        }
+        let dest = match serde_json::from_str::<Routed>(&line) {
+            Ok(Routed { dest }) => dest,
+            Err(e) => {
+                eprintln!(
+                    "{}| unroutable line: {}",
+                    from.as_deref().unwrap_or("stdin"),
+                    e
+                );
+                continue;
+            }
+        };
+        if let (Some(soak), None) = (&mut soak, &from) {
+            soak.sent(&line);
+        }
+        if let Some(input) = inputs.get_mut(&dest) {
+            if writeln!(input, "{}", line).is_err() {
+                eprintln!("{} is gone, dropping a message for it", dest);
+                inputs.remove(&dest);
+            }
+        } else if dest == "c0" {
+            // the answers to our inits
+        } else if let (Some(soak), soak::CLIENT) = (&mut soak, dest.as_str()) {
+            soak.answered(&line);
+        } else {
+            writeln!(stdout, "{}", line).context("write to stdout")?;
+        }
+    }
+
+    // nodes still have their say while they stop, it just goes nowhere
+    drop(inputs);
+    if let Some(soak) = &soak {
+        eprintln!("{}", soak.summary());
+    }
+    stop(&mut nodes);
+    drop(rx);
+    Ok(())
+}
@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, HashMap};
+use std::process::Child;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::prelude::*;
+use rustengan::ddsketch::DdSketch;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::Line;
+
+// who a soak's requests come from
+pub(crate) const CLIENT: &str = "soak";
+// how long a request gets before it's counted as lost
+const TIMEOUT: Duration = Duration::from_secs(5);
+// the keys kv ops pick from, few enough that they contend
+const KEYS: usize = 16;
+
+/// An op a soak can send, as the workload it's from names it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Op {
+    Echo,
+    Generate,
+    Broadcast,
+    Read,
+    Add,
+    Write,
+    Cas,
+}
+
+impl Op {
+    fn name(self) -> &'static str {
+        match self {
+            Op::Echo => "echo",
+            Op::Generate => "generate",
+            Op::Broadcast => "broadcast",
+            Op::Read => "read",
+            Op::Add => "add",
+            Op::Write => "write",
+            Op::Cas => "cas",
+        }
+    }
+
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        let ops = [
+            Op::Echo,
+            Op::Generate,
+            Op::Broadcast,
+            Op::Read,
+            Op::Add,
+            Op::Write,
+            Op::Cas,
+        ];
+        ops.into_iter()
+            .find(|op| op.name() == name)
+            .with_context(|| format!("no op {:?}", name))
+    }
+
+    // the body of the `n`th request of the soak, as this op. a read has a key for the kv
+    // workloads, which the others ignore.
+    fn body(self, n: usize, rng: &mut impl Rng) -> Value {
+        let key = rng.gen_range(0..KEYS);
+        let value = rng.gen_range(0..KEYS);
+        match self {
+            Op::Echo => json!({ "type": "echo", "echo": format!("soak {}", n) }),
+            Op::Generate => json!({ "type": "generate" }),
+            Op::Broadcast => json!({ "type": "broadcast", "message": n }),
+            Op::Read => json!({ "type": "read", "key": key }),
+            Op::Add => json!({ "type": "add", "delta": rng.gen_range(1..=10) }),
+            Op::Write => json!({ "type": "write", "key": key, "value": value }),
+            Op::Cas => {
+                let to = rng.gen_range(0..KEYS);
+                json!({ "type": "cas", "key": key, "from": value, "to": to })
+            }
+        }
+    }
+}
+
+/// What a soak sends, and how often: ops with their weights, like `broadcast=9,read=1`.
+#[derive(Debug, Clone)]
+pub(crate) struct Mix {
+    ops: Vec<(Op, u32)>,
+}
+
+impl Mix {
+    pub(crate) fn parse(mix: &str) -> anyhow::Result<Self> {
+        let mut ops = Vec::new();
+        for op in mix.split(',') {
+            let (name, weight) = op.split_once('=').unwrap_or((op, "1"));
+            let weight = weight
+                .parse()
+                .with_context(|| format!("invalid weight {:?} for {}", weight, name))?;
+            ops.push((Op::parse(name)?, weight));
+        }
+        anyhow::ensure!(
+            ops.iter().any(|(_, weight)| *weight > 0),
+            "--mix has nothing to send"
+        );
+        Ok(Self { ops })
+    }
+
+    fn pick(&self, rng: &mut impl Rng) -> Op {
+        self.ops
+            .choose_weighted(rng, |(_, weight)| *weight)
+            .expect("some op has a weight")
+            .0
+    }
+}
+
+/// How long a soak goes on for and how hard it pushes.
+#[derive(Debug, Clone)]
+pub(crate) struct Load {
+    pub(crate) run_for: Duration,
+    /// Requests a second, across the whole cluster.
+    pub(crate) rate: f64,
+    pub(crate) mix: Mix,
+    pub(crate) report_every: Duration,
+}
+
+/// Sends requests from the mix to random nodes at the load's rate, as lines for the router, on
+/// a thread of its own, and the line that stops the cluster once the soak's run for long enough.
+pub(crate) fn generate(load: &Load, ids: Vec<String>, tx: Sender<Line>) {
+    let load = load.clone();
+    std::thread::spawn(move || {
+        let mut rng = rand::thread_rng();
+        let start = Instant::now();
+        let gap = Duration::from_secs_f64(1.0 / load.rate);
+        let mut next = start;
+        for n in 1.. {
+            if next - start >= load.run_for {
+                break;
+            }
+            if let Some(wait) = next.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+            let mut body = load.mix.pick(&mut rng).body(n, &mut rng);
+            body["msg_id"] = json!(n);
+            let dest = ids.choose(&mut rng).expect("a cluster has nodes");
+            let line = json!({ "src": CLIENT, "dest": dest, "body": body });
+            if tx.send((None, line.to_string())).is_err() {
+                return;
+            }
+            next += gap;
+        }
+        let _ = tx.send((None, String::new()));
+    });
+}
+
+#[derive(Deserialize)]
+struct Sent {
+    body: SentBody,
+}
+
+#[derive(Deserialize)]
+struct SentBody {
+    #[serde(rename = "type")]
+    kind: String,
+    msg_id: usize,
+}
+
+#[derive(Deserialize)]
+struct Reply {
+    body: ReplyBody,
+}
+
+#[derive(Deserialize)]
+struct ReplyBody {
+    #[serde(rename = "type")]
+    kind: String,
+    in_reply_to: Option<usize>,
+    code: Option<usize>,
+}
+
+// how one op has done so far
+#[derive(Default)]
+struct Outcomes {
+    sent: u64,
+    ok: u64,
+    // by error code
+    errors: BTreeMap<usize, u64>,
+    timed_out: u64,
+    // milliseconds to an answer, whatever it was
+    latency: DdSketch,
+}
+
+impl Outcomes {
+    fn failed(&self) -> u64 {
+        self.errors.values().sum::<u64>() + self.timed_out
+    }
+}
+
+// a node's resident memory, in KiB, when it was first and last looked at
+struct Memory {
+    first: u64,
+    since: Instant,
+    last: u64,
+}
+
+/// What a soak's requests have come to: how many of each op were answered, failed or never
+/// answered at all, and how much memory each node's using as it goes, so a node that leaks
+/// shows as one whose memory climbs while the load stays the same.
+pub(crate) struct Soak {
+    start: Instant,
+    report_every: Duration,
+    next_report: Instant,
+    // by msg_id, what's been sent but not answered yet, and when it went
+    pending: HashMap<usize, (String, Instant)>,
+    // by op name
+    outcomes: BTreeMap<String, Outcomes>,
+    // by node, and only where it can be read
+    memory: BTreeMap<String, Memory>,
+}
+
+impl Soak {
+    pub(crate) fn new(load: &Load) -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            report_every: load.report_every,
+            next_report: start + load.report_every,
+            pending: HashMap::new(),
+            outcomes: BTreeMap::new(),
+            memory: BTreeMap::new(),
+        }
+    }
+
+    /// Notes a request the generator made, on its way to a node.
+    pub(crate) fn sent(&mut self, line: &str) {
+        let Ok(Sent { body }) = serde_json::from_str(line) else {
+            return;
+        };
+        self.outcomes.entry(body.kind.clone()).or_default().sent += 1;
+        self.pending
+            .insert(body.msg_id, (body.kind, Instant::now()));
+    }
+
+    /// Notes an answer to one of the soak's requests.
+    pub(crate) fn answered(&mut self, line: &str) {
+        let Ok(Reply { body }) = serde_json::from_str(line) else {
+            return;
+        };
+        // answered too late, or not an answer at all
+        let Some((kind, sent)) = body.in_reply_to.and_then(|id| self.pending.remove(&id)) else {
+            return;
+        };
+        let outcomes = self.outcomes.entry(kind).or_default();
+        outcomes
+            .latency
+            .insert(sent.elapsed().as_secs_f64() * 1000.0);
+        match (body.kind.as_str(), body.code) {
+            ("error", Some(code)) => *outcomes.errors.entry(code).or_default() += 1,
+            _ => outcomes.ok += 1,
+        }
+    }
+
+    /// Counts whatever's gone unanswered for too long as lost, and reports how the soak's
+    /// going if it's been `report_every` since the last time.
+    pub(crate) fn check(&mut self, nodes: &[(String, Child)]) {
+        let now = Instant::now();
+        let outcomes = &mut self.outcomes;
+        self.pending.retain(|_, (kind, sent)| {
+            let waiting = now.duration_since(*sent) < TIMEOUT;
+            if !waiting {
+                outcomes.entry(kind.clone()).or_default().timed_out += 1;
+            }
+            waiting
+        });
+        if now < self.next_report {
+            return;
+        }
+        self.next_report += self.report_every;
+        for (id, node) in nodes {
+            let Some(kib) = resident(node.id()) else {
+                continue;
+            };
+            self.memory
+                .entry(id.clone())
+                .and_modify(|memory| memory.last = kib)
+                .or_insert(Memory {
+                    first: kib,
+                    since: now,
+                    last: kib,
+                });
+        }
+        eprintln!("{}", self.progress());
+    }
+
+    fn progress(&self) -> String {
+        let (sent, ok, failed) = self.outcomes.values().fold((0, 0, 0), |(s, o, f), op| {
+            (s + op.sent, o + op.ok, f + op.failed())
+        });
+        let mut line = format!(
+            "soak {:.0}s: {} sent, {} ok, {} failed ({:.2}%), {} waiting",
+            self.start.elapsed().as_secs_f64(),
+            sent,
+            ok,
+            failed,
+            percent(failed, sent),
+            self.pending.len()
+        );
+        for (id, memory) in &self.memory {
+            let growth = memory.last as i64 - memory.first as i64;
+            line.push_str(&format!("; {} {}KiB ({:+}KiB)", id, memory.last, growth));
+        }
+        line
+    }
+
+    /// Everything the soak came to, once it's over: each op's outcomes and latencies, and how
+    /// far each node's memory grew, by the hour.
+    pub(crate) fn summary(&self) -> String {
+        let mut summary = vec![format!(
+            "soak over after {:.0}s, with {} requests still waiting",
+            self.start.elapsed().as_secs_f64(),
+            self.pending.len()
+        )];
+        for (kind, op) in &self.outcomes {
+            let quantile = |q| op.latency.quantile(q).unwrap_or(0.0);
+            let mut line = format!(
+                "  {}: {} sent, {} ok, {} timed out, p50 {:.1}ms, p99 {:.1}ms",
+                kind,
+                op.sent,
+                op.ok,
+                op.timed_out,
+                quantile(0.5),
+                quantile(0.99)
+            );
+            for (code, count) in &op.errors {
+                line.push_str(&format!(", {} error {}", count, code));
+            }
+            line.push_str(&format!(" ({:.2}% failed)", percent(op.failed(), op.sent)));
+            summary.push(line);
+        }
+        for (id, memory) in &self.memory {
+            let growth = memory.last as f64 - memory.first as f64;
+            let hours = memory.since.elapsed().as_secs_f64() / 3600.0;
+            summary.push(format!(
+                "  {}: {}KiB to {}KiB, {:+.0}KiB an hour",
+                id,
+                memory.first,
+                memory.last,
+                growth / hours
+            ));
+        }
+        summary.join("\n")
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        0.0
+    } else {
+        part as f64 * 100.0 / whole as f64
+    }
+}
+
+// the resident memory of process `pid` in KiB, where /proc has it
+fn resident(pid: u32) -> Option<u64> {
+    let status = std::fs::read_to_string(format!("/proc/{}/status", pid)).ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, IsTerminal, Write};
+use std::path::Path;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rustengan::stats::Line;
+
+// how often the table's redrawn
+const REDRAW_EVERY: Duration = Duration::from_secs(1);
+// how long a followed file is left before looking for more in it
+const FOLLOW_EVERY: Duration = Duration::from_millis(200);
+// how many stats lines a node can miss before it's marked as quiet
+const QUIET_AFTER: u32 = 3;
+// how much of the last thing a node logged is shown
+const SAID_WIDTH: usize = 60;
+
+// a line a node wrote to stderr, and which node
+type Said = (String, String);
+
+#[derive(Default)]
+struct Watched {
+    stats: Option<(Instant, Line)>,
+    // the last thing it logged that wasn't stats
+    said: Option<String>,
+}
+
+// hands every line of stdin to `tx`, as the node the cluster's prefix says wrote it
+fn read_stdin(tx: Sender<Said>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let said = match line.split_once("| ") {
+                Some((node, line)) if !node.contains(' ') => (node.to_string(), line.to_string()),
+                _ => ("node".to_string(), line),
+            };
+            if tx.send(said).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// hands every line written to `path` to `tx`, as `node`, now and as more are
+fn follow(node: String, path: String, tx: Sender<Said>) -> anyhow::Result<()> {
+    let file = File::open(&path).with_context(|| format!("open {}", path))?;
+    std::thread::spawn(move || {
+        let mut file = BufReader::new(file);
+        let mut line = String::new();
+        loop {
+            match file.read_line(&mut line) {
+                // nothing more yet, or half a line
+                Ok(_) if !line.ends_with('\n') => std::thread::sleep(FOLLOW_EVERY),
+                Ok(_) => {
+                    let said = (node.clone(), line.trim_end().to_string());
+                    if tx.send(said).is_err() {
+                        return;
+                    }
+                    line.clear();
+                }
+                Err(e) => {
+                    let _ = tx.send((node, format!("can't read {}: {}", path, e)));
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+fn per_second(n: u64, over: Duration) -> String {
+    format!("{:.1}", n as f64 / over.as_secs_f64().max(0.001))
+}
+
+// what all the nodes that know of a leader say it is
+fn leaders(nodes: &BTreeMap<String, Watched>) -> String {
+    let mut leaders: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, line) in nodes.values().filter_map(|node| node.stats.as_ref()) {
+        if let Some((_, leader)) = line.status.iter().find(|(key, _)| key == "leader") {
+            *leaders.entry(leader).or_default() += 1;
+        }
+    }
+    match leaders.len() {
+        0 => String::new(),
+        1 => {
+            let (leader, n) = leaders.into_iter().next().expect("one leader");
+            format!("leader {} ({} of {} nodes agree)\n", leader, n, nodes.len())
+        }
+        _ => {
+            let votes: Vec<String> = leaders
+                .iter()
+                .map(|(leader, n)| format!("{} by {}", leader, n))
+                .collect();
+            format!("leaders disagree: {}\n", votes.join(", "))
+        }
+    }
+}
+
+fn render(nodes: &BTreeMap<String, Watched>) -> String {
+    let mut columns: Vec<&str> = Vec::new();
+    for (_, line) in nodes.values().filter_map(|node| node.stats.as_ref()) {
+        for (key, _) in &line.status {
+            if !columns.contains(&key.as_str()) {
+                columns.push(key);
+            }
+        }
+    }
+    let mut header: Vec<String> = ["node", "events/s", "sent/s", "queued", "kB/s", "most sent"]
+        .into_iter()
+        .chain(columns.iter().copied())
+        .map(str::to_string)
+        .collect();
+    header.push("last said".to_string());
+    let mut rows = vec![header];
+    for (id, node) in nodes {
+        let mut row = vec![id.clone()];
+        match &node.stats {
+            Some((at, line)) => {
+                let over = line.over;
+                let mut events = per_second(line.events, over);
+                if at.elapsed() > over * QUIET_AFTER {
+                    events = format!("quiet {}s", at.elapsed().as_secs());
+                }
+                row.push(events);
+                row.push(per_second(line.sent.iter().map(|(_, n)| n).sum(), over));
+                row.push(line.queued.to_string());
+                row.push(format!(
+                    "{:.1}",
+                    line.bytes as f64 / 1000.0 / over.as_secs_f64().max(0.001)
+                ));
+                row.push(match line.sent.first() {
+                    Some((kind, n)) => format!("{} {}", kind, per_second(*n, over)),
+                    None => "-".to_string(),
+                });
+                for column in &columns {
+                    let value = line.status.iter().find(|(key, _)| key == column);
+                    row.push(value.map_or("-".to_string(), |(_, value)| value.clone()));
+                }
+            }
+            None => row.extend(std::iter::repeat_n("-".to_string(), 5 + columns.len())),
+        }
+        let said = node.said.as_deref().unwrap_or_default();
+        row.push(said.chars().take(SAID_WIDTH).collect());
+        rows.push(row);
+    }
+
+    let mut widths = vec![0; rows[0].len()];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut table = leaders(nodes);
+    for row in rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+// watches a cluster's nodes through the stats lines RUSTENGAN_STATS_MS has them write to
+// stderr, as a table redrawn every second of each node's message rates, queue depth and
+// whatever its status says, like how many messages it knows of or who it thinks leads, with
+// which leader the nodes agree on above it. Either what the cluster launcher writes to stderr,
+// with each line after its node's id:
+//
+//     RUSTENGAN_STATS_MS=1000 cluster --nodes 5 -- target/debug/broadcast 2>&1 >/dev/null | dashboard
+//
+// which it watches until the cluster stops, or the stderr of nodes run some other way, each in
+// a file named for its node, which it follows as they grow:
+//
+//     dashboard /tmp/logs/n0.log /tmp/logs/n1.log /tmp/logs/n2.log
+//
+// a node that's missed its last few stats lines is marked as quiet.
+fn main() -> anyhow::Result<()> {
+    let paths: Vec<String> = std::env::args().skip(1).collect();
+    anyhow::ensure!(
+        !paths.iter().any(|arg| arg.starts_with('-')),
+        "usage: dashboard [<node stderr>...]"
+    );
+    let (tx, rx) = std::sync::mpsc::channel::<Said>();
+    let mut nodes: BTreeMap<String, Watched> = BTreeMap::new();
+    if paths.is_empty() {
+        read_stdin(tx);
+    } else {
+        for path in paths {
+            let node = Path::new(&path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("no node id in {}", path))?
+                .to_string();
+            nodes.entry(node.clone()).or_default();
+            follow(node, path, tx.clone())?;
+        }
+        drop(tx);
+    }
+
+    let terminal = std::io::stdout().is_terminal();
+    let mut stdout = std::io::stdout().lock();
+    let mut next = Instant::now() + REDRAW_EVERY;
+    loop {
+        let (node, line) = match rx.recv_timeout(next.saturating_duration_since(Instant::now())) {
+            Ok(said) => said,
+            Err(RecvTimeoutError::Timeout) => {
+                next = Instant::now() + REDRAW_EVERY;
+                if terminal {
+                    // from the top of a cleared screen
+                    write!(stdout, "\x1b[H\x1b[2J").context("write to stdout")?;
+                }
+                write!(stdout, "{}", render(&nodes)).context("write to stdout")?;
+                if !terminal {
+                    writeln!(stdout).context("write to stdout")?;
+                }
+                stdout.flush().context("write to stdout")?;
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let watched = nodes.entry(node).or_default();
+        match Line::parse(&line) {
+            Some(stats) => watched.stats = Some((Instant::now(), stats)),
+            None => watched.said = Some(line),
+        }
+    }
+    // whatever was going on when the cluster stopped
+    write!(stdout, "{}", render(&nodes)).context("write to stdout")?;
+    Ok(())
+}
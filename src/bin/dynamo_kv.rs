@@ -0,0 +1,758 @@
+use anyhow::{Context, Ok};
+use rand::prelude::*;
+use rustengan::failure_detector::{FailureDetector, FdEvent, Strategy};
+use rustengan::merkle::MerkleTree;
+use rustengan::shard::{self, Placement};
+use rustengan::vclock::VClock;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+const FAIL_AFTER: Duration = Duration::from_millis(1000);
+pub const VNODES: usize = 64;
+// copies of every key, and how many of them a read and a write wait for. R + W > N, so every
+// read hears from at least one replica that took the latest acknowledged write, as long as the
+// preference list doesn't change underneath.
+pub const N: usize = 3;
+const R: usize = 2;
+const W: usize = 2;
+// a request that hasn't heard from enough replicas by now is failed
+const REQUEST_TIMEOUT: Duration = Duration::from_millis(1000);
+// anti-entropy with a random peer every this many heartbeats
+const SYNC_EVERY: usize = 10;
+// the merkle trees split the keyspace into 2^MERKLE_DEPTH ranges
+const MERKLE_DEPTH: u32 = 6;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: usize,
+    },
+    ReadOk {
+        value: usize,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // coordinator to replica: what do you have for key?
+    Fetch {
+        req_id: usize,
+        key: usize,
+    },
+    FetchOk {
+        req_id: usize,
+        versions: Vec<Version>,
+    },
+    // coordinator to replica: keep these. a hint names the replica they're really meant for,
+    // which was down when the coordinator picked us to stand in for it.
+    Store {
+        req_id: usize,
+        key: usize,
+        versions: Vec<Version>,
+        hint: Option<String>,
+    },
+    StoreOk {
+        req_id: usize,
+    },
+    // read repair: like a store, but nobody waits for it
+    Repair {
+        key: usize,
+        versions: Vec<Version>,
+        hint: Option<String>,
+    },
+    // hinted writes going home once their replica is back up
+    Handoff {
+        entries: Vec<(usize, Vec<Version>)>,
+    },
+    HandoffOk {
+        entries: Vec<(usize, Vec<Version>)>,
+    },
+    // anti-entropy: our merkle tree hashes at `level` for the keys we and the receiver both
+    // replicate, for the nodes the receiver should check
+    Compare {
+        level: usize,
+        hashes: Vec<(usize, u64)>,
+    },
+    // everything we have in ranges (leaf buckets) the two trees disagree on. if `reply`, the
+    // receiver sends its own back.
+    SyncRanges {
+        buckets: Vec<usize>,
+        entries: Vec<(usize, Vec<Version>)>,
+        reply: bool,
+    },
+    SyncStats,
+    SyncStatsOk {
+        stats: SyncStats,
+    },
+    Heartbeat,
+}
+
+/// How anti-entropy has gone on this node so far.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncStats {
+    // comparisons we started
+    rounds: u64,
+    // comparisons whose roots matched, so there was nothing to do
+    in_sync: u64,
+    // ranges that differed and were exchanged
+    ranges_synced: u64,
+    // keys that changed here as a result
+    keys_repaired: u64,
+}
+
+/// One write of a key. A replica keeps every write no other write it has seen descends from, so
+/// after a partition it may hold several siblings until a later write supersedes them all.
+#[derive(Debug, Clone, Hash, Serialize, Deserialize)]
+pub struct Version {
+    value: usize,
+    clock: VClock,
+    // the coordinator, which breaks ties between siblings
+    writer: String,
+}
+
+pub enum InjectedPayload {
+    Fd(FdEvent),
+}
+
+impl From<FdEvent> for InjectedPayload {
+    fn from(event: FdEvent) -> Self {
+        Self::Fd(event)
+    }
+}
+
+enum Phase {
+    Fetching,
+    // got enough fetches and sent out the new version
+    Storing { acks: usize },
+    // a read that has been answered, kept around to repair replicas that reply late
+    Repairing,
+}
+
+struct Request {
+    client: String,
+    msg_id: Option<usize>,
+    key: usize,
+    // what a write writes
+    value: Option<usize>,
+    started: Instant,
+    phase: Phase,
+    // replica, and the node it's standing in for if it isn't a preferred replica
+    targets: Vec<(String, Option<String>)>,
+    replies: HashMap<String, Vec<Version>>,
+    merged: Vec<Version>,
+}
+
+/// An eventually consistent key/value store in the style of Dynamo. Keys are placed on a hash
+/// ring and copied to the first N members of each key's preference list. Any node coordinates
+/// the requests it gets: a read asks the replicas for their versions and answers once R have
+/// replied, and a write does the same to learn the key's current clock, then stores a version
+/// descending from everything it heard on the replicas and answers after W acknowledge it.
+///
+/// Quorums are sloppy: a preferred replica the failure detector thinks is down is replaced by the
+/// next healthy member along the list, which keeps the data as a hint and hands it back once the
+/// replica is heard from again. Replicas a read finds behind are repaired on the spot, and every
+/// so often each node compares a merkle tree of the keys it shares with a random peer, which
+/// catches whatever both of those missed (a stand-in that crashed with its hints, say) without
+/// either side scanning every key.
+///
+/// Siblings left by concurrent writes are all kept, and a read returns the one with the highest
+/// clock total (then writer), which every node picks alike. Compare-and-set can't be offered on
+/// top of that.
+pub struct DynamoNode {
+    node: String,
+    id: usize,
+    fd: FailureDetector<Payload, InjectedPayload>,
+    placement: Box<dyn Placement>,
+    store: HashMap<usize, Vec<Version>>,
+    // writes we took on behalf of a replica that was down, by that replica
+    hints: HashMap<String, HashMap<usize, Vec<Version>>>,
+    requests: HashMap<usize, Request>,
+    heartbeats: usize,
+    stats: SyncStats,
+}
+
+impl Node<(), Payload, InjectedPayload> for DynamoNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let placement = shard::from_env(&init.node_ids, VNODES)?;
+        Ok(Self {
+            fd: FailureDetector::start(
+                &init.node_id,
+                init.node_ids.clone(),
+                HEARTBEAT_INTERVAL,
+                Strategy::from_env(FAIL_AFTER)?,
+                tx,
+            ),
+            node: init.node_id,
+            id: 1,
+            placement,
+            store: HashMap::new(),
+            hints: HashMap::new(),
+            requests: HashMap::new(),
+            heartbeats: 0,
+            stats: SyncStats::default(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Fd(FdEvent::Heartbeat)) => {
+                self.fd.heartbeat(&mut *output)?;
+                self.expire(output)?;
+                for (n, entries) in &self.hints {
+                    if !self.fd.is_down(n) {
+                        let handoff = Payload::Handoff {
+                            entries: entries.iter().map(|(&k, v)| (k, v.clone())).collect(),
+                        };
+                        self.send(n, handoff, output)?;
+                    }
+                }
+                self.heartbeats += 1;
+                if self.heartbeats.is_multiple_of(SYNC_EVERY) {
+                    self.start_sync(output)?;
+                }
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerDown(n))) => {
+                log::warn!("{} is down, standing in for it", n);
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerUp(n))) => {
+                log::info!("{} is back up", n);
+            }
+            Event::Message(input) => {
+                self.fd.heard_from(&input.src);
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    Payload::Read { key } => {
+                        self.coordinate(src, client_msg_id, key, None, output)?
+                    }
+                    Payload::Write { key, value } => {
+                        self.coordinate(src, client_msg_id, key, Some(value), output)?
+                    }
+                    Payload::Cas { .. } => {
+                        let error = Payload::Error {
+                            code: error::NOT_SUPPORTED,
+                            text: "cas is not supported by an eventually consistent store"
+                                .to_string(),
+                        };
+                        self.reply(src, client_msg_id, error, output)?;
+                    }
+                    Payload::Fetch { req_id, key } => {
+                        let versions = self.local(key);
+                        self.send(&src, Payload::FetchOk { req_id, versions }, output)?;
+                    }
+                    Payload::FetchOk { req_id, versions } => {
+                        self.fetched(req_id, src, versions, output)?
+                    }
+                    Payload::Store {
+                        req_id,
+                        key,
+                        versions,
+                        hint,
+                    } => {
+                        self.keep(key, versions, hint);
+                        self.send(&src, Payload::StoreOk { req_id }, output)?;
+                    }
+                    Payload::StoreOk { req_id } => self.stored(req_id, output)?,
+                    Payload::Repair {
+                        key,
+                        versions,
+                        hint,
+                    } => {
+                        self.keep(key, versions, hint);
+                    }
+                    Payload::Handoff { entries } => {
+                        for (key, versions) in entries.clone() {
+                            self.keep(key, versions, None);
+                        }
+                        self.send(&src, Payload::HandoffOk { entries }, output)?;
+                    }
+                    Payload::HandoffOk { entries } => {
+                        if let Some(hinted) = self.hints.get_mut(&src) {
+                            for (key, acked) in entries {
+                                // a write may have come in for the key since we handed it off
+                                if hinted.get(&key).is_some_and(|v| covered(v, &acked)) {
+                                    hinted.remove(&key);
+                                }
+                            }
+                            if hinted.is_empty() {
+                                log::info!("handed everything back to {}", src);
+                                self.hints.remove(&src);
+                            }
+                        }
+                    }
+                    Payload::Compare { level, hashes } => {
+                        self.compare(&src, level, hashes, output)?
+                    }
+                    Payload::SyncRanges {
+                        buckets,
+                        entries,
+                        reply,
+                    } => {
+                        self.stats.ranges_synced += buckets.len() as u64;
+                        for (key, versions) in entries {
+                            if self.keep(key, versions, None) {
+                                self.stats.keys_repaired += 1;
+                            }
+                        }
+                        if reply {
+                            let entries = self.ranges(&src, &buckets);
+                            let sync = Payload::SyncRanges {
+                                buckets,
+                                entries,
+                                reply: false,
+                            };
+                            self.send(&src, sync, output)?;
+                        }
+                    }
+                    Payload::SyncStats => {
+                        let stats = self.stats.clone();
+                        self.reply(src, client_msg_id, Payload::SyncStatsOk { stats }, output)?;
+                    }
+                    Payload::Heartbeat
+                    | Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::SyncStatsOk { .. }
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DynamoNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    // the first N members of the key's preference list, with every one that's down swapped for
+    // the next healthy member further along
+    fn targets(&self, key: usize) -> Vec<(String, Option<String>)> {
+        let all = self.placement.replicas(shard::hash(&key), usize::MAX);
+        let (preferred, rest) = all.split_at(N.min(all.len()));
+        let mut stand_ins = rest.iter().filter(|n| !self.fd.is_down(n));
+        preferred
+            .iter()
+            .filter_map(|&n| {
+                if self.fd.is_down(n) {
+                    stand_ins
+                        .next()
+                        .map(|s| (s.to_string(), Some(n.to_string())))
+                } else {
+                    Some((n.to_string(), None))
+                }
+            })
+            .collect()
+    }
+
+    // everything we have for a key, as a preferred replica or standing in for one
+    fn local(&self, key: usize) -> Vec<Version> {
+        let mut versions = self.store.get(&key).cloned().unwrap_or_default();
+        for hinted in self.hints.values() {
+            if let Some(v) = hinted.get(&key) {
+                merge(&mut versions, v.clone());
+            }
+        }
+        versions
+    }
+
+    // returns whether anything changed
+    fn keep(&mut self, key: usize, versions: Vec<Version>, hint: Option<String>) -> bool {
+        match hint {
+            Some(n) => merge(
+                self.hints.entry(n).or_default().entry(key).or_default(),
+                versions,
+            ),
+            None => merge(self.store.entry(key).or_default(), versions),
+        }
+    }
+
+    fn coordinate(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        key: usize,
+        value: Option<usize>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let targets = self.targets(key);
+        let quorum = if value.is_some() { R.max(W) } else { R };
+        if targets.len() < quorum {
+            let error = Payload::Error {
+                code: error::TEMPORARILY_UNAVAILABLE,
+                text: format!("only {} replicas are up for key {}", targets.len(), key),
+            };
+            return self.reply(client, msg_id, error, output);
+        }
+        let req_id = self.id;
+        self.id += 1;
+        for (n, _) in &targets {
+            if *n != self.node {
+                self.send(n, Payload::Fetch { req_id, key }, output)?;
+            }
+        }
+        let local = targets.iter().any(|(n, _)| *n == self.node);
+        let request = Request {
+            client,
+            msg_id,
+            key,
+            value,
+            started: clock::now(),
+            phase: Phase::Fetching,
+            targets,
+            replies: HashMap::new(),
+            merged: Vec::new(),
+        };
+        self.requests.insert(req_id, request);
+        if local {
+            let versions = self.local(key);
+            let me = self.node.clone();
+            self.fetched(req_id, me, versions, output)?;
+        }
+        Ok(())
+    }
+
+    fn fetched(
+        &mut self,
+        req_id: usize,
+        from: String,
+        versions: Vec<Version>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(request) = self.requests.get_mut(&req_id) else {
+            return Ok(());
+        };
+        merge(&mut request.merged, versions.clone());
+        request.replies.insert(from.clone(), versions);
+        match request.phase {
+            Phase::Fetching if request.replies.len() >= R => {}
+            Phase::Repairing => {
+                self.repair(req_id, &from, output)?;
+                return self.finish_repairs(req_id);
+            }
+            _ => return Ok(()),
+        }
+
+        let Some(value) = request.value else {
+            request.phase = Phase::Repairing;
+            let reply = match winner(&request.merged) {
+                Some(v) => Payload::ReadOk { value: v.value },
+                None => Payload::Error {
+                    code: error::KEY_DOES_NOT_EXIST,
+                    text: format!("key {} does not exist", request.key),
+                },
+            };
+            let (client, msg_id) = (request.client.clone(), request.msg_id);
+            self.reply(client, msg_id, reply, output)?;
+            let replied: Vec<_> = self.requests[&req_id].replies.keys().cloned().collect();
+            for n in replied {
+                self.repair(req_id, &n, output)?;
+            }
+            return self.finish_repairs(req_id);
+        };
+
+        // the new version descends from every version the replicas told us about
+        let mut clock = VClock::new();
+        for v in &request.merged {
+            clock.merge(&v.clock);
+        }
+        clock.increment(&self.node);
+        let version = Version {
+            value,
+            clock,
+            writer: self.node.clone(),
+        };
+        request.phase = Phase::Storing { acks: 0 };
+        let key = request.key;
+        let targets = request.targets.clone();
+        for (n, hint) in targets {
+            if n == self.node {
+                self.keep(key, vec![version.clone()], hint);
+                self.stored(req_id, output)?;
+            } else {
+                let store = Payload::Store {
+                    req_id,
+                    key,
+                    versions: vec![version.clone()],
+                    hint,
+                };
+                self.send(&n, store, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn stored(&mut self, req_id: usize, output: &mut Output) -> anyhow::Result<()> {
+        let Some(request) = self.requests.get_mut(&req_id) else {
+            return Ok(());
+        };
+        let Phase::Storing { acks } = &mut request.phase else {
+            return Ok(());
+        };
+        *acks += 1;
+        if *acks < W {
+            return Ok(());
+        }
+        let request = self.requests.remove(&req_id).expect("just had it");
+        self.reply(request.client, request.msg_id, Payload::WriteOk, output)
+    }
+
+    // sends a replica whatever it's missing of what the read found
+    fn repair(&mut self, req_id: usize, n: &str, output: &mut Output) -> anyhow::Result<()> {
+        let request = &self.requests[&req_id];
+        let had = &request.replies[n];
+        if covered(&request.merged, had) {
+            return Ok(());
+        }
+        let key = request.key;
+        let versions = request.merged.clone();
+        let hint = request
+            .targets
+            .iter()
+            .find(|(t, _)| t == n)
+            .and_then(|(_, hint)| hint.clone());
+        log::debug!("repairing key {} on {}", key, n);
+        if n == self.node {
+            self.keep(key, versions, hint);
+            return Ok(());
+        }
+        self.send(
+            n,
+            Payload::Repair {
+                key,
+                versions,
+                hint,
+            },
+            output,
+        )
+    }
+
+    fn finish_repairs(&mut self, req_id: usize) -> anyhow::Result<()> {
+        let request = &self.requests[&req_id];
+        if request.replies.len() == request.targets.len() {
+            self.requests.remove(&req_id);
+        }
+        Ok(())
+    }
+
+    // the keys we replicate along with `peer`, that is the ones we're both preferred replicas of.
+    // hints aren't included: they're on their way to where they belong already.
+    fn shared<'a>(&'a self, peer: &'a str) -> impl Iterator<Item = (&'a usize, &'a Vec<Version>)> {
+        self.store
+            .iter()
+            .filter(move |(key, _)| self.placement.replicas(shard::hash(key), N).contains(&peer))
+    }
+
+    fn tree(&self, peer: &str) -> MerkleTree {
+        MerkleTree::build(
+            MERKLE_DEPTH,
+            self.shared(peer).map(|(key, versions)| {
+                // siblings can be in any order
+                let digest = versions
+                    .iter()
+                    .fold(0u64, |d, v| d.wrapping_add(shard::hash(v)));
+                (shard::hash(key), digest)
+            }),
+        )
+    }
+
+    fn ranges(&self, peer: &str, buckets: &[usize]) -> Vec<(usize, Vec<Version>)> {
+        self.shared(peer)
+            .filter(|(key, _)| {
+                buckets.contains(&MerkleTree::bucket(MERKLE_DEPTH, shard::hash(key)))
+            })
+            .map(|(&key, versions)| (key, versions.clone()))
+            .collect()
+    }
+
+    fn start_sync(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let peers: Vec<_> = self
+            .placement
+            .members()
+            .into_iter()
+            .filter(|n| *n != self.node && !self.fd.is_down(n))
+            .collect();
+        let Some(peer) = peers.choose(&mut rng::thread()) else {
+            return Ok(());
+        };
+        self.stats.rounds += 1;
+        let compare = Payload::Compare {
+            level: 0,
+            hashes: vec![(0, self.tree(peer).root())],
+        };
+        self.send(peer, compare, output)
+    }
+
+    // checks a peer's hashes against our own tree, and either goes a level further down where
+    // they differ or, at the leaves, swaps the ranges that differ
+    fn compare(
+        &mut self,
+        peer: &str,
+        level: usize,
+        hashes: Vec<(usize, u64)>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let tree = self.tree(peer);
+        let differing: Vec<_> = hashes
+            .into_iter()
+            .filter(|&(index, hash)| tree.get(level, index) != hash)
+            .map(|(index, _)| index)
+            .collect();
+        if differing.is_empty() {
+            if level == 0 {
+                self.stats.in_sync += 1;
+            }
+            return Ok(());
+        }
+        if level == tree.depth() {
+            let entries = self.ranges(peer, &differing);
+            let sync = Payload::SyncRanges {
+                buckets: differing,
+                entries,
+                reply: true,
+            };
+            return self.send(peer, sync, output);
+        }
+        let hashes = differing
+            .into_iter()
+            .flat_map(MerkleTree::children)
+            .map(|child| (child, tree.get(level + 1, child)))
+            .collect();
+        let compare = Payload::Compare {
+            level: level + 1,
+            hashes,
+        };
+        self.send(peer, compare, output)
+    }
+
+    fn expire(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let expired: Vec<_> = self
+            .requests
+            .iter()
+            .filter(|(_, r)| clock::since(r.started) >= REQUEST_TIMEOUT)
+            .map(|(&id, _)| id)
+            .collect();
+        for req_id in expired {
+            let request = self.requests.remove(&req_id).expect("just listed it");
+            if matches!(request.phase, Phase::Repairing) {
+                continue;
+            }
+            let error = Payload::Error {
+                code: error::TIMEOUT,
+                text: format!(
+                    "heard from {} of {} replicas",
+                    request.replies.len(),
+                    request.targets.len()
+                ),
+            };
+            self.reply(request.client, request.msg_id, error, output)?;
+        }
+        Ok(())
+    }
+}
+
+// folds `incoming` into `versions`, keeping only versions nothing else descends from. returns
+// whether anything changed.
+fn merge(versions: &mut Vec<Version>, incoming: Vec<Version>) -> bool {
+    let mut changed = false;
+    for v in incoming {
+        if versions.iter().any(|ours| {
+            matches!(
+                ours.clock.compare(&v.clock),
+                Some(Ordering::Greater | Ordering::Equal)
+            )
+        }) {
+            continue;
+        }
+        versions.retain(|ours| !ours.clock.happened_before(&v.clock));
+        versions.push(v);
+        changed = true;
+    }
+    changed
+}
+
+// whether every version in `versions` is in, or superseded by, one in `by`
+fn covered(versions: &[Version], by: &[Version]) -> bool {
+    versions.iter().all(|v| {
+        by.iter().any(|b| {
+            matches!(
+                v.clock.compare(&b.clock),
+                Some(Ordering::Less | Ordering::Equal)
+            )
+        })
+    })
+}
+
+fn winner(versions: &[Version]) -> Option<&Version> {
+    versions
+        .iter()
+        .max_by_key(|v| (v.clock.total(), v.writer.as_str()))
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, DynamoNode, _, _>(())
+}
@@ -1,17 +1,17 @@
 use anyhow::{Context, Ok};
 use rustengan::*;
 use serde::{Deserialize, Serialize};
-use std::io::{StdoutLock, Write};
+use std::io::Write;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
-enum Payload {
+pub(crate) enum Payload {
     Echo { echo: String },
     EchoOk { echo: String },
 }
 
-struct EchoNode {
+pub(crate) struct EchoNode {
     id: usize,
 }
 
@@ -27,9 +27,12 @@ impl Node<(), Payload> for EchoNode {
         Ok(EchoNode { id: 1 })
     }
 
-    fn step(&mut self, input: Event<Payload>, output: &mut StdoutLock) -> anyhow::Result<()> {
-        let Event::Message(input) = input else {
-            panic!("got injected event when there is no event injection");
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            // nobody's left to answer once the input's closed
+            Event::EOF => return Ok(()),
+            Event::Injected(()) => panic!("got injected event when there is no event injection"),
         };
 
         let mut reply = input.into_reply(Some(&mut self.id));
@@ -0,0 +1,416 @@
+use anyhow::{Context, Ok};
+use rustengan::kv::{KvRequest, SEQ_KV};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
+
+const POOL: &str = "escrow-pool";
+const REBALANCE_INTERVAL: Duration = Duration::from_millis(300);
+// how much a node tries to keep on hand. it hands back anything over twice this and tops up when
+// it drops under half.
+const ALLOTMENT: u64 = 10;
+// a pool update gives up after losing this many cas races in a row
+const MAX_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Add { delta: u64 },
+    AddOk,
+    // decrement, but never below zero
+    Take { amount: u64 },
+    TakeOk,
+    Read,
+    // carries either the counter for a client or, coming from seq-kv, what's left in the pool
+    ReadOk { value: u64 },
+    CasOk,
+    Error { code: usize, text: String },
+    // node -> node: what I currently hold, so reads can count it
+    Allotment { amount: u64 },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Add(u64),
+    Take(u64),
+    Read,
+    // background rebalancing: hand some of our allotment back, or top it up from the pool
+    Return(u64),
+    Refill(u64),
+}
+
+struct Op {
+    client: Option<(String, Option<usize>)>,
+    request: Request,
+    attempts: usize,
+    // how much the cas in flight takes out of the pool, for withdrawals
+    grab: u64,
+}
+
+enum KvCall {
+    Read(usize),
+    Cas(usize),
+}
+
+pub enum InjectedPayload {
+    Rebalance,
+}
+
+/// A counter that can be decremented but never below zero (stock in a warehouse, say), using
+/// escrow. The counter is split between an unallocated pool in seq-kv and an allotment held by
+/// each node. A node answers decrements out of its own allotment without talking to anyone, and
+/// only goes to the pool, with a cas, when its allotment runs short. Since a node never spends
+/// more than it holds and every unit is either in the pool or in exactly one allotment, the
+/// counter can't go negative however many nodes take from it at once.
+///
+/// In the background, nodes hand back allotment they're not using and top up from the pool when
+/// running low, so units drift to where the decrements are. A take is refused when its node's
+/// allotment and the pool together can't cover it, even if another node's allotment could; that
+/// node will hand its excess back soon enough. Increments go straight to the pool. Reads add up
+/// the pool and every node's last reported allotment, so they lag a little behind.
+///
+/// Allotments live in memory only, so a crashed node loses what it held: the counter ends up
+/// lower than it should, never higher.
+pub struct EscrowNode {
+    node: String,
+    id: usize,
+    peers: Vec<String>,
+    // ours to hand out
+    escrow: u64,
+    // taken out of our escrow on its way back to the pool
+    returning: u64,
+    rebalancing: bool,
+    // what the others last told us they hold
+    allotments: HashMap<String, u64>,
+    ops: HashMap<usize, Op>,
+    // outstanding seq-kv requests, by msg_id
+    kv: HashMap<usize, KvCall>,
+}
+
+impl Node<(), Payload, InjectedPayload> for EscrowNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(REBALANCE_INTERVAL, tx, || {
+            Event::Injected(InjectedPayload::Rebalance)
+        });
+        Ok(Self {
+            peers: init
+                .node_ids
+                .into_iter()
+                .filter(|n| *n != init.node_id)
+                .collect(),
+            node: init.node_id,
+            id: 1,
+            escrow: 0,
+            returning: 0,
+            rebalancing: false,
+            allotments: HashMap::new(),
+            ops: HashMap::new(),
+            kv: HashMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let input = match input {
+            Event::EOF => return Ok(()),
+            Event::Injected(InjectedPayload::Rebalance) => return self.rebalance(output),
+            Event::Message(input) => input,
+        };
+        if input.src == SEQ_KV {
+            let Some(call) = input.body.in_reply_to.and_then(|id| self.kv.remove(&id)) else {
+                log::warn!("reply from seq-kv to nothing we asked: {:?}", input.body);
+                return Ok(());
+            };
+            return self.kv_reply(call, input.body.payload, output);
+        }
+        let request = match input.body.payload {
+            Payload::Add { delta } => Request::Add(delta),
+            Payload::Take { amount } if amount <= self.escrow => {
+                // the fast path: no coordination at all
+                self.escrow -= amount;
+                let op_id = self.start_op(Some((input.src, input.body.id)), Request::Take(amount));
+                return self.reply(op_id, Payload::TakeOk, output);
+            }
+            Payload::Take { amount } => Request::Take(amount),
+            Payload::Read => Request::Read,
+            Payload::Allotment { amount } => {
+                self.allotments.insert(input.src, amount);
+                return Ok(());
+            }
+            Payload::AddOk
+            | Payload::TakeOk
+            | Payload::ReadOk { .. }
+            | Payload::CasOk
+            | Payload::Error { .. } => return Ok(()),
+        };
+        let op_id = self.start_op(Some((input.src, input.body.id)), request);
+        self.read(op_id, output)
+    }
+}
+
+impl EscrowNode {
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn start_op(&mut self, client: Option<(String, Option<usize>)>, request: Request) -> usize {
+        let op_id = self.next_id();
+        let op = Op {
+            client,
+            request,
+            attempts: 0,
+            grab: 0,
+        };
+        self.ops.insert(op_id, op);
+        op_id
+    }
+
+    fn rebalance(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let amount = self.escrow + self.returning;
+        for peer in &self.peers {
+            self.send(peer, Payload::Allotment { amount }, output)?;
+        }
+        if self.rebalancing {
+            return Ok(());
+        }
+        let request = if self.escrow > 2 * ALLOTMENT {
+            let surplus = self.escrow - ALLOTMENT;
+            // out of our hands as soon as we decide to give it back
+            self.escrow -= surplus;
+            self.returning += surplus;
+            Request::Return(surplus)
+        } else if self.escrow < ALLOTMENT / 2 {
+            Request::Refill(ALLOTMENT - self.escrow)
+        } else {
+            return Ok(());
+        };
+        self.rebalancing = true;
+        let op_id = self.start_op(None, request);
+        self.read(op_id, output)
+    }
+
+    fn read(&mut self, op_id: usize, output: &mut Output) -> anyhow::Result<()> {
+        let id = self.next_id();
+        self.kv.insert(id, KvCall::Read(op_id));
+        KvRequest::<_, u64>::Read {
+            key: POOL.to_string(),
+        }
+        .send(&self.node, SEQ_KV, id, &mut *output)
+        .context("read escrow pool")
+    }
+
+    fn kv_reply(
+        &mut self,
+        call: KvCall,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match (call, reply) {
+            (KvCall::Read(op_id), Payload::ReadOk { value }) => {
+                self.decide(op_id, Some(value), output)
+            }
+            // nothing has ever been added
+            (KvCall::Read(op_id), Payload::Error { code, .. })
+                if code == error::KEY_DOES_NOT_EXIST =>
+            {
+                self.decide(op_id, None, output)
+            }
+            (KvCall::Cas(op_id), Payload::CasOk) => self.applied(op_id, output),
+            // another node changed the pool since we read it
+            (KvCall::Cas(op_id), Payload::Error { code, .. })
+                if code == error::PRECONDITION_FAILED || code == error::KEY_DOES_NOT_EXIST =>
+            {
+                let Some(op) = self.ops.get_mut(&op_id) else {
+                    return Ok(());
+                };
+                // nothing came out of the pool, and the next read decides afresh what to take
+                op.grab = 0;
+                op.attempts += 1;
+                if op.attempts >= MAX_ATTEMPTS {
+                    let error = Payload::Error {
+                        code: error::TEMPORARILY_UNAVAILABLE,
+                        text: "too much contention on the escrow pool".to_string(),
+                    };
+                    return self.fail(op_id, error, output);
+                }
+                self.read(op_id, output)
+            }
+            (KvCall::Read(op_id) | KvCall::Cas(op_id), Payload::Error { code, text }) => {
+                self.fail(op_id, Payload::Error { code, text }, output)
+            }
+            // whatever seq-kv meant by it, the op can't go on from here
+            (KvCall::Read(op_id) | KvCall::Cas(op_id), reply) => {
+                log::warn!("unexpected reply from seq-kv: {:?}", reply);
+                let error = Payload::Error {
+                    code: error::CRASH,
+                    text: "seq-kv gave an answer we can't make sense of".to_string(),
+                };
+                self.fail(op_id, error, output)
+            }
+        }
+    }
+
+    // `pool` is what's unallocated, or none if nothing has ever been added
+    fn decide(
+        &mut self,
+        op_id: usize,
+        pool: Option<u64>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let available = pool.unwrap_or(0);
+        let to = match self.ops[&op_id].request {
+            Request::Read => {
+                let value = available
+                    + self.escrow
+                    + self.returning
+                    + self.allotments.values().sum::<u64>();
+                return self.reply(op_id, Payload::ReadOk { value }, output);
+            }
+            Request::Add(delta) => available + delta,
+            Request::Return(amount) => available + amount,
+            // another take may have topped us up while we were reading
+            Request::Take(amount) if amount <= self.escrow => {
+                self.escrow -= amount;
+                return self.reply(op_id, Payload::TakeOk, output);
+            }
+            Request::Take(amount) if amount > self.escrow + available => {
+                let error = Payload::Error {
+                    code: error::TEMPORARILY_UNAVAILABLE,
+                    text: format!(
+                        "only {} available here ({} held, {} in the pool)",
+                        self.escrow + available,
+                        self.escrow,
+                        available
+                    ),
+                };
+                return self.reply(op_id, error, output);
+            }
+            Request::Take(amount) => {
+                // take enough for a few more while we're at it
+                let grab = (amount - self.escrow + ALLOTMENT).min(available);
+                self.ops.get_mut(&op_id).expect("just looked").grab = grab;
+                available - grab
+            }
+            Request::Refill(_) if available == 0 => return self.applied(op_id, output),
+            Request::Refill(want) => {
+                let grab = want.min(available);
+                self.ops.get_mut(&op_id).expect("just looked").grab = grab;
+                available - grab
+            }
+        };
+        let id = self.next_id();
+        self.kv.insert(id, KvCall::Cas(op_id));
+        KvRequest::Cas {
+            key: POOL.to_string(),
+            from: available,
+            to,
+            create_if_not_exists: pool.is_none(),
+        }
+        .send(&self.node, SEQ_KV, id, &mut *output)
+        .context("cas escrow pool")
+    }
+
+    // the pool update went through
+    fn applied(&mut self, op_id: usize, output: &mut Output) -> anyhow::Result<()> {
+        let op = &self.ops[&op_id];
+        self.escrow += op.grab;
+        match op.request {
+            Request::Add(_) => self.reply(op_id, Payload::AddOk, output),
+            Request::Take(amount) if amount <= self.escrow => {
+                self.escrow -= amount;
+                self.reply(op_id, Payload::TakeOk, output)
+            }
+            // other takes spent what we grabbed before we got to it, go around again
+            Request::Take(_) => {
+                self.ops.get_mut(&op_id).expect("just looked").grab = 0;
+                self.read(op_id, output)
+            }
+            Request::Return(amount) => {
+                self.returning -= amount;
+                self.rebalancing = false;
+                self.ops.remove(&op_id);
+                Ok(())
+            }
+            Request::Refill(_) => {
+                self.rebalancing = false;
+                self.ops.remove(&op_id);
+                Ok(())
+            }
+            Request::Read => unreachable!("reads don't cas"),
+        }
+    }
+
+    fn fail(&mut self, op_id: usize, error: Payload, output: &mut Output) -> anyhow::Result<()> {
+        let definite = matches!(error, Payload::Error { code, .. } if error::is_definite(code));
+        match self.ops[&op_id].request {
+            Request::Return(amount) => {
+                // if it definitely never reached the pool it's still ours. if we can't tell, it's
+                // safer to lose it than to risk it being counted twice.
+                self.returning -= amount;
+                if definite {
+                    self.escrow += amount;
+                }
+                self.rebalancing = false;
+            }
+            Request::Refill(_) => self.rebalancing = false,
+            Request::Add(_) | Request::Take(_) | Request::Read => {}
+        }
+        self.reply(op_id, error, output)
+    }
+
+    fn reply(&mut self, op_id: usize, reply: Payload, output: &mut Output) -> anyhow::Result<()> {
+        let Some(Op {
+            client: Some((client, msg_id)),
+            ..
+        }) = self.ops.remove(&op_id)
+        else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, EscrowNode, _, _>(())
+}
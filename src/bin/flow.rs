@@ -0,0 +1,73 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::Context;
+use rustengan::{flow, session};
+
+const USAGE: &str = "usage: flow [--sequence] [--window <from ms>..<to ms>] [--chain <node>:<msg_id>] <session log>...";
+
+// draws the messages in the session logs RUSTENGAN_SESSION_LOG had a cluster's nodes write, as
+// a Graphviz graph of who sent what to whom and how much of it was lost:
+//
+//     flow /tmp/session/*.jsonl | dot -Tsvg > flow.svg
+//
+// or, with --sequence, as a Mermaid sequence diagram of each message in the order it went.
+// --window keeps only what went between two points in the run, in ms since its first message,
+// and --chain only what followed from one message, named by its sender and msg_id, as a
+// client's request, its forwarding and the replies do:
+//
+//     flow --sequence --chain c4:12 /tmp/session/*.jsonl
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut sequence = false;
+    let mut window = None;
+    let mut chain = None;
+    while let Some(arg) = args.next_if(|arg| arg.starts_with("--")) {
+        match arg.as_str() {
+            "--sequence" => sequence = true,
+            "--window" => {
+                let range = args.next().context(USAGE)?;
+                let (from, to) = range
+                    .split_once("..")
+                    .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)))
+                    .with_context(|| format!("invalid --window {:?}", range))?;
+                window = Some((Duration::from_millis(from), Duration::from_millis(to)));
+            }
+            "--chain" => {
+                let start = args.next().context(USAGE)?;
+                let (node, msg_id) = start
+                    .split_once(':')
+                    .and_then(|(node, id)| Some((node.to_string(), id.parse::<u64>().ok()?)))
+                    .with_context(|| format!("invalid --chain {:?}", start))?;
+                chain = Some((node, msg_id));
+            }
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    let paths: Vec<String> = args.collect();
+    anyhow::ensure!(!paths.is_empty(), USAGE);
+    let logs = paths
+        .iter()
+        .map(|path| {
+            let node = Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("no node id in {}", path))?;
+            Ok((node.to_string(), session::read(path)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let mut hops = flow::from_sessions(&logs);
+    if let Some((from, to)) = window {
+        hops = flow::window(&hops, from, to);
+    }
+    if let Some((node, msg_id)) = chain {
+        hops = flow::chain(&hops, &node, msg_id);
+        anyhow::ensure!(!hops.is_empty(), "{} never sent msg_id {}", node, msg_id);
+    }
+    match sequence {
+        true => print!("{}", flow::to_sequence(&hops)),
+        false => print!("{}", flow::to_dot(&hops)),
+    }
+    Ok(())
+}
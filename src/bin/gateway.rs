@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use anyhow::Context;
+use rustengan::*;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+// whatever the request, it goes to the cluster as it came
+type Payload = serde_json::Value;
+
+// lines the cluster sends back, to be written out as they are
+type InjectedPayload = String;
+
+/// Stands in for one node of a cluster running over TCP (`RUSTENGAN_TRANSPORT=tcp`) under
+/// Maelstrom: it answers Maelstrom's init itself, and passes everything else on to the cluster
+/// node of the same id, over a connection of its own for every client, the way that client
+/// would connect if it were talking to the cluster directly. Replies come back out on stdout.
+/// So Maelstrom can check a cluster that real clients are using at the same time, with
+/// `RUSTENGAN_NODES` (or the discovery settings) the same as the cluster has it and run with one
+/// gateway for each of its nodes.
+struct GatewayNode {
+    addr: String,
+    // by the client a connection is for
+    connections: HashMap<String, TcpStream>,
+    inject: Sender<Event<Payload, InjectedPayload>>,
+}
+
+impl GatewayNode {
+    fn connection(&mut self, client: &str) -> anyhow::Result<&mut TcpStream> {
+        if !self.connections.contains_key(client) {
+            let resolved = self
+                .addr
+                .to_socket_addrs()
+                .with_context(|| format!("resolve {}", self.addr))?
+                .next()
+                .with_context(|| format!("{} resolves to nothing", self.addr))?;
+            let stream = TcpStream::connect_timeout(&resolved, CONNECT_TIMEOUT)
+                .with_context(|| format!("connect to {}", self.addr))?;
+            stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            stream.set_nodelay(true)?;
+            let replies = stream.try_clone()?;
+            let inject = self.inject.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(replies).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if inject.send(Event::Injected(line)).is_err() {
+                        break;
+                    }
+                }
+            });
+            self.connections.insert(client.to_string(), stream);
+        }
+        Ok(self.connections.get_mut(client).expect("just connected"))
+    }
+}
+
+impl Node<(), Payload, InjectedPayload> for GatewayNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        inject: Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let nodes = match config::var::<String>("RUSTENGAN_NODES")? {
+            Some(nodes) => transport::parse_nodes(&nodes)?,
+            None => transport::discovery::from_env()?.context(
+                "RUSTENGAN_NODES, RUSTENGAN_PEERS_FILE or RUSTENGAN_PEERS_DNS is required",
+            )?,
+        };
+        let addr = nodes
+            .into_iter()
+            .find(|(name, _)| *name == init.node_id)
+            .map(|(_, addr)| addr)
+            .with_context(|| format!("{} isn't one of the cluster's nodes", init.node_id))?;
+        Ok(Self {
+            addr,
+            connections: HashMap::new(),
+            inject,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(reply) => {
+                output.write_all(reply.as_bytes()).context("write reply")?;
+                output.write_all(b"\n").context("write new line")?;
+            }
+            Event::Message(request) => {
+                let line = serde_json::to_vec(&request).context("serialize request")?;
+                let client = request.src.clone();
+                // an unreachable node is a node that doesn't answer, and clients retry
+                let written = self.connection(&client).and_then(|stream| {
+                    stream.write_all(&line)?;
+                    stream.write_all(b"\n")?;
+                    Ok(())
+                });
+                if let Err(e) = written {
+                    log::warn!("forward to {} for {}: {:#}", self.addr, client, e);
+                    self.connections.remove(&client);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, GatewayNode, _, _>(())
+}
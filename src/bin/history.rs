@@ -0,0 +1,45 @@
+use std::path::Path;
+
+use anyhow::Context;
+use rustengan::{history, session};
+
+// turns the session logs RUSTENGAN_SESSION_LOG had a cluster's nodes write into a Jepsen
+// history of the reads, writes and cas operations its clients made, in EDN, for knossos or any
+// other Jepsen checker to check:
+//
+//     history /tmp/session/*.jsonl > history.edn
+//
+// or, with --check, checks it for linearizability itself, and fails if it isn't:
+//
+//     history --check /tmp/session/*.jsonl
+fn main() -> anyhow::Result<()> {
+    let mut paths: Vec<String> = std::env::args().skip(1).collect();
+    let check = paths.first().is_some_and(|arg| arg == "--check");
+    if check {
+        paths.remove(0);
+    }
+    anyhow::ensure!(
+        !paths.is_empty(),
+        "usage: history [--check] <session log>..."
+    );
+    let logs = paths
+        .iter()
+        .map(|path| {
+            let node = Path::new(path)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .with_context(|| format!("no node id in {}", path))?;
+            Ok((node.to_string(), session::read(path)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let history = history::from_sessions(&logs);
+    if check {
+        history::linearizable::check(&history)?;
+        eprintln!("{} ops, linearizable", history.len());
+        return Ok(());
+    }
+    for op in history {
+        println!("{}", op.to_edn());
+    }
+    Ok(())
+}
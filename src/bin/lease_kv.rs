@@ -0,0 +1,888 @@
+use anyhow::{Context, Ok};
+use rand::Rng;
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+const TICK: Duration = Duration::from_millis(50);
+// how long a lease lasts unless RUSTENGAN_LEASE_MS says otherwise
+const DEFAULT_LEASE_MS: u64 = 1000;
+// how far apart any two nodes' clocks can run unless RUSTENGAN_MAX_DRIFT says otherwise: a
+// hundredth, so no clock gains or loses more than 10ms a second on the right time
+const DEFAULT_MAX_DRIFT: f64 = 0.01;
+// a replica or grantor that hasn't answered in this long is asked again
+const RETRY_AFTER: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: usize,
+    },
+    // every answer carries the fencing token of the lease it was given under, which only ever
+    // goes up, so whatever a client does with it can turn away anyone with an older one
+    ReadOk {
+        value: usize,
+        token: u64,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk {
+        token: u64,
+    },
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    CasOk {
+        token: u64,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a candidate, or the leader renewing, asking for the lease under `epoch`. `catch_up` asks
+    // for the grantor's store along with the grant, which a candidate takes over from.
+    Request {
+        epoch: u64,
+        round: u64,
+        catch_up: bool,
+    },
+    Grant {
+        epoch: u64,
+        round: u64,
+        version: Version,
+        // a list rather than a map: integer map keys don't survive serde's internally tagged enums
+        store: Option<Vec<(usize, usize)>>,
+    },
+    // the grantor has promised the lease to someone else, or under an epoch at least as high
+    Refuse {
+        round: u64,
+        promised: u64,
+    },
+    Replicate {
+        version: Version,
+        store: Vec<(usize, usize)>,
+    },
+    Replicated {
+        version: Version,
+    },
+    // the replica has promised a higher epoch than the one it was sent
+    Fenced {
+        promised: u64,
+    },
+}
+
+type Store = BTreeMap<usize, usize>;
+
+// which store is the newest: the epoch of the leader that wrote it, and how many times it had
+// written before
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Version {
+    epoch: u64,
+    seq: u64,
+}
+
+// promises and stores, logged before they're answered: a node that restarted and granted a lower
+// epoch, or forgot a store it acknowledged, would break the majorities it was part of
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Promised {
+        epoch: u64,
+        to: String,
+    },
+    Stored {
+        version: Version,
+        store: Vec<(usize, usize)>,
+    },
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+// what an operation does to the current value of its key
+#[derive(Clone, Copy)]
+enum Change {
+    Write(usize),
+    Cas { from: usize, to: usize },
+}
+
+impl Change {
+    // applies it to `store`, and says what to tell the client once the store's committed
+    fn apply(self, key: usize, store: &mut Store, token: u64) -> Payload {
+        match (self, store.get(&key).copied()) {
+            (Change::Write(value), _) => {
+                store.insert(key, value);
+                Payload::WriteOk { token }
+            }
+            (Change::Cas { from, to }, Some(value)) if value == from => {
+                store.insert(key, to);
+                Payload::CasOk { token }
+            }
+            (Change::Cas { from, .. }, Some(value)) => Payload::Error {
+                code: error::PRECONDITION_FAILED,
+                text: format!("expected {}, had {}", from, value),
+            },
+            (Change::Cas { .. }, None) => Payload::Error {
+                code: error::KEY_DOES_NOT_EXIST,
+                text: format!("key {} does not exist", key),
+            },
+        }
+    }
+}
+
+// a store on its way to a majority, and what to tell the clients whose writes made it once it's
+// there, each under the correlation id its write came with. the batch itself goes out under its
+// first write's.
+struct Batch {
+    version: Version,
+    store: Store,
+    replies: Vec<(String, Option<usize>, Option<String>, Payload)>,
+    stored: HashSet<String>,
+    sent: Instant,
+    correlation_id: Option<String>,
+}
+
+// a client's write, waiting for the batch it'll go in
+struct Queued {
+    client: String,
+    msg_id: Option<usize>,
+    correlation_id: Option<String>,
+    key: usize,
+    change: Change,
+}
+
+// a round of asking for the lease, and who's granted it
+struct Round {
+    round: u64,
+    asked: Instant,
+    granted: HashMap<String, Option<(Version, Store)>>,
+}
+
+struct Leader {
+    epoch: u64,
+    // the lease runs out at this by our own clock
+    until: Instant,
+    renewal: Option<Round>,
+    // what a majority has stored, which reads are answered from. Until the store we took over
+    // is stored on a majority under our own epoch, it's none of our writes and we don't read.
+    committed: Store,
+    committed_version: Version,
+    batch: Option<Batch>,
+    queued: Vec<Queued>,
+}
+
+enum Role {
+    Follower,
+    Candidate { epoch: u64, round: Round },
+    Leader(Box<Leader>),
+}
+
+/// A lin-kv store with a leader that holds a lease from a majority, and answers reads from its
+/// own copy of the store for as long as the lease lasts, without asking anyone.
+///
+/// A node that hasn't promised the lease to anyone for a while asks everyone for it under an
+/// epoch higher than any it's seen. Each grants it unless it's promised it to somebody else
+/// whose lease may not have run out yet, or under an epoch as high, and sends what it's stored
+/// along with the grant. A majority of grants makes the candidate leader: it takes over the
+/// newest store it was sent, which has every write a majority acknowledged, and has a majority
+/// store it under its own epoch before it answers anything. Writes go in batches, each the whole
+/// store, and are acknowledged once a majority has stored them. The leader renews its lease a
+/// few times a lease, from whoever will grant it again.
+///
+/// Leases are timed on monotonic clocks, so a wall clock stepped by NTP changes nothing, but no
+/// two clocks run at quite the same rate. So long as none is more than `RUSTENGAN_MAX_DRIFT` off
+/// the right rate, the leader's lease runs out before any grantor's promise to it does: the
+/// leader counts `lease × (1 − drift)` from when it asked, and a grantor holds out for
+/// `lease × (1 + drift)` from when it was asked, which is later. Nobody else can gather a
+/// majority until the promises run out, so no two leaders ever answer reads at once, and a node
+/// that's restarted doesn't grant anyone anything for a lease, in case of a promise it made and
+/// didn't get to act on.
+///
+/// The epoch is the fencing token: every answer carries it, and a replica turns away stores from
+/// any leader with an epoch below one it's promised or stored under, so a leader that's lost its
+/// lease without noticing, paused say, can't get anything stored on a majority either.
+pub struct LeaseKvNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    wal: Option<Wal<Record>>,
+    lease: Duration,
+    drift: f64,
+    // the highest epoch we've promised, who to, and until when by our own clock
+    epoch: u64,
+    promised_to: Option<String>,
+    promised_until: Instant,
+    // the newest store we've stored
+    version: Version,
+    store: Store,
+    // the highest epoch we've heard of, so ours can go above it
+    highest: u64,
+    next_round: u64,
+    // when we can next try for the lease: a while after our last promise runs out, a different
+    // while on every node, so they don't all try at once
+    campaign_after: Instant,
+    role: Role,
+}
+
+impl Node<(), Payload, InjectedPayload> for LeaseKvNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let lease = Duration::from_millis(config::var_or("RUSTENGAN_LEASE_MS", DEFAULT_LEASE_MS)?);
+        let drift = config::var_or("RUSTENGAN_MAX_DRIFT", DEFAULT_MAX_DRIFT)?;
+        anyhow::ensure!(
+            (0.0..1.0).contains(&drift),
+            "RUSTENGAN_MAX_DRIFT must be at least 0 and below 1, not {}",
+            drift
+        );
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        let mut node = Self {
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            wal: None,
+            lease,
+            drift,
+            epoch: 0,
+            promised_to: None,
+            // whatever we promised before a restart may still hold
+            promised_until: clock::now() + lease.mul_f64(1.0 + drift),
+            version: Version::default(),
+            store: Store::new(),
+            highest: 0,
+            next_round: 1,
+            campaign_after: clock::now(),
+            role: Role::Follower,
+        };
+        node.campaign_after = node.promised_until + node.jitter();
+        let (wal, records) = Wal::open(wal::data_dir().join(format!("{}.lease.wal", node.node)))
+            .context("open lease wal")?;
+        for record in records {
+            node.apply(record);
+        }
+        node.wal = Some(wal);
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => self.tick(output)?,
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    Payload::Read { key } => self.read(src, client_msg_id, key, output)?,
+                    Payload::Write { key, value } => {
+                        let change = Change::Write(value);
+                        self.write(src, client_msg_id, key, change, output)?;
+                    }
+                    Payload::Cas { key, from, to } => {
+                        let change = Change::Cas { from, to };
+                        self.write(src, client_msg_id, key, change, output)?;
+                    }
+                    Payload::Request {
+                        epoch,
+                        round,
+                        catch_up,
+                    } => {
+                        let reply = self.on_request(&src, epoch, round, catch_up)?;
+                        self.send(&src, reply, output)?;
+                    }
+                    Payload::Replicate { version, store } => {
+                        let reply = self.on_replicate(version, store.into_iter().collect())?;
+                        self.send(&src, reply, output)?;
+                    }
+                    Payload::Grant {
+                        epoch,
+                        round,
+                        version,
+                        store,
+                    } => self.granted(src, epoch, round, version, store, output)?,
+                    Payload::Refuse { promised, .. } | Payload::Fenced { promised } => {
+                        self.refused(promised)
+                    }
+                    Payload::Replicated { version } => self.replicated(src, version, output)?,
+                    Payload::ReadOk { .. }
+                    | Payload::WriteOk { .. }
+                    | Payload::CasOk { .. }
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        let role = match self.role {
+            Role::Follower => "follower",
+            Role::Candidate { .. } => "candidate",
+            Role::Leader(_) => "leader",
+        };
+        serde_json::json!({
+            "role": role,
+            "leader": self.promised_to,
+            "epoch": self.fence(),
+            "keys": self.store.len(),
+        })
+    }
+
+    fn invariants(&self) -> anyhow::Result<()> {
+        if let Role::Leader(leader) = &self.role {
+            anyhow::ensure!(
+                leader.committed_version <= self.version,
+                "committed {:?} but only stored {:?}",
+                leader.committed_version,
+                self.version
+            );
+        }
+        Ok(())
+    }
+
+    // the last promise and the newest store are all a restart needs
+    fn snapshot(&mut self) -> anyhow::Result<()> {
+        let mut records = vec![Record::Stored {
+            version: self.version,
+            store: self.store.iter().map(|(k, v)| (*k, *v)).collect(),
+        }];
+        if let Some(to) = &self.promised_to {
+            records.push(Record::Promised {
+                epoch: self.epoch,
+                to: to.clone(),
+            });
+        }
+        self.wal
+            .as_mut()
+            .expect("wal is open")
+            .rewrite(&records)
+            .context("rewrite lease wal")
+    }
+}
+
+impl LeaseKvNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        client: &str,
+        msg_id: Option<usize>,
+        payload: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client.to_string(),
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn majority(&self) -> usize {
+        self.nodes.len() / 2 + 1
+    }
+
+    fn log(&mut self, record: Record) -> anyhow::Result<()> {
+        self.wal
+            .as_mut()
+            .expect("wal is open")
+            .append(&record)
+            .context("append to lease wal")?;
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Promised { epoch, to } => {
+                self.epoch = epoch;
+                self.promised_to = Some(to);
+            }
+            Record::Stored { version, store } => {
+                self.version = version;
+                self.store = store.into_iter().collect();
+            }
+        }
+        self.highest = self.highest.max(self.fence());
+    }
+
+    // the lowest epoch we'll take anything from: the one we've promised, or a higher one we've
+    // stored under, which is as good as a promise not to take any lower
+    fn fence(&self) -> u64 {
+        self.epoch.max(self.version.epoch)
+    }
+
+    fn not_leader(&self) -> Payload {
+        let text = match &self.promised_to {
+            Some(leader) if *leader != self.node => format!("not the leader, try {}", leader),
+            _ => "not the leader".to_string(),
+        };
+        Payload::Error {
+            code: error::TEMPORARILY_UNAVAILABLE,
+            text,
+        }
+    }
+
+    fn tick(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        match &mut self.role {
+            Role::Follower => {
+                let now = clock::now();
+                if now >= self.promised_until && now >= self.campaign_after {
+                    self.campaign(output)?;
+                }
+            }
+            Role::Candidate { round, .. } => {
+                if clock::since(round.asked) > RETRY_AFTER {
+                    self.lost();
+                }
+            }
+            Role::Leader(leader) => {
+                if clock::now() >= leader.until {
+                    log::info!("lease ran out, stepping down");
+                    self.role = Role::Follower;
+                    return Ok(());
+                }
+                let renewing = leader
+                    .renewal
+                    .as_ref()
+                    .is_some_and(|round| clock::since(round.asked) <= RETRY_AFTER);
+                // a few times a lease, so a lost grant or two doesn't cost it
+                let due = leader.until - clock::now() < self.lease.mul_f64(0.75);
+                let stale = leader
+                    .batch
+                    .as_ref()
+                    .is_some_and(|batch| clock::since(batch.sent) > RETRY_AFTER);
+                if due && !renewing {
+                    self.renew(output)?;
+                }
+                if stale {
+                    self.replicate(output)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // gave up on the lease for now, and tries again after a while, so two candidates don't keep
+    // splitting the vote
+    fn lost(&mut self) {
+        self.role = Role::Follower;
+        // we never led under the epoch we promised ourselves, and now never will
+        if self.promised_to.as_deref() == Some(&self.node) {
+            self.promised_until = clock::now();
+        }
+        self.campaign_after = clock::now() + self.jitter();
+    }
+
+    fn jitter(&self) -> Duration {
+        rng::thread().gen_range(Duration::ZERO..self.lease / 2)
+    }
+
+    fn campaign(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        self.highest += 1;
+        let epoch = self.highest;
+        let round = self.new_round();
+        log::debug!("asking for the lease under epoch {}", epoch);
+        self.role = Role::Candidate { epoch, round };
+        self.ask(epoch, true, output)
+    }
+
+    fn renew(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let round = self.new_round();
+        let Role::Leader(leader) = &mut self.role else {
+            return Ok(());
+        };
+        leader.renewal = Some(round);
+        let epoch = leader.epoch;
+        self.ask(epoch, false, output)
+    }
+
+    fn new_round(&mut self) -> Round {
+        let round = self.next_round;
+        self.next_round += 1;
+        Round {
+            round,
+            asked: clock::now(),
+            granted: HashMap::new(),
+        }
+    }
+
+    // asks everyone for the lease for the round just started, ourselves included
+    fn ask(&mut self, epoch: u64, catch_up: bool, output: &mut Output) -> anyhow::Result<()> {
+        let round = match &self.role {
+            Role::Candidate { round, .. } => round.round,
+            Role::Leader(leader) => match &leader.renewal {
+                Some(round) => round.round,
+                None => return Ok(()),
+            },
+            Role::Follower => return Ok(()),
+        };
+        for n in self.nodes.clone() {
+            if n != self.node {
+                let request = Payload::Request {
+                    epoch,
+                    round,
+                    catch_up,
+                };
+                self.send(&n, request, output)?;
+                continue;
+            }
+            match self.on_request(&n, epoch, round, catch_up)? {
+                Payload::Grant {
+                    epoch,
+                    round,
+                    version,
+                    store,
+                } => self.granted(n, epoch, round, version, store, output)?,
+                Payload::Refuse { promised, .. } => self.refused(promised),
+                _ => unreachable!("grantors only grant or refuse"),
+            }
+        }
+        Ok(())
+    }
+
+    fn on_request(
+        &mut self,
+        src: &str,
+        epoch: u64,
+        round: u64,
+        catch_up: bool,
+    ) -> anyhow::Result<Payload> {
+        self.highest = self.highest.max(epoch);
+        let live = clock::now() < self.promised_until;
+        let theirs = self.promised_to.as_deref() == Some(src);
+        let fence = self.fence();
+        if !(theirs && epoch >= fence || !live && epoch > fence) {
+            return Ok(Payload::Refuse {
+                round,
+                promised: fence,
+            });
+        }
+        if epoch != self.epoch || !theirs {
+            self.log(Record::Promised {
+                epoch,
+                to: src.to_string(),
+            })?;
+            self.superseded();
+        }
+        self.promised_until = clock::now() + self.lease.mul_f64(1.0 + self.drift);
+        self.campaign_after = self.promised_until + self.jitter();
+        Ok(Payload::Grant {
+            epoch,
+            round,
+            version: self.version,
+            store: catch_up.then(|| self.store.iter().map(|(k, v)| (*k, *v)).collect()),
+        })
+    }
+
+    fn on_replicate(&mut self, version: Version, store: Store) -> anyhow::Result<Payload> {
+        if version.epoch < self.fence() {
+            return Ok(Payload::Fenced {
+                promised: self.fence(),
+            });
+        }
+        // a newer store has everything an older one from the same leader had
+        if version > self.version {
+            let store = store.into_iter().collect();
+            self.log(Record::Stored { version, store })?;
+            self.superseded();
+        }
+        Ok(Payload::Replicated { version })
+    }
+
+    // stops trying for the lease, or leading, under an epoch below one we've promised since
+    fn superseded(&mut self) {
+        let ours = match &self.role {
+            Role::Follower => return,
+            Role::Candidate { epoch, .. } => *epoch,
+            Role::Leader(leader) => leader.epoch,
+        };
+        if ours < self.fence() {
+            log::info!("superseded by epoch {}", self.fence());
+            self.role = Role::Follower;
+        }
+    }
+
+    fn granted(
+        &mut self,
+        grantor: String,
+        epoch: u64,
+        round: u64,
+        version: Version,
+        store: Option<Vec<(usize, usize)>>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let majority = self.majority();
+        let (current, asked, granted) = match &mut self.role {
+            Role::Candidate {
+                epoch: ours,
+                round: r,
+            } if *ours == epoch && r.round == round => {
+                r.granted.insert(
+                    grantor,
+                    store.map(|store| (version, store.into_iter().collect())),
+                );
+                (*ours, r.asked, r.granted.len())
+            }
+            Role::Leader(leader) if leader.epoch == epoch => {
+                let Some(r) = leader.renewal.as_mut().filter(|r| r.round == round) else {
+                    return Ok(());
+                };
+                r.granted.insert(grantor, None);
+                (epoch, r.asked, r.granted.len())
+            }
+            _ => return Ok(()),
+        };
+        if granted < majority {
+            return Ok(());
+        }
+        let until = asked + self.lease.mul_f64(1.0 - self.drift);
+        match std::mem::replace(&mut self.role, Role::Follower) {
+            Role::Candidate { round, .. } => {
+                // the newest store any of a majority has, which has every write a majority did
+                let (newest, store) = round
+                    .granted
+                    .into_values()
+                    .flatten()
+                    .max_by_key(|(version, _)| *version)
+                    .expect("a majority granted it");
+                log::info!(
+                    "leading under epoch {}, from {:?} of the last leader's writes",
+                    current,
+                    newest
+                );
+                self.role = Role::Leader(Box::new(Leader {
+                    epoch: current,
+                    until,
+                    renewal: None,
+                    committed: Store::new(),
+                    committed_version: Version::default(),
+                    batch: Some(Batch {
+                        version: Version {
+                            epoch: current,
+                            seq: 0,
+                        },
+                        store,
+                        replies: Vec::new(),
+                        stored: HashSet::new(),
+                        sent: clock::now(),
+                        correlation_id: None,
+                    }),
+                    queued: Vec::new(),
+                }));
+                self.replicate(output)
+            }
+            Role::Leader(mut leader) => {
+                leader.until = leader.until.max(until);
+                leader.renewal = None;
+                self.role = Role::Leader(leader);
+                Ok(())
+            }
+            Role::Follower => unreachable!("only candidates and leaders are granted"),
+        }
+    }
+
+    // someone's promised an epoch at least `promised`
+    fn refused(&mut self, promised: u64) {
+        self.highest = self.highest.max(promised);
+        if let Role::Leader(leader) = &self.role {
+            if promised > leader.epoch {
+                log::info!("fenced off by epoch {}, stepping down", promised);
+                self.role = Role::Follower;
+            }
+        }
+    }
+
+    fn read(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        key: usize,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let reply = match &self.role {
+            Role::Leader(leader)
+                if clock::now() < leader.until
+                    && leader.committed_version.epoch == leader.epoch =>
+            {
+                match leader.committed.get(&key) {
+                    Some(&value) => Payload::ReadOk {
+                        value,
+                        token: leader.epoch,
+                    },
+                    None => Payload::Error {
+                        code: error::KEY_DOES_NOT_EXIST,
+                        text: format!("key {} does not exist", key),
+                    },
+                }
+            }
+            _ => self.not_leader(),
+        };
+        self.reply_client(&client, msg_id, reply, output)
+    }
+
+    fn write(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        key: usize,
+        change: Change,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Role::Leader(leader) = &mut self.role else {
+            let reply = self.not_leader();
+            return self.reply_client(&client, msg_id, reply, output);
+        };
+        leader.queued.push(Queued {
+            client,
+            msg_id,
+            correlation_id: correlation::current(),
+            key,
+            change,
+        });
+        self.flush(output)
+    }
+
+    // puts everything queued into the next batch, unless one's still on its way
+    fn flush(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Role::Leader(leader) = &mut self.role else {
+            return Ok(());
+        };
+        if leader.batch.is_some() || leader.queued.is_empty() {
+            return Ok(());
+        }
+        let epoch = leader.epoch;
+        let mut store = leader.committed.clone();
+        let replies: Vec<_> = leader
+            .queued
+            .drain(..)
+            .map(|queued| {
+                let reply = queued.change.apply(queued.key, &mut store, epoch);
+                (queued.client, queued.msg_id, queued.correlation_id, reply)
+            })
+            .collect();
+        let correlation_id = replies.first().and_then(|(_, _, id, _)| id.clone());
+        leader.batch = Some(Batch {
+            version: Version {
+                epoch,
+                seq: leader.committed_version.seq + 1,
+            },
+            store,
+            replies,
+            stored: HashSet::new(),
+            sent: clock::now(),
+            correlation_id,
+        });
+        self.replicate(output)
+    }
+
+    // sends the batch to every replica that hasn't stored it, ourselves included
+    fn replicate(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Role::Leader(leader) = &mut self.role else {
+            return Ok(());
+        };
+        let Some(batch) = &mut leader.batch else {
+            return Ok(());
+        };
+        batch.sent = clock::now();
+        let (version, store) = (batch.version, batch.store.clone());
+        let correlation_id = batch.correlation_id.clone();
+        let waiting: Vec<String> = self
+            .nodes
+            .iter()
+            .filter(|n| !batch.stored.contains(*n))
+            .cloned()
+            .collect();
+        for n in waiting {
+            if n != self.node {
+                let replicate = Payload::Replicate {
+                    version,
+                    store: store.iter().map(|(k, v)| (*k, *v)).collect(),
+                };
+                correlation::stepping(correlation_id.clone(), || self.send(&n, replicate, output))?;
+                continue;
+            }
+            match self.on_replicate(version, store.clone())? {
+                Payload::Replicated { version } => self.replicated(n, version, output)?,
+                Payload::Fenced { promised } => self.refused(promised),
+                _ => unreachable!("replicas only store or fence"),
+            }
+        }
+        Ok(())
+    }
+
+    fn replicated(
+        &mut self,
+        replica: String,
+        version: Version,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let majority = self.majority();
+        let Role::Leader(leader) = &mut self.role else {
+            return Ok(());
+        };
+        let Some(batch) = leader.batch.as_mut().filter(|b| b.version == version) else {
+            return Ok(());
+        };
+        batch.stored.insert(replica);
+        if batch.stored.len() < majority {
+            return Ok(());
+        }
+        let Some(batch) = leader.batch.take() else {
+            return Ok(());
+        };
+        leader.committed = batch.store;
+        leader.committed_version = batch.version;
+        for (client, msg_id, correlation_id, reply) in batch.replies {
+            correlation::stepping(correlation_id, || {
+                self.reply_client(&client, msg_id, reply, output)
+            })?;
+        }
+        self.flush(output)
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, LeaseKvNode, _, _>(())
+}
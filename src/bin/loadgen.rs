@@ -0,0 +1,419 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::Message;
+use serde_json::{json, Value};
+
+const USAGE: &str = "usage: loadgen [--rate <per second,...>] [--for <seconds>] [--mix <op=weight,...>] [--keys <n>] [--timeout <ms>] [--latencies <csv>] (--tcp <addr> | -- <node binary> [args...])";
+
+// the requests loadgen knows how to make
+const OPS: &[&str] = &["echo", "generate", "broadcast", "read", "write", "cas"];
+
+// what came back for a request, as the thread reading the node's output saw it
+struct Reply {
+    in_reply_to: usize,
+    at: Instant,
+    // the error's code, if it was one
+    error: Option<u64>,
+}
+
+// one request on its way, or answered
+struct Request {
+    op: &'static str,
+    sent: Instant,
+}
+
+// how one rate's phase went
+#[derive(Default)]
+struct Phase {
+    sent: usize,
+    // by op, how long each request that was answered without an error took
+    ok: BTreeMap<&'static str, Vec<Duration>>,
+    // by op and error code, how many requests were answered with an error
+    errors: BTreeMap<(&'static str, u64), usize>,
+    timed_out: usize,
+    // from the first request being sent to the last answer that came in before the timeout
+    took: Duration,
+}
+
+// the node, however it's reached: its stdin, and the lines of its stdout, or a tcp connection
+// to it, both ways
+struct Target {
+    input: Box<dyn Write + Send>,
+    child: Option<Child>,
+    next_id: usize,
+}
+
+// the requests to make, and how often each comes up
+struct Mix {
+    ops: Vec<(&'static str, u32)>,
+    keys: u64,
+    rng: StdRng,
+}
+
+// sends a node requests at the rates and mix given, one rate after another, straight down its
+// stdin or over tcp, and reports how many it answered, how fast, and what errors it gave:
+//
+//     loadgen --rate 1000,5000,20000 --mix read=8,write=1,cas=1 -- target/release/lww_kv
+//     loadgen --rate 500 --mix broadcast=1,read=1 --tcp 127.0.0.1:7000
+//
+// a node started by loadgen is sent Maelstrom's init, as n0 of a cluster of one, and one
+// reached over tcp is taken to have made up its own. Requests go out at a steady rate, whether
+// or not earlier ones have been answered, for --for seconds (5 unless it says otherwise) at
+// each rate, so a rate the node can't keep up with shows as latencies that grow and requests
+// that time out, --timeout ms (1000) after the last one went. The mix is ops and how often each
+// comes up, out of echo, generate, broadcast, read, write and cas, over --keys keys (5); it's
+// echo unless it says otherwise. --latencies writes every request's latency as csv.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut rates = vec![100];
+    let mut load_for = Duration::from_secs(5);
+    let mut mix = Mix {
+        ops: vec![("echo", 1)],
+        keys: 5,
+        rng: StdRng::from_entropy(),
+    };
+    let mut timeout = Duration::from_millis(1000);
+    let mut latencies = None;
+    let mut tcp = None;
+    let mut command = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().context(USAGE);
+        match arg.as_str() {
+            "--rate" => {
+                rates = value()?
+                    .split(',')
+                    .map(number)
+                    .collect::<anyhow::Result<_>>()?
+            }
+            "--for" => load_for = Duration::from_secs(number(&value()?)?),
+            "--mix" => mix.ops = parse_mix(&value()?)?,
+            "--keys" => mix.keys = number(&value()?)?,
+            "--timeout" => timeout = Duration::from_millis(number(&value()?)?),
+            "--latencies" => latencies = Some(value()?),
+            "--tcp" => tcp = Some(value()?),
+            "--" => command = Some(args.by_ref().collect::<Vec<_>>()),
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    anyhow::ensure!(rates.iter().all(|&rate| rate > 0) && mix.keys > 0, USAGE);
+    let (mut target, replies) = match (tcp, command) {
+        (Some(addr), None) => connect(&addr)?,
+        (None, Some(command)) if !command.is_empty() => spawn(&command)?,
+        _ => anyhow::bail!(USAGE),
+    };
+    let mut latencies = latencies
+        .map(|path| {
+            let mut file =
+                std::fs::File::create(&path).with_context(|| format!("create {}", path))?;
+            writeln!(file, "rate,op,sent_us,latency_us,outcome")?;
+            anyhow::Ok((path, file))
+        })
+        .transpose()?;
+
+    println!(
+        "{:>8} {:>8} {:>10} {:<10} {:>6} {:>9} {:>9} {:>9} {:>9}  (ms)",
+        "rate", "sent", "ok/s", "op", "ok", "p50", "p90", "p99", "max"
+    );
+    for rate in rates {
+        let phase = phase(
+            &mut target,
+            &replies,
+            &mut mix,
+            rate,
+            load_for,
+            timeout,
+            latencies.as_mut().map(|(_, file)| file),
+        )?;
+        report(rate, &phase);
+    }
+    if let Some((path, mut file)) = latencies {
+        file.flush().with_context(|| format!("write {}", path))?;
+    }
+    if let Some(mut child) = target.child.take() {
+        // a node stops once its stdin closes, but one that doesn't gets a moment to, and is
+        // killed after that
+        drop(target.input);
+        let give_up = Instant::now() + Duration::from_secs(1);
+        while child.try_wait().context("wait for the node")?.is_none() {
+            if Instant::now() >= give_up {
+                child.kill().context("kill the node")?;
+                child.wait().context("wait for the node")?;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    Ok(())
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> anyhow::Result<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("{:?} isn't a number", value))
+}
+
+fn parse_mix(mix: &str) -> anyhow::Result<Vec<(&'static str, u32)>> {
+    let mix = mix
+        .split(',')
+        .map(|part| {
+            let (op, weight) = part.split_once('=').unwrap_or((part, "1"));
+            let op = OPS
+                .iter()
+                .find(|known| **known == op.trim())
+                .with_context(|| format!("no op called {:?}: one of {}", op, OPS.join(", ")))?;
+            Ok((*op, number(weight)?))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    anyhow::ensure!(
+        mix.iter().any(|(_, weight)| *weight > 0),
+        "nothing in the mix"
+    );
+    Ok(mix)
+}
+
+impl Mix {
+    // the body of the next request, picked from the mix, and its op
+    fn next(&mut self, msg_id: usize) -> (&'static str, Value) {
+        let rng = &mut self.rng;
+        let total: u32 = self.ops.iter().map(|(_, weight)| weight).sum();
+        let mut pick = rng.gen_range(0..total);
+        let op = self
+            .ops
+            .iter()
+            .find(|(_, weight)| {
+                let here = pick < *weight;
+                pick = pick.saturating_sub(*weight);
+                here
+            })
+            .map(|(op, _)| *op)
+            .expect("a weight for every pick");
+        let key = rng.gen_range(0..self.keys);
+        let body = match op {
+            "echo" => json!({ "type": "echo", "echo": format!("loadgen {}", msg_id) }),
+            "generate" => json!({ "type": "generate" }),
+            "broadcast" => json!({ "type": "broadcast", "message": msg_id }),
+            "read" => json!({ "type": "read", "key": key }),
+            "write" => json!({ "type": "write", "key": key, "value": rng.gen_range(0..5) }),
+            "cas" => json!({
+                "type": "cas",
+                "key": key,
+                "from": rng.gen_range(0..5),
+                "to": rng.gen_range(0..5),
+            }),
+            _ => unreachable!("only known ops are in the mix"),
+        };
+        (op, body)
+    }
+}
+
+fn spawn(command: &[String]) -> anyhow::Result<(Target, Receiver<Reply>)> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .with_context(|| format!("start {}", command[0]))?;
+    let stdout = child.stdout.take().expect("piped");
+    let replies = read_replies(BufReader::new(stdout));
+    let mut target = Target {
+        input: Box::new(child.stdin.take().expect("piped")),
+        child: Some(child),
+        next_id: 1,
+    };
+    let init = json!({
+        "src": "c0",
+        "dest": "n0",
+        "body": { "type": "init", "msg_id": 0, "node_id": "n0", "node_ids": ["n0"] },
+    });
+    writeln!(target.input, "{}", init).context("send the init")?;
+    target.input.flush().context("send the init")?;
+    match replies.recv_timeout(Duration::from_secs(5)) {
+        Ok(reply) if reply.in_reply_to == 0 && reply.error.is_none() => Ok((target, replies)),
+        Ok(_) => anyhow::bail!("the node didn't answer its init with init_ok"),
+        Err(_) => anyhow::bail!("the node didn't answer its init"),
+    }
+}
+
+fn connect(addr: &str) -> anyhow::Result<(Target, Receiver<Reply>)> {
+    let stream = TcpStream::connect(addr).with_context(|| format!("connect to {}", addr))?;
+    stream.set_nodelay(true)?;
+    let replies = read_replies(BufReader::new(stream.try_clone()?));
+    let target = Target {
+        input: Box::new(stream),
+        child: None,
+        next_id: 1,
+    };
+    Ok((target, replies))
+}
+
+// every reply the node sends loadgen's client, as it comes in; messages to anyone else, like
+// gossip to peers it doesn't have, are dropped
+fn read_replies(lines: impl BufRead + Send + 'static) -> Receiver<Reply> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in lines.lines() {
+            let Ok(line) = line else { break };
+            let at = Instant::now();
+            let Ok(message) = serde_json::from_str::<Message<Value>>(&line) else {
+                continue;
+            };
+            let body = &message.body;
+            let Some(in_reply_to) = body.in_reply_to else {
+                continue;
+            };
+            if !message.dst.starts_with('c') {
+                continue;
+            }
+            let error = match body.payload["type"].as_str() {
+                Some("error") => Some(body.payload["code"].as_u64().unwrap_or(u64::MAX)),
+                _ => None,
+            };
+            let reply = Reply {
+                in_reply_to,
+                at,
+                error,
+            };
+            if tx.send(reply).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+// sends requests at `rate` for `load_for`, and waits up to `timeout` for the last answers
+fn phase(
+    target: &mut Target,
+    replies: &Receiver<Reply>,
+    mix: &mut Mix,
+    rate: u64,
+    load_for: Duration,
+    timeout: Duration,
+    mut latencies: Option<&mut std::fs::File>,
+) -> anyhow::Result<Phase> {
+    let mut phase = Phase::default();
+    let mut waiting: HashMap<usize, Request> = HashMap::new();
+    let gap = Duration::from_secs(1) / rate as u32;
+    let start = Instant::now();
+    let mut last_answer = start;
+    let mut next_send = start;
+    let stop_sending = start + load_for;
+    let mut answered = |reply: Reply,
+                        waiting: &mut HashMap<usize, Request>,
+                        phase: &mut Phase|
+     -> anyhow::Result<()> {
+        let Some(request) = waiting.remove(&reply.in_reply_to) else {
+            return Ok(());
+        };
+        let took = reply.at.saturating_duration_since(request.sent);
+        last_answer = last_answer.max(reply.at);
+        match reply.error {
+            None => phase.ok.entry(request.op).or_default().push(took),
+            Some(code) => *phase.errors.entry((request.op, code)).or_default() += 1,
+        }
+        if let Some(file) = latencies.as_deref_mut() {
+            let outcome = match reply.error {
+                None => "ok".to_string(),
+                Some(code) => format!("error {}", code),
+            };
+            writeln!(
+                file,
+                "{},{},{},{},{}",
+                rate,
+                request.op,
+                request.sent.duration_since(start).as_micros(),
+                took.as_micros(),
+                outcome
+            )?;
+        }
+        Ok(())
+    };
+    while next_send < stop_sending {
+        let now = Instant::now();
+        if now >= next_send {
+            let msg_id = target.next_id;
+            target.next_id += 1;
+            let (op, mut body) = mix.next(msg_id);
+            body["msg_id"] = json!(msg_id);
+            let message = json!({ "src": "c1", "dest": "n0", "body": body });
+            writeln!(target.input, "{}", message).context("send a request")?;
+            target.input.flush().context("send a request")?;
+            waiting.insert(msg_id, Request { op, sent: now });
+            phase.sent += 1;
+            next_send += gap;
+            continue;
+        }
+        match replies.recv_timeout(next_send - now) {
+            Ok(reply) => answered(reply, &mut waiting, &mut phase)?,
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("the node went away"),
+        }
+    }
+    let give_up = Instant::now() + timeout;
+    while !waiting.is_empty() {
+        let now = Instant::now();
+        if now >= give_up {
+            break;
+        }
+        match replies.recv_timeout(give_up - now) {
+            Ok(reply) => answered(reply, &mut waiting, &mut phase)?,
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => anyhow::bail!("the node went away"),
+        }
+    }
+    phase.timed_out = waiting.len();
+    phase.took = last_answer.duration_since(start);
+    Ok(phase)
+}
+
+fn report(rate: u64, phase: &Phase) {
+    let ok: usize = phase.ok.values().map(Vec::len).sum();
+    let per_second = match phase.took.as_secs_f64() {
+        0.0 => 0.0,
+        took => ok as f64 / took,
+    };
+    let first = format!("{:>8} {:>8} {:>10.0}", rate, phase.sent, per_second);
+    let mut lines = Vec::new();
+    for (op, took) in &phase.ok {
+        let mut took = took.clone();
+        took.sort();
+        let at = |q: f64| {
+            let rank = ((took.len() as f64 * q).ceil() as usize).clamp(1, took.len());
+            took[rank - 1].as_secs_f64() * 1000.0
+        };
+        lines.push(format!(
+            "{:<10} {:>6} {:>9.2} {:>9.2} {:>9.2} {:>9.2}",
+            op,
+            took.len(),
+            at(0.5),
+            at(0.9),
+            at(0.99),
+            at(1.0)
+        ));
+    }
+    if lines.is_empty() {
+        lines.push("nothing answered".to_string());
+    }
+    for (i, line) in lines.iter().enumerate() {
+        match i {
+            0 => println!("{} {}", first, line),
+            _ => println!("{:28} {}", "", line),
+        }
+    }
+    for ((op, code), n) in &phase.errors {
+        println!("{:28} {} {} answered with error {}", "", n, op, code);
+    }
+    if phase.timed_out > 0 {
+        println!("{:28} {} timed out", "", phase.timed_out);
+    }
+}
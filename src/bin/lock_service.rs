@@ -0,0 +1,348 @@
+use anyhow::{Context, Ok};
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// how long a lease lasts when the client doesn't say
+const DEFAULT_TTL_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Acquire {
+        lock: String,
+        #[serde(default)]
+        ttl_ms: Option<u64>,
+    },
+    // token increases with every grant, of any lock, ever. whatever the lock protects should
+    // reject requests carrying a smaller token than one it has already seen, which shuts out a
+    // holder whose lease expired without it noticing (a long gc pause, say).
+    AcquireOk {
+        token: u64,
+        ttl_ms: u64,
+    },
+    Renew {
+        lock: String,
+        token: u64,
+        #[serde(default)]
+        ttl_ms: Option<u64>,
+    },
+    RenewOk {
+        ttl_ms: u64,
+    },
+    Release {
+        lock: String,
+        token: u64,
+    },
+    ReleaseOk,
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a client request on its way to the lock server
+    Forward {
+        origin: String,
+        req_id: usize,
+        holder: String,
+        request: Box<Payload>,
+    },
+    // the reply to a forwarded request, on its way back to the node the client talked to
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+}
+
+// every change to the lock table, logged before it's acknowledged so a restarted server neither
+// forgets a lease it granted nor hands out a token twice
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Granted {
+        lock: String,
+        holder: String,
+        token: u64,
+        expires_ms: u64,
+    },
+    Renewed {
+        lock: String,
+        token: u64,
+        expires_ms: u64,
+    },
+    Released {
+        lock: String,
+        token: u64,
+    },
+}
+
+struct Lease {
+    holder: String,
+    token: u64,
+    expires_ms: u64,
+}
+
+/// A lock service with a single server, the first node, which keeps the lock table in memory and
+/// persists it through a write-ahead log. Every other node just passes requests through to it.
+/// Leases expire on their own, so a crashed client doesn't hold its locks forever.
+pub struct LockNode {
+    node: String,
+    id: usize,
+    server: String,
+    // only on the server
+    wal: Option<Wal<Record>>,
+    locks: HashMap<String, Lease>,
+    next_token: u64,
+    // requests from our own clients we've passed on to the server
+    pending: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl Node<(), Payload> for LockNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        _tx: std::sync::mpsc::Sender<Event<Payload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let server = init.node_ids.first().expect("at least one node").clone();
+        let mut node = Self {
+            id: 1,
+            wal: None,
+            locks: HashMap::new(),
+            next_token: 1,
+            pending: HashMap::new(),
+            node: init.node_id,
+            server,
+        };
+        if node.node == node.server {
+            let (wal, records) =
+                Wal::open(wal::data_dir().join(format!("{}.locks.wal", node.node)))
+                    .context("open lock wal")?;
+            for record in records {
+                node.apply(record);
+            }
+            node.wal = Some(wal);
+        }
+        Ok(node)
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let src = input.src.clone();
+        let client_msg_id = input.body.id;
+        match input.body.payload {
+            request @ (Payload::Acquire { .. }
+            | Payload::Renew { .. }
+            | Payload::Release { .. }) => {
+                let req_id = self.id;
+                self.id += 1;
+                self.pending.insert(req_id, (src.clone(), client_msg_id));
+                if self.node == self.server {
+                    let reply = self.handle(&src, request)?;
+                    self.reply_client(req_id, reply, output)?;
+                } else {
+                    let forward = Payload::Forward {
+                        origin: self.node.clone(),
+                        req_id,
+                        holder: src,
+                        request: Box::new(request),
+                    };
+                    let server = self.server.clone();
+                    self.send(&server, forward, output)?;
+                }
+            }
+            Payload::Forward {
+                origin,
+                req_id,
+                holder,
+                request,
+            } => {
+                let reply = self.handle(&holder, *request)?;
+                let done = Payload::Done {
+                    req_id,
+                    reply: Box::new(reply),
+                };
+                self.send(&origin, done, output)?;
+            }
+            Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+            Payload::AcquireOk { .. }
+            | Payload::RenewOk { .. }
+            | Payload::ReleaseOk
+            | Payload::Error { .. } => {}
+        }
+        Ok(())
+    }
+}
+
+impl LockNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    // only ever runs on the server
+    fn handle(&mut self, holder: &str, request: Payload) -> anyhow::Result<Payload> {
+        let now = clock::wall_ms();
+        // expired leases are as good as released
+        self.locks.retain(|_, lease| lease.expires_ms > now);
+
+        let reply = match request {
+            Payload::Acquire { lock, ttl_ms } => {
+                let ttl_ms = ttl_ms.unwrap_or(DEFAULT_TTL_MS);
+                match self.locks.get(&lock) {
+                    // asking again for a lock you hold extends it
+                    Some(lease) if lease.holder == holder => {
+                        let token = lease.token;
+                        self.log(Record::Renewed {
+                            lock,
+                            token,
+                            expires_ms: now + ttl_ms,
+                        })?;
+                        Payload::AcquireOk { token, ttl_ms }
+                    }
+                    Some(lease) => Payload::Error {
+                        code: error::PRECONDITION_FAILED,
+                        text: format!(
+                            "{} is held by {} for another {}ms",
+                            lock,
+                            lease.holder,
+                            lease.expires_ms - now
+                        ),
+                    },
+                    None => {
+                        let token = self.next_token;
+                        self.log(Record::Granted {
+                            lock,
+                            holder: holder.to_string(),
+                            token,
+                            expires_ms: now + ttl_ms,
+                        })?;
+                        Payload::AcquireOk { token, ttl_ms }
+                    }
+                }
+            }
+            Payload::Renew {
+                lock,
+                token,
+                ttl_ms,
+            } => {
+                let ttl_ms = ttl_ms.unwrap_or(DEFAULT_TTL_MS);
+                if self.locks.get(&lock).is_some_and(|l| l.token == token) {
+                    self.log(Record::Renewed {
+                        lock,
+                        token,
+                        expires_ms: now + ttl_ms,
+                    })?;
+                    Payload::RenewOk { ttl_ms }
+                } else {
+                    lost(&lock, token)
+                }
+            }
+            Payload::Release { lock, token } => {
+                if self.locks.get(&lock).is_some_and(|l| l.token == token) {
+                    self.log(Record::Released { lock, token })?;
+                    Payload::ReleaseOk
+                } else {
+                    lost(&lock, token)
+                }
+            }
+            _ => unreachable!("only client requests are handled"),
+        };
+        Ok(reply)
+    }
+
+    fn log(&mut self, record: Record) -> anyhow::Result<()> {
+        self.wal
+            .as_mut()
+            .expect("only the server changes the lock table")
+            .append(&record)
+            .context("log lock change")?;
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Granted {
+                lock,
+                holder,
+                token,
+                expires_ms,
+            } => {
+                self.next_token = self.next_token.max(token + 1);
+                let lease = Lease {
+                    holder,
+                    token,
+                    expires_ms,
+                };
+                self.locks.insert(lock, lease);
+            }
+            Record::Renewed {
+                lock,
+                token,
+                expires_ms,
+            } => {
+                if let Some(lease) = self.locks.get_mut(&lock).filter(|l| l.token == token) {
+                    lease.expires_ms = expires_ms;
+                }
+            }
+            Record::Released { lock, token } => {
+                if self.locks.get(&lock).is_some_and(|l| l.token == token) {
+                    self.locks.remove(&lock);
+                }
+            }
+        }
+    }
+}
+
+fn lost(lock: &str, token: u64) -> Payload {
+    Payload::Error {
+        code: error::PRECONDITION_FAILED,
+        text: format!("lease {} on {} has expired or was released", token, lock),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, LockNode, _, _>(())
+}
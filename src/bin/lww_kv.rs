@@ -0,0 +1,139 @@
+use anyhow::{Context, Ok};
+use rustengan::crdt::LwwMap;
+use rustengan::hlc::Hlc;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub(crate) const GOSSIP_EVERY: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Payload {
+    Read { key: usize },
+    ReadOk { value: usize },
+    Write { key: usize, value: usize },
+    WriteOk,
+    Delete { key: usize },
+    DeleteOk,
+    Cas { key: usize, from: usize, to: usize },
+    Error { code: usize, text: String },
+    Gossip { state: LwwMap<usize, usize> },
+}
+
+pub(crate) enum InjectedPayload {
+    Gossip,
+}
+
+pub(crate) struct LwwKvNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    clock: Hlc,
+    store: LwwMap<usize, usize>,
+}
+
+impl Node<(), Payload, InjectedPayload> for LwwKvNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(GOSSIP_EVERY, tx, || {
+            Event::Injected(InjectedPayload::Gossip)
+        });
+        Ok(Self {
+            id: 1,
+            node: init.node_id,
+            nodes: init.node_ids,
+            clock: Hlc::new(),
+            store: LwwMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Gossip) => {
+                for n in self.nodes.iter().filter(|n| **n != self.node) {
+                    Message {
+                        src: self.node.clone(),
+                        dst: n.clone(),
+                        body: Body {
+                            id: None,
+                            in_reply_to: None,
+                            correlation_id: None,
+                            payload: Payload::Gossip {
+                                state: self.store.clone(),
+                            },
+                        },
+                    }
+                    .send(&mut *output)
+                    .with_context(|| format!("gossip to {}", n))?;
+                }
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Gossip { state } => {
+                        // move our clock past their writes so a write we accept after seeing
+                        // theirs wins over them, even if our wall clock is behind
+                        if let Some(latest) = state.latest() {
+                            self.clock.observe(latest);
+                        }
+                        self.store.merge(&state);
+                    }
+                    Payload::Read { key } => {
+                        reply.body.payload = match self.store.get(&key) {
+                            Some(&value) => Payload::ReadOk { value },
+                            None => Payload::Error {
+                                code: error::KEY_DOES_NOT_EXIST,
+                                text: format!("key {} does not exist", key),
+                            },
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::Write { key, value } => {
+                        let ts = self.clock.now();
+                        self.store.insert(&self.node, ts, key, value);
+                        reply.body.payload = Payload::WriteOk;
+                        reply.send(&mut *output).context("reply to write")?;
+                    }
+                    Payload::Delete { key } => {
+                        let ts = self.clock.now();
+                        self.store.remove(&self.node, ts, key);
+                        reply.body.payload = Payload::DeleteOk;
+                        reply.send(&mut *output).context("reply to delete")?;
+                    }
+                    Payload::Cas { .. } => {
+                        // a compare-and-set decided on one replica can be silently overwritten
+                        // by a concurrent write elsewhere, so we don't pretend to offer it
+                        reply.body.payload = Payload::Error {
+                            code: error::NOT_SUPPORTED,
+                            text: "cas is not supported by a last-write-wins store".to_string(),
+                        };
+                        reply.send(&mut *output).context("reply to cas")?;
+                    }
+                    Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::DeleteOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, LwwKvNode, _, _>(())
+}
@@ -0,0 +1,179 @@
+use anyhow::{Context, Ok};
+use rand::prelude::*;
+use rustengan::metadata::{Entry, Metadata};
+use rustengan::vclock::VClock;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Set {
+        key: String,
+        value: serde_json::Value,
+    },
+    SetOk,
+    Get {
+        key: String,
+    },
+    GetOk {
+        value: Option<serde_json::Value>,
+    },
+    // the whole map, as this node currently sees it
+    Read,
+    ReadOk {
+        metadata: BTreeMap<String, serde_json::Value>,
+    },
+    // anti-entropy round, started by the sender: here's what versions I have
+    Digest {
+        versions: Vec<(String, VClock)>,
+    },
+    // what the sender has that the digest didn't, and what the digest had that the sender wants
+    Sync {
+        entries: Vec<(String, Entry)>,
+        want: Vec<String>,
+    },
+    Entries {
+        entries: Vec<(String, Entry)>,
+    },
+}
+
+pub enum InjectedPayload {
+    Gossip,
+}
+
+/// Keeps a copy of the cluster metadata map and converges with the rest of the cluster through
+/// push-pull anti-entropy: every round it sends one random peer a digest of its key versions, and
+/// the two swap whichever entries they disagree on. An update reaches everyone in a number of
+/// rounds logarithmic in the cluster size, and only differing keys ever go over the wire.
+pub struct MetadataNode {
+    node: String,
+    id: usize,
+    peers: Vec<String>,
+    // this run of the node, which its writes are versioned under: it comes back from a crash
+    // knowing nothing, and counting from scratch under its plain id would give a new write the
+    // version of one it made before, which replicas would then never exchange
+    boot: String,
+    metadata: Metadata,
+}
+
+impl Node<(), Payload, InjectedPayload> for MetadataNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(GOSSIP_INTERVAL, tx, || {
+            Event::Injected(InjectedPayload::Gossip)
+        });
+        Ok(Self {
+            peers: init
+                .node_ids
+                .into_iter()
+                .filter(|n| *n != init.node_id)
+                .collect(),
+            boot: format!("{}-{}", init.node_id, rng::ulid()),
+            node: init.node_id,
+            id: 1,
+            metadata: Metadata::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Gossip) => {
+                let Some(peer) = self.peers.choose(&mut rng::thread()) else {
+                    return Ok(());
+                };
+                let digest = Payload::Digest {
+                    versions: self.metadata.digest(),
+                };
+                self.send(peer, digest, output)?;
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Set { key, value } => {
+                        log::debug!("set {} to {}", key, value);
+                        self.metadata.set(&self.boot, key, value);
+                        reply.body.payload = Payload::SetOk;
+                        reply.send(&mut *output).context("reply to set")?;
+                    }
+                    Payload::Get { key } => {
+                        reply.body.payload = Payload::GetOk {
+                            value: self.metadata.get(&key).cloned(),
+                        };
+                        reply.send(&mut *output).context("reply to get")?;
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            metadata: self
+                                .metadata
+                                .iter()
+                                .map(|(k, v)| (k.clone(), v.clone()))
+                                .collect(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::Digest { versions } => {
+                        let (entries, want) = self.metadata.diff(&versions);
+                        if !entries.is_empty() || !want.is_empty() {
+                            self.send(&src, Payload::Sync { entries, want }, output)?;
+                        }
+                    }
+                    Payload::Sync { entries, want } => {
+                        self.merge(entries);
+                        if !want.is_empty() {
+                            let entries = self.metadata.entries(&want);
+                            self.send(&src, Payload::Entries { entries }, output)?;
+                        }
+                    }
+                    Payload::Entries { entries } => self.merge(entries),
+                    Payload::SetOk | Payload::GetOk { .. } | Payload::ReadOk { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MetadataNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn merge(&mut self, entries: Vec<(String, Entry)>) {
+        let changed = self.metadata.merge(entries);
+        if !changed.is_empty() {
+            log::debug!("metadata changed: {:?}", changed);
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, MetadataNode, _, _>(())
+}
@@ -0,0 +1,140 @@
+use anyhow::{Context, Ok};
+use rustengan::crdt::OrSet;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Add {
+        element: usize,
+    },
+    AddOk,
+    Remove {
+        element: usize,
+    },
+    RemoveOk,
+    Read,
+    ReadOk {
+        value: HashSet<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    Gossip {
+        state: OrSet<usize>,
+    },
+}
+
+enum InjectedPayload {
+    Gossip,
+}
+
+struct OrSetNode {
+    node: String,
+    id: usize,
+    // the whole state goes out every round. merging is idempotent, so repeats are harmless, and a
+    // round lost to a partition is made up for by the next one.
+    set: OrSet<usize>,
+    neighborhood: Vec<String>,
+}
+
+impl Node<(), Payload, InjectedPayload> for OrSetNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(Duration::from_millis(300), tx, || {
+            Event::Injected(InjectedPayload::Gossip)
+        });
+        Ok(Self {
+            id: 1,
+            set: OrSet::new(),
+            // until we're told otherwise, gossip with everyone
+            neighborhood: init
+                .node_ids
+                .iter()
+                .filter(|n| **n != init.node_id)
+                .cloned()
+                .collect(),
+            node: init.node_id,
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Gossip) => {
+                for n in &self.neighborhood {
+                    Message {
+                        src: self.node.clone(),
+                        dst: n.clone(),
+                        body: Body {
+                            id: None,
+                            in_reply_to: None,
+                            correlation_id: None,
+                            payload: Payload::Gossip {
+                                state: self.set.clone(),
+                            },
+                        },
+                    }
+                    .send(&mut *output)
+                    .with_context(|| format!("gossip to {}", n))?;
+                }
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Gossip { state } => self.set.merge(&state),
+                    Payload::Add { element } => {
+                        self.set.add(&self.node, element);
+                        reply.body.payload = Payload::AddOk;
+                        reply.send(&mut *output).context("reply to add")?;
+                    }
+                    Payload::Remove { element } => {
+                        self.set.remove(&element);
+                        reply.body.payload = Payload::RemoveOk;
+                        reply.send(&mut *output).context("reply to remove")?;
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            value: self.set.elements(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::Topology { mut topology } => {
+                        if let Some(neighborhood) = topology.remove(&self.node) {
+                            self.neighborhood = neighborhood;
+                        }
+                        reply.body.payload = Payload::TopologyOk;
+                        reply.send(&mut *output).context("reply to topology")?;
+                    }
+                    Payload::AddOk
+                    | Payload::RemoveOk
+                    | Payload::ReadOk { .. }
+                    | Payload::TopologyOk => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, OrSetNode, _, _>(())
+}
@@ -0,0 +1,803 @@
+use anyhow::Context;
+use rustengan::kv::{KvRequest, LIN_KV};
+use rustengan::txn::Op;
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+// a lock older than this belongs to a coordinator we assume has died, and whoever trips over it
+// may clean it up.
+const LOCK_TTL: Duration = Duration::from_secs(2);
+// past this we stop waiting for the kv and the oracle and tell the client we don't know.
+const TXN_TIMEOUT: Duration = Duration::from_secs(5);
+// the oracle persists its high-water mark in batches of this many timestamps.
+const TS_BATCH: u64 = 1000;
+// how many settled versions a row keeps. a snapshot from before the oldest of them can't be read
+// from the row any more, and its transaction has to start over.
+pub const KEPT_VERSIONS: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Txn { txn: Vec<Op> },
+    TxnOk { txn: Vec<Op> },
+    Timestamp,
+    TimestampOk { ts: u64 },
+    ReadOk { value: serde_json::Value },
+    CasOk,
+    WriteOk,
+    Error { code: usize, text: String },
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+// one lin-kv entry per user key, holding percolator's data, lock and write columns. every change
+// to a row is a read followed by a cas, so rows never see lost updates.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Row {
+    // start_ts -> value
+    #[serde(default)]
+    pub data: BTreeMap<u64, usize>,
+    #[serde(default)]
+    pub lock: Option<Lock>,
+    // commit_ts -> the write whose data it makes visible
+    #[serde(default)]
+    pub write: BTreeMap<u64, Write>,
+    // snapshots before this can't be read from the row, the versions they'd see are collected
+    #[serde(default)]
+    pub horizon: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lock {
+    pub start_ts: u64,
+    pub primary: usize,
+    pub wall_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Write {
+    pub start_ts: u64,
+    // a primary's write until every secondary has committed too. until then, whoever trips over
+    // a secondary's leftover lock needs it to tell the transaction committed, so it's never
+    // collected. one whose coordinator died before settling it is kept for good.
+    #[serde(default)]
+    pub pending: bool,
+}
+
+impl Lock {
+    fn expired(&self) -> bool {
+        clock::wall_ms().saturating_sub(self.wall_ms) > LOCK_TTL.as_millis() as u64
+    }
+}
+
+impl Row {
+    pub fn visible(&self, ts: u64) -> Option<usize> {
+        let (_, write) = self.write.range(..=ts).next_back()?;
+        self.data.get(&write.start_ts).copied()
+    }
+
+    pub fn committed_at(&self, start_ts: u64) -> Option<u64> {
+        self.write
+            .iter()
+            .find(|(_, w)| w.start_ts == start_ts)
+            .map(|(commit_ts, _)| *commit_ts)
+    }
+
+    // drops every settled write older than the newest KEPT_VERSIONS, and the data only they made
+    // visible, moving the horizon up to the oldest write that's left
+    fn collect(&mut self) {
+        let settled = self.write.iter().filter(|(_, w)| !w.pending);
+        let Some((&horizon, _)) = settled.rev().nth(KEPT_VERSIONS - 1) else {
+            return;
+        };
+        // never moves back, even when a write pending below it settles
+        self.horizon = self.horizon.max(horizon);
+        self.write
+            .retain(|&commit_ts, w| commit_ts >= horizon || w.pending);
+        let mut visible: HashSet<u64> = self
+            .write
+            .range(horizon..)
+            .map(|(_, w)| w.start_ts)
+            .collect();
+        visible.extend(self.lock.as_ref().map(|lock| lock.start_ts));
+        self.data.retain(|start_ts, _| visible.contains(start_ts));
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Mutation {
+    Prewrite {
+        start_ts: u64,
+        primary: usize,
+        value: usize,
+    },
+    Commit {
+        start_ts: u64,
+        commit_ts: u64,
+        // a primary with secondaries still to commit
+        pending: bool,
+    },
+    // every secondary of the transaction that committed at `commit_ts` has committed too
+    Settle {
+        commit_ts: u64,
+    },
+    Rollback {
+        start_ts: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum Conflict {
+    Locked(Lock),
+    NewerWrite,
+    LockGone,
+}
+
+impl Mutation {
+    // Ok(None) means the row already reflects the mutation
+    pub fn apply(&self, row: &Row) -> Result<Option<Row>, Conflict> {
+        let mut row = row.clone();
+        match *self {
+            Mutation::Prewrite {
+                start_ts,
+                primary,
+                value,
+            } => {
+                match &row.lock {
+                    Some(lock) if lock.start_ts == start_ts => return Ok(None),
+                    Some(lock) => return Err(Conflict::Locked(lock.clone())),
+                    None => {}
+                }
+                if row.write.range(start_ts..).next().is_some() {
+                    return Err(Conflict::NewerWrite);
+                }
+                row.lock = Some(Lock {
+                    start_ts,
+                    primary,
+                    wall_ms: clock::wall_ms(),
+                });
+                row.data.insert(start_ts, value);
+            }
+            Mutation::Commit {
+                start_ts,
+                commit_ts,
+                pending,
+            } => match &row.lock {
+                Some(lock) if lock.start_ts == start_ts => {
+                    row.lock = None;
+                    row.write.insert(commit_ts, Write { start_ts, pending });
+                }
+                _ if row.committed_at(start_ts).is_some() => return Ok(None),
+                _ => return Err(Conflict::LockGone),
+            },
+            Mutation::Settle { commit_ts } => match row.write.get_mut(&commit_ts) {
+                Some(write) if write.pending => write.pending = false,
+                _ => return Ok(None),
+            },
+            Mutation::Rollback { start_ts } => match &row.lock {
+                Some(lock) if lock.start_ts == start_ts => {
+                    row.lock = None;
+                    row.data.remove(&start_ts);
+                }
+                _ => return Ok(None),
+            },
+        }
+        row.collect();
+        Ok(Some(row))
+    }
+}
+
+// what to do with the outcome of a row operation once it completes
+#[derive(Debug, Clone)]
+enum Owner {
+    Txn(String),
+    // reading the primary of an abandoned lock to find out how its transaction ended
+    ResolvePrimary { key: usize, lock: Lock },
+    // rolling back the primary of an abandoned lock before doing the same to `key`
+    ResolveRollback { key: usize, lock: Lock },
+    // committing a secondary of the transaction that committed at `commit_ts`, which settles
+    // its primary's write once every secondary is done
+    Secondary { commit_ts: u64 },
+    // fire and forget: settling, resolving abandoned locks, rollbacks of our own aborted
+    // prewrites
+    Cleanup,
+}
+
+struct RowOp {
+    owner: Owner,
+    key: usize,
+    mutation: Option<Mutation>,
+    updated: Option<Row>,
+}
+
+enum Rpc {
+    Row(RowOp),
+    Timestamp(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    StartTs,
+    Read(usize),
+    Prewrite(usize),
+    CommitTs,
+    CommitPrimary,
+}
+
+struct Txn {
+    client: String,
+    client_msg_id: Option<usize>,
+    ops: Vec<Op>,
+    started: Instant,
+    stage: Stage,
+    start_ts: u64,
+    commit_ts: u64,
+    // buffered writes in key order; the first one is the primary
+    writes: BTreeMap<usize, usize>,
+    prewritten: Vec<usize>,
+}
+
+impl Txn {
+    fn primary(&self) -> Option<usize> {
+        self.writes.keys().next().copied()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Reservation {
+    upto: u64,
+}
+
+struct Oracle {
+    next: u64,
+    reserved: u64,
+    wal: Wal<Reservation>,
+}
+
+impl Oracle {
+    fn next(&mut self) -> anyhow::Result<u64> {
+        if self.next >= self.reserved {
+            self.reserved = self.next + TS_BATCH;
+            self.wal
+                .append(&Reservation {
+                    upto: self.reserved,
+                })
+                .context("reserve timestamps")?;
+        }
+        self.next += 1;
+        Ok(self.next)
+    }
+}
+
+pub struct PercolatorNode {
+    node: String,
+    id: usize,
+    oracle_node: String,
+    // only set on the oracle node itself
+    oracle: Option<Oracle>,
+    txns: HashMap<String, Txn>,
+    rpcs: HashMap<usize, Rpc>,
+    // by commit timestamp, the primary of each transaction whose secondaries are committing, and
+    // how many of them are still to go
+    settling: HashMap<u64, (usize, usize)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for PercolatorNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let oracle_node = init.node_ids.first().context("empty cluster")?.clone();
+        let oracle = if init.node_id == oracle_node {
+            let (wal, reservations) =
+                Wal::<Reservation>::open(wal::data_dir().join(format!("{}.tso.wal", init.node_id)))
+                    .context("open oracle wal")?;
+            // anything below the last reservation may have been handed out before we crashed
+            let reserved = reservations.iter().map(|r| r.upto).max().unwrap_or(0);
+            Some(Oracle {
+                next: reserved,
+                reserved,
+                wal,
+            })
+        } else {
+            None
+        };
+        ticks::every(Duration::from_millis(500), tx, || {
+            Event::Injected(InjectedPayload::Tick)
+        });
+        Ok(Self {
+            node: init.node_id,
+            id: 1,
+            oracle_node,
+            oracle,
+            txns: HashMap::new(),
+            rpcs: HashMap::new(),
+            settling: HashMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => {
+                let stuck: Vec<_> = self
+                    .txns
+                    .iter()
+                    .filter(|(_, txn)| clock::since(txn.started) > TXN_TIMEOUT)
+                    .map(|(txn_id, _)| txn_id.clone())
+                    .collect();
+                for txn_id in stuck {
+                    // possibly half-committed, so all we can say is that we don't know. whatever
+                    // locks it left behind expire and get resolved by the next reader.
+                    self.finish(&txn_id, Err(error::TIMEOUT), output)?;
+                }
+            }
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let in_reply_to = input.body.in_reply_to;
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Txn { txn } => {
+                        let txn_id = format!("{}-{}", self.node, rng::ulid());
+                        let writes = txn
+                            .iter()
+                            .filter(|op| op.is_write())
+                            .map(|op| (op.key(), op.2.unwrap_or_default()))
+                            .collect();
+                        self.txns.insert(
+                            txn_id.clone(),
+                            Txn {
+                                client: src,
+                                client_msg_id: reply.body.in_reply_to,
+                                ops: txn,
+                                started: clock::now(),
+                                stage: Stage::StartTs,
+                                start_ts: 0,
+                                commit_ts: 0,
+                                writes,
+                                prewritten: Vec::new(),
+                            },
+                        );
+                        self.request_ts(&txn_id, output)?;
+                    }
+                    Payload::Timestamp => {
+                        let oracle = self
+                            .oracle
+                            .as_mut()
+                            .context("timestamp request sent to a node that isn't the oracle")?;
+                        reply.body.payload = Payload::TimestampOk { ts: oracle.next()? };
+                        reply.send(&mut *output).context("reply to timestamp")?;
+                    }
+                    Payload::TimestampOk { ts } => {
+                        if let Some(Rpc::Timestamp(txn_id)) =
+                            in_reply_to.and_then(|id| self.rpcs.remove(&id))
+                        {
+                            self.got_ts(&txn_id, ts, output)?;
+                        }
+                    }
+                    Payload::ReadOk { value } => {
+                        let row = serde_json::from_value(value).context("parse row")?;
+                        self.kv_reply(in_reply_to, KvOutcome::Read(row, true), output)?;
+                    }
+                    Payload::CasOk => self.kv_reply(in_reply_to, KvOutcome::Written, output)?,
+                    Payload::Error { code, .. } if code == error::KEY_DOES_NOT_EXIST => {
+                        self.kv_reply(in_reply_to, KvOutcome::Read(Row::default(), false), output)?;
+                    }
+                    Payload::Error { code, .. } if code == error::PRECONDITION_FAILED => {
+                        self.kv_reply(in_reply_to, KvOutcome::Raced, output)?;
+                    }
+                    Payload::Error { code, text } => {
+                        log::warn!("kv error {}: {}", code, text);
+                    }
+                    Payload::TxnOk { .. } | Payload::WriteOk => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+enum KvOutcome {
+    Read(Row, bool),
+    Written,
+    Raced,
+}
+
+fn row_key(key: usize) -> String {
+    format!("row:{}", key)
+}
+
+impl PercolatorNode {
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    fn request_ts(&mut self, txn_id: &str, output: &mut Output) -> anyhow::Result<()> {
+        if let Some(oracle) = &mut self.oracle {
+            let ts = oracle.next()?;
+            return self.got_ts(txn_id, ts, output);
+        }
+        let id = self.next_id();
+        self.rpcs.insert(id, Rpc::Timestamp(txn_id.to_string()));
+        Message {
+            src: self.node.clone(),
+            dst: self.oracle_node.clone(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                correlation_id: None,
+                payload: Payload::Timestamp,
+            },
+        }
+        .send(&mut *output)
+        .context("request timestamp")
+    }
+
+    fn row_op(
+        &mut self,
+        owner: Owner,
+        key: usize,
+        mutation: Option<Mutation>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let id = self.next_id();
+        self.rpcs.insert(
+            id,
+            Rpc::Row(RowOp {
+                owner,
+                key,
+                mutation,
+                updated: None,
+            }),
+        );
+        KvRequest::<_, serde_json::Value>::Read { key: row_key(key) }
+            .send(&self.node, LIN_KV, id, &mut *output)
+            .context("read row")
+    }
+
+    fn kv_reply(
+        &mut self,
+        in_reply_to: Option<usize>,
+        outcome: KvOutcome,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(Rpc::Row(mut op)) = in_reply_to.and_then(|id| self.rpcs.remove(&id)) else {
+            return Ok(());
+        };
+        match outcome {
+            KvOutcome::Read(row, exists) => {
+                let Some(mutation) = &op.mutation else {
+                    return self.row_done(op.owner, Ok(row), output);
+                };
+                match mutation.apply(&row) {
+                    Err(conflict) => self.row_done(op.owner, Err(conflict), output),
+                    Ok(None) => self.row_done(op.owner, Ok(row), output),
+                    Ok(Some(updated)) => {
+                        let id = self.next_id();
+                        KvRequest::Cas {
+                            key: row_key(op.key),
+                            from: serde_json::to_value(&row)?,
+                            to: serde_json::to_value(&updated)?,
+                            create_if_not_exists: !exists,
+                        }
+                        .send(&self.node, LIN_KV, id, &mut *output)
+                        .context("cas row")?;
+                        op.updated = Some(updated);
+                        self.rpcs.insert(id, Rpc::Row(op));
+                        Ok(())
+                    }
+                }
+            }
+            KvOutcome::Written => {
+                let row = op.updated.take().expect("cas without an update");
+                self.row_done(op.owner, Ok(row), output)
+            }
+            // somebody else changed the row between our read and our cas, start over
+            KvOutcome::Raced => self.row_op(op.owner, op.key, op.mutation, output),
+        }
+    }
+
+    fn row_done(
+        &mut self,
+        owner: Owner,
+        result: Result<Row, Conflict>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match owner {
+            Owner::Txn(txn_id) => self.advance(&txn_id, result, output),
+            Owner::ResolvePrimary { key, lock } => {
+                let Ok(primary) = result else {
+                    return Ok(());
+                };
+                if let Some(commit_ts) = primary.committed_at(lock.start_ts) {
+                    // it made it, finish the job for it
+                    let mutation = Mutation::Commit {
+                        start_ts: lock.start_ts,
+                        commit_ts,
+                        pending: false,
+                    };
+                    self.row_op(Owner::Cleanup, key, Some(mutation), output)
+                } else {
+                    let mutation = Mutation::Rollback {
+                        start_ts: lock.start_ts,
+                    };
+                    let primary = lock.primary;
+                    self.row_op(
+                        Owner::ResolveRollback { key, lock },
+                        primary,
+                        Some(mutation),
+                        output,
+                    )
+                }
+            }
+            Owner::ResolveRollback {
+                key: secondary,
+                lock,
+            } => {
+                let Ok(primary) = result else {
+                    return Ok(());
+                };
+                // the primary may have committed between our read and our rollback
+                let mutation = match primary.committed_at(lock.start_ts) {
+                    Some(commit_ts) => Mutation::Commit {
+                        start_ts: lock.start_ts,
+                        commit_ts,
+                        pending: false,
+                    },
+                    None => Mutation::Rollback {
+                        start_ts: lock.start_ts,
+                    },
+                };
+                if secondary == lock.primary {
+                    // the abandoned lock was on the primary itself
+                    return Ok(());
+                }
+                self.row_op(Owner::Cleanup, secondary, Some(mutation), output)
+            }
+            Owner::Secondary { commit_ts } => {
+                let Some((primary, left)) = self.settling.get_mut(&commit_ts) else {
+                    return Ok(());
+                };
+                if result.is_err() {
+                    // we can't tell the secondary committed, so the primary's write stays pending
+                    self.settling.remove(&commit_ts);
+                    return Ok(());
+                }
+                *left -= 1;
+                if *left > 0 {
+                    return Ok(());
+                }
+                let primary = *primary;
+                self.settling.remove(&commit_ts);
+                let mutation = Mutation::Settle { commit_ts };
+                self.row_op(Owner::Cleanup, primary, Some(mutation), output)
+            }
+            Owner::Cleanup => Ok(()),
+        }
+    }
+
+    fn resolve(&mut self, key: usize, lock: Lock, output: &mut Output) -> anyhow::Result<()> {
+        if !lock.expired() {
+            return Ok(());
+        }
+        log::info!("resolving abandoned lock on {} from {}", key, lock.start_ts);
+        let primary = lock.primary;
+        self.row_op(Owner::ResolvePrimary { key, lock }, primary, None, output)
+    }
+
+    fn got_ts(&mut self, txn_id: &str, ts: u64, output: &mut Output) -> anyhow::Result<()> {
+        let Some(txn) = self.txns.get_mut(txn_id) else {
+            return Ok(());
+        };
+        match txn.stage {
+            Stage::StartTs => {
+                txn.start_ts = ts;
+                self.read_from(txn_id, 0, output)
+            }
+            Stage::CommitTs => {
+                txn.commit_ts = ts;
+                txn.stage = Stage::CommitPrimary;
+                let mutation = Mutation::Commit {
+                    start_ts: txn.start_ts,
+                    commit_ts: ts,
+                    pending: txn.writes.len() > 1,
+                };
+                let Some(primary) = txn.primary() else {
+                    // read-only transactions finish before they get a commit timestamp
+                    return Ok(());
+                };
+                self.row_op(
+                    Owner::Txn(txn_id.to_string()),
+                    primary,
+                    Some(mutation),
+                    output,
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // issue the next snapshot read at or after op `from`, or move on to prewriting
+    fn read_from(&mut self, txn_id: &str, from: usize, output: &mut Output) -> anyhow::Result<()> {
+        let Some(txn) = self.txns.get_mut(txn_id) else {
+            return Ok(());
+        };
+        let mut buffered = HashMap::new();
+        for (i, op) in txn.ops.iter_mut().enumerate() {
+            if op.is_write() {
+                buffered.insert(op.key(), op.2);
+            } else if i >= from {
+                if let Some(&value) = buffered.get(&op.key()) {
+                    // we read our own write
+                    op.2 = value;
+                    continue;
+                }
+                txn.stage = Stage::Read(i);
+                let key = op.key();
+                return self.row_op(Owner::Txn(txn_id.to_string()), key, None, output);
+            }
+        }
+        self.prewrite_from(txn_id, 0, output)
+    }
+
+    fn prewrite_from(
+        &mut self,
+        txn_id: &str,
+        from: usize,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(txn) = self.txns.get_mut(txn_id) else {
+            return Ok(());
+        };
+        let Some(primary) = txn.primary() else {
+            // read-only transactions are done once they've read their snapshot
+            let ops = txn.ops.clone();
+            return self.finish(txn_id, Ok(ops), output);
+        };
+        let Some((&key, &value)) = txn.writes.iter().nth(from) else {
+            txn.stage = Stage::CommitTs;
+            return self.request_ts(txn_id, output);
+        };
+        txn.stage = Stage::Prewrite(from);
+        let mutation = Mutation::Prewrite {
+            start_ts: txn.start_ts,
+            primary,
+            value,
+        };
+        self.row_op(Owner::Txn(txn_id.to_string()), key, Some(mutation), output)
+    }
+
+    fn advance(
+        &mut self,
+        txn_id: &str,
+        result: Result<Row, Conflict>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(txn) = self.txns.get_mut(txn_id) else {
+            return Ok(());
+        };
+        match (txn.stage, result) {
+            (Stage::Read(i), Ok(row)) => {
+                match &row.lock {
+                    Some(lock) if lock.start_ts <= txn.start_ts => {
+                        // someone might commit below our snapshot, we can't tell what to read
+                        let (key, lock) = (txn.ops[i].key(), lock.clone());
+                        self.resolve(key, lock, output)?;
+                        return self.finish(txn_id, Err(error::TXN_CONFLICT), output);
+                    }
+                    _ => {}
+                }
+                if txn.start_ts < row.horizon {
+                    // the version our snapshot would see has been collected
+                    return self.finish(txn_id, Err(error::TXN_CONFLICT), output);
+                }
+                txn.ops[i].2 = row.visible(txn.start_ts);
+                self.read_from(txn_id, i + 1, output)
+            }
+            (Stage::Prewrite(i), Ok(_)) => {
+                let key = *txn.writes.keys().nth(i).expect("prewrote unknown key");
+                txn.prewritten.push(key);
+                self.prewrite_from(txn_id, i + 1, output)
+            }
+            (Stage::Prewrite(i), Err(conflict)) => {
+                if let Conflict::Locked(lock) = conflict {
+                    let key = *txn.writes.keys().nth(i).expect("prewrote unknown key");
+                    self.resolve(key, lock, output)?;
+                }
+                self.finish(txn_id, Err(error::TXN_CONFLICT), output)
+            }
+            (Stage::CommitPrimary, Ok(_)) => {
+                // the primary's write record is the commit point. secondaries can catch up on
+                // their own time; readers roll them forward if we crash before they do.
+                let (start_ts, commit_ts) = (txn.start_ts, txn.commit_ts);
+                let secondaries: Vec<_> = txn.writes.keys().skip(1).copied().collect();
+                if let Some(primary) = txn.primary().filter(|_| !secondaries.is_empty()) {
+                    self.settling
+                        .insert(commit_ts, (primary, secondaries.len()));
+                }
+                let ops = txn.ops.clone();
+                txn.prewritten.clear();
+                for key in secondaries {
+                    let mutation = Mutation::Commit {
+                        start_ts,
+                        commit_ts,
+                        pending: false,
+                    };
+                    let owner = Owner::Secondary { commit_ts };
+                    self.row_op(owner, key, Some(mutation), output)?;
+                }
+                self.finish(txn_id, Ok(ops), output)
+            }
+            (Stage::CommitPrimary, Err(_)) => {
+                // our primary lock was rolled back by someone who thought we were dead
+                self.finish(txn_id, Err(error::TXN_CONFLICT), output)
+            }
+            (stage, result) => {
+                log::warn!("unexpected row result in stage {:?}: {:?}", stage, result);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(
+        &mut self,
+        txn_id: &str,
+        result: Result<Vec<Op>, usize>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(txn) = self.txns.remove(txn_id) else {
+            return Ok(());
+        };
+        if result.is_err() && txn.stage != Stage::CommitPrimary {
+            for key in txn.prewritten {
+                let mutation = Mutation::Rollback {
+                    start_ts: txn.start_ts,
+                };
+                self.row_op(Owner::Cleanup, key, Some(mutation), output)?;
+            }
+        }
+        let payload = match result {
+            Ok(txn) => Payload::TxnOk { txn },
+            Err(code) => Payload::Error {
+                code,
+                text: format!("transaction {} failed", txn_id),
+            },
+        };
+        let id = self.next_id();
+        Message {
+            src: self.node.clone(),
+            dst: txn.client,
+            body: Body {
+                id: Some(id),
+                in_reply_to: txn.client_msg_id,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to txn")
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, PercolatorNode, _, _>(())
+}
@@ -0,0 +1,803 @@
+use anyhow::{Context, Ok};
+use rustengan::kv::{KvRequest, LIN_KV};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::{Duration, Instant},
+};
+
+const LEASE_KEY: &str = "primary-backup-lease";
+const LEASE_DURATION: Duration = Duration::from_millis(1000);
+// the primary stops serving this long before its lease runs out, so it never answers a client
+// after a backup could have taken over.
+const LEASE_MARGIN: Duration = Duration::from_millis(200);
+const TICK: Duration = Duration::from_millis(100);
+// a backup that hasn't acknowledged anything in this long is dropped from the in-sync set.
+const BACKUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    // with a staleness bound any replica may answer from its own copy, as long as that copy is
+    // known to have been current no more than that many milliseconds ago
+    Read {
+        key: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_staleness_ms: Option<u64>,
+    },
+    // carries either a client's value or, coming from lin-kv, the lease
+    ReadOk {
+        value: serde_json::Value,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    CasOk,
+    Error {
+        code: usize,
+        text: String,
+    },
+    Forward {
+        origin: String,
+        req_id: usize,
+        request: Box<Payload>,
+    },
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+    Replicate {
+        view: u64,
+        entry: Entry,
+    },
+    Snapshot {
+        view: u64,
+        seq: u64,
+        // a list rather than a map: integer map keys don't survive serde's internally tagged enums
+        store: Vec<(usize, usize)>,
+    },
+    // the backup has applied everything up to and including seq
+    ReplicateOk {
+        view: u64,
+        seq: u64,
+    },
+    SnapshotOk {
+        view: u64,
+        seq: u64,
+    },
+    // primary -> backups at every tick: at sent_ms, by the primary's clock, the log reached seq
+    Progress {
+        view: u64,
+        seq: u64,
+        sent_ms: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    seq: u64,
+    // reads and failed cas's still go through the log as no-ops, so that they are only answered
+    // once everything before them is on every backup
+    write: Option<(usize, usize)>,
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+// the single source of truth about who is primary, kept in lin-kv. only members of `in_sync` are
+// guaranteed to have every acknowledged write, so only they may take over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Lease {
+    primary: String,
+    view: u64,
+    expires_ms: u64,
+    in_sync: Vec<String>,
+}
+
+enum KvCall {
+    ReadLease,
+    Renew(Lease),
+    Takeover(Lease),
+}
+
+struct Primary {
+    // backups confirmed in the lease, which every commit waits for
+    in_sync: HashSet<String>,
+    // what we'd like the lease to say at the next renewal
+    desired: HashSet<String>,
+    // backups that installed our snapshot, and so can be sent log entries
+    synced: HashSet<String>,
+    acked: HashMap<String, u64>,
+    last_ack: HashMap<String, Instant>,
+    last_snapshot: HashMap<String, Instant>,
+    next_seq: u64,
+    // entries not yet on every in-sync backup, and who to answer once they are
+    log: BTreeMap<u64, Entry>,
+    waiting: BTreeMap<u64, (String, usize, Payload)>,
+}
+
+pub struct PrimaryBackupNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    lease: Option<Lease>,
+    role: Option<Primary>,
+    renewing: bool,
+
+    view: u64,
+    // the view whose snapshot we last installed. replication in a newer view is refused until its
+    // snapshot arrives, since we may hold entries the new primary never saw.
+    synced_view: u64,
+    store: HashMap<usize, usize>,
+    applied: u64,
+    buffered: BTreeMap<u64, Entry>,
+    // progress reports we haven't caught up with yet, and the time of the last one we have: our
+    // copy held everything the primary had as of then
+    progress: VecDeque<(u64, u64)>,
+    fresh_ms: Option<u64>,
+    // replicas that follow the log for reads but never join the in-sync set, so never take over
+    replicas: HashSet<String>,
+    // the bound for reads that don't bring their own. without one they go to the primary.
+    max_staleness_ms: Option<u64>,
+
+    kv: HashMap<usize, KvCall>,
+    pending: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for PrimaryBackupNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        Ok(Self {
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            lease: None,
+            role: None,
+            renewing: false,
+            view: 0,
+            synced_view: 0,
+            store: HashMap::new(),
+            applied: 0,
+            buffered: BTreeMap::new(),
+            progress: VecDeque::new(),
+            fresh_ms: None,
+            replicas: config::var::<String>("RUSTENGAN_READ_REPLICAS")?
+                .unwrap_or_default()
+                .split(',')
+                .filter(|n| !n.is_empty())
+                .map(str::to_string)
+                .collect(),
+            max_staleness_ms: config::var("RUSTENGAN_MAX_STALENESS_MS")?,
+            kv: HashMap::new(),
+            pending: HashMap::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => self.tick(output)?,
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                if let Some(call) = input.body.in_reply_to.and_then(|id| self.kv.remove(&id)) {
+                    return self.kv_reply(call, input.body.payload, output);
+                }
+                match input.body.payload {
+                    Payload::Read {
+                        key,
+                        max_staleness_ms,
+                    } if !self.holds_lease()
+                        && max_staleness_ms.or(self.max_staleness_ms).is_some() =>
+                    {
+                        let req_id = self.next_id();
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let bound = max_staleness_ms.or(self.max_staleness_ms).expect("checked");
+                        let reply = self.follower_read(key, bound);
+                        self.reply_client(req_id, reply, output)?;
+                    }
+                    request @ (Payload::Read { .. }
+                    | Payload::Write { .. }
+                    | Payload::Cas { .. }) => {
+                        let req_id = self.next_id();
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let me = self.node.clone();
+                        self.handle(me, req_id, request, output)?;
+                    }
+                    Payload::Forward {
+                        origin,
+                        req_id,
+                        request,
+                    } => self.handle(origin, req_id, *request, output)?,
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    Payload::Replicate { view, entry } => {
+                        if self.accept_view(view, &src) && self.synced_view == view {
+                            self.buffered.insert(entry.seq, entry);
+                            while let Some(entry) = self.buffered.remove(&(self.applied + 1)) {
+                                self.applied = entry.seq;
+                                if let Some((key, value)) = entry.write {
+                                    self.store.insert(key, value);
+                                }
+                            }
+                            self.buffered.retain(|seq, _| *seq > self.applied);
+                            self.catch_up();
+                            let seq = self.applied;
+                            self.send(&src, Payload::ReplicateOk { view, seq }, output)?;
+                        }
+                    }
+                    Payload::Snapshot { view, seq, store } => {
+                        if self.accept_view(view, &src) {
+                            // within a view a snapshot is a prefix of the log, so an old one
+                            // showing up late has nothing to teach us
+                            if self.synced_view != view || seq > self.applied {
+                                self.store = store.into_iter().collect();
+                                self.applied = seq;
+                                self.buffered.retain(|s, _| *s > seq);
+                                if self.synced_view != view {
+                                    // numbered by a primary whose log may have differed from ours
+                                    self.progress.clear();
+                                }
+                                self.synced_view = view;
+                                self.catch_up();
+                            }
+                            let seq = self.applied;
+                            self.send(&src, Payload::SnapshotOk { view, seq }, output)?;
+                        }
+                    }
+                    Payload::ReplicateOk { view, seq } => {
+                        self.replicated(&src, view, seq, false, output)?;
+                    }
+                    Payload::SnapshotOk { view, seq } => {
+                        self.replicated(&src, view, seq, true, output)?;
+                    }
+                    Payload::Progress { view, seq, sent_ms } => {
+                        if self.accept_view(view, &src) && self.synced_view == view {
+                            self.progress.push_back((seq, sent_ms));
+                            self.catch_up();
+                        }
+                    }
+                    Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::CasOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PrimaryBackupNode {
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    // the nodes that may take over, which is everyone but the read replicas
+    fn members(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|n| !self.replicas.contains(*n))
+            .cloned()
+            .collect()
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn kv_call(
+        &mut self,
+        call: KvCall,
+        request: KvRequest<&str, Option<Lease>>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let id = self.next_id();
+        self.kv.insert(id, call);
+        request
+            .send(&self.node, LIN_KV, id, &mut *output)
+            .context("lease request")
+    }
+
+    fn holds_lease(&self) -> bool {
+        self.role.is_some()
+            && self.lease.as_ref().is_some_and(|lease| {
+                lease.primary == self.node
+                    && lease.view == self.view
+                    && clock::wall_ms() + (LEASE_MARGIN.as_millis() as u64) < lease.expires_ms
+            })
+    }
+
+    // a backup only listens to the primary of the newest view it has heard of
+    fn accept_view(&mut self, view: u64, src: &str) -> bool {
+        if view < self.view || self.role.is_some() && view == self.view {
+            return false;
+        }
+        if view > self.view && self.role.take().is_some() {
+            log::info!("{} took over in view {}, stepping down", src, view);
+        }
+        self.view = view;
+        true
+    }
+
+    fn tick(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        match (&self.role, &self.lease) {
+            // renew from our own copy rather than from a read, which could be older than our
+            // last renewal and make us think we lost the lease
+            (Some(_), Some(lease)) => {
+                if !self.renewing {
+                    self.renewing = true;
+                    self.renew(lease.clone(), output)?;
+                }
+            }
+            _ => self.kv_call(
+                KvCall::ReadLease,
+                KvRequest::Read { key: LEASE_KEY },
+                output,
+            )?,
+        }
+
+        let Some(primary) = &mut self.role else {
+            return Ok(());
+        };
+        let lagging: Vec<_> = primary
+            .in_sync
+            .iter()
+            .filter(|b| {
+                primary
+                    .last_ack
+                    .get(*b)
+                    .is_none_or(|at| clock::since(*at) > BACKUP_TIMEOUT)
+            })
+            .cloned()
+            .collect();
+        for backup in lagging {
+            if primary.desired.remove(&backup) {
+                log::warn!("dropping {} from the in-sync set", backup);
+            }
+        }
+        // keep the rest of the in-sync set up to date, and bring everyone else back into it
+        let mut resend = Vec::new();
+        for backup in self.nodes.iter().filter(|n| **n != self.node) {
+            let acked = primary.acked.get(backup).copied().unwrap_or(0);
+            // a replica isn't waited for, so it can fall behind what the log still holds
+            let gap = primary
+                .log
+                .first_key_value()
+                .map_or(acked < self.applied, |(&first, _)| acked + 1 < first);
+            let follows =
+                primary.in_sync.contains(backup) || self.replicas.contains(backup) && !gap;
+            if follows && primary.synced.contains(backup) {
+                resend.extend(
+                    primary
+                        .log
+                        .range(acked + 1..)
+                        .map(|(_, entry)| (backup.clone(), entry.clone())),
+                );
+            } else if primary
+                .last_snapshot
+                .get(backup)
+                .is_none_or(|at| clock::since(*at) > BACKUP_TIMEOUT)
+            {
+                primary.last_snapshot.insert(backup.clone(), clock::now());
+                resend.push((
+                    backup.clone(),
+                    Entry {
+                        seq: 0,
+                        write: None,
+                    },
+                ));
+            }
+        }
+        let progress: Vec<_> = primary.synced.iter().cloned().collect();
+        for backup in progress {
+            let progress = Payload::Progress {
+                view: self.view,
+                seq: self.applied,
+                sent_ms: clock::wall_ms(),
+            };
+            self.send(&backup, progress, output)?;
+        }
+        for (backup, entry) in resend {
+            let payload = if entry.seq == 0 {
+                Payload::Snapshot {
+                    view: self.view,
+                    seq: self.applied,
+                    store: self.store.iter().map(|(k, v)| (*k, *v)).collect(),
+                }
+            } else {
+                Payload::Replicate {
+                    view: self.view,
+                    entry,
+                }
+            };
+            self.send(&backup, payload, output)?;
+        }
+        Ok(())
+    }
+
+    fn kv_reply(
+        &mut self,
+        call: KvCall,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match (call, reply) {
+            (KvCall::ReadLease, Payload::ReadOk { value }) => {
+                let lease: Lease = serde_json::from_value(value).context("parse lease")?;
+                self.lease = Some(lease.clone());
+                if self.role.is_none()
+                    && lease.expires_ms < clock::wall_ms()
+                    && lease.in_sync.contains(&self.node)
+                {
+                    let next = Lease {
+                        primary: self.node.clone(),
+                        view: lease.view + 1,
+                        expires_ms: clock::wall_ms() + LEASE_DURATION.as_millis() as u64,
+                        in_sync: lease.in_sync.clone(),
+                    };
+                    self.kv_call(
+                        KvCall::Takeover(next.clone()),
+                        KvRequest::Cas {
+                            key: LEASE_KEY,
+                            from: Some(lease),
+                            to: Some(next),
+                            create_if_not_exists: false,
+                        },
+                        output,
+                    )?;
+                }
+            }
+            // nobody has been primary yet and everyone starts out empty, so all nodes are in
+            // sync. let the first node have a go.
+            (KvCall::ReadLease, Payload::Error { code, .. })
+                if code == error::KEY_DOES_NOT_EXIST
+                    && self.members().first() == Some(&self.node) =>
+            {
+                let first = Lease {
+                    primary: self.node.clone(),
+                    view: 1,
+                    expires_ms: clock::wall_ms() + LEASE_DURATION.as_millis() as u64,
+                    in_sync: self.members(),
+                };
+                self.kv_call(
+                    KvCall::Takeover(first.clone()),
+                    KvRequest::Cas {
+                        key: LEASE_KEY,
+                        from: None,
+                        to: Some(first),
+                        create_if_not_exists: true,
+                    },
+                    output,
+                )?;
+            }
+            (KvCall::Takeover(lease), Payload::CasOk) => self.take_over(lease),
+            (KvCall::Renew(lease), Payload::CasOk) => {
+                self.renewing = false;
+                if let Some(primary) = &mut self.role {
+                    primary.in_sync = lease
+                        .in_sync
+                        .iter()
+                        .filter(|n| **n != self.node)
+                        .cloned()
+                        .collect();
+                }
+                self.lease = Some(lease);
+                self.commit(output)?;
+            }
+            (KvCall::Renew(_), Payload::Error { .. }) => {
+                self.renewing = false;
+                // somebody else moved the lease, so we're no longer the primary
+                if self.role.take().is_some() {
+                    log::warn!("lost the lease in view {}", self.view);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn renew(&mut self, current: Lease, output: &mut Output) -> anyhow::Result<()> {
+        let primary = self.role.as_ref().expect("only the primary renews");
+        let mut in_sync: Vec<_> = primary.desired.iter().cloned().collect();
+        in_sync.push(self.node.clone());
+        in_sync.sort();
+        let renewed = Lease {
+            expires_ms: clock::wall_ms() + LEASE_DURATION.as_millis() as u64,
+            in_sync,
+            ..current.clone()
+        };
+        self.kv_call(
+            KvCall::Renew(renewed.clone()),
+            KvRequest::Cas {
+                key: LEASE_KEY,
+                from: Some(current),
+                to: Some(renewed),
+                create_if_not_exists: false,
+            },
+            output,
+        )
+    }
+
+    fn take_over(&mut self, lease: Lease) {
+        log::info!("primary for view {}", lease.view);
+        let backups: HashSet<_> = lease
+            .in_sync
+            .iter()
+            .filter(|n| **n != self.node)
+            .cloned()
+            .collect();
+        let now = clock::now();
+        self.view = lease.view;
+        self.synced_view = lease.view;
+        self.renewing = false;
+        self.role = Some(Primary {
+            desired: backups.clone(),
+            // nobody has installed a snapshot in this view yet, so they're lagging until the
+            // one we send them at the next tick gets through
+            synced: HashSet::new(),
+            acked: backups.iter().map(|b| (b.clone(), 0)).collect(),
+            last_ack: backups.iter().map(|b| (b.clone(), now)).collect(),
+            last_snapshot: HashMap::new(),
+            in_sync: backups,
+            next_seq: self.applied + 1,
+            log: BTreeMap::new(),
+            waiting: BTreeMap::new(),
+        });
+        self.lease = Some(lease);
+    }
+
+    fn handle(
+        &mut self,
+        origin: String,
+        req_id: usize,
+        request: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if !self.holds_lease() {
+            return match self.lease.as_ref().map(|lease| lease.primary.clone()) {
+                Some(primary) if primary != self.node && origin == self.node => {
+                    let forward = Payload::Forward {
+                        origin,
+                        req_id,
+                        request: Box::new(request),
+                    };
+                    self.send(&primary, forward, output)
+                }
+                _ => {
+                    let reply = Payload::Error {
+                        code: error::TEMPORARILY_UNAVAILABLE,
+                        text: "no primary right now".to_string(),
+                    };
+                    self.respond(&origin, req_id, reply, output)
+                }
+            };
+        }
+
+        let (write, reply) = match request {
+            Payload::Read { key, .. } => match self.store.get(&key) {
+                Some(&value) => (
+                    None,
+                    Payload::ReadOk {
+                        value: value.into(),
+                    },
+                ),
+                None => (None, not_found(key)),
+            },
+            Payload::Write { key, value } => (Some((key, value)), Payload::WriteOk),
+            Payload::Cas { key, from, to } => match self.store.get(&key) {
+                Some(&current) if current == from => (Some((key, to)), Payload::CasOk),
+                Some(&current) => (
+                    None,
+                    Payload::Error {
+                        code: error::PRECONDITION_FAILED,
+                        text: format!("expected {}, had {}", from, current),
+                    },
+                ),
+                None => (None, not_found(key)),
+            },
+            _ => unreachable!("only client requests are forwarded"),
+        };
+
+        let primary = self.role.as_mut().expect("lease holder is primary");
+        let entry = Entry {
+            seq: primary.next_seq,
+            write,
+        };
+        primary.next_seq += 1;
+        self.applied = entry.seq;
+        if let Some((key, value)) = write {
+            self.store.insert(key, value);
+        }
+        primary.log.insert(entry.seq, entry.clone());
+        primary.waiting.insert(entry.seq, (origin, req_id, reply));
+        let replicas = self.replicas.iter().filter(|r| primary.synced.contains(*r));
+        let backups: Vec<_> = primary.in_sync.iter().chain(replicas).cloned().collect();
+        for backup in backups {
+            let replicate = Payload::Replicate {
+                view: self.view,
+                entry: entry.clone(),
+            };
+            self.send(&backup, replicate, output)?;
+        }
+        self.commit(output)
+    }
+
+    // notes how fresh our copy is, from the progress reports it has caught up with
+    fn catch_up(&mut self) {
+        while let Some(&(seq, sent_ms)) = self.progress.front() {
+            if seq > self.applied {
+                break;
+            }
+            self.fresh_ms = Some(sent_ms);
+            self.progress.pop_front();
+        }
+    }
+
+    // staleness is measured against the primary's clock, so it is only as good as the clocks
+    // agree. a write may also show up here before the primary has acknowledged it.
+    fn follower_read(&self, key: usize, max_staleness_ms: u64) -> Payload {
+        let staleness = self.fresh_ms.map(|at| clock::wall_ms().saturating_sub(at));
+        match staleness {
+            Some(staleness) if staleness <= max_staleness_ms => match self.store.get(&key) {
+                Some(&value) => Payload::ReadOk {
+                    value: value.into(),
+                },
+                None => not_found(key),
+            },
+            _ => Payload::Error {
+                code: error::TEMPORARILY_UNAVAILABLE,
+                text: match staleness {
+                    Some(staleness) => format!(
+                        "{}ms behind the primary, more than {}ms",
+                        staleness, max_staleness_ms
+                    ),
+                    None => "not caught up with the primary yet".to_string(),
+                },
+            },
+        }
+    }
+
+    fn replicated(
+        &mut self,
+        backup: &str,
+        view: u64,
+        seq: u64,
+        snapshot: bool,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(primary) = &mut self.role else {
+            return Ok(());
+        };
+        if view != self.view {
+            return Ok(());
+        }
+        if snapshot {
+            primary.synced.insert(backup.to_string());
+        } else if !primary.synced.contains(backup) {
+            return Ok(());
+        }
+        let acked = primary.acked.entry(backup.to_string()).or_default();
+        *acked = (*acked).max(seq);
+        primary.last_ack.insert(backup.to_string(), clock::now());
+        if *acked >= self.applied
+            && !primary.desired.contains(backup)
+            && !self.replicas.contains(backup)
+        {
+            // caught up from a snapshot, it goes into the lease at the next renewal
+            log::info!("{} is back in sync", backup);
+            primary.desired.insert(backup.to_string());
+        }
+        self.commit(output)
+    }
+
+    // answer every request that has made it to all in-sync backups
+    fn commit(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Some(primary) = &mut self.role else {
+            return Ok(());
+        };
+        let committed = primary
+            .in_sync
+            .iter()
+            .map(|b| primary.acked.get(b).copied().unwrap_or(0))
+            .min()
+            .unwrap_or(self.applied);
+        primary.log.retain(|seq, _| *seq > committed);
+        let mut done = Vec::new();
+        while let Some(entry) = primary.waiting.first_entry() {
+            if *entry.key() > committed {
+                break;
+            }
+            done.push(entry.remove());
+        }
+        for (origin, req_id, reply) in done {
+            self.respond(&origin, req_id, reply, output)?;
+        }
+        Ok(())
+    }
+
+    fn respond(
+        &mut self,
+        origin: &str,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if origin == self.node {
+            return self.reply_client(req_id, reply, output);
+        }
+        let done = Payload::Done {
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.send(origin, done, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        let id = self.next_id();
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")
+    }
+}
+
+fn not_found(key: usize) -> Payload {
+    Payload::Error {
+        code: error::KEY_DOES_NOT_EXIST,
+        text: format!("key {} does not exist", key),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, PrimaryBackupNode, _, _>(())
+}
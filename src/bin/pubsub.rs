@@ -0,0 +1,533 @@
+use anyhow::{Context, Ok};
+use rustengan::shard::{self, Placement};
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+
+pub const VNODES: usize = 16;
+// how often a node pulls from the owners of the topics its subscribers follow, catching up on
+// whatever pushes got lost
+const PULL_INTERVAL: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    // attaches a subscriber to this node: it'll be sent everything published from here on
+    Subscribe {
+        subscriber: String,
+        topic: String,
+    },
+    SubscribeOk,
+    Publish {
+        topic: String,
+        message: serde_json::Value,
+    },
+    PublishOk {
+        offset: u64,
+    },
+    // everything not yet acked in every topic the subscriber follows, by topic and offset
+    Poll {
+        subscriber: String,
+    },
+    PollOk {
+        messages: BTreeMap<String, Vec<(u64, serde_json::Value)>>,
+    },
+    // the subscriber is done with everything up to and including these offsets
+    Ack {
+        subscriber: String,
+        offsets: BTreeMap<String, u64>,
+    },
+    AckOk,
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a publication on its way to the topic's owner, and the offset it got on the way back
+    Forward {
+        origin: String,
+        req_id: usize,
+        topic: String,
+        message: serde_json::Value,
+    },
+    Published {
+        req_id: usize,
+        offset: u64,
+    },
+    // node -> owner: send me the topic from `from` on, or from the end if none. also subscribes
+    // the node to pushes of new publications.
+    Fetch {
+        topic: String,
+        from: Option<u64>,
+    },
+    // `messages` start at offset `from`
+    Feed {
+        topic: String,
+        from: u64,
+        messages: Vec<serde_json::Value>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    // on the topic's owner
+    Published {
+        topic: String,
+        message: serde_json::Value,
+    },
+    // on the node the subscriber is attached to
+    Subscribed {
+        subscriber: String,
+        topic: String,
+        cursor: u64,
+    },
+    Acked {
+        subscriber: String,
+        topic: String,
+        offset: u64,
+    },
+}
+
+// this node's copy of a topic someone else owns, from `base` on
+struct Feed {
+    base: u64,
+    messages: Vec<serde_json::Value>,
+}
+
+impl Feed {
+    fn end(&self) -> u64 {
+        self.base + self.messages.len() as u64
+    }
+}
+
+pub enum InjectedPayload {
+    Pull,
+}
+
+/// Topic-based publish/subscribe. Every topic has an owner, picked by the placement in
+/// `RUSTENGAN_PLACEMENT`, that keeps the topic's log and numbers publications by their offset in
+/// it. Subscribers attach to whichever node they like, and that node follows the topic: the owner
+/// pushes it every new publication, and it pulls from the owner on a timer to fill in whatever
+/// pushes went missing.
+///
+/// Each subscriber has a cursor per topic, past the last offset it acked, and every poll returns
+/// everything after it. Whatever a subscriber hasn't acked it gets again, so delivery is at least
+/// once and in offset order. The owner's logs and the cursors are both written ahead, so neither
+/// a publication that was acknowledged nor a subscriber's place is lost to a crash.
+pub struct PubSubNode {
+    node: String,
+    id: usize,
+    wal: Wal<Record>,
+    placement: Box<dyn Placement>,
+    // topics we own
+    logs: HashMap<String, Vec<serde_json::Value>>,
+    // nodes that follow each topic we own
+    followers: HashMap<String, HashSet<String>>,
+    // topics we follow
+    feeds: HashMap<String, Feed>,
+    cursors: HashMap<String, HashMap<String, u64>>,
+    // subscribes waiting to hear where the topic ends
+    waiting: HashMap<String, Vec<(String, Option<usize>, String)>>,
+    // publications from our own clients forwarded to an owner
+    pending: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for PubSubNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let placement = shard::from_env(&init.node_ids, VNODES)?;
+        let (wal, records) =
+            Wal::open(wal::data_dir().join(format!("{}.pubsub.wal", init.node_id)))
+                .context("open pubsub wal")?;
+        let mut node = Self {
+            node: init.node_id,
+            id: 1,
+            wal,
+            placement,
+            logs: HashMap::new(),
+            followers: HashMap::new(),
+            feeds: HashMap::new(),
+            cursors: HashMap::new(),
+            waiting: HashMap::new(),
+            pending: HashMap::new(),
+        };
+        for record in records {
+            node.apply(record);
+        }
+        ticks::every(PULL_INTERVAL, tx, || Event::Injected(InjectedPayload::Pull));
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Pull) => self.pull(output)?,
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Subscribe { subscriber, topic } => {
+                        let Some(end) = self.end(&topic) else {
+                            // find out where the topic ends first, so the subscriber gets
+                            // everything published after it subscribed
+                            let msg_id = reply.body.in_reply_to;
+                            let waiting = self.waiting.entry(topic.clone()).or_default();
+                            waiting.push((src, msg_id, subscriber));
+                            if waiting.len() == 1 {
+                                self.fetch(&topic, None, output)?;
+                            }
+                            return Ok(());
+                        };
+                        self.subscribe(subscriber, topic, end)?;
+                        reply.body.payload = Payload::SubscribeOk;
+                        reply.send(&mut *output).context("reply to subscribe")?;
+                    }
+                    Payload::Publish { topic, message } => {
+                        let owner = self.owner(&topic);
+                        if owner == self.node {
+                            let offset = self.publish(topic, message, output)?;
+                            reply.body.payload = Payload::PublishOk { offset };
+                            reply.send(&mut *output).context("reply to publish")?;
+                        } else {
+                            let req_id = self.id;
+                            self.id += 1;
+                            self.pending.insert(req_id, (src, reply.body.in_reply_to));
+                            let forward = Payload::Forward {
+                                origin: self.node.clone(),
+                                req_id,
+                                topic,
+                                message,
+                            };
+                            self.send(&owner, forward, output)?;
+                        }
+                    }
+                    Payload::Forward {
+                        origin,
+                        req_id,
+                        topic,
+                        message,
+                    } => {
+                        let offset = self.publish(topic, message, output)?;
+                        self.send(&origin, Payload::Published { req_id, offset }, output)?;
+                    }
+                    Payload::Published { req_id, offset } => {
+                        self.reply_client(req_id, Payload::PublishOk { offset }, output)?;
+                    }
+                    Payload::Poll { subscriber } => {
+                        reply.body.payload = Payload::PollOk {
+                            messages: self.poll(&subscriber),
+                        };
+                        reply.send(&mut *output).context("reply to poll")?;
+                    }
+                    Payload::Ack {
+                        subscriber,
+                        offsets,
+                    } => {
+                        for (topic, offset) in offsets {
+                            let cursor = self
+                                .cursors
+                                .get(&subscriber)
+                                .and_then(|c| c.get(&topic))
+                                .copied();
+                            if cursor.is_some_and(|c| offset >= c) {
+                                self.log(Record::Acked {
+                                    subscriber: subscriber.clone(),
+                                    topic,
+                                    offset,
+                                })?;
+                            }
+                        }
+                        reply.body.payload = Payload::AckOk;
+                        reply.send(&mut *output).context("reply to ack")?;
+                    }
+                    Payload::Fetch { topic, from } => {
+                        self.followers
+                            .entry(topic.clone())
+                            .or_default()
+                            .insert(src.clone());
+                        let log = self.logs.get(&topic).map_or(&[][..], |l| l.as_slice());
+                        let from = from.unwrap_or(log.len() as u64).min(log.len() as u64);
+                        let feed = Payload::Feed {
+                            messages: log[from as usize..].to_vec(),
+                            topic,
+                            from,
+                        };
+                        self.send(&src, feed, output)?;
+                    }
+                    Payload::Feed {
+                        topic,
+                        from,
+                        messages,
+                    } => self.feed(topic, from, messages, output)?,
+                    Payload::SubscribeOk
+                    | Payload::PublishOk { .. }
+                    | Payload::PollOk { .. }
+                    | Payload::AckOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl PubSubNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        self.send_to_client(client, msg_id, reply, output)
+    }
+
+    fn send_to_client(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn owner(&self, topic: &str) -> String {
+        self.placement
+            .owner(shard::hash(&topic))
+            .expect("every node is a member")
+            .to_string()
+    }
+
+    // the offset the next publication to a topic will get, as far as we know, or none if we
+    // don't follow the topic
+    fn end(&self, topic: &str) -> Option<u64> {
+        if self.owner(topic) == self.node {
+            return Some(self.logs.get(topic).map_or(0, |l| l.len() as u64));
+        }
+        self.feeds.get(topic).map(Feed::end)
+    }
+
+    fn log(&mut self, record: Record) -> anyhow::Result<()> {
+        self.wal.append(&record).context("log pubsub change")?;
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Published { topic, message } => {
+                self.logs.entry(topic).or_default().push(message);
+            }
+            Record::Subscribed {
+                subscriber,
+                topic,
+                cursor,
+            } => {
+                self.cursors
+                    .entry(subscriber)
+                    .or_default()
+                    .entry(topic)
+                    .or_insert(cursor);
+            }
+            Record::Acked {
+                subscriber,
+                topic,
+                offset,
+            } => {
+                if let Some(cursor) = self
+                    .cursors
+                    .get_mut(&subscriber)
+                    .and_then(|c| c.get_mut(&topic))
+                {
+                    *cursor = (*cursor).max(offset + 1);
+                }
+            }
+        }
+    }
+
+    fn subscribe(&mut self, subscriber: String, topic: String, cursor: u64) -> anyhow::Result<()> {
+        if self
+            .cursors
+            .get(&subscriber)
+            .is_some_and(|c| c.contains_key(&topic))
+        {
+            // already subscribed, keep the cursor we have
+            return Ok(());
+        }
+        self.log(Record::Subscribed {
+            subscriber,
+            topic,
+            cursor,
+        })
+    }
+
+    // only ever runs on the topic's owner
+    fn publish(
+        &mut self,
+        topic: String,
+        message: serde_json::Value,
+        output: &mut Output,
+    ) -> anyhow::Result<u64> {
+        let offset = self.logs.get(&topic).map_or(0, |l| l.len() as u64);
+        self.log(Record::Published {
+            topic: topic.clone(),
+            message: message.clone(),
+        })?;
+        for follower in self.followers.get(&topic).into_iter().flatten() {
+            let feed = Payload::Feed {
+                topic: topic.clone(),
+                from: offset,
+                messages: vec![message.clone()],
+            };
+            self.send(follower, feed, output)?;
+        }
+        Ok(offset)
+    }
+
+    fn fetch(&self, topic: &str, from: Option<u64>, output: &mut Output) -> anyhow::Result<()> {
+        let fetch = Payload::Fetch {
+            topic: topic.to_string(),
+            from,
+        };
+        self.send(&self.owner(topic), fetch, output)
+    }
+
+    fn feed(
+        &mut self,
+        topic: String,
+        from: u64,
+        messages: Vec<serde_json::Value>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let oldest = self.oldest(&topic);
+        match self.feeds.get_mut(&topic) {
+            Some(feed) => {
+                // a push or pull overlapping what we have, or behind it. one starting past our
+                // end leaves a gap, so wait for the next pull instead.
+                if from <= feed.end() {
+                    let skip = (feed.end() - from) as usize;
+                    feed.messages.extend(messages.into_iter().skip(skip));
+                }
+            }
+            // a push that beats our pull after a crash starts past what our subscribers haven't
+            // acked, and starting our copy from it would skip the rest, so wait for the pull
+            None if oldest.is_some_and(|oldest| from > oldest) => {}
+            None => {
+                let feed = Feed {
+                    base: from,
+                    messages,
+                };
+                self.feeds.insert(topic.clone(), feed);
+            }
+        }
+        for (client, msg_id, subscriber) in self.waiting.remove(&topic).unwrap_or_default() {
+            self.subscribe(subscriber, topic.clone(), from)?;
+            self.send_to_client(client, msg_id, Payload::SubscribeOk, output)?;
+        }
+        Ok(())
+    }
+
+    fn poll(&self, subscriber: &str) -> BTreeMap<String, Vec<(u64, serde_json::Value)>> {
+        let mut messages = BTreeMap::new();
+        for (topic, &cursor) in self.cursors.get(subscriber).into_iter().flatten() {
+            let (base, log) = if self.owner(topic) == self.node {
+                (0, self.logs.get(topic).map_or(&[][..], |l| l.as_slice()))
+            } else if let Some(feed) = self.feeds.get(topic) {
+                (feed.base, feed.messages.as_slice())
+            } else {
+                // we lost our copy in a crash and haven't caught up yet
+                continue;
+            };
+            let unacked: Vec<_> = (cursor.max(base)..)
+                .zip(log.iter().skip(cursor.saturating_sub(base) as usize))
+                .map(|(offset, message)| (offset, message.clone()))
+                .collect();
+            if !unacked.is_empty() {
+                messages.insert(topic.clone(), unacked);
+            }
+        }
+        messages
+    }
+
+    // the oldest offset of a topic any of our subscribers hasn't acked, or none if none follow it
+    fn oldest(&self, topic: &str) -> Option<u64> {
+        self.cursors
+            .values()
+            .filter_map(|cursors| cursors.get(topic))
+            .copied()
+            .min()
+    }
+
+    fn pull(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let mut topics: HashMap<&str, u64> = HashMap::new();
+        for cursors in self.cursors.values() {
+            for (topic, &cursor) in cursors {
+                let from = topics.entry(topic).or_insert(cursor);
+                *from = (*from).min(cursor);
+            }
+        }
+        for (topic, from) in topics {
+            if self.owner(topic) == self.node {
+                continue;
+            }
+            // carry on from the end of our copy, or rebuild it from the oldest unacked offset
+            let from = self.feeds.get(topic).map_or(from, Feed::end);
+            self.fetch(topic, Some(from), output)?;
+        }
+        for topic in self.waiting.keys() {
+            self.fetch(topic, None, output)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, PubSubNode, _, _>(())
+}
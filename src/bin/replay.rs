@@ -0,0 +1,106 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+const USAGE: &str =
+    "usage: replay [--speed <factor>] [--stop-at <n>] <capture> -- <node binary> [args...]";
+
+// a line of a session log, see rustengan::session
+#[derive(Deserialize)]
+struct Recorded<'a> {
+    time: u64,
+    direction: &'a str,
+    #[serde(borrow)]
+    message: &'a RawValue,
+}
+
+// one line to feed the node, and when it first arrived, if the capture says
+struct Input {
+    time: Option<u64>,
+    line: String,
+}
+
+fn load(path: &str) -> anyhow::Result<Vec<Input>> {
+    let file = std::fs::File::open(path).with_context(|| format!("open {}", path))?;
+    let mut inputs = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line.with_context(|| format!("read {}", path))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // a session log has the node's output in it too, and only what it received goes back in
+        match serde_json::from_str::<Recorded>(&line) {
+            Ok(recorded) if recorded.direction == "received" => inputs.push(Input {
+                time: Some(recorded.time),
+                line: recorded.message.get().to_string(),
+            }),
+            Ok(_) => {}
+            Err(_) => inputs.push(Input { time: None, line }),
+        }
+    }
+    Ok(inputs)
+}
+
+// feeds a node binary what was captured of its stdin, either as the lines it read or as the
+// session log RUSTENGAN_SESSION_LOG had it write, and then closes its stdin:
+//
+//     replay --speed 10 --stop-at 200 /tmp/session/n0.jsonl -- target/debug/broadcast
+//
+// a session log is replayed with the gaps between messages it recorded, divided by --speed
+// (`inf` for no gaps at all); plain lines go in as fast as the node takes them. --stop-at feeds
+// only the first n messages. The node's stdout and stderr are ours, and so is its exit status once
+// it exits.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut speed = 1.0;
+    let mut stop_at = usize::MAX;
+    let capture = loop {
+        match args.next().as_deref() {
+            Some("--speed") => {
+                let factor = args.next().context(USAGE)?;
+                speed = factor
+                    .parse::<f64>()
+                    .with_context(|| format!("invalid --speed {:?}", factor))?;
+                anyhow::ensure!(speed > 0.0, "--speed has to be more than 0");
+            }
+            Some("--stop-at") => {
+                let n = args.next().context(USAGE)?;
+                stop_at = n
+                    .parse()
+                    .with_context(|| format!("invalid --stop-at {:?}", n))?;
+            }
+            Some(capture) => break capture.to_string(),
+            None => anyhow::bail!(USAGE),
+        }
+    };
+    anyhow::ensure!(args.next().as_deref() == Some("--"), USAGE);
+    let binary = args.next().context(USAGE)?;
+
+    let inputs = load(&capture)?;
+    let mut node = Command::new(&binary)
+        .args(args)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("start {}", binary))?;
+    let mut stdin = node.stdin.take().expect("piped");
+    let mut last = None;
+    for input in inputs.iter().take(stop_at) {
+        if let (Some(last), Some(time)) = (last, input.time) {
+            let gap = Duration::from_nanos(time.saturating_sub(last));
+            std::thread::sleep(gap.div_f64(speed));
+        }
+        last = input.time.or(last);
+        if writeln!(stdin, "{}", input.line).is_err() {
+            // the node is gone, and its exit status says why
+            break;
+        }
+    }
+    drop(stdin);
+
+    let status = node.wait().context("wait for the node")?;
+    std::process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,122 @@
+use anyhow::{Context, Ok};
+use rustengan::crdt::Rga;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    InsertAt { index: usize, value: String },
+    InsertAtOk,
+    DeleteAt { index: usize },
+    DeleteAtOk { value: String },
+    Read,
+    ReadOk { value: Vec<String> },
+    Error { code: usize, text: String },
+    Gossip { state: Rga<String> },
+}
+
+enum InjectedPayload {
+    Gossip,
+}
+
+struct RgaNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    sequence: Rga<String>,
+}
+
+impl Node<(), Payload, InjectedPayload> for RgaNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(Duration::from_millis(300), tx, || {
+            Event::Injected(InjectedPayload::Gossip)
+        });
+        Ok(Self {
+            id: 1,
+            node: init.node_id,
+            nodes: init.node_ids,
+            sequence: Rga::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Gossip) => {
+                for n in self.nodes.iter().filter(|n| **n != self.node) {
+                    Message {
+                        src: self.node.clone(),
+                        dst: n.clone(),
+                        body: Body {
+                            id: None,
+                            in_reply_to: None,
+                            correlation_id: None,
+                            payload: Payload::Gossip {
+                                state: self.sequence.clone(),
+                            },
+                        },
+                    }
+                    .send(&mut *output)
+                    .with_context(|| format!("gossip to {}", n))?;
+                }
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Gossip { state } => self.sequence.merge(&state),
+                    Payload::InsertAt { index, value } => {
+                        reply.body.payload = match self.sequence.insert(&self.node, index, value) {
+                            Some(_) => Payload::InsertAtOk,
+                            None => out_of_range(index, self.sequence.len()),
+                        };
+                        reply.send(&mut *output).context("reply to insert_at")?;
+                    }
+                    Payload::DeleteAt { index } => {
+                        reply.body.payload = match self.sequence.delete(index) {
+                            Some(value) => Payload::DeleteAtOk { value },
+                            None => out_of_range(index, self.sequence.len()),
+                        };
+                        reply.send(&mut *output).context("reply to delete_at")?;
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            value: self.sequence.values(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::InsertAtOk
+                    | Payload::DeleteAtOk { .. }
+                    | Payload::ReadOk { .. }
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn out_of_range(index: usize, len: usize) -> Payload {
+    Payload::Error {
+        code: error::MALFORMED_REQUEST,
+        text: format!("index {} is out of range for a sequence of {}", index, len),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, RgaNode, _, _>(())
+}
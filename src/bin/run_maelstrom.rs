@@ -0,0 +1,417 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use rustengan::config;
+use serde_json::Value;
+
+#[path = "run_maelstrom/edn.rs"]
+mod edn;
+
+const USAGE: &str = "usage: run_maelstrom <test>...|all|list [--maelstrom <dir>] [--time-limit <seconds>] [-- <maelstrom args>...]";
+
+// a Maelstrom test of one of the crate's binaries: the workload it serves, and what to run it
+// with, as its challenge (or the node's own docs) has it
+struct Test {
+    name: &'static str,
+    bin: &'static str,
+    workload: &'static str,
+    nodes: usize,
+    time_limit: u64,
+    // on top of --workload, --node-count and --time-limit
+    args: &'static [&'static str],
+}
+
+const TESTS: &[Test] = &[
+    Test {
+        name: "echo",
+        bin: "echo",
+        workload: "echo",
+        nodes: 1,
+        time_limit: 10,
+        args: &[],
+    },
+    Test {
+        name: "unique-ids",
+        bin: "unique_ids",
+        workload: "unique-ids",
+        nodes: 3,
+        time_limit: 30,
+        args: &[
+            "--rate",
+            "1000",
+            "--availability",
+            "total",
+            "--nemesis",
+            "partition",
+        ],
+    },
+    Test {
+        name: "broadcast",
+        bin: "broadcast",
+        workload: "broadcast",
+        nodes: 5,
+        time_limit: 20,
+        args: &["--rate", "10", "--nemesis", "partition"],
+    },
+    Test {
+        name: "broadcast-efficiency",
+        bin: "broadcast",
+        workload: "broadcast",
+        nodes: 25,
+        time_limit: 20,
+        args: &["--rate", "100", "--latency", "100"],
+    },
+    Test {
+        name: "causal-broadcast",
+        bin: "causal_broadcast",
+        workload: "broadcast",
+        nodes: 5,
+        time_limit: 20,
+        args: &["--rate", "10"],
+    },
+    Test {
+        name: "total-order-broadcast",
+        bin: "total_order_broadcast",
+        workload: "broadcast",
+        nodes: 5,
+        time_limit: 20,
+        args: &["--rate", "10"],
+    },
+    Test {
+        name: "or-set",
+        bin: "or_set",
+        workload: "g-set",
+        nodes: 5,
+        time_limit: 20,
+        args: &["--rate", "10", "--nemesis", "partition"],
+    },
+    // quorum registers stay linearizable through partitions, if not available
+    Test {
+        name: "abd",
+        bin: "abd",
+        workload: "lin-kv",
+        nodes: 3,
+        time_limit: 20,
+        args: &[
+            "--rate",
+            "100",
+            "--concurrency",
+            "2n",
+            "--nemesis",
+            "partition",
+        ],
+    },
+    Test {
+        name: "caspaxos",
+        bin: "caspaxos",
+        workload: "lin-kv",
+        nodes: 3,
+        time_limit: 20,
+        args: &[
+            "--rate",
+            "100",
+            "--concurrency",
+            "2n",
+            "--nemesis",
+            "partition",
+        ],
+    },
+    Test {
+        name: "lease-kv",
+        bin: "lease_kv",
+        workload: "lin-kv",
+        nodes: 5,
+        time_limit: 20,
+        args: &[
+            "--rate",
+            "100",
+            "--concurrency",
+            "2n",
+            "--nemesis",
+            "partition",
+        ],
+    },
+    // chain replication assumes fail-stop, which a partition isn't
+    Test {
+        name: "chain",
+        bin: "chain",
+        workload: "lin-kv",
+        nodes: 3,
+        time_limit: 20,
+        args: &["--rate", "100", "--concurrency", "2n"],
+    },
+    Test {
+        name: "primary-backup",
+        bin: "primary_backup",
+        workload: "lin-kv",
+        nodes: 3,
+        time_limit: 20,
+        args: &["--rate", "100", "--concurrency", "2n"],
+    },
+    Test {
+        name: "wal-shipping",
+        bin: "wal_shipping",
+        workload: "lin-kv",
+        nodes: 3,
+        time_limit: 20,
+        args: &["--rate", "100", "--concurrency", "2n"],
+    },
+    Test {
+        name: "sharded-kv",
+        bin: "sharded_kv",
+        workload: "lin-kv",
+        nodes: 3,
+        time_limit: 20,
+        args: &["--rate", "100", "--concurrency", "2n"],
+    },
+    // 2pl by default, and TXN_ISOLATION passes through to the nodes for si or ssi
+    Test {
+        name: "txn",
+        bin: "txn",
+        workload: "txn-rw-register",
+        nodes: 2,
+        time_limit: 20,
+        args: &[
+            "--rate",
+            "1000",
+            "--concurrency",
+            "2n",
+            "--consistency-models",
+            "serializable",
+        ],
+    },
+    Test {
+        name: "calvin",
+        bin: "calvin",
+        workload: "txn-rw-register",
+        nodes: 3,
+        time_limit: 20,
+        args: &[
+            "--rate",
+            "100",
+            "--concurrency",
+            "2n",
+            "--consistency-models",
+            "serializable",
+        ],
+    },
+    Test {
+        name: "percolator",
+        bin: "percolator",
+        workload: "txn-rw-register",
+        nodes: 3,
+        time_limit: 20,
+        args: &[
+            "--rate",
+            "100",
+            "--concurrency",
+            "2n",
+            "--consistency-models",
+            "snapshot-isolation",
+        ],
+    },
+];
+
+// what a test came to, from its results.edn
+struct Outcome {
+    name: &'static str,
+    valid: bool,
+    // the checkers that found something wrong
+    invalid: Vec<String>,
+    ok: u64,
+    ops: u64,
+    msgs_per_op: Option<f64>,
+    results: PathBuf,
+}
+
+// builds the binaries the tests given need, runs each through Maelstrom in turn, and sums up
+// which passed:
+//
+//     run_maelstrom broadcast broadcast-efficiency --maelstrom ~/maelstrom
+//
+// Maelstrom is the directory it unpacks to (RUSTENGAN_MAELSTROM, or ./maelstrom, unless
+// --maelstrom says otherwise), run with java directly rather than through its launcher script,
+// and its results go where it always puts them, under ./store. --time-limit shortens (or
+// lengthens) every test, and whatever follows -- goes to every test as it is, after the test's
+// own flags, so it can override them. Only a test Maelstrom finds invalid fails the run; one
+// that Maelstrom couldn't finish is reported as such.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut names = Vec::new();
+    let mut maelstrom = config::var_or("RUSTENGAN_MAELSTROM", "maelstrom".to_string())?;
+    let mut time_limit = None;
+    let mut extra = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--maelstrom" => maelstrom = args.next().context(USAGE)?,
+            "--time-limit" => {
+                let value = args.next().context(USAGE)?;
+                let seconds: u64 = value
+                    .parse()
+                    .with_context(|| format!("invalid --time-limit {:?}", value))?;
+                time_limit = Some(seconds);
+            }
+            "--" => extra.extend(args.by_ref()),
+            "list" => {
+                for test in TESTS {
+                    println!(
+                        "{:<24} {:<22} {:<16} {} nodes",
+                        test.name, test.bin, test.workload, test.nodes
+                    );
+                }
+                return Ok(());
+            }
+            "all" => names.extend(TESTS.iter().map(|test| test.name.to_string())),
+            _ if arg.starts_with('-') => anyhow::bail!(USAGE),
+            _ => names.push(arg),
+        }
+    }
+    anyhow::ensure!(!names.is_empty(), USAGE);
+    let tests = names
+        .iter()
+        .map(|name| {
+            TESTS
+                .iter()
+                .find(|test| test.name == name)
+                .with_context(|| format!("no test called {:?}, see run_maelstrom list", name))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let jar = jar(Path::new(&maelstrom))?;
+    let bins = build(&tests)?;
+
+    let mut outcomes = Vec::new();
+    for test in tests {
+        eprintln!("running {}", test.name);
+        let time_limit = time_limit.unwrap_or(test.time_limit);
+        let started = SystemTime::now();
+        let status = Command::new("java")
+            .arg("-Djava.awt.headless=true")
+            .arg("-jar")
+            .arg(&jar)
+            .arg("test")
+            .arg("--workload")
+            .arg(test.workload)
+            .arg("--bin")
+            .arg(bins.join(test.bin))
+            .arg("--node-count")
+            .arg(test.nodes.to_string())
+            .arg("--time-limit")
+            .arg(time_limit.to_string())
+            .args(test.args)
+            .args(&extra)
+            .status()
+            .context("run java")?;
+        match outcome(test.name, started) {
+            Ok(outcome) => outcomes.push(outcome),
+            Err(e) => {
+                eprintln!("{} didn't finish ({}): {:#}", test.name, status, e);
+                outcomes.push(Outcome {
+                    name: test.name,
+                    valid: false,
+                    invalid: vec!["maelstrom".to_string()],
+                    ok: 0,
+                    ops: 0,
+                    msgs_per_op: None,
+                    results: PathBuf::new(),
+                });
+            }
+        }
+    }
+
+    println!();
+    for outcome in &outcomes {
+        let verdict = if outcome.valid { "pass" } else { "FAIL" };
+        let msgs_per_op = outcome
+            .msgs_per_op
+            .map(|m| format!(", {:.1} msgs/op", m))
+            .unwrap_or_default();
+        let invalid = match outcome.invalid.as_slice() {
+            [] => String::new(),
+            invalid => format!(", invalid: {}", invalid.join(" ")),
+        };
+        println!(
+            "{:<24} {} {}/{} ok{}{}  {}",
+            outcome.name,
+            verdict,
+            outcome.ok,
+            outcome.ops,
+            msgs_per_op,
+            invalid,
+            outcome.results.display()
+        );
+    }
+    let failed = outcomes.iter().filter(|outcome| !outcome.valid).count();
+    anyhow::ensure!(failed == 0, "{} of {} failed", failed, outcomes.len());
+    Ok(())
+}
+
+// Maelstrom's jar: `maelstrom` is either the jar itself or the directory Maelstrom unpacks to
+fn jar(maelstrom: &Path) -> anyhow::Result<PathBuf> {
+    let jar = if maelstrom.extension().is_some_and(|ext| ext == "jar") {
+        maelstrom.to_path_buf()
+    } else {
+        maelstrom.join("lib").join("maelstrom.jar")
+    };
+    anyhow::ensure!(
+        jar.is_file(),
+        "no maelstrom jar at {}: point --maelstrom (or RUSTENGAN_MAELSTROM) at maelstrom's directory",
+        jar.display()
+    );
+    Ok(jar)
+}
+
+// builds every binary the tests run, in release, and returns the directory they're in
+fn build(tests: &[&Test]) -> anyhow::Result<PathBuf> {
+    let cargo = option_env!("CARGO").unwrap_or("cargo");
+    let mut build = Command::new(cargo);
+    build
+        .args(["build", "--release", "--manifest-path"])
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"));
+    for test in tests {
+        build.args(["--bin", test.bin]);
+    }
+    let status = build.status().context("run cargo")?;
+    anyhow::ensure!(status.success(), "cargo build failed: {}", status);
+    // alongside this binary's own profile directory, wherever the target directory is
+    let exe = std::env::current_exe().context("find run_maelstrom's own binary")?;
+    let target = exe
+        .parent()
+        .and_then(Path::parent)
+        .context("run_maelstrom isn't in a target directory")?;
+    Ok(target.join("release"))
+}
+
+// what Maelstrom made of the test it's just run, from the results of its latest run, as long
+// as that run is this one and not one from before it that left its results behind
+fn outcome(name: &'static str, started: SystemTime) -> anyhow::Result<Outcome> {
+    let latest = Path::new("store").join("latest");
+    let results = latest.join("results.edn");
+    let written = std::fs::metadata(&results)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("no results at {}", results.display()))?;
+    anyhow::ensure!(written >= started, "no results since it started");
+    let edn =
+        std::fs::read_to_string(&results).with_context(|| format!("read {}", results.display()))?;
+    let results = std::fs::canonicalize(&latest).unwrap_or(latest);
+    let summary = edn::parse(&edn).with_context(|| format!("parse {}", results.display()))?;
+    let valid = |v: &Value| v.get("valid?").cloned().unwrap_or(Value::Null);
+    let invalid = summary
+        .as_object()
+        .context("results aren't a map")?
+        .iter()
+        .filter(|(_, checker)| checker.is_object() && valid(checker) != Value::Bool(true))
+        .map(|(name, _)| name.clone())
+        .collect();
+    let count = |key| summary["stats"][key].as_u64().unwrap_or(0);
+    Ok(Outcome {
+        name,
+        valid: valid(&summary) == Value::Bool(true),
+        invalid,
+        ok: count("ok-count"),
+        ops: count("count"),
+        msgs_per_op: summary["net"]["servers"]["msgs-per-op"].as_f64(),
+        results,
+    })
+}
@@ -0,0 +1,168 @@
+use anyhow::Context;
+use serde_json::{Map, Number, Value};
+
+// reads the edn Maelstrom writes its results in into json, as much of it as a summary needs:
+// keywords and symbols become strings without their colon, sets and lists become arrays, map keys
+// that aren't strings become the edn they were written as, and tagged values (records, mostly)
+// become whatever they tag. Ratios become floats. Characters aren't handled, since results don't
+// have any.
+pub(crate) fn parse(edn: &str) -> anyhow::Result<Value> {
+    let mut reader = Reader { edn, at: 0 };
+    let value = reader.value()?;
+    reader.skip();
+    anyhow::ensure!(reader.at == edn.len(), "trailing edn at {}", reader.at);
+    Ok(value)
+}
+
+struct Reader<'a> {
+    edn: &'a str,
+    at: usize,
+}
+
+impl Reader<'_> {
+    fn rest(&self) -> &str {
+        &self.edn[self.at..]
+    }
+
+    // past whitespace, commas and comments
+    fn skip(&mut self) {
+        loop {
+            let rest = self.rest();
+            let trimmed = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+            let mut skipped = rest.len() - trimmed.len();
+            let comment = trimmed.starts_with(';');
+            if comment {
+                skipped += trimmed.find('\n').unwrap_or(trimmed.len());
+            }
+            self.at += skipped;
+            if !comment {
+                return;
+            }
+        }
+    }
+
+    fn value(&mut self) -> anyhow::Result<Value> {
+        self.skip();
+        let rest = self.rest();
+        let next = rest
+            .chars()
+            .next()
+            .with_context(|| format!("edn ends early at {}", self.at))?;
+        match next {
+            '{' => {
+                self.at += 1;
+                let mut map = Map::new();
+                while !self.close('}')? {
+                    let key = match self.value()? {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    map.insert(key, self.value()?);
+                }
+                Ok(Value::Object(map))
+            }
+            '[' | '(' => {
+                self.at += 1;
+                self.elements(if next == '[' { ']' } else { ')' })
+            }
+            '#' if rest.starts_with("#{") => {
+                self.at += 2;
+                self.elements('}')
+            }
+            '#' if rest.starts_with("#_") => {
+                // a form that's commented out
+                self.at += 2;
+                self.value()?;
+                self.value()
+            }
+            '#' => {
+                self.at += 1;
+                self.token();
+                self.value()
+            }
+            '"' => self.string(),
+            _ => {
+                let at = self.at;
+                let token = self.token();
+                anyhow::ensure!(!token.is_empty(), "unexpected {:?} at {}", next, at);
+                Ok(atom(token))
+            }
+        }
+    }
+
+    fn elements(&mut self, close: char) -> anyhow::Result<Value> {
+        let mut elements = Vec::new();
+        while !self.close(close)? {
+            elements.push(self.value()?);
+        }
+        Ok(Value::Array(elements))
+    }
+
+    // whether the collection closes here, stepping past the close if it does
+    fn close(&mut self, close: char) -> anyhow::Result<bool> {
+        self.skip();
+        match self.rest().chars().next() {
+            Some(c) if c == close => {
+                self.at += 1;
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => anyhow::bail!("edn ends before its {:?}", close),
+        }
+    }
+
+    fn token(&mut self) -> &str {
+        let rest = &self.edn[self.at..];
+        let end = rest
+            .find(|c: char| c.is_whitespace() || ",{}[]()\";".contains(c))
+            .unwrap_or(rest.len());
+        self.at += end;
+        &rest[..end]
+    }
+
+    fn string(&mut self) -> anyhow::Result<Value> {
+        let start = self.at;
+        let mut chars = self.rest().char_indices().skip(1);
+        let mut string = String::new();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.at += i + 1;
+                    return Ok(Value::String(string));
+                }
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => string.push('\n'),
+                    Some((_, 't')) => string.push('\t'),
+                    Some((_, 'r')) => string.push('\r'),
+                    Some((_, c)) => string.push(c),
+                    None => break,
+                },
+                c => string.push(c),
+            }
+        }
+        anyhow::bail!("string at {} never ends", start)
+    }
+}
+
+fn atom(token: &str) -> Value {
+    match token {
+        "nil" => return Value::Null,
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(n) = token.trim_end_matches('N').parse::<i64>() {
+        return Value::Number(n.into());
+    }
+    let number = match token.split_once('/') {
+        Some((p, q)) => p
+            .parse::<f64>()
+            .and_then(|p| Ok(p / q.parse::<f64>()?))
+            .ok(),
+        None => token.trim_end_matches('M').parse::<f64>().ok(),
+    };
+    match number.and_then(Number::from_f64) {
+        Some(n) => Value::Number(n),
+        None => Value::String(token.trim_start_matches(':').to_string()),
+    }
+}
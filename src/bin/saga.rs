@@ -0,0 +1,635 @@
+use anyhow::{Context, Ok};
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const TICK: Duration = Duration::from_millis(100);
+// how long to wait for an answer before sending a step again
+const RETRY_AFTER: Duration = Duration::from_millis(300);
+// how long a step gets before the saga gives up on it and compensates instead
+const STEP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// One step of a saga: add `delta` to the counter `key` held by `node`. A step that would take
+/// the counter below zero fails (think taking stock out of inventory), and its compensation adds
+/// the delta back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Step {
+    pub node: String,
+    pub key: String,
+    pub delta: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaState {
+    Running,
+    Compensating,
+    Completed,
+    Aborted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Begin {
+        steps: Vec<Step>,
+    },
+    BeginOk {
+        saga_id: String,
+    },
+    Status {
+        saga_id: String,
+    },
+    StatusOk {
+        state: SagaState,
+        // steps that went through, and how many of those have been compensated since
+        completed: usize,
+        compensated: usize,
+    },
+    // one of this node's counters
+    Read {
+        key: String,
+    },
+    ReadOk {
+        value: i64,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // orchestrator -> participant
+    Invoke {
+        saga_id: String,
+        step: usize,
+        key: String,
+        delta: i64,
+    },
+    Invoked {
+        saga_id: String,
+        step: usize,
+        ok: bool,
+    },
+    Compensate {
+        saga_id: String,
+        step: usize,
+        key: String,
+        delta: i64,
+    },
+    Compensated {
+        saga_id: String,
+        step: usize,
+    },
+    // a status request on its way to the saga's orchestrator, and the answer on its way back
+    Forward {
+        origin: String,
+        req_id: usize,
+        request: Box<Payload>,
+    },
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+}
+
+// everything that moves a saga or a participant forward, logged before anyone hears about it.
+// orchestrators only log the first four and participants the rest, but a node is both.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Begun {
+        saga_id: String,
+        steps: Vec<Step>,
+    },
+    StepDone {
+        saga_id: String,
+    },
+    // the saga is rolling back, starting from the step below `undo`
+    StepFailed {
+        saga_id: String,
+        undo: usize,
+    },
+    StepCompensated {
+        saga_id: String,
+    },
+    Applied {
+        saga_id: String,
+        step: usize,
+        key: String,
+        delta: i64,
+    },
+    Refused {
+        saga_id: String,
+        step: usize,
+    },
+    // also logged for a compensation that overtook its step, so the step is refused when it
+    // finally shows up
+    Undone {
+        saga_id: String,
+        step: usize,
+        key: String,
+        delta: i64,
+    },
+}
+
+struct Saga {
+    steps: Vec<Step>,
+    state: SagaState,
+    // steps that have gone through
+    done: usize,
+    // steps still to compensate while compensating, counting down
+    undo: usize,
+    // when the step in flight was first sent, and last sent
+    step_started: Instant,
+    last_sent: Instant,
+}
+
+// how a participant left a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StepOutcome {
+    Applied,
+    Refused,
+    Undone,
+}
+
+/// Runs sagas: multi-step workflows across nodes without a distributed transaction. The node a
+/// saga begins on orchestrates it, invoking one step at a time on whichever node owns it. If a
+/// step fails, or its node doesn't answer in time, the orchestrator runs the compensations of
+/// every step before it (the timed out one included, it may have gone through) in reverse. There
+/// is no isolation: other sagas see the effects of a saga's steps before it completes, and may
+/// see them undone later.
+///
+/// Both sides log their progress before acting on it, so a restarted orchestrator picks its
+/// sagas up where it left off and a restarted participant still knows which steps it applied.
+/// Every message is retried until answered and participants apply each step and each
+/// compensation at most once, however often it arrives.
+pub struct SagaNode {
+    node: String,
+    id: usize,
+    wal: Wal<Record>,
+    // sagas this node orchestrates
+    sagas: HashMap<String, Saga>,
+    counters: HashMap<String, i64>,
+    // the fate of every step this node has seen as a participant
+    steps: HashMap<(String, usize), StepOutcome>,
+    // status requests from our own clients forwarded to an orchestrator
+    pending: HashMap<usize, (String, Option<usize>)>,
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+impl Node<(), Payload, InjectedPayload> for SagaNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let (wal, records) = Wal::open(wal::data_dir().join(format!("{}.saga.wal", init.node_id)))
+            .context("open saga wal")?;
+        let mut node = Self {
+            node: init.node_id,
+            id: 1,
+            wal,
+            sagas: HashMap::new(),
+            counters: HashMap::new(),
+            steps: HashMap::new(),
+            pending: HashMap::new(),
+        };
+        for record in records {
+            node.apply(record);
+        }
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => self.tick(output)?,
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Begin { steps } => {
+                        let saga_id = format!("{}-{}", self.node, rng::ulid());
+                        log::debug!("beginning {} with {} steps", saga_id, steps.len());
+                        self.log(Record::Begun {
+                            saga_id: saga_id.clone(),
+                            steps,
+                        })?;
+                        self.advance(&saga_id, output)?;
+                        reply.body.payload = Payload::BeginOk { saga_id };
+                        reply.send(&mut *output).context("reply to begin")?;
+                    }
+                    Payload::Status { saga_id } => {
+                        // saga ids start with the orchestrator's node id
+                        let origin = match saga_id.split_once('-') {
+                            Some((origin, _)) if origin != self.node => origin.to_string(),
+                            _ => {
+                                reply.body.payload = self.status(&saga_id);
+                                reply.send(&mut *output).context("reply to status")?;
+                                return Ok(());
+                            }
+                        };
+                        let req_id = self.id;
+                        self.id += 1;
+                        self.pending.insert(req_id, (src, reply.body.in_reply_to));
+                        let forward = Payload::Forward {
+                            origin: self.node.clone(),
+                            req_id,
+                            request: Box::new(Payload::Status { saga_id }),
+                        };
+                        self.send(&origin, forward, output)?;
+                    }
+                    Payload::Forward {
+                        origin,
+                        req_id,
+                        request,
+                    } => {
+                        let Payload::Status { saga_id } = *request else {
+                            unreachable!("only status requests are forwarded")
+                        };
+                        let done = Payload::Done {
+                            req_id,
+                            reply: Box::new(self.status(&saga_id)),
+                        };
+                        self.send(&origin, done, output)?;
+                    }
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    Payload::Read { key } => {
+                        reply.body.payload = Payload::ReadOk {
+                            value: self.counters.get(&key).copied().unwrap_or_default(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::Invoke {
+                        saga_id,
+                        step,
+                        key,
+                        delta,
+                    } => {
+                        let ok = self.invoke(&saga_id, step, key, delta)?;
+                        self.send(&src, Payload::Invoked { saga_id, step, ok }, output)?;
+                    }
+                    Payload::Invoked { saga_id, step, ok } => {
+                        self.invoked(&saga_id, step, ok, output)?;
+                    }
+                    Payload::Compensate {
+                        saga_id,
+                        step,
+                        key,
+                        delta,
+                    } => {
+                        self.compensate(&saga_id, step, key, delta)?;
+                        self.send(&src, Payload::Compensated { saga_id, step }, output)?;
+                    }
+                    Payload::Compensated { saga_id, step } => {
+                        self.compensated(&saga_id, step, output)?;
+                    }
+                    Payload::BeginOk { .. }
+                    | Payload::StatusOk { .. }
+                    | Payload::ReadOk { .. }
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SagaNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn status(&self, saga_id: &str) -> Payload {
+        match self.sagas.get(saga_id) {
+            Some(saga) => Payload::StatusOk {
+                state: saga.state,
+                completed: saga.done,
+                compensated: match saga.state {
+                    SagaState::Compensating | SagaState::Aborted => {
+                        saga.done - saga.undo.min(saga.done)
+                    }
+                    SagaState::Running | SagaState::Completed => 0,
+                },
+            },
+            None => Payload::Error {
+                code: error::KEY_DOES_NOT_EXIST,
+                text: format!("no saga {}", saga_id),
+            },
+        }
+    }
+
+    fn log(&mut self, record: Record) -> anyhow::Result<()> {
+        self.wal.append(&record).context("log saga progress")?;
+        self.apply(record);
+        Ok(())
+    }
+
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Begun { saga_id, steps } => {
+                let state = if steps.is_empty() {
+                    SagaState::Completed
+                } else {
+                    SagaState::Running
+                };
+                let saga = Saga {
+                    steps,
+                    state,
+                    done: 0,
+                    undo: 0,
+                    step_started: clock::now(),
+                    last_sent: clock::now(),
+                };
+                self.sagas.insert(saga_id, saga);
+            }
+            Record::StepDone { saga_id } => {
+                if let Some(saga) = self.sagas.get_mut(&saga_id) {
+                    saga.done += 1;
+                    saga.step_started = clock::now();
+                    if saga.done == saga.steps.len() {
+                        saga.state = SagaState::Completed;
+                    }
+                }
+            }
+            Record::StepFailed { saga_id, undo } => {
+                if let Some(saga) = self.sagas.get_mut(&saga_id) {
+                    saga.undo = undo;
+                    saga.state = if undo == 0 {
+                        SagaState::Aborted
+                    } else {
+                        SagaState::Compensating
+                    };
+                }
+            }
+            Record::StepCompensated { saga_id } => {
+                if let Some(saga) = self.sagas.get_mut(&saga_id) {
+                    saga.undo -= 1;
+                    if saga.undo == 0 {
+                        saga.state = SagaState::Aborted;
+                    }
+                }
+            }
+            Record::Applied {
+                saga_id,
+                step,
+                key,
+                delta,
+            } => {
+                *self.counters.entry(key).or_default() += delta;
+                self.steps.insert((saga_id, step), StepOutcome::Applied);
+            }
+            Record::Refused { saga_id, step } => {
+                self.steps.insert((saga_id, step), StepOutcome::Refused);
+            }
+            Record::Undone {
+                saga_id,
+                step,
+                key,
+                delta,
+            } => {
+                let outcome = self.steps.insert((saga_id, step), StepOutcome::Undone);
+                if outcome == Some(StepOutcome::Applied) {
+                    *self.counters.entry(key).or_default() -= delta;
+                }
+            }
+        }
+    }
+
+    // sends whatever the saga is waiting on: the next step, or the next compensation
+    fn advance(&mut self, saga_id: &str, output: &mut Output) -> anyhow::Result<()> {
+        let Some(saga) = self.sagas.get_mut(saga_id) else {
+            return Ok(());
+        };
+        saga.last_sent = clock::now();
+        let (step, request) = match saga.state {
+            SagaState::Running => {
+                let step = &saga.steps[saga.done];
+                let request = Payload::Invoke {
+                    saga_id: saga_id.to_string(),
+                    step: saga.done,
+                    key: step.key.clone(),
+                    delta: step.delta,
+                };
+                (step.clone(), request)
+            }
+            SagaState::Compensating => {
+                let i = saga.undo - 1;
+                let step = &saga.steps[i];
+                let request = Payload::Compensate {
+                    saga_id: saga_id.to_string(),
+                    step: i,
+                    key: step.key.clone(),
+                    delta: step.delta,
+                };
+                (step.clone(), request)
+            }
+            SagaState::Completed | SagaState::Aborted => return Ok(()),
+        };
+        if step.node != self.node {
+            return self.send(&step.node, request, output);
+        }
+        // our own step, no need to go through the network
+        match request {
+            Payload::Invoke {
+                saga_id,
+                step,
+                key,
+                delta,
+            } => {
+                let ok = self.invoke(&saga_id, step, key, delta)?;
+                self.invoked(&saga_id, step, ok, output)
+            }
+            Payload::Compensate {
+                saga_id,
+                step,
+                key,
+                delta,
+            } => {
+                self.compensate(&saga_id, step, key, delta)?;
+                self.compensated(&saga_id, step, output)
+            }
+            _ => unreachable!("advance only sends steps and compensations"),
+        }
+    }
+
+    // participant side of a step
+    fn invoke(
+        &mut self,
+        saga_id: &str,
+        step: usize,
+        key: String,
+        delta: i64,
+    ) -> anyhow::Result<bool> {
+        if let Some(&outcome) = self.steps.get(&(saga_id.to_string(), step)) {
+            return Ok(outcome == StepOutcome::Applied);
+        }
+        let saga_id = saga_id.to_string();
+        let value = self.counters.get(&key).copied().unwrap_or_default();
+        if value + delta < 0 {
+            self.log(Record::Refused { saga_id, step })?;
+            return Ok(false);
+        }
+        self.log(Record::Applied {
+            saga_id,
+            step,
+            key,
+            delta,
+        })?;
+        Ok(true)
+    }
+
+    fn compensate(
+        &mut self,
+        saga_id: &str,
+        step: usize,
+        key: String,
+        delta: i64,
+    ) -> anyhow::Result<()> {
+        match self.steps.get(&(saga_id.to_string(), step)) {
+            Some(StepOutcome::Refused | StepOutcome::Undone) => Ok(()),
+            Some(StepOutcome::Applied) | None => self.log(Record::Undone {
+                saga_id: saga_id.to_string(),
+                step,
+                key,
+                delta,
+            }),
+        }
+    }
+
+    fn invoked(
+        &mut self,
+        saga_id: &str,
+        step: usize,
+        ok: bool,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(saga) = self.sagas.get(saga_id) else {
+            return Ok(());
+        };
+        if saga.state != SagaState::Running || saga.done != step {
+            // a duplicate answer, or one for a step we've given up on
+            return Ok(());
+        }
+        let saga_id = saga_id.to_string();
+        if ok {
+            self.log(Record::StepDone {
+                saga_id: saga_id.clone(),
+            })?;
+        } else {
+            log::info!("step {} of {} failed, compensating", step, saga_id);
+            self.log(Record::StepFailed {
+                saga_id: saga_id.clone(),
+                undo: step,
+            })?;
+        }
+        self.advance(&saga_id, output)
+    }
+
+    fn compensated(
+        &mut self,
+        saga_id: &str,
+        step: usize,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(saga) = self.sagas.get(saga_id) else {
+            return Ok(());
+        };
+        if saga.state != SagaState::Compensating || saga.undo != step + 1 {
+            return Ok(());
+        }
+        let saga_id = saga_id.to_string();
+        self.log(Record::StepCompensated {
+            saga_id: saga_id.clone(),
+        })?;
+        self.advance(&saga_id, output)
+    }
+
+    fn tick(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let mut timed_out = Vec::new();
+        let mut retry = Vec::new();
+        for (saga_id, saga) in &self.sagas {
+            match saga.state {
+                SagaState::Running if clock::since(saga.step_started) > STEP_TIMEOUT => {
+                    timed_out.push((saga_id.clone(), saga.done));
+                }
+                SagaState::Running | SagaState::Compensating
+                    if clock::since(saga.last_sent) > RETRY_AFTER =>
+                {
+                    retry.push(saga_id.clone());
+                }
+                _ => {}
+            }
+        }
+        for (saga_id, step) in timed_out {
+            // the step may well have gone through without us hearing about it, so it gets
+            // compensated along with the rest
+            log::warn!("step {} of {} timed out, compensating", step, saga_id);
+            self.log(Record::StepFailed {
+                saga_id: saga_id.clone(),
+                undo: step + 1,
+            })?;
+            self.advance(&saga_id, output)?;
+        }
+        for saga_id in retry {
+            self.advance(&saga_id, output)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, SagaNode, _, _>(())
+}
@@ -0,0 +1,269 @@
+use anyhow::{Context, Ok};
+use rustengan::kv::{KvRequest, SEQ_KV};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// permits per semaphore unless RUSTENGAN_SEMAPHORE_PERMITS says otherwise
+const DEFAULT_PERMITS: u64 = 10;
+// a request gives up after losing this many cas races in a row
+const MAX_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Acquire { name: String, permits: u64 },
+    AcquireOk,
+    Release { name: String, permits: u64 },
+    ReleaseOk,
+    // how many permits are free
+    Read { name: String },
+    // carries either the free permits for a client or, coming from seq-kv, the permits in use
+    ReadOk { value: u64 },
+    CasOk,
+    Error { code: usize, text: String },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Acquire(u64),
+    Release(u64),
+    Read,
+}
+
+struct Op {
+    client: String,
+    msg_id: Option<usize>,
+    name: String,
+    request: Request,
+    attempts: usize,
+}
+
+enum KvCall {
+    Read(usize),
+    Cas(usize),
+}
+
+/// Cluster-wide counting semaphores. The number of permits in use for each semaphore lives in
+/// seq-kv, and every node serves any client: it reads the count, checks the request against it
+/// and cas's in the new count, starting over from the read whenever another node got there first.
+/// A stale read can only make that cas fail, so no more than the configured permits are ever
+/// handed out; it can also make an acquire fail for lack of permits that were in fact released a
+/// moment ago, and a client `read` is only as fresh as seq-kv lets it be.
+///
+/// Permits belong to nobody in particular: a client that crashes while holding some leaks them.
+/// The lock service's leases are the thing to use when that matters.
+pub struct SemaphoreNode {
+    node: String,
+    id: usize,
+    permits: u64,
+    ops: HashMap<usize, Op>,
+    // outstanding seq-kv requests, by msg_id
+    kv: HashMap<usize, KvCall>,
+}
+
+impl Node<(), Payload> for SemaphoreNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        _tx: std::sync::mpsc::Sender<Event<Payload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            node: init.node_id,
+            id: 1,
+            permits: config::var_or("RUSTENGAN_SEMAPHORE_PERMITS", DEFAULT_PERMITS)?,
+            ops: HashMap::new(),
+            kv: HashMap::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        if input.src == SEQ_KV {
+            let Some(call) = input.body.in_reply_to.and_then(|id| self.kv.remove(&id)) else {
+                log::warn!("reply from seq-kv to nothing we asked: {:?}", input.body);
+                return Ok(());
+            };
+            return self.kv_reply(call, input.body.payload, output);
+        }
+        let (name, request) = match input.body.payload {
+            Payload::Acquire { name, permits } => (name, Request::Acquire(permits)),
+            Payload::Release { name, permits } => (name, Request::Release(permits)),
+            Payload::Read { name } => (name, Request::Read),
+            Payload::AcquireOk
+            | Payload::ReleaseOk
+            | Payload::ReadOk { .. }
+            | Payload::CasOk
+            | Payload::Error { .. } => return Ok(()),
+        };
+        let op_id = self.next_id();
+        let op = Op {
+            client: input.src,
+            msg_id: input.body.id,
+            name,
+            request,
+            attempts: 0,
+        };
+        self.ops.insert(op_id, op);
+        self.read(op_id, output)
+    }
+}
+
+impl SemaphoreNode {
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    fn key(name: &str) -> String {
+        format!("semaphore-{}", name)
+    }
+
+    fn read(&mut self, op_id: usize, output: &mut Output) -> anyhow::Result<()> {
+        let key = Self::key(&self.ops[&op_id].name);
+        let id = self.next_id();
+        self.kv.insert(id, KvCall::Read(op_id));
+        KvRequest::<_, u64>::Read { key }
+            .send(&self.node, SEQ_KV, id, &mut *output)
+            .context("read permits in use")
+    }
+
+    fn kv_reply(
+        &mut self,
+        call: KvCall,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match (call, reply) {
+            (KvCall::Read(op_id), Payload::ReadOk { value }) => {
+                self.decide(op_id, Some(value), output)
+            }
+            // nobody has acquired anything from this semaphore yet
+            (KvCall::Read(op_id), Payload::Error { code, .. })
+                if code == error::KEY_DOES_NOT_EXIST =>
+            {
+                self.decide(op_id, None, output)
+            }
+            (KvCall::Cas(op_id), Payload::CasOk) => {
+                let reply = match self.ops[&op_id].request {
+                    Request::Acquire(_) => Payload::AcquireOk,
+                    Request::Release(_) => Payload::ReleaseOk,
+                    Request::Read => unreachable!("reads don't cas"),
+                };
+                self.reply(op_id, reply, output)
+            }
+            // another node changed the count since we read it
+            (KvCall::Cas(op_id), Payload::Error { code, .. })
+                if code == error::PRECONDITION_FAILED || code == error::KEY_DOES_NOT_EXIST =>
+            {
+                let Some(op) = self.ops.get_mut(&op_id) else {
+                    return Ok(());
+                };
+                op.attempts += 1;
+                if op.attempts >= MAX_ATTEMPTS {
+                    let error = Payload::Error {
+                        code: error::TEMPORARILY_UNAVAILABLE,
+                        text: format!("too much contention on {}", op.name),
+                    };
+                    return self.reply(op_id, error, output);
+                }
+                self.read(op_id, output)
+            }
+            (KvCall::Read(op_id) | KvCall::Cas(op_id), Payload::Error { code, text }) => {
+                self.reply(op_id, Payload::Error { code, text }, output)
+            }
+            // whatever seq-kv meant by it, the op can't go on from here
+            (KvCall::Read(op_id) | KvCall::Cas(op_id), reply) => {
+                log::warn!("unexpected reply from seq-kv: {:?}", reply);
+                let error = Payload::Error {
+                    code: error::CRASH,
+                    text: "seq-kv gave an answer we can't make sense of".to_string(),
+                };
+                self.reply(op_id, error, output)
+            }
+        }
+    }
+
+    // `held` is the number of permits in use, or none if the semaphore has never been touched
+    fn decide(
+        &mut self,
+        op_id: usize,
+        held: Option<u64>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let op = &self.ops[&op_id];
+        let in_use = held.unwrap_or(0);
+        let to = match op.request {
+            Request::Read => {
+                let value = self.permits.saturating_sub(in_use);
+                return self.reply(op_id, Payload::ReadOk { value }, output);
+            }
+            // checked, since `permits` is whatever the client asked for
+            Request::Acquire(permits) => match in_use.checked_add(permits) {
+                Some(to) if to <= self.permits => to,
+                _ => {
+                    let error = Payload::Error {
+                        code: error::TEMPORARILY_UNAVAILABLE,
+                        text: format!(
+                            "{} has {} of {} permits free",
+                            op.name,
+                            self.permits.saturating_sub(in_use),
+                            self.permits
+                        ),
+                    };
+                    return self.reply(op_id, error, output);
+                }
+            },
+            Request::Release(permits) if permits > in_use => {
+                let error = Payload::Error {
+                    code: error::PRECONDITION_FAILED,
+                    text: format!("{} only has {} permits in use", op.name, in_use),
+                };
+                return self.reply(op_id, error, output);
+            }
+            Request::Release(permits) => in_use - permits,
+        };
+        let key = Self::key(&op.name);
+        let id = self.next_id();
+        self.kv.insert(id, KvCall::Cas(op_id));
+        KvRequest::Cas {
+            key,
+            from: in_use,
+            to,
+            create_if_not_exists: held.is_none(),
+        }
+        .send(&self.node, SEQ_KV, id, &mut *output)
+        .context("cas permits in use")
+    }
+
+    fn reply(&mut self, op_id: usize, reply: Payload, output: &mut Output) -> anyhow::Result<()> {
+        let Some(op) = self.ops.remove(&op_id) else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: op.client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: op.msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, SemaphoreNode, _, _>(())
+}
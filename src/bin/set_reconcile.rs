@@ -0,0 +1,242 @@
+use anyhow::{Context, Ok};
+use rand::prelude::*;
+use rustengan::iblt::Iblt;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::Duration,
+};
+
+const GOSSIP_INTERVAL: Duration = Duration::from_millis(300);
+// sketch sizes, in cells. past the largest it's cheaper to just send the whole set.
+const MIN_CELLS: usize = 12;
+const MAX_CELLS: usize = 3072;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Add {
+        element: u64,
+    },
+    AddOk,
+    Read,
+    ReadOk {
+        elements: BTreeSet<u64>,
+    },
+    Stats,
+    StatsOk {
+        stats: Stats,
+    },
+    // a reconciliation round, started by the sender, who has `len` elements
+    Sketch {
+        sketch: Iblt,
+        len: usize,
+    },
+    // the sketch couldn't be decoded, try again with one this big
+    SketchFailed {
+        cells: usize,
+    },
+    // what the sender has that the receiver doesn't, and how many elements the two differed by
+    // in all, for sizing the next sketch
+    Elements {
+        elements: Vec<u64>,
+        difference: usize,
+    },
+    // the fallback when even the largest sketch is too small
+    Full {
+        elements: BTreeSet<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Stats {
+    rounds: u64,
+    decode_failures: u64,
+    full_transfers: u64,
+    // what reconciliation actually put on the wire, and what it would have if every round had
+    // sent the whole set instead
+    bytes_sent: u64,
+    naive_bytes: u64,
+}
+
+pub enum InjectedPayload {
+    Gossip,
+}
+
+/// Keeps a grow-only set of integers and reconciles it with one random peer per round using an
+/// [`Iblt`] sketch instead of sending the set. The receiver subtracts its own sketch, decodes
+/// the difference, adds whatever it was missing and sends back whatever the sender was missing,
+/// so one round trip settles a pair of replicas. When a sketch turns out too small to decode the
+/// sender tries a sketch twice the size next time, and every successful round resizes the next
+/// sketch to the difference it found. `stats` reports the bytes this saves over naive gossip of
+/// the full set.
+pub struct SetNode {
+    node: String,
+    id: usize,
+    peers: Vec<String>,
+    elements: BTreeSet<u64>,
+    // how big a sketch to send each peer next
+    cells: HashMap<String, usize>,
+    stats: Stats,
+}
+
+impl Node<(), Payload, InjectedPayload> for SetNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(GOSSIP_INTERVAL, tx, || {
+            Event::Injected(InjectedPayload::Gossip)
+        });
+        Ok(Self {
+            peers: init
+                .node_ids
+                .into_iter()
+                .filter(|n| *n != init.node_id)
+                .collect(),
+            node: init.node_id,
+            id: 1,
+            elements: BTreeSet::new(),
+            cells: HashMap::new(),
+            stats: Stats::default(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Gossip) => {
+                let Some(peer) = self.peers.choose(&mut rng::thread()).cloned() else {
+                    return Ok(());
+                };
+                self.stats.rounds += 1;
+                self.stats.naive_bytes += size(&Payload::Full {
+                    elements: self.elements.clone(),
+                });
+                let cells = self.cells.get(&peer).copied().unwrap_or(MIN_CELLS);
+                let payload = if cells > MAX_CELLS {
+                    self.cells.remove(&peer);
+                    self.stats.full_transfers += 1;
+                    Payload::Full {
+                        elements: self.elements.clone(),
+                    }
+                } else {
+                    Payload::Sketch {
+                        sketch: Iblt::from_elements(cells, &self.elements),
+                        len: self.elements.len(),
+                    }
+                };
+                self.gossip(&peer, payload, output)?;
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Add { element } => {
+                        self.elements.insert(element);
+                        reply.body.payload = Payload::AddOk;
+                        reply.send(&mut *output).context("reply to add")?;
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            elements: self.elements.clone(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    Payload::Stats => {
+                        reply.body.payload = Payload::StatsOk { stats: self.stats };
+                        reply.send(&mut *output).context("reply to stats")?;
+                    }
+                    Payload::Sketch { sketch, len } => {
+                        let ours = Iblt::from_elements(sketch.len(), &self.elements);
+                        // decoded from the sender's point of view
+                        let Some((missing, extra)) = sketch.subtract(&ours).decode() else {
+                            self.stats.decode_failures += 1;
+                            // the sets differ by at least as much as their sizes do
+                            let at_least = len.abs_diff(self.elements.len());
+                            let failed = Payload::SketchFailed {
+                                cells: (2 * sketch.len()).max(2 * at_least),
+                            };
+                            return self.gossip(&src, failed, output);
+                        };
+                        let difference = missing.len() + extra.len();
+                        self.elements.extend(missing);
+                        let elements = Payload::Elements {
+                            elements: extra,
+                            difference,
+                        };
+                        self.gossip(&src, elements, output)?;
+                    }
+                    Payload::SketchFailed { cells } => {
+                        self.cells.insert(src, cells);
+                    }
+                    Payload::Elements {
+                        elements,
+                        difference,
+                    } => {
+                        // about as big as the next sketch needs to be, if the sets keep drifting
+                        // apart at the same rate
+                        let cells = (2 * difference).max(MIN_CELLS);
+                        self.cells.insert(src, cells);
+                        self.elements.extend(elements);
+                    }
+                    Payload::Full { elements } => {
+                        let extra: Vec<u64> =
+                            self.elements.difference(&elements).copied().collect();
+                        let missing = elements.difference(&self.elements).count();
+                        let difference = missing + extra.len();
+                        self.elements.extend(elements);
+                        let elements = Payload::Elements {
+                            elements: extra,
+                            difference,
+                        };
+                        self.gossip(&src, elements, output)?;
+                    }
+                    Payload::AddOk | Payload::ReadOk { .. } | Payload::StatsOk { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SetNode {
+    fn send(&self, dst: &str, payload: &Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    // sends a reconciliation message, counting its bytes
+    fn gossip(&mut self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        self.stats.bytes_sent += size(&payload);
+        self.send(dst, &payload, output)
+    }
+}
+
+fn size(payload: &Payload) -> u64 {
+    serde_json::to_vec(payload).map_or(0, |bytes| bytes.len() as u64)
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, SetNode, _, _>(())
+}
@@ -0,0 +1,562 @@
+use anyhow::{Context, Ok};
+use rustengan::failure_detector::{FailureDetector, FdEvent, Strategy};
+use rustengan::hlc::{Hlc, Timestamp};
+
+use rustengan::shard::{self, Placement};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+const FAIL_AFTER: Duration = Duration::from_millis(1000);
+// virtual nodes per member when RUSTENGAN_PLACEMENT is ring
+const VNODES: usize = 64;
+// a request bounces between nodes at most this often while they disagree about who owns its key.
+// after that, whoever has it handles it.
+const MAX_HOPS: usize = 3;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: usize,
+    },
+    ReadOk {
+        value: usize,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    CasOk,
+    // the keys whose value is `value`, as far as the index has heard. the index is kept up to
+    // date after writes are acknowledged, not before, so it can be a little behind: a key written
+    // a moment ago may be missing, and one overwritten a moment ago may still be listed. keys
+    // that died with a failed node stay listed for good, unless their partition died with it.
+    QueryIndex {
+        value: usize,
+    },
+    QueryIndexOk {
+        keys: Vec<usize>,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a client request on its way to the node that owns its key
+    Forward {
+        origin: String,
+        // which run of the origin it came from: its request ids start over when it restarts
+        boot: String,
+        req_id: usize,
+        hops: usize,
+        request: Box<Payload>,
+    },
+    // the reply to a forwarded request, on its way back to the node the client talked to
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+    // keys that moved to the receiver after a membership change, with the version of each
+    Handoff {
+        entries: Vec<(usize, usize, u64)>,
+    },
+    HandoffOk {
+        keys: Vec<usize>,
+    },
+    // changes to the index partitions the receiver owns, from the owners of the keys, or from
+    // the index partition's previous owner
+    IndexUpdate {
+        entries: Vec<IndexEntry>,
+    },
+    IndexUpdateOk {
+        entries: Vec<(usize, usize, u64)>,
+    },
+    Heartbeat,
+}
+
+// whether key had value as of version. the index keeps the newest of these for every pair and
+// so doesn't care what order they arrive in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexEntry {
+    value: usize,
+    key: usize,
+    version: u64,
+    present: bool,
+}
+
+// (value, key) -> (version, present)
+type IndexEntries = HashMap<(usize, usize), (u64, bool)>;
+
+pub enum InjectedPayload {
+    Fd(FdEvent),
+}
+
+impl From<FdEvent> for InjectedPayload {
+    fn from(event: FdEvent) -> Self {
+        Self::Fd(event)
+    }
+}
+
+pub struct ShardedKvNode {
+    node: String,
+    id: usize,
+    fd: FailureDetector<Payload, InjectedPayload>,
+    placement: Box<dyn Placement>,
+    store: HashMap<usize, usize>,
+    // when each key was last written, by the hlc of whoever wrote it
+    versions: HashMap<usize, u64>,
+    hlc: Hlc,
+    // keys we've given away whose new owner hasn't confirmed having them yet
+    handing_off: HashMap<String, HashMap<usize, (usize, u64)>>,
+    // our partitions of the index on values. entries that are no longer present stay behind, so
+    // a late add can't bring them back.
+    index: IndexEntries,
+    // index entries on their way to the partition's owner, until it confirms having them
+    index_outbox: HashMap<String, IndexEntries>,
+    // requests from our own clients that we forwarded to the owner
+    pending: HashMap<usize, (String, Option<usize>)>,
+    // this run of the node, as opposed to any before it crashed
+    boot: String,
+    // requests we've handled, by the run of the node the client talked to and its id for the
+    // request, so that a forward the network repeats isn't applied again after a later write
+    handled: HashSet<(String, usize)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for ShardedKvNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let placement = shard::from_env(&init.node_ids, VNODES)?;
+        let boot = format!("{}-{}", init.node_id, rng::ulid());
+        Ok(Self {
+            fd: FailureDetector::start(
+                &init.node_id,
+                init.node_ids.clone(),
+                HEARTBEAT_INTERVAL,
+                Strategy::from_env(FAIL_AFTER)?,
+                tx,
+            ),
+            node: init.node_id,
+            id: 1,
+            placement,
+            store: HashMap::new(),
+            versions: HashMap::new(),
+            hlc: Hlc::new(),
+            handing_off: HashMap::new(),
+            index: HashMap::new(),
+            index_outbox: HashMap::new(),
+            pending: HashMap::new(),
+            boot,
+            handled: HashSet::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Fd(FdEvent::Heartbeat)) => {
+                self.fd.heartbeat(&mut *output)?;
+                // a handoff or its ack may have been lost
+                for (n, entries) in &self.handing_off {
+                    let handoff = Payload::Handoff {
+                        entries: entries.iter().map(|(&k, &(v, ver))| (k, v, ver)).collect(),
+                    };
+                    self.send(n, handoff, output)?;
+                }
+                self.flush_index(output)?;
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerDown(n))) => {
+                log::warn!("{} is down, taking over its keys", n);
+                // whatever it held dies with it: there's no replication here. keys we were still
+                // handing to it come back to us and go wherever they belong now, and so do index
+                // changes on their way to it: it may only be cut off, and an entry it holds that
+                // one of them would have overwritten comes back with its partition once it's up.
+                self.placement.remove(&n);
+                for (key, (value, version)) in self.handing_off.remove(&n).unwrap_or_default() {
+                    self.merge(key, value, version);
+                }
+                for (slot, (version, present)) in self.index_outbox.remove(&n).unwrap_or_default() {
+                    self.queue_index(slot, version, present);
+                }
+                self.rebalance(output)?;
+                self.backfill(output)?;
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerUp(n))) => {
+                log::info!("{} is back up", n);
+                self.placement.add(&n);
+                self.rebalance(output)?;
+                self.backfill(output)?;
+            }
+            Event::Message(input) => {
+                self.fd.heard_from(&input.src);
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    request @ (Payload::Read { .. }
+                    | Payload::Write { .. }
+                    | Payload::Cas { .. }
+                    | Payload::QueryIndex { .. }) => {
+                        let req_id = self.id;
+                        self.id += 1;
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let (me, boot) = (self.node.clone(), self.boot.clone());
+                        self.handle(me, boot, req_id, 0, request, output)?;
+                    }
+                    Payload::Forward {
+                        origin,
+                        boot,
+                        req_id,
+                        hops,
+                        request,
+                    } => self.handle(origin, boot, req_id, hops, *request, output)?,
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    Payload::Handoff { entries } => {
+                        let keys = entries.iter().map(|(k, _, _)| *k).collect();
+                        let mut strays = false;
+                        for (key, value, version) in entries {
+                            // writes here have to sort after the ones that came with the key
+                            self.hlc.observe(Timestamp::from_u64(version));
+                            self.merge(key, value, version);
+                            strays |= self.owner(key) != self.node;
+                        }
+                        self.send(&src, Payload::HandoffOk { keys }, output)?;
+                        // the sender's idea of who's up isn't ours, so pass on whatever isn't ours
+                        // either, or it'd stay here for good
+                        if strays {
+                            self.rebalance(output)?;
+                        }
+                    }
+                    Payload::HandoffOk { keys } => {
+                        if let Some(entries) = self.handing_off.get_mut(&src) {
+                            for key in keys {
+                                entries.remove(&key);
+                            }
+                            if entries.is_empty() {
+                                self.handing_off.remove(&src);
+                            }
+                        }
+                    }
+                    Payload::IndexUpdate { entries } => {
+                        let mut acked = Vec::new();
+                        for entry in entries {
+                            acked.push((entry.value, entry.key, entry.version));
+                            let slot = (entry.value, entry.key);
+                            if self.index_owner(entry.value) != self.node {
+                                // stale routing on the sender's side, pass it on
+                                self.queue_index(slot, entry.version, entry.present);
+                                continue;
+                            }
+                            let known = self
+                                .index
+                                .get(&slot)
+                                .is_some_and(|(v, _)| *v >= entry.version);
+                            if !known {
+                                self.index.insert(slot, (entry.version, entry.present));
+                            }
+                        }
+                        self.send(&src, Payload::IndexUpdateOk { entries: acked }, output)?;
+                    }
+                    Payload::IndexUpdateOk { entries } => {
+                        if let Some(outbox) = self.index_outbox.get_mut(&src) {
+                            for (value, key, version) in entries {
+                                // unless something newer has been queued since
+                                if outbox
+                                    .get(&(value, key))
+                                    .is_some_and(|(v, _)| *v == version)
+                                {
+                                    outbox.remove(&(value, key));
+                                }
+                            }
+                            if outbox.is_empty() {
+                                self.index_outbox.remove(&src);
+                            }
+                        }
+                    }
+                    Payload::Heartbeat
+                    | Payload::QueryIndexOk { .. }
+                    | Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::CasOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ShardedKvNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn owner(&self, key: usize) -> String {
+        self.placement
+            .owner(shard::hash(&key))
+            .expect("we're always a member ourselves")
+            .to_string()
+    }
+
+    // the index is partitioned by value, independently of the data, so a key and its index entry
+    // usually live on different nodes
+    fn index_owner(&self, value: usize) -> String {
+        self.placement
+            .owner(shard::hash(&("index", value)))
+            .expect("we're always a member ourselves")
+            .to_string()
+    }
+
+    fn handle(
+        &mut self,
+        origin: String,
+        boot: String,
+        req_id: usize,
+        hops: usize,
+        request: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let owner = match request {
+            Payload::Read { key } | Payload::Write { key, .. } | Payload::Cas { key, .. } => {
+                self.owner(key)
+            }
+            Payload::QueryIndex { value } => self.index_owner(value),
+            _ => unreachable!("only client requests are forwarded"),
+        };
+        if owner != self.node && hops < MAX_HOPS {
+            let forward = Payload::Forward {
+                origin,
+                boot,
+                req_id,
+                hops: hops + 1,
+                request: Box::new(request),
+            };
+            return self.send(&owner, forward, output);
+        }
+        if !self.handled.insert((boot, req_id)) {
+            return Ok(());
+        }
+
+        let reply = match request {
+            Payload::Read { key } => match self.store.get(&key) {
+                Some(&value) => Payload::ReadOk { value },
+                None => not_found(key),
+            },
+            Payload::Write { key, value } => {
+                self.write(key, value);
+                Payload::WriteOk
+            }
+            Payload::Cas { key, from, to } => match self.store.get(&key) {
+                Some(&current) if current == from => {
+                    self.write(key, to);
+                    Payload::CasOk
+                }
+                Some(current) => Payload::Error {
+                    code: error::PRECONDITION_FAILED,
+                    text: format!("expected {}, had {}", from, current),
+                },
+                None => not_found(key),
+            },
+            Payload::QueryIndex { value } => {
+                let mut keys: Vec<usize> = self
+                    .index
+                    .iter()
+                    .filter(|((v, _), (_, present))| *v == value && *present)
+                    .map(|((_, key), _)| *key)
+                    .collect();
+                keys.sort();
+                Payload::QueryIndexOk { keys }
+            }
+            _ => unreachable!("only client requests are forwarded"),
+        };
+        if origin == self.node {
+            return self.reply_client(req_id, reply, output);
+        }
+        let done = Payload::Done {
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.send(&origin, done, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, key: usize, value: usize) {
+        let version = self.hlc.now().as_u64();
+        self.versions.insert(key, version);
+        let old = self.store.insert(key, value);
+        if let Some(old) = old.filter(|old| *old != value) {
+            self.queue_index((old, key), version, false);
+        }
+        // even if the value didn't change: its entry has to carry the version the key has now,
+        // or an older write of something else on the other side of a partition could outrank it
+        self.queue_index((value, key), version, true);
+    }
+
+    // a copy of a key from another node. while the cluster was split both sides may have taken
+    // writes for the key, so the newest write wins, just as it does in the index. neither side
+    // may have had the other's value when it wrote, so the winner takes the loser's value out of
+    // the index.
+    fn merge(&mut self, key: usize, value: usize, version: u64) {
+        if let (Some(&ours), Some(&current)) = (self.versions.get(&key), self.store.get(&key)) {
+            if ours >= version {
+                if current != value {
+                    self.queue_index((value, key), ours, false);
+                }
+                return;
+            }
+        }
+        self.versions.insert(key, version);
+        if let Some(old) = self.store.insert(key, value).filter(|old| *old != value) {
+            self.queue_index((old, key), version, false);
+        }
+    }
+
+    fn queue_index(&mut self, slot: (usize, usize), version: u64, present: bool) {
+        let owner = self.index_owner(slot.0);
+        let outbox = self.index_outbox.entry(owner).or_default();
+        if outbox.get(&slot).is_none_or(|(v, _)| *v < version) {
+            outbox.insert(slot, (version, present));
+        }
+    }
+
+    fn flush_index(&self, output: &mut Output) -> anyhow::Result<()> {
+        for (n, outbox) in &self.index_outbox {
+            let entries = outbox
+                .iter()
+                .map(|(&(value, key), &(version, present))| IndexEntry {
+                    value,
+                    key,
+                    version,
+                    present,
+                })
+                .collect();
+            self.send(n, Payload::IndexUpdate { entries }, output)?;
+        }
+        Ok(())
+    }
+
+    // after a membership change the index partitions move too. every node sends the index entry
+    // of every key it holds to wherever that entry belongs now, which rebuilds the partitions of
+    // a node that failed (for the keys that survived it), and passes on the index entries it
+    // holds for partitions that moved elsewhere.
+    fn backfill(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let held: Vec<_> = self
+            .store
+            .iter()
+            .map(|(&key, &value)| ((value, key), self.versions[&key]))
+            .collect();
+        for (slot, version) in held {
+            self.queue_index(slot, version, true);
+        }
+        let moved: Vec<_> = self
+            .index
+            .keys()
+            .filter(|(value, _)| self.index_owner(*value) != self.node)
+            .copied()
+            .collect();
+        for slot in moved {
+            let (version, present) = self.index.remove(&slot).expect("just listed it");
+            self.queue_index(slot, version, present);
+        }
+        log::debug!(
+            "backfilling {} index entries",
+            self.index_outbox.values().map(|o| o.len()).sum::<usize>()
+        );
+        self.flush_index(output)
+    }
+
+    // hands every key we no longer own to its new owner
+    fn rebalance(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let moved: Vec<_> = self
+            .store
+            .keys()
+            .map(|&key| (key, self.owner(key)))
+            .filter(|(_, owner)| *owner != self.node)
+            .collect();
+        for (key, owner) in moved {
+            let value = self.store.remove(&key).expect("just listed it");
+            let version = self.versions.remove(&key).expect("every key has a version");
+            self.handing_off
+                .entry(owner)
+                .or_default()
+                .insert(key, (value, version));
+        }
+        log::info!(
+            "members now {:?}, handing off {} keys",
+            self.placement.members(),
+            self.handing_off.values().map(|e| e.len()).sum::<usize>()
+        );
+        for (n, entries) in &self.handing_off {
+            let handoff = Payload::Handoff {
+                entries: entries.iter().map(|(&k, &(v, ver))| (k, v, ver)).collect(),
+            };
+            self.send(n, handoff, output)?;
+        }
+        Ok(())
+    }
+}
+
+fn not_found(key: usize) -> Payload {
+    Payload::Error {
+        code: error::KEY_DOES_NOT_EXIST,
+        text: format!("key {} does not exist", key),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, ShardedKvNode, _, _>(())
+}
@@ -0,0 +1,453 @@
+use anyhow::{Context, Ok};
+use rand::prelude::*;
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+const TICK: Duration = Duration::from_millis(50);
+// every protocol period we probe one member
+const PROTOCOL_PERIOD: Duration = Duration::from_millis(300);
+// how long a direct ping gets before we ask others to ping on our behalf
+const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+const INDIRECT_PROBES: usize = 3;
+// how long a suspected member has to refute the suspicion before it's declared dead
+const SUSPICION_TIMEOUT: Duration = Duration::from_millis(1500);
+// how often we ping a member we've declared dead, in case it was only cut off from us and is
+// still around to refute it
+const DEAD_PROBE_PERIOD: Duration = Duration::from_millis(1500);
+// most updates piggybacked on any one message
+const MAX_PIGGYBACK: usize = 6;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Ping {
+        seq: u64,
+        updates: Vec<Update>,
+    },
+    Ack {
+        seq: u64,
+        updates: Vec<Update>,
+    },
+    // please ping target for me, and pass its ack back under seq
+    PingReq {
+        target: String,
+        seq: u64,
+        updates: Vec<Update>,
+    },
+    // asked by other workloads (or a client) for the current view of the cluster
+    Members,
+    MembersOk {
+        members: Vec<String>,
+        suspected: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Alive,
+    Suspect,
+    Dead,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Update {
+    node: String,
+    status: Status,
+    incarnation: u64,
+}
+
+struct Member {
+    status: Status,
+    incarnation: u64,
+    since: Instant,
+}
+
+impl Member {
+    // SWIM's precedence rules: a higher incarnation wins, within the same incarnation suspect
+    // beats alive, and dead beats both. a node that really is alive gets past suspicion (or
+    // past being declared dead after a restart) by bumping its incarnation.
+    fn overridden_by(&self, update: &Update) -> bool {
+        match (self.status, update.status) {
+            (Status::Dead, Status::Dead) => false,
+            (_, Status::Dead) => update.incarnation >= self.incarnation,
+            (Status::Dead, Status::Alive) => update.incarnation > self.incarnation,
+            (Status::Dead, Status::Suspect) => false,
+            (Status::Alive, Status::Suspect) => update.incarnation >= self.incarnation,
+            (_, _) => update.incarnation > self.incarnation,
+        }
+    }
+}
+
+struct Probe {
+    target: String,
+    seq: u64,
+    started: Instant,
+    indirect: bool,
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+pub struct SwimNode {
+    node: String,
+    id: usize,
+    incarnation: u64,
+    members: HashMap<String, Member>,
+    seq: u64,
+    probe: Option<Probe>,
+    last_probe: Instant,
+    last_dead_probe: Instant,
+    // who to probe next this round; reshuffled every time we run out, so each member is probed
+    // once per round
+    probe_order: Vec<String>,
+    // pings we're sending for someone else's ping-req: our seq -> (requester, their seq)
+    relays: HashMap<u64, (String, u64, Instant)>,
+    // updates still being spread, with how many more messages each should ride on
+    rumors: Vec<(Update, usize)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for SwimNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        let now = clock::now();
+        Ok(Self {
+            members: init
+                .node_ids
+                .iter()
+                .filter(|n| **n != init.node_id)
+                .map(|n| {
+                    let member = Member {
+                        status: Status::Alive,
+                        incarnation: 0,
+                        since: now,
+                    };
+                    (n.clone(), member)
+                })
+                .collect(),
+            node: init.node_id,
+            id: 1,
+            incarnation: 0,
+            seq: 0,
+            probe: None,
+            last_probe: now,
+            last_dead_probe: now,
+            probe_order: Vec::new(),
+            relays: HashMap::new(),
+            rumors: Vec::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => self.tick(output)?,
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Ping { seq, updates } => {
+                        self.apply(updates);
+                        if self
+                            .members
+                            .get(&src)
+                            .is_some_and(|m| m.status == Status::Dead)
+                        {
+                            // it's been declared dead but is clearly talking to us, so tell it
+                            // and let it refute
+                            let incarnation = self.members[&src].incarnation;
+                            self.rumor(Update {
+                                node: src.clone(),
+                                status: Status::Dead,
+                                incarnation,
+                            });
+                        }
+                        let updates = self.piggyback();
+                        self.send(&src, Payload::Ack { seq, updates }, output)?;
+                    }
+                    Payload::Ack { seq, updates } => {
+                        self.apply(updates);
+                        if let Some((requester, their_seq, _)) = self.relays.remove(&seq) {
+                            let updates = self.piggyback();
+                            let ack = Payload::Ack {
+                                seq: their_seq,
+                                updates,
+                            };
+                            self.send(&requester, ack, output)?;
+                        } else if self.probe.as_ref().is_some_and(|p| p.seq == seq) {
+                            self.probe = None;
+                        }
+                    }
+                    Payload::PingReq {
+                        target,
+                        seq,
+                        updates,
+                    } => {
+                        self.apply(updates);
+                        self.seq += 1;
+                        self.relays.insert(self.seq, (src, seq, clock::now()));
+                        let updates = self.piggyback();
+                        let ping = Payload::Ping {
+                            seq: self.seq,
+                            updates,
+                        };
+                        self.send(&target, ping, output)?;
+                    }
+                    Payload::Members => {
+                        let mut members: Vec<_> = self
+                            .members
+                            .iter()
+                            .filter(|(_, m)| m.status != Status::Dead)
+                            .map(|(n, _)| n.clone())
+                            .chain(std::iter::once(self.node.clone()))
+                            .collect();
+                        members.sort();
+                        let mut suspected: Vec<_> = self
+                            .members
+                            .iter()
+                            .filter(|(_, m)| m.status == Status::Suspect)
+                            .map(|(n, _)| n.clone())
+                            .collect();
+                        suspected.sort();
+                        reply.body.payload = Payload::MembersOk { members, suspected };
+                        reply.send(&mut *output).context("reply to members")?;
+                    }
+                    Payload::MembersOk { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SwimNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn tick(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        // suspects that didn't refute in time are dead
+        let expired: Vec<_> = self
+            .members
+            .iter()
+            .filter(|(_, m)| {
+                m.status == Status::Suspect && clock::since(m.since) > SUSPICION_TIMEOUT
+            })
+            .map(|(n, m)| Update {
+                node: n.clone(),
+                status: Status::Dead,
+                incarnation: m.incarnation,
+            })
+            .collect();
+        self.apply(expired);
+        // the requester has given up on these by now
+        self.relays
+            .retain(|_, (_, _, sent)| clock::since(*sent) < PROTOCOL_PERIOD);
+
+        if let Some(probe) = &mut self.probe {
+            let elapsed = clock::since(probe.started);
+            if elapsed >= PROTOCOL_PERIOD {
+                // nobody got an ack out of it either
+                let target = probe.target.clone();
+                self.probe = None;
+                if let Some(member) = self.members.get(&target) {
+                    if member.status == Status::Alive {
+                        let suspicion = Update {
+                            node: target,
+                            status: Status::Suspect,
+                            incarnation: member.incarnation,
+                        };
+                        self.apply(vec![suspicion]);
+                    }
+                }
+            } else if elapsed >= ACK_TIMEOUT && !probe.indirect {
+                probe.indirect = true;
+                let (target, seq) = (probe.target.clone(), probe.seq);
+                let mut helpers: Vec<_> = self
+                    .members
+                    .iter()
+                    .filter(|(n, m)| **n != target && m.status != Status::Dead)
+                    .map(|(n, _)| n.clone())
+                    .collect();
+                helpers.shuffle(&mut rng::thread());
+                for helper in helpers.into_iter().take(INDIRECT_PROBES) {
+                    let updates = self.piggyback();
+                    let ping_req = Payload::PingReq {
+                        target: target.clone(),
+                        seq,
+                        updates,
+                    };
+                    self.send(&helper, ping_req, output)?;
+                }
+            }
+        }
+
+        if self.probe.is_none() && clock::since(self.last_probe) >= PROTOCOL_PERIOD {
+            if let Some(target) = self.next_target() {
+                self.seq += 1;
+                self.last_probe = clock::now();
+                self.probe = Some(Probe {
+                    target: target.clone(),
+                    seq: self.seq,
+                    started: self.last_probe,
+                    indirect: false,
+                });
+                let updates = self.piggyback();
+                let ping = Payload::Ping {
+                    seq: self.seq,
+                    updates,
+                };
+                self.send(&target, ping, output)?;
+            }
+        }
+
+        // after a partition both sides have declared each other dead and stopped probing, so
+        // nobody would ever hear the other refute it. a ping telling a dead member so gets a
+        // refutation back in the ack if it's alive after all, and nothing at all if it isn't.
+        if clock::since(self.last_dead_probe) >= DEAD_PROBE_PERIOD {
+            self.last_dead_probe = clock::now();
+            let dead: Vec<_> = self
+                .members
+                .iter()
+                .filter(|(_, m)| m.status == Status::Dead)
+                .map(|(n, m)| Update {
+                    node: n.clone(),
+                    status: Status::Dead,
+                    incarnation: m.incarnation,
+                })
+                .collect();
+            if let Some(update) = dead.choose(&mut rng::thread()).cloned() {
+                self.seq += 1;
+                let target = update.node.clone();
+                let mut updates = self.piggyback();
+                updates.push(update);
+                let ping = Payload::Ping {
+                    seq: self.seq,
+                    updates,
+                };
+                self.send(&target, ping, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn next_target(&mut self) -> Option<String> {
+        loop {
+            if self.probe_order.is_empty() {
+                self.probe_order = self
+                    .members
+                    .iter()
+                    .filter(|(_, m)| m.status != Status::Dead)
+                    .map(|(n, _)| n.clone())
+                    .collect();
+                if self.probe_order.is_empty() {
+                    return None;
+                }
+                self.probe_order.shuffle(&mut rng::thread());
+            }
+            let target = self.probe_order.pop().expect("refilled above");
+            // may have died since the round was shuffled
+            if self.members[&target].status != Status::Dead {
+                return Some(target);
+            }
+        }
+    }
+
+    fn apply(&mut self, updates: Vec<Update>) {
+        for update in updates {
+            if update.node == self.node {
+                if update.status == Status::Alive {
+                    continue;
+                }
+                // refute: we're alive, and a newer incarnation says so. news older than our
+                // incarnation was refuted already, but whoever's still passing it on missed the
+                // refutation, and it's long since stopped spreading, so spread it again.
+                if update.incarnation >= self.incarnation {
+                    self.incarnation = update.incarnation + 1;
+                }
+                self.rumor(Update {
+                    node: self.node.clone(),
+                    status: Status::Alive,
+                    incarnation: self.incarnation,
+                });
+                continue;
+            }
+            let Some(member) = self.members.get_mut(&update.node) else {
+                continue;
+            };
+            if !member.overridden_by(&update) {
+                continue;
+            }
+            if member.status != update.status {
+                log::info!(
+                    "{} is now {:?} (incarnation {})",
+                    update.node,
+                    update.status,
+                    update.incarnation
+                );
+            }
+            member.status = update.status;
+            member.incarnation = update.incarnation;
+            member.since = clock::now();
+            self.rumor(update);
+        }
+    }
+
+    fn rumor(&mut self, update: Update) {
+        // newer news about a node makes older news about it pointless to spread
+        self.rumors.retain(|(u, _)| u.node != update.node);
+        // enough retransmissions for an update to reach everyone with high probability
+        let cluster = self.members.len() + 1;
+        let transmissions = 3 * (usize::BITS - cluster.leading_zeros()) as usize;
+        self.rumors.push((update, transmissions));
+    }
+
+    // the updates to send along with the next message, preferring those sent the fewest times
+    fn piggyback(&mut self) -> Vec<Update> {
+        self.rumors
+            .sort_by_key(|(_, remaining)| std::cmp::Reverse(*remaining));
+        let updates = self
+            .rumors
+            .iter_mut()
+            .take(MAX_PIGGYBACK)
+            .map(|(update, remaining)| {
+                *remaining -= 1;
+                update.clone()
+            })
+            .collect();
+        self.rumors.retain(|(_, remaining)| *remaining > 0);
+        updates
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, SwimNode, _, _>(())
+}
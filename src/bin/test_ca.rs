@@ -0,0 +1,29 @@
+use anyhow::Context;
+use rustengan::transport::tls::TestCa;
+
+// writes a throwaway ca, and a certificate it signed for the names given, to a directory:
+//
+//     test_ca /tmp/certs 127.0.0.1 localhost
+//
+// and then RUSTENGAN_TLS_CA=/tmp/certs/ca.pem RUSTENGAN_TLS_CERT=/tmp/certs/node.pem
+// RUSTENGAN_TLS_KEY=/tmp/certs/node.key for every node of the cluster.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let dir = args.next().context("usage: test_ca <dir> <name>...")?;
+    let names: Vec<String> = args.collect();
+    anyhow::ensure!(!names.is_empty(), "usage: test_ca <dir> <name>...");
+    let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+    let ca = TestCa::new()?;
+    let (cert, key) = ca.issue(&names)?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir))?;
+    for (file, contents) in [
+        ("ca.pem", ca.ca_pem()),
+        ("node.pem", cert),
+        ("node.key", key),
+    ] {
+        let path = format!("{}/{}", dir, file);
+        std::fs::write(&path, contents).with_context(|| format!("write {}", path))?;
+    }
+    Ok(())
+}
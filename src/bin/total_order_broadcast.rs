@@ -0,0 +1,344 @@
+use anyhow::{Context, Ok};
+use rustengan::lamport::{Clock, Timestamp};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+pub(crate) const RETRANSMIT_EVERY: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Payload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    // in delivery order, which is the same on every node
+    ReadOk {
+        messages: Vec<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+    // origin -> everyone: where would you put this?
+    Propose {
+        id: MsgId,
+        message: usize,
+    },
+    Proposal {
+        id: MsgId,
+        ts: Timestamp,
+    },
+    // origin -> everyone: the largest proposal, which is where it goes
+    Final {
+        id: MsgId,
+        ts: Timestamp,
+    },
+    FinalOk {
+        id: MsgId,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub(crate) struct MsgId {
+    origin: String,
+    seq: u64,
+}
+
+struct Queued {
+    ts: Timestamp,
+    // until the origin tells us the final timestamp, ts is only our own proposal
+    is_final: bool,
+    message: usize,
+}
+
+// one of our own broadcasts that not everyone has the final timestamp of yet
+struct Outgoing {
+    message: usize,
+    proposals: HashMap<String, Timestamp>,
+    final_ts: Option<Timestamp>,
+    final_acked: HashSet<String>,
+}
+
+pub(crate) enum InjectedPayload {
+    Retransmit,
+}
+
+/// Total order broadcast the ISIS way: every node proposes a lamport timestamp for a message, and
+/// the origin picks the largest proposal as the message's final timestamp. A node delivers
+/// messages in timestamp order once the one at the front of its queue is final. Any message that
+/// reaches a node later gets a proposal larger than everything that node has seen, so its final
+/// timestamp can't land in front of anything already delivered.
+pub(crate) struct TobNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    clock: Clock,
+    seq: u64,
+    outgoing: HashMap<u64, Outgoing>,
+    queue: HashMap<MsgId, Queued>,
+    delivered: HashSet<MsgId>,
+    log: Vec<usize>,
+}
+
+impl Node<(), Payload, InjectedPayload> for TobNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(RETRANSMIT_EVERY, tx, || {
+            Event::Injected(InjectedPayload::Retransmit)
+        });
+        Ok(Self {
+            id: 1,
+            node: init.node_id,
+            nodes: init.node_ids,
+            clock: Clock::new(),
+            seq: 0,
+            outgoing: HashMap::new(),
+            queue: HashMap::new(),
+            delivered: HashSet::new(),
+            log: Vec::new(),
+        })
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Retransmit) => {
+                let mut resend = Vec::new();
+                for (&seq, outgoing) in &self.outgoing {
+                    let id = MsgId {
+                        origin: self.node.clone(),
+                        seq,
+                    };
+                    for n in self.nodes.iter().filter(|n| **n != self.node) {
+                        match &outgoing.final_ts {
+                            None if !outgoing.proposals.contains_key(n) => {
+                                let propose = Payload::Propose {
+                                    id: id.clone(),
+                                    message: outgoing.message,
+                                };
+                                resend.push((n.clone(), propose));
+                            }
+                            Some(ts) if !outgoing.final_acked.contains(n) => {
+                                let fin = Payload::Final {
+                                    id: id.clone(),
+                                    ts: ts.clone(),
+                                };
+                                resend.push((n.clone(), fin));
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                for (n, payload) in resend {
+                    self.send(&n, payload, output)?;
+                }
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                let src = reply.dst.clone();
+                match reply.body.payload {
+                    Payload::Broadcast { message } => {
+                        self.seq += 1;
+                        let id = MsgId {
+                            origin: self.node.clone(),
+                            seq: self.seq,
+                        };
+                        let mut outgoing = Outgoing {
+                            message,
+                            proposals: HashMap::new(),
+                            final_ts: None,
+                            final_acked: HashSet::new(),
+                        };
+                        let ours = self.propose(id.clone(), message).expect("it's brand new");
+                        outgoing.proposals.insert(self.node.clone(), ours);
+                        self.outgoing.insert(self.seq, outgoing);
+                        for n in self.nodes.clone() {
+                            if n != self.node {
+                                let propose = Payload::Propose {
+                                    id: id.clone(),
+                                    message,
+                                };
+                                self.send(&n, propose, output)?;
+                            }
+                        }
+                        // a single node cluster has all the proposals it's going to get
+                        self.maybe_finalize(self.seq, output)?;
+                        reply.body.payload = Payload::BroadcastOk;
+                        reply.send(&mut *output).context("reply to broadcast")?;
+                    }
+                    Payload::Propose { id, message } => {
+                        if let Some(ts) = self.propose(id.clone(), message) {
+                            self.send(&src, Payload::Proposal { id, ts }, output)?;
+                        }
+                    }
+                    Payload::Proposal { id, ts } => {
+                        self.clock.observe(ts.time);
+                        if let Some(outgoing) = self.outgoing.get_mut(&id.seq) {
+                            outgoing.proposals.insert(src, ts);
+                            self.maybe_finalize(id.seq, output)?;
+                        }
+                    }
+                    Payload::Final { id, ts } => {
+                        self.finalize(&id, ts);
+                        self.send(&src, Payload::FinalOk { id }, output)?;
+                    }
+                    Payload::FinalOk { id } => {
+                        if let Some(outgoing) = self.outgoing.get_mut(&id.seq) {
+                            outgoing.final_acked.insert(src);
+                            if outgoing.final_acked.len() == self.nodes.len() - 1 {
+                                self.outgoing.remove(&id.seq);
+                            }
+                        }
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            messages: self.log.clone(),
+                        };
+                        reply.send(&mut *output).context("reply to read")?;
+                    }
+                    // everything goes straight to every node, so there's nothing to route
+                    Payload::Topology { .. } => {
+                        reply.body.payload = Payload::TopologyOk;
+                        reply.send(&mut *output).context("reply to topology")?;
+                    }
+                    Payload::BroadcastOk | Payload::ReadOk { .. } | Payload::TopologyOk => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({ "log": self.log, "queued": self.queue.len() })
+    }
+
+    fn invariants(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.log.len() == self.delivered.len(),
+            "{} messages in the log, but {} delivered",
+            self.log.len(),
+            self.delivered.len()
+        );
+        if let Some(id) = self.queue.keys().find(|id| self.delivered.contains(id)) {
+            anyhow::bail!("{:?} is still queued after it was delivered", id);
+        }
+        Ok(())
+    }
+}
+
+impl TobNode {
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    // queues a message under a fresh proposal of ours. a retransmitted propose gets the same
+    // proposal again, and one for a message we've already delivered gets nothing since the origin
+    // must have had all proposals by then.
+    fn propose(&mut self, id: MsgId, message: usize) -> Option<Timestamp> {
+        if self.delivered.contains(&id) {
+            return None;
+        }
+        if let Some(queued) = self.queue.get(&id) {
+            return Some(queued.ts.clone());
+        }
+        let ts = self.clock.timestamp(&self.node);
+        self.queue.insert(
+            id,
+            Queued {
+                ts: ts.clone(),
+                is_final: false,
+                message,
+            },
+        );
+        Some(ts)
+    }
+
+    fn maybe_finalize(&mut self, seq: u64, output: &mut Output) -> anyhow::Result<()> {
+        let outgoing = self
+            .outgoing
+            .get_mut(&seq)
+            .expect("only called for our own");
+        if outgoing.final_ts.is_some() || outgoing.proposals.len() < self.nodes.len() {
+            return Ok(());
+        }
+        let ts = outgoing
+            .proposals
+            .values()
+            .max()
+            .cloned()
+            .expect("at least our own proposal");
+        outgoing.final_ts = Some(ts.clone());
+        if self.nodes.len() == 1 {
+            self.outgoing.remove(&seq);
+        }
+        let id = MsgId {
+            origin: self.node.clone(),
+            seq,
+        };
+        self.finalize(&id, ts.clone());
+        for n in self.nodes.iter().filter(|n| **n != self.node) {
+            let fin = Payload::Final {
+                id: id.clone(),
+                ts: ts.clone(),
+            };
+            self.send(n, fin, output)?;
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self, id: &MsgId, ts: Timestamp) {
+        self.clock.observe(ts.time);
+        if let Some(queued) = self.queue.get_mut(id) {
+            queued.ts = ts;
+            queued.is_final = true;
+        }
+        // deliver from the front of the queue for as long as the front is final. a proposal
+        // further back can only grow, so nothing will ever be put in front of a final message.
+        while let Some(front) = self.final_front() {
+            let queued = self.queue.remove(&front).expect("just found it");
+            self.log.push(queued.message);
+            self.delivered.insert(front);
+        }
+    }
+
+    fn final_front(&self) -> Option<MsgId> {
+        let (front, queued) = self
+            .queue
+            .iter()
+            .min_by(|(a_id, a), (b_id, b)| (&a.ts, a_id).cmp(&(&b.ts, b_id)))?;
+        queued.is_final.then(|| front.clone())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, TobNode, _, _>(())
+}
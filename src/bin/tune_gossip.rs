@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::{self, Sim};
+
+// the broadcast workload's node, built from the same source as its own binary
+#[allow(dead_code)]
+#[path = "broadcast.rs"]
+mod broadcast;
+
+use broadcast::{BroadcastNode, Gossip, InjectedPayload, Payload};
+
+const USAGE: &str = "usage: tune_gossip [--every <ms,...>] [--fanout <n,...>] [--batch <n,...>] [--topology full|hub|ring] [--nodes <n>] [--latency <ms>] [--rate <per second>] [--for <seconds>] [--seeds <n>]";
+
+// how often every node is read, which is how finely convergence is measured
+const READ_EVERY: Duration = Duration::from_millis(50);
+// how long, once the last broadcast's made, it's given to get everywhere
+const SETTLE: Duration = Duration::from_secs(10);
+
+// the neighbours each node's handed, out of every node's id
+#[derive(Debug, Clone, Copy)]
+enum Topology {
+    // everyone's everyone's neighbour
+    Full,
+    // n0 has everyone else, and everyone else has only n0
+    Hub,
+    // each node has the one before it and the one after it
+    Ring,
+}
+
+impl Topology {
+    fn of(self, ids: &[String]) -> HashMap<String, Vec<String>> {
+        let n = ids.len();
+        ids.iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let neighbours = match self {
+                    Topology::Full => ids.iter().filter(|other| *other != id).cloned().collect(),
+                    Topology::Hub if i == 0 => ids[1..].to_vec(),
+                    Topology::Hub => vec![ids[0].clone()],
+                    Topology::Ring => {
+                        let mut ring = vec![ids[(i + n - 1) % n].clone(), ids[(i + 1) % n].clone()];
+                        ring.dedup();
+                        ring.retain(|other| other != id);
+                        ring
+                    }
+                };
+                (id.clone(), neighbours)
+            })
+            .collect()
+    }
+}
+
+// the cluster and load every combination's run against
+struct Workload {
+    topology: Topology,
+    nodes: usize,
+    latency: Duration,
+    rate: u64,
+    load_for: Duration,
+}
+
+// what one run came to
+struct Measured {
+    // messages between nodes for every broadcast a client made
+    msgs_per_op: f64,
+    // by message that got everywhere, how long from its broadcast until every node had it
+    converged: Vec<Duration>,
+    // how many broadcasts never got to every node
+    unconverged: usize,
+}
+
+// runs the broadcast node under simulation, once for every combination of the gossip
+// intervals, fanouts and batch sizes given and every seed, and writes how each run went as csv:
+//
+//     tune_gossip --every 100,200,300 --fanout 0,3,6 --batch 0,20 --topology full > sweep.csv
+//
+// each run is the efficient broadcast challenge's unless the flags say otherwise: 25 nodes,
+// 100ms between any two, 50 broadcasts a second for 10 seconds, to nodes picked at random. A
+// broadcast has converged once a read of every node has found it; nodes are read every 50ms.
+// Seeds start at RUSTENGAN_SIM_SEED, if it's set, so a sweep can be run again exactly.
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let mut every = vec![broadcast::GOSSIP_EVERY];
+    let mut fanout = vec![0];
+    let mut batch = vec![0];
+    let mut seeds = 1;
+    let mut workload = Workload {
+        topology: Topology::Full,
+        nodes: 25,
+        latency: Duration::from_millis(100),
+        rate: 50,
+        load_for: Duration::from_secs(10),
+    };
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().context(USAGE);
+        match arg.as_str() {
+            "--every" => {
+                every = list(&value()?)?
+                    .into_iter()
+                    .map(Duration::from_millis)
+                    .collect()
+            }
+            "--fanout" => fanout = list(&value()?)?,
+            "--batch" => batch = list(&value()?)?,
+            "--topology" => {
+                workload.topology = match value()?.as_str() {
+                    "full" => Topology::Full,
+                    "hub" => Topology::Hub,
+                    "ring" => Topology::Ring,
+                    other => anyhow::bail!("no topology called {:?}: full, hub or ring", other),
+                }
+            }
+            "--nodes" => workload.nodes = number(&value()?)?,
+            "--latency" => workload.latency = Duration::from_millis(number(&value()?)?),
+            "--rate" => workload.rate = number(&value()?)?,
+            "--for" => workload.load_for = Duration::from_secs(number(&value()?)?),
+            "--seeds" => seeds = number(&value()?)?,
+            _ => anyhow::bail!(USAGE),
+        }
+    }
+    anyhow::ensure!(workload.nodes > 0 && workload.rate > 0 && seeds > 0, USAGE);
+    let first_seed = sim::seed()?;
+
+    println!("every_ms,fanout,batch,seed,msgs_per_op,median_ms,p99_ms,max_ms,unconverged");
+    for &every in &every {
+        for &fanout in &fanout {
+            for &batch in &batch {
+                let gossip = Gossip {
+                    every,
+                    fanout,
+                    batch,
+                };
+                for seed in (0..seeds).map(|n| first_seed.wrapping_add(n)) {
+                    let measured = run(seed, gossip, &workload)
+                        .with_context(|| format!("run {:?} with seed {}", gossip, seed))?;
+                    let mut converged = measured.converged;
+                    converged.sort();
+                    let at = |q: f64| {
+                        let rank = (converged.len() as f64 * q).ceil() as usize;
+                        let ms = |took: &Duration| format!("{:.0}", took.as_secs_f64() * 1000.0);
+                        converged
+                            .get(rank.clamp(1, converged.len().max(1)) - 1)
+                            .map(ms)
+                            .unwrap_or_default()
+                    };
+                    println!(
+                        "{},{},{},{},{:.2},{},{},{},{}",
+                        every.as_millis(),
+                        fanout,
+                        batch,
+                        seed,
+                        measured.msgs_per_op,
+                        at(0.5),
+                        at(0.99),
+                        at(1.0),
+                        measured.unconverged
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn number<T: std::str::FromStr>(value: &str) -> anyhow::Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("{:?} isn't a number", value))
+}
+
+// a comma-separated list of numbers
+fn list<T: std::str::FromStr>(value: &str) -> anyhow::Result<Vec<T>> {
+    value.split(',').map(|n| number(n.trim())).collect()
+}
+
+fn run(seed: u64, gossip: Gossip, workload: &Workload) -> anyhow::Result<Measured> {
+    let ids: Vec<String> = (0..workload.nodes).map(|i| format!("n{}", i)).collect();
+    let mut sim: Sim<Payload, InjectedPayload> =
+        Sim::new(seed, &ids.iter().map(String::as_str).collect::<Vec<_>>());
+    sim.latency(workload.latency..=workload.latency);
+    sim.start::<_, BroadcastNode>(gossip)?;
+    sim.every(gossip.every, || InjectedPayload::Gossip);
+    let mut requests = 0;
+    let topology = workload.topology.of(&ids);
+    for id in &ids {
+        let topology = topology.clone();
+        sim.send("c0", id, Payload::Topology { topology })?;
+        requests += 1;
+    }
+    sim.run_for(workload.latency * 2)?;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    // by message, when it was broadcast
+    let mut broadcast_at = BTreeMap::new();
+    // by msg_id, when a read got to the node it was for
+    let mut read_at = HashMap::new();
+    // by message, when the read that found the last node to have it got there
+    let mut everywhere_at = BTreeMap::new();
+    // by message, the nodes that have had it in a read
+    let mut seen: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let start = sim.now();
+    let gap = Duration::from_secs(1) / workload.rate as u32;
+    let mut next_broadcast = start;
+    let mut next_read = start;
+    let mut message = 0;
+    while sim.now() < start + workload.load_for + SETTLE {
+        let now = sim.now();
+        if now >= next_broadcast {
+            let dst = &ids[rng.gen_range(0..ids.len())];
+            sim.send("c1", dst, Payload::Broadcast { message })?;
+            requests += 1;
+            broadcast_at.insert(message, now);
+            message += 1;
+            next_broadcast += gap;
+            if next_broadcast >= start + workload.load_for {
+                next_broadcast = Duration::MAX;
+            }
+        }
+        if now >= next_read {
+            for id in &ids {
+                let id = sim.send("c2", id, Payload::Read)?;
+                requests += 1;
+                read_at.insert(id, now + workload.latency);
+            }
+            next_read += READ_EVERY;
+        }
+        sim.run_for(next_broadcast.min(next_read).saturating_sub(sim.now()))?;
+        for reply in sim.take_replies("c2")? {
+            let Payload::ReadOk { messages } = reply.body.payload else {
+                anyhow::bail!("{:?} isn't a read_ok", reply.body.payload);
+            };
+            let at = reply
+                .body
+                .in_reply_to
+                .and_then(|id| read_at.get(&id))
+                .copied()
+                .context("a read_ok that isn't in reply to a read")?;
+            for m in messages {
+                let seen = seen.entry(m).or_default();
+                if !seen.contains(&reply.src) {
+                    seen.push(reply.src.clone());
+                    if seen.len() == ids.len() {
+                        everywhere_at.insert(m, at);
+                    }
+                }
+            }
+        }
+        // everything's everywhere, so there's nothing left to wait for
+        if next_broadcast == Duration::MAX && everywhere_at.len() == broadcast_at.len() {
+            break;
+        }
+    }
+
+    let between_nodes = sim.tally().sent - requests;
+    let converged: Vec<Duration> = broadcast_at
+        .iter()
+        .filter_map(|(m, at)| everywhere_at.get(m).map(|everywhere| *everywhere - *at))
+        .collect();
+    Ok(Measured {
+        msgs_per_op: between_nodes as f64 / broadcast_at.len() as f64,
+        unconverged: broadcast_at.len() - converged.len(),
+        converged,
+    })
+}
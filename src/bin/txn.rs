@@ -0,0 +1,1809 @@
+use anyhow::{Context, Ok};
+use rustengan::hlc::{Hlc, Timestamp};
+use rustengan::mvcc::Mvcc;
+use rustengan::txn::Op;
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+// a coordinator that hasn't heard back from every participant by then aborts if it's still
+// voting, and (3pc only) asks again if it's pre-committing.
+const PHASE_TIMEOUT: Duration = Duration::from_millis(1000);
+// how long a participant sits on a prepared transaction before asking around about it, and how
+// often an unacknowledged decision is re-sent.
+const RETRY_INTERVAL: Duration = Duration::from_millis(300);
+// how long a 3pc participant running the termination protocol waits for the others to report in,
+// or to acknowledge its pre-commit, before asking again.
+const TERMINATION_WAIT: Duration = Duration::from_millis(300);
+// how far back snapshot reads can go. older versions are garbage collected.
+const SNAPSHOT_RETENTION: Duration = Duration::from_millis(5000);
+// how many transactions have to end for good before the log is compacted down to what's live
+const COMPACT_AFTER: usize = 64;
+
+/// Which atomic commitment protocol to run, picked with `TXN_PROTOCOL=2pc|3pc`.
+///
+/// 2pc blocks: a participant that voted yes holds its locks until it hears the decision from the
+/// coordinator, however long that takes. 3pc adds a pre-commit round so that participants can
+/// time out and finish the transaction among themselves when the coordinator is gone, at the
+/// price of an extra round trip. They only abort once every participant has said it's uncertain,
+/// so while one of them can't be reached they block much as they would under 2pc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    TwoPhase,
+    ThreePhase,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2pc" => std::result::Result::Ok(Self::TwoPhase),
+            "3pc" => std::result::Result::Ok(Self::ThreePhase),
+            _ => Err(format!(
+                "unknown commit protocol {}, expected 2pc or 3pc",
+                s
+            )),
+        }
+    }
+}
+
+/// How read-write transactions are isolated, picked with `TXN_ISOLATION=2pl|si|ssi`.
+///
+/// 2pl locks every key a transaction touches, reads included, and is serializable. si only locks
+/// what it writes: reads see a snapshot taken when the transaction began, and a transaction
+/// aborts if anything it writes changed since (first committer wins). That leaves readers and
+/// writers out of each other's way but allows write skew. ssi is si plus tracking of read-write
+/// conflicts between concurrent transactions, aborting any transaction that could complete a
+/// cycle of them, which makes it serializable again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Isolation {
+    TwoPhaseLocking,
+    Snapshot,
+    SerializableSnapshot,
+}
+
+impl FromStr for Isolation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "2pl" => std::result::Result::Ok(Self::TwoPhaseLocking),
+            "si" => std::result::Result::Ok(Self::Snapshot),
+            "ssi" => std::result::Result::Ok(Self::SerializableSnapshot),
+            _ => Err(format!("unknown isolation {}, expected 2pl, si or ssi", s)),
+        }
+    }
+}
+
+/// The read-write antidependencies (T1 read a version T2 overwrote, written T1 -> T2) a
+/// transaction is part of, with transactions it ran concurrently with. Every non-serializable
+/// snapshot isolation history has a "pivot" with one coming in and one going out, the outgoing
+/// one to a transaction that committed first.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Conflicts {
+    // a concurrent transaction that has already committed read something this one overwrites
+    inbound: bool,
+    // this one read something a concurrent transaction has since overwritten and committed
+    outbound: bool,
+    // ...and that transaction was itself a pivot's outgoing half
+    outbound_to_pivot: bool,
+}
+
+impl Conflicts {
+    fn merge(&mut self, other: Conflicts) {
+        self.inbound |= other.inbound;
+        self.outbound |= other.outbound;
+        self.outbound_to_pivot |= other.outbound_to_pivot;
+    }
+
+    fn dangerous(&self) -> bool {
+        (self.inbound && self.outbound) || self.outbound_to_pivot
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Txn {
+        txn: Vec<Op>,
+    },
+    TxnOk {
+        txn: Vec<Op>,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    Prepare {
+        txn_id: String,
+        ops: Vec<Op>,
+        participants: Vec<String>,
+        // where reads are served from under si and ssi
+        #[serde(default)]
+        snapshot: Option<u64>,
+    },
+    // carries the participant's proposed commit timestamp. the coordinator commits at the
+    // highest one, which sorts after everything any participant has already committed.
+    Vote {
+        txn_id: String,
+        yes: bool,
+        ops: Vec<Op>,
+        #[serde(default)]
+        ts: u64,
+        #[serde(default)]
+        conflicts: Conflicts,
+    },
+    PreCommit {
+        txn_id: String,
+        #[serde(default)]
+        ts: u64,
+        // from a participant running the termination protocol rather than the coordinator
+        #[serde(default)]
+        terminating: bool,
+    },
+    PreCommitOk {
+        txn_id: String,
+        ok: bool,
+    },
+    Decide {
+        txn_id: String,
+        commit: bool,
+        #[serde(default)]
+        ts: u64,
+        // whether the transaction had an outbound conflict, for participants checking later
+        // transactions under ssi
+        #[serde(default)]
+        outbound: bool,
+    },
+    DecideOk {
+        txn_id: String,
+    },
+    // every participant has acknowledged the outcome, so nobody is going to ask about it again
+    Forget {
+        txn_id: String,
+    },
+    Query {
+        txn_id: String,
+    },
+    StateReq {
+        txn_id: String,
+    },
+    StateOk {
+        txn_id: String,
+        state: TxnState,
+        // the commit timestamp, if the sender knows it
+        #[serde(default)]
+        ts: u64,
+    },
+    // read-only transactions skip the commit protocol and read a snapshot at `ts` instead
+    SnapshotRead {
+        txn_id: String,
+        ts: u64,
+        ops: Vec<Op>,
+    },
+    SnapshotReadOk {
+        txn_id: String,
+        ok: bool,
+        ops: Vec<Op>,
+    },
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxnState {
+    Unknown,
+    Uncertain,
+    PreCommitted,
+    Committed,
+    Aborted,
+}
+
+// everything either role needs to come back from a crash. participants rebuild their store and
+// locks from it, along with the outcomes other participants may still ask them about during 3pc
+// termination, and coordinators the set of decisions not every participant has acknowledged yet.
+// once a transaction has ended everywhere, compacting the log drops all of it but the writes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Prepared {
+        txn_id: String,
+        coordinator: String,
+        #[serde(default)]
+        participants: Vec<String>,
+        keys: Vec<usize>,
+        writes: Vec<(usize, usize)>,
+        #[serde(default)]
+        ts: u64,
+        #[serde(default)]
+        snapshot: Option<u64>,
+    },
+    PreCommitted {
+        txn_id: String,
+        #[serde(default)]
+        ts: u64,
+    },
+    Committed {
+        txn_id: String,
+        #[serde(default)]
+        ts: u64,
+    },
+    Aborted {
+        txn_id: String,
+    },
+    Decision {
+        txn_id: String,
+        commit: bool,
+        participants: Vec<String>,
+        #[serde(default)]
+        ts: u64,
+        #[serde(default)]
+        outbound: bool,
+    },
+    End {
+        txn_id: String,
+    },
+    // we told a participant running the 3pc termination protocol that we're uncertain about the
+    // transaction, or know nothing of it, so the coordinator can no longer pre-commit it here
+    Fenced {
+        txn_id: String,
+    },
+    // every version in the store as of a compaction, as key, timestamp and value, and the
+    // horizon it had been collected up to
+    Stored {
+        horizon: u64,
+        versions: Vec<(usize, u64, usize)>,
+    },
+}
+
+struct Prepared {
+    coordinator: String,
+    participants: Vec<String>,
+    keys: Vec<usize>,
+    writes: Vec<(usize, usize)>,
+    // the commit timestamp we proposed, raised to the real one once the coordinator tells us. the
+    // transaction can't commit any earlier.
+    ts: u64,
+    snapshot: Option<u64>,
+    conflicts: Conflicts,
+    precommitted: bool,
+    last_heard: Instant,
+    // set while we're running the 3pc termination protocol for this transaction
+    termination: Option<Termination>,
+}
+
+struct Termination {
+    // when we last asked around, or sent our pre-commit
+    asked: Instant,
+    states: HashMap<String, TxnState>,
+    // the highest commit timestamp anyone reported
+    ts: u64,
+    // once someone turns out to have pre-committed, who still has to acknowledge our own
+    // pre-commit before we can commit
+    precommitting: Option<HashSet<String>>,
+}
+
+struct Active {
+    client: String,
+    client_msg_id: Option<usize>,
+    txn: Vec<Op>,
+    // which positions of `txn` each participant is responsible for
+    parts: HashMap<String, Vec<usize>>,
+    waiting_on: HashSet<String>,
+    precommitting: bool,
+    phase_started: Instant,
+    // the highest timestamp proposed so far
+    commit_ts: u64,
+    conflicts: Conflicts,
+    // the client operation it's for, which what we send for it from a tick goes under
+    correlation_id: Option<String>,
+}
+
+struct Snapshot {
+    client: String,
+    client_msg_id: Option<usize>,
+    txn: Vec<Op>,
+    parts: HashMap<String, Vec<usize>>,
+    waiting_on: HashSet<String>,
+    started: Instant,
+    correlation_id: Option<String>,
+}
+
+struct Decided {
+    participants: Vec<String>,
+    commit: bool,
+    ts: u64,
+    outbound: bool,
+    unacked: HashSet<String>,
+    last_sent: Instant,
+    // what the decision's resends go under, if we know
+    correlation_id: Option<String>,
+}
+
+pub struct TxnNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    protocol: Protocol,
+    isolation: Isolation,
+    wal: Wal<Record>,
+
+    clock: Hlc,
+
+    // participant
+    store: Mvcc<usize, usize>,
+    locks: HashMap<usize, String>,
+    prepared: HashMap<String, Prepared>,
+    // how the transactions we took part in ended, and at what timestamp, so we can tell others
+    // during 3pc termination
+    outcomes: HashMap<String, (bool, u64)>,
+    // the outcomes not every participant has acknowledged yet, which have to survive a crash
+    remembered: HashSet<String>,
+    // transactions that ended everywhere since the log was last compacted
+    forgotten: usize,
+    // 3pc only: the transactions we've told a participant terminating them that we're uncertain
+    // about, and won't take a pre-commit from their coordinator for
+    fenced: HashSet<String>,
+    // ssi only: every transaction that read a key here, with its snapshot, and the commit
+    // timestamps of the transactions that had an outbound conflict. neither is logged, so a
+    // participant that restarts checks transactions from before the crash as if under si.
+    sireads: HashMap<usize, HashMap<String, u64>>,
+    outbound_at: HashSet<u64>,
+
+    // coordinator
+    active: HashMap<String, Active>,
+    decided: HashMap<String, Decided>,
+    snapshots: HashMap<String, Snapshot>,
+}
+
+impl Node<(), Payload, InjectedPayload> for TxnNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let protocol = config::var_or("TXN_PROTOCOL", Protocol::TwoPhase)?;
+        let isolation = config::var_or("TXN_ISOLATION", Isolation::TwoPhaseLocking)?;
+        let (wal, records) = Wal::open(wal::data_dir().join(format!("{}.txn.wal", init.node_id)))
+            .context("open txn wal")?;
+        ticks::every(Duration::from_millis(100), tx, || {
+            Event::Injected(InjectedPayload::Tick)
+        });
+        let mut node = Self {
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            protocol,
+            isolation,
+            wal,
+            clock: Hlc::new(),
+            store: Mvcc::new(),
+            locks: HashMap::new(),
+            prepared: HashMap::new(),
+            outcomes: HashMap::new(),
+            remembered: HashSet::new(),
+            forgotten: 0,
+            fenced: HashSet::new(),
+            sireads: HashMap::new(),
+            outbound_at: HashSet::new(),
+            active: HashMap::new(),
+            decided: HashMap::new(),
+            snapshots: HashMap::new(),
+        };
+        for record in records {
+            node.replay(record);
+        }
+        if protocol == Protocol::TwoPhase {
+            // we were our own participant for these. nobody else is going to tell us how they
+            // ended, so go by our own decision log, and presume abort if we never got that far.
+            // under 3pc the termination protocol takes care of them like any other.
+            let orphaned: Vec<_> = node
+                .prepared
+                .iter()
+                .filter(|(_, prepared)| prepared.coordinator == node.node)
+                .map(|(txn_id, _)| txn_id.clone())
+                .collect();
+            for txn_id in orphaned {
+                let (commit, ts, outbound) = node
+                    .decided
+                    .get(&txn_id)
+                    .map_or((false, 0, false), |d| (d.commit, d.ts, d.outbound));
+                node.apply_decision(&txn_id, commit, ts, outbound)?;
+            }
+        }
+        if !node.prepared.is_empty() || !node.decided.is_empty() {
+            log::info!(
+                "recovered {} in-doubt and {} unacknowledged transactions",
+                node.prepared.len(),
+                node.decided.len()
+            );
+        }
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => self.tick(output)?,
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Txn { txn } => {
+                        self.begin(src, reply.body.in_reply_to, txn, output)?;
+                    }
+                    Payload::Prepare {
+                        txn_id,
+                        mut ops,
+                        participants,
+                        snapshot,
+                    } => {
+                        let yes = self.prepare(&txn_id, &src, participants, snapshot, &mut ops)?;
+                        let ts = self.known_ts(&txn_id);
+                        let conflicts = self.conflicts_of(&txn_id);
+                        reply.body.payload = Payload::Vote {
+                            txn_id,
+                            yes,
+                            ops,
+                            ts,
+                            conflicts,
+                        };
+                        reply.send(&mut *output).context("reply to prepare")?;
+                    }
+                    Payload::Vote {
+                        txn_id,
+                        yes,
+                        ops,
+                        ts,
+                        conflicts,
+                    } => {
+                        self.vote(&txn_id, &src, yes, ops, ts, conflicts, output)?;
+                    }
+                    Payload::PreCommit {
+                        txn_id,
+                        ts,
+                        terminating,
+                    } => {
+                        let ok = self.precommit(&txn_id, ts, terminating)?;
+                        reply.body.payload = Payload::PreCommitOk { txn_id, ok };
+                        reply.send(&mut *output).context("reply to pre-commit")?;
+                    }
+                    Payload::PreCommitOk { txn_id, ok } => {
+                        if self.active.contains_key(&txn_id) {
+                            self.precommitted(&txn_id, &src, ok, output)?;
+                        } else {
+                            self.termination_precommitted(&txn_id, &src, ok);
+                        }
+                    }
+                    Payload::Decide {
+                        txn_id,
+                        commit,
+                        ts,
+                        outbound,
+                    } => {
+                        if let Some(active) = self.active.get_mut(&txn_id) {
+                            // the participants terminated without us, go along with them
+                            active.commit_ts = active.commit_ts.max(ts);
+                            active.conflicts.outbound |= outbound;
+                            self.decide(&txn_id, commit, output)?;
+                        } else {
+                            self.apply_decision(&txn_id, commit, ts, outbound)?;
+                        }
+                        reply.body.payload = Payload::DecideOk { txn_id };
+                        reply.send(&mut *output).context("reply to decide")?;
+                    }
+                    Payload::DecideOk { txn_id } => {
+                        self.acked(&txn_id, &src, output)?;
+                    }
+                    Payload::Forget { txn_id } => {
+                        if self.remembered.remove(&txn_id) {
+                            self.wal
+                                .append(&Record::End { txn_id })
+                                .context("log end")?;
+                            self.forgotten += 1;
+                        }
+                    }
+                    Payload::Query { txn_id } => {
+                        let (commit, ts, outbound) = if let Some(d) = self.decided.get(&txn_id) {
+                            (d.commit, d.ts, d.outbound)
+                        } else if self.active.contains_key(&txn_id) {
+                            // still collecting votes, they'll hear from us soon enough
+                            return Ok(());
+                        } else {
+                            // never decided, or decided to abort and forgot about it. either way
+                            // it can't have committed.
+                            (false, 0, false)
+                        };
+                        reply.body.payload = Payload::Decide {
+                            txn_id,
+                            commit,
+                            ts,
+                            outbound,
+                        };
+                        reply.send(&mut *output).context("reply to query")?;
+                    }
+                    Payload::StateReq { txn_id } => {
+                        let state = self.state_of(&txn_id);
+                        // it may abort on the strength of this, so we can't turn out to have
+                        // pre-committed after all
+                        let fence = match state {
+                            TxnState::Unknown => true,
+                            TxnState::Uncertain => self.prepared.contains_key(&txn_id),
+                            _ => false,
+                        };
+                        if fence {
+                            self.fence(&txn_id)?;
+                        }
+                        let ts = self.known_ts(&txn_id);
+                        reply.body.payload = Payload::StateOk { txn_id, state, ts };
+                        reply.send(&mut *output).context("reply to state request")?;
+                    }
+                    Payload::StateOk { txn_id, state, ts } => {
+                        if let Some(termination) = self
+                            .prepared
+                            .get_mut(&txn_id)
+                            .and_then(|prepared| prepared.termination.as_mut())
+                        {
+                            termination.states.insert(src, state);
+                            termination.ts = termination.ts.max(ts);
+                        }
+                    }
+                    Payload::SnapshotRead {
+                        txn_id,
+                        ts,
+                        mut ops,
+                    } => {
+                        let ok = self.read_snapshot(ts, &mut ops);
+                        reply.body.payload = Payload::SnapshotReadOk { txn_id, ok, ops };
+                        reply.send(&mut *output).context("reply to snapshot read")?;
+                    }
+                    Payload::SnapshotReadOk { txn_id, ok, ops } => {
+                        self.snapshot_read(&txn_id, &src, ok, ops, output)?;
+                    }
+                    Payload::TxnOk { .. } | Payload::Error { .. } => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // the store, whatever is still in doubt or unacknowledged, and the outcomes another
+    // participant may still ask about are all a restart needs
+    fn snapshot(&mut self) -> anyhow::Result<()> {
+        let mut records = vec![Record::Stored {
+            horizon: self.store.horizon(),
+            versions: self
+                .store
+                .versions()
+                .map(|(&k, ts, &v)| (k, ts, v))
+                .collect(),
+        }];
+        for (txn_id, prepared) in &self.prepared {
+            records.push(Record::Prepared {
+                txn_id: txn_id.clone(),
+                coordinator: prepared.coordinator.clone(),
+                participants: prepared.participants.clone(),
+                keys: prepared.keys.clone(),
+                writes: prepared.writes.clone(),
+                ts: prepared.ts,
+                snapshot: prepared.snapshot,
+            });
+            if prepared.precommitted {
+                records.push(Record::PreCommitted {
+                    txn_id: txn_id.clone(),
+                    ts: prepared.ts,
+                });
+            }
+        }
+        for txn_id in &self.remembered {
+            let Some(&(commit, ts)) = self.outcomes.get(txn_id) else {
+                continue;
+            };
+            let txn_id = txn_id.clone();
+            records.push(if commit {
+                Record::Committed { txn_id, ts }
+            } else {
+                Record::Aborted { txn_id }
+            });
+        }
+        for txn_id in &self.fenced {
+            records.push(Record::Fenced {
+                txn_id: txn_id.clone(),
+            });
+        }
+        for (txn_id, decided) in &self.decided {
+            records.push(Record::Decision {
+                txn_id: txn_id.clone(),
+                commit: decided.commit,
+                participants: decided.participants.clone(),
+                ts: decided.ts,
+                outbound: decided.outbound,
+            });
+        }
+        self.wal.rewrite(&records).context("rewrite txn wal")?;
+        self.forgotten = 0;
+        Ok(())
+    }
+}
+
+impl TxnNode {
+    fn owner(&self, key: usize) -> &str {
+        &self.nodes[key % self.nodes.len()]
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn replay(&mut self, record: Record) {
+        match record {
+            Record::Prepared {
+                txn_id,
+                coordinator,
+                participants,
+                keys,
+                writes,
+                ts,
+                snapshot,
+            } => {
+                self.clock.observe(Timestamp::from_u64(ts));
+                for &k in &keys {
+                    self.locks.insert(k, txn_id.clone());
+                }
+                self.prepared.insert(
+                    txn_id,
+                    Prepared {
+                        coordinator,
+                        participants,
+                        keys,
+                        writes,
+                        ts,
+                        snapshot,
+                        conflicts: Conflicts::default(),
+                        precommitted: false,
+                        last_heard: clock::now(),
+                        termination: None,
+                    },
+                );
+            }
+            Record::PreCommitted { txn_id, ts } => {
+                if let Some(prepared) = self.prepared.get_mut(&txn_id) {
+                    prepared.precommitted = true;
+                    prepared.ts = prepared.ts.max(ts);
+                }
+            }
+            Record::Committed { txn_id, ts } => {
+                if self.resolve(&txn_id, true, ts).is_none() {
+                    // kept through a compaction
+                    self.outcomes.insert(txn_id.clone(), (true, ts));
+                    self.remembered.insert(txn_id);
+                }
+            }
+            Record::Aborted { txn_id } => {
+                if self.resolve(&txn_id, false, 0).is_none() {
+                    // we voted no, or it was kept through a compaction
+                    self.outcomes.insert(txn_id.clone(), (false, 0));
+                    self.remembered.insert(txn_id);
+                }
+            }
+            Record::Decision {
+                txn_id,
+                commit,
+                participants,
+                ts,
+                outbound,
+            } => {
+                self.decided.insert(
+                    txn_id,
+                    Decided {
+                        unacked: participants
+                            .iter()
+                            .filter(|p| **p != self.node)
+                            .cloned()
+                            .collect(),
+                        participants,
+                        commit,
+                        ts,
+                        outbound,
+                        // make sure the first tick after recovery re-sends it
+                        last_sent: clock::now() - RETRY_INTERVAL,
+                        correlation_id: None,
+                    },
+                );
+            }
+            Record::End { txn_id } => {
+                self.decided.remove(&txn_id);
+                self.remembered.remove(&txn_id);
+            }
+            Record::Fenced { txn_id } => {
+                if !self.outcomes.contains_key(&txn_id) {
+                    self.fenced.insert(txn_id);
+                }
+            }
+            Record::Stored { horizon, versions } => {
+                for (k, ts, v) in versions {
+                    self.clock.observe(Timestamp::from_u64(ts));
+                    self.store.write(k, ts, v);
+                }
+                self.store.gc(horizon);
+            }
+        }
+    }
+
+    fn begin(
+        &mut self,
+        client: String,
+        client_msg_id: Option<usize>,
+        txn: Vec<Op>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let txn_id = format!("{}-{}", self.node, rng::ulid());
+        let mut parts: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, op) in txn.iter().enumerate() {
+            parts
+                .entry(self.owner(op.key()).to_string())
+                .or_default()
+                .push(i);
+        }
+        // ssi has to see what read-only transactions read like any other transaction's reads,
+        // or it can't tell when one of them completes a cycle
+        if !txn.iter().any(Op::is_write) && self.isolation != Isolation::SerializableSnapshot {
+            return self.begin_snapshot(txn_id, client, client_msg_id, txn, parts, output);
+        }
+        let snapshot = match self.isolation {
+            Isolation::TwoPhaseLocking => None,
+            Isolation::Snapshot | Isolation::SerializableSnapshot => {
+                Some(self.clock.now().as_u64())
+            }
+        };
+        let participants: Vec<_> = parts.keys().cloned().collect();
+        self.active.insert(
+            txn_id.clone(),
+            Active {
+                client,
+                client_msg_id,
+                txn,
+                waiting_on: parts.keys().cloned().collect(),
+                parts,
+                precommitting: false,
+                phase_started: clock::now(),
+                commit_ts: 0,
+                conflicts: Conflicts::default(),
+                correlation_id: correlation::current(),
+            },
+        );
+
+        for participant in &participants {
+            let Some(active) = self.active.get(&txn_id) else {
+                // our own vote already aborted it
+                break;
+            };
+            let mut ops: Vec<_> = active.parts[participant]
+                .iter()
+                .map(|&i| active.txn[i].clone())
+                .collect();
+            if *participant == self.node {
+                let me = self.node.clone();
+                let yes = self.prepare(&txn_id, &me, participants.clone(), snapshot, &mut ops)?;
+                let ts = self.known_ts(&txn_id);
+                let conflicts = self.conflicts_of(&txn_id);
+                self.vote(&txn_id, &me, yes, ops, ts, conflicts, output)?;
+            } else {
+                self.send(
+                    participant,
+                    Payload::Prepare {
+                        txn_id: txn_id.clone(),
+                        ops,
+                        participants: participants.clone(),
+                        snapshot,
+                    },
+                    output,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    // participant side of phase one. under 2pl, reads are served under the same locks that protect
+    // the writes, so the values we hand back are the ones the transaction commits against. under
+    // si and ssi they come from the snapshot and only writes are locked.
+    fn prepare(
+        &mut self,
+        txn_id: &str,
+        coordinator: &str,
+        participants: Vec<String>,
+        snapshot: Option<u64>,
+        ops: &mut [Op],
+    ) -> anyhow::Result<bool> {
+        if let Some(prepared) = self.prepared.get(txn_id) {
+            // duplicate prepare, we already voted yes
+            for op in ops.iter_mut().filter(|op| !op.is_write()) {
+                op.2 = match prepared.snapshot {
+                    Some(snapshot) => self.store.read_at(&op.key(), snapshot),
+                    None => self.store.latest(&op.key()),
+                }
+                .copied();
+            }
+            return Ok(true);
+        }
+        if let Some(&(commit, _)) = self.outcomes.get(txn_id) {
+            // a prepare that showed up after the transaction was already terminated
+            return Ok(commit);
+        }
+
+        // we already told someone terminating it that we know nothing of it
+        let fenced = self.fenced.remove(txn_id);
+        let checked = match snapshot {
+            _ if fenced => None,
+            None => {
+                let keys: HashSet<usize> = ops.iter().map(Op::key).collect();
+                let free = !keys.iter().any(|k| self.locks.contains_key(k));
+                free.then_some((keys, Conflicts::default()))
+            }
+            Some(snapshot) => self.check_snapshot(txn_id, snapshot, ops),
+        };
+        let Some((keys, conflicts)) = checked else {
+            // the other participants may still ask us about it
+            self.wal
+                .append(&Record::Aborted {
+                    txn_id: txn_id.to_string(),
+                })
+                .context("log no vote")?;
+            self.outcomes.insert(txn_id.to_string(), (false, 0));
+            self.remembered.insert(txn_id.to_string());
+            return Ok(false);
+        };
+
+        let mut writes = Vec::new();
+        for op in ops.iter_mut() {
+            if op.is_write() {
+                writes.push((op.key(), op.2.context("write without a value")?));
+            } else {
+                // reads observe earlier writes of the same transaction
+                op.2 = writes
+                    .iter()
+                    .rev()
+                    .find(|(k, _)| *k == op.key())
+                    .map(|(_, v)| *v)
+                    .or_else(|| match snapshot {
+                        Some(snapshot) => self.store.read_at(&op.key(), snapshot).copied(),
+                        None => self.store.latest(&op.key()).copied(),
+                    });
+            }
+        }
+        if self.isolation == Isolation::SerializableSnapshot {
+            for op in ops.iter().filter(|op| !op.is_write()) {
+                self.sireads
+                    .entry(op.key())
+                    .or_default()
+                    .insert(txn_id.to_string(), snapshot.unwrap_or_default());
+            }
+        }
+        let record = Record::Prepared {
+            txn_id: txn_id.to_string(),
+            coordinator: coordinator.to_string(),
+            participants,
+            keys: keys.into_iter().collect(),
+            writes,
+            // later than anything we've committed or served a snapshot at
+            ts: self.clock.now().as_u64(),
+            snapshot,
+        };
+        self.wal.append(&record).context("log prepare")?;
+        self.replay(record);
+        if let Some(prepared) = self.prepared.get_mut(txn_id) {
+            prepared.conflicts = conflicts;
+        }
+        Ok(true)
+    }
+
+    // decides whether a transaction reading from `snapshot` can go ahead here, and if so what it
+    // has to lock and which conflicts it's part of. under ssi anything still in flight that it
+    // would conflict with is refused outright, so the rw antidependencies we track are only ever
+    // with transactions that have already committed.
+    fn check_snapshot(
+        &mut self,
+        txn_id: &str,
+        snapshot: u64,
+        ops: &[Op],
+    ) -> Option<(HashSet<usize>, Conflicts)> {
+        self.clock.observe(Timestamp::from_u64(snapshot));
+        if snapshot < self.store.horizon() {
+            return None;
+        }
+        let ssi = self.isolation == Isolation::SerializableSnapshot;
+        let writes: HashSet<usize> = ops.iter().filter(|op| op.is_write()).map(Op::key).collect();
+        let reads: HashSet<usize> = ops
+            .iter()
+            .filter(|op| !op.is_write())
+            .map(Op::key)
+            .collect();
+        for &k in &writes {
+            // someone else is writing it, or has written it since our snapshot: first committer
+            // wins
+            if self.locks.contains_key(&k)
+                || self.store.written_after(&k, snapshot).next().is_some()
+            {
+                return None;
+            }
+            // a reader still in flight
+            if ssi
+                && self.sireads.get(&k).is_some_and(|readers| {
+                    readers
+                        .keys()
+                        .any(|r| r != txn_id && self.prepared.contains_key(r))
+                })
+            {
+                return None;
+            }
+        }
+        for &k in &reads {
+            // a writer that might still commit before our snapshot, or under ssi at all
+            if self.locks.get(&k).is_some_and(|writer| {
+                ssi || self.prepared.get(writer).is_some_and(|w| w.ts <= snapshot)
+            }) {
+                return None;
+            }
+        }
+        if !ssi {
+            return Some((writes, Conflicts::default()));
+        }
+
+        let mut conflicts = Conflicts::default();
+        for &k in &reads {
+            for ts in self.store.written_after(&k, snapshot) {
+                conflicts.outbound = true;
+                conflicts.outbound_to_pivot |= self.outbound_at.contains(&ts);
+            }
+        }
+        for &k in &writes {
+            let Some(readers) = self.sireads.get(&k) else {
+                continue;
+            };
+            // readers that committed after our snapshot was taken ran concurrently with us and
+            // didn't see what we're about to write
+            conflicts.inbound |= readers.keys().any(|r| {
+                r != txn_id
+                    && self
+                        .outcomes
+                        .get(r)
+                        .is_some_and(|&(commit, ts)| commit && ts > snapshot)
+            });
+        }
+        Some((writes, conflicts))
+    }
+
+    fn conflicts_of(&self, txn_id: &str) -> Conflicts {
+        self.prepared
+            .get(txn_id)
+            .map_or(Conflicts::default(), |p| p.conflicts)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn vote(
+        &mut self,
+        txn_id: &str,
+        participant: &str,
+        yes: bool,
+        ops: Vec<Op>,
+        ts: u64,
+        conflicts: Conflicts,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(active) = self.active.get_mut(txn_id) else {
+            // late vote for something we already decided on
+            return Ok(());
+        };
+        if active.precommitting || !active.waiting_on.remove(participant) {
+            return Ok(());
+        }
+        if !yes {
+            return self.decide(txn_id, false, output);
+        }
+        active.commit_ts = active.commit_ts.max(ts);
+        active.conflicts.merge(conflicts);
+        for (&i, op) in active.parts[participant].iter().zip(ops) {
+            active.txn[i] = op;
+        }
+        if !active.waiting_on.is_empty() {
+            return Ok(());
+        }
+        if active.conflicts.dangerous() {
+            log::debug!("aborting {}: {:?}", txn_id, active.conflicts);
+            return self.decide(txn_id, false, output);
+        }
+        match self.protocol {
+            Protocol::TwoPhase => self.decide(txn_id, true, output),
+            Protocol::ThreePhase => self.start_precommit(txn_id, output),
+        }
+    }
+
+    // 3pc's extra round: nobody commits until everybody knows that everybody voted yes, so a
+    // participant that is still merely prepared knows the transaction can't have committed yet.
+    fn start_precommit(&mut self, txn_id: &str, output: &mut Output) -> anyhow::Result<()> {
+        let active = self
+            .active
+            .get_mut(txn_id)
+            .expect("pre-committing inactive txn");
+        active.precommitting = true;
+        active.phase_started = clock::now();
+        active.waiting_on = active.parts.keys().cloned().collect();
+        let ts = active.commit_ts;
+        let participants: Vec<_> = active.waiting_on.iter().cloned().collect();
+        for participant in participants {
+            if participant == self.node {
+                let ok = self.precommit(txn_id, ts, false)?;
+                let me = self.node.clone();
+                self.precommitted(txn_id, &me, ok, output)?;
+            } else {
+                self.send(
+                    &participant,
+                    Payload::PreCommit {
+                        txn_id: txn_id.to_string(),
+                        ts,
+                        terminating: false,
+                    },
+                    output,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    fn precommit(&mut self, txn_id: &str, ts: u64, terminating: bool) -> anyhow::Result<bool> {
+        let Some(prepared) = self.prepared.get_mut(txn_id) else {
+            // we timed out and terminated this one already
+            return Ok(self.outcomes.get(txn_id).is_some_and(|&(commit, _)| commit));
+        };
+        if !prepared.precommitted && !terminating && self.fenced.contains(txn_id) {
+            return Ok(false);
+        }
+        if !prepared.precommitted {
+            prepared.precommitted = true;
+            prepared.ts = prepared.ts.max(ts);
+            prepared.last_heard = clock::now();
+            self.wal
+                .append(&Record::PreCommitted {
+                    txn_id: txn_id.to_string(),
+                    ts,
+                })
+                .context("log pre-commit")?;
+        }
+        Ok(true)
+    }
+
+    fn precommitted(
+        &mut self,
+        txn_id: &str,
+        participant: &str,
+        ok: bool,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(active) = self.active.get_mut(txn_id) else {
+            return Ok(());
+        };
+        if !active.precommitting || !active.waiting_on.contains(participant) {
+            return Ok(());
+        }
+        if !ok {
+            // it's told someone terminating the transaction that it's uncertain, which makes the
+            // outcome theirs: an abort could be as wrong as a commit if they've found a
+            // pre-commit elsewhere. they'll tell us how it went.
+            log::info!(
+                "{} left {} to the termination protocol",
+                participant,
+                txn_id
+            );
+            return Ok(());
+        }
+        active.waiting_on.remove(participant);
+        if active.waiting_on.is_empty() {
+            self.decide(txn_id, true, output)?;
+        }
+        Ok(())
+    }
+
+    fn decide(&mut self, txn_id: &str, commit: bool, output: &mut Output) -> anyhow::Result<()> {
+        let active = self
+            .active
+            .remove(txn_id)
+            .expect("deciding on a transaction that isn't active");
+        let participants: Vec<String> = active.parts.into_keys().collect();
+        let ts = active.commit_ts;
+        let outbound = active.conflicts.outbound;
+        // this is the commit point: once the decision is on disk it will reach every participant,
+        // even if we crash right after writing it.
+        self.wal
+            .append(&Record::Decision {
+                txn_id: txn_id.to_string(),
+                commit,
+                participants: participants.clone(),
+                ts,
+                outbound,
+            })
+            .context("log decision")?;
+
+        let mut unacked = HashSet::new();
+        for participant in &participants {
+            if *participant == self.node {
+                self.apply_decision(txn_id, commit, ts, outbound)?;
+            } else {
+                self.send(
+                    participant,
+                    Payload::Decide {
+                        txn_id: txn_id.to_string(),
+                        commit,
+                        ts,
+                        outbound,
+                    },
+                    output,
+                )?;
+                unacked.insert(participant.clone());
+            }
+        }
+        if unacked.is_empty() {
+            self.end(txn_id, &participants, output)?;
+        } else {
+            self.decided.insert(
+                txn_id.to_string(),
+                Decided {
+                    participants,
+                    commit,
+                    ts,
+                    outbound,
+                    unacked,
+                    last_sent: clock::now(),
+                    correlation_id: active.correlation_id.clone(),
+                },
+            );
+        }
+
+        let payload = if commit {
+            Payload::TxnOk { txn: active.txn }
+        } else {
+            Payload::Error {
+                code: error::TXN_CONFLICT,
+                text: format!("transaction {} aborted", txn_id),
+            }
+        };
+        self.reply_client(active.client, active.client_msg_id, payload, output)
+    }
+
+    fn apply_decision(
+        &mut self,
+        txn_id: &str,
+        commit: bool,
+        ts: u64,
+        outbound: bool,
+    ) -> anyhow::Result<()> {
+        if !self.prepared.contains_key(txn_id) {
+            // we either voted no or have already applied it
+            return Ok(());
+        }
+        let record = if commit {
+            Record::Committed {
+                txn_id: txn_id.to_string(),
+                ts,
+            }
+        } else {
+            Record::Aborted {
+                txn_id: txn_id.to_string(),
+            }
+        };
+        self.wal.append(&record).context("log outcome")?;
+        if let Some(ts) = self.resolve(txn_id, commit, ts) {
+            if commit && outbound {
+                self.outbound_at.insert(ts);
+            }
+        }
+        Ok(())
+    }
+
+    // returns the timestamp the transaction ended at, unless it had already
+    fn resolve(&mut self, txn_id: &str, commit: bool, ts: u64) -> Option<u64> {
+        let prepared = self.prepared.remove(txn_id)?;
+        for k in prepared.keys {
+            self.locks.remove(&k);
+        }
+        // logs from before commit timestamps existed say 0. the prepare timestamp is still a
+        // safe place to put the writes.
+        let ts = ts.max(prepared.ts);
+        if commit {
+            self.clock.observe(Timestamp::from_u64(ts));
+            for (k, v) in prepared.writes {
+                self.store.write(k, ts, v);
+            }
+        }
+        self.outcomes.insert(txn_id.to_string(), (commit, ts));
+        self.remembered.insert(txn_id.to_string());
+        self.fenced.remove(txn_id);
+        Some(ts)
+    }
+
+    fn acked(
+        &mut self,
+        txn_id: &str,
+        participant: &str,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(decided) = self.decided.get_mut(txn_id) else {
+            return Ok(());
+        };
+        decided.unacked.remove(participant);
+        if decided.unacked.is_empty() {
+            let participants = std::mem::take(&mut decided.participants);
+            self.decided.remove(txn_id);
+            self.end(txn_id, &participants, output)?;
+        }
+        Ok(())
+    }
+
+    // every participant knows the outcome, so nothing about the transaction has to survive a
+    // crash any more, here or at any of them. a participant that misses the news keeps its
+    // outcome logged, which costs it some log and nothing else.
+    fn end(
+        &mut self,
+        txn_id: &str,
+        participants: &[String],
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        self.wal
+            .append(&Record::End {
+                txn_id: txn_id.to_string(),
+            })
+            .context("log end")?;
+        self.remembered.remove(txn_id);
+        self.forgotten += 1;
+        for participant in participants.iter().filter(|p| **p != self.node) {
+            let forget = Payload::Forget {
+                txn_id: txn_id.to_string(),
+            };
+            self.send(participant, forget, output)?;
+        }
+        Ok(())
+    }
+
+    fn state_of(&self, txn_id: &str) -> TxnState {
+        if let Some(&(commit, _)) = self.outcomes.get(txn_id) {
+            return if commit {
+                TxnState::Committed
+            } else {
+                TxnState::Aborted
+            };
+        }
+        if let Some(prepared) = self.prepared.get(txn_id) {
+            return if prepared.precommitted {
+                TxnState::PreCommitted
+            } else {
+                TxnState::Uncertain
+            };
+        }
+        // we may be the coordinator without holding any of the keys
+        if let Some(decided) = self.decided.get(txn_id) {
+            return if decided.commit {
+                TxnState::Committed
+            } else {
+                TxnState::Aborted
+            };
+        }
+        match self.active.get(txn_id) {
+            Some(active) if active.precommitting => TxnState::PreCommitted,
+            Some(_) => TxnState::Uncertain,
+            None => TxnState::Unknown,
+        }
+    }
+
+    // the transaction's commit timestamp as far as we know it, or a lower bound on it: what we
+    // proposed as a participant or have heard proposed as the coordinator
+    fn known_ts(&self, txn_id: &str) -> u64 {
+        if let Some(&(_, ts)) = self.outcomes.get(txn_id) {
+            ts
+        } else if let Some(prepared) = self.prepared.get(txn_id) {
+            prepared.ts
+        } else if let Some(decided) = self.decided.get(txn_id) {
+            decided.ts
+        } else {
+            self.active.get(txn_id).map_or(0, |a| a.commit_ts)
+        }
+    }
+
+    // we've told a participant running the termination protocol that we're uncertain, and it may
+    // abort on the strength of it, so from now on the coordinator can't pre-commit it here. it
+    // has to last through a crash as much as the transaction's outcome does.
+    fn fence(&mut self, txn_id: &str) -> anyhow::Result<()> {
+        if self.fenced.insert(txn_id.to_string()) {
+            self.wal
+                .append(&Record::Fenced {
+                    txn_id: txn_id.to_string(),
+                })
+                .context("log fence")?;
+        }
+        Ok(())
+    }
+
+    // termination found someone pre-committed, so everyone voted yes. we pre-commit ourselves and
+    // everyone who isn't yet, and commit once they all have.
+    fn precommit_everyone(&mut self, txn_id: &str, output: &mut Output) -> anyhow::Result<()> {
+        let Some(prepared) = self.prepared.get_mut(txn_id) else {
+            return Ok(());
+        };
+        let Some(termination) = prepared.termination.as_mut() else {
+            return Ok(());
+        };
+        let waiting: HashSet<_> = prepared
+            .participants
+            .iter()
+            .filter(|p| **p != self.node)
+            .filter(|p| termination.states.get(*p) != Some(&TxnState::PreCommitted))
+            .cloned()
+            .collect();
+        termination.asked = clock::now();
+        termination.precommitting = Some(waiting.clone());
+        let ts = termination.ts;
+        self.precommit(txn_id, ts, true)?;
+        for participant in waiting {
+            let precommit = Payload::PreCommit {
+                txn_id: txn_id.to_string(),
+                ts,
+                terminating: true,
+            };
+            self.send(&participant, precommit, output)?;
+        }
+        Ok(())
+    }
+
+    fn termination_precommitted(&mut self, txn_id: &str, participant: &str, ok: bool) {
+        let Some(waiting) = self
+            .prepared
+            .get_mut(txn_id)
+            .and_then(|prepared| prepared.termination.as_mut())
+            .and_then(|termination| termination.precommitting.as_mut())
+        else {
+            return;
+        };
+        if ok {
+            waiting.remove(participant);
+        } else {
+            // only a participant that's already heard how it ended would refuse
+            log::warn!("{} refused to pre-commit {}", participant, txn_id);
+        }
+    }
+
+    // a read-only transaction reads every key as of one timestamp, taken from our clock, without
+    // locking anything or logging anything, so it never holds up writers
+    fn begin_snapshot(
+        &mut self,
+        txn_id: String,
+        client: String,
+        client_msg_id: Option<usize>,
+        txn: Vec<Op>,
+        parts: HashMap<String, Vec<usize>>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let ts = self.clock.now().as_u64();
+        let snapshot = Snapshot {
+            client,
+            client_msg_id,
+            waiting_on: parts.keys().cloned().collect(),
+            txn,
+            parts,
+            started: clock::now(),
+            correlation_id: correlation::current(),
+        };
+        let reads: Vec<(String, Vec<Op>)> = snapshot
+            .parts
+            .iter()
+            .map(|(p, positions)| {
+                let ops = positions.iter().map(|&i| snapshot.txn[i].clone()).collect();
+                (p.clone(), ops)
+            })
+            .collect();
+        self.snapshots.insert(txn_id.clone(), snapshot);
+        for (participant, mut ops) in reads {
+            if participant == self.node {
+                let ok = self.read_snapshot(ts, &mut ops);
+                let me = self.node.clone();
+                self.snapshot_read(&txn_id, &me, ok, ops, output)?;
+            } else {
+                let read = Payload::SnapshotRead {
+                    txn_id: txn_id.clone(),
+                    ts,
+                    ops,
+                };
+                self.send(&participant, read, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    // participant side of a snapshot read. a prepared transaction on one of the keys might
+    // still commit at or before `ts`, and we can't know which way it goes, so we refuse instead.
+    fn read_snapshot(&mut self, ts: u64, ops: &mut [Op]) -> bool {
+        // whatever commits here from now on sorts after the snapshot
+        self.clock.observe(Timestamp::from_u64(ts));
+        if ts < self.store.horizon() {
+            return false;
+        }
+        let in_doubt = ops.iter().any(|op| {
+            self.locks
+                .get(&op.key())
+                .is_some_and(|txn_id| self.prepared[txn_id].ts <= ts)
+        });
+        if in_doubt {
+            return false;
+        }
+        for op in ops {
+            op.2 = self.store.read_at(&op.key(), ts).copied();
+        }
+        true
+    }
+
+    fn snapshot_read(
+        &mut self,
+        txn_id: &str,
+        participant: &str,
+        ok: bool,
+        ops: Vec<Op>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some(snapshot) = self.snapshots.get_mut(txn_id) else {
+            return Ok(());
+        };
+        if !snapshot.waiting_on.remove(participant) {
+            return Ok(());
+        }
+        let payload = if ok {
+            for (&i, op) in snapshot.parts[participant].iter().zip(ops) {
+                snapshot.txn[i] = op;
+            }
+            if !snapshot.waiting_on.is_empty() {
+                return Ok(());
+            }
+            Payload::TxnOk {
+                txn: std::mem::take(&mut snapshot.txn),
+            }
+        } else {
+            Payload::Error {
+                code: error::TXN_CONFLICT,
+                text: format!("snapshot {} ran into a transaction in flight", txn_id),
+            }
+        };
+        let snapshot = self.snapshots.remove(txn_id).expect("just had it");
+        self.reply_client(snapshot.client, snapshot.client_msg_id, payload, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        client: String,
+        msg_id: Option<usize>,
+        payload: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to txn")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn tick(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let expired: Vec<_> = self
+            .active
+            .iter_mut()
+            .filter(|(_, active)| clock::since(active.phase_started) > PHASE_TIMEOUT)
+            .map(|(txn_id, active)| {
+                active.phase_started = clock::now();
+                (txn_id.clone(), active.precommitting)
+            })
+            .collect();
+        for (txn_id, precommitting) in expired {
+            let correlation_id = self.active[&txn_id].correlation_id.clone();
+            if !precommitting {
+                correlation::stepping(correlation_id, || self.decide(&txn_id, false, output))?;
+                continue;
+            }
+            // a participant we haven't heard from may be running the termination protocol by
+            // now, so committing without it is no safer than aborting. keep asking until it
+            // answers or whoever's terminating the transaction tells us how it went.
+            let active = &self.active[&txn_id];
+            let ts = active.commit_ts;
+            correlation::stepping(correlation_id, || {
+                for participant in active.waiting_on.iter().filter(|p| **p != self.node) {
+                    let precommit = Payload::PreCommit {
+                        txn_id: txn_id.clone(),
+                        ts,
+                        terminating: false,
+                    };
+                    self.send(participant, precommit, output)?;
+                }
+                Ok(())
+            })?;
+        }
+
+        let expired: Vec<_> = self
+            .snapshots
+            .iter()
+            .filter(|(_, snapshot)| clock::since(snapshot.started) > PHASE_TIMEOUT)
+            .map(|(txn_id, _)| txn_id.clone())
+            .collect();
+        for txn_id in expired {
+            let snapshot = self.snapshots.remove(&txn_id).expect("just listed it");
+            let error = Payload::Error {
+                code: error::TEMPORARILY_UNAVAILABLE,
+                text: format!("not every participant answered snapshot {}", txn_id),
+            };
+            correlation::stepping(snapshot.correlation_id, || {
+                self.reply_client(snapshot.client, snapshot.client_msg_id, error, output)
+            })?;
+        }
+
+        let now = self.clock.now();
+        let horizon = Timestamp {
+            wall_ms: now
+                .wall_ms
+                .saturating_sub(SNAPSHOT_RETENTION.as_millis() as u64),
+            logical: 0,
+        };
+        self.store.gc(horizon.as_u64());
+        // a transaction that read before the horizon, or aborted, can't conflict with anything
+        // that starts from now on
+        let horizon = self.store.horizon();
+        for readers in self.sireads.values_mut() {
+            readers.retain(|r, _| {
+                self.outcomes
+                    .get(r)
+                    .is_none_or(|&(commit, ts)| commit && ts >= horizon)
+            });
+        }
+        self.sireads.retain(|_, readers| !readers.is_empty());
+        self.outbound_at.retain(|&ts| ts >= horizon);
+
+        for (txn_id, decided) in &mut self.decided {
+            if clock::since(decided.last_sent) < RETRY_INTERVAL {
+                continue;
+            }
+            decided.last_sent = clock::now();
+            for participant in &decided.unacked {
+                Message {
+                    src: self.node.clone(),
+                    dst: participant.clone(),
+                    body: Body {
+                        id: None,
+                        in_reply_to: None,
+                        correlation_id: decided.correlation_id.clone(),
+                        payload: Payload::Decide {
+                            txn_id: txn_id.clone(),
+                            commit: decided.commit,
+                            ts: decided.ts,
+                            outbound: decided.outbound,
+                        },
+                    },
+                }
+                .send(&mut *output)
+                .with_context(|| format!("resend decision to {}", participant))?;
+            }
+        }
+
+        if self.forgotten >= COMPACT_AFTER {
+            self.snapshot()?;
+        }
+
+        match self.protocol {
+            Protocol::TwoPhase => self.query_coordinators(output),
+            Protocol::ThreePhase => self.terminate(output),
+        }
+    }
+
+    // 2pc participants are blocked on these until the coordinator tells them what happened
+    fn query_coordinators(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        for (txn_id, prepared) in &mut self.prepared {
+            if clock::since(prepared.last_heard) < RETRY_INTERVAL
+                || prepared.coordinator == self.node
+            {
+                continue;
+            }
+            prepared.last_heard = clock::now();
+            Message {
+                src: self.node.clone(),
+                dst: prepared.coordinator.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    correlation_id: None,
+                    payload: Payload::Query {
+                        txn_id: txn_id.clone(),
+                    },
+                },
+            }
+            .send(&mut *output)
+            .with_context(|| format!("query {}", prepared.coordinator))?;
+        }
+        Ok(())
+    }
+
+    // the 3pc termination protocol: a participant that hasn't heard from its coordinator in a
+    // while collects the states of every other participant and decides for them. anyone who
+    // committed or aborted settles it. otherwise, if anyone pre-committed, everyone voted yes,
+    // but the coordinator may have left some uncertain, so they're all pre-committed before
+    // anyone commits. if nobody did, nobody can have committed, but that only holds once every
+    // participant has said so, and promised not to take a pre-commit from the coordinator after.
+    fn terminate(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let mut ask = Vec::new();
+        let mut precommit = Vec::new();
+        let mut finished = Vec::new();
+        for (txn_id, prepared) in &mut self.prepared {
+            if self.active.contains_key(txn_id) || self.decided.contains_key(txn_id) {
+                // we're coordinating this one ourselves
+                continue;
+            }
+            let mut peers: HashSet<_> = prepared.participants.iter().cloned().collect();
+            peers.insert(prepared.coordinator.clone());
+            peers.remove(&self.node);
+            let Some(termination) = &mut prepared.termination else {
+                // give the coordinator a chance to time out on its own first
+                if clock::since(prepared.last_heard) > 2 * PHASE_TIMEOUT {
+                    let own = if prepared.precommitted {
+                        TxnState::PreCommitted
+                    } else {
+                        TxnState::Uncertain
+                    };
+                    prepared.termination = Some(Termination {
+                        asked: clock::now(),
+                        states: HashMap::from([(self.node.clone(), own)]),
+                        ts: prepared.ts,
+                        precommitting: None,
+                    });
+                    let state_req = Payload::StateReq {
+                        txn_id: txn_id.clone(),
+                    };
+                    ask.push((txn_id.clone(), state_req, peers));
+                }
+                continue;
+            };
+            let states: Vec<_> = termination.states.values().copied().collect();
+            let again = clock::since(termination.asked) > TERMINATION_WAIT;
+            let participants = prepared.participants.clone();
+            if states.contains(&TxnState::Committed) {
+                finished.push((txn_id.clone(), true, termination.ts, peers, participants));
+            } else if states.contains(&TxnState::Aborted) {
+                finished.push((txn_id.clone(), false, termination.ts, peers, participants));
+            } else if let Some(waiting) = &termination.precommitting {
+                if waiting.is_empty() {
+                    finished.push((txn_id.clone(), true, termination.ts, peers, participants));
+                } else if again {
+                    termination.asked = clock::now();
+                    let precommit = Payload::PreCommit {
+                        txn_id: txn_id.clone(),
+                        ts: termination.ts,
+                        terminating: true,
+                    };
+                    ask.push((txn_id.clone(), precommit, waiting.clone()));
+                }
+            } else if states.contains(&TxnState::PreCommitted) {
+                precommit.push(txn_id.clone());
+            } else if participants
+                .iter()
+                .all(|p| termination.states.contains_key(p))
+            {
+                finished.push((txn_id.clone(), false, termination.ts, peers, participants));
+            } else if again {
+                // whoever hasn't answered may only be slow, or cut off from us for now
+                termination.asked = clock::now();
+                peers.retain(|p| !termination.states.contains_key(p));
+                let state_req = Payload::StateReq {
+                    txn_id: txn_id.clone(),
+                };
+                ask.push((txn_id.clone(), state_req, peers));
+            }
+        }
+
+        for (txn_id, payload, peers) in ask {
+            if matches!(payload, Payload::StateReq { .. }) {
+                // we count ourselves among the participants we hear from
+                let uncertain = self.state_of(&txn_id) == TxnState::Uncertain;
+                if uncertain {
+                    self.fence(&txn_id)?;
+                }
+            }
+            for peer in peers {
+                self.send(&peer, payload.clone(), output)?;
+            }
+        }
+        for txn_id in precommit {
+            self.precommit_everyone(&txn_id, output)?;
+        }
+        for (txn_id, commit, ts, peers, participants) in finished {
+            log::info!(
+                "terminated {} without its coordinator: {}",
+                txn_id,
+                if commit { "commit" } else { "abort" }
+            );
+            // from here on we stand in for the coordinator, re-sending the outcome until every
+            // participant has it and then having them forget it. we can't know every
+            // participant's conflicts, so assume the worst.
+            self.wal
+                .append(&Record::Decision {
+                    txn_id: txn_id.clone(),
+                    commit,
+                    participants: participants.clone(),
+                    ts,
+                    outbound: true,
+                })
+                .context("log termination")?;
+            self.apply_decision(&txn_id, commit, ts, true)?;
+            for peer in &peers {
+                self.send(
+                    peer,
+                    Payload::Decide {
+                        txn_id: txn_id.clone(),
+                        commit,
+                        ts,
+                        outbound: true,
+                    },
+                    output,
+                )?;
+            }
+            let unacked = participants
+                .iter()
+                .filter(|p| **p != self.node)
+                .cloned()
+                .collect();
+            self.decided.insert(
+                txn_id,
+                Decided {
+                    participants,
+                    commit,
+                    ts,
+                    outbound: true,
+                    unacked,
+                    last_sent: clock::now(),
+                    correlation_id: correlation::current(),
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, TxnNode, _, _>(())
+}
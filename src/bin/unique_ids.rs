@@ -1,12 +1,12 @@
 use anyhow::{Context, Ok};
 use rustengan::*;
 use serde::{Deserialize, Serialize};
-use std::io::{StdoutLock, Write};
+use std::io::Write;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
-enum Payload {
+pub(crate) enum Payload {
     Generate,
     GenerateOk {
         #[serde(rename = "id")]
@@ -14,7 +14,7 @@ enum Payload {
     },
 }
 
-struct UniqueNode {
+pub(crate) struct UniqueNode {
     node: String,
     id: usize,
 }
@@ -33,9 +33,12 @@ impl Node<(), Payload> for UniqueNode {
             node: init.node_id,
         })
     }
-    fn step(&mut self, input: Event<Payload>, output: &mut StdoutLock) -> anyhow::Result<()> {
-        let Event::Message(input) = input else {
-            panic!("got injected event when there is no event injection");
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            // nobody's left to answer once the input's closed
+            Event::EOF => return Ok(()),
+            Event::Injected(()) => panic!("got injected event when there is no event injection"),
         };
         let mut reply = input.into_reply(Some(&mut self.id));
         match reply.body.payload {
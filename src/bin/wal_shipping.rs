@@ -0,0 +1,631 @@
+use anyhow::{Context, Ok};
+use rustengan::wal::{self, Wal};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
+
+const TICK: Duration = Duration::from_millis(100);
+// writes per shipped segment, and segments per standby per tick while it's catching up
+const SEGMENT_WRITES: usize = 64;
+const SEGMENTS_PER_TICK: usize = 4;
+// how long a promotion waits for the old primary to hand over before going ahead without it
+const PROMOTE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Read {
+        key: usize,
+    },
+    ReadOk {
+        value: usize,
+    },
+    Write {
+        key: usize,
+        value: usize,
+    },
+    WriteOk,
+    Cas {
+        key: usize,
+        from: usize,
+        to: usize,
+    },
+    CasOk,
+    // admin: make the receiving node the primary
+    Promote,
+    PromoteOk {
+        epoch: u64,
+        lsn: u64,
+    },
+    // admin: what this node thinks of the cluster, and how far its log is
+    Status,
+    StatusOk {
+        epoch: u64,
+        primary: String,
+        lsn: u64,
+    },
+    Error {
+        code: usize,
+        text: String,
+    },
+    Forward {
+        origin: String,
+        // which run of the origin sent it, since its request ids start over after a restart
+        boot: String,
+        req_id: usize,
+        request: Box<Payload>,
+    },
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+    // primary -> standby: the writes from `start` on, following a write of epoch `prev_epoch`
+    // (0 for the start of the log), out of the `lsn` the primary has
+    Segment {
+        epoch: u64,
+        start: u64,
+        prev_epoch: u64,
+        writes: Vec<Write>,
+        lsn: u64,
+    },
+    // standby -> primary: ship me what comes after lsn
+    SegmentOk {
+        epoch: u64,
+        lsn: u64,
+    },
+    // promoting standby -> primary: stop taking writes and hand over
+    Demote {
+        epoch: u64,
+    },
+    // the old primary shipped everything up to lsn and stepped down
+    Demoted {
+        epoch: u64,
+        lsn: u64,
+    },
+    // the new primary, to everyone
+    Promoted {
+        epoch: u64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Write {
+    // the epoch of the primary that took the write, which tells divergent logs apart
+    epoch: u64,
+    key: usize,
+    value: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Record {
+    Write(Write),
+    // drops every write past `lsn`, which a standby does when its log turns out to have run
+    // ahead of the primary's
+    Truncate { lsn: u64 },
+    Epoch { epoch: u64, primary: String },
+}
+
+pub enum InjectedPayload {
+    Tick,
+}
+
+struct Promotion {
+    epoch: u64,
+    client: String,
+    msg_id: Option<usize>,
+    started: Instant,
+    // how far the old primary's log went, once it has handed over
+    lsn: Option<u64>,
+}
+
+/// A KV store replicated by shipping its write-ahead log. The primary logs every write before
+/// acknowledging it and streams the log to every standby in segments, each standby logging and
+/// applying them in turn. Shipping is asynchronous, so a standby trails the primary by however
+/// much is in flight. A standby that falls behind (or comes back from a crash) answers a segment
+/// with how far it actually got, and the primary rewinds to there and ships it the rest a few
+/// segments per tick until it's caught up.
+///
+/// `promote` makes the node it's sent to the primary. It first asks the current primary to stop
+/// taking writes and ship what it has left, and only takes over once it has applied all of it,
+/// so a controlled failover loses nothing. If the old primary doesn't answer in time the standby
+/// takes over anyway, and whatever the old primary hadn't shipped is lost: when it comes back,
+/// the segments of the new primary's epoch make it truncate its log to where the two agree.
+pub struct ShippingNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    wal: Option<Wal<Record>>,
+    epoch: u64,
+    primary: String,
+    log: Vec<Write>,
+    store: HashMap<usize, usize>,
+    // where each standby's log ends, as far as the primary knows
+    acked: HashMap<String, u64>,
+    promotion: Option<Promotion>,
+    pending: HashMap<usize, (String, Option<usize>)>,
+    // this run of the node, told apart from the ones before a crash
+    boot: String,
+    // requests we've answered, by the run of the node the client talked to and its request id:
+    // the network may hand us a forward again after later writes, and applying it twice would
+    // undo them
+    handled: HashSet<(String, usize)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for ShippingNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        ticks::every(TICK, tx, || Event::Injected(InjectedPayload::Tick));
+        let boot = format!("{}-{}", init.node_id, rng::ulid());
+        let mut node = Self {
+            node: init.node_id,
+            id: 1,
+            primary: init.node_ids.first().cloned().context("no nodes")?,
+            nodes: init.node_ids,
+            wal: None,
+            epoch: 0,
+            log: Vec::new(),
+            store: HashMap::new(),
+            acked: HashMap::new(),
+            promotion: None,
+            pending: HashMap::new(),
+            boot,
+            handled: HashSet::new(),
+        };
+        let (wal, records) = Wal::open(wal::data_dir().join(format!("{}.shipping.wal", node.node)))
+            .context("open shipping wal")?;
+        for record in records {
+            node.apply(record);
+        }
+        node.wal = Some(wal);
+        node.rebuild();
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Tick) => self.tick(output)?,
+            Event::Message(input) => {
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    request @ (Payload::Read { .. }
+                    | Payload::Write { .. }
+                    | Payload::Cas { .. }) => {
+                        let req_id = self.next_id();
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let (me, boot) = (self.node.clone(), self.boot.clone());
+                        self.handle(me, boot, req_id, request, output)?;
+                    }
+                    Payload::Forward {
+                        origin,
+                        boot,
+                        req_id,
+                        request,
+                    } => self.handle(origin, boot, req_id, *request, output)?,
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    Payload::Promote => {
+                        if self.primary == self.node {
+                            let req_id = self.next_id();
+                            self.pending.insert(req_id, (src, client_msg_id));
+                            let promoted = Payload::PromoteOk {
+                                epoch: self.epoch,
+                                lsn: self.lsn(),
+                            };
+                            return self.reply_client(req_id, promoted, output);
+                        }
+                        let epoch = self.epoch + 1;
+                        self.promotion = Some(Promotion {
+                            epoch,
+                            client: src,
+                            msg_id: client_msg_id,
+                            started: clock::now(),
+                            lsn: None,
+                        });
+                        let primary = self.primary.clone();
+                        self.send(&primary, Payload::Demote { epoch }, output)?;
+                    }
+                    Payload::Status => {
+                        let req_id = self.next_id();
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let status = Payload::StatusOk {
+                            epoch: self.epoch,
+                            primary: self.primary.clone(),
+                            lsn: self.lsn(),
+                        };
+                        self.reply_client(req_id, status, output)?;
+                    }
+                    Payload::Segment {
+                        epoch,
+                        start,
+                        prev_epoch,
+                        writes,
+                        lsn,
+                    } => {
+                        self.adopt(epoch, &src)?;
+                        if epoch < self.epoch || src != self.primary {
+                            return Ok(());
+                        }
+                        self.receive(start, prev_epoch, writes, lsn)?;
+                        let lsn = self.lsn();
+                        self.send(&src, Payload::SegmentOk { epoch, lsn }, output)?;
+                        self.maybe_promote(output)?;
+                    }
+                    Payload::SegmentOk { epoch, lsn } => {
+                        if epoch == self.epoch && self.primary == self.node {
+                            self.acked.insert(src, lsn);
+                        }
+                    }
+                    Payload::Demote { epoch } => {
+                        if epoch <= self.epoch || self.primary != self.node {
+                            return Ok(());
+                        }
+                        // ship whatever they're missing while we're still the primary they follow
+                        let from = self.acked.get(&src).copied().unwrap_or(0);
+                        self.ship(&src, from, usize::MAX, output)?;
+                        let lsn = self.lsn();
+                        log::info!("handing over to {} in epoch {}", src, epoch);
+                        self.adopt(epoch, &src)?;
+                        self.send(&src, Payload::Demoted { epoch, lsn }, output)?;
+                    }
+                    Payload::Demoted { epoch, lsn } => {
+                        if let Some(promotion) = &mut self.promotion {
+                            if promotion.epoch == epoch {
+                                promotion.lsn = Some(lsn);
+                            }
+                        }
+                        self.maybe_promote(output)?;
+                    }
+                    Payload::Promoted { epoch } => self.adopt(epoch, &src)?,
+                    Payload::ReadOk { .. }
+                    | Payload::WriteOk
+                    | Payload::CasOk
+                    | Payload::PromoteOk { .. }
+                    | Payload::StatusOk { .. }
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ShippingNode {
+    fn next_id(&mut self) -> usize {
+        let id = self.id;
+        self.id += 1;
+        id
+    }
+
+    fn lsn(&self) -> u64 {
+        self.log.len() as u64
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn log(&mut self, record: Record) -> anyhow::Result<()> {
+        self.wal
+            .as_mut()
+            .expect("wal is open")
+            .append(&record)
+            .context("append to shipping wal")?;
+        self.apply(record);
+        Ok(())
+    }
+
+    // the log and epoch only. the store is kept up to date alongside, or rebuilt after replay.
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Write(write) => self.log.push(write),
+            Record::Truncate { lsn } => self.log.truncate(lsn as usize),
+            Record::Epoch { epoch, primary } => {
+                self.epoch = epoch;
+                self.primary = primary;
+            }
+        }
+    }
+
+    fn rebuild(&mut self) {
+        self.store = self.log.iter().map(|w| (w.key, w.value)).collect();
+    }
+
+    // follows a primary of a newer epoch than the one we know of
+    fn adopt(&mut self, epoch: u64, primary: &str) -> anyhow::Result<()> {
+        if epoch <= self.epoch {
+            return Ok(());
+        }
+        if self.primary == self.node {
+            log::info!("{} is primary in epoch {}, stepping down", primary, epoch);
+        }
+        if self.promotion.as_ref().is_some_and(|p| p.epoch <= epoch) {
+            log::info!("lost the promotion to {}", primary);
+            self.promotion = None;
+        }
+        self.log(Record::Epoch {
+            epoch,
+            primary: primary.to_string(),
+        })
+    }
+
+    fn receive(
+        &mut self,
+        start: u64,
+        prev_epoch: u64,
+        writes: Vec<Write>,
+        lsn: u64,
+    ) -> anyhow::Result<()> {
+        // a gap: the reply tells the primary where to rewind to
+        if start > self.lsn() + 1 {
+            return Ok(());
+        }
+        let prev = start - 1;
+        if prev > 0 && self.log[prev as usize - 1].epoch != prev_epoch {
+            // we diverged somewhere before this segment. back off one write at a time, like the
+            // primary would have to anyway.
+            return self.truncate(prev - 1);
+        }
+        let mut changed = false;
+        for (lsn, write) in (start..).zip(writes) {
+            if lsn <= self.lsn() {
+                if self.log[lsn as usize - 1].epoch == write.epoch {
+                    continue;
+                }
+                self.truncate(lsn - 1)?;
+            }
+            self.store.insert(write.key, write.value);
+            self.log(Record::Write(write))?;
+            changed = true;
+        }
+        // anything past the primary's own log is left over from an epoch it doesn't know
+        if !changed && self.lsn() > lsn {
+            self.truncate(lsn)?;
+        }
+        Ok(())
+    }
+
+    fn truncate(&mut self, lsn: u64) -> anyhow::Result<()> {
+        log::info!("truncating log from {} to {}", self.lsn(), lsn);
+        self.log(Record::Truncate { lsn })?;
+        self.rebuild();
+        Ok(())
+    }
+
+    fn ship(
+        &mut self,
+        standby: &str,
+        from: u64,
+        segments: usize,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let mut start = from.min(self.lsn()) + 1;
+        for _ in 0..segments {
+            let prev = start - 1;
+            let prev_epoch = match prev {
+                0 => 0,
+                prev => self.log[prev as usize - 1].epoch,
+            };
+            let end = (prev as usize + SEGMENT_WRITES).min(self.log.len());
+            let writes = self.log[prev as usize..end].to_vec();
+            let segment = Payload::Segment {
+                epoch: self.epoch,
+                start,
+                prev_epoch,
+                writes,
+                lsn: self.lsn(),
+            };
+            self.send(standby, segment, output)?;
+            start = end as u64 + 1;
+            if end == self.log.len() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    fn tick(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        if let Some(promotion) = &self.promotion {
+            if promotion.lsn.is_none() && clock::since(promotion.started) > PROMOTE_TIMEOUT {
+                log::warn!("{} didn't hand over, taking over anyway", self.primary);
+                self.promote(output)?;
+            }
+        }
+        if self.primary != self.node {
+            return Ok(());
+        }
+        // sent even when there's nothing new, so standbys hear where the log ends
+        let standbys: Vec<_> = self
+            .nodes
+            .iter()
+            .filter(|n| **n != self.node)
+            .cloned()
+            .collect();
+        for standby in standbys {
+            let from = self.acked.get(&standby).copied().unwrap_or(self.lsn());
+            self.ship(&standby, from, SEGMENTS_PER_TICK, output)?;
+        }
+        Ok(())
+    }
+
+    fn maybe_promote(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let caught_up = self
+            .promotion
+            .as_ref()
+            .and_then(|p| p.lsn)
+            .is_some_and(|lsn| self.lsn() >= lsn);
+        if caught_up {
+            self.promote(output)?;
+        }
+        Ok(())
+    }
+
+    fn promote(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let promotion = self.promotion.take().expect("promoting");
+        log::info!(
+            "primary for epoch {} at lsn {}",
+            promotion.epoch,
+            self.lsn()
+        );
+        let node = self.node.clone();
+        self.log(Record::Epoch {
+            epoch: promotion.epoch,
+            primary: node,
+        })?;
+        // assume everyone has what we have. the first segments find out otherwise.
+        self.acked.clear();
+        for other in self.nodes.iter().filter(|n| **n != self.node) {
+            let promoted = Payload::Promoted {
+                epoch: promotion.epoch,
+            };
+            self.send(other, promoted, output)?;
+        }
+        let req_id = self.next_id();
+        self.pending
+            .insert(req_id, (promotion.client, promotion.msg_id));
+        let promoted = Payload::PromoteOk {
+            epoch: promotion.epoch,
+            lsn: self.lsn(),
+        };
+        self.reply_client(req_id, promoted, output)
+    }
+
+    fn handle(
+        &mut self,
+        origin: String,
+        boot: String,
+        req_id: usize,
+        request: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if self.primary != self.node {
+            if origin == self.node && self.promotion.is_none() {
+                let forward = Payload::Forward {
+                    origin,
+                    boot,
+                    req_id,
+                    request: Box::new(request),
+                };
+                let primary = self.primary.clone();
+                return self.send(&primary, forward, output);
+            }
+            let reply = Payload::Error {
+                code: error::TEMPORARILY_UNAVAILABLE,
+                text: "failing over".to_string(),
+            };
+            return self.respond(&origin, req_id, reply, output);
+        }
+        if !self.handled.insert((boot, req_id)) {
+            return Ok(());
+        }
+
+        let (write, reply) = match request {
+            Payload::Read { key } => match self.store.get(&key) {
+                Some(&value) => (None, Payload::ReadOk { value }),
+                None => (None, not_found(key)),
+            },
+            Payload::Write { key, value } => (Some((key, value)), Payload::WriteOk),
+            Payload::Cas { key, from, to } => match self.store.get(&key) {
+                Some(&current) if current == from => (Some((key, to)), Payload::CasOk),
+                Some(&current) => (
+                    None,
+                    Payload::Error {
+                        code: error::PRECONDITION_FAILED,
+                        text: format!("expected {}, had {}", from, current),
+                    },
+                ),
+                None => (None, not_found(key)),
+            },
+            _ => unreachable!("only client requests are forwarded"),
+        };
+        if let Some((key, value)) = write {
+            self.store.insert(key, value);
+            let write = Write {
+                epoch: self.epoch,
+                key,
+                value,
+            };
+            self.log(Record::Write(write))?;
+        }
+        self.respond(&origin, req_id, reply, output)
+    }
+
+    fn respond(
+        &mut self,
+        origin: &str,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if origin == self.node {
+            return self.reply_client(req_id, reply, output);
+        }
+        let done = Payload::Done {
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.send(origin, done, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        let id = self.next_id();
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")
+    }
+}
+
+fn not_found(key: usize) -> Payload {
+    Payload::Error {
+        code: error::KEY_DOES_NOT_EXIST,
+        text: format!("key {} does not exist", key),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, ShippingNode, _, _>(())
+}
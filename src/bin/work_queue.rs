@@ -0,0 +1,546 @@
+use anyhow::{Context, Ok};
+use rustengan::failure_detector::{FailureDetector, FdEvent, Strategy};
+use rustengan::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::Duration,
+};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(100);
+// like chain replication this assumes fail-stop: a node declared failed stays out for good
+const FAIL_AFTER: Duration = Duration::from_millis(1000);
+// how long a dequeued item stays invisible when the client doesn't say
+const DEFAULT_VISIBILITY_MS: u64 = 5000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum Payload {
+    Enqueue {
+        item: serde_json::Value,
+    },
+    EnqueueOk {
+        id: u64,
+    },
+    // hands out the oldest visible item and hides it for the visibility timeout. if it isn't
+    // acked by then it becomes visible again and goes to the next dequeue.
+    Dequeue {
+        #[serde(default)]
+        visibility_ms: Option<u64>,
+    },
+    // all none when there is nothing to hand out. `delivery` counts the times the item has been
+    // handed out, and is what the consumer acks it with.
+    DequeueOk {
+        id: Option<u64>,
+        item: Option<serde_json::Value>,
+        delivery: Option<u32>,
+    },
+    Ack {
+        id: u64,
+        delivery: u32,
+    },
+    AckOk,
+    Error {
+        code: usize,
+        text: String,
+    },
+    // a client request on its way to the leader, and the reply on its way back
+    Forward {
+        origin: String,
+        req_id: usize,
+        request: Box<Payload>,
+    },
+    Done {
+        req_id: usize,
+        reply: Box<Payload>,
+    },
+    // leader -> followers: log entries, and how far the log is on every member
+    Replicate {
+        entries: Vec<Entry>,
+        committed: u64,
+    },
+    // the follower has applied everything up to and including seq
+    ReplicateOk {
+        seq: u64,
+    },
+    // new leader -> followers: send me whatever the old leader got to you
+    Recover,
+    Recovered {
+        entries: Vec<Entry>,
+    },
+    Heartbeat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    seq: u64,
+    change: Change,
+    // who to answer once the entry is on every member
+    origin: String,
+    req_id: usize,
+    reply: Box<Payload>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Change {
+    Enqueue { id: u64, item: serde_json::Value },
+    // the leader's clock decides when the item shows up again, so every replica agrees on it
+    Dequeue { id: u64, visible_at_ms: u64 },
+    Ack { id: u64 },
+}
+
+struct Item {
+    item: serde_json::Value,
+    visible_at_ms: u64,
+    deliveries: u32,
+}
+
+pub enum InjectedPayload {
+    Fd(FdEvent),
+}
+
+impl From<FdEvent> for InjectedPayload {
+    fn from(event: FdEvent) -> Self {
+        Self::Fd(event)
+    }
+}
+
+struct Leader {
+    // how far each follower has applied the log
+    acked: HashMap<String, u64>,
+    // followers we're still waiting on to tell us what the old leader sent them. no new entries
+    // until they have, or we might hand out a sequence number that's already taken.
+    recovering: HashSet<String>,
+    queued: Vec<(String, usize, Payload)>,
+}
+
+/// A work queue with visibility timeouts, replicated on every node. The first node not declared
+/// failed leads: every request goes to it, it turns each into an entry in a log it replicates to
+/// every other node, and it only answers once every node has the entry. Dequeuing is an entry
+/// too, so an item handed out and not yet acked is hidden on every replica, and when the leader
+/// dies the next one hands it out again once its timeout runs out, not before.
+///
+/// A new leader may be missing entries the old one got to some followers, so before it does
+/// anything else it collects those from everyone and passes them on. Acks carry the delivery
+/// number the item was handed out with, so a consumer too slow to ack before its item was handed
+/// to someone else is told so instead of acking the other consumer's copy.
+pub struct QueueNode {
+    node: String,
+    id: usize,
+    nodes: Vec<String>,
+    failed: HashSet<String>,
+    fd: FailureDetector<Payload, InjectedPayload>,
+
+    items: BTreeMap<u64, Item>,
+    next_item: u64,
+    applied: u64,
+    // entries ahead of one we haven't seen yet
+    buffered: BTreeMap<u64, Entry>,
+    // entries not yet known to be on every member, kept for a new leader in case ours dies
+    log: BTreeMap<u64, Entry>,
+    role: Option<Leader>,
+
+    // requests from our own clients waiting on the leader
+    pending: HashMap<usize, (String, Option<usize>)>,
+}
+
+impl Node<(), Payload, InjectedPayload> for QueueNode {
+    fn from_init(
+        _init_state: (),
+        init: Init,
+        tx: std::sync::mpsc::Sender<Event<Payload, InjectedPayload>>,
+    ) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut node = Self {
+            fd: FailureDetector::start(
+                &init.node_id,
+                init.node_ids.clone(),
+                HEARTBEAT_INTERVAL,
+                Strategy::from_env(FAIL_AFTER)?,
+                tx,
+            ),
+            node: init.node_id,
+            id: 1,
+            nodes: init.node_ids,
+            failed: HashSet::new(),
+            items: BTreeMap::new(),
+            next_item: 1,
+            applied: 0,
+            buffered: BTreeMap::new(),
+            log: BTreeMap::new(),
+            role: None,
+            pending: HashMap::new(),
+        };
+        if node.leader() == node.node {
+            node.role = Some(Leader {
+                acked: HashMap::new(),
+                recovering: HashSet::new(),
+                queued: Vec::new(),
+            });
+        }
+        Ok(node)
+    }
+
+    fn step(
+        &mut self,
+        input: Event<Payload, InjectedPayload>,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        match input {
+            Event::EOF => {}
+            Event::Injected(InjectedPayload::Fd(FdEvent::Heartbeat)) => {
+                self.fd.heartbeat(&mut *output)?;
+                self.resend(output)?;
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerDown(n))) => {
+                if !self.failed.contains(&n) {
+                    self.declare_failed(n, output)?;
+                }
+            }
+            Event::Injected(InjectedPayload::Fd(FdEvent::PeerUp(_))) => {}
+            Event::Message(input) => {
+                self.fd.heard_from(&input.src);
+                let src = input.src.clone();
+                let client_msg_id = input.body.id;
+                match input.body.payload {
+                    request @ (Payload::Enqueue { .. }
+                    | Payload::Dequeue { .. }
+                    | Payload::Ack { .. }) => {
+                        let req_id = self.id;
+                        self.id += 1;
+                        self.pending.insert(req_id, (src, client_msg_id));
+                        let me = self.node.clone();
+                        self.handle(me, req_id, request, output)?;
+                    }
+                    Payload::Forward {
+                        origin,
+                        req_id,
+                        request,
+                    } => self.handle(origin, req_id, *request, output)?,
+                    Payload::Done { req_id, reply } => self.reply_client(req_id, *reply, output)?,
+                    Payload::Replicate { entries, committed } => {
+                        self.receive(entries);
+                        self.log.retain(|seq, _| *seq > committed);
+                        let seq = self.applied;
+                        self.send(&src, Payload::ReplicateOk { seq }, output)?;
+                    }
+                    Payload::ReplicateOk { seq } => {
+                        if let Some(leader) = &mut self.role {
+                            let acked = leader.acked.entry(src).or_default();
+                            *acked = (*acked).max(seq);
+                            self.commit(output)?;
+                        }
+                    }
+                    Payload::Recover => {
+                        let entries = self.log.values().cloned().collect();
+                        self.send(&src, Payload::Recovered { entries }, output)?;
+                    }
+                    Payload::Recovered { entries } => {
+                        self.receive(entries);
+                        if let Some(leader) = &mut self.role {
+                            leader.recovering.remove(&src);
+                        }
+                        self.maybe_recovered(output)?;
+                    }
+                    Payload::Heartbeat
+                    | Payload::EnqueueOk { .. }
+                    | Payload::DequeueOk { .. }
+                    | Payload::AckOk
+                    | Payload::Error { .. } => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl QueueNode {
+    fn members(&self) -> Vec<String> {
+        self.nodes
+            .iter()
+            .filter(|n| !self.failed.contains(*n))
+            .cloned()
+            .collect()
+    }
+
+    fn leader(&self) -> String {
+        self.members().first().cloned().expect("we're a member")
+    }
+
+    fn followers(&self) -> Vec<String> {
+        self.members()
+            .into_iter()
+            .filter(|n| *n != self.node)
+            .collect()
+    }
+
+    fn send(&self, dst: &str, payload: Payload, output: &mut Output) -> anyhow::Result<()> {
+        Message {
+            src: self.node.clone(),
+            dst: dst.to_string(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        }
+        .send(&mut *output)
+        .with_context(|| format!("send to {}", dst))
+    }
+
+    fn handle(
+        &mut self,
+        origin: String,
+        req_id: usize,
+        request: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let leader = self.leader();
+        let Some(role) = &mut self.role else {
+            let forward = Payload::Forward {
+                origin,
+                req_id,
+                request: Box::new(request),
+            };
+            return self.send(&leader, forward, output);
+        };
+        if !role.recovering.is_empty() {
+            role.queued.push((origin, req_id, request));
+            return Ok(());
+        }
+
+        let now = clock::wall_ms();
+        let (change, reply) = match request {
+            Payload::Enqueue { item } => {
+                let id = self.next_item;
+                (Change::Enqueue { id, item }, Payload::EnqueueOk { id })
+            }
+            Payload::Dequeue { visibility_ms } => {
+                let visible = self.items.iter().find(|(_, i)| i.visible_at_ms <= now);
+                let Some((&id, item)) = visible else {
+                    let empty = Payload::DequeueOk {
+                        id: None,
+                        item: None,
+                        delivery: None,
+                    };
+                    return self.respond(&origin, req_id, empty, output);
+                };
+                let reply = Payload::DequeueOk {
+                    id: Some(id),
+                    item: Some(item.item.clone()),
+                    delivery: Some(item.deliveries + 1),
+                };
+                let visible_at_ms = now + visibility_ms.unwrap_or(DEFAULT_VISIBILITY_MS);
+                (Change::Dequeue { id, visible_at_ms }, reply)
+            }
+            Payload::Ack { id, delivery } => match self.items.get(&id) {
+                Some(item) if item.deliveries == delivery && item.visible_at_ms > now => {
+                    (Change::Ack { id }, Payload::AckOk)
+                }
+                Some(_) => {
+                    let error = Payload::Error {
+                        code: error::PRECONDITION_FAILED,
+                        text: format!("delivery {} of {} timed out", delivery, id),
+                    };
+                    return self.respond(&origin, req_id, error, output);
+                }
+                None => {
+                    let error = Payload::Error {
+                        code: error::KEY_DOES_NOT_EXIST,
+                        text: format!("no item {}, or it was already acked", id),
+                    };
+                    return self.respond(&origin, req_id, error, output);
+                }
+            },
+            _ => unreachable!("only client requests are forwarded"),
+        };
+        let entry = Entry {
+            seq: self.applied + 1,
+            change,
+            origin,
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.receive(vec![entry.clone()]);
+        for follower in self.followers() {
+            let replicate = Payload::Replicate {
+                entries: vec![entry.clone()],
+                committed: self.committed(),
+            };
+            self.send(&follower, replicate, output)?;
+        }
+        // a cluster of one has every member's ack already
+        self.commit(output)
+    }
+
+    // applies whatever entries follow on from what we have, in order
+    fn receive(&mut self, entries: Vec<Entry>) {
+        for entry in entries {
+            if entry.seq > self.applied {
+                self.buffered.insert(entry.seq, entry);
+            }
+        }
+        while let Some(entry) = self.buffered.remove(&(self.applied + 1)) {
+            self.applied = entry.seq;
+            match &entry.change {
+                Change::Enqueue { id, item } => {
+                    self.next_item = self.next_item.max(id + 1);
+                    let item = Item {
+                        item: item.clone(),
+                        visible_at_ms: 0,
+                        deliveries: 0,
+                    };
+                    self.items.insert(*id, item);
+                }
+                Change::Dequeue { id, visible_at_ms } => {
+                    if let Some(item) = self.items.get_mut(id) {
+                        item.visible_at_ms = *visible_at_ms;
+                        item.deliveries += 1;
+                    }
+                }
+                Change::Ack { id } => {
+                    self.items.remove(id);
+                }
+            }
+            self.log.insert(entry.seq, entry);
+        }
+    }
+
+    // how far the log is on every member, as far as the leader knows
+    fn committed(&self) -> u64 {
+        let Some(leader) = &self.role else {
+            return 0;
+        };
+        self.followers()
+            .iter()
+            .map(|n| leader.acked.get(n).copied().unwrap_or(0))
+            .fold(self.applied, u64::min)
+    }
+
+    fn commit(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let committed = self.committed();
+        let done: Vec<u64> = self.log.range(..=committed).map(|(&seq, _)| seq).collect();
+        for seq in done {
+            let entry = self.log.remove(&seq).expect("just found it");
+            self.respond(&entry.origin, entry.req_id, *entry.reply, output)?;
+        }
+        Ok(())
+    }
+
+    // brings lagging followers up to date, in case an entry got lost on the way
+    fn resend(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Some(leader) = &self.role else {
+            return Ok(());
+        };
+        if !leader.recovering.is_empty() {
+            return Ok(());
+        }
+        let committed = self.committed();
+        for follower in self.followers() {
+            let acked = leader.acked.get(&follower).copied().unwrap_or(0);
+            if acked >= self.applied {
+                continue;
+            }
+            let entries = self
+                .log
+                .range(acked + 1..)
+                .map(|(_, e)| e.clone())
+                .collect();
+            self.send(&follower, Payload::Replicate { entries, committed }, output)?;
+        }
+        Ok(())
+    }
+
+    fn respond(
+        &mut self,
+        origin: &str,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        if origin == self.node {
+            return self.reply_client(req_id, reply, output);
+        }
+        let done = Payload::Done {
+            req_id,
+            reply: Box::new(reply),
+        };
+        self.send(origin, done, output)
+    }
+
+    fn reply_client(
+        &mut self,
+        req_id: usize,
+        reply: Payload,
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        let Some((client, msg_id)) = self.pending.remove(&req_id) else {
+            return Ok(());
+        };
+        Message {
+            src: self.node.clone(),
+            dst: client,
+            body: Body {
+                id: Some(self.id),
+                in_reply_to: msg_id,
+                correlation_id: None,
+                payload: reply,
+            },
+        }
+        .send(&mut *output)
+        .context("reply to client")?;
+        self.id += 1;
+        Ok(())
+    }
+
+    fn declare_failed(&mut self, n: String, output: &mut Output) -> anyhow::Result<()> {
+        log::warn!("declaring {} failed", n);
+        let was_leader = self.leader() == n;
+        self.failed.insert(n.clone());
+        if let Some(leader) = &mut self.role {
+            // one fewer member to wait for
+            leader.recovering.remove(&n);
+            self.maybe_recovered(output)?;
+            return self.commit(output);
+        }
+        if was_leader && self.leader() == self.node {
+            log::info!("taking over from {}", n);
+            let followers = self.followers();
+            self.role = Some(Leader {
+                acked: HashMap::new(),
+                recovering: followers.iter().cloned().collect(),
+                queued: Vec::new(),
+            });
+            for follower in followers {
+                self.send(&follower, Payload::Recover, output)?;
+            }
+            self.maybe_recovered(output)?;
+        }
+        Ok(())
+    }
+
+    // once every follower has reported in, everything any of them got from the old leader is in
+    // our log, and the usual resending passes it on to the rest
+    fn maybe_recovered(&mut self, output: &mut Output) -> anyhow::Result<()> {
+        let Some(leader) = &mut self.role else {
+            return Ok(());
+        };
+        if !leader.recovering.is_empty() || leader.queued.is_empty() {
+            return Ok(());
+        }
+        for (origin, req_id, request) in std::mem::take(&mut leader.queued) {
+            self.handle(origin, req_id, request, output)?;
+        }
+        Ok(())
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    main_loop::<_, QueueNode, _, _>(())
+}
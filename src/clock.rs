@@ -0,0 +1,67 @@
+use std::cell::Cell;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// what the simulator says the time is on the node it's stepping, while it's stepping it
+thread_local! {
+    static SIMULATED: Cell<Option<Reading>> = const { Cell::new(None) };
+}
+
+/// The time as a node sees it: how long since it started by its monotonic clock, and what its
+/// wall clock says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    pub monotonic: Duration,
+    pub wall: Duration,
+}
+
+/// The node's monotonic clock, for timeouts and anything else measured from one moment to
+/// another. Under the simulator it's the node's own clock, drift and all, rather than the
+/// machine's.
+pub fn now() -> Instant {
+    match SIMULATED.get() {
+        Some(reading) => base() + reading.monotonic,
+        None => Instant::now(),
+    }
+}
+
+/// How long it's been since `earlier` by [`now`], which is what `earlier.elapsed()` would be if
+/// it didn't always go by the machine's clock.
+pub fn since(earlier: Instant) -> Duration {
+    now().saturating_duration_since(earlier)
+}
+
+/// The node's wall clock, as time since the Unix epoch, for timestamps and leases. Under the
+/// simulator it's the node's own clock, however far off the simulation has set it.
+pub fn wall() -> Duration {
+    match SIMULATED.get() {
+        Some(reading) => reading.wall,
+        None => SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock is after the epoch"),
+    }
+}
+
+/// [`wall`] in whole milliseconds, which is what most timestamps are kept in.
+pub fn wall_ms() -> u64 {
+    wall().as_millis() as u64
+}
+
+/// Whether the simulator is stepping, or starting, a node on this thread. A node that would
+/// keep a timer thread of its own leaves it to the simulator's ticks when it is, since nothing
+/// a thread does on real time can be repeated from a seed.
+pub fn simulated() -> bool {
+    SIMULATED.get().is_some()
+}
+
+// has `now` and `wall` answer with `reading` on this thread, until it's `None` again
+pub(crate) fn simulate(reading: Option<Reading>) {
+    SIMULATED.set(reading);
+}
+
+// the instant simulated monotonic clocks count from, which is only ever compared with other
+// simulated instants
+fn base() -> Instant {
+    static BASE: OnceLock<Instant> = OnceLock::new();
+    *BASE.get_or_init(Instant::now)
+}
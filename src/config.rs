@@ -0,0 +1,29 @@
+use std::fmt::Display;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+// maelstrom only lets us point it at a binary, so knobs come in through the environment.
+pub fn var<T>(name: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| anyhow::anyhow!("{}", e))
+            .with_context(|| format!("invalid value {:?} for {}", value, name)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("read {}", name)),
+    }
+}
+
+pub fn var_or<T>(name: &str, default: T) -> anyhow::Result<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    Ok(var(name)?.unwrap_or(default))
+}
@@ -0,0 +1,57 @@
+use std::cell::RefCell;
+
+use crate::{config, Message};
+
+// the correlation id of the step the node's taking, on the thread taking it, for whatever it
+// sends and logs to carry on
+thread_local! {
+    static STEPPING: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Correlation ids, for joining up what different nodes logged about one client operation.
+///
+/// A message that comes with a `correlation_id` in its body has the node take its step under
+/// it: every message the node sends during the step carries the same id, unless it was given
+/// one of its own, and so does every line it logs, so however many hops a request's forwarding
+/// and fanning out takes, it's all under the id it started with. Replies carry the id of what
+/// they answer ([`Message::into_reply`]) wherever they're sent from.
+///
+/// With `RUSTENGAN_CORRELATE=true` a request that comes without an id, a client's say, is given
+/// one, `<src>:<msg_id>`, which is also how `flow --chain` names where a chain starts. Otherwise
+/// only ids that come in go back out, so nothing's added to what Maelstrom sees.
+pub struct Correlation {
+    mint: bool,
+}
+
+impl Correlation {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(Self {
+            mint: config::var_or("RUSTENGAN_CORRELATE", false)?,
+        })
+    }
+
+    /// The id the node's step of `message` is taken under.
+    pub fn of<P>(&self, message: &Message<P>) -> Option<String> {
+        match (&message.body.correlation_id, message.body.id) {
+            (Some(id), _) => Some(id.clone()),
+            (None, Some(msg_id)) if self.mint => Some(format!("{}:{}", message.src, msg_id)),
+            (None, _) => None,
+        }
+    }
+}
+
+/// Runs `step` under the correlation id `id`, and puts back whatever id was current before.
+/// Besides the node's step of each event, this is for work a node queued under one id and gets
+/// round to in a step taken under another, a batch it flushes or a message it resends from a
+/// tick, which it can then send under the id it was queued with.
+pub fn stepping<T>(id: Option<String>, step: impl FnOnce() -> T) -> T {
+    let before = STEPPING.replace(id);
+    let stepped = step();
+    STEPPING.set(before);
+    stepped
+}
+
+/// The correlation id of the step being taken, if it's taken under one.
+pub fn current() -> Option<String> {
+    STEPPING.with_borrow(|id| id.clone())
+}
@@ -0,0 +1,411 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hlc::Timestamp;
+
+/// Identifies a single add: the node that performed it plus that node's running counter. Dots are
+/// never reused, so removing the dots we've observed can't take out an add we haven't seen yet.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Dot {
+    pub node: String,
+    pub counter: u64,
+}
+
+/// Observed-remove set. A remove only cancels the adds the removing node had seen at the time, so
+/// an add concurrent with a remove survives the merge (add wins).
+///
+/// Removed dots are kept as tombstones forever so a late-arriving copy of a removed add can't bring
+/// it back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize",
+    deserialize = "T: Deserialize<'de> + Eq + Hash"
+))]
+pub struct OrSet<T> {
+    // kept as (element, dot) pairs rather than a map so elements don't have to serialize as
+    // json object keys
+    adds: HashSet<(T, Dot)>,
+    removed: HashSet<Dot>,
+    #[serde(skip)]
+    counter: u64,
+}
+
+impl<T> Default for OrSet<T> {
+    fn default() -> Self {
+        Self {
+            adds: HashSet::new(),
+            removed: HashSet::new(),
+            counter: 0,
+        }
+    }
+}
+
+// two replicas are the same once they've seen the same adds and removes, whatever their counters
+impl<T> PartialEq for OrSet<T>
+where
+    T: Eq + Hash,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.adds == other.adds && self.removed == other.removed
+    }
+}
+
+impl<T> OrSet<T>
+where
+    T: Clone + Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, node: &str, element: T) {
+        self.counter += 1;
+        let dot = Dot {
+            node: node.to_string(),
+            counter: self.counter,
+        };
+        self.adds.insert((element, dot));
+    }
+
+    pub fn remove(&mut self, element: &T) {
+        let removed = &mut self.removed;
+        self.adds.retain(|(e, dot)| {
+            if e == element {
+                removed.insert(dot.clone());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    pub fn contains(&self, element: &T) -> bool {
+        self.adds.iter().any(|(e, _)| e == element)
+    }
+
+    pub fn elements(&self) -> HashSet<T> {
+        self.adds.iter().map(|(e, _)| e.clone()).collect()
+    }
+
+    /// Folds another replica's state into ours. Merging is commutative, associative and idempotent,
+    /// so replicas can exchange state in any order, any number of times.
+    pub fn merge(&mut self, other: &OrSet<T>) {
+        self.removed.extend(other.removed.iter().cloned());
+        let removed = &self.removed;
+        self.adds.extend(
+            other
+                .adds
+                .iter()
+                .filter(|(_, dot)| !removed.contains(dot))
+                .cloned(),
+        );
+        self.adds.retain(|(_, dot)| !removed.contains(dot));
+        // dots only need to be unique per node, but staying ahead of every counter we've seen
+        // also covers our own dots coming back to us after a restart wiped our state
+        let seen = self
+            .adds
+            .iter()
+            .map(|(_, dot)| dot)
+            .chain(&self.removed)
+            .map(|dot| dot.counter)
+            .max()
+            .unwrap_or(0);
+        self.counter = self.counter.max(seen);
+    }
+}
+
+/// Last-write-wins register. Concurrent writes are ordered by their hybrid logical clock
+/// timestamp, with the writing node's id breaking ties between identical timestamps.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LwwRegister<V> {
+    pub value: V,
+    pub ts: Timestamp,
+    pub node: String,
+}
+
+impl<V> LwwRegister<V>
+where
+    V: Clone,
+{
+    pub fn new(node: &str, ts: Timestamp, value: V) -> Self {
+        Self {
+            value,
+            ts,
+            node: node.to_string(),
+        }
+    }
+
+    pub fn set(&mut self, node: &str, ts: Timestamp, value: V) {
+        self.merge(&Self::new(node, ts, value));
+    }
+
+    /// Keeps whichever of the two writes came last.
+    pub fn merge(&mut self, other: &LwwRegister<V>) {
+        if (other.ts, &other.node) > (self.ts, &self.node) {
+            *self = other.clone();
+        }
+    }
+}
+
+/// Map of last-write-wins registers. Deletes are writes of `None`, so a delete and a concurrent
+/// write race like any other pair of writes instead of the delete being undone by gossip.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "K: Serialize + Clone, V: Serialize + Clone",
+    deserialize = "K: Deserialize<'de> + Eq + Hash, V: Deserialize<'de>"
+))]
+#[serde(into = "Vec<(K, LwwRegister<Option<V>>)>")]
+#[serde(from = "Vec<(K, LwwRegister<Option<V>>)>")]
+pub struct LwwMap<K, V> {
+    entries: HashMap<K, LwwRegister<Option<V>>>,
+}
+
+impl<K, V> Default for LwwMap<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+// serialized as a list of pairs rather than a map so keys don't have to serialize as json object
+// keys
+impl<K, V> From<LwwMap<K, V>> for Vec<(K, LwwRegister<Option<V>>)> {
+    fn from(map: LwwMap<K, V>) -> Self {
+        map.entries.into_iter().collect()
+    }
+}
+
+impl<K, V> From<Vec<(K, LwwRegister<Option<V>>)>> for LwwMap<K, V>
+where
+    K: Eq + Hash,
+{
+    fn from(entries: Vec<(K, LwwRegister<Option<V>>)>) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+        }
+    }
+}
+
+impl<K, V> PartialEq for LwwMap<K, V>
+where
+    K: Eq + Hash,
+    V: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl<K, V> LwwMap<K, V>
+where
+    K: Clone + Eq + Hash,
+    V: Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key)?.value.as_ref()
+    }
+
+    pub fn insert(&mut self, node: &str, ts: Timestamp, key: K, value: V) {
+        self.set(node, ts, key, Some(value));
+    }
+
+    pub fn remove(&mut self, node: &str, ts: Timestamp, key: K) {
+        self.set(node, ts, key, None);
+    }
+
+    fn set(&mut self, node: &str, ts: Timestamp, key: K, value: Option<V>) {
+        let write = LwwRegister::new(node, ts, value);
+        self.entries
+            .entry(key)
+            .and_modify(|register| register.merge(&write))
+            .or_insert_with(|| write.clone());
+    }
+
+    /// The latest timestamp of any write in the map, deletes included.
+    pub fn latest(&self) -> Option<Timestamp> {
+        self.entries.values().map(|register| register.ts).max()
+    }
+
+    pub fn merge(&mut self, other: &LwwMap<K, V>) {
+        for (key, theirs) in &other.entries {
+            self.entries
+                .entry(key.clone())
+                .and_modify(|ours| ours.merge(theirs))
+                .or_insert_with(|| theirs.clone());
+        }
+    }
+}
+
+/// Identifies an element of an [`Rga`]. The counter works like a lamport clock, so an insert
+/// always gets an id larger than every id its replica had seen when it was made.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RgaId {
+    pub counter: u64,
+    pub node: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RgaElement<T> {
+    id: RgaId,
+    // the element this one was inserted directly after, or None for the front of the sequence
+    after: Option<RgaId>,
+    value: T,
+    deleted: bool,
+}
+
+/// Replicated growable array: a sequence where every replica that has seen the same inserts and
+/// deletes ends up with the same order.
+///
+/// Each element remembers what it was inserted after. Elements inserted after the same one are
+/// ordered by descending id, so a new insert (having a larger id than anything its replica knew
+/// of) lands right where it was asked to, and concurrent inserts at the same spot are ordered the
+/// same way everywhere. Deleted elements stay behind as tombstones since later inserts may refer
+/// to them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(
+    serialize = "T: Serialize + Clone",
+    deserialize = "T: Deserialize<'de>"
+))]
+#[serde(into = "Vec<RgaElement<T>>")]
+#[serde(from = "Vec<RgaElement<T>>")]
+pub struct Rga<T> {
+    elements: HashMap<RgaId, RgaElement<T>>,
+    counter: u64,
+}
+
+impl<T> Default for Rga<T> {
+    fn default() -> Self {
+        Self {
+            elements: HashMap::new(),
+            counter: 0,
+        }
+    }
+}
+
+impl<T> From<Rga<T>> for Vec<RgaElement<T>> {
+    fn from(rga: Rga<T>) -> Self {
+        rga.elements.into_values().collect()
+    }
+}
+
+impl<T> From<Vec<RgaElement<T>>> for Rga<T> {
+    fn from(elements: Vec<RgaElement<T>>) -> Self {
+        Self {
+            counter: elements.iter().map(|e| e.id.counter).max().unwrap_or(0),
+            elements: elements.into_iter().map(|e| (e.id.clone(), e)).collect(),
+        }
+    }
+}
+
+// two replicas are the same once they've seen the same inserts and deletes, whatever their
+// counters
+impl<T> PartialEq for Rga<T>
+where
+    T: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.elements == other.elements
+    }
+}
+
+impl<T> Rga<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts `value` so that it ends up at `index` among the visible elements. Returns `None`
+    /// if `index` is past the end.
+    pub fn insert(&mut self, node: &str, index: usize, value: T) -> Option<RgaId> {
+        let after = match index {
+            0 => None,
+            _ => Some(self.visible().nth(index - 1)?.id.clone()),
+        };
+        self.counter += 1;
+        let id = RgaId {
+            counter: self.counter,
+            node: node.to_string(),
+        };
+        self.elements.insert(
+            id.clone(),
+            RgaElement {
+                id: id.clone(),
+                after,
+                value,
+                deleted: false,
+            },
+        );
+        Some(id)
+    }
+
+    /// Deletes the visible element at `index`, returning its value, or `None` if there isn't one.
+    pub fn delete(&mut self, index: usize) -> Option<T> {
+        let id = self.visible().nth(index)?.id.clone();
+        let element = self.elements.get_mut(&id).expect("just found it");
+        element.deleted = true;
+        Some(element.value.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.visible().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn values(&self) -> Vec<T> {
+        self.visible().map(|e| e.value.clone()).collect()
+    }
+
+    pub fn merge(&mut self, other: &Rga<T>) {
+        for (id, theirs) in &other.elements {
+            self.counter = self.counter.max(id.counter);
+            self.elements
+                .entry(id.clone())
+                .and_modify(|ours| ours.deleted |= theirs.deleted)
+                .or_insert_with(|| theirs.clone());
+        }
+    }
+
+    fn visible(&self) -> impl Iterator<Item = &RgaElement<T>> {
+        self.ordered().into_iter().filter(|e| !e.deleted)
+    }
+
+    // every element, tombstones included, in sequence order
+    fn ordered(&self) -> Vec<&RgaElement<T>> {
+        let mut children: HashMap<Option<&RgaId>, Vec<&RgaElement<T>>> = HashMap::new();
+        for element in self.elements.values() {
+            children
+                .entry(element.after.as_ref())
+                .or_default()
+                .push(element);
+        }
+        for siblings in children.values_mut() {
+            siblings.sort_by(|a, b| b.id.cmp(&a.id));
+        }
+        // depth first: an element is followed by everything inserted after it before its next
+        // older sibling
+        let mut ordered = Vec::with_capacity(self.elements.len());
+        let mut stack: Vec<&RgaElement<T>> = children
+            .get(&None)
+            .map(|roots| roots.iter().rev().copied().collect())
+            .unwrap_or_default();
+        while let Some(element) = stack.pop() {
+            ordered.push(element);
+            if let Some(after) = children.get(&Some(&element.id)) {
+                stack.extend(after.iter().rev().copied());
+            }
+        }
+        ordered
+    }
+}
@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+// every value is reported to within this fraction of what it really was
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// A quantile sketch in the style of DDSketch: values go into logarithmically sized buckets, so
+/// a quantile read back is within 1% of an actual value however skewed the distribution. Two
+/// sketches merge by adding up their buckets, which is what lets every node summarise its own
+/// values and ship the summary instead of the values.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Buckets", into = "Buckets")]
+pub struct DdSketch {
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zeros: u64,
+}
+
+// maps don't survive internally tagged enums with integer keys, so the wire has lists
+#[derive(Serialize, Deserialize)]
+struct Buckets {
+    positive: Vec<(i32, u64)>,
+    negative: Vec<(i32, u64)>,
+    zeros: u64,
+}
+
+impl From<Buckets> for DdSketch {
+    fn from(buckets: Buckets) -> Self {
+        Self {
+            positive: buckets.positive.into_iter().collect(),
+            negative: buckets.negative.into_iter().collect(),
+            zeros: buckets.zeros,
+        }
+    }
+}
+
+impl From<DdSketch> for Buckets {
+    fn from(sketch: DdSketch) -> Self {
+        Self {
+            positive: sketch.positive.into_iter().collect(),
+            negative: sketch.negative.into_iter().collect(),
+            zeros: sketch.zeros,
+        }
+    }
+}
+
+fn gamma() -> f64 {
+    (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+}
+
+// bucket i holds (gamma^(i-1), gamma^i]
+fn bucket(magnitude: f64) -> i32 {
+    (magnitude.ln() / gamma().ln()).ceil() as i32
+}
+
+// within the relative accuracy of both ends of the bucket
+fn value(bucket: i32) -> f64 {
+    2.0 * gamma().powi(bucket) / (gamma() + 1.0)
+}
+
+impl DdSketch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, v: f64) {
+        if v > f64::MIN_POSITIVE {
+            *self.positive.entry(bucket(v)).or_default() += 1;
+        } else if v < -f64::MIN_POSITIVE {
+            *self.negative.entry(bucket(-v)).or_default() += 1;
+        } else {
+            self.zeros += 1;
+        }
+    }
+
+    pub fn merge(&mut self, other: &DdSketch) {
+        for (&b, &n) in &other.positive {
+            *self.positive.entry(b).or_default() += n;
+        }
+        for (&b, &n) in &other.negative {
+            *self.negative.entry(b).or_default() += n;
+        }
+        self.zeros += other.zeros;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.positive.values().sum::<u64>() + self.negative.values().sum::<u64>() + self.zeros
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// The value at quantile `q` (between 0 and 1), or `None` if nothing has been inserted.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (count - 1) as f64).round() as u64;
+        // from the most negative value up
+        let mut seen = 0;
+        for (&b, &n) in self.negative.iter().rev() {
+            seen += n;
+            if seen > rank {
+                return Some(-value(b));
+            }
+        }
+        seen += self.zeros;
+        if seen > rank {
+            return Some(0.0);
+        }
+        for (&b, &n) in &self.positive {
+            seen += n;
+            if seen > rank {
+                return Some(value(b));
+            }
+        }
+        unreachable!("rank is below the count")
+    }
+}
@@ -0,0 +1,19 @@
+// error codes understood by maelstrom, see
+// https://github.com/jepsen-io/maelstrom/blob/main/doc/protocol.md#errors
+pub const TIMEOUT: usize = 0;
+pub const NODE_NOT_FOUND: usize = 1;
+pub const NOT_SUPPORTED: usize = 10;
+pub const TEMPORARILY_UNAVAILABLE: usize = 11;
+pub const MALFORMED_REQUEST: usize = 12;
+pub const CRASH: usize = 13;
+pub const ABORT: usize = 14;
+pub const KEY_DOES_NOT_EXIST: usize = 20;
+pub const KEY_ALREADY_EXISTS: usize = 21;
+pub const PRECONDITION_FAILED: usize = 22;
+pub const TXN_CONFLICT: usize = 30;
+
+/// Whether an error with this code means the operation certainly didn't happen. Timeouts and
+/// crashes leave it up in the air.
+pub fn is_definite(code: usize) -> bool {
+    !matches!(code, TIMEOUT | CRASH)
+}
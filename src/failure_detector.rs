@@ -0,0 +1,250 @@
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{clock, config, ticks, Body, Event, Message};
+
+/// Events the detector injects into the node it runs in. Wrap them in the node's own injected
+/// payload type (it needs a `From<FdEvent>` impl) and hand `Heartbeat` back to
+/// [`FailureDetector::heartbeat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FdEvent {
+    // time to send heartbeats and look for peers that have gone quiet
+    Heartbeat,
+    PeerDown(String),
+    // a peer we'd declared down has been heard from again
+    PeerUp(String),
+}
+
+/// What goes over the wire. The node's payload type needs a matching unit `Heartbeat` variant so
+/// it can deserialize these (and otherwise ignore them).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum HeartbeatPayload {
+    Heartbeat,
+}
+
+/// Decides, from when we've heard from a single peer, whether to suspect it has failed.
+pub trait Suspicion: Send {
+    fn heard_from(&mut self, at: Instant);
+    fn suspect(&self, now: Instant) -> bool;
+}
+
+/// Suspects a peer once nothing has been heard from it for a fixed amount of time.
+pub struct Timeout {
+    timeout: Duration,
+    last_seen: Instant,
+}
+
+impl Timeout {
+    pub fn new(timeout: Duration, now: Instant) -> Self {
+        Self {
+            timeout,
+            last_seen: now,
+        }
+    }
+}
+
+impl Suspicion for Timeout {
+    fn heard_from(&mut self, at: Instant) {
+        self.last_seen = at;
+    }
+
+    fn suspect(&self, now: Instant) -> bool {
+        now.duration_since(self.last_seen) > self.timeout
+    }
+}
+
+// how many inter-arrival times phi is estimated from
+const PHI_WINDOW: usize = 100;
+// keeps a peer with very regular heartbeats from being suspected over a tiny hiccup
+const PHI_MIN_STD_DEV_MS: f64 = 50.0;
+
+/// The phi accrual detector (Hayashibara et al). Rather than a yes/no timeout it tracks the
+/// distribution of gaps between messages from a peer and computes phi, how unlikely (on a log10
+/// scale) the current silence is given that history. It suspects the peer once phi crosses the
+/// threshold, so it adapts to networks whose latency varies: a threshold of 8 with a normal
+/// distribution means being wrong about one time in 10^8.
+pub struct PhiAccrual {
+    threshold: f64,
+    intervals_ms: VecDeque<f64>,
+    last_seen: Instant,
+}
+
+impl PhiAccrual {
+    /// Starts out assuming messages arrive every `expected` until it has real gaps to go on.
+    pub fn new(threshold: f64, expected: Duration, now: Instant) -> Self {
+        Self {
+            threshold,
+            intervals_ms: VecDeque::from([expected.as_secs_f64() * 1000.0]),
+            last_seen: now,
+        }
+    }
+
+    pub fn phi(&self, now: Instant) -> f64 {
+        let n = self.intervals_ms.len() as f64;
+        let mean = self.intervals_ms.iter().sum::<f64>() / n;
+        let variance = self
+            .intervals_ms
+            .iter()
+            .map(|i| (i - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let std_dev = variance.sqrt().max(PHI_MIN_STD_DEV_MS);
+        let elapsed = now.duration_since(self.last_seen).as_secs_f64() * 1000.0;
+        // logistic approximation of the normal cdf, as used by akka and cassandra
+        let y = (elapsed - mean) / std_dev;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+}
+
+impl Suspicion for PhiAccrual {
+    fn heard_from(&mut self, at: Instant) {
+        let interval = at.duration_since(self.last_seen).as_secs_f64() * 1000.0;
+        self.last_seen = at;
+        if self.intervals_ms.len() == PHI_WINDOW {
+            self.intervals_ms.pop_front();
+        }
+        self.intervals_ms.push_back(interval);
+    }
+
+    fn suspect(&self, now: Instant) -> bool {
+        self.phi(now) > self.threshold
+    }
+}
+
+/// Which [`Suspicion`] the detector uses for each peer.
+#[derive(Debug, Clone, Copy)]
+pub enum Strategy {
+    Timeout(Duration),
+    PhiAccrual { threshold: f64 },
+}
+
+impl Strategy {
+    /// `RUSTENGAN_FD` picks the detector, `timeout` (the default, using `timeout`) or `phi`.
+    /// `RUSTENGAN_PHI_THRESHOLD` sets phi's threshold, 8 unless given.
+    pub fn from_env(timeout: Duration) -> anyhow::Result<Self> {
+        match config::var_or("RUSTENGAN_FD", "timeout".to_string())?.as_str() {
+            "timeout" => Ok(Self::Timeout(timeout)),
+            "phi" => Ok(Self::PhiAccrual {
+                threshold: config::var_or("RUSTENGAN_PHI_THRESHOLD", 8.0)?,
+            }),
+            other => anyhow::bail!(
+                "unknown failure detector {}, expected timeout or phi",
+                other
+            ),
+        }
+    }
+
+    fn build(&self, interval: Duration, now: Instant) -> Box<dyn Suspicion> {
+        match *self {
+            Self::Timeout(timeout) => Box::new(Timeout::new(timeout, now)),
+            Self::PhiAccrual { threshold } => Box::new(PhiAccrual::new(threshold, interval, now)),
+        }
+    }
+}
+
+struct Peer {
+    suspicion: Box<dyn Suspicion>,
+    down: bool,
+}
+
+/// Heartbeat-based failure detector. Every node sends every peer a heartbeat each interval, and
+/// whenever we hear from a peer (heartbeat or otherwise) its [`Suspicion`] is told about it. A
+/// peer is declared down once that suspects it.
+pub struct FailureDetector<P, IP> {
+    node: String,
+    peers: HashMap<String, Peer>,
+    inject: Sender<Event<P, IP>>,
+}
+
+impl<P, IP> FailureDetector<P, IP>
+where
+    P: Send + 'static,
+    IP: From<FdEvent> + Send + 'static,
+{
+    /// Starts the heartbeat timer. Every peer starts out up, as if we'd just heard from it.
+    pub fn start(
+        node: &str,
+        peers: impl IntoIterator<Item = String>,
+        interval: Duration,
+        strategy: Strategy,
+        inject: Sender<Event<P, IP>>,
+    ) -> Self {
+        let timer = inject.clone();
+        ticks::every(interval, timer, || {
+            Event::Injected(FdEvent::Heartbeat.into())
+        });
+        let now = clock::now();
+        Self {
+            node: node.to_string(),
+            peers: peers
+                .into_iter()
+                .filter(|p| p != node)
+                .map(|p| {
+                    let peer = Peer {
+                        suspicion: strategy.build(interval, now),
+                        down: false,
+                    };
+                    (p, peer)
+                })
+                .collect(),
+            inject,
+        }
+    }
+
+    /// Call for every message received. Senders that aren't peers (clients, services) are ignored.
+    pub fn heard_from(&mut self, src: &str) {
+        let Some(peer) = self.peers.get_mut(src) else {
+            return;
+        };
+        peer.suspicion.heard_from(clock::now());
+        if peer.down {
+            peer.down = false;
+            let _ = self
+                .inject
+                .send(Event::Injected(FdEvent::PeerUp(src.to_string()).into()));
+        }
+    }
+
+    /// Call on every injected `FdEvent::Heartbeat`.
+    pub fn heartbeat(&mut self, output: &mut impl Write) -> anyhow::Result<()> {
+        let now = clock::now();
+        for (n, peer) in &mut self.peers {
+            if !peer.down && peer.suspicion.suspect(now) {
+                peer.down = true;
+                let _ = self
+                    .inject
+                    .send(Event::Injected(FdEvent::PeerDown(n.clone()).into()));
+            }
+            // down peers get heartbeats too, otherwise they'd never hear from us to come back up
+            Message {
+                src: self.node.clone(),
+                dst: n.clone(),
+                body: Body {
+                    id: None,
+                    in_reply_to: None,
+                    correlation_id: None,
+                    payload: HeartbeatPayload::Heartbeat,
+                },
+            }
+            .send(&mut *output)
+            .with_context(|| format!("heartbeat to {}", n))?;
+        }
+        Ok(())
+    }
+
+    pub fn is_down(&self, peer: &str) -> bool {
+        self.peers.get(peer).is_some_and(|p| p.down)
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::session::{Direction, Record};
+
+/// One message's trip from one node to another, out of the session logs at either end: when
+/// it was sent, if its sender kept a log, and when it arrived, if its receiver did and it got
+/// there. Clients keep no logs, so what a client sends has only an arrival and what it's sent
+/// only a departure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hop {
+    pub src: String,
+    pub dst: String,
+    /// The body's `type`.
+    pub kind: String,
+    pub msg_id: Option<u64>,
+    pub in_reply_to: Option<u64>,
+    /// Nanoseconds since the unix epoch, as the session logs have it.
+    pub sent: Option<u64>,
+    pub received: Option<u64>,
+    /// Whether it was sent to a node that kept a log, which never logged it arriving.
+    pub lost: bool,
+    pub message: Value,
+}
+
+impl Hop {
+    /// When the hop started, as far as anyone saw.
+    pub fn at(&self) -> u64 {
+        self.sent.or(self.received).unwrap_or_default()
+    }
+}
+
+/// Every hop in the session logs of a cluster's nodes, given with the id of the node that
+/// wrote each, in the order they started. A message sent is matched up with one received that's
+/// the same message between the same two nodes, first sent to first received.
+pub fn from_sessions(logs: &[(String, Vec<Record>)]) -> Vec<Hop> {
+    let nodes: HashSet<&str> = logs.iter().map(|(node, _)| node.as_str()).collect();
+    let mut records: Vec<&Record> = logs.iter().flat_map(|(_, records)| records).collect();
+    records.sort_by_key(|record| record.time);
+
+    let mut hops: Vec<Hop> = Vec::new();
+    // by the message as it went over the wire, the hops sent that haven't been received yet
+    let mut in_flight: HashMap<String, VecDeque<usize>> = HashMap::new();
+    for record in records {
+        let message = &record.message;
+        let (Some(src), Some(dst), Some(body)) = (
+            message.get("src").and_then(Value::as_str),
+            message.get("dest").and_then(Value::as_str),
+            message.get("body"),
+        ) else {
+            continue;
+        };
+        let key = message.to_string();
+        if record.direction == Direction::Received {
+            if let Some(hop) = in_flight.get_mut(&key).and_then(VecDeque::pop_front) {
+                hops[hop].received = Some(record.time);
+                hops[hop].lost = false;
+                continue;
+            }
+        }
+        let (sent, received) = match record.direction {
+            Direction::Sent => (Some(record.time), None),
+            Direction::Received => (None, Some(record.time)),
+        };
+        if record.direction == Direction::Sent {
+            in_flight.entry(key).or_default().push_back(hops.len());
+        }
+        hops.push(Hop {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            kind: body
+                .get("type")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+                .to_string(),
+            msg_id: body.get("msg_id").and_then(Value::as_u64),
+            in_reply_to: body.get("in_reply_to").and_then(Value::as_u64),
+            sent,
+            received,
+            lost: sent.is_some() && nodes.contains(dst),
+            message: message.clone(),
+        });
+    }
+    hops.sort_by_key(Hop::at);
+    hops
+}
+
+/// The hops that started between `from` and `to` after the first hop did.
+pub fn window(hops: &[Hop], from: Duration, to: Duration) -> Vec<Hop> {
+    let Some(start) = hops.iter().map(Hop::at).min() else {
+        return Vec::new();
+    };
+    hops.iter()
+        .filter(|hop| {
+            let since = Duration::from_nanos(hop.at() - start);
+            from <= since && since <= to
+        })
+        .cloned()
+        .collect()
+}
+
+/// The hops that follow from the message `src` sent as `msg_id`: that message, the replies to
+/// it, and whatever each node that got one of them sent after it before anything else arrived,
+/// and so on from those, as far as it goes. Which is how a request's forwarding and fanning
+/// out, or an election's votes, look in the logs, though a node that sends something on a
+/// timer of its own just then gets it put down to whatever it got last.
+pub fn chain(hops: &[Hop], src: &str, msg_id: u64) -> Vec<Hop> {
+    // by node, everything that happened there in order: true for a hop arriving
+    let mut timelines: BTreeMap<&str, Vec<(u64, bool, usize)>> = BTreeMap::new();
+    for (i, hop) in hops.iter().enumerate() {
+        if let Some(sent) = hop.sent {
+            timelines
+                .entry(&hop.src)
+                .or_default()
+                .push((sent, false, i));
+        }
+        if let Some(received) = hop.received {
+            timelines
+                .entry(&hop.dst)
+                .or_default()
+                .push((received, true, i));
+        }
+    }
+    for timeline in timelines.values_mut() {
+        timeline.sort();
+    }
+
+    let mut chained = BTreeSet::new();
+    let mut next: VecDeque<usize> = hops
+        .iter()
+        .position(|hop| hop.src == src && hop.msg_id == Some(msg_id))
+        .into_iter()
+        .collect();
+    while let Some(i) = next.pop_front() {
+        if !chained.insert(i) {
+            continue;
+        }
+        let hop = &hops[i];
+        // replies to it
+        if let Some(id) = hop.msg_id {
+            next.extend((0..hops.len()).filter(|&j| {
+                hops[j].src == hop.dst && hops[j].dst == hop.src && hops[j].in_reply_to == Some(id)
+            }));
+        }
+        // and what its receiver sent before it got anything else
+        let (Some(received), Some(timeline)) = (hop.received, timelines.get(hop.dst.as_str()))
+        else {
+            continue;
+        };
+        let after = timeline.partition_point(|&(at, _, j)| (at, j) <= (received, i));
+        next.extend(
+            timeline[after..]
+                .iter()
+                .take_while(|(_, arrived, _)| !arrived)
+                .map(|&(_, _, j)| j),
+        );
+    }
+    chained.into_iter().map(|i| hops[i].clone()).collect()
+}
+
+/// `hops` as a Graphviz graph of who sent what to whom: an edge for each type of message
+/// between each two nodes, with how many went, dashed if any of them were lost.
+pub fn to_dot(hops: &[Hop]) -> String {
+    let mut edges: BTreeMap<(&str, &str, &str), (usize, usize)> = BTreeMap::new();
+    let mut nodes = BTreeSet::new();
+    for hop in hops {
+        nodes.insert(hop.src.as_str());
+        nodes.insert(hop.dst.as_str());
+        let (sent, lost) = edges.entry((&hop.src, &hop.dst, &hop.kind)).or_default();
+        *sent += 1;
+        *lost += usize::from(hop.lost);
+    }
+    let mut dot = String::from("digraph flow {\n  rankdir=LR;\n");
+    for node in nodes {
+        // Maelstrom names its clients c1, c2, ...
+        let shape = match node.starts_with('c') {
+            true => "box",
+            false => "ellipse",
+        };
+        let _ = writeln!(dot, "  {:?} [shape={}];", node, shape);
+    }
+    for ((src, dst, kind), (sent, lost)) in edges {
+        let mut label = format!("{} ×{}", kind, sent);
+        if lost > 0 {
+            let _ = write!(label, " ({} lost)", lost);
+        }
+        let style = match lost {
+            0 => "solid",
+            _ => "dashed",
+        };
+        let _ = writeln!(
+            dot,
+            "  {:?} -> {:?} [label={:?}, style={}];",
+            src, dst, label, style
+        );
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// `hops` as a Mermaid sequence diagram, each in the order it started, numbered, with the
+/// time since the first alongside and a cross for each that was lost.
+pub fn to_sequence(hops: &[Hop]) -> String {
+    let start = hops.iter().map(Hop::at).min().unwrap_or_default();
+    let mut participants = Vec::new();
+    for hop in hops {
+        for node in [&hop.src, &hop.dst] {
+            if !participants.contains(&node) {
+                participants.push(node);
+            }
+        }
+    }
+    let mut diagram = String::from("sequenceDiagram\n  autonumber\n");
+    for participant in participants {
+        let _ = writeln!(diagram, "  participant {}", participant);
+    }
+    for hop in hops {
+        let arrow = match hop.lost {
+            true => "-x",
+            false => "->>",
+        };
+        let mut label = hop.kind.clone();
+        if let Some(id) = hop.msg_id {
+            let _ = write!(label, " #{}", id);
+        }
+        if let Some(id) = hop.in_reply_to {
+            let _ = write!(label, " re #{}", id);
+        }
+        let since = Duration::from_nanos(hop.at() - start);
+        let _ = writeln!(
+            diagram,
+            "  {}{}{}: {} @{:.3}ms",
+            hop.src,
+            arrow,
+            hop.dst,
+            label,
+            since.as_secs_f64() * 1000.0
+        );
+    }
+    diagram
+}
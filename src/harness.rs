@@ -0,0 +1,343 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::kv::service::Service;
+use crate::{rng, Body, Message};
+
+/// How long a [`Process`] waits for its node to answer, or to exit, unless it's been told
+/// otherwise with [`Process::within`].
+pub const WITHIN: Duration = Duration::from_secs(5);
+
+// how much of the node's stderr an error quotes
+const STDERR_TAIL: usize = 20;
+
+// `Process`es started by this process, for telling their data directories apart
+static SPAWNED: AtomicUsize = AtomicUsize::new(0);
+
+/// A node binary running as a child process, spoken to the way Maelstrom speaks to it: a line of
+/// JSON on its stdin for every message in, and a line out of its stdout for every message back.
+/// Where the simulator steps a node in-process, this goes through the binary's `main`, its
+/// pipes and its threads, so a reply that sits in a buffer, input the node can't read, or a node
+/// that hangs once its input closes, shows up here as it would under Maelstrom, only sooner and
+/// with an error saying which.
+///
+/// Messages the node writes besides the reply being waited for, like gossip to its peers or
+/// requests to a Maelstrom service, are kept in order for [`Process::recv`]. Every line the node
+/// writes has to be a message: anything else, like a stray `println!`, is an error, since it
+/// would be one under Maelstrom too.
+pub struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    lines: Receiver<String>,
+    stderr: Arc<Mutex<Vec<String>>>,
+    // what the node wrote that nothing has asked for yet
+    unclaimed: VecDeque<Message<Value>>,
+    next_id: usize,
+    node_id: String,
+    // a data directory of our own, to clean up once the node's gone
+    data_dir: Option<PathBuf>,
+    within: Duration,
+    services: Vec<Service>,
+    // services' answers, and when they're due to go to the node
+    answers: Vec<(Instant, String)>,
+    started: Instant,
+}
+
+impl Process {
+    /// Starts `binary` on stdio, with a data directory of its own under the system's temporary
+    /// directory for anything it keeps on disk.
+    pub fn spawn(binary: &str) -> anyhow::Result<Self> {
+        Self::command(Command::new(binary))
+    }
+
+    /// Starts `command` with its stdin and stdout piped to the harness, and its stderr kept for
+    /// the harness's errors to quote. Unless `command` sets `RUSTENGAN_DATA_DIR` or
+    /// `RUSTENGAN_TRANSPORT` itself, the node gets a fresh data directory and stdio.
+    pub fn command(mut command: Command) -> anyhow::Result<Self> {
+        let set = |command: &Command, name: &str| command.get_envs().any(|(key, _)| key == name);
+        if !set(&command, "RUSTENGAN_TRANSPORT") {
+            command.env_remove("RUSTENGAN_TRANSPORT");
+        }
+        let data_dir = if set(&command, "RUSTENGAN_DATA_DIR") {
+            None
+        } else {
+            let dir = std::env::temp_dir().join(format!(
+                "rustengan-harness-{}-{}",
+                std::process::id(),
+                SPAWNED.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            command.env("RUSTENGAN_DATA_DIR", &dir);
+            Some(dir)
+        };
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("start {:?}", command.get_program()))?;
+
+        let stdout = child.stdout.take().expect("piped");
+        let (tx, lines) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let stderr = Arc::new(Mutex::new(Vec::new()));
+        let kept = Arc::clone(&stderr);
+        let pipe = child.stderr.take().expect("piped");
+        std::thread::spawn(move || {
+            for line in BufReader::new(pipe).lines() {
+                let Ok(line) = line else { break };
+                kept.lock().expect("not poisoned").push(line);
+            }
+        });
+
+        Ok(Self {
+            stdin: child.stdin.take(),
+            child,
+            lines,
+            stderr,
+            unclaimed: VecDeque::new(),
+            next_id: 1,
+            node_id: String::new(),
+            data_dir,
+            within: WITHIN,
+            services: Vec::new(),
+            answers: Vec::new(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Waits `within` for replies, messages and exits from now on, instead of [`WITHIN`].
+    pub fn within(&mut self, within: Duration) -> &mut Self {
+        self.within = within;
+        self
+    }
+
+    /// Has `service` answer whatever the node sends to it by its name, in place of Maelstrom's.
+    /// The harness only hears the node while it's waiting on it, for a reply or a message or
+    /// for [`Process::recv`] to time out, so that's when the service answers too: after its
+    /// [`Service::delay`], as long as the harness is still waiting by then.
+    pub fn serve(&mut self, service: Service) -> &mut Self {
+        self.services.push(service);
+        self
+    }
+
+    /// Sends the node the init Maelstrom would, making it `node_id` of `node_ids`, and waits for
+    /// it to say it's ready.
+    pub fn init(&mut self, node_id: &str, node_ids: &[&str]) -> anyhow::Result<()> {
+        self.node_id = node_id.to_string();
+        let init = json!({ "type": "init", "node_id": node_id, "node_ids": node_ids });
+        let reply: Message<Value> = self.request("c0", init)?;
+        anyhow::ensure!(
+            reply.body.payload.get("type").and_then(Value::as_str) == Some("init_ok"),
+            "{} answered its init with {}",
+            node_id,
+            reply.body.payload
+        );
+        Ok(())
+    }
+
+    /// Sends the node `payload` from `src`, under a msg_id of its own, which it returns.
+    pub fn send<P: Serialize>(&mut self, src: &str, payload: P) -> anyhow::Result<usize> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.deliver(&Message {
+            src: src.to_string(),
+            dst: self.node_id.clone(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        })?;
+        Ok(id)
+    }
+
+    /// Sends the node `message` as it is, like a reply to something the node asked a peer or a
+    /// service.
+    pub fn deliver<P: Serialize>(&mut self, message: &Message<P>) -> anyhow::Result<()> {
+        let line = serde_json::to_string(message).context("serialize message")?;
+        self.write_line(&line)
+    }
+
+    /// Writes `line` to the node's stdin. It doesn't have to be a message.
+    pub fn write_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let stdin = self.stdin.as_mut().context("the node's input is closed")?;
+        let written = writeln!(stdin, "{}", line).and_then(|()| stdin.flush());
+        written
+            .with_context(|| format!("{} stopped reading its input{}", self.node_id, self.tail()))
+    }
+
+    /// Sends the node `payload` from `src`, and waits for its reply.
+    pub fn request<P: Serialize, R: DeserializeOwned>(
+        &mut self,
+        src: &str,
+        payload: P,
+    ) -> anyhow::Result<Message<R>> {
+        let id = self.send(src, payload)?;
+        self.reply_to(src, id)
+    }
+
+    /// Waits for the node's reply to `src`'s msg_id `id`, keeping whatever else it writes in the
+    /// meantime for [`Process::recv`].
+    pub fn reply_to<R: DeserializeOwned>(
+        &mut self,
+        src: &str,
+        id: usize,
+    ) -> anyhow::Result<Message<R>> {
+        let answers =
+            |message: &Message<Value>| message.dst == src && message.body.in_reply_to == Some(id);
+        let reply = match self.unclaimed.iter().position(answers) {
+            Some(at) => self.unclaimed.remove(at).expect("in range"),
+            None => {
+                let deadline = Instant::now() + self.within;
+                loop {
+                    let message = self.next(deadline)?.with_context(|| {
+                        format!(
+                            "{} didn't answer {}'s msg {} within {:?}, or wrote its answer \
+                             without flushing it{}",
+                            self.node_id,
+                            src,
+                            id,
+                            self.within,
+                            self.tail()
+                        )
+                    })?;
+                    if answers(&message) {
+                        break message;
+                    }
+                    self.unclaimed.push_back(message);
+                }
+            }
+        };
+        let value = serde_json::to_value(&reply).context("serialize reply")?;
+        serde_json::from_value(value.clone()).with_context(|| {
+            format!(
+                "{} answered {}'s msg {} with {}",
+                self.node_id, src, id, value
+            )
+        })
+    }
+
+    /// The next message the node writes that nothing has asked for, if it writes one in time.
+    pub fn recv(&mut self) -> anyhow::Result<Option<Message<Value>>> {
+        if let Some(message) = self.unclaimed.pop_front() {
+            return Ok(Some(message));
+        }
+        self.next(Instant::now() + self.within)
+    }
+
+    /// Closes the node's input, as Maelstrom does at the end of a run, and waits for it to exit.
+    pub fn close(&mut self) -> anyhow::Result<ExitStatus> {
+        drop(self.stdin.take());
+        let deadline = Instant::now() + self.within;
+        loop {
+            if let Some(status) = self.child.try_wait().context("wait for the node")? {
+                return Ok(status);
+            }
+            anyhow::ensure!(
+                Instant::now() < deadline,
+                "{} was still running {:?} after its input closed{}",
+                self.node_id,
+                self.within,
+                self.tail()
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// The node's process id, for signalling it.
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Everything the node has written to its stderr so far.
+    pub fn stderr(&self) -> Vec<String> {
+        self.stderr.lock().expect("not poisoned").clone()
+    }
+
+    // the next message the node writes before `deadline` that isn't for a service, answering
+    // what is for a service as it comes in, and delivering the answers as they fall due
+    fn next(&mut self, deadline: Instant) -> anyhow::Result<Option<Message<Value>>> {
+        loop {
+            let now = Instant::now();
+            let (due, pending): (Vec<_>, Vec<_>) =
+                self.answers.drain(..).partition(|(at, _)| *at <= now);
+            self.answers = pending;
+            for (_, answer) in due {
+                self.write_line(&answer)?;
+            }
+            let until = self
+                .answers
+                .iter()
+                .map(|(at, _)| *at)
+                .fold(deadline, Instant::min);
+            let line = match self
+                .lines
+                .recv_timeout(until.saturating_duration_since(now))
+            {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) if until < deadline => continue,
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    let status = self.child.wait().context("wait for the node")?;
+                    anyhow::bail!("{} exited with {}{}", self.node_id, status, self.tail());
+                }
+            };
+            let message: Message<Value> = serde_json::from_str(&line).with_context(|| {
+                format!(
+                    "{} wrote a line that isn't a message: {}",
+                    self.node_id, line
+                )
+            })?;
+            let Some(service) = self.services.iter_mut().find(|s| s.name() == message.dst) else {
+                return Ok(Some(message));
+            };
+            let mut rng = rng::thread();
+            let at = Instant::now() + service.delay(&mut rng);
+            if let Some(answer) = service.handle(&message, self.started.elapsed(), &mut rng) {
+                let answer = serde_json::to_string(&answer).context("serialize answer")?;
+                self.answers.push((at, answer));
+            }
+        }
+    }
+
+    // the end of the node's stderr, to go on the end of an error
+    fn tail(&self) -> String {
+        // the node may have written more than we've read yet
+        std::thread::sleep(Duration::from_millis(50));
+        let stderr = self.stderr.lock().expect("not poisoned");
+        let tail = &stderr[stderr.len().saturating_sub(STDERR_TAIL)..];
+        if tail.is_empty() {
+            return String::new();
+        }
+        format!("; its stderr ends:\n{}", tail.join("\n"))
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(dir) = &self.data_dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
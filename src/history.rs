@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use serde_json::Value;
+
+use crate::error;
+use crate::session::{Direction, Record};
+
+pub mod linearizable;
+pub mod workload;
+
+/// What an entry of a history says happened to its operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Invoke,
+    Ok,
+    Fail,
+    Info,
+}
+
+/// The register operations a history can hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum F {
+    Read,
+    Write,
+    Cas,
+}
+
+/// One entry of a Jepsen history. `value` is a `[key value]` tuple, the way
+/// `jepsen.independent` has them: `[k nil]` for a read that's yet to be answered, `[k v]` once
+/// it is or for a write, and `[k [from to]]` for a cas.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Op {
+    pub index: usize,
+    pub kind: Type,
+    pub f: F,
+    pub value: Value,
+    pub process: usize,
+    // nanoseconds since the first operation was invoked
+    pub time: u64,
+}
+
+impl Op {
+    /// The entry as an EDN map, the way Jepsen writes histories out.
+    pub fn to_edn(&self) -> String {
+        let kind = match self.kind {
+            Type::Invoke => "invoke",
+            Type::Ok => "ok",
+            Type::Fail => "fail",
+            Type::Info => "info",
+        };
+        let f = match self.f {
+            F::Read => "read",
+            F::Write => "write",
+            F::Cas => "cas",
+        };
+        let mut out = String::new();
+        let _ = write!(out, "{{:type :{}, :f :{}, :value ", kind, f);
+        edn(&self.value, &mut out);
+        let _ = write!(
+            out,
+            ", :process {}, :time {}, :index {}}}",
+            self.process, self.time, self.index
+        );
+        out
+    }
+}
+
+fn edn(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("nil"),
+        Value::Bool(b) => {
+            let _ = write!(out, "{}", b);
+        }
+        Value::Number(n) => {
+            let _ = write!(out, "{}", n);
+        }
+        Value::String(s) => {
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(' ');
+                }
+                edn(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(entries) => {
+            out.push('{');
+            for (i, (k, v)) in entries.iter().enumerate() {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                edn(&Value::String(k.clone()), out);
+                out.push(' ');
+                edn(v, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+struct Pending {
+    process: usize,
+    f: F,
+    value: Value,
+}
+
+// what a client asked for, as a history has it
+fn invocation(body: &Value) -> Option<(F, Value)> {
+    let key = body.get("key")?.clone();
+    match body.get("type")?.as_str()? {
+        "read" => Some((F::Read, Value::Array(vec![key, Value::Null]))),
+        "write" => Some((
+            F::Write,
+            Value::Array(vec![key, body.get("value")?.clone()]),
+        )),
+        "cas" => {
+            let from_to = Value::Array(vec![body.get("from")?.clone(), body.get("to")?.clone()]);
+            Some((F::Cas, Value::Array(vec![key, from_to])))
+        }
+        _ => None,
+    }
+}
+
+/// The history of the reads, writes and cas operations clients made of a cluster, out of the
+/// session logs of its nodes, given with the id of the node that wrote each. Anyone a node
+/// heard from who isn't one of those nodes is a client, and every client is a process. A
+/// client that asks for something new without having been answered is taken to have given up
+/// on the operation it's waiting on, which is then `:info`, and goes on as a fresh process, the
+/// same as Jepsen does with a process that crashed.
+pub fn from_sessions(logs: &[(String, Vec<Record>)]) -> Vec<Op> {
+    let nodes: HashSet<&str> = logs.iter().map(|(node, _)| node.as_str()).collect();
+    let mut records: Vec<&Record> = logs.iter().flat_map(|(_, records)| records).collect();
+    records.sort_by_key(|record| record.time);
+
+    let mut history = Vec::new();
+    let mut processes: HashMap<String, usize> = HashMap::new();
+    let mut next_process = 0;
+    // by client, the request it's waiting on an answer to
+    let mut waiting: HashMap<String, (u64, Pending)> = HashMap::new();
+    let mut start = None;
+    for record in records {
+        let message = &record.message;
+        let (Some(src), Some(dest), Some(body)) = (
+            message.get("src").and_then(Value::as_str),
+            message.get("dest").and_then(Value::as_str),
+            message.get("body"),
+        ) else {
+            continue;
+        };
+        match record.direction {
+            Direction::Received if !nodes.contains(src) => {
+                let (Some(msg_id), Some((f, value))) =
+                    (body.get("msg_id").and_then(Value::as_u64), invocation(body))
+                else {
+                    continue;
+                };
+                let start = *start.get_or_insert(record.time);
+                let time = record.time - start;
+                if let Some((_, abandoned)) = waiting.remove(src) {
+                    history.push(Op {
+                        index: history.len(),
+                        kind: Type::Info,
+                        f: abandoned.f,
+                        value: abandoned.value,
+                        process: abandoned.process,
+                        time,
+                    });
+                    processes.remove(src);
+                }
+                let process = *processes.entry(src.to_string()).or_insert_with(|| {
+                    next_process += 1;
+                    next_process - 1
+                });
+                history.push(Op {
+                    index: history.len(),
+                    kind: Type::Invoke,
+                    f,
+                    value: value.clone(),
+                    process,
+                    time,
+                });
+                waiting.insert(src.to_string(), (msg_id, Pending { process, f, value }));
+            }
+            Direction::Sent if !nodes.contains(dest) => {
+                let Some(in_reply_to) = body.get("in_reply_to").and_then(Value::as_u64) else {
+                    continue;
+                };
+                if waiting
+                    .get(dest)
+                    .is_none_or(|(msg_id, _)| *msg_id != in_reply_to)
+                {
+                    continue;
+                }
+                let (_, pending) = waiting.remove(dest).expect("just checked");
+                let (kind, value) = match body.get("type").and_then(Value::as_str) {
+                    Some("read_ok") => {
+                        let key = pending.value[0].clone();
+                        let read = body.get("value").cloned().unwrap_or(Value::Null);
+                        (Type::Ok, Value::Array(vec![key, read]))
+                    }
+                    Some("write_ok" | "cas_ok") => (Type::Ok, pending.value),
+                    Some("error") => {
+                        let definite = body
+                            .get("code")
+                            .and_then(Value::as_u64)
+                            .is_some_and(|code| error::is_definite(code as usize));
+                        let kind = if definite { Type::Fail } else { Type::Info };
+                        (kind, pending.value)
+                    }
+                    _ => (Type::Info, pending.value),
+                };
+                // an answer can't be seen before the question was, whatever the clocks say
+                let time = record.time.saturating_sub(start.unwrap_or(record.time));
+                history.push(Op {
+                    index: history.len(),
+                    kind,
+                    f: pending.f,
+                    value,
+                    process: pending.process,
+                    time,
+                });
+                if kind == Type::Info {
+                    processes.remove(dest);
+                }
+            }
+            _ => {}
+        }
+    }
+    history
+}
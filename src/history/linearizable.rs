@@ -0,0 +1,261 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use serde_json::Value;
+
+use super::{Op, Type, F};
+
+/// Why a history isn't linearizable: the ops on `key` that could be put in an order, and the
+/// op none of those orders could make sense of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub key: Value,
+    /// The longest order of the key's ops the search found that a register could have gone
+    /// through, as the indexes of their invocations in the history.
+    pub linearized: Vec<usize>,
+    /// The invocation of the op that completed without any way of fitting it in after them.
+    pub stuck: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "key {} isn't linearizable: after the ops invoked at {:?}, nothing explains the op invoked at {}",
+            self.key, self.linearized, self.stuck
+        )
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Checks that `history` is linearizable as a map of registers that start out nil: that every
+/// read, write and cas that completed took effect at some single point between its invocation
+/// and its completion, the way it would on a single copy of the data. Failed ops definitely
+/// didn't happen, so they're left out. Ops that never completed, or completed `:info`, may or
+/// may not have happened: writes and cas operations among them are fitted in wherever they
+/// explain the rest, or nowhere, and reads are left out, since they change nothing.
+///
+/// Each key is a register of its own, so each is checked on its own (P-compositionality), with
+/// Wing & Gong's search over the orders its ops could have taken effect in, pruned by
+/// remembering every set of ops and register value it's tried before (Lowe's memoization).
+/// That's usually fast, but in the worst case the search is exponential in how many of a key's
+/// ops overlap in time.
+pub fn check(history: &[Op]) -> Result<(), Violation> {
+    let mut keys: BTreeMap<String, Register> = BTreeMap::new();
+    // by process, the op it's invoked and is yet to complete, and on which key
+    let mut open: HashMap<usize, (String, usize)> = HashMap::new();
+    for op in history {
+        let Some([key, value]) = op.value.as_array().map(Vec::as_slice) else {
+            log::warn!("skipping op {}, whose value isn't [key value]", op.index);
+            continue;
+        };
+        let name = key.to_string();
+        if op.kind == Type::Invoke {
+            let register = keys.entry(name.clone()).or_insert_with(|| Register {
+                key: key.clone(),
+                ..Register::default()
+            });
+            let Some(call) = register.invoke(op.index, op.f, value) else {
+                log::warn!("skipping op {}, which isn't a read, write or cas", op.index);
+                continue;
+            };
+            open.insert(op.process, (name, call));
+            continue;
+        }
+        let Some((name, call)) = open.remove(&op.process) else {
+            continue;
+        };
+        let register = keys.get_mut(&name).expect("invoked");
+        match op.kind {
+            Type::Ok => register.complete(call, value),
+            Type::Fail => register.calls[call].failed = true,
+            Type::Info | Type::Invoke => {}
+        }
+    }
+    keys.into_values().try_for_each(|register| register.check())
+}
+
+// what an op did to a register, with values interned
+#[derive(Debug, Clone, Copy)]
+enum Effect {
+    // what it read, once it's known, and None for nil
+    Read(Option<Option<usize>>),
+    Write(usize),
+    Cas(usize, usize),
+}
+
+#[derive(Debug)]
+struct Call {
+    index: usize,
+    effect: Effect,
+    completed: bool,
+    failed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Register {
+    key: Value,
+    calls: Vec<Call>,
+    // in the order they happened, each call's invocation and completion, if it completed
+    events: Vec<(usize, bool)>,
+    values: HashMap<String, usize>,
+}
+
+impl Register {
+    fn intern(&mut self, value: &Value) -> usize {
+        let next = self.values.len();
+        *self.values.entry(value.to_string()).or_insert(next)
+    }
+
+    fn invoke(&mut self, index: usize, f: F, value: &Value) -> Option<usize> {
+        let effect = match f {
+            F::Read => Effect::Read(None),
+            F::Write => Effect::Write(self.intern(value)),
+            F::Cas => {
+                let [from, to] = value.as_array()?.as_slice() else {
+                    return None;
+                };
+                Effect::Cas(self.intern(from), self.intern(to))
+            }
+        };
+        self.calls.push(Call {
+            index,
+            effect,
+            completed: false,
+            failed: false,
+        });
+        self.events.push((self.calls.len() - 1, false));
+        Some(self.calls.len() - 1)
+    }
+
+    fn complete(&mut self, call: usize, value: &Value) {
+        if let Effect::Read(_) = self.calls[call].effect {
+            let read = (!value.is_null()).then(|| self.intern(value));
+            self.calls[call].effect = Effect::Read(Some(read));
+        }
+        self.calls[call].completed = true;
+        self.events.push((call, true));
+    }
+
+    fn check(self) -> Result<(), Violation> {
+        let calls = &self.calls;
+        // what's left to search: what definitely happened, and what might have changed something
+        let events: Vec<(usize, bool)> = self
+            .events
+            .iter()
+            .copied()
+            .filter(|&(call, _)| {
+                let call = &calls[call];
+                !call.failed && (call.completed || !matches!(call.effect, Effect::Read(_)))
+            })
+            .collect();
+        let must = calls
+            .iter()
+            .filter(|call| call.completed && !call.failed)
+            .count();
+        // by call, its completion's place in `events`
+        let mut completion = vec![None; calls.len()];
+        for (at, &(call, completes)) in events.iter().enumerate() {
+            if completes {
+                completion[call] = Some(at);
+            }
+        }
+
+        // the events not yet linearized, as a doubly linked list with its head at `head`
+        let head = events.len();
+        let mut next: Vec<usize> = (1..=events.len()).chain([0]).collect();
+        let mut prev: Vec<usize> = std::iter::once(head).chain(0..head).collect();
+        next[head] = if events.is_empty() { usize::MAX } else { 0 };
+        if let Some(last) = events.len().checked_sub(1) {
+            next[last] = usize::MAX;
+        }
+        let lift = |next: &mut Vec<usize>, prev: &mut Vec<usize>, at: usize| {
+            next[prev[at]] = next[at];
+            if next[at] != usize::MAX {
+                prev[next[at]] = prev[at];
+            }
+        };
+        let unlift = |next: &mut Vec<usize>, prev: &mut Vec<usize>, at: usize| {
+            next[prev[at]] = at;
+            if next[at] != usize::MAX {
+                prev[next[at]] = at;
+            }
+        };
+
+        let mut state: Option<usize> = None;
+        let mut linearized = vec![false; calls.len()];
+        let mut done = 0;
+        let mut tried: HashSet<(Vec<bool>, Option<usize>)> = HashSet::new();
+        // the invocations linearized so far, with the register's value before each
+        let mut stack: Vec<(usize, Option<usize>)> = Vec::new();
+        let mut deepest: Option<(Vec<usize>, usize)> = None;
+        let mut at = next[head];
+        loop {
+            if done == must {
+                return Ok(());
+            }
+            let (call, completes) = match events.get(at) {
+                Some(&event) => event,
+                None => (usize::MAX, true),
+            };
+            if !completes {
+                if let Some(after) = apply(state, calls[call].effect) {
+                    linearized[call] = true;
+                    if tried.insert((linearized.clone(), after)) {
+                        stack.push((at, state));
+                        state = after;
+                        done += usize::from(calls[call].completed);
+                        lift(&mut next, &mut prev, at);
+                        if let Some(completion) = completion[call] {
+                            lift(&mut next, &mut prev, completion);
+                        }
+                        at = next[head];
+                        continue;
+                    }
+                    linearized[call] = false;
+                }
+                at = next[at];
+                continue;
+            }
+            // a completion with its invocation still to be linearized, which nothing before it
+            // could be: back up and try the last op linearized somewhere later on
+            if call != usize::MAX
+                && deepest
+                    .as_ref()
+                    .is_none_or(|(order, _)| stack.len() >= order.len())
+            {
+                let order = stack.iter().map(|&(at, _)| calls[events[at].0].index);
+                deepest = Some((order.collect(), calls[call].index));
+            }
+            let Some((undo, before)) = stack.pop() else {
+                let (linearized, stuck) = deepest.unwrap_or_default();
+                return Err(Violation {
+                    key: self.key,
+                    linearized,
+                    stuck,
+                });
+            };
+            let call = events[undo].0;
+            state = before;
+            linearized[call] = false;
+            done -= usize::from(calls[call].completed);
+            if let Some(completion) = completion[call] {
+                unlift(&mut next, &mut prev, completion);
+            }
+            unlift(&mut next, &mut prev, undo);
+            at = next[undo];
+        }
+    }
+}
+
+// the register's value after `effect`, if it could have happened with the register at `state`
+fn apply(state: Option<usize>, effect: Effect) -> Option<Option<usize>> {
+    match effect {
+        Effect::Read(Some(read)) => (read == state).then_some(state),
+        // a read that never completed changes nothing, whatever it would have read
+        Effect::Read(None) => Some(state),
+        Effect::Write(value) => Some(Some(value)),
+        Effect::Cas(from, to) => (state == Some(from)).then_some(Some(to)),
+    }
+}
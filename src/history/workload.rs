@@ -0,0 +1,356 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::session::{Direction, Record};
+
+/// Something a workload's checker found wrong with what a cluster's clients saw, the way
+/// Maelstrom's checker for the workload would have it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// An acknowledged broadcast that the last read answered by `node` didn't have.
+    Missing { message: Value, node: String },
+    /// A message a read of `node` returned that no client ever broadcast.
+    Phantom { message: Value, node: String },
+    /// An id handed out twice, by `first` and then by `second`.
+    Duplicate {
+        id: Value,
+        first: String,
+        second: String,
+    },
+    /// A final read of a counter that can't be made of the adds that were made: it has to take
+    /// in every acknowledged add, and can take in any of the ones that weren't acknowledged.
+    Miscounted {
+        node: String,
+        read: i64,
+        least: i64,
+        most: i64,
+    },
+    /// An acknowledged send to a log that was never polled, though it was at or before an
+    /// offset committed for the log, or between two messages one poll returned.
+    Lost { key: String, offset: u64 },
+    /// Two different messages seen at the same offset of a log.
+    Inconsistent {
+        key: String,
+        offset: u64,
+        messages: (Value, Value),
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::Missing { message, node } => {
+                write!(f, "{} was acknowledged but {} never had it", message, node)
+            }
+            Anomaly::Phantom { message, node } => {
+                write!(f, "{} read {}, which was never broadcast", node, message)
+            }
+            Anomaly::Duplicate { id, first, second } => {
+                write!(
+                    f,
+                    "{} handed out {}, which {} already had",
+                    second, id, first
+                )
+            }
+            Anomaly::Miscounted {
+                node,
+                read,
+                least,
+                most,
+            } => write!(
+                f,
+                "{} read {}, outside the {}..={} the adds allow",
+                node, read, least, most
+            ),
+            Anomaly::Lost { key, offset } => {
+                write!(f, "{} at offset {} was acknowledged and lost", key, offset)
+            }
+            Anomaly::Inconsistent {
+                key,
+                offset,
+                messages: (one, other),
+            } => write!(
+                f,
+                "{} has both {} and {} at offset {}",
+                key, one, other, offset
+            ),
+        }
+    }
+}
+
+// a client's request, with the node that had it and that node's answer, if it answered
+struct Exchange {
+    node: String,
+    request: Value,
+    reply: Option<Value>,
+}
+
+impl Exchange {
+    fn replied(&self, kind: &str) -> Option<&Value> {
+        self.reply
+            .as_ref()
+            .filter(|reply| reply.get("type").and_then(Value::as_str) == Some(kind))
+    }
+}
+
+// every request a client made of the nodes whose session logs are `logs`, in the order the
+// requests arrived
+fn exchanges(logs: &[(String, Vec<Record>)]) -> Vec<Exchange> {
+    let nodes: HashSet<&str> = logs.iter().map(|(node, _)| node.as_str()).collect();
+    let mut records: Vec<(&str, &Record)> = logs
+        .iter()
+        .flat_map(|(node, records)| records.iter().map(move |r| (node.as_str(), r)))
+        .collect();
+    records.sort_by_key(|(_, record)| record.time);
+
+    let mut exchanges = Vec::new();
+    // by client and msg_id, where its request is in `exchanges`
+    let mut asked: HashMap<(String, u64), usize> = HashMap::new();
+    for (node, record) in records {
+        let message = &record.message;
+        let (Some(src), Some(dest), Some(body)) = (
+            message.get("src").and_then(Value::as_str),
+            message.get("dest").and_then(Value::as_str),
+            message.get("body"),
+        ) else {
+            continue;
+        };
+        match record.direction {
+            Direction::Received if !nodes.contains(src) => {
+                let Some(msg_id) = body.get("msg_id").and_then(Value::as_u64) else {
+                    continue;
+                };
+                asked.insert((src.to_string(), msg_id), exchanges.len());
+                exchanges.push(Exchange {
+                    node: node.to_string(),
+                    request: body.clone(),
+                    reply: None,
+                });
+            }
+            Direction::Sent if !nodes.contains(dest) => {
+                let Some(in_reply_to) = body.get("in_reply_to").and_then(Value::as_u64) else {
+                    continue;
+                };
+                if let Some(&at) = asked.get(&(dest.to_string(), in_reply_to)) {
+                    exchanges[at].reply.get_or_insert_with(|| body.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+    exchanges
+}
+
+fn of_type<'a>(exchanges: &'a [Exchange], kind: &'a str) -> impl Iterator<Item = &'a Exchange> {
+    exchanges
+        .iter()
+        .filter(move |e| e.request.get("type").and_then(Value::as_str) == Some(kind))
+}
+
+/// Checks a broadcast workload: that the last read each node answered has every message
+/// whose broadcast was acknowledged, and nothing that was never broadcast. The reads to check
+/// against are the ones made once the cluster was given time to settle, so the history should
+/// end with a read of every node.
+pub fn broadcast(logs: &[(String, Vec<Record>)]) -> Vec<Anomaly> {
+    let exchanges = exchanges(logs);
+    let message = |e: &Exchange| e.request.get("message").cloned().unwrap_or(Value::Null);
+    let sent: BTreeSet<String> = of_type(&exchanges, "broadcast")
+        .map(|e| message(e).to_string())
+        .collect();
+    let acked: Vec<Value> = of_type(&exchanges, "broadcast")
+        .filter(|e| e.replied("broadcast_ok").is_some())
+        .map(message)
+        .collect();
+    let mut last: BTreeMap<&str, &Value> = BTreeMap::new();
+    let mut anomalies = Vec::new();
+    for exchange in of_type(&exchanges, "read") {
+        let Some(read) = exchange.replied("read_ok") else {
+            continue;
+        };
+        let messages = read.get("messages").unwrap_or(&Value::Null);
+        for message in messages.as_array().into_iter().flatten() {
+            if !sent.contains(&message.to_string()) {
+                anomalies.push(Anomaly::Phantom {
+                    message: message.clone(),
+                    node: exchange.node.clone(),
+                });
+            }
+        }
+        last.insert(&exchange.node, messages);
+    }
+    for (node, messages) in last {
+        let had: HashSet<String> = messages
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(Value::to_string)
+            .collect();
+        for message in &acked {
+            if !had.contains(&message.to_string()) {
+                anomalies.push(Anomaly::Missing {
+                    message: message.clone(),
+                    node: node.to_string(),
+                });
+            }
+        }
+    }
+    anomalies
+}
+
+/// Checks a unique-ids workload: that no two `generate` requests were ever answered with the
+/// same id.
+pub fn unique_ids(logs: &[(String, Vec<Record>)]) -> Vec<Anomaly> {
+    let exchanges = exchanges(logs);
+    let mut seen: HashMap<String, &str> = HashMap::new();
+    let mut anomalies = Vec::new();
+    for exchange in of_type(&exchanges, "generate") {
+        let Some(id) = exchange.replied("generate_ok").and_then(|ok| ok.get("id")) else {
+            continue;
+        };
+        if let Some(first) = seen.insert(id.to_string(), &exchange.node) {
+            anomalies.push(Anomaly::Duplicate {
+                id: id.clone(),
+                first: first.to_string(),
+                second: exchange.node.clone(),
+            });
+        }
+    }
+    anomalies
+}
+
+/// Checks a grow-only counter workload: that the last read each node answered takes in every
+/// `add` that was acknowledged, and nothing but those and the ones that might have happened.
+pub fn counter(logs: &[(String, Vec<Record>)]) -> Vec<Anomaly> {
+    let exchanges = exchanges(logs);
+    let (mut least, mut most) = (0, 0);
+    for exchange in of_type(&exchanges, "add") {
+        let delta = exchange
+            .request
+            .get("delta")
+            .and_then(Value::as_i64)
+            .unwrap_or(0);
+        most += delta;
+        if exchange.replied("add_ok").is_some() {
+            least += delta;
+        } else if exchange.replied("error").is_some_and(definite) {
+            most -= delta;
+        }
+    }
+    let mut last: BTreeMap<&str, i64> = BTreeMap::new();
+    for exchange in of_type(&exchanges, "read") {
+        let read = exchange.replied("read_ok").and_then(|ok| ok.get("value"));
+        if let Some(read) = read.and_then(Value::as_i64) {
+            last.insert(&exchange.node, read);
+        }
+    }
+    last.into_iter()
+        .filter(|(_, read)| !(least..=most).contains(read))
+        .map(|(node, read)| Anomaly::Miscounted {
+            node: node.to_string(),
+            read,
+            least,
+            most,
+        })
+        .collect()
+}
+
+/// Checks a Kafka-style log workload: that no two messages were ever seen at the same offset of
+/// a log, whether a `send` was acknowledged with it or a `poll` returned it, and that no
+/// acknowledged send is lost, by being missing from the polls at an offset that's since been
+/// committed, or from a poll that returned messages either side of it.
+pub fn kafka(logs: &[(String, Vec<Record>)]) -> Vec<Anomaly> {
+    let exchanges = exchanges(logs);
+    let mut anomalies = Vec::new();
+    // by log and offset, the message first seen there
+    let mut seen: BTreeMap<(String, u64), Value> = BTreeMap::new();
+    let mut see = |key: &str, offset: u64, message: &Value, anomalies: &mut Vec<Anomaly>| {
+        let at = (key.to_string(), offset);
+        match seen.get(&at) {
+            Some(first) if first != message => anomalies.push(Anomaly::Inconsistent {
+                key: key.to_string(),
+                offset,
+                messages: (first.clone(), message.clone()),
+            }),
+            Some(_) => {}
+            None => {
+                seen.insert(at, message.clone());
+            }
+        }
+    };
+
+    let mut acked: BTreeSet<(String, u64)> = BTreeSet::new();
+    for exchange in of_type(&exchanges, "send") {
+        let (Some(key), Some(message)) = (
+            exchange.request.get("key").and_then(Value::as_str),
+            exchange.request.get("msg"),
+        ) else {
+            continue;
+        };
+        let offset = exchange.replied("send_ok").and_then(|ok| ok.get("offset"));
+        if let Some(offset) = offset.and_then(Value::as_u64) {
+            see(key, offset, message, &mut anomalies);
+            acked.insert((key.to_string(), offset));
+        }
+    }
+
+    let mut polled: BTreeSet<(String, u64)> = BTreeSet::new();
+    // by log, the ranges of offsets single polls returned, first to last
+    let mut spans: Vec<(String, u64, u64)> = Vec::new();
+    for exchange in of_type(&exchanges, "poll") {
+        let msgs = exchange.replied("poll_ok").and_then(|ok| ok.get("msgs"));
+        for (key, entries) in msgs.and_then(Value::as_object).into_iter().flatten() {
+            let mut offsets = Vec::new();
+            for entry in entries.as_array().into_iter().flatten() {
+                let Some([offset, message]) = entry.as_array().map(Vec::as_slice) else {
+                    continue;
+                };
+                let Some(offset) = offset.as_u64() else {
+                    continue;
+                };
+                see(key, offset, message, &mut anomalies);
+                polled.insert((key.clone(), offset));
+                offsets.push(offset);
+            }
+            if let (Some(&first), Some(&last)) = (offsets.iter().min(), offsets.iter().max()) {
+                spans.push((key.clone(), first, last));
+            }
+        }
+    }
+
+    let mut committed: BTreeMap<String, u64> = BTreeMap::new();
+    for exchange in of_type(&exchanges, "commit_offsets") {
+        if exchange.replied("commit_offsets_ok").is_none() {
+            continue;
+        }
+        let offsets = exchange.request.get("offsets").and_then(Value::as_object);
+        for (key, offset) in offsets.into_iter().flatten() {
+            if let Some(offset) = offset.as_u64() {
+                let at = committed.entry(key.clone()).or_insert(offset);
+                *at = (*at).max(offset);
+            }
+        }
+    }
+
+    for (key, offset) in acked.difference(&polled) {
+        let committed = committed.get(key).is_some_and(|&c| *offset <= c);
+        let skipped = spans
+            .iter()
+            .any(|(k, first, last)| k == key && (first..=last).contains(&offset));
+        if committed || skipped {
+            anomalies.push(Anomaly::Lost {
+                key: key.clone(),
+                offset: *offset,
+            });
+        }
+    }
+    anomalies
+}
+
+fn definite(error: &Value) -> bool {
+    error
+        .get("code")
+        .and_then(Value::as_u64)
+        .is_some_and(|code| crate::error::is_definite(code as usize))
+}
@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+
+/// A hybrid logical clock reading: milliseconds of wall time, plus a counter that orders events
+/// within the same millisecond (or while our wall clock is behind someone else's).
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct Timestamp {
+    pub wall_ms: u64,
+    pub logical: u16,
+}
+
+impl Timestamp {
+    /// Packs the timestamp into a single integer that sorts the same way, for workloads that want
+    /// plain numbers (transaction timestamps, ids). Wall time gets the top 48 bits, which lasts
+    /// until the year 10889.
+    pub fn as_u64(self) -> u64 {
+        (self.wall_ms << 16) | u64::from(self.logical)
+    }
+
+    pub fn from_u64(packed: u64) -> Self {
+        Self {
+            wall_ms: packed >> 16,
+            logical: packed as u16,
+        }
+    }
+
+    // the next timestamp in the same millisecond, or the start of the next one if there are no
+    // counter values left in this one
+    fn next(self) -> Self {
+        match self.logical.checked_add(1) {
+            Some(logical) => Self {
+                wall_ms: self.wall_ms,
+                logical,
+            },
+            None => Self {
+                wall_ms: self.wall_ms + 1,
+                logical: 0,
+            },
+        }
+    }
+}
+
+/// Hybrid logical clock. Timestamps it hands out never go backwards and always sort after any
+/// timestamp it has observed, while staying close to wall time.
+#[derive(Debug, Default)]
+pub struct Hlc {
+    last: Timestamp,
+}
+
+impl Hlc {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A timestamp for a local event.
+    pub fn now(&mut self) -> Timestamp {
+        let wall_ms = clock::wall_ms();
+        if wall_ms > self.last.wall_ms {
+            self.last = Timestamp {
+                wall_ms,
+                logical: 0,
+            };
+        } else {
+            self.last = self.last.next();
+        }
+        self.last
+    }
+
+    /// Folds in a timestamp from another node, so everything we do from here on sorts after it.
+    pub fn observe(&mut self, remote: Timestamp) -> Timestamp {
+        let wall_ms = clock::wall_ms();
+        let local = self.last;
+        self.last = if wall_ms > local.wall_ms && wall_ms > remote.wall_ms {
+            Timestamp {
+                wall_ms,
+                logical: 0,
+            }
+        } else {
+            local.max(remote).next()
+        };
+        self.last
+    }
+}
@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+
+use crate::shard::hash;
+
+// cells every element goes into, one per subtable
+const HASHES: usize = 3;
+
+// on the wire as a bare triple, sketches are mostly cells
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "(i64, u64, u64)", into = "(i64, u64, u64)")]
+pub struct Cell {
+    count: i64,
+    key_sum: u64,
+    hash_sum: u64,
+}
+
+impl From<(i64, u64, u64)> for Cell {
+    fn from((count, key_sum, hash_sum): (i64, u64, u64)) -> Self {
+        Self {
+            count,
+            key_sum,
+            hash_sum,
+        }
+    }
+}
+
+impl From<Cell> for (i64, u64, u64) {
+    fn from(cell: Cell) -> Self {
+        (cell.count, cell.key_sum, cell.hash_sum)
+    }
+}
+
+impl Cell {
+    fn toggle(&mut self, element: u64, count: i64) {
+        self.count += count;
+        self.key_sum ^= element;
+        self.hash_sum ^= hash(&element);
+    }
+
+    // holds exactly one element, from one side or the other
+    fn pure(&self) -> bool {
+        (self.count == 1 || self.count == -1) && self.hash_sum == hash(&self.key_sum)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0 && self.key_sum == 0 && self.hash_sum == 0
+    }
+}
+
+/// Invertible Bloom lookup table over a set of `u64`s: a sketch of a fixed number of cells,
+/// however large the set. Subtracting one replica's sketch from another's cancels out every
+/// element they share, and what's left can be decoded into the elements only one side has, as
+/// long as there are not many more of those than about two thirds of the cells. Two replicas can
+/// therefore reconcile by swapping a sketch sized for their difference, not for their sets.
+///
+/// Both sides must build their sketches with the same number of cells.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Iblt {
+    cells: Vec<Cell>,
+}
+
+impl Iblt {
+    /// An empty sketch of at least `cells` cells (rounded up to a multiple of the hash count).
+    pub fn new(cells: usize) -> Self {
+        let cells = cells.max(1).div_ceil(HASHES) * HASHES;
+        Self {
+            cells: vec![Cell::default(); cells],
+        }
+    }
+
+    pub fn from_elements<'a>(cells: usize, elements: impl IntoIterator<Item = &'a u64>) -> Self {
+        let mut iblt = Self::new(cells);
+        for &element in elements {
+            iblt.insert(element);
+        }
+        iblt
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.iter().all(Cell::is_empty)
+    }
+
+    pub fn insert(&mut self, element: u64) {
+        self.toggle(element, 1);
+    }
+
+    fn toggle(&mut self, element: u64, count: i64) {
+        for i in self.indices(element) {
+            self.cells[i].toggle(element, count);
+        }
+    }
+
+    // each hash gets its own subtable, so an element never lands in the same cell twice
+    fn indices(&self, element: u64) -> [usize; HASHES] {
+        let width = self.cells.len() / HASHES;
+        std::array::from_fn(|i| i * width + (hash(&(i, element)) % width as u64) as usize)
+    }
+
+    /// `self - other`, cell by cell. Elements in both cancel out.
+    pub fn subtract(&self, other: &Iblt) -> Iblt {
+        assert_eq!(self.len(), other.len(), "sketches of different sizes");
+        let cells = self
+            .cells
+            .iter()
+            .zip(&other.cells)
+            .map(|(a, b)| Cell {
+                count: a.count - b.count,
+                key_sum: a.key_sum ^ b.key_sum,
+                hash_sum: a.hash_sum ^ b.hash_sum,
+            })
+            .collect();
+        Iblt { cells }
+    }
+
+    /// Decodes a difference `a - b` into the elements only `a` has and the elements only `b` has,
+    /// or `None` if the difference is too large for this many cells.
+    pub fn decode(mut self) -> Option<(Vec<u64>, Vec<u64>)> {
+        let mut ours = Vec::new();
+        let mut theirs = Vec::new();
+        // peel pure cells off one at a time. removing an element can leave other cells pure.
+        let mut pure: Vec<usize> = (0..self.cells.len())
+            .filter(|&i| self.cells[i].pure())
+            .collect();
+        while let Some(i) = pure.pop() {
+            let cell = self.cells[i];
+            if !cell.pure() {
+                continue;
+            }
+            let element = cell.key_sum;
+            if cell.count == 1 {
+                ours.push(element);
+            } else {
+                theirs.push(element);
+            }
+            for j in self.indices(element) {
+                self.cells[j].toggle(element, -cell.count);
+                if self.cells[j].pure() {
+                    pure.push(j);
+                }
+            }
+        }
+        self.is_empty().then_some((ours, theirs))
+    }
+}
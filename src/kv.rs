@@ -0,0 +1,61 @@
+use std::io::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Body, Message};
+
+pub mod service;
+
+// the key/value services maelstrom runs alongside the nodes under test
+pub const LIN_KV: &str = "lin-kv";
+pub const SEQ_KV: &str = "seq-kv";
+pub const LWW_KV: &str = "lww-kv";
+
+/// Requests understood by the maelstrom key/value services. Their replies (`read_ok { value }`,
+/// `write_ok`, `cas_ok` and `error { code, text }`) come back as part of the node's own payload
+/// type, correlated through `in_reply_to`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum KvRequest<K = String, V = serde_json::Value> {
+    Read {
+        key: K,
+    },
+    Write {
+        key: K,
+        value: V,
+    },
+    Cas {
+        key: K,
+        from: V,
+        to: V,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+}
+
+impl<K, V> KvRequest<K, V>
+where
+    K: Serialize,
+    V: Serialize,
+{
+    pub fn send(
+        self,
+        src: &str,
+        service: &str,
+        msg_id: usize,
+        output: &mut impl Write,
+    ) -> anyhow::Result<()> {
+        Message {
+            src: src.to_string(),
+            dst: service.to_string(),
+            body: Body {
+                id: Some(msg_id),
+                in_reply_to: None,
+                correlation_id: None,
+                payload: self,
+            },
+        }
+        .send(output)
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use rand::Rng;
+use serde_json::{json, Value};
+
+use super::{KvRequest, LIN_KV, LWW_KV, SEQ_KV};
+use crate::error;
+use crate::{Body, Message};
+
+/// How consistent a [`Service`]'s reads are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Every read sees the last write, as lin-kv's do.
+    Linearizable,
+    /// A read can be behind, but never behind what the same client has already seen or
+    /// written, as seq-kv's can.
+    Sequential,
+    /// A read can be behind, and further behind than the last one was, as lww-kv's can.
+    LastWriteWins,
+}
+
+/// How a [`Service`] behaves besides how it answers. Nothing goes wrong unless it's asked to:
+/// by default it answers straight away, with the latest of everything, and never fails.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conduct {
+    /// How long the service takes over a request, picked afresh for every request, on top of
+    /// the time the request and its answer take to get there and back.
+    pub latency: RangeInclusive<Duration>,
+    /// The chance a request is answered with a `temporarily-unavailable` error, having had no
+    /// effect.
+    pub unavailable: f64,
+    /// The chance a request takes effect but is never answered, so whoever sent it can't tell
+    /// whether it happened.
+    pub lost: f64,
+    /// How far behind a read that isn't linearizable can be: it can see any value the key has
+    /// had since this long ago.
+    pub stale: Duration,
+}
+
+impl Default for Conduct {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO..=Duration::ZERO,
+            unavailable: 0.0,
+            lost: 0.0,
+            stale: Duration::ZERO,
+        }
+    }
+}
+
+/// One of the key/value services Maelstrom runs alongside the nodes, for the simulator
+/// ([`crate::sim::Sim::service`]) and the subprocess harness
+/// ([`crate::harness::Process::serve`]) to answer nodes' [`KvRequest`]s with, so a node built on
+/// seq-kv or lin-kv can be tried without Maelstrom.
+///
+/// Time is whatever its host says it is: virtual time in the simulator, time since the node
+/// started in the harness. The host draws the randomness for staleness and failures, so a
+/// simulation's seed covers the service too.
+#[derive(Debug, Clone)]
+pub struct Service {
+    name: String,
+    consistency: Consistency,
+    conduct: Conduct,
+    // by key, as JSON, every value it's had, oldest first, and when it got it
+    versions: HashMap<String, Vec<(Duration, Value)>>,
+    // by client and key, the oldest version a sequential read can still show the client
+    seen: HashMap<(String, String), usize>,
+    next_id: usize,
+}
+
+impl Service {
+    pub fn new(name: &str, consistency: Consistency, conduct: Conduct) -> Self {
+        Self {
+            name: name.to_string(),
+            consistency,
+            conduct,
+            versions: HashMap::new(),
+            seen: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn lin_kv(conduct: Conduct) -> Self {
+        Self::new(LIN_KV, Consistency::Linearizable, conduct)
+    }
+
+    pub fn seq_kv(conduct: Conduct) -> Self {
+        Self::new(SEQ_KV, Consistency::Sequential, conduct)
+    }
+
+    pub fn lww_kv(conduct: Conduct) -> Self {
+        Self::new(LWW_KV, Consistency::LastWriteWins, conduct)
+    }
+
+    /// The node id nodes send the service's requests to.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The latest value of `key`, whatever a read of it might say.
+    pub fn latest(&self, key: &Value) -> Option<&Value> {
+        let versions = self.versions.get(&key.to_string())?;
+        versions.last().map(|(_, value)| value)
+    }
+
+    /// How long the service takes over the next request, by [`Conduct::latency`].
+    pub fn delay(&self, rng: &mut impl Rng) -> Duration {
+        rng.gen_range(self.conduct.latency.clone())
+    }
+
+    /// Carries out `request` at `now`, and answers it, unless the answer is to be lost.
+    pub fn handle(
+        &mut self,
+        request: &Message<Value>,
+        now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<Message<Value>> {
+        let payload = if chance(rng, self.conduct.unavailable) {
+            failure(error::TEMPORARILY_UNAVAILABLE, "temporarily unavailable")
+        } else {
+            let payload = self.apply(&request.src, &request.body.payload, now, rng);
+            if chance(rng, self.conduct.lost) {
+                return None;
+            }
+            payload
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        Some(Message {
+            src: self.name.clone(),
+            dst: request.src.clone(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: request.body.id,
+                correlation_id: None,
+                payload,
+            },
+        })
+    }
+
+    fn apply(&mut self, client: &str, payload: &Value, now: Duration, rng: &mut impl Rng) -> Value {
+        let request = match serde_json::from_value(payload.clone()) {
+            Ok(request) => request,
+            Err(e) => {
+                let known = ["read", "write", "cas"];
+                let kind = payload.get("type").and_then(Value::as_str);
+                return match kind {
+                    Some(kind) if !known.contains(&kind) => {
+                        failure(error::NOT_SUPPORTED, &format!("{} isn't supported", kind))
+                    }
+                    _ => failure(error::MALFORMED_REQUEST, &e.to_string()),
+                };
+            }
+        };
+        match request {
+            KvRequest::Read { key } => match self.read(client, &key, now, rng) {
+                Some(value) => json!({ "type": "read_ok", "value": value }),
+                None => missing(&key),
+            },
+            KvRequest::Write { key, value } => {
+                self.write(client, &key, value, now);
+                json!({ "type": "write_ok" })
+            }
+            KvRequest::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            } => match self.latest(&key) {
+                Some(current) if *current == from => {
+                    self.write(client, &key, to, now);
+                    json!({ "type": "cas_ok" })
+                }
+                Some(current) => failure(
+                    error::PRECONDITION_FAILED,
+                    &format!("expected {}, but had {}", from, current),
+                ),
+                None if create_if_not_exists => {
+                    self.write(client, &key, to, now);
+                    json!({ "type": "cas_ok" })
+                }
+                None => missing(&key),
+            },
+        }
+    }
+
+    fn read(
+        &mut self,
+        client: &str,
+        key: &Value,
+        now: Duration,
+        rng: &mut impl Rng,
+    ) -> Option<Value> {
+        let key = key.to_string();
+        let versions = self.versions.get(&key)?;
+        let latest = versions.len() - 1;
+        let oldest = match self.consistency {
+            Consistency::Linearizable => latest,
+            Consistency::Sequential | Consistency::LastWriteWins => {
+                // the value it had `stale` ago, and anything it's had since
+                let since = now.saturating_sub(self.conduct.stale);
+                versions
+                    .iter()
+                    .rposition(|(at, _)| *at <= since)
+                    .unwrap_or(0)
+            }
+        };
+        let seen = self.seen.entry((client.to_string(), key));
+        let shown = match self.consistency {
+            Consistency::Sequential => {
+                let seen = seen.or_default();
+                *seen = rng.gen_range(oldest.max(*seen)..=latest);
+                *seen
+            }
+            _ => rng.gen_range(oldest..=latest),
+        };
+        Some(versions[shown].1.clone())
+    }
+
+    fn write(&mut self, client: &str, key: &Value, value: Value, now: Duration) {
+        let key = key.to_string();
+        let versions = self.versions.entry(key.clone()).or_default();
+        versions.push((now, value));
+        // whoever wrote it sees it from now on
+        let latest = versions.len() - 1;
+        self.seen.insert((client.to_string(), key), latest);
+    }
+}
+
+// like the simulator's own, draws nothing for what can't happen
+fn chance(rng: &mut impl Rng, p: f64) -> bool {
+    p > 0.0 && rng.gen::<f64>() < p
+}
+
+fn failure(code: usize, text: &str) -> Value {
+    json!({ "type": "error", "code": code, "text": text })
+}
+
+fn missing(key: &Value) -> Value {
+    failure(error::KEY_DOES_NOT_EXIST, &format!("{} doesn't exist", key))
+}
@@ -0,0 +1,45 @@
+use serde::{Deserialize, Serialize};
+
+/// Lamport logical clock. If one event happened before another, its time is smaller; the reverse
+/// doesn't hold, as concurrent events get arbitrary times relative to each other.
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    time: u64,
+}
+
+impl Clock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn time(&self) -> u64 {
+        self.time
+    }
+
+    /// Advances the clock for a local event (including sending a message) and returns its time.
+    pub fn tick(&mut self) -> u64 {
+        self.time += 1;
+        self.time
+    }
+
+    /// Advances the clock past a time carried by a message we've received.
+    pub fn observe(&mut self, time: u64) -> u64 {
+        self.time = self.time.max(time) + 1;
+        self.time
+    }
+
+    pub fn timestamp(&mut self, node: &str) -> Timestamp {
+        Timestamp {
+            time: self.tick(),
+            node: node.to_string(),
+        }
+    }
+}
+
+/// A lamport time together with the node it was taken on. Breaking ties on the node id turns the
+/// clock's partial order into a total one that every node agrees on.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub time: u64,
+    pub node: String,
+}
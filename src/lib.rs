@@ -1,12 +1,52 @@
 use core::str;
-use std::io::BufRead;
-use std::io::StdoutLock;
 use std::io::Write;
 
 use anyhow::{Context, Ok};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg(feature = "admin")]
+mod admin;
+pub mod clock;
+pub mod config;
+pub mod correlation;
+pub mod crdt;
+pub mod ddsketch;
+pub mod error;
+pub mod failure_detector;
+pub mod flow;
+pub mod harness;
+pub mod history;
+pub mod hlc;
+pub mod iblt;
+pub mod kv;
+pub mod lamport;
+pub mod logging;
+pub mod merkle;
+pub mod metadata;
+pub mod metrics;
+pub mod mvcc;
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "pprof")]
+mod profiling;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod rng;
+pub mod runtime;
+pub mod session;
+pub mod shard;
+pub mod sim;
+pub mod stats;
+pub mod testing;
+pub mod ticks;
+pub mod transport;
+pub mod txn;
+pub mod vclock;
+pub mod wal;
+
+pub use transport::Output;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message<Payload> {
     pub src: String,
     #[serde(rename = "dest")]
@@ -33,25 +73,72 @@ impl<Payload> Message<Payload> {
                     mid
                 }),
                 in_reply_to: self.body.id,
+                correlation_id: self.body.correlation_id,
                 payload: self.body.payload,
             },
         }
     }
+    /// Writes the message out as a line of JSON. One without a correlation id of its own goes
+    /// out under the id of the step being taken, if there is one ([`correlation`]).
     pub fn send(&self, output: &mut impl Write) -> anyhow::Result<()>
     where
         Payload: Serialize,
     {
-        serde_json::to_writer(&mut *output, &self).context("serialize response to message")?;
+        let stepping = match self.body.correlation_id {
+            Some(_) => None,
+            None => correlation::current(),
+        };
+        match stepping {
+            None => serde_json::to_writer(&mut *output, &self),
+            Some(correlation_id) => {
+                let sending = Sending {
+                    src: &self.src,
+                    dst: &self.dst,
+                    body: SendingBody {
+                        id: self.body.id,
+                        in_reply_to: self.body.in_reply_to,
+                        correlation_id: &correlation_id,
+                        payload: &self.body.payload,
+                    },
+                };
+                serde_json::to_writer(&mut *output, &sending)
+            }
+        }
+        .context("serialize response to message")?;
         output.write_all(b"\n").context("write new line")?;
         Ok(())
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// a message going out under a correlation id it wasn't made with, serialized the same as a
+// `Message` with the id filled in, without having to copy the payload to fill it in
+#[derive(Serialize)]
+struct Sending<'a, Payload> {
+    src: &'a str,
+    #[serde(rename = "dest")]
+    dst: &'a str,
+    body: SendingBody<'a, Payload>,
+}
+
+#[derive(Serialize)]
+struct SendingBody<'a, Payload> {
+    #[serde(rename = "msg_id")]
+    id: Option<usize>,
+    in_reply_to: Option<usize>,
+    correlation_id: &'a str,
+    #[serde(flatten)]
+    payload: &'a Payload,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Body<Payload> {
     #[serde(rename = "msg_id")]
     pub id: Option<usize>,
     pub in_reply_to: Option<usize>,
+    /// Which client operation this is a part of, the same on every message sent on its behalf
+    /// by every node it passes through ([`correlation`]).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
     #[serde(flatten)]
     pub payload: Payload,
 }
@@ -83,8 +170,34 @@ pub trait Node<S, Payload, InjectedPayload = ()> {
     fn step(
         &mut self,
         input: Event<Payload, InjectedPayload>,
-        output: &mut StdoutLock,
+        output: &mut Output,
     ) -> anyhow::Result<()>;
+
+    /// A summary of the node's state, for the admin API to show. Nothing unless a node has
+    /// something worth saying.
+    fn status(&self) -> serde_json::Value {
+        serde_json::Value::Null
+    }
+
+    /// Checks what must be true of the node's state whatever it's been through, like a log and
+    /// the index over it agreeing. The simulator checks after every step, so a step that corrupts
+    /// the state fails there and then, not wherever the corruption first shows. Nothing to check
+    /// unless a node says so.
+    fn invariants(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Compacts whatever the node keeps on disk down to what it needs to recover, when the admin
+    /// API asks it to.
+    fn snapshot(&mut self) -> anyhow::Result<()> {
+        anyhow::bail!("this node has nothing to snapshot")
+    }
+}
+
+/// Parses one line of input as a message. The node's loops log the lines this rejects and carry
+/// on with the next, so a peer that sends garbage can't bring a node down.
+pub fn parse<P: DeserializeOwned>(line: &str) -> anyhow::Result<Message<P>> {
+    serde_json::from_str(line).context("input could not be deserialized")
 }
 
 pub fn main_loop<S, N, P, IP>(init_state: S) -> anyhow::Result<()>
@@ -93,63 +206,263 @@ where
     IP: Send + 'static,
     N: Node<S, P, IP>,
 {
-    let stdin = std::io::stdin().lock();
-    let mut stdin = stdin.lines();
-    let mut stdout = std::io::stdout().lock();
-
-    let init_msg: Message<InitPayload> = serde_json::from_str(
-        &stdin
-            .next()
-            .expect("no init message received")
-            .context("failed to read init message from stdin")?,
-    )
-    .context("init message could not be deserialized")?;
+    logging::init()?;
+    #[cfg(feature = "pprof")]
+    profiling::install()?;
+    let ran = match transport::Config::from_env()? {
+        transport::Config::Stdio => stdio_loop::<S, N, P, IP>(init_state),
+        transport::Config::Tcp(cluster) => {
+            let (tcp, lines) = transport::tcp::TcpTransport::bind(&cluster)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(tcp), lines)
+        }
+        #[cfg(feature = "tls")]
+        transport::Config::Tls(cluster, tls) => {
+            let (tls, lines) = transport::tls::TlsTransport::bind_with(&cluster, tls)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(tls), lines)
+        }
+        transport::Config::Udp(cluster) => {
+            let (udp, lines) = transport::udp::UdpTransport::bind(&cluster)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(udp), lines)
+        }
+        #[cfg(unix)]
+        transport::Config::Uds(cluster) => {
+            let (uds, lines) = transport::uds::UdsTransport::bind(&cluster)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(uds), lines)
+        }
+        transport::Config::WebSocket(cluster) => {
+            let (ws, lines) = transport::websocket::WebSocketTransport::bind(&cluster)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(ws), lines)
+        }
+        transport::Config::Nats {
+            cluster,
+            url,
+            prefix,
+        } => {
+            let (nats, lines) = transport::nats::NatsTransport::bind(&cluster, &url, &prefix)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(nats), lines)
+        }
+        #[cfg(feature = "grpc")]
+        transport::Config::Grpc {
+            cluster,
+            client_addr,
+        } => {
+            let (grpc, lines) =
+                transport::grpc::GrpcTransport::bind(&cluster, client_addr.as_deref())?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(grpc), lines)
+        }
+        #[cfg(feature = "quic")]
+        transport::Config::Quic(cluster, tls) => {
+            let (quic, lines) = transport::quic::QuicTransport::bind(&cluster, &tls)?;
+            networked::<S, N, P, IP>(init_state, cluster, Box::new(quic), lines)
+        }
+    };
+    wal::remove_scratch();
+    ran
+}
+
+/// Runs a node on a [`transport::channel::Network`] instead of stdio or the network, as
+/// `init.node_id` and alongside whoever else shares it, until its mailbox is closed with
+/// [`transport::channel::Network::leave`]. Nobody sends the node an init; it starts from `init`.
+pub fn channel_loop<S, N, P, IP>(
+    init_state: S,
+    init: Init,
+    network: &transport::channel::Network,
+) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+    N: Node<S, P, IP>,
+{
+    let (transport, lines) = network.join(&init.node_id);
+    let mut output = Output::routed(Box::new(transport));
+    if let Some(session) = session::Log::from_env(&init.node_id)? {
+        output.record_to(session);
+    }
+    run::<S, N, P, IP>(init_state, init, lines.into_iter().map(Ok), output)
+}
+
+fn networked<S, N, P, IP>(
+    init_state: S,
+    cluster: transport::Cluster,
+    transport: Box<dyn transport::Transport>,
+    lines: std::sync::mpsc::Receiver<String>,
+) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+    N: Node<S, P, IP>,
+{
+    // nobody sends us an init over a socket, so we make it up from the config
+    let init = Init {
+        node_id: cluster.node_id,
+        node_ids: cluster.nodes.into_iter().map(|(name, _)| name).collect(),
+    };
+    let mut output = Output::routed(transport);
+    if let Some(session) = session::Log::from_env(&init.node_id)? {
+        output.record_to(session);
+    }
+    run::<S, N, P, IP>(init_state, init, lines.into_iter().map(Ok), output)
+}
+
+// one line of stdin at a time. `Stdin` itself (unlike its lock) can move to another thread.
+fn stdin_lines() -> impl Iterator<Item = anyhow::Result<String>> + Send {
+    let stdin = std::io::stdin();
+    std::iter::from_fn(move || {
+        let mut line = String::new();
+        match stdin.read_line(&mut line) {
+            std::result::Result::Ok(0) => None,
+            std::result::Result::Ok(_) => {
+                let len = line.trim_end_matches(['\r', '\n']).len();
+                line.truncate(len);
+                Some(Ok(line))
+            }
+            Err(e) => Some(Err(e.into())),
+        }
+    })
+}
+
+fn stdio_loop<S, N, P, IP>(init_state: S) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+    N: Node<S, P, IP>,
+{
+    let mut stdin = stdin_lines();
+    let mut stdout = Output::stdout();
+
+    let init_line = stdin
+        .next()
+        .expect("no init message received")
+        .context("failed to read init message from stdin")?;
+    let init_msg: Message<InitPayload> =
+        serde_json::from_str(&init_line).context("init message could not be deserialized")?;
 
     let InitPayload::Init(init) = init_msg.body.payload else {
         panic!("first mesage should be init")
     };
+    if let Some(session) = session::Log::from_env(&init.node_id)? {
+        session.record(session::Direction::Received, init_line.as_bytes());
+        stdout.record_to(session);
+    }
 
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    let mut node: N =
-        Node::from_init(init_state, init, tx.clone()).context("node initialization failed")?;
     let reply = Message {
         src: init_msg.dst,
         dst: init_msg.src,
         body: Body {
             id: Some(0),
             in_reply_to: init_msg.body.id,
+            correlation_id: None,
             payload: InitPayload::InitOk,
         },
     };
 
     serde_json::to_writer(&mut stdout, &reply).context("serialize response to init")?;
     stdout.write_all(b"\n").context("write new line")?;
-    drop(stdin);
-    // let stdin_tx = tx.clone();
-    let jh = std::thread::spawn(move || {
-        let stdin = std::io::stdin().lock();
-
-        for line in stdin.lines() {
-            let line = line.context("Maelstrop input from STDIN could not be read")?;
-            let input = serde_json::from_str(&line)
-                .context("Maelstrom input from STDIN could not be deserialized")?;
-            if let Err(_) = tx.send(Event::Message(input)) {
-                return anyhow::Result::Err(anyhow::anyhow!("stdin tx closed"));
-            }
-        }
-        let _ = tx.send(Event::EOF);
-        Ok(())
-    });
+    run::<S, N, P, IP>(init_state, init, stdin, stdout)
+}
 
-    for input in rx {
-        node.step(input, &mut stdout)
-            .context("Node step function failed")?;
-    }
+fn run<S, N, P, IP>(
+    init_state: S,
+    init: Init,
+    lines: impl Iterator<Item = anyhow::Result<String>> + Send + 'static,
+    mut output: Output,
+) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+    N: Node<S, P, IP>,
+{
+    #[cfg(feature = "admin")]
+    let mut admin = admin::Admin::from_env(&init)?;
+    #[cfg(not(feature = "admin"))]
+    anyhow::ensure!(
+        config::var::<String>("RUSTENGAN_ADMIN_ADDR")?.is_none(),
+        "RUSTENGAN_ADMIN_ADDR needs the admin feature"
+    );
+    #[cfg(feature = "pprof")]
+    profiling::running_as(&init.node_id);
+    #[cfg(feature = "otel")]
+    let otel = otel::Otel::from_env(&init)?;
+    #[cfg(not(feature = "otel"))]
+    anyhow::ensure!(
+        config::var::<String>("RUSTENGAN_OTLP_ENDPOINT")?.is_none(),
+        "RUSTENGAN_OTLP_ENDPOINT needs the otel feature"
+    );
+    let (tx, rx) = std::sync::mpsc::channel();
 
-    jh.join()
-        .expect("stdin thread panicked")
-        .context("stdin thread err'd")?;
+    let mut node: N =
+        Node::from_init(init_state, init, tx.clone()).context("node initialization failed")?;
+    let session = output.session().cloned();
+    #[cfg(feature = "otel")]
+    let reader = otel.as_ref().map(otel::Otel::reader);
+    let accept = move |line: String| {
+        if metrics::enabled() {
+            let kind = metrics::message_type(line.as_bytes());
+            metrics::count("rustengan_messages_received_total", &[("type", &kind)], 1);
+            metrics::count("rustengan_received_bytes_total", &[], line.len() as u64 + 1);
+        }
+        let input = match parse(&line) {
+            std::result::Result::Ok(input) => input,
+            Err(e) => {
+                // whoever sent it, it's no reason to stop serving everyone else
+                log::warn!("dropping a message that doesn't parse: {:#}: {}", e, line);
+                metrics::count("rustengan_malformed_messages_total", &[], 1);
+                return None;
+            }
+        };
+        if let Some(session) = &session {
+            session.record(session::Direction::Received, line.as_bytes());
+        }
+        #[cfg(feature = "otel")]
+        if let Some(reader) = &reader {
+            reader.read(&line);
+        }
+        Some(Event::Message(input))
+    };
 
-    Ok(())
+    #[cfg(feature = "admin")]
+    let poll = admin.as_ref().map(|_| admin::POLL);
+    #[cfg(not(feature = "admin"))]
+    let poll = None;
+    let mut stats = stats::Stats::from_env()?;
+    let correlation = correlation::Correlation::from_env()?;
+    let poll = poll
+        .into_iter()
+        .chain(stats.as_ref().map(|stats| stats.every))
+        .min();
+    runtime::run::<runtime::Std, P, IP>(lines, accept, tx, rx, poll, |input| {
+        if let Some(stats) = &mut stats {
+            stats.tick(|| node.status());
+        }
+        #[cfg(feature = "admin")]
+        if let Some(admin) = &mut admin {
+            match input {
+                Some(_) => admin.events += 1,
+                None => admin.serve(&mut node),
+            }
+        }
+        let Some(input) = input else {
+            return Ok(());
+        };
+        let (event, correlation_id) = match &input {
+            Event::Message(message) => ("message", correlation.of(message)),
+            Event::Injected(_) => ("injected", None),
+            Event::EOF => ("eof", None),
+        };
+        let started = std::time::Instant::now();
+        let stepped = correlation::stepping(correlation_id, || {
+            #[cfg(feature = "otel")]
+            if let Some(otel) = &otel {
+                return otel.step(input, |input| node.step(input, &mut output));
+            }
+            node.step(input, &mut output)
+        });
+        stepped.context("Node step function failed")?;
+        metrics::observe(
+            "rustengan_step_seconds",
+            &[("event", event)],
+            started.elapsed(),
+        );
+        Ok(())
+    })
 }
@@ -0,0 +1,37 @@
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{config, correlation};
+
+struct Stderr;
+
+impl Log for Stderr {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        match correlation::current() {
+            Some(id) => eprintln!("{} [{}] {}", record.level(), id, record.args()),
+            None => eprintln!("{} {}", record.level(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: Stderr = Stderr;
+
+/// Sends everything logged through the `log` macros to stderr, which is where Maelstrom keeps
+/// each node's log. `RUSTENGAN_LOG` sets how much (`info` unless it says otherwise); the level
+/// can be changed while the node runs with [`log::set_max_level`]. What's logged during a step
+/// taken under a [correlation id](crate::correlation) has the id after the level.
+pub fn init() -> anyhow::Result<()> {
+    let level = config::var_or("RUSTENGAN_LOG", LevelFilter::Info)?;
+    // only the first call gets to install a logger, and that's the one we want anyway
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(level);
+    Ok(())
+}
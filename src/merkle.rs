@@ -0,0 +1,56 @@
+use crate::shard::hash;
+
+/// A Merkle tree over a keyspace split into `2^depth` ranges (buckets) by the top bits of each
+/// key's [`hash`]. A leaf summarises the items in its range and every inner node its two
+/// children, so two replicas can find the ranges they disagree on by comparing hashes from the
+/// root down, only descending where they differ, instead of comparing every key.
+///
+/// Leaves don't depend on the order items are added in, so replicas can build them straight from
+/// a `HashMap`.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    // levels[0] is the root, levels[depth] the leaves
+    levels: Vec<Vec<u64>>,
+}
+
+impl MerkleTree {
+    /// Builds the tree from every item's key hash and a digest of its contents.
+    pub fn build(depth: u32, items: impl IntoIterator<Item = (u64, u64)>) -> Self {
+        let mut leaves = vec![0u64; 1 << depth];
+        for (key_hash, digest) in items {
+            let leaf = &mut leaves[Self::bucket(depth, key_hash)];
+            *leaf = leaf.wrapping_add(hash(&(key_hash, digest)));
+        }
+        let mut levels = vec![leaves];
+        while levels[0].len() > 1 {
+            let parents = levels[0]
+                .chunks(2)
+                .map(|pair| hash(&(pair[0], pair[1])))
+                .collect();
+            levels.insert(0, parents);
+        }
+        Self { levels }
+    }
+
+    /// Which leaf a key with the given hash falls in.
+    pub fn bucket(depth: u32, key_hash: u64) -> usize {
+        key_hash.checked_shr(64 - depth).unwrap_or(0) as usize
+    }
+
+    pub fn depth(&self) -> usize {
+        self.levels.len() - 1
+    }
+
+    pub fn root(&self) -> u64 {
+        self.levels[0][0]
+    }
+
+    pub fn get(&self, level: usize, index: usize) -> u64 {
+        self.levels[level][index]
+    }
+
+    /// The two nodes below `index` at `level`.
+    pub fn children(index: usize) -> [usize; 2] {
+        [2 * index, 2 * index + 1]
+    }
+}
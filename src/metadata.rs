@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vclock::VClock;
+
+/// One key of the metadata map, with the version vector of the write that produced it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub value: serde_json::Value,
+    pub version: VClock,
+    // breaks ties between concurrent writes
+    pub writer: String,
+}
+
+impl Entry {
+    // a total order on writes that respects causality: a write made after seeing another has a
+    // higher version total, and concurrent ones fall back on the total and then the writer
+    fn rank(&self) -> (u64, &str) {
+        (self.version.total(), &self.writer)
+    }
+}
+
+/// A replicated map of cluster metadata (schema versions, shard maps, feature flags, ...), meant
+/// to be spread by anti-entropy gossip and read by whatever needs dynamic configuration.
+///
+/// Every key carries its own version vector, so updates to different keys never interfere and
+/// replicas can tell from a digest of versions alone which keys they disagree on. Of two
+/// concurrent writes to the same key, every replica keeps the same one, and a write made after
+/// seeing either replaces both.
+#[derive(Debug, Clone, Default)]
+pub struct Metadata {
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Metadata {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        self.entries.get(key).map(|e| &e.value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &serde_json::Value)> {
+        self.entries.iter().map(|(k, e)| (k, &e.value))
+    }
+
+    /// Writes `key` on `node`, after everything this replica has seen of it.
+    pub fn set(&mut self, node: &str, key: String, value: serde_json::Value) {
+        let mut version = self
+            .entries
+            .get(&key)
+            .map(|e| e.version.clone())
+            .unwrap_or_default();
+        version.increment(node);
+        let entry = Entry {
+            value,
+            version,
+            writer: node.to_string(),
+        };
+        self.entries.insert(key, entry);
+    }
+
+    /// Every key and its version, to tell a peer what we have without sending the values.
+    pub fn digest(&self) -> Vec<(String, VClock)> {
+        self.entries
+            .iter()
+            .map(|(k, e)| (k.clone(), e.version.clone()))
+            .collect()
+    }
+
+    /// Compares a peer's digest with what we have. Returns our entries for the keys the peer
+    /// lacks or has a different version of, and the keys to ask the peer for in turn. Versions
+    /// identify writes, so whichever side turns out to be behind, exchanging both settles it.
+    pub fn diff(&self, digest: &[(String, VClock)]) -> (Vec<(String, Entry)>, Vec<String>) {
+        let theirs: BTreeMap<_, _> = digest.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        let send = self
+            .entries
+            .iter()
+            .filter(|(k, e)| theirs.get(k.as_str()) != Some(&&e.version))
+            .map(|(k, e)| (k.clone(), e.clone()))
+            .collect();
+        let want = theirs
+            .into_iter()
+            .filter(|(k, v)| self.entries.get(*k).map(|e| &e.version) != Some(*v))
+            .map(|(k, _)| k.to_string())
+            .collect();
+        (send, want)
+    }
+
+    /// Entries for the given keys, skipping any we don't have.
+    pub fn entries(&self, keys: &[String]) -> Vec<(String, Entry)> {
+        keys.iter()
+            .filter_map(|k| self.entries.get(k).map(|e| (k.clone(), e.clone())))
+            .collect()
+    }
+
+    /// Folds in entries from a peer and returns the keys whose value changed.
+    pub fn merge(&mut self, entries: Vec<(String, Entry)>) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (key, theirs) in entries {
+            match self.entries.get(&key) {
+                Some(ours) if ours.rank() >= theirs.rank() => {}
+                _ => {
+                    self.entries.insert(key.clone(), theirs);
+                    changed.push(key);
+                }
+            }
+        }
+        changed
+    }
+}
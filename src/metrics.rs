@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+// upper bounds of the histogram buckets, in seconds
+const BUCKETS: [f64; 12] = [
+    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+type Key = (&'static str, Vec<(String, String)>);
+
+#[derive(Default)]
+struct Histogram {
+    // observations at or under each bucket's bound, not counting the ones under the bound before
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+struct Registry {
+    counters: BTreeMap<Key, u64>,
+    gauges: BTreeMap<Key, f64>,
+    histograms: BTreeMap<Key, Histogram>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    counters: BTreeMap::new(),
+    gauges: BTreeMap::new(),
+    histograms: BTreeMap::new(),
+});
+
+/// Starts recording. Until something asks for metrics, recording them is skipped altogether, so
+/// a node run under Maelstrom doesn't pay for what nobody reads.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn key(name: &'static str, labels: &[(&str, &str)]) -> Key {
+    let labels = labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    (name, labels)
+}
+
+/// Adds `by` to the counter `name` with these labels.
+pub fn count(name: &'static str, labels: &[(&str, &str)], by: u64) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "otel")]
+    crate::otel::count(name, labels, by);
+    let mut registry = REGISTRY.lock().expect("not poisoned");
+    *registry.counters.entry(key(name, labels)).or_default() += by;
+}
+
+/// Sets the gauge `name` with these labels to `value`.
+pub fn gauge(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "otel")]
+    crate::otel::gauge(name, labels, value);
+    let mut registry = REGISTRY.lock().expect("not poisoned");
+    registry.gauges.insert(key(name, labels), value);
+}
+
+/// Records how long something took in the histogram `name` with these labels.
+pub fn observe(name: &'static str, labels: &[(&str, &str)], took: Duration) {
+    if !enabled() {
+        return;
+    }
+    #[cfg(feature = "otel")]
+    crate::otel::observe(name, labels, took);
+    let seconds = took.as_secs_f64();
+    let mut registry = REGISTRY.lock().expect("not poisoned");
+    let histogram = registry.histograms.entry(key(name, labels)).or_default();
+    if let Some(bucket) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+        histogram.buckets[bucket] += 1;
+    }
+    histogram.count += 1;
+    histogram.sum += seconds;
+}
+
+/// The counters called `name`, by their labels, as they stand.
+pub fn counters(name: &str) -> BTreeMap<Vec<(String, String)>, u64> {
+    let registry = REGISTRY.lock().expect("not poisoned");
+    registry
+        .counters
+        .iter()
+        .filter(|((counter, _), _)| *counter == name)
+        .map(|((_, labels), value)| (labels.clone(), *value))
+        .collect()
+}
+
+/// The gauges called `name`, by their labels, as they stand.
+pub fn gauges(name: &str) -> BTreeMap<Vec<(String, String)>, f64> {
+    let registry = REGISTRY.lock().expect("not poisoned");
+    registry
+        .gauges
+        .iter()
+        .filter(|((gauge, _), _)| *gauge == name)
+        .map(|((_, labels), value)| (labels.clone(), *value))
+        .collect()
+}
+
+/// How many times each histogram called `name` has been observed, by its labels.
+pub fn observations(name: &str) -> BTreeMap<Vec<(String, String)>, u64> {
+    let registry = REGISTRY.lock().expect("not poisoned");
+    registry
+        .histograms
+        .iter()
+        .filter(|((histogram, _), _)| *histogram == name)
+        .map(|((_, labels), histogram)| (labels.clone(), histogram.count))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct Typed {
+    body: TypedBody,
+}
+
+#[derive(Deserialize)]
+struct TypedBody {
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// The `type` of a serialized message, to label its metrics with.
+pub fn message_type(frame: &[u8]) -> String {
+    serde_json::from_slice::<Typed>(frame)
+        .map(|typed| typed.body.kind)
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn labels(labels: &[(String, String)], extra: Option<(&str, &str)>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+        .collect();
+    if let Some((k, v)) = extra {
+        pairs.push(format!("{}=\"{}\"", k, v));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Everything recorded so far, in the Prometheus text exposition format.
+pub fn render() -> String {
+    let registry = REGISTRY.lock().expect("not poisoned");
+    let mut out = String::new();
+    let mut last = None;
+    for ((name, labelled), value) in &registry.counters {
+        if last != Some(*name) {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            last = Some(*name);
+        }
+        let _ = writeln!(out, "{}{} {}", name, labels(labelled, None), value);
+    }
+    last = None;
+    for ((name, labelled), value) in &registry.gauges {
+        if last != Some(*name) {
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            last = Some(*name);
+        }
+        let _ = writeln!(out, "{}{} {}", name, labels(labelled, None), value);
+    }
+    last = None;
+    for ((name, labelled), histogram) in &registry.histograms {
+        if last != Some(*name) {
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            last = Some(*name);
+        }
+        let mut cumulative = 0;
+        for (bound, n) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += n;
+            let le = bound.to_string();
+            let _ = writeln!(
+                out,
+                "{}_bucket{} {}",
+                name,
+                labels(labelled, Some(("le", &le))),
+                cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            labels(labelled, Some(("le", "+Inf"))),
+            histogram.count
+        );
+        let _ = writeln!(
+            out,
+            "{}_sum{} {}",
+            name,
+            labels(labelled, None),
+            histogram.sum
+        );
+        let _ = writeln!(
+            out,
+            "{}_count{} {}",
+            name,
+            labels(labelled, None),
+            histogram.count
+        );
+    }
+    out
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Multi-version store: every write is kept under the timestamp it committed at, so a reader can
+/// ask for the state of the store as of any timestamp and get a consistent snapshot while newer
+/// writes keep coming in. Timestamps are plain integers; [`crate::hlc::Timestamp::as_u64`] makes
+/// good ones.
+///
+/// Old versions pile up until [`Mvcc::gc`] drops the ones no snapshot at or after its horizon can
+/// see any more.
+#[derive(Debug, Clone)]
+pub struct Mvcc<K, V> {
+    // every key's versions, oldest first
+    versions: HashMap<K, Vec<(u64, V)>>,
+    horizon: u64,
+}
+
+impl<K, V> Default for Mvcc<K, V> {
+    fn default() -> Self {
+        Self {
+            versions: HashMap::new(),
+            horizon: 0,
+        }
+    }
+}
+
+impl<K, V> Mvcc<K, V>
+where
+    K: Eq + Hash,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `value` as written at `ts`. A second write at the same timestamp replaces the
+    /// first.
+    pub fn write(&mut self, key: K, ts: u64, value: V) {
+        let versions = self.versions.entry(key).or_default();
+        match versions.binary_search_by_key(&ts, |(t, _)| *t) {
+            Ok(i) => versions[i].1 = value,
+            Err(i) => versions.insert(i, (ts, value)),
+        }
+    }
+
+    /// The newest version of `key`, whatever its timestamp.
+    pub fn latest(&self, key: &K) -> Option<&V> {
+        self.versions.get(key)?.last().map(|(_, v)| v)
+    }
+
+    /// `key` as of `ts`: the newest version written at or before it. Only meaningful for
+    /// timestamps at or after the [`horizon`](Mvcc::horizon), since older versions may be gone.
+    pub fn read_at(&self, key: &K, ts: u64) -> Option<&V> {
+        let versions = self.versions.get(key)?;
+        let newer = versions.partition_point(|(t, _)| *t <= ts);
+        newer.checked_sub(1).map(|i| &versions[i].1)
+    }
+
+    /// The timestamps of every version of `key` written after `ts`, oldest first.
+    pub fn written_after(&self, key: &K, ts: u64) -> impl Iterator<Item = u64> + '_ {
+        let versions = self.versions.get(key).map_or(&[][..], |v| v.as_slice());
+        let newer = versions.partition_point(|(t, _)| *t <= ts);
+        versions[newer..].iter().map(|(t, _)| *t)
+    }
+
+    /// Every version still kept, as key, timestamp and value, each key's oldest first.
+    pub fn versions(&self) -> impl Iterator<Item = (&K, u64, &V)> + '_ {
+        self.versions
+            .iter()
+            .flat_map(|(k, versions)| versions.iter().map(move |(t, v)| (k, *t, v)))
+    }
+
+    /// The oldest timestamp that can still be read at.
+    pub fn horizon(&self) -> u64 {
+        self.horizon
+    }
+
+    /// Forgets every version a read at `horizon` or later can't see: for each key, everything
+    /// older than its newest version at or before `horizon`. The horizon never moves back.
+    pub fn gc(&mut self, horizon: u64) {
+        if horizon <= self.horizon {
+            return;
+        }
+        self.horizon = horizon;
+        for versions in self.versions.values_mut() {
+            let visible = versions.partition_point(|(t, _)| *t <= horizon);
+            if visible > 1 {
+                versions.drain(..visible - 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use anyhow::Context as _;
+use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter, MeterProvider as _};
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::{Span as _, TraceContextExt, Tracer as _, TracerProvider as _};
+use opentelemetry::{Context, KeyValue};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::{config, metrics, Event, Init};
+
+// how often metrics are pushed
+const PUSH_EVERY: Duration = Duration::from_secs(5);
+
+// the span of the step the node's taking, on the thread taking it, for whatever it sends to
+// carry on
+thread_local! {
+    static STEPPING: RefCell<Option<Context>> = const { RefCell::new(None) };
+}
+
+// what the metrics recorded so far have been forwarded to
+static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+
+struct Instruments {
+    meter: Meter,
+    counters: Mutex<HashMap<&'static str, Counter<u64>>>,
+    gauges: Mutex<HashMap<&'static str, Gauge<f64>>>,
+    histograms: Mutex<HashMap<&'static str, Histogram<f64>>>,
+}
+
+/// Traces and metrics shipped over OTLP/HTTP to `RUSTENGAN_OTLP_ENDPOINT` (a collector,
+/// Jaeger or Tempo, say, at `http://localhost:4318`), for clusters run on a network of their
+/// own rather than under Maelstrom, which has no collector to send to.
+///
+/// Every step the node takes of a message is a span, named for the message's type. The span
+/// goes out with every message the node sends while it's stepping, as a W3C `traceparent` in
+/// the message's body, and the node that gets the message takes its step under it, so one
+/// client's request shows as one trace across every node it touched. A message that doesn't
+/// come with a trace, like one from a client, starts one.
+///
+/// Whatever [`metrics`] records goes over too, which asking for OTLP turns on.
+pub(crate) struct Otel {
+    tracer: SdkTracer,
+    tracers: SdkTracerProvider,
+    meters: SdkMeterProvider,
+    // what the tracing needs of each message that's been read, in the order they were read,
+    // which is the order they're stepped in since nothing but the input thread sends the node
+    // messages
+    read: Arc<Mutex<VecDeque<TracedBody>>>,
+}
+
+#[derive(Deserialize)]
+struct Traced {
+    body: TracedBody,
+}
+
+#[derive(Default, Deserialize)]
+struct TracedBody {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    traceparent: Option<String>,
+}
+
+impl Otel {
+    /// Starts exporting if `RUSTENGAN_OTLP_ENDPOINT` asks for it.
+    pub(crate) fn from_env(init: &Init) -> anyhow::Result<Option<Self>> {
+        let Some(endpoint) = config::var::<String>("RUSTENGAN_OTLP_ENDPOINT")? else {
+            return Ok(None);
+        };
+        let endpoint = endpoint.trim_end_matches('/');
+        let resource = Resource::builder()
+            .with_service_name("rustengan")
+            .with_attribute(KeyValue::new("service.instance.id", init.node_id.clone()))
+            .build();
+        let spans = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint))
+            .build()
+            .context("otlp span exporter")?;
+        let tracers = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+        let exported = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/metrics", endpoint))
+            .build()
+            .context("otlp metric exporter")?;
+        let meters = SdkMeterProvider::builder()
+            .with_reader(
+                PeriodicReader::builder(exported)
+                    .with_interval(PUSH_EVERY)
+                    .build(),
+            )
+            .with_resource(resource)
+            .build();
+        // a second node in the same process exports its metrics with the first's; they're
+        // one registry anyway
+        let _ = INSTRUMENTS.set(Instruments {
+            meter: meters.meter("rustengan"),
+            counters: Mutex::new(HashMap::new()),
+            gauges: Mutex::new(HashMap::new()),
+            histograms: Mutex::new(HashMap::new()),
+        });
+        metrics::enable();
+        log::info!("exporting traces and metrics to {}", endpoint);
+        Ok(Some(Self {
+            tracer: tracers.tracer("rustengan"),
+            tracers,
+            meters,
+            read: Arc::default(),
+        }))
+    }
+
+    /// Where the input thread notes the trace each message it reads came with.
+    pub(crate) fn reader(&self) -> Reader {
+        Reader {
+            read: Arc::clone(&self.read),
+        }
+    }
+
+    /// Steps `event` with `step` in a span of its own, under the trace the message came with.
+    pub(crate) fn step<P, IP, T>(
+        &self,
+        event: Event<P, IP>,
+        step: impl FnOnce(Event<P, IP>) -> anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        let (name, parent, attributes) = match &event {
+            Event::Message(message) => {
+                let read = self
+                    .read
+                    .lock()
+                    .expect("not poisoned")
+                    .pop_front()
+                    .unwrap_or_default();
+                let parent = match read.traceparent {
+                    Some(traceparent) => {
+                        let carrier = HashMap::from([("traceparent".to_string(), traceparent)]);
+                        TraceContextPropagator::new().extract(&carrier)
+                    }
+                    None => Context::new(),
+                };
+                let mut attributes = vec![
+                    KeyValue::new("message.src", message.src.clone()),
+                    KeyValue::new("message.dest", message.dst.clone()),
+                ];
+                if let Some(id) = message.body.id {
+                    attributes.push(KeyValue::new("message.msg_id", id as i64));
+                }
+                if let Some(id) = message.body.in_reply_to {
+                    attributes.push(KeyValue::new("message.in_reply_to", id as i64));
+                }
+                let name = read.kind.unwrap_or_else(|| "unknown".to_string());
+                (name, parent, attributes)
+            }
+            Event::Injected(_) => ("injected".to_string(), Context::new(), Vec::new()),
+            Event::EOF => return step(event),
+        };
+        let mut span = self
+            .tracer
+            .span_builder(name)
+            .with_attributes(attributes)
+            .start_with_context(&self.tracer, &parent);
+        let cx = Context::new().with_remote_span_context(span.span_context().clone());
+        STEPPING.set(Some(cx));
+        let stepped = step(event);
+        STEPPING.set(None);
+        if let Err(e) = &stepped {
+            span.set_status(opentelemetry::trace::Status::error(format!("{:#}", e)));
+        }
+        span.end();
+        stepped
+    }
+}
+
+impl Drop for Otel {
+    fn drop(&mut self) {
+        // whatever's still batched up goes before the node does
+        if let Err(e) = self.tracers.shutdown() {
+            log::warn!("shutting down the otlp span exporter: {}", e);
+        }
+        if let Err(e) = self.meters.shutdown() {
+            log::warn!("shutting down the otlp metric exporter: {}", e);
+        }
+    }
+}
+
+/// The input thread's end of [`Otel`].
+pub(crate) struct Reader {
+    read: Arc<Mutex<VecDeque<TracedBody>>>,
+}
+
+impl Reader {
+    /// Notes the type of the message on `line`, and the trace it came with, as it's handed to
+    /// the node.
+    pub(crate) fn read(&self, line: &str) {
+        let body = serde_json::from_str::<Traced>(line)
+            .map(|traced| traced.body)
+            .unwrap_or_default();
+        self.read.lock().expect("not poisoned").push_back(body);
+    }
+}
+
+/// `frame` with the span of the step being taken as its `traceparent`, if it's being taken in
+/// one, and what the span makes of it.
+pub(crate) fn stamp(frame: &[u8]) -> Option<Vec<u8>> {
+    let cx = STEPPING.with_borrow(|cx| cx.clone())?;
+    let mut message: Value = serde_json::from_slice(frame).ok()?;
+    let body = message.get_mut("body")?.as_object_mut()?;
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&cx, &mut carrier);
+    body.insert(
+        "traceparent".to_string(),
+        carrier.remove("traceparent")?.into(),
+    );
+    serde_json::to_vec(&message).ok()
+}
+
+/// Forwards a counter [`metrics`] has been given.
+pub(crate) fn count(name: &'static str, labels: &[(&str, &str)], by: u64) {
+    let Some(instruments) = INSTRUMENTS.get() else {
+        return;
+    };
+    let mut counters = instruments.counters.lock().expect("not poisoned");
+    let counter = counters
+        .entry(name)
+        .or_insert_with(|| instruments.meter.u64_counter(name).build());
+    counter.add(by, &attributes(labels));
+}
+
+/// Forwards a gauge [`metrics`] has been given.
+pub(crate) fn gauge(name: &'static str, labels: &[(&str, &str)], value: f64) {
+    let Some(instruments) = INSTRUMENTS.get() else {
+        return;
+    };
+    let mut gauges = instruments.gauges.lock().expect("not poisoned");
+    let gauge = gauges
+        .entry(name)
+        .or_insert_with(|| instruments.meter.f64_gauge(name).build());
+    gauge.record(value, &attributes(labels));
+}
+
+/// Forwards an observation [`metrics`] has been given.
+pub(crate) fn observe(name: &'static str, labels: &[(&str, &str)], took: Duration) {
+    let Some(instruments) = INSTRUMENTS.get() else {
+        return;
+    };
+    let mut histograms = instruments.histograms.lock().expect("not poisoned");
+    let histogram = histograms
+        .entry(name)
+        .or_insert_with(|| instruments.meter.f64_histogram(name).with_unit("s").build());
+    histogram.record(took.as_secs_f64(), &attributes(labels));
+}
+
+fn attributes(labels: &[(&str, &str)]) -> Vec<KeyValue> {
+    labels
+        .iter()
+        .map(|(k, v)| KeyValue::new(k.to_string(), v.to_string()))
+        .collect()
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use signal_hook::consts::SIGUSR2;
+use signal_hook::iterator::Signals;
+
+use crate::config;
+
+// samples a second, a little off 100 so as not to keep time with anything else that's periodic
+const FREQUENCY: i32 = 99;
+// what pprof can't make sense of the frames of
+const BLOCKLIST: &[&str] = &["libc", "libgcc", "pthread", "vdso"];
+
+// who's being profiled, once they know, for how long unless asked otherwise, and the profile
+// being taken, if one is
+static NODE_ID: OnceLock<String> = OnceLock::new();
+static SECONDS: OnceLock<Duration> = OnceLock::new();
+static TAKING: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Has a signal take a CPU profile of the process: send it SIGUSR2 and it samples every thread
+/// for `RUSTENGAN_PROFILE_SECS` seconds (10 unless it says otherwise), then writes what it saw
+/// as a flamegraph under `RUSTENGAN_DATA_DIR`, as `profiles/<node>-<unix ms>.svg`, or under
+/// `rustengan/` in the system's temporary directory without one, since the scratch directory a
+/// node has otherwise goes when it exits. For finding out what a node that's eating a core is
+/// eating it on, without stopping it.
+///
+/// Installed before the node's even been sent its init, since a SIGUSR2 that comes before
+/// there's anything to catch it kills the process.
+pub(crate) fn install() -> anyhow::Result<()> {
+    let seconds = config::var_or("RUSTENGAN_PROFILE_SECS", 10)?;
+    let _ = SECONDS.set(Duration::from_secs(seconds));
+    let mut signals = Signals::new([SIGUSR2]).context("catch SIGUSR2")?;
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            if let Err(e) = take(None) {
+                log::warn!("couldn't profile: {:#}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Names the profiles taken from now on for `node_id`.
+pub(crate) fn running_as(node_id: &str) {
+    let _ = NODE_ID.set(node_id.to_string());
+}
+
+/// Starts a profile of the next `seconds` (`RUSTENGAN_PROFILE_SECS` unless it says otherwise),
+/// and where it'll be written. Asking for another while one's being taken gets where that one
+/// will be.
+pub(crate) fn take(seconds: Option<Duration>) -> anyhow::Result<PathBuf> {
+    let seconds = seconds
+        .or_else(|| SECONDS.get().copied())
+        .unwrap_or(Duration::from_secs(10));
+    let mut taking = TAKING.lock().expect("not poisoned");
+    if let Some(path) = &*taking {
+        return Ok(path.clone());
+    }
+    let dir = match std::env::var_os("RUSTENGAN_DATA_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join("rustengan"),
+    }
+    .join("profiles");
+    std::fs::create_dir_all(&dir).with_context(|| format!("create {}", dir.display()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let node_id = match NODE_ID.get() {
+        Some(node_id) => node_id.clone(),
+        None => std::process::id().to_string(),
+    };
+    let path = dir.join(format!("{}-{}.svg", node_id, now.as_millis()));
+    let profiler = pprof::ProfilerGuardBuilder::default()
+        .frequency(FREQUENCY)
+        .blocklist(BLOCKLIST)
+        .build()
+        .context("start the profiler")?;
+    log::info!("profiling for {:?} to {}", seconds, path.display());
+    *taking = Some(path.clone());
+    let written = path.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(seconds);
+        let flamegraph = profiler
+            .report()
+            .build()
+            .context("build the report")
+            .and_then(|report| {
+                let file = std::fs::File::create(&written)
+                    .with_context(|| format!("create {}", written.display()))?;
+                report.flamegraph(file).context("write the flamegraph")
+            });
+        // the profiler stops once it's dropped, and another can start
+        drop(profiler);
+        match flamegraph {
+            Ok(()) => log::info!("profile written to {}", written.display()),
+            Err(e) => log::warn!("couldn't write the profile: {:#}", e),
+        }
+        *TAKING.lock().expect("not poisoned") = None;
+    });
+    Ok(path)
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+pub mod payloads {
+    include!(concat!(env!("OUT_DIR"), "/rustengan.payloads.rs"));
+}
+
+use payloads::{broadcast_payload, kv_payload, BroadcastPayload, Empty, KvPayload};
+
+/// A body in one of the schemas of `proto/payloads.proto`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Typed {
+    Broadcast(BroadcastPayload),
+    Kv(KvPayload),
+}
+
+/// What the body of type `kind` with these `fields` (everything but `type`, `msg_id` and
+/// `in_reply_to`) is in a schema, if it fits one exactly: a field missing, left over, or of the
+/// wrong type and it doesn't.
+pub fn encode(kind: &str, fields: &Map<String, Value>) -> Option<Typed> {
+    broadcast(kind, fields)
+        .map(Typed::Broadcast)
+        .or_else(|| kv(kind, fields).map(Typed::Kv))
+}
+
+/// The `type` and fields of a typed body, as JSON would have them.
+pub fn decode(typed: &Typed) -> (&'static str, Map<String, Value>) {
+    let mut fields = Map::new();
+    let mut set = |name: &str, value: Value| {
+        fields.insert(name.to_string(), value);
+    };
+    let kind = match typed {
+        Typed::Broadcast(BroadcastPayload { payload }) => match payload {
+            Some(broadcast_payload::Payload::Broadcast(b)) => {
+                set("message", b.message.into());
+                "broadcast"
+            }
+            Some(broadcast_payload::Payload::BroadcastOk(_)) => "broadcast_ok",
+            Some(broadcast_payload::Payload::Read(_)) => "read",
+            Some(broadcast_payload::Payload::ReadOk(r)) => {
+                set("messages", r.messages.clone().into());
+                "read_ok"
+            }
+            Some(broadcast_payload::Payload::Topology(t)) => {
+                let topology: Map<String, Value> = t
+                    .topology
+                    .iter()
+                    .map(|(node, n)| (node.clone(), n.nodes.clone().into()))
+                    .collect();
+                set("topology", topology.into());
+                "topology"
+            }
+            Some(broadcast_payload::Payload::TopologyOk(_)) => "topology_ok",
+            Some(broadcast_payload::Payload::Gossip(g)) => {
+                set("seen", g.seen.clone().into());
+                "gossip"
+            }
+            None => "unknown",
+        },
+        Typed::Kv(KvPayload { payload }) => match payload {
+            Some(kv_payload::Payload::Read(r)) => {
+                set("key", r.key.into());
+                "read"
+            }
+            Some(kv_payload::Payload::ReadOk(r)) => {
+                set("value", r.value.into());
+                "read_ok"
+            }
+            Some(kv_payload::Payload::Write(w)) => {
+                set("key", w.key.into());
+                set("value", w.value.into());
+                "write"
+            }
+            Some(kv_payload::Payload::WriteOk(_)) => "write_ok",
+            Some(kv_payload::Payload::Cas(c)) => {
+                set("key", c.key.into());
+                set("from", c.from.into());
+                set("to", c.to.into());
+                if c.create_if_not_exists {
+                    set("create_if_not_exists", true.into());
+                }
+                "cas"
+            }
+            Some(kv_payload::Payload::CasOk(_)) => "cas_ok",
+            Some(kv_payload::Payload::Error(e)) => {
+                set("code", e.code.into());
+                set("text", e.text.clone().into());
+                "error"
+            }
+            None => "unknown",
+        },
+    };
+    (kind, fields)
+}
+
+// the fields called `names`, if those are all there is
+fn exactly<'a, const N: usize>(
+    fields: &'a Map<String, Value>,
+    names: [&str; N],
+) -> Option<[&'a Value; N]> {
+    if fields.len() != N {
+        return None;
+    }
+    let mut found = [&Value::Null; N];
+    for (slot, name) in found.iter_mut().zip(names) {
+        *slot = fields.get(name)?;
+    }
+    Some(found)
+}
+
+fn numbers(value: &Value) -> Option<Vec<u64>> {
+    value.as_array()?.iter().map(Value::as_u64).collect()
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_str().map(str::to_string))
+        .collect()
+}
+
+fn broadcast(kind: &str, fields: &Map<String, Value>) -> Option<BroadcastPayload> {
+    use broadcast_payload::Payload;
+    let payload = match kind {
+        "broadcast" => {
+            let [message] = exactly(fields, ["message"])?;
+            Payload::Broadcast(payloads::BroadcastMessage {
+                message: message.as_u64()?,
+            })
+        }
+        "broadcast_ok" => {
+            exactly(fields, [])?;
+            Payload::BroadcastOk(Empty {})
+        }
+        "read" => {
+            exactly(fields, [])?;
+            Payload::Read(Empty {})
+        }
+        "read_ok" => {
+            let [messages] = exactly(fields, ["messages"])?;
+            Payload::ReadOk(payloads::BroadcastReadOk {
+                messages: numbers(messages)?,
+            })
+        }
+        "topology" => {
+            let [topology] = exactly(fields, ["topology"])?;
+            let topology: Option<HashMap<String, payloads::Neighbours>> = topology
+                .as_object()?
+                .iter()
+                .map(|(node, n)| Some((node.clone(), payloads::Neighbours { nodes: strings(n)? })))
+                .collect();
+            Payload::Topology(payloads::Topology {
+                topology: topology?,
+            })
+        }
+        "topology_ok" => {
+            exactly(fields, [])?;
+            Payload::TopologyOk(Empty {})
+        }
+        "gossip" => {
+            let [seen] = exactly(fields, ["seen"])?;
+            Payload::Gossip(payloads::Gossip {
+                seen: numbers(seen)?,
+            })
+        }
+        _ => return None,
+    };
+    Some(BroadcastPayload {
+        payload: Some(payload),
+    })
+}
+
+fn kv(kind: &str, fields: &Map<String, Value>) -> Option<KvPayload> {
+    use kv_payload::Payload;
+    let payload = match kind {
+        "read" => {
+            let [key] = exactly(fields, ["key"])?;
+            Payload::Read(payloads::KvRead { key: key.as_u64()? })
+        }
+        "read_ok" => {
+            let [value] = exactly(fields, ["value"])?;
+            Payload::ReadOk(payloads::KvReadOk {
+                value: value.as_u64()?,
+            })
+        }
+        "write" => {
+            let [key, value] = exactly(fields, ["key", "value"])?;
+            Payload::Write(payloads::KvWrite {
+                key: key.as_u64()?,
+                value: value.as_u64()?,
+            })
+        }
+        "write_ok" => {
+            exactly(fields, [])?;
+            Payload::WriteOk(Empty {})
+        }
+        "cas" => {
+            let create = fields.get("create_if_not_exists");
+            let [key, from, to] = if create.is_some() {
+                let [key, from, to, _] =
+                    exactly(fields, ["key", "from", "to", "create_if_not_exists"])?;
+                [key, from, to]
+            } else {
+                exactly(fields, ["key", "from", "to"])?
+            };
+            Payload::Cas(payloads::KvCas {
+                key: key.as_u64()?,
+                from: from.as_u64()?,
+                to: to.as_u64()?,
+                create_if_not_exists: match create {
+                    Some(create) => create.as_bool()?,
+                    None => false,
+                },
+            })
+        }
+        "cas_ok" => {
+            exactly(fields, [])?;
+            Payload::CasOk(Empty {})
+        }
+        "error" => {
+            let [code, text] = exactly(fields, ["code", "text"])?;
+            Payload::Error(payloads::Error {
+                code: code.as_u64()?,
+                text: text.as_str()?.to_string(),
+            })
+        }
+        _ => return None,
+    };
+    Some(KvPayload {
+        payload: Some(payload),
+    })
+}
@@ -0,0 +1,59 @@
+use std::cell::RefCell;
+
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore};
+
+use crate::clock;
+
+// the generator the simulator hands the node it's stepping, while it's stepping it
+thread_local! {
+    static SIMULATED: RefCell<Option<StdRng>> = const { RefCell::new(None) };
+}
+
+/// Random numbers for a node, in place of `rand::thread_rng()`: the thread's own generator, or
+/// under the simulator, one of the node's own seeded from the simulation's seed, so that a run
+/// with the same seed makes the same choices.
+pub fn thread() -> Source {
+    Source
+}
+
+/// What [`thread`] draws from, wherever that is at the time.
+#[derive(Debug, Clone, Copy)]
+pub struct Source;
+
+impl RngCore for Source {
+    fn next_u32(&mut self) -> u32 {
+        with(|rng| rng.next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        with(|rng| rng.next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        with(|rng| rng.fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        with(|rng| rng.try_fill_bytes(dest))
+    }
+}
+
+/// A ULID for now by [`clock::wall_ms`], with its randomness from [`thread`]: what
+/// `Ulid::new()` makes, without going by the machine's clock and generator.
+pub fn ulid() -> ulid::Ulid {
+    ulid::Ulid::from_parts(clock::wall_ms(), thread().gen())
+}
+
+fn with<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    SIMULATED.with_borrow_mut(|simulated| match simulated {
+        Some(rng) => f(rng),
+        None => f(&mut rand::thread_rng()),
+    })
+}
+
+// has `thread` draw from `rng` on this thread, until it's `None` again, and hands back what it
+// was drawing from before
+pub(crate) fn simulate(rng: Option<StdRng>) -> Option<StdRng> {
+    SIMULATED.replace(rng)
+}
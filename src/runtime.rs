@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::Event;
+
+/// The threads a node runs on and the channel between them, std's in a running node. Tests put
+/// a scheduler's threads and channels in their place, like shuttle's, to run the runtime
+/// through orderings of its threads a real run would hit only once in a blue moon.
+pub trait Threads: 'static {
+    type Sender<T: Send + 'static>: Clone + Send + 'static;
+    type Receiver<T: Send + 'static>;
+    type JoinHandle<T: Send + 'static>;
+
+    /// Sends `value` down `tx`, false if nobody's receiving any more.
+    fn send<T: Send + 'static>(tx: &Self::Sender<T>, value: T) -> bool;
+
+    /// The next value on `rx`, waiting for it no longer than `timeout`, if there is one.
+    fn recv<T: Send + 'static>(rx: &Self::Receiver<T>, timeout: Option<Duration>) -> Recv<T>;
+
+    fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Self::JoinHandle<T>;
+
+    /// Waits for a thread to finish, and what it came to, unless it panicked.
+    fn join<T: Send + 'static>(handle: Self::JoinHandle<T>) -> Option<T>;
+
+    fn sleep(duration: Duration);
+}
+
+/// What waiting on a channel came to.
+#[derive(Debug)]
+pub enum Recv<T> {
+    Got(T),
+    Timeout,
+    // every sender's gone
+    Closed,
+}
+
+/// Real threads and std's channels.
+pub struct Std;
+
+impl Threads for Std {
+    type Sender<T: Send + 'static> = std::sync::mpsc::Sender<T>;
+    type Receiver<T: Send + 'static> = std::sync::mpsc::Receiver<T>;
+    type JoinHandle<T: Send + 'static> = std::thread::JoinHandle<T>;
+
+    fn send<T: Send + 'static>(tx: &Self::Sender<T>, value: T) -> bool {
+        tx.send(value).is_ok()
+    }
+
+    fn recv<T: Send + 'static>(rx: &Self::Receiver<T>, timeout: Option<Duration>) -> Recv<T> {
+        use std::sync::mpsc::RecvTimeoutError;
+        let Some(timeout) = timeout else {
+            return rx.recv().map_or(Recv::Closed, Recv::Got);
+        };
+        match rx.recv_timeout(timeout) {
+            Ok(value) => Recv::Got(value),
+            Err(RecvTimeoutError::Timeout) => Recv::Timeout,
+            Err(RecvTimeoutError::Disconnected) => Recv::Closed,
+        }
+    }
+
+    fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Self::JoinHandle<T> {
+        std::thread::spawn(f)
+    }
+
+    fn join<T: Send + 'static>(handle: Self::JoinHandle<T>) -> Option<T> {
+        handle.join().ok()
+    }
+
+    fn sleep(duration: Duration) {
+        std::thread::sleep(duration)
+    }
+}
+
+/// Runs a node: reads `lines` on a thread of their own, turning each into an event with
+/// `parse` (or dropping it, if that comes to nothing) and sending it down `tx`, while `handle`
+/// is given every event that arrives on `rx`, whoever sent it, in the order they arrive.
+///
+/// Stops once `handle` has been given EOF, which the input thread sends last however it ends,
+/// by running out of lines, failing to read one or panicking, so a node whose timers keep
+/// sending it ticks still hears that its input is done. Stops straight away if `handle` fails,
+/// without waiting for the input thread, which might be waiting on a read that never comes;
+/// it stops at its next send, as do the timers, once there's nobody receiving.
+///
+/// With a `poll`, `handle` is given nothing at least that often, for whatever else the node
+/// looks after in between events.
+pub fn run<T, P, IP>(
+    lines: impl Iterator<Item = anyhow::Result<String>> + Send + 'static,
+    mut parse: impl FnMut(String) -> Option<Event<P, IP>> + Send + 'static,
+    tx: T::Sender<Event<P, IP>>,
+    rx: T::Receiver<Event<P, IP>>,
+    poll: Option<Duration>,
+    mut handle: impl FnMut(Option<Event<P, IP>>) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    T: Threads,
+    P: Send + 'static,
+    IP: Send + 'static,
+{
+    let input = T::spawn(move || {
+        let tx = Eof::<T, P, IP>(tx);
+        for line in lines {
+            let line = line.context("input could not be read")?;
+            let Some(event) = parse(line) else {
+                continue;
+            };
+            if !T::send(&tx.0, event) {
+                // the node's stopped, and has no more use for input
+                break;
+            }
+        }
+        anyhow::Ok(())
+    });
+
+    loop {
+        if poll.is_some() {
+            handle(None)?;
+        }
+        let event = match T::recv(&rx, poll) {
+            Recv::Got(event) => event,
+            Recv::Timeout => continue,
+            Recv::Closed => break,
+        };
+        let eof = matches!(event, Event::EOF);
+        handle(Some(event))?;
+        if eof {
+            break;
+        }
+    }
+    drop(rx);
+
+    T::join(input)
+        .context("input thread panicked")?
+        .context("input thread err'd")
+}
+
+/// Sends `tick()` down `tx` every `every`, from a thread of its own, until nobody's receiving.
+/// The thread, which there's no need to wait for.
+pub fn timer<T: Threads, E: Send + 'static>(
+    every: Duration,
+    tx: T::Sender<E>,
+    tick: impl Fn() -> E + Send + 'static,
+) -> T::JoinHandle<()> {
+    T::spawn(move || loop {
+        T::sleep(every);
+        if !T::send(&tx, tick()) {
+            break;
+        }
+    })
+}
+
+// the input thread's end of the channel, which sends EOF as it goes, however the thread ends
+struct Eof<T: Threads, P: Send + 'static, IP: Send + 'static>(T::Sender<Event<P, IP>>);
+
+impl<T: Threads, P: Send + 'static, IP: Send + 'static> Drop for Eof<T, P, IP> {
+    fn drop(&mut self) {
+        T::send(&self.0, Event::EOF);
+    }
+}
@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, LineWriter, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::config;
+
+/// Which way a recorded message went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Received,
+    Sent,
+}
+
+impl Direction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::Received => "received",
+            Direction::Sent => "sent",
+        }
+    }
+}
+
+/// A record of every message a node receives and sends, one JSON object per line:
+///
+/// ```text
+/// {"time":1718000000123456789,"direction":"received","message":{"src":"c1","dest":"n0",...}}
+/// ```
+///
+/// `time` is in nanoseconds since the unix epoch, so the logs of a cluster's nodes merge into
+/// one session, and `message` is the message exactly as it went over the wire. Clones write to
+/// the same file.
+#[derive(Clone)]
+pub struct Log {
+    file: Arc<Mutex<LineWriter<File>>>,
+}
+
+impl Log {
+    /// Starts `<dir>/<node_id>.jsonl`, replacing whatever an earlier session left there.
+    pub fn create(dir: &str, node_id: &str) -> anyhow::Result<Self> {
+        std::fs::create_dir_all(dir).with_context(|| format!("create {}", dir))?;
+        let path = format!("{}/{}.jsonl", dir, node_id);
+        let file = File::create(&path).with_context(|| format!("create {}", path))?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(LineWriter::new(file))),
+        })
+    }
+
+    /// The log in the directory `RUSTENGAN_SESSION_LOG` names, if it names one.
+    pub fn from_env(node_id: &str) -> anyhow::Result<Option<Self>> {
+        config::var::<String>("RUSTENGAN_SESSION_LOG")?
+            .map(|dir| Self::create(&dir, node_id))
+            .transpose()
+    }
+
+    /// Records `message`, a serialized message, as having gone `direction` just now. A log that
+    /// can't be written to isn't worth stopping the node for, so failing to is only a warning.
+    pub fn record(&self, direction: Direction, message: &[u8]) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos());
+        let mut line = format!(
+            "{{\"time\":{},\"direction\":\"{}\",\"message\":",
+            time,
+            direction.name()
+        )
+        .into_bytes();
+        line.extend_from_slice(message);
+        line.extend_from_slice(b"}\n");
+        if let Err(e) = self.file.lock().expect("not poisoned").write_all(&line) {
+            log::warn!("session log: {}", e);
+        }
+    }
+}
+
+/// One line of a session log.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Record {
+    pub time: u64,
+    pub direction: Direction,
+    pub message: serde_json::Value,
+}
+
+/// Everything recorded in the session log at `path`, in the order it was recorded.
+pub fn read(path: &str) -> anyhow::Result<Vec<Record>> {
+    let file = File::open(path).with_context(|| format!("open {}", path))?;
+    BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.with_context(|| format!("read {}", path))?;
+            serde_json::from_str(&line).with_context(|| format!("{}:{}", path, i + 1))
+        })
+        .collect()
+}
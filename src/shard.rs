@@ -0,0 +1,168 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::hash::{Hash, Hasher};
+
+use crate::config;
+
+/// Hashes a key (or anything else) the same way on every node. `DefaultHasher::new` always starts
+/// from the same keys, unlike the randomly seeded hashers `HashMap` uses.
+pub fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Decides which node owns a key, given the current members. Every node with the same members
+/// must come to the same answer, so implementations may only depend on the member set, not on the
+/// order members were added in.
+pub trait Placement: Send {
+    fn add(&mut self, node: &str);
+    fn remove(&mut self, node: &str);
+    /// The owner of a key with the given [`hash`], or `None` if there are no members.
+    fn owner(&self, key_hash: u64) -> Option<&str>;
+    /// The key's preference list: up to `n` distinct members in the order they should hold
+    /// copies of it, starting with the owner.
+    fn replicas(&self, key_hash: u64, n: usize) -> Vec<&str>;
+    fn members(&self) -> Vec<String>;
+}
+
+/// Consistent hashing: every member is placed on a ring at several pseudo-random points (virtual
+/// nodes), and a key belongs to the first member at or after the key's point, wrapping around.
+/// Adding or removing a member only moves the keys that landed next to its points, and the
+/// virtual nodes spread that load over everyone else instead of dumping it on one neighbour.
+pub struct HashRing {
+    vnodes: usize,
+    ring: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new(vnodes: usize) -> Self {
+        Self {
+            vnodes,
+            ring: BTreeMap::new(),
+        }
+    }
+
+    fn points<'a>(&self, node: &'a str) -> impl Iterator<Item = u64> + 'a {
+        (0..self.vnodes).map(move |vnode| hash(&(node, vnode)))
+    }
+}
+
+impl Placement for HashRing {
+    fn add(&mut self, node: &str) {
+        for point in self.points(node).collect::<Vec<_>>() {
+            self.ring.insert(point, node.to_string());
+        }
+    }
+
+    fn remove(&mut self, node: &str) {
+        self.ring.retain(|_, owner| owner != node);
+    }
+
+    fn owner(&self, key_hash: u64) -> Option<&str> {
+        self.ring
+            .range(key_hash..)
+            .chain(self.ring.iter())
+            .next()
+            .map(|(_, node)| node.as_str())
+    }
+
+    fn replicas(&self, key_hash: u64, n: usize) -> Vec<&str> {
+        let mut replicas = Vec::new();
+        // walk clockwise from the key's point, skipping further points of members we already have
+        for (_, node) in self
+            .ring
+            .range(key_hash..)
+            .chain(self.ring.range(..key_hash))
+        {
+            if replicas.len() == n {
+                break;
+            }
+            if !replicas.contains(&node.as_str()) {
+                replicas.push(node.as_str());
+            }
+        }
+        replicas
+    }
+
+    fn members(&self) -> Vec<String> {
+        let mut members: Vec<_> = self.ring.values().cloned().collect();
+        members.sort();
+        members.dedup();
+        members
+    }
+}
+
+/// Rendezvous (highest random weight) hashing: a key belongs to whichever member scores highest
+/// for it, the score being a hash of the member and key together. A membership change only moves
+/// the keys the added or removed member wins (or won), the minimum possible, at the cost of
+/// scoring every member on each lookup.
+#[derive(Default)]
+pub struct Rendezvous {
+    members: BTreeSet<String>,
+}
+
+impl Rendezvous {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Placement for Rendezvous {
+    fn add(&mut self, node: &str) {
+        self.members.insert(node.to_string());
+    }
+
+    fn remove(&mut self, node: &str) {
+        self.members.remove(node);
+    }
+
+    fn owner(&self, key_hash: u64) -> Option<&str> {
+        self.members
+            .iter()
+            .max_by_key(|node| (hash(&(node.as_str(), key_hash)), node.as_str()))
+            .map(|node| node.as_str())
+    }
+
+    fn replicas(&self, key_hash: u64, n: usize) -> Vec<&str> {
+        let mut replicas: Vec<_> = self.members.iter().map(|node| node.as_str()).collect();
+        replicas.sort_by_key(|node| std::cmp::Reverse((hash(&(*node, key_hash)), *node)));
+        replicas.truncate(n);
+        replicas
+    }
+
+    fn members(&self) -> Vec<String> {
+        self.members.iter().cloned().collect()
+    }
+}
+
+/// How many of the keys with the given [`hash`]es have a different owner under `after` than
+/// under `before`: what a change of members costs in keys handed from one node to another.
+pub fn moved(
+    before: &dyn Placement,
+    after: &dyn Placement,
+    key_hashes: impl IntoIterator<Item = u64>,
+) -> usize {
+    key_hashes
+        .into_iter()
+        .filter(|&key_hash| before.owner(key_hash) != after.owner(key_hash))
+        .count()
+}
+
+/// Builds the placement `RUSTENGAN_PLACEMENT` asks for over the given members: `ring` (the
+/// default, with `vnodes` points per member) or `rendezvous`.
+pub fn from_env(
+    members: impl IntoIterator<Item = impl AsRef<str>>,
+    vnodes: usize,
+) -> anyhow::Result<Box<dyn Placement>> {
+    let mut placement: Box<dyn Placement> =
+        match config::var_or("RUSTENGAN_PLACEMENT", "ring".to_string())?.as_str() {
+            "ring" => Box::new(HashRing::new(vnodes)),
+            "rendezvous" => Box::new(Rendezvous::new()),
+            other => anyhow::bail!("unknown placement {}, expected ring or rendezvous", other),
+        };
+    for node in members {
+        placement.add(node.as_ref());
+    }
+    Ok(placement)
+}
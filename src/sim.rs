@@ -0,0 +1,1112 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::history;
+use crate::kv::service::Service;
+use crate::session::{Direction, Record};
+use crate::transport::channel::Network;
+use crate::{clock, config, rng, wal, Body, Event, Init, Message, Node, Output};
+
+pub mod explore;
+pub mod faults;
+#[cfg(feature = "stateright")]
+pub mod model;
+pub mod nemesis;
+pub mod profile;
+pub mod scenario;
+pub mod shrink;
+pub mod skew;
+pub mod trace;
+
+use faults::{Faults, Tally};
+use nemesis::{Disruption, Nemesis, Split, Target};
+use profile::{Latency, Pipe, Profile};
+use shrink::{Choice, Fault, Schedule};
+use skew::{Clock, Skew};
+use trace::{Cause, Transition};
+
+// how long a message takes between any two parties unless `latency` says otherwise
+const LATENCY: RangeInclusive<Duration> = Duration::from_millis(1)..=Duration::from_millis(10);
+
+// how many simulations this process has set up, for each to have a data directory of its own
+static SIMULATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// A seed for a simulation: `RUSTENGAN_SIM_SEED` if it's set, to replay a run that failed, or a
+/// fresh one every run otherwise, so that every run tries something new.
+pub fn seed() -> anyhow::Result<u64> {
+    Ok(config::var("RUSTENGAN_SIM_SEED")?.unwrap_or_else(rand::random))
+}
+
+/// A whole cluster in one process, on a clock of its own. Nodes are ordinary [`Node`]s, started
+/// from the same init Maelstrom would have sent them, and what they send each other or their
+/// clients goes as JSON, as it would on the wire, but it's the simulator that decides when each
+/// message arrives: every message takes a latency picked from `latency` by a random number
+/// generator seeded with the simulation's seed, and messages and timer ticks are handled one at
+/// a time, in the order they fall due on the virtual clock. Nothing waits for real time to pass,
+/// so a minute of gossip runs in however long it takes to compute, and the same seed gives the
+/// same run, message for message.
+///
+/// Everything else about a run is set up through its methods: [`Sim::faults`] and [`Sim::link`]
+/// for the network, [`Sim::nemesis`] for partitions and crashes, [`Sim::skew`] for clocks,
+/// [`Sim::record_choices`] for shrinking a failing run and [`Sim::trace`] for checking one
+/// against a spec. [`explore`] goes through every run a small cluster can have instead of one
+/// per seed, and a [`scenario`] scripts one from TOML.
+pub struct Sim<P, IP = ()> {
+    node_ids: Vec<String>,
+    nodes: BTreeMap<String, Simulated<P, IP>>,
+    clock: Duration,
+    rng: StdRng,
+    latency: RangeInclusive<Duration>,
+    faults: Faults,
+    // by sender and receiver, links whose faults aren't the same as everywhere else's
+    links: BTreeMap<Link, Faults>,
+    // by sender and receiver, links whose latency isn't the same as everywhere else's
+    latencies: BTreeMap<Link, Latency>,
+    // by sender and receiver, links with a cap on their bandwidth
+    pipes: BTreeMap<Link, Pipe>,
+    // by sender and receiver, messages waiting to come in behind the next message on the link
+    held: BTreeMap<Link, Vec<String>>,
+    tally: Tally,
+    // by link and message fingerprint, how often the message's gone along the link, while the
+    // run's recording or replaying
+    sent_on: BTreeMap<(Link, u64), u64>,
+    // what the run's chosen so far, if it's recording
+    recording: Option<Vec<Choice>>,
+    // by link, message fingerprint and which time it's gone along the link, the faults a
+    // replayed run makes, and no others
+    script: Option<BTreeMap<(Link, u64, u64), Vec<Fault>>>,
+    // by node, which component of the partition it's in, if the cluster is partitioned
+    components: BTreeMap<String, usize>,
+    // by node, what's arrived for the nodes that are paused, to be handled once they're not
+    paused: BTreeMap<String, Vec<Happening<IP>>>,
+    killed: BTreeSet<String>,
+    // by node, how to start it again after it's killed
+    boots: BTreeMap<String, Boot<P, IP>>,
+    // by when they're due, and after that in the order they were scheduled
+    scheduled: BinaryHeap<Reverse<(Duration, u64)>>,
+    happenings: BTreeMap<u64, Happening<IP>>,
+    next: u64,
+    timers: Vec<Box<dyn Fn() -> IP>>,
+    // by node, whose clocks have been skewed
+    clocks: BTreeMap<String, Clock>,
+    // by name, the services standing in for Maelstrom's
+    services: BTreeMap<String, Service>,
+    // what's been sent to anyone who isn't a node or a service, by who it was sent to
+    replies: BTreeMap<String, Vec<String>>,
+    // by node, what it's heard from clients and said to them, as its session log would have it
+    sessions: BTreeMap<String, Vec<Record>>,
+    client_msg_id: usize,
+    trace: Option<Box<dyn Write>>,
+    // by name, what must hold between a node's status before a step and after it
+    invariants: Vec<(String, Invariant)>,
+    seed: u64,
+    // where the nodes' write-ahead logs go, which a node restarted after it's killed reads back
+    data_dir: PathBuf,
+}
+
+impl<P, IP> Drop for Sim<P, IP> {
+    fn drop(&mut self) {
+        // a test that fails panics, and the harness shows what it wrote to stderr
+        if std::thread::panicking() {
+            eprintln!(
+                "simulation failed at {:?} with seed {}: RUSTENGAN_SIM_SEED={} replays it",
+                self.clock, self.seed, self.seed
+            );
+        }
+        let _ = std::fs::remove_dir_all(&self.data_dir);
+    }
+}
+
+struct Simulated<P, IP> {
+    node: Box<dyn Running<P, IP>>,
+    // what `rng::thread` draws from while the node steps
+    rng: Option<StdRng>,
+    output: Output,
+    // what the node sends, on a switched network of its own, for the simulator to deliver
+    sent: Receiver<(String, String)>,
+    injected: Receiver<Event<P, IP>>,
+}
+
+type Link = (String, String);
+
+// a frame on its way, and which time it is the frame's gone along its link, while the run's
+// recording or replaying
+type OnLink<'a> = (&'a str, Option<u64>);
+
+type Invariant = Box<dyn Fn(&serde_json::Value, &serde_json::Value) -> anyhow::Result<()>>;
+
+type Boot<P, IP> =
+    Box<dyn Fn(Init, Sender<Event<P, IP>>) -> anyhow::Result<Box<dyn Running<P, IP>>>>;
+
+enum Happening<IP> {
+    Deliver {
+        link: Link,
+        frame: String,
+    },
+    Disrupt {
+        disruption: Disruption,
+        again: Option<Duration>,
+        until: Option<Duration>,
+    },
+    Release {
+        link: Link,
+    },
+    Faults {
+        link: Option<Link>,
+        faults: Faults,
+    },
+    Tick {
+        timer: usize,
+        every: Duration,
+        node: String,
+    },
+    Skew {
+        node: String,
+        skew: Skew,
+    },
+    Inject {
+        dst: String,
+        event: IP,
+    },
+    Serve {
+        service: String,
+        frame: String,
+    },
+    Request {
+        frame: String,
+    },
+}
+
+// a node, with whatever it was started from forgotten
+trait Running<P, IP> {
+    fn step(&mut self, event: Event<P, IP>, output: &mut Output) -> anyhow::Result<()>;
+    fn status(&self) -> serde_json::Value;
+    fn invariants(&self) -> anyhow::Result<()>;
+}
+
+struct Started<S, N> {
+    node: N,
+    state: PhantomData<fn() -> S>,
+}
+
+impl<S, P, IP, N: Node<S, P, IP>> Running<P, IP> for Started<S, N> {
+    fn step(&mut self, event: Event<P, IP>, output: &mut Output) -> anyhow::Result<()> {
+        self.node.step(event, output)
+    }
+
+    fn status(&self) -> serde_json::Value {
+        self.node.status()
+    }
+
+    fn invariants(&self) -> anyhow::Result<()> {
+        self.node.invariants()
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct Route {
+    src: String,
+    dest: String,
+}
+
+impl<P, IP> Sim<P, IP>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+{
+    /// A cluster of `node_ids`, none of them started yet, whose randomness all comes from
+    /// `seed`.
+    ///
+    /// The seed is behind every choice a run makes: latencies and faults, and so the order
+    /// messages arrive in, which node a nemesis picks, and, for nodes that draw from
+    /// [`rng::thread`] rather than their own generator, whatever the nodes choose. Start from
+    /// [`seed`] and a simulation that panics says which seed it had, for setting
+    /// `RUSTENGAN_SIM_SEED` to so the next run is the same one again.
+    pub fn new(seed: u64, node_ids: &[&str]) -> Self {
+        Self {
+            node_ids: node_ids.iter().map(|id| id.to_string()).collect(),
+            nodes: BTreeMap::new(),
+            clock: Duration::ZERO,
+            rng: StdRng::seed_from_u64(seed),
+            latency: LATENCY,
+            faults: Faults::default(),
+            links: BTreeMap::new(),
+            latencies: BTreeMap::new(),
+            pipes: BTreeMap::new(),
+            held: BTreeMap::new(),
+            tally: Tally::default(),
+            sent_on: BTreeMap::new(),
+            recording: None,
+            script: None,
+            components: BTreeMap::new(),
+            paused: BTreeMap::new(),
+            killed: BTreeSet::new(),
+            boots: BTreeMap::new(),
+            scheduled: BinaryHeap::new(),
+            happenings: BTreeMap::new(),
+            next: 0,
+            timers: Vec::new(),
+            clocks: BTreeMap::new(),
+            services: BTreeMap::new(),
+            replies: BTreeMap::new(),
+            sessions: BTreeMap::new(),
+            client_msg_id: 0,
+            trace: None,
+            invariants: Vec::new(),
+            seed,
+            data_dir: std::env::temp_dir().join(format!(
+                "rustengan-sim-{}-{}",
+                std::process::id(),
+                SIMULATIONS.fetch_add(1, Ordering::Relaxed)
+            )),
+        }
+    }
+
+    /// How long a message takes to arrive: anywhere in `latency`, picked at random for each
+    /// message, so messages between the same two parties can overtake each other. A link with a
+    /// latency of its own in its [`Profile`] keeps it.
+    pub fn latency(&mut self, latency: RangeInclusive<Duration>) -> &mut Self {
+        self.latency = latency;
+        self
+    }
+
+    /// What goes wrong on every link between two nodes that doesn't have faults of its own:
+    /// messages lost, held up, duplicated or reordered. [`Sim::link_faults`] sets them for one
+    /// link in particular, and [`Sim::faults_after`] changes them from a point in virtual time on.
+    pub fn faults(&mut self, faults: Faults) -> &mut Self {
+        self.faults = faults;
+        self
+    }
+
+    /// What goes wrong on the link from `src` to `dst` in particular, which can be a client's.
+    pub fn link_faults(&mut self, src: &str, dst: &str, faults: Faults) -> &mut Self {
+        self.links
+            .insert((src.to_string(), dst.to_string()), faults);
+        self
+    }
+
+    /// How the link from `src` to `dst` behaves, which can be a client's. Only what `profile`
+    /// sets changes, so a link can be given a latency of its own and keep its faults. With a
+    /// latency spread its own way and a cap on its bandwidth for each link, a topology can be
+    /// tried on a network that isn't the same everywhere.
+    pub fn link(&mut self, src: &str, dst: &str, profile: Profile) -> &mut Self {
+        let link = (src.to_string(), dst.to_string());
+        if let Some(latency) = profile.latency {
+            self.latencies.insert(link.clone(), latency);
+        }
+        if let Some(faults) = profile.faults {
+            self.links.insert(link.clone(), faults);
+        }
+        if let Some(bandwidth) = profile.bandwidth {
+            let pipe = Pipe {
+                bandwidth,
+                busy_until: self.clock,
+            };
+            self.pipes.insert(link, pipe);
+        }
+        self
+    }
+
+    /// Changes what goes wrong `after` from now: on the link from `src` to `dst` given `Some`,
+    /// and on every link between nodes without faults of its own given `None`, so a test can
+    /// script a link going bad and coming back.
+    pub fn faults_after(
+        &mut self,
+        after: Duration,
+        link: Option<(&str, &str)>,
+        faults: Faults,
+    ) -> &mut Self {
+        let link = link.map(|(src, dst)| (src.to_string(), dst.to_string()));
+        self.schedule(self.clock + after, Happening::Faults { link, faults });
+        self
+    }
+
+    /// Sets how far `node`'s clock is off, from now on.
+    ///
+    /// While a node steps, [`crate::clock`] reads its own clock, which starts at the same fixed
+    /// time on every node and keeps to virtual time unless it's skewed, so an HLC or a lease can
+    /// be tried against a clock that's ahead, behind or running fast.
+    pub fn skew(&mut self, node: &str, skew: Skew) -> &mut Self {
+        let now = self.clock;
+        self.clocks
+            .entry(node.to_string())
+            .or_default()
+            .skew(now, skew);
+        self
+    }
+
+    /// Changes how far `node`'s clock is off `after` from now, so a test can script a clock
+    /// jumping or starting to drift partway through.
+    pub fn skew_after(&mut self, after: Duration, node: &str, skew: Skew) -> &mut Self {
+        let node = node.to_string();
+        self.schedule(self.clock + after, Happening::Skew { node, skew });
+        self
+    }
+
+    /// What `node`'s clock reads now.
+    pub fn clock_of(&self, node: &str) -> clock::Reading {
+        self.clocks
+            .get(node)
+            .cloned()
+            .unwrap_or_default()
+            .reading(self.clock)
+    }
+
+    /// What's happened to the messages for nodes so far.
+    pub fn tally(&self) -> Tally {
+        self.tally
+    }
+
+    /// The nodes in the cluster, whether they're running or not.
+    pub fn node_ids(&self) -> &[String] {
+        &self.node_ids
+    }
+
+    /// The seed the simulation was started from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Writes every step a node takes from now on to `to`, as a [`Transition`] on a line of
+    /// JSON. A node's state in the trace is its [`Node::status`], so a node that doesn't say
+    /// anything about itself is in the same state throughout. That's what a run is compared
+    /// against a spec with.
+    pub fn trace(&mut self, to: impl Write + 'static) -> &mut Self {
+        self.trace = Some(Box::new(to));
+        self
+    }
+
+    /// Checks `holds` of every node's [`Node::status`] before and after every step it takes from
+    /// now on, for what no one step may do, like shrink a set that only grows or change an entry
+    /// that's been committed. A step that breaks it fails the run with `name` in the error.
+    ///
+    /// Each node's own [`Node::invariants`] are checked after every step whether or not it's
+    /// given any, and the first step to break one fails the run, saying which node it was, when,
+    /// and what it was stepping through.
+    pub fn invariant(
+        &mut self,
+        name: &str,
+        holds: impl Fn(&serde_json::Value, &serde_json::Value) -> anyhow::Result<()> + 'static,
+    ) -> &mut Self {
+        self.invariants.push((name.to_string(), Box::new(holds)));
+        self
+    }
+
+    /// Keeps a [`Schedule`] of the choices the run makes from now on, for [`Sim::recorded`]: what
+    /// clients asked, how the cluster was disrupted and what went wrong with which message. A run
+    /// that [`Sim::replay`]s one makes just those choices, so [`shrink::check`] can boil a failing
+    /// test's run down to the handful of choices that make it fail.
+    pub fn record_choices(&mut self) -> &mut Self {
+        self.recording.get_or_insert_with(Vec::new);
+        self
+    }
+
+    /// What the run's chosen since it started recording.
+    pub fn recorded(&self) -> Schedule {
+        Schedule {
+            choices: self.recording.clone().unwrap_or_default(),
+        }
+    }
+
+    /// Makes `schedule`'s choices, each at the time it was made, and no others: its requests are
+    /// sent and its disruptions made, and links fault the messages it says and only those,
+    /// whatever [`Sim::faults`] says. Called on a fresh simulation of the same nodes with the
+    /// same seed, once the nodes have started and before it runs, a schedule recorded from a run
+    /// replays that run.
+    pub fn replay(&mut self, schedule: &Schedule) {
+        let mut script: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for choice in &schedule.choices {
+            let at = choice.at().max(self.clock);
+            match choice {
+                Choice::Send { frame, .. } => {
+                    let frame = frame.clone();
+                    self.schedule(at, Happening::Request { frame });
+                }
+                Choice::Disrupt { disruption, .. } => {
+                    let disrupt = Happening::Disrupt {
+                        disruption: disruption.clone(),
+                        again: None,
+                        until: None,
+                    };
+                    self.schedule(at, disrupt);
+                }
+                Choice::Fault {
+                    link,
+                    frame,
+                    nth,
+                    fault,
+                    ..
+                } => script
+                    .entry((link.clone(), shrink::fingerprint(frame), *nth))
+                    .or_default()
+                    .push(fault.clone()),
+            }
+        }
+        self.script = Some(script);
+    }
+
+    /// Has `service` answer whatever nodes send to it by its name, as Maelstrom's own services
+    /// would. A request takes a latency to get there and another to get back, like any other
+    /// message, and whatever [`Service::delay`] says in between, but no faults: partitions and
+    /// lossy links are between nodes, and a service fails only as its own conduct has it.
+    pub fn service(&mut self, service: Service) -> &mut Self {
+        self.services.insert(service.name().to_string(), service);
+        self
+    }
+
+    /// Starts every node that isn't running yet as an `N`, from `state`.
+    pub fn start<S, N>(&mut self, state: S) -> anyhow::Result<()>
+    where
+        N: Node<S, P, IP> + 'static,
+        S: Clone + 'static,
+    {
+        for id in self.node_ids.clone() {
+            if !self.boots.contains_key(&id) {
+                self.start_node::<S, N>(&id, state.clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts `id` as an `N`, from `state`, so one cluster can run nodes of different kinds.
+    /// A node that's killed and restarted starts over from the same state.
+    pub fn start_node<S, N>(&mut self, id: &str, state: S) -> anyhow::Result<()>
+    where
+        N: Node<S, P, IP> + 'static,
+        S: Clone + 'static,
+    {
+        anyhow::ensure!(
+            self.node_ids.iter().any(|n| n == id),
+            "{} isn't one of the nodes",
+            id
+        );
+        let boot: Boot<P, IP> = Box::new(move |init, tx| {
+            let node = N::from_init(state.clone(), init, tx)?;
+            Ok(Box::new(Started {
+                node,
+                state: PhantomData,
+            }))
+        });
+        self.boots.insert(id.to_string(), boot);
+        self.boot(id)
+    }
+
+    fn boot(&mut self, id: &str) -> anyhow::Result<()> {
+        let init = Init {
+            node_id: id.to_string(),
+            node_ids: self.node_ids.clone(),
+        };
+        let (tx, injected) = std::sync::mpsc::channel();
+        clock::simulate(Some(self.clock_of(id)));
+        rng::simulate(Some(StdRng::seed_from_u64(self.rng.gen())));
+        wal::simulate(Some(self.data_dir.clone()));
+        let node = (self.boots[id])(init, tx);
+        wal::simulate(None);
+        let rng = rng::simulate(None);
+        clock::simulate(None);
+        let node = node.with_context(|| format!("start {}", id))?;
+        // what the node writes goes to the simulator, which works out where it's going and when
+        // it gets there once the step is done
+        let (network, sent) = Network::switched();
+        let output = Output::routed(Box::new(network.transport()));
+        self.nodes.insert(
+            id.to_string(),
+            Simulated {
+                node,
+                rng,
+                output,
+                sent,
+                injected,
+            },
+        );
+        Ok(())
+    }
+
+    /// Hands every node the event `tick` makes, every `every` by its own clock from now on.
+    ///
+    /// A node that sets its timers with [`crate::ticks::every`] leaves them to this. One that
+    /// keeps a timer thread of its own still runs, but what that thread injects arrives whenever
+    /// the thread gets to it, which no seed can repeat.
+    pub fn every(&mut self, every: Duration, tick: impl Fn() -> IP + 'static) {
+        self.timers.push(Box::new(tick));
+        let timer = self.timers.len() - 1;
+        for node in self.node_ids.clone() {
+            let at = self.clock + self.span(&node, every);
+            self.schedule(at, Happening::Tick { timer, every, node });
+        }
+    }
+
+    /// Lets `nemesis` loose on the cluster, its timeline starting now: partitioning it, and
+    /// pausing, killing and restarting nodes, as [`Sim::disrupt`] does.
+    pub fn nemesis(&mut self, nemesis: Nemesis) {
+        let until = nemesis.until.map(|after| self.clock + after);
+        for (after, disruption) in nemesis.timeline {
+            let at = self.clock + after;
+            if until.is_some_and(|until| at >= until) {
+                continue;
+            }
+            let again = nemesis.period;
+            let disrupt = Happening::Disrupt {
+                disruption,
+                again,
+                until,
+            };
+            self.schedule(at, disrupt);
+        }
+        if let Some(until) = until {
+            for disruption in [Disruption::Heal, Disruption::Resume, Disruption::Restart] {
+                let disrupt = Happening::Disrupt {
+                    disruption,
+                    again: None,
+                    until: None,
+                };
+                self.schedule(until, disrupt);
+            }
+        }
+    }
+
+    /// Partitions, heals, pauses, resumes, kills or restarts nodes, right away. A restarted node
+    /// finds whatever it wrote under [`wal::data_dir`] still there, in a directory the simulation
+    /// has to itself until it's dropped.
+    pub fn disrupt(&mut self, disruption: Disruption) -> anyhow::Result<()> {
+        log::debug!("{:?}: {:?}", self.clock, disruption);
+        match disruption {
+            Disruption::Partition(split) => {
+                let components = match split {
+                    Split::Halves => {
+                        let mut nodes = self.node_ids.clone();
+                        nodes.shuffle(&mut self.rng);
+                        let half = nodes.len() / 2;
+                        vec![nodes[..half].to_vec(), nodes[half..].to_vec()]
+                    }
+                    Split::Isolate => {
+                        let alone = self.node_ids.choose(&mut self.rng).cloned();
+                        vec![alone.into_iter().collect()]
+                    }
+                    Split::Components(components) => components,
+                };
+                let split = Split::Components(components.clone());
+                self.chose(Disruption::Partition(split));
+                self.components.clear();
+                for (component, nodes) in components.iter().enumerate() {
+                    for node in nodes {
+                        self.components.insert(node.clone(), component + 1);
+                    }
+                }
+                // whoever isn't in a component is in the one left over
+                for node in &self.node_ids {
+                    self.components.entry(node.clone()).or_insert(0);
+                }
+            }
+            Disruption::Heal => {
+                self.chose(Disruption::Heal);
+                self.components.clear();
+            }
+            Disruption::Pause(target) => {
+                let running = self.running().filter(|id| !self.paused.contains_key(id));
+                if let Some(id) = self.target(target, running.collect()) {
+                    self.chose(Disruption::Pause(Target::Node(id.clone())));
+                    self.paused.insert(id, Vec::new());
+                }
+            }
+            Disruption::Resume => {
+                self.chose(Disruption::Resume);
+                for happening in std::mem::take(&mut self.paused).into_values().flatten() {
+                    self.schedule(self.clock, happening);
+                }
+            }
+            Disruption::Kill(target) => {
+                let running = self.running().collect();
+                if let Some(id) = self.target(target, running) {
+                    self.chose(Disruption::Kill(Target::Node(id.clone())));
+                    self.nodes.remove(&id);
+                    self.paused.remove(&id);
+                    self.killed.insert(id);
+                }
+            }
+            Disruption::Restart => {
+                self.chose(Disruption::Restart);
+                for id in std::mem::take(&mut self.killed) {
+                    self.boot(&id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn running(&self) -> impl Iterator<Item = String> + '_ {
+        self.nodes.keys().cloned()
+    }
+
+    fn target(&mut self, target: Target, candidates: Vec<String>) -> Option<String> {
+        match target {
+            Target::Node(id) => candidates.contains(&id).then_some(id),
+            Target::Random => candidates.choose(&mut self.rng).cloned(),
+        }
+    }
+
+    /// Hands `dst` an injected event, `after` from now.
+    pub fn inject(&mut self, dst: &str, after: Duration, event: IP) {
+        let dst = dst.to_string();
+        self.schedule(self.clock + after, Happening::Inject { dst, event });
+    }
+
+    /// Sends `payload` from the client `src` to `dst`, as a request with a msg_id of its own,
+    /// which it returns.
+    pub fn send(&mut self, src: &str, dst: &str, payload: P) -> anyhow::Result<usize>
+    where
+        P: Serialize,
+    {
+        self.client_msg_id += 1;
+        let message = Message {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            body: Body {
+                id: Some(self.client_msg_id),
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        };
+        let frame = serde_json::to_string(&message).context("serialize request")?;
+        if let Some(recording) = &mut self.recording {
+            recording.push(Choice::Send {
+                at: self.clock,
+                src: src.to_string(),
+                dst: dst.to_string(),
+                frame: frame.clone(),
+            });
+        }
+        self.route(frame)?;
+        Ok(self.client_msg_id)
+    }
+
+    /// The virtual time since the simulation started.
+    pub fn now(&self) -> Duration {
+        self.clock
+    }
+
+    /// Handles whatever falls due next, moving the clock up to it. False if nothing's left to
+    /// happen.
+    pub fn step(&mut self) -> anyhow::Result<bool> {
+        let Some(Reverse((at, id))) = self.scheduled.pop() else {
+            return Ok(false);
+        };
+        self.clock = at;
+        let happening = self.happenings.remove(&id).expect("scheduled");
+        match happening {
+            Happening::Deliver { link, frame } => {
+                if self.apart(&link) {
+                    self.tally.partitioned += 1;
+                } else if let Some(waiting) = self.paused.get_mut(&link.1) {
+                    waiting.push(Happening::Deliver { link, frame });
+                } else {
+                    let message = serde_json::from_str(&frame)
+                        .with_context(|| format!("{} can't make sense of {}", link.1, frame))?;
+                    let cause = Cause::Message {
+                        message: serde_json::from_str(&frame).expect("parsed once already"),
+                    };
+                    self.handle(&link.1, Event::Message(message), cause)?;
+                }
+            }
+            Happening::Tick { timer, every, node } => {
+                if !self.paused.contains_key(&node) {
+                    let event = Event::Injected((self.timers[timer])());
+                    self.handle(&node, event, Cause::Tick { timer })?;
+                }
+                let next = at + self.span(&node, every);
+                self.schedule(next, Happening::Tick { timer, every, node });
+            }
+            Happening::Skew { node, skew } => {
+                self.skew(&node, skew);
+            }
+            Happening::Inject { dst, event } => match self.paused.get_mut(&dst) {
+                Some(waiting) => waiting.push(Happening::Inject { dst, event }),
+                None => self.handle(&dst, Event::Injected(event), Cause::Inject)?,
+            },
+            Happening::Disrupt {
+                disruption,
+                again,
+                until,
+            } => {
+                if let Some(period) = again {
+                    if until.is_none_or(|until| at + period < until) {
+                        let disrupt = Happening::Disrupt {
+                            disruption: disruption.clone(),
+                            again,
+                            until,
+                        };
+                        self.schedule(at + period, disrupt);
+                    }
+                }
+                self.disrupt(disruption)?;
+            }
+            Happening::Release { link } => {
+                for frame in self.held.remove(&link).into_iter().flatten() {
+                    let link = link.clone();
+                    self.schedule(at, Happening::Deliver { link, frame });
+                }
+            }
+            Happening::Faults {
+                link: Some(link),
+                faults,
+            } => {
+                self.links.insert(link, faults);
+            }
+            Happening::Faults { link: None, faults } => self.faults = faults,
+            Happening::Serve { service, frame } => {
+                let request = serde_json::from_str(&frame)
+                    .with_context(|| format!("{} can't make sense of {}", service, frame))?;
+                let service = self.services.get_mut(&service).expect("served");
+                if let Some(reply) = service.handle(&request, self.clock, &mut self.rng) {
+                    self.route(serde_json::to_string(&reply).context("serialize reply")?)?;
+                }
+            }
+            Happening::Request { frame } => self.route(frame)?,
+        }
+        Ok(true)
+    }
+
+    /// Runs for `how_long` of virtual time, or until nothing's left to happen.
+    pub fn run_for(&mut self, how_long: Duration) -> anyhow::Result<()> {
+        let until = self.clock + how_long;
+        while self
+            .scheduled
+            .peek()
+            .is_some_and(|Reverse((at, _))| *at <= until)
+        {
+            self.step()?;
+        }
+        self.clock = until;
+        Ok(())
+    }
+
+    /// Runs until nothing's left to happen. With a timer going, that's never.
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    /// Everything sent to the client `dst` so far, in the order it arrived.
+    pub fn replies(&self, dst: &str) -> anyhow::Result<Vec<Message<P>>> {
+        self.replies
+            .get(dst)
+            .into_iter()
+            .flatten()
+            .map(|frame| serde_json::from_str(frame).with_context(|| format!("reply to {}", dst)))
+            .collect()
+    }
+
+    /// What's been sent to the client `dst` since the last time it was taken, for a run too
+    /// long to keep everything in.
+    pub fn take_replies(&mut self, dst: &str) -> anyhow::Result<Vec<Message<P>>> {
+        let replies = self.replies.remove(dst).unwrap_or_default();
+        replies
+            .iter()
+            .map(|frame| serde_json::from_str(frame).with_context(|| format!("reply to {}", dst)))
+            .collect()
+    }
+
+    /// The Jepsen history of the reads, writes and cas operations clients have made so far, timed
+    /// by the virtual clock, the same as [`history::from_sessions`] would make of the nodes'
+    /// session logs. It's ready for [`history::linearizable::check`].
+    pub fn history(&self) -> Vec<history::Op> {
+        history::from_sessions(&self.sessions())
+    }
+
+    /// What each node heard from clients and said to them so far, by node, as its session log
+    /// would have it, for [`history::workload`]'s checkers.
+    pub fn sessions(&self) -> Vec<(String, Vec<Record>)> {
+        self.node_ids
+            .iter()
+            .map(|id| {
+                (
+                    id.clone(),
+                    self.sessions.get(id).cloned().unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    // steps `dst` through `event`, and whatever it injects into itself meanwhile, checking the
+    // invariants after each step, and sends whatever it wrote. `cause` is what to trace the event
+    // as, and what to say it was stepping through if it breaks one.
+    fn handle(&mut self, dst: &str, event: Event<P, IP>, cause: Cause) -> anyhow::Result<()> {
+        let reading = self.clock_of(dst);
+        let Some(node) = self.nodes.get_mut(dst) else {
+            log::debug!("{} isn't running, dropping an event for it", dst);
+            return Ok(());
+        };
+        clock::simulate(Some(reading));
+        rng::simulate(node.rng.take());
+        wal::simulate(Some(self.data_dir.clone()));
+        let mut event = Some((event, cause));
+        let mut stepped = Ok(());
+        let watched = self.trace.is_some() || !self.invariants.is_empty();
+        while let Some((input, cause)) = event.take().or_else(|| {
+            let injected = node.injected.try_recv().ok()?;
+            Some((injected, Cause::Injected))
+        }) {
+            let before = watched.then(|| node.node.status());
+            stepped = node.node.step(input, &mut node.output);
+            if stepped.is_err() {
+                break;
+            }
+            let after = watched.then(|| node.node.status());
+            stepped = node.node.invariants().context("broke its own invariants");
+            if let (Ok(()), Some(before), Some(after)) = (&stepped, &before, &after) {
+                for (name, holds) in &self.invariants {
+                    if let Err(e) = holds(before, after) {
+                        stepped = Err(e.context(format!("broke {}", name)));
+                        break;
+                    }
+                }
+            }
+            if let Err(e) = stepped {
+                let cause = serde_json::to_string(&cause).expect("causes serialize");
+                stepped = Err(e.context(format!("stepping through {}", cause)));
+                break;
+            }
+            if let (Some(trace), Some(before), Some(state)) = (&mut self.trace, before, after) {
+                let transition = Transition {
+                    node: dst.to_string(),
+                    at_us: self.clock.as_micros() as u64,
+                    before: trace::fingerprint(&before),
+                    event: cause,
+                    after: trace::fingerprint(&state),
+                    state,
+                };
+                serde_json::to_writer(&mut *trace, &transition)?;
+                trace.write_all(b"\n").context("writing the trace")?;
+            }
+        }
+        wal::simulate(None);
+        clock::simulate(None);
+        node.rng = rng::simulate(None);
+        stepped.with_context(|| format!("{} failed a step at {:?}", dst, self.clock))?;
+        let sent: Vec<_> = node.sent.try_iter().map(|(_, frame)| frame).collect();
+        for frame in sent {
+            self.route(frame)?;
+        }
+        Ok(())
+    }
+
+    fn route(&mut self, frame: String) -> anyhow::Result<()> {
+        let Route { src, dest } = serde_json::from_str(&frame).context("frame has no route")?;
+        if let Some(service) = self.services.get(&dest) {
+            let delay = service.delay(&mut self.rng);
+            self.record(&src, Direction::Sent, &frame)?;
+            let at = self.clock + self.rng.gen_range(self.latency.clone()) + delay;
+            self.schedule(
+                at,
+                Happening::Serve {
+                    service: dest,
+                    frame,
+                },
+            );
+            return Ok(());
+        }
+        if !self.node_ids.contains(&dest) {
+            self.record(&src, Direction::Sent, &frame)?;
+            self.replies.entry(dest).or_default().push(frame);
+            return Ok(());
+        }
+        if !self.node_ids.contains(&src) {
+            self.record(&dest, Direction::Received, &frame)?;
+        }
+        let link = (src, dest);
+        let faults = match self.links.get(&link) {
+            Some(faults) => faults.clone(),
+            None if self.node_ids.contains(&link.0) => self.faults.clone(),
+            None => Faults::default(),
+        };
+        self.tally.sent += 1;
+        let nth = if self.recording.is_some() || self.script.is_some() {
+            let key = (link.clone(), shrink::fingerprint(&frame));
+            let sent_on = self.sent_on.entry(key).or_default();
+            *sent_on += 1;
+            Some(*sent_on - 1)
+        } else {
+            None
+        };
+        let sent: OnLink<'_> = (&frame, nth);
+        if self.fault(&link, sent, Fault::Drop, faults.drop) {
+            self.tally.dropped += 1;
+            return Ok(());
+        }
+        let copies = if self.fault(&link, sent, Fault::Duplicate, faults.duplicate) {
+            self.tally.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let sent_at = match self.pipes.get_mut(&link) {
+                Some(pipe) => pipe.transmit(self.clock, frame.len()),
+                None => self.clock,
+            };
+            let mut at = sent_at + self.latency_of(&link);
+            if let Some(by) = self.delay(&link, sent, &faults) {
+                self.tally.delayed += 1;
+                at += by;
+            }
+            if self.fault(&link, sent, Fault::Reorder, faults.reorder) {
+                self.tally.reordered += 1;
+                self.held
+                    .entry(link.clone())
+                    .or_default()
+                    .push(frame.clone());
+                // in case nothing else comes along the link to overtake it
+                let late = sent_at + self.longest(&link) * 2;
+                self.schedule(late, Happening::Release { link: link.clone() });
+                continue;
+            }
+            self.schedule(
+                at,
+                Happening::Deliver {
+                    link: link.clone(),
+                    frame: frame.clone(),
+                },
+            );
+            for frame in self.held.remove(&link).into_iter().flatten() {
+                // right behind the message that overtook it
+                let link = link.clone();
+                self.schedule(
+                    at + Duration::from_nanos(1),
+                    Happening::Deliver { link, frame },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, node: &str, direction: Direction, frame: &str) -> anyhow::Result<()> {
+        let record = Record {
+            time: self.clock.as_nanos() as u64,
+            direction,
+            message: serde_json::from_str(frame).context("frame isn't json")?,
+        };
+        self.sessions
+            .entry(node.to_string())
+            .or_default()
+            .push(record);
+        Ok(())
+    }
+
+    // how long the next message on `link` takes
+    fn latency_of(&mut self, link: &Link) -> Duration {
+        match self.latencies.get(link) {
+            Some(latency) => latency.pick(&mut self.rng),
+            None => self.rng.gen_range(self.latency.clone()),
+        }
+    }
+
+    // the longest a message on `link` can take
+    fn longest(&self, link: &Link) -> Duration {
+        match self.latencies.get(link) {
+            Some(latency) => latency.longest(),
+            None => *self.latency.end(),
+        }
+    }
+
+    // how long `local` of `node`'s own time takes in virtual time
+    fn span(&self, node: &str, local: Duration) -> Duration {
+        match self.clocks.get(node) {
+            Some(clock) => clock.span(local),
+            None => local,
+        }
+    }
+
+    // whether the partition keeps `link` from carrying anything. Clients are never cut off.
+    fn apart(&self, (src, dst): &Link) -> bool {
+        match (self.components.get(src), self.components.get(dst)) {
+            (Some(src), Some(dst)) => src != dst,
+            _ => false,
+        }
+    }
+
+    // whether `frame`, on its way along `link` for the `nth` time, has `fault`: the script
+    // says, when there is one, and otherwise it has a chance of `p`, and the recording says it did
+    fn fault(&mut self, link: &Link, (frame, nth): OnLink<'_>, fault: Fault, p: f64) -> bool {
+        if let (Some(script), Some(nth)) = (&self.script, nth) {
+            let key = (link.clone(), shrink::fingerprint(frame), nth);
+            return script
+                .get(&key)
+                .is_some_and(|faults| faults.contains(&fault));
+        }
+        let happens = self.chance(p);
+        if happens {
+            self.chose_fault(link, (frame, nth), fault);
+        }
+        happens
+    }
+
+    // how long `frame` is held up for, if it is, the same way as `fault`
+    fn delay(
+        &mut self,
+        link: &Link,
+        (frame, nth): OnLink<'_>,
+        faults: &Faults,
+    ) -> Option<Duration> {
+        if let (Some(script), Some(nth)) = (&self.script, nth) {
+            let key = (link.clone(), shrink::fingerprint(frame), nth);
+            return script.get(&key)?.iter().find_map(|fault| match fault {
+                Fault::Delay(by) => Some(*by),
+                _ => None,
+            });
+        }
+        if !self.chance(faults.delay) {
+            return None;
+        }
+        let by = self.rng.gen_range(Duration::ZERO..=faults.delay_by);
+        self.chose_fault(link, (frame, nth), Fault::Delay(by));
+        Some(by)
+    }
+
+    fn chose_fault(&mut self, link: &Link, (frame, nth): OnLink<'_>, fault: Fault) {
+        if let (Some(recording), Some(nth)) = (&mut self.recording, nth) {
+            recording.push(Choice::Fault {
+                at: self.clock,
+                link: link.clone(),
+                frame: frame.to_string(),
+                nth,
+                fault,
+            });
+        }
+    }
+
+    fn chose(&mut self, disruption: Disruption) {
+        if let Some(recording) = &mut self.recording {
+            recording.push(Choice::Disrupt {
+                at: self.clock,
+                disruption,
+            });
+        }
+    }
+
+    // whether something with a chance of `p` happens. Nothing is drawn for what can't happen, so
+    // a run without faults goes the same as it would have before there were any to pick.
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && self.rng.gen::<f64>() < p
+    }
+
+    fn schedule(&mut self, at: Duration, happening: Happening<IP>) {
+        let id = self.next;
+        self.next += 1;
+        self.scheduled.push(Reverse((at, id)));
+        self.happenings.insert(id, happening);
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Context;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::skew::EPOCH;
+use super::Route;
+use crate::transport::channel::Network;
+use crate::{clock, rng, Body, Event, Init, Message, Node, Output};
+
+// how many states an exploration gets through before giving up on running out of them
+const MAX_STATES: usize = 1_000_000;
+
+/// Every way a small cluster's run can go, within a bound: every order the messages in flight
+/// can be delivered in, every point a node's timer can fire at, and, if it's given any, at most
+/// one partition, coming up at any point and healing at any later one. Where the
+/// [`Sim`](super::Sim) samples a run per seed, this goes through all of them, so a property
+/// it says holds holds in every run there is at this scale, not just the ones tried.
+///
+/// Ticks are what make runs go on for ever, so a node's timer only fires while fewer than
+/// [`in_flight`](Explorer::in_flight) messages are on their way, and since a message is
+/// delivered once, and states that differ only in the order their messages were sent in are
+/// the same state, that's enough to leave a finite number of states to go through. A property
+/// the cluster should come to, like everyone having every message, is checked as one it can
+/// still come to from every state it can get into: with no state it can get stuck in, a run
+/// that keeps delivering messages and firing timers gets there.
+///
+/// Nodes are ordinary [`Node`]s, handed the same init as under Maelstrom, and have to be
+/// values that can be cloned, compared and hashed, to tell the states that have been seen from
+/// the ones that haven't. They're stepped on a stopped clock, and drawing from
+/// [`rng::thread`] the same numbers every step, so a step always goes the same way from the
+/// same state. Their invariants are checked after every step.
+pub struct Explorer<S, N, P, IP = ()> {
+    state: S,
+    node_ids: Vec<String>,
+    clients: Vec<Vec<(String, P)>>,
+    tick: Option<fn() -> IP>,
+    partitions: Vec<Vec<Vec<String>>>,
+    in_flight: usize,
+    always: Vec<Property<N>>,
+    converges: Vec<Property<N>>,
+    _node: PhantomData<fn() -> N>,
+}
+
+// a property's name, and whether it holds in a state
+type Property<N> = (&'static str, fn(&Reached<N>) -> bool);
+
+/// A state the cluster got into: each node's state, the replies each client's had, and what's
+/// still on its way.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Reached<N> {
+    nodes: Vec<N>,
+    replies: Vec<BTreeSet<String>>,
+    // the frames on their way, kept sorted, since which was sent first makes no difference to
+    // which can be delivered next
+    in_flight: Vec<String>,
+    partition: Partition,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Partition {
+    Before,
+    Up(usize),
+    Healed,
+}
+
+// what happens to get from one state to the next
+#[derive(Debug, Clone, Copy)]
+enum Move {
+    // the frame at this index of what's in flight
+    Deliver(usize),
+    Tick(usize),
+    Split(usize),
+    Heal,
+}
+
+/// How much there was to go through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explored {
+    pub states: usize,
+    pub moves: usize,
+}
+
+impl<N> Reached<N> {
+    /// The nodes' states, in the order they were named.
+    pub fn nodes(&self) -> &[N] {
+        &self.nodes
+    }
+
+    /// The replies the `client`th client has had, as the frames they came in.
+    pub fn replies(&self, client: usize) -> &BTreeSet<String> {
+        &self.replies[client]
+    }
+
+    /// How many messages are on their way.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Whether the partition's up.
+    pub fn partitioned(&self) -> bool {
+        matches!(self.partition, Partition::Up(_))
+    }
+}
+
+impl<S, N, P, IP> Explorer<S, N, P, IP>
+where
+    S: Clone,
+    N: Node<S, P, IP> + Clone + Eq + Hash,
+    P: Serialize + DeserializeOwned + Clone,
+{
+    /// A cluster of `node_ids`, each started from `state`, with no clients yet, whose timers
+    /// hold off while four messages are in flight.
+    pub fn new(node_ids: &[&str], state: S) -> Self {
+        Self {
+            state,
+            node_ids: node_ids.iter().map(|id| id.to_string()).collect(),
+            clients: Vec::new(),
+            tick: None,
+            partitions: Vec::new(),
+            in_flight: 4,
+            always: Vec::new(),
+            converges: Vec::new(),
+            _node: PhantomData,
+        }
+    }
+
+    /// Adds a client, named `c0`, `c1` and so on in the order they're added, whose `requests`
+    /// are all on their way to the nodes they name as the cluster starts.
+    pub fn client<'a>(mut self, requests: impl IntoIterator<Item = (&'a str, P)>) -> Self {
+        let requests = requests
+            .into_iter()
+            .map(|(dst, payload)| (dst.to_string(), payload))
+            .collect();
+        self.clients.push(requests);
+        self
+    }
+
+    /// Hands a node the event `tick` makes whenever its timer fires.
+    pub fn every(mut self, tick: fn() -> IP) -> Self {
+        self.tick = Some(tick);
+        self
+    }
+
+    /// A partition the cluster might go through, splitting it into `components`, between
+    /// which messages are lost while it's up, as Maelstrom loses them: one that arrives while
+    /// it's up is gone, whenever it was sent. A node left out of every component is cut off from
+    /// no one, and so are clients. Given more than one, a run goes through one of them, or none.
+    pub fn partition(mut self, components: &[&[&str]]) -> Self {
+        let components = components
+            .iter()
+            .map(|component| component.iter().map(|id| id.to_string()).collect())
+            .collect();
+        self.partitions.push(components);
+        self
+    }
+
+    /// How many messages in flight hold the nodes' timers off. The more there are, the more of
+    /// the ways gossip can pile up and overtake itself get explored, and the more states there
+    /// are to explore.
+    pub fn in_flight(mut self, in_flight: usize) -> Self {
+        self.in_flight = in_flight;
+        self
+    }
+
+    /// A property that holds in every state the cluster can get into.
+    pub fn always(mut self, name: &'static str, holds: fn(&Reached<N>) -> bool) -> Self {
+        self.always.push((name, holds));
+        self
+    }
+
+    /// A property the cluster can still come to from every state it can get into, however
+    /// things have gone so far.
+    pub fn converges(mut self, name: &'static str, holds: fn(&Reached<N>) -> bool) -> Self {
+        self.converges.push((name, holds));
+        self
+    }
+
+    /// Goes through every state the cluster can get into, failing with the shortest run to the
+    /// first one with a property broken, or a node failing in it.
+    pub fn explore(&self) -> anyhow::Result<Explored> {
+        let start = self.start()?;
+        let mut states = vec![start.clone()];
+        let mut seen = HashMap::from([(start, 0)]);
+        // how each state was first got to, for saying how to get there again
+        let mut parents: Vec<Option<(usize, Move)>> = vec![None];
+        // the states each state can be got to from
+        let mut before: Vec<Vec<usize>> = vec![Vec::new()];
+        let mut moves = 0;
+        let mut queue = VecDeque::from([0]);
+        while let Some(at) = queue.pop_front() {
+            let state = states[at].clone();
+            if let Some((name, _)) = self.always.iter().find(|(_, holds)| !holds(&state)) {
+                anyhow::bail!(
+                    "{} doesn't hold after:\n{}",
+                    name,
+                    self.run(&states, &parents, at)
+                );
+            }
+            for m in self.moves(&state) {
+                let next = self.apply(&state, m).with_context(|| {
+                    format!(
+                        "{}{}",
+                        self.run(&states, &parents, at),
+                        self.describe(&state, m)
+                    )
+                })?;
+                moves += 1;
+                let to = match seen.get(&next) {
+                    Some(&to) => to,
+                    None => {
+                        anyhow::ensure!(
+                            states.len() < MAX_STATES,
+                            "more than {} states, and still going: hold timers off sooner",
+                            MAX_STATES
+                        );
+                        let to = states.len();
+                        seen.insert(next.clone(), to);
+                        states.push(next);
+                        parents.push(Some((at, m)));
+                        before.push(Vec::new());
+                        queue.push_back(to);
+                        to
+                    }
+                };
+                before[to].push(at);
+            }
+        }
+        for (name, holds) in &self.converges {
+            // back from every state it holds in, to every state that can get to one
+            let mut can = vec![false; states.len()];
+            let mut back: VecDeque<usize> =
+                (0..states.len()).filter(|&at| holds(&states[at])).collect();
+            for &at in &back {
+                can[at] = true;
+            }
+            while let Some(at) = back.pop_front() {
+                for &from in &before[at] {
+                    if !can[from] {
+                        can[from] = true;
+                        back.push_back(from);
+                    }
+                }
+            }
+            if let Some(stuck) = can.iter().position(|can| !can) {
+                anyhow::bail!(
+                    "{} can't come about after:\n{}",
+                    name,
+                    self.run(&states, &parents, stuck)
+                );
+            }
+        }
+        Ok(Explored {
+            states: states.len(),
+            moves,
+        })
+    }
+
+    // the cluster started, with the clients' requests on their way
+    fn start(&self) -> anyhow::Result<Reached<N>> {
+        let nodes = self
+            .node_ids
+            .iter()
+            .map(|id| {
+                let init = Init {
+                    node_id: id.clone(),
+                    node_ids: self.node_ids.clone(),
+                };
+                // nothing's listening: what a node injects into itself isn't explored
+                let (inject, _) = mpsc::channel();
+                stopped(|| N::from_init(self.state.clone(), init, inject))
+                    .with_context(|| format!("start {}", id))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let mut in_flight = Vec::new();
+        for (client, requests) in self.clients.iter().enumerate() {
+            for (msg_id, (dst, payload)) in requests.iter().enumerate() {
+                let request = Message {
+                    src: format!("c{}", client),
+                    dst: dst.clone(),
+                    body: Body {
+                        id: Some(msg_id + 1),
+                        in_reply_to: None,
+                        correlation_id: None,
+                        payload: payload.clone(),
+                    },
+                };
+                in_flight.push(serde_json::to_string(&request).context("request serializes")?);
+            }
+        }
+        in_flight.sort();
+        Ok(Reached {
+            nodes,
+            replies: vec![BTreeSet::new(); self.clients.len()],
+            in_flight,
+            partition: Partition::Before,
+        })
+    }
+
+    fn moves(&self, state: &Reached<N>) -> Vec<Move> {
+        // the same frame twice over goes the same way whichever copy's delivered
+        let mut moves: Vec<Move> = (0..state.in_flight.len())
+            .filter(|&i| i == 0 || state.in_flight[i - 1] != state.in_flight[i])
+            .map(Move::Deliver)
+            .collect();
+        if self.tick.is_some() && state.in_flight.len() < self.in_flight {
+            moves.extend((0..state.nodes.len()).map(Move::Tick));
+        }
+        match state.partition {
+            Partition::Before => moves.extend((0..self.partitions.len()).map(Move::Split)),
+            Partition::Up(_) => moves.push(Move::Heal),
+            Partition::Healed => {}
+        }
+        moves
+    }
+
+    fn apply(&self, state: &Reached<N>, m: Move) -> anyhow::Result<Reached<N>> {
+        let mut next = state.clone();
+        match m {
+            Move::Deliver(i) => {
+                let frame = next.in_flight.remove(i);
+                let Route { src, dest } =
+                    serde_json::from_str(&frame).context("frame has no route")?;
+                if self.cut(state.partition, &src, &dest) {
+                    return Ok(next);
+                }
+                if let Some(node) = self.node_ids.iter().position(|id| *id == dest) {
+                    let message: Message<P> =
+                        serde_json::from_str(&frame).context("frame doesn't parse")?;
+                    let sent = step(&mut next.nodes[node], Event::Message(message))?;
+                    next.in_flight.extend(sent);
+                } else if let Some(client) = dest
+                    .strip_prefix('c')
+                    .and_then(|client| client.parse::<usize>().ok())
+                    .filter(|&client| client < next.replies.len())
+                {
+                    next.replies[client].insert(frame);
+                } else {
+                    log::debug!("dropping a message for {}, who isn't explored", dest);
+                }
+            }
+            Move::Tick(node) => {
+                let tick = self.tick.expect("only ticks with a timer");
+                let sent = step(&mut next.nodes[node], Event::Injected(tick()))?;
+                next.in_flight.extend(sent);
+            }
+            Move::Split(partition) => next.partition = Partition::Up(partition),
+            Move::Heal => next.partition = Partition::Healed,
+        }
+        next.in_flight.sort();
+        Ok(next)
+    }
+
+    // whether the partition, if it's up, keeps `src` and `dst` apart
+    fn cut(&self, partition: Partition, src: &str, dst: &str) -> bool {
+        let Partition::Up(partition) = partition else {
+            return false;
+        };
+        let components = &self.partitions[partition];
+        let side = |id: &str| components.iter().position(|c| c.iter().any(|n| n == id));
+        matches!((side(src), side(dst)), (Some(a), Some(b)) if a != b)
+    }
+
+    // the moves from the start to the `at`th state, one to a line
+    fn run(&self, states: &[Reached<N>], parents: &[Option<(usize, Move)>], at: usize) -> String {
+        let mut path = Vec::new();
+        let mut at = at;
+        while let Some((from, m)) = parents[at] {
+            path.push(self.describe(&states[from], m));
+            at = from;
+        }
+        path.reverse();
+        path.concat()
+    }
+
+    fn describe(&self, state: &Reached<N>, m: Move) -> String {
+        match m {
+            Move::Deliver(i) => {
+                let frame = &state.in_flight[i];
+                let lost = serde_json::from_str(frame)
+                    .is_ok_and(|Route { src, dest }| self.cut(state.partition, &src, &dest));
+                match lost {
+                    true => format!("  lost to the partition: {}\n", frame),
+                    false => format!("  {}\n", frame),
+                }
+            }
+            Move::Tick(node) => format!("  {} ticks\n", self.node_ids[node]),
+            Move::Split(partition) => format!("  partition {:?}\n", self.partitions[partition]),
+            Move::Heal => "  heal\n".to_string(),
+        }
+    }
+}
+
+// runs `f` on a stopped clock, drawing the same numbers from `rng::thread` every time
+fn stopped<T>(f: impl FnOnce() -> T) -> T {
+    clock::simulate(Some(clock::Reading {
+        monotonic: Duration::ZERO,
+        wall: EPOCH,
+    }));
+    rng::simulate(Some(StdRng::seed_from_u64(0)));
+    let done = f();
+    rng::simulate(None);
+    clock::simulate(None);
+    done
+}
+
+// steps `node` with `event`, checks it still holds to its invariants, and hands back what it sent
+fn step<S, N, P, IP>(node: &mut N, event: Event<P, IP>) -> anyhow::Result<Vec<String>>
+where
+    N: Node<S, P, IP>,
+{
+    let (network, sent) = Network::switched();
+    let mut output = Output::routed(Box::new(network.transport()));
+    stopped(|| node.step(event, &mut output))?;
+    node.invariants().context("broke its own invariants")?;
+    Ok(sent.try_iter().map(|(_, frame)| frame).collect())
+}
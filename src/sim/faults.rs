@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// What can go wrong with the messages on a link, as the chance of each fault happening to any
+/// one message, from 0 (never, the default) to 1 (every time). Faults are picked one message at
+/// a time by the simulation's random number generator, so a seed that lost a message once loses
+/// it every time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    /// The chance a message is lost.
+    pub drop: f64,
+    /// The chance a message arrives twice, each copy in its own time.
+    pub duplicate: f64,
+    /// The chance a message is held up, by up to `delay_by` on top of its latency.
+    pub delay: f64,
+    pub delay_by: Duration,
+    /// The chance a message is held back until the next message on the same link has arrived,
+    /// so the two arrive the wrong way round. One with nothing behind it arrives late instead.
+    pub reorder: f64,
+}
+
+impl Faults {
+    /// A link that loses every message, as if it were cut.
+    pub fn cut() -> Self {
+        Self {
+            drop: 1.0,
+            ..Self::default()
+        }
+    }
+}
+
+/// How many messages for nodes the simulation has carried, and what's happened to them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    pub sent: u64,
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub delayed: u64,
+    pub reordered: u64,
+    /// Lost to a partition between the sender and the receiver.
+    pub partitioned: u64,
+}
@@ -0,0 +1,274 @@
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use serde::{de::DeserializeOwned, Serialize};
+use stateright::actor::{model_timeout, Actor, ActorModel, Id, Network, Out};
+
+use super::skew::EPOCH;
+use crate::transport::channel;
+use crate::{clock, Body, Event, Init, Message, Node, Output};
+
+/// A small cluster of nodes, and the clients making requests of it, for [stateright] to check
+/// exhaustively: where [`Sim`](super::Sim) takes one path through a run per seed, the model
+/// checker takes every path, delivering whichever message is in flight in every order there is,
+/// and firing every timer at every point it could fire, until it's seen every state the cluster
+/// can get into or found one that breaks a property.
+///
+/// Nodes are ordinary [`Node`]s, handed the same init as under Maelstrom, and messages go
+/// between them as JSON frames. To be checked, a node has to be a value stateright can clone,
+/// compare and hash, since that's how it tells states it's explored from ones it hasn't, and it
+/// has to be deterministic: its clock is stopped while it steps, and anything it injects into
+/// itself from a thread of its own is dropped, so [`every`](Model::every) is the way to give it
+/// timers. A node failing to start or step panics, which the checker reports as it would any
+/// other panic.
+///
+/// Keep clusters small: the number of states grows exponentially in the number of messages
+/// that can be in flight at once.
+pub struct Model<S, N, P, IP = ()> {
+    state: S,
+    node_ids: Vec<String>,
+    clients: Vec<Vec<(String, P)>>,
+    tick: Option<fn() -> IP>,
+    _node: PhantomData<fn() -> N>,
+}
+
+/// A node or client of a [`Model`] as stateright sees it.
+pub struct Participant<S, N, P, IP = ()> {
+    role: Role<S, P, IP>,
+    directory: Arc<Directory>,
+    _node: PhantomData<fn() -> N>,
+}
+
+// everyone in the model, nodes first, in the order of their stateright ids
+struct Directory {
+    names: Vec<String>,
+    nodes: usize,
+}
+
+enum Role<S, P, IP> {
+    Node { state: S, tick: Option<fn() -> IP> },
+    Client { requests: Vec<(String, P)> },
+}
+
+/// What a participant has got to: a node's own state, or the replies a client has had, as the
+/// frames they came in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum State<N> {
+    Node(N),
+    Client(BTreeSet<String>),
+}
+
+impl<N> State<N> {
+    /// The node this is the state of, if it's a node's.
+    pub fn node(&self) -> Option<&N> {
+        match self {
+            State::Node(node) => Some(node),
+            State::Client(_) => None,
+        }
+    }
+
+    /// The replies this client has had, if it's a client's.
+    pub fn replies(&self) -> Option<&BTreeSet<String>> {
+        match self {
+            State::Node(_) => None,
+            State::Client(replies) => Some(replies),
+        }
+    }
+}
+
+impl<S, N, P, IP> Model<S, N, P, IP>
+where
+    S: Clone + Send + Sync + 'static,
+    N: Node<S, P, IP> + Clone + std::fmt::Debug + PartialEq + std::hash::Hash + Send + Sync,
+    P: Serialize + DeserializeOwned + Clone + Send + Sync + 'static,
+    IP: Send + 'static,
+{
+    /// A cluster of `node_ids`, each started from `state`, with no clients yet.
+    pub fn new(node_ids: &[&str], state: S) -> Self {
+        Self {
+            state,
+            node_ids: node_ids.iter().map(|id| id.to_string()).collect(),
+            clients: Vec::new(),
+            tick: None,
+            _node: PhantomData,
+        }
+    }
+
+    /// Adds a client, named `c0`, `c1` and so on in the order they're added, that sends each of
+    /// `requests` to the node it names as soon as the cluster starts.
+    pub fn client<'a>(mut self, requests: impl IntoIterator<Item = (&'a str, P)>) -> Self {
+        let requests = requests
+            .into_iter()
+            .map(|(dst, payload)| (dst.to_string(), payload))
+            .collect();
+        self.clients.push(requests);
+        self
+    }
+
+    /// Hands every node the event `tick` makes whenever its timer fires, which the checker
+    /// has it do at every point it could. Whatever a tick sends piles up in the network, so a
+    /// model with ticks never runs out of states, and checking it wants a bound, like the
+    /// checker's `target_max_depth`.
+    pub fn every(mut self, tick: fn() -> IP) -> Self {
+        self.tick = Some(tick);
+        self
+    }
+
+    /// The stateright model of the cluster, on a network that delivers every message once, in
+    /// any order, for properties to be added to and the checker to be run on. The nodes are
+    /// the model's first actors, in the order they were named, and the clients come after.
+    pub fn into_actor_model(self) -> ActorModel<Participant<S, N, P, IP>> {
+        let clients = (0..self.clients.len()).map(|client| format!("c{}", client));
+        let directory = Arc::new(Directory {
+            names: self.node_ids.iter().cloned().chain(clients).collect(),
+            nodes: self.node_ids.len(),
+        });
+        let nodes = self.node_ids.iter().map(|_| Role::Node {
+            state: self.state.clone(),
+            tick: self.tick,
+        });
+        let clients = self
+            .clients
+            .into_iter()
+            .map(|requests| Role::Client { requests });
+        ActorModel::new((), ())
+            .actors(nodes.chain(clients).map(|role| Participant {
+                role,
+                directory: directory.clone(),
+                _node: PhantomData,
+            }))
+            .init_network(Network::new_unordered_nonduplicating([]))
+    }
+}
+
+impl<S, N, P, IP> Actor for Participant<S, N, P, IP>
+where
+    S: Clone,
+    N: Node<S, P, IP> + Clone + std::fmt::Debug + PartialEq + std::hash::Hash,
+    P: Serialize + DeserializeOwned + Clone,
+{
+    type Msg = String;
+    type Timer = ();
+    type State = State<N>;
+    type Storage = ();
+    type Random = ();
+
+    fn on_start(&self, id: Id, _storage: &Option<()>, o: &mut Out<Self>) -> State<N> {
+        match &self.role {
+            Role::Node { state, tick } => {
+                let init = Init {
+                    node_id: self.name(id).to_string(),
+                    node_ids: self.directory.names[..self.directory.nodes].to_vec(),
+                };
+                // nothing's listening: what a node injects into itself isn't modelled
+                let (inject, _) = mpsc::channel();
+                let node = N::from_init(state.clone(), init, inject)
+                    .unwrap_or_else(|e| panic!("node failed to start: {:#}", e));
+                if tick.is_some() {
+                    o.set_timer((), model_timeout());
+                }
+                State::Node(node)
+            }
+            Role::Client { requests } => {
+                for (msg_id, (dst, payload)) in requests.iter().enumerate() {
+                    let Some(to) = self.id(dst) else {
+                        log::warn!(
+                            "not sending request {} to {}, who isn't modelled",
+                            msg_id,
+                            dst
+                        );
+                        continue;
+                    };
+                    let request = Message {
+                        src: self.name(id).to_string(),
+                        dst: dst.clone(),
+                        body: Body {
+                            id: Some(msg_id + 1),
+                            in_reply_to: None,
+                            correlation_id: None,
+                            payload: payload.clone(),
+                        },
+                    };
+                    let frame = serde_json::to_string(&request).expect("requests serialize");
+                    o.send(to, frame);
+                }
+                State::Client(BTreeSet::new())
+            }
+        }
+    }
+
+    fn on_msg(
+        &self,
+        _id: Id,
+        state: &mut Cow<State<N>>,
+        _src: Id,
+        frame: String,
+        o: &mut Out<Self>,
+    ) {
+        match state.to_mut() {
+            State::Node(node) => {
+                let message: Message<P> = serde_json::from_str(&frame)
+                    .unwrap_or_else(|e| panic!("frame {} doesn't parse: {}", frame, e));
+                self.step(node, Event::Message(message), o);
+            }
+            State::Client(replies) => {
+                replies.insert(frame);
+            }
+        }
+    }
+
+    fn on_timeout(&self, _id: Id, state: &mut Cow<State<N>>, _timer: &(), o: &mut Out<Self>) {
+        let Role::Node {
+            tick: Some(tick), ..
+        } = &self.role
+        else {
+            return;
+        };
+        if let State::Node(node) = state.to_mut() {
+            self.step(node, Event::Injected(tick()), o);
+        }
+        o.set_timer((), model_timeout());
+    }
+}
+
+impl<S, N, P, IP> Participant<S, N, P, IP>
+where
+    Self: Actor<Msg = String>,
+    N: Node<S, P, IP>,
+{
+    fn name(&self, id: Id) -> &str {
+        &self.directory.names[usize::from(id)]
+    }
+
+    fn id(&self, name: &str) -> Option<Id> {
+        self.directory
+            .names
+            .iter()
+            .position(|n| n == name)
+            .map(Id::from)
+    }
+
+    // steps `node` with `event`, its clock stopped, and sends on what it sends
+    fn step(&self, node: &mut N, event: Event<P, IP>, o: &mut Out<Self>) {
+        let (network, sent) = channel::Network::switched();
+        let mut output = Output::routed(Box::new(network.transport()));
+        clock::simulate(Some(clock::Reading {
+            monotonic: Duration::ZERO,
+            wall: EPOCH,
+        }));
+        let stepped = node.step(event, &mut output);
+        clock::simulate(None);
+        if let Err(e) = stepped {
+            panic!("node failed to step: {:#}", e);
+        }
+        for (dst, frame) in sent.try_iter() {
+            match self.id(&dst) {
+                Some(dst) => o.send(dst, frame),
+                None => log::debug!("dropping a message for {}, who isn't modelled", dst),
+            }
+        }
+    }
+}
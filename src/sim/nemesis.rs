@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// Something a nemesis does to a simulated cluster, as Maelstrom's nemesis would.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Disruption {
+    /// Splits the nodes into components that can't reach each other. Messages between
+    /// components are lost, including the ones that were already on their way.
+    Partition(Split),
+    /// Puts every component back together.
+    Heal,
+    /// Stops a node handling anything, as if its process were suspended. Messages for it wait
+    /// until it's resumed, and the timer ticks it misses are gone.
+    Pause(Target),
+    /// Lets every paused node go on, starting with everything that arrived meanwhile.
+    Resume,
+    /// Crashes a node, losing whatever it had in memory and every message that arrives for it
+    /// while it's down.
+    Kill(Target),
+    /// Starts every killed node again, from its init.
+    Restart,
+}
+
+/// How a partition splits the cluster.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Split {
+    /// Into two halves picked at random, the smaller of them without a majority.
+    Halves,
+    /// One node picked at random, on its own, and everyone else.
+    Isolate,
+    /// Into these components. Nodes in none of them make up one more.
+    Components(Vec<Vec<String>>),
+}
+
+/// Which node a disruption picks on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    Node(String),
+    /// One picked at random, from the nodes the disruption can still do something to.
+    Random,
+}
+
+/// A timeline of disruptions, each at some point after the nemesis is let loose on a cluster,
+/// and, given a period, the same timeline over again each period after that. The random picks a
+/// disruption makes come from the simulation's seed, like everything else in it.
+#[derive(Debug, Clone, Default)]
+pub struct Nemesis {
+    pub(super) timeline: Vec<(Duration, Disruption)>,
+    pub(super) period: Option<Duration>,
+    pub(super) until: Option<Duration>,
+}
+
+impl Nemesis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Partitions the cluster the way `split` says once it's been whole for `healed`, and heals
+    /// it once it's been apart for `apart`, over and over.
+    pub fn partitions(split: Split, healed: Duration, apart: Duration) -> Self {
+        Self::new()
+            .at(healed, Disruption::Partition(split))
+            .at(healed + apart, Disruption::Heal)
+            .repeat_every(healed + apart)
+    }
+
+    /// Adds `disruption`, `after` from when the nemesis starts.
+    pub fn at(mut self, after: Duration, disruption: Disruption) -> Self {
+        self.timeline.push((after, disruption));
+        self
+    }
+
+    /// Goes through the timeline again every `period`.
+    pub fn repeat_every(mut self, period: Duration) -> Self {
+        self.period = Some(period);
+        self
+    }
+
+    /// Stops `after` from when the nemesis starts, and puts right everything it did: heals the
+    /// cluster, resumes whoever's paused and restarts whoever's been killed, so a test can check
+    /// what the nodes make of it once the dust has settled.
+    pub fn until(mut self, after: Duration) -> Self {
+        self.until = Some(after);
+        self
+    }
+}
@@ -0,0 +1,100 @@
+use std::f64::consts::PI;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::Rng;
+
+use super::faults::Faults;
+
+/// How one link behaves, in the direction from its sender to its receiver: the way back is a
+/// link of its own, with a profile of its own, so a link can be fast one way and slow the other.
+/// Whatever's left out of a profile stays as it was.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    /// How long a message takes, in place of the simulation's [`super::Sim::latency`].
+    pub latency: Option<Latency>,
+    /// What goes wrong on the link, as [`super::Sim::link_faults`] would have it, loss included.
+    pub faults: Option<Faults>,
+    /// How many bytes a second the link carries. A message takes as long to put on the wire as
+    /// its size says, and waits for whatever's ahead of it on the link to be put there first,
+    /// before its latency starts.
+    pub bandwidth: Option<u64>,
+}
+
+/// How a link's latency is spread. Every distribution has a longest latency it ever gives, so a
+/// message held back to be overtaken knows how long to wait before nothing will.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Latency {
+    /// Anywhere in the range, evenly.
+    Uniform(RangeInclusive<Duration>),
+    /// Around `mean`, normally, but never more than three deviations either side of it, or less
+    /// than nothing.
+    Normal { mean: Duration, deviation: Duration },
+    /// Anywhere in `typical` but for a chance of `tail`, when it's anywhere in `slow` instead:
+    /// a link that's fast until it loses a packet and has to send it again.
+    Tail {
+        typical: RangeInclusive<Duration>,
+        tail: f64,
+        slow: RangeInclusive<Duration>,
+    },
+}
+
+impl From<RangeInclusive<Duration>> for Latency {
+    fn from(latency: RangeInclusive<Duration>) -> Self {
+        Self::Uniform(latency)
+    }
+}
+
+impl Latency {
+    pub(super) fn pick(&self, rng: &mut StdRng) -> Duration {
+        match self {
+            Self::Uniform(latency) => rng.gen_range(latency.clone()),
+            Self::Normal { mean, deviation } => {
+                // Box-Muller, from two uniform draws, the first kept off zero for the log
+                let u = 1.0 - rng.gen::<f64>();
+                let v = rng.gen::<f64>();
+                let z = (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos();
+                let latency = mean.as_secs_f64() + deviation.as_secs_f64() * z.clamp(-3.0, 3.0);
+                Duration::from_secs_f64(latency.max(0.0))
+            }
+            Self::Tail {
+                typical,
+                tail,
+                slow,
+            } => {
+                if *tail > 0.0 && rng.gen::<f64>() < *tail {
+                    rng.gen_range(slow.clone())
+                } else {
+                    rng.gen_range(typical.clone())
+                }
+            }
+        }
+    }
+
+    pub(super) fn longest(&self) -> Duration {
+        match self {
+            Self::Uniform(latency) => *latency.end(),
+            Self::Normal { mean, deviation } => *mean + *deviation * 3,
+            Self::Tail { typical, slow, .. } => *typical.end().max(slow.end()),
+        }
+    }
+}
+
+// a link with a bandwidth cap, and when it's done putting what it's been given on the wire
+#[derive(Debug, Clone)]
+pub(super) struct Pipe {
+    pub(super) bandwidth: u64,
+    pub(super) busy_until: Duration,
+}
+
+impl Pipe {
+    // when the last of `bytes`, handed over `now`, is on the wire
+    pub(super) fn transmit(&mut self, now: Duration, bytes: usize) -> Duration {
+        let takes = Duration::from_nanos(
+            (bytes as u128 * 1_000_000_000 / self.bandwidth.max(1) as u128) as u64,
+        );
+        self.busy_until = self.busy_until.max(now) + takes;
+        self.busy_until
+    }
+}
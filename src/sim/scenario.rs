@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::nemesis::{Disruption, Split, Target};
+use super::Sim;
+
+/// A failure scenario: a timeline of client requests, partitions, pauses and crashes, and what
+/// must hold along the way, for pinning a bug that's been found down as a regression test. It's
+/// written in TOML, one `[[step]]` a time, each at a point in virtual time and doing one
+/// thing:
+///
+/// ```toml
+/// nodes = ["n0", "n1", "n2"]
+///
+/// [[step]]
+/// at_ms = 0
+/// do = "partition"
+/// components = [["n0"], ["n1", "n2"]]
+///
+/// [[step]]
+/// at_ms = 100
+/// do = "send"
+/// from = "c1"
+/// to = "n0"
+/// body = { type = "broadcast", message = 1 }
+///
+/// [[step]]
+/// at_ms = 500
+/// do = "heal"
+///
+/// [[step]]
+/// at_ms = 2000
+/// do = "expect_status"
+/// node = "n2"
+/// status = { messages = [1] }
+/// ```
+///
+/// or built in Rust, since it's all public. Steps happen in the order of their times, and steps
+/// at the same time in the order they're written. A scenario says nothing about which nodes it's
+/// for: the test starts them on [`Scenario::sim`], along with whatever timers they need, and
+/// then [`Scenario::run`]s it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub nodes: Vec<String>,
+    /// The seed to run with, for a scenario that only goes wrong with it. [`super::seed`]
+    /// otherwise.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    #[serde(default, rename = "step")]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Step {
+    /// When, in milliseconds of virtual time from when the scenario's run.
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "do", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Action {
+    /// A client request, whose body is read as the nodes' payload, less its msg_id.
+    Send {
+        from: String,
+        to: String,
+        body: Value,
+    },
+    /// Splits the nodes into `components`, as [`Split::Components`].
+    Partition {
+        components: Vec<Vec<String>>,
+    },
+    Heal,
+    Pause {
+        node: String,
+    },
+    Resume,
+    Kill {
+        node: String,
+    },
+    Restart,
+    /// Some reply the client's had so far matches `reply`.
+    ExpectReply {
+        client: String,
+        reply: Value,
+    },
+    /// The node's [`crate::Node::status`] matches `status`.
+    ExpectStatus {
+        node: String,
+        status: Value,
+    },
+}
+
+impl Scenario {
+    pub fn from_toml(toml: &str) -> anyhow::Result<Self> {
+        toml::from_str(toml).context("parse scenario")
+    }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let toml = std::fs::read_to_string(path).with_context(|| format!("read {}", path))?;
+        Self::from_toml(&toml).with_context(|| format!("load {}", path))
+    }
+
+    /// A simulation of the scenario's nodes, with its seed, none of them started yet.
+    pub fn sim<P, IP>(&self) -> anyhow::Result<Sim<P, IP>>
+    where
+        P: DeserializeOwned + Send + 'static,
+        IP: Send + 'static,
+    {
+        let seed = match self.seed {
+            Some(seed) => seed,
+            None => super::seed()?,
+        };
+        let nodes: Vec<&str> = self.nodes.iter().map(String::as_str).collect();
+        Ok(Sim::new(seed, &nodes))
+    }
+
+    /// Plays the scenario out on `sim`, failing at the first expectation that doesn't hold, or
+    /// the first step the simulation fails.
+    pub fn run<P, IP>(&self, sim: &mut Sim<P, IP>) -> anyhow::Result<()>
+    where
+        P: Serialize + DeserializeOwned + Send + 'static,
+        IP: Send + 'static,
+    {
+        let start = sim.now();
+        let mut steps: Vec<&Step> = self.steps.iter().collect();
+        steps.sort_by_key(|step| step.at_ms);
+        for (i, step) in steps.into_iter().enumerate() {
+            let at = start + Duration::from_millis(step.at_ms);
+            sim.run_for(at.saturating_sub(sim.now()))?;
+            act(sim, &step.action).with_context(|| {
+                format!("step {} at {}ms: {:?}", i + 1, step.at_ms, step.action)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+fn act<P, IP>(sim: &mut Sim<P, IP>, action: &Action) -> anyhow::Result<()>
+where
+    P: Serialize + DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+{
+    let node = |node: &String| Target::Node(node.clone());
+    match action {
+        Action::Send { from, to, body } => {
+            let payload = serde_json::from_value(body.clone()).context("body isn't a payload")?;
+            sim.send(from, to, payload)?;
+        }
+        Action::Partition { components } => {
+            sim.disrupt(Disruption::Partition(Split::Components(components.clone())))?
+        }
+        Action::Heal => sim.disrupt(Disruption::Heal)?,
+        Action::Pause { node: id } => sim.disrupt(Disruption::Pause(node(id)))?,
+        Action::Resume => sim.disrupt(Disruption::Resume)?,
+        Action::Kill { node: id } => sim.disrupt(Disruption::Kill(node(id)))?,
+        Action::Restart => sim.disrupt(Disruption::Restart)?,
+        Action::ExpectReply { client, reply } => {
+            let replies = sim
+                .replies
+                .get(client)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let replies = replies
+                .iter()
+                .map(|frame| serde_json::from_str(frame).context("reply isn't json"))
+                .collect::<anyhow::Result<Vec<Value>>>()?;
+            anyhow::ensure!(
+                replies.iter().any(|got| matches(&got["body"], reply)),
+                "{} had no reply like {}, only {:?}",
+                client,
+                reply,
+                replies.iter().map(|got| &got["body"]).collect::<Vec<_>>()
+            );
+        }
+        Action::ExpectStatus { node, status } => {
+            let running = sim
+                .nodes
+                .get(node)
+                .with_context(|| format!("{} isn't running", node))?;
+            let got = running.node.status();
+            anyhow::ensure!(
+                matches(&got, status),
+                "{}'s status is {}, not like {}",
+                node,
+                got,
+                status
+            );
+        }
+    }
+    Ok(())
+}
+
+// whether `got` is like `expected`: an object has at least the fields expected, each like the
+// one expected, and an array has exactly the elements expected, each like one of them, in any
+// order, since the sets nodes report come out in whatever order they're kept in
+fn matches(got: &Value, expected: &Value) -> bool {
+    match (got, expected) {
+        (Value::Object(got), Value::Object(expected)) => expected
+            .iter()
+            .all(|(k, v)| got.get(k).is_some_and(|got| matches(got, v))),
+        (Value::Array(got), Value::Array(expected)) => {
+            let mut left: Vec<&Value> = got.iter().collect();
+            got.len() == expected.len()
+                && expected.iter().all(|v| {
+                    let Some(at) = left.iter().position(|got| matches(got, v)) else {
+                        return false;
+                    };
+                    left.swap_remove(at);
+                    true
+                })
+        }
+        (Value::Number(got), Value::Number(expected)) => got.as_f64() == expected.as_f64(),
+        _ => got == expected,
+    }
+}
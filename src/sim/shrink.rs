@@ -0,0 +1,166 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+
+use super::nemesis::Disruption;
+use super::Sim;
+
+/// Everything about a run that wasn't up to the nodes: what clients asked for, how the cluster
+/// was disrupted, and which messages the links lost, duplicated, held up or reordered. A
+/// simulation told to [`super::Sim::record_choices`] makes one of these as it goes, and one
+/// given a schedule to [`super::Sim::replay`] makes those same choices and no others, whatever
+/// its faults say, so a run can be tried again with some of its choices left out.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Schedule {
+    pub choices: Vec<Choice>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    /// A client's request, as it went on the wire, so it goes out as the same message when
+    /// it's replayed, whatever else is left out of the schedule.
+    Send {
+        at: Duration,
+        src: String,
+        dst: String,
+        frame: String,
+    },
+    /// A disruption, with whatever it picked at random already picked: a random node is the
+    /// node it turned out to be, and a random split the components it came to.
+    Disrupt {
+        at: Duration,
+        disruption: Disruption,
+    },
+    /// What the link did to `frame`, the `nth` time that frame went along it, counting from 0.
+    /// A message is picked out by what it says rather than where it came in the run, so the
+    /// same message is faulted when other choices before it have been left out.
+    Fault {
+        at: Duration,
+        link: (String, String),
+        frame: String,
+        nth: u64,
+        fault: Fault,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    Drop,
+    Duplicate,
+    /// Held up by this long on top of its latency.
+    Delay(Duration),
+    Reorder,
+}
+
+// a message's fingerprint, for keeping track of how often the same message has gone along a
+// link without keeping the message: FNV-1a
+pub(super) fn fingerprint(frame: &str) -> u64 {
+    frame.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Choice {
+    /// When it was made, in virtual time since the simulation started.
+    pub fn at(&self) -> Duration {
+        match self {
+            Choice::Send { at, .. } | Choice::Disrupt { at, .. } | Choice::Fault { at, .. } => *at,
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for choice in &self.choices {
+            write!(f, "{:>10?}  ", choice.at())?;
+            match choice {
+                Choice::Send { frame, .. } => writeln!(f, "Send {}", frame)?,
+                Choice::Disrupt { disruption, .. } => writeln!(f, "{:?}", disruption)?,
+                Choice::Fault {
+                    frame, nth, fault, ..
+                } => match nth {
+                    0 => writeln!(f, "{:?} {}", fault, frame)?,
+                    nth => writeln!(f, "{:?} {} (copy {} of it)", fault, frame, nth + 1)?,
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The smallest schedule made of `schedule`'s choices that still `fails`, by delta debugging:
+/// it tries leaving out each of a few big chunks of the choices, keeping whatever still fails,
+/// and splits the chunks finer whenever nothing can go, down to one choice at a time. What's
+/// left is 1-minimal: leaving out any one more choice makes the failure go away.
+///
+/// `fails` is tried with many schedules, so it should start a fresh simulation of the same
+/// nodes, with the same seed, [`super::Sim::replay`] the schedule it's given, and say whether
+/// the run fails the same way. A fault on a message the nodes no longer send once other choices
+/// are left out doesn't happen, so a schedule that needs it doesn't fail, and the fault stays
+/// in along with whatever made the nodes send the message.
+pub fn shrink(schedule: &Schedule, mut fails: impl FnMut(&Schedule) -> bool) -> Schedule {
+    let mut choices = schedule.choices.clone();
+    let mut chunks = 2;
+    while choices.len() >= 2 {
+        let size = choices.len().div_ceil(chunks);
+        let mut shrunk = false;
+        for start in (0..choices.len()).step_by(size) {
+            let mut without = choices[..start].to_vec();
+            without.extend_from_slice(&choices[(start + size).min(choices.len())..]);
+            if fails(&Schedule {
+                choices: without.clone(),
+            }) {
+                choices = without;
+                chunks = (chunks - 1).max(2);
+                shrunk = true;
+                break;
+            }
+        }
+        if shrunk {
+            continue;
+        }
+        if chunks >= choices.len() {
+            break;
+        }
+        chunks = (chunks * 2).min(choices.len());
+    }
+    if choices.len() == 1 && fails(&Schedule::default()) {
+        choices.clear();
+    }
+    Schedule { choices }
+}
+
+/// Runs a simulation test, and if it fails, shrinks what the run chose down to the smallest
+/// schedule that makes it fail and says what that was, as context on the error the run failed
+/// with, rather than leaving a thousand steps of trace to read. `start` makes a fresh
+/// simulation, nodes started, every time it's asked, the same way and with the same seed, and
+/// `run` puts it through the test. Replays run for as long as the failing run had, and a second
+/// more, in case leaving choices out makes the failure come a little later.
+pub fn check<P, IP>(
+    start: impl Fn() -> Sim<P, IP>,
+    run: impl FnOnce(&mut Sim<P, IP>) -> anyhow::Result<()>,
+) -> anyhow::Result<()>
+where
+    P: DeserializeOwned + Send + 'static,
+    IP: Send + 'static,
+{
+    let mut sim = start();
+    sim.record_choices();
+    let Err(e) = run(&mut sim) else {
+        return Ok(());
+    };
+    let recorded = sim.recorded();
+    let replay_for = sim.now() + Duration::from_secs(1);
+    let shrunk = shrink(&recorded, |schedule| {
+        let mut sim = start();
+        sim.replay(schedule);
+        sim.run_for(replay_for).is_err()
+    });
+    Err(e.context(format!(
+        "fails with {} of the run's {} choices:\n{}",
+        shrunk.choices.len(),
+        recorded.choices.len(),
+        shrunk
+    )))
+}
@@ -0,0 +1,52 @@
+use std::time::Duration;
+
+use crate::clock::Reading;
+
+// the wall time every simulated clock starts from, unless it's skewed: a fixed one, so the same
+// seed hands out the same timestamps
+pub(super) const EPOCH: Duration = Duration::from_secs(1_700_000_000);
+
+/// How far a node's clock is off. Nodes' clocks are all right by default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Skew {
+    /// How far ahead of the right time the wall clock is, in milliseconds, or behind it given a
+    /// negative number. Setting it moves a running clock there at once, as a step from NTP
+    /// would, but leaves the monotonic clock alone.
+    pub offset_ms: i64,
+    /// How much faster than the right time both clocks run, as a fraction: 0.001 gains a
+    /// millisecond every second, and -0.001 loses one. Timers the simulator ticks run fast or
+    /// slow along with them.
+    pub drift: f64,
+}
+
+// a node's clock, worked out from the time it was last skewed
+#[derive(Debug, Clone, Default)]
+pub(super) struct Clock {
+    skew: Skew,
+    since: Duration,
+    monotonic: Duration,
+}
+
+impl Clock {
+    pub(super) fn reading(&self, now: Duration) -> Reading {
+        let monotonic = self.monotonic + (now - self.since).mul_f64(1.0 + self.skew.drift);
+        let offset = Duration::from_millis(self.skew.offset_ms.unsigned_abs());
+        let wall = if self.skew.offset_ms < 0 {
+            EPOCH + monotonic - offset
+        } else {
+            EPOCH + monotonic + offset
+        };
+        Reading { monotonic, wall }
+    }
+
+    pub(super) fn skew(&mut self, now: Duration, skew: Skew) {
+        self.monotonic = self.reading(now).monotonic;
+        self.since = now;
+        self.skew = skew;
+    }
+
+    // how long `local` of the node's own time takes in the simulation's
+    pub(super) fn span(&self, local: Duration) -> Duration {
+        local.div_f64(1.0 + self.skew.drift)
+    }
+}
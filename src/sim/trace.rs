@@ -0,0 +1,50 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// One step a node took in a simulation, as a line of the simulator's trace: which node it was,
+/// when by the virtual clock, what it was handed, and the state it was in either side, as its
+/// [`Node::status`](crate::Node::status) has it. Lined up against the behaviours a TLA+ spec
+/// allows, with the status mapped onto the spec's variables, a trace says whether the
+/// implementation ever took a step the spec doesn't.
+///
+/// States are fingerprinted, so a checker comparing against a spec's state graph can match on
+/// the fingerprints alone, and a node's steps chain: each one's `before` is the `after` of the
+/// step before it, unless something the trace can't see, like a thread of the node's own,
+/// changed the node in between.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transition {
+    pub node: String,
+    /// Microseconds of virtual time since the simulation started.
+    pub at_us: u64,
+    pub before: String,
+    pub event: Cause,
+    pub after: String,
+    pub state: Value,
+}
+
+/// What a node was handed to step through.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+#[serde(rename_all = "snake_case")]
+pub enum Cause {
+    /// A message off the wire, as it arrived.
+    Message { message: Value },
+    /// An event of the simulator's timer `timer`, numbered in the order they were set.
+    Tick { timer: usize },
+    /// An event the test injected.
+    Inject,
+    /// An event the node injected into itself while stepping.
+    Injected,
+}
+
+/// A fingerprint of `state`, the same from one run, build and machine to the next: FNV-1a over
+/// its JSON, in hex.
+pub fn fingerprint(state: &Value) -> String {
+    let hash = state
+        .to_string()
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+        });
+    format!("{:016x}", hash)
+}
@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use crate::{config, metrics};
+
+/// A line on stderr every `RUSTENGAN_STATS_MS` of what the node's been up to since the last,
+/// for watching a node through a long Maelstrom run with nothing else running:
+///
+/// ```text
+/// stats over 5000ms: 1042 events, 3 queued, 980 sent (gossip 860, broadcast_ok 120), 81234 bytes written; messages 42
+/// ```
+///
+/// Queued is how many messages have been read but not yet stepped, as the line's written. It's
+/// all counted by [`metrics`], so asking for stats turns those on. After the semicolon is what
+/// the node says of its state, if anything.
+pub(crate) struct Stats {
+    pub(crate) every: Duration,
+    next: Instant,
+    last: Totals,
+}
+
+// what's been counted since the node started
+#[derive(Default)]
+struct Totals {
+    events: u64,
+    // by type
+    sent: BTreeMap<String, u64>,
+    bytes: u64,
+}
+
+impl Stats {
+    /// Stats every `RUSTENGAN_STATS_MS`, if it's set.
+    pub(crate) fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(every) = config::var::<u64>("RUSTENGAN_STATS_MS")? else {
+            return Ok(None);
+        };
+        anyhow::ensure!(every > 0, "RUSTENGAN_STATS_MS must be more than 0");
+        metrics::enable();
+        let every = Duration::from_millis(every);
+        Ok(Some(Self {
+            every,
+            next: Instant::now() + every,
+            last: Totals::default(),
+        }))
+    }
+
+    /// Writes the line, if it's due, with the node's `status` as it stands.
+    pub(crate) fn tick(&mut self, status: impl FnOnce() -> Value) {
+        let now = Instant::now();
+        if now < self.next {
+            return;
+        }
+        self.next = now + self.every;
+        let totals = Totals::now();
+        let mut sent: Vec<(String, u64)> = totals
+            .sent
+            .iter()
+            .map(|(kind, n)| (kind.clone(), n - self.last.sent.get(kind).unwrap_or(&0)))
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        sent.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+        let line = Line {
+            over: self.every,
+            events: totals.events - self.last.events,
+            queued: queued(),
+            sent,
+            bytes: totals.bytes - self.last.bytes,
+            status: summarize(&status()),
+        };
+        log::info!("{}", line);
+        self.last = totals;
+    }
+}
+
+/// One of the lines a node asked for stats with `RUSTENGAN_STATS_MS` writes, as it's written and
+/// as whatever's watching the node reads it back.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Line {
+    /// How long it covers.
+    pub over: Duration,
+    pub events: u64,
+    pub queued: u64,
+    /// Messages sent, by type, most first.
+    pub sent: Vec<(String, u64)>,
+    pub bytes: u64,
+    /// The node's [`status`](crate::Node::status), field by field, with how many there are of
+    /// anything there's a list or map of.
+    pub status: Vec<(String, String)>,
+}
+
+impl Line {
+    /// The stats line in `line`, whatever's before it, like the level the logger put there or
+    /// the node id the cluster did.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (_, line) = line.split_once("stats over ")?;
+        let (over, line) = line.split_once("ms: ")?;
+        let (counts, status) = line.split_once(" bytes written")?;
+        let (events, counts) = counts.split_once(" events, ")?;
+        let (queued, counts) = counts.split_once(" queued, ")?;
+        let (total, counts) = counts.split_once(" sent")?;
+        let (by_type, bytes) = match counts.strip_prefix(" (") {
+            Some(counts) => counts.rsplit_once("), ")?,
+            None => ("", counts.strip_prefix(", ")?),
+        };
+        let sent = pairs(by_type)
+            .map(|(kind, n)| Some((kind, n.parse().ok()?)))
+            .collect::<Option<Vec<(String, u64)>>>()?;
+        if sent.iter().map(|(_, n)| n).sum::<u64>() != total.parse::<u64>().ok()? {
+            return None;
+        }
+        Some(Self {
+            over: Duration::from_millis(over.parse().ok()?),
+            events: events.parse().ok()?,
+            queued: queued.parse().ok()?,
+            sent,
+            bytes: bytes.parse().ok()?,
+            status: pairs(status.strip_prefix("; ").unwrap_or_default()).collect(),
+        })
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stats over {}ms: {} events, {} queued, {} sent",
+            self.over.as_millis(),
+            self.events,
+            self.queued,
+            self.sent.iter().map(|(_, n)| n).sum::<u64>(),
+        )?;
+        if !self.sent.is_empty() {
+            write!(f, " ({})", join(&self.sent))?;
+        }
+        write!(f, ", {} bytes written", self.bytes)?;
+        if !self.status.is_empty() {
+            write!(f, "; {}", join(&self.status))?;
+        }
+        Ok(())
+    }
+}
+
+fn join(pairs: &[(String, impl fmt::Display)]) -> String {
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{} {}", key, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn pairs(line: &str) -> impl Iterator<Item = (String, String)> + '_ {
+    line.split(", ")
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once(' ') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (pair.to_string(), String::new()),
+        })
+}
+
+// a status short enough for a line: what it says, but only how many of whatever it lists
+fn summarize(status: &Value) -> Vec<(String, String)> {
+    let Value::Object(fields) = status else {
+        return Vec::new();
+    };
+    fields
+        .iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                Value::Null => return None,
+                Value::String(s) => s.clone(),
+                Value::Array(items) => items.len().to_string(),
+                Value::Object(items) => items.len().to_string(),
+                value => value.to_string(),
+            };
+            Some((key.clone(), value))
+        })
+        .collect()
+}
+
+impl Totals {
+    fn now() -> Self {
+        let sent = metrics::counters("rustengan_messages_sent_total")
+            .into_iter()
+            .map(|(labels, n)| (label(&labels, "type").to_string(), n))
+            .collect();
+        Self {
+            events: metrics::observations("rustengan_step_seconds")
+                .values()
+                .sum(),
+            sent,
+            bytes: metrics::counters("rustengan_sent_bytes_total")
+                .values()
+                .sum(),
+        }
+    }
+}
+
+// messages read, less those that didn't parse and those stepped
+fn queued() -> u64 {
+    let read: u64 = metrics::counters("rustengan_messages_received_total")
+        .values()
+        .sum();
+    let malformed: u64 = metrics::counters("rustengan_malformed_messages_total")
+        .values()
+        .sum();
+    let stepped: u64 = metrics::observations("rustengan_step_seconds")
+        .iter()
+        .filter(|(labels, _)| label(labels, "event") == "message")
+        .map(|(_, n)| n)
+        .sum();
+    read.saturating_sub(malformed + stepped)
+}
+
+fn label<'a>(labels: &'a [(String, String)], name: &str) -> &'a str {
+    labels
+        .iter()
+        .find(|(key, _)| key == name)
+        .map_or("", |(_, value)| value)
+}
@@ -0,0 +1,182 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::ticks::Manual;
+use crate::transport::channel::Network;
+use crate::{Body, Event, Init, Message, Node, Output};
+
+/// One node, stepped by a test one event at a time, for unit testing a handler without a
+/// cluster around it. The node starts from the same init Maelstrom would have sent, and
+/// everything it sends comes back as messages of its own payload type, so a test asserts on
+/// them the way the node built them.
+///
+/// Timers the node sets with [`crate::ticks::every`] tick only when the test calls
+/// [`TestNode::advance`], and whatever the node injects into itself while it steps is stepped
+/// through before the step returns, as the node's own loop would get to it next.
+///
+/// Everything the node sends is kept as JSON as well, for [`TestNode::sent_json_to`], so a
+/// request in a payload other than the node's own, to a key/value service say, can be looked at
+/// and answered with [`TestNode::deliver`] like anything else.
+pub struct TestNode<N, P, IP = (), S = ()> {
+    node: N,
+    node_id: String,
+    output: Output,
+    // what the node sends, on a switched network of its own
+    outbox: Receiver<(String, String)>,
+    injected: Receiver<Event<P, IP>>,
+    clock: Manual,
+    // what the node's sent that the test hasn't taken yet, oldest first, and in the node's own
+    // payload if it reads as one
+    sent: VecDeque<(Message<Value>, Option<Message<P>>)>,
+    next_id: usize,
+    state: PhantomData<fn() -> S>,
+}
+
+impl<N, P, IP, S> TestNode<N, P, IP, S>
+where
+    N: Node<S, P, IP>,
+    P: DeserializeOwned,
+{
+    /// Starts the node from `state` as `node_id`, in a cluster of `node_ids`.
+    pub fn start(state: S, node_id: &str, node_ids: &[&str]) -> anyhow::Result<Self> {
+        let clock = Manual::install();
+        let (tx, injected) = std::sync::mpsc::channel();
+        let init = Init {
+            node_id: node_id.to_string(),
+            node_ids: node_ids.iter().map(|id| id.to_string()).collect(),
+        };
+        let node = N::from_init(state, init, tx).with_context(|| format!("start {}", node_id))?;
+        let (network, outbox) = Network::switched();
+        Ok(Self {
+            node,
+            node_id: node_id.to_string(),
+            output: Output::routed(Box::new(network.transport())),
+            outbox,
+            injected,
+            clock,
+            sent: VecDeque::new(),
+            next_id: 0,
+            state: PhantomData,
+        })
+    }
+
+    /// The node itself, for asserting on its state.
+    pub fn node(&self) -> &N {
+        &self.node
+    }
+
+    pub fn node_mut(&mut self) -> &mut N {
+        &mut self.node
+    }
+
+    /// Steps the node through `event`, and anything it injects into itself meanwhile.
+    pub fn step(&mut self, event: Event<P, IP>) -> anyhow::Result<()> {
+        self.run(Some(event))
+    }
+
+    // steps the node through `event`, if there is one, and then through whatever's waiting on
+    // its inject channel, and keeps what it sent
+    fn run(&mut self, mut event: Option<Event<P, IP>>) -> anyhow::Result<()> {
+        while let Some(input) = event.take().or_else(|| self.injected.try_recv().ok()) {
+            self.node
+                .step(input, &mut self.output)
+                .with_context(|| format!("{} failed a step", self.node_id))?;
+        }
+        for (_, frame) in self.outbox.try_iter() {
+            let message: Message<Value> = serde_json::from_str(&frame).with_context(|| {
+                format!(
+                    "{} sent something that isn't a message: {}",
+                    self.node_id, frame
+                )
+            })?;
+            self.sent
+                .push_back((message, serde_json::from_str(&frame).ok()));
+        }
+        Ok(())
+    }
+
+    /// Hands the node `message`, as if it had come off the wire.
+    pub fn deliver(&mut self, message: Message<P>) -> anyhow::Result<()> {
+        self.step(Event::Message(message))
+    }
+
+    /// Sends the node `payload` from `src`, with a msg_id of its own, which it returns.
+    pub fn receive(&mut self, src: &str, payload: P) -> anyhow::Result<usize> {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.deliver(Message {
+            src: src.to_string(),
+            dst: self.node_id.clone(),
+            body: Body {
+                id: Some(id),
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        })?;
+        Ok(id)
+    }
+
+    /// Sends the node `payload` from `src`, and takes its answer, leaving anything else it sent
+    /// for [`TestNode::sent`]. An error if it didn't answer there and then.
+    pub fn request(&mut self, src: &str, payload: P) -> anyhow::Result<Message<P>> {
+        let id = self.receive(src, payload)?;
+        let reply = self
+            .sent
+            .iter()
+            .position(|(m, ours)| ours.is_some() && m.dst == src && m.body.in_reply_to == Some(id));
+        let reply = reply
+            .with_context(|| format!("{} didn't answer {}'s msg {}", self.node_id, src, id))?;
+        let (_, reply) = self.sent.remove(reply).expect("just found it");
+        Ok(reply.expect("reads as the node's own"))
+    }
+
+    /// Hands the node an injected event.
+    pub fn inject(&mut self, event: IP) -> anyhow::Result<()> {
+        self.step(Event::Injected(event))
+    }
+
+    /// Moves the node's timers on `by`, stepping it through every tick that falls due, in
+    /// order. How many ticks that came to.
+    pub fn advance(&mut self, by: Duration) -> anyhow::Result<usize> {
+        let ticks = self.clock.advance(by);
+        self.run(None)?;
+        Ok(ticks)
+    }
+
+    /// Everything the node's sent in its own payload that hasn't been taken yet, oldest first.
+    pub fn sent(&mut self) -> Vec<Message<P>> {
+        self.take(|_, ours| ours)
+    }
+
+    /// What the node's sent to `dst` in its own payload that hasn't been taken yet, leaving the
+    /// rest.
+    pub fn sent_to(&mut self, dst: &str) -> Vec<Message<P>> {
+        self.take(|m, ours| ours && m.dst == dst)
+    }
+
+    /// What the node's sent to `dst` that hasn't been taken yet, whatever its payload, as JSON,
+    /// leaving the rest.
+    pub fn sent_json_to(&mut self, dst: &str) -> Vec<Message<Value>> {
+        let (to, rest): (VecDeque<_>, _) = self.sent.drain(..).partition(|(m, _)| m.dst == dst);
+        self.sent = rest;
+        to.into_iter().map(|(m, _)| m).collect()
+    }
+
+    // takes what the node's sent that `which` picks out, given whether it's in the node's own
+    // payload, and leaves the rest
+    fn take(&mut self, which: impl Fn(&Message<Value>, bool) -> bool) -> Vec<Message<P>> {
+        let (taken, rest): (VecDeque<_>, _) = self
+            .sent
+            .drain(..)
+            .partition(|(m, ours)| which(m, ours.is_some()));
+        self.sent = rest;
+        taken.into_iter().filter_map(|(_, ours)| ours).collect()
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use crate::{clock, runtime};
+
+// the manual clock installed on this thread, if a test has installed one
+thread_local! {
+    static MANUAL: RefCell<Option<Rc<RefCell<Timers>>>> = const { RefCell::new(None) };
+}
+
+/// Sends `tick()` on `inject` every `every`, for as long as anyone's receiving: a node's timer,
+/// set when it starts. On its own it's a thread that sleeps in between. Under the simulator
+/// there's no thread, and the simulator's [`Sim::every`](crate::sim::Sim::every) ticks
+/// instead, and with a [`Manual`] clock installed the timer ticks only as the clock is
+/// advanced.
+pub fn every<E: Send + 'static>(
+    every: Duration,
+    inject: Sender<E>,
+    tick: impl Fn() -> E + Send + 'static,
+) {
+    if clock::simulated() {
+        return;
+    }
+    let manual = MANUAL.with_borrow(|manual| manual.clone());
+    if let Some(timers) = manual {
+        timers
+            .borrow_mut()
+            .set(every, Box::new(move || inject.send(tick()).is_ok()));
+        return;
+    }
+    runtime::timer::<runtime::Std, E>(every, inject, tick);
+}
+
+/// A clock that only moves when a test moves it, for the timers of nodes started on this
+/// thread while it's installed. No thread sleeps, so a test that needs a hundred gossip rounds
+/// advances through them as fast as the nodes can step, and every round happens at the same
+/// point in the test every run.
+///
+/// Ticks go on the nodes' inject channels like any timer's would, so whoever's stepping the
+/// nodes steps them through the ticks the same way they would through anything else a node
+/// injects.
+pub struct Manual {
+    timers: Rc<RefCell<Timers>>,
+    // whatever was installed before, to put back once this one goes
+    outer: Option<Rc<RefCell<Timers>>>,
+}
+
+#[derive(Default)]
+struct Timers {
+    now: Duration,
+    // in the order they were set, which is the order timers due at the same time tick in
+    set: Vec<Timer>,
+}
+
+struct Timer {
+    every: Duration,
+    next: Duration,
+    // false once nobody's receiving
+    tick: Box<dyn FnMut() -> bool>,
+}
+
+impl Timers {
+    fn set(&mut self, every: Duration, tick: Box<dyn FnMut() -> bool>) {
+        // a timer that's always due would never let the clock move on
+        let every = every.max(Duration::from_nanos(1));
+        self.set.push(Timer {
+            every,
+            next: self.now + every,
+            tick,
+        });
+    }
+}
+
+impl Manual {
+    /// Installs a manual clock on this thread, at zero, until it's dropped, when whatever clock
+    /// was installed before it is again.
+    pub fn install() -> Self {
+        let timers = Rc::new(RefCell::new(Timers::default()));
+        let outer = MANUAL.replace(Some(timers.clone()));
+        Self { timers, outer }
+    }
+
+    /// How far the clock's been advanced since it was installed.
+    pub fn now(&self) -> Duration {
+        self.timers.borrow().now
+    }
+
+    /// Moves the clock on `by`, ticking every timer that falls due on the way, in the order
+    /// they fall due, as many times as each one does. How many ticks that came to.
+    pub fn advance(&self, by: Duration) -> usize {
+        let mut timers = self.timers.borrow_mut();
+        let until = timers.now + by;
+        let mut ticked = 0;
+        loop {
+            let due = timers
+                .set
+                .iter()
+                .enumerate()
+                .filter(|(_, timer)| timer.next <= until)
+                .min_by_key(|(_, timer)| timer.next)
+                .map(|(i, _)| i);
+            let Some(due) = due else {
+                break;
+            };
+            let timer = &mut timers.set[due];
+            let at = timer.next;
+            timer.next += timer.every;
+            if (timer.tick)() {
+                ticked += 1;
+            } else {
+                timers.set.remove(due);
+            }
+            timers.now = at;
+        }
+        timers.now = until;
+        ticked
+    }
+}
+
+impl Drop for Manual {
+    fn drop(&mut self) {
+        MANUAL.set(self.outer.take());
+    }
+}
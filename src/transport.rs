@@ -0,0 +1,256 @@
+use std::io::{StdoutLock, Write};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::{config, metrics, session};
+
+pub mod backoff;
+pub mod channel;
+pub mod codec;
+pub mod discovery;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod mux;
+pub mod nats;
+pub mod quality;
+#[cfg(feature = "quic")]
+pub mod quic;
+pub mod stream;
+pub mod tcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod udp;
+#[cfg(unix)]
+pub mod uds;
+pub mod websocket;
+
+/// Carries serialized messages between nodes (and their clients) outside of Maelstrom. A frame is
+/// one message as a single line of JSON, without the newline.
+pub trait Transport {
+    /// Sends a frame to the node or client called `dst`. Delivery is best effort, as it is under
+    /// Maelstrom: a frame for somewhere unreachable is dropped, and it's up to the node to retry.
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()>;
+}
+
+/// What a node writes its messages to. Under Maelstrom that's stdout; with a [`Transport`]
+/// every complete line written is routed to whoever its `dest` names.
+pub struct Output {
+    sink: Sink,
+    // what's been written of the current line
+    line: Vec<u8>,
+    session: Option<session::Log>,
+}
+
+enum Sink {
+    Stdout(StdoutLock<'static>),
+    Routed(Box<dyn Transport>),
+}
+
+#[derive(Deserialize)]
+struct Dest {
+    dest: String,
+}
+
+impl Output {
+    pub fn stdout() -> Self {
+        Self {
+            sink: Sink::Stdout(std::io::stdout().lock()),
+            line: Vec::new(),
+            session: None,
+        }
+    }
+
+    pub fn routed(transport: Box<dyn Transport>) -> Self {
+        Self {
+            sink: Sink::Routed(transport),
+            line: Vec::new(),
+            session: None,
+        }
+    }
+
+    /// Records every message sent from now on in `session`.
+    pub fn record_to(&mut self, session: session::Log) {
+        self.session = Some(session);
+    }
+
+    pub fn session(&self) -> Option<&session::Log> {
+        self.session.as_ref()
+    }
+
+    fn emit(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        #[cfg(feature = "otel")]
+        let stamped = crate::otel::stamp(frame);
+        #[cfg(feature = "otel")]
+        let frame = stamped.as_deref().unwrap_or(frame);
+        if metrics::enabled() {
+            let kind = metrics::message_type(frame);
+            metrics::count("rustengan_messages_sent_total", &[("type", &kind)], 1);
+            metrics::count("rustengan_sent_bytes_total", &[], frame.len() as u64 + 1);
+        }
+        if let Some(session) = &self.session {
+            session.record(session::Direction::Sent, frame);
+        }
+        match &mut self.sink {
+            Sink::Stdout(stdout) => {
+                stdout.write_all(frame)?;
+                stdout.write_all(b"\n")
+            }
+            Sink::Routed(transport) => {
+                let dest: Dest = serde_json::from_slice(frame)?;
+                transport
+                    .send(&dest.dest, frame)
+                    .map_err(|e| std::io::Error::other(format!("{:#}", e)))
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            if byte != b'\n' {
+                self.line.push(byte);
+                continue;
+            }
+            let frame = std::mem::take(&mut self.line);
+            self.emit(&frame)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.sink {
+            Sink::Stdout(stdout) => stdout.flush(),
+            Sink::Routed(_) => Ok(()),
+        }
+    }
+}
+
+/// The transport `RUSTENGAN_TRANSPORT` asks for: `stdio` (the default, for Maelstrom), `tcp`,
+/// `udp`, `uds`, `websocket`, `nats` or, built with the `grpc` and `quic` features, `grpc`
+/// and `quic`. Built with the `tls` feature, `tcp` goes over TLS whenever `RUSTENGAN_TLS_CERT`
+/// is set; `quic` always does, so it needs the certificate.
+pub enum Config {
+    Stdio,
+    Tcp(Cluster),
+    #[cfg(feature = "tls")]
+    Tls(Cluster, tls::Tls),
+    Udp(Cluster),
+    #[cfg(unix)]
+    Uds(Cluster),
+    WebSocket(Cluster),
+    Nats {
+        cluster: Cluster,
+        url: String,
+        prefix: String,
+    },
+    #[cfg(feature = "grpc")]
+    Grpc {
+        cluster: Cluster,
+        client_addr: Option<String>,
+    },
+    #[cfg(feature = "quic")]
+    Quic(Cluster, tls::Tls),
+}
+
+impl Config {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match config::var_or("RUSTENGAN_TRANSPORT", "stdio".to_string())?.as_str() {
+            "stdio" => Ok(Self::Stdio),
+            "tcp" => {
+                #[cfg(feature = "tls")]
+                if let Some(tls) = tls::Tls::from_env()? {
+                    return Ok(Self::Tls(Cluster::from_env()?, tls));
+                }
+                #[cfg(not(feature = "tls"))]
+                anyhow::ensure!(
+                    config::var::<String>("RUSTENGAN_TLS_CERT")?.is_none(),
+                    "RUSTENGAN_TLS_CERT needs the tls feature"
+                );
+                Ok(Self::Tcp(Cluster::from_env()?))
+            }
+            "udp" => Ok(Self::Udp(Cluster::from_env()?)),
+            #[cfg(unix)]
+            "uds" => Ok(Self::Uds(Cluster::from_env()?)),
+            "websocket" => Ok(Self::WebSocket(Cluster::from_env()?)),
+            "nats" => {
+                let prefix = config::var_or("RUSTENGAN_NATS_PREFIX", "rustengan".to_string())?;
+                Ok(Self::Nats {
+                    cluster: Cluster::from_env_with(|nodes| nats::parse_nodes(nodes, &prefix))?,
+                    url: config::var_or("RUSTENGAN_NATS_URL", "127.0.0.1:4222".to_string())?,
+                    prefix,
+                })
+            }
+            #[cfg(feature = "grpc")]
+            "grpc" => Ok(Self::Grpc {
+                cluster: Cluster::from_env()?,
+                client_addr: config::var("RUSTENGAN_CLIENT_ADDR")?,
+            }),
+            #[cfg(not(feature = "grpc"))]
+            "grpc" => anyhow::bail!("built without the grpc feature"),
+            #[cfg(feature = "quic")]
+            "quic" => {
+                let tls = tls::Tls::from_env()?.context("quic needs RUSTENGAN_TLS_CERT")?;
+                Ok(Self::Quic(Cluster::from_env()?, tls))
+            }
+            #[cfg(not(feature = "quic"))]
+            "quic" => anyhow::bail!("built without the quic feature"),
+            other => anyhow::bail!("unknown RUSTENGAN_TRANSPORT {:?}", other),
+        }
+    }
+}
+
+/// Who we are and where everyone is: `RUSTENGAN_NODE_ID`, and `RUSTENGAN_NODES` as a
+/// `n0=127.0.0.1:7000,n1=127.0.0.1:7001` list that includes us. Every process of a cluster gets
+/// the same list, which stands in for the node ids Maelstrom's init would have carried. Without
+/// `RUSTENGAN_NODES`, the list comes from [`discovery`], and without `RUSTENGAN_NODE_ID`, we're
+/// whichever node on it has an address of this host.
+pub struct Cluster {
+    pub node_id: String,
+    pub nodes: Vec<(String, String)>,
+}
+
+impl Cluster {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::from_env_with(parse_nodes)
+    }
+
+    /// The same, for a transport that takes `RUSTENGAN_NODES` in a list of its own.
+    pub fn from_env_with(
+        parse: impl FnOnce(&str) -> anyhow::Result<Vec<(String, String)>>,
+    ) -> anyhow::Result<Self> {
+        let nodes = match config::var::<String>("RUSTENGAN_NODES")? {
+            Some(nodes) => parse(&nodes)?,
+            None => discovery::from_env()?.context(
+                "off stdio, RUSTENGAN_NODES, RUSTENGAN_PEERS_FILE or RUSTENGAN_PEERS_DNS is required",
+            )?,
+        };
+        let node_id = match config::var::<String>("RUSTENGAN_NODE_ID")? {
+            Some(node_id) => node_id,
+            None => discovery::local(&nodes).context(
+                "RUSTENGAN_NODE_ID is required off stdio, unless exactly one node is on this host",
+            )?,
+        };
+        anyhow::ensure!(
+            nodes.iter().any(|(name, _)| *name == node_id),
+            "{} isn't one of the nodes",
+            node_id
+        );
+        Ok(Self { node_id, nodes })
+    }
+}
+
+/// Parses a `name=address,name=address` list of nodes, as the socket transports take them.
+pub fn parse_nodes(nodes: &str) -> anyhow::Result<Vec<(String, String)>> {
+    nodes
+        .split(',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (name, addr) = entry
+                .split_once('=')
+                .with_context(|| format!("expected name=address, got {:?}", entry))?;
+            Ok((name.to_string(), addr.to_string()))
+        })
+        .collect()
+}
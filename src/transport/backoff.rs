@@ -0,0 +1,33 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+const FIRST: Duration = Duration::from_millis(50);
+const LONGEST: Duration = Duration::from_secs(5);
+
+/// How long to wait between tries at reaching a peer that isn't there: twice as long each time,
+/// from 50ms up to 5s, less up to half of that at random, so nodes that lost a peer at the
+/// same moment don't all come knocking at the same moment when it's back.
+pub struct Backoff {
+    next: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self { next: FIRST }
+    }
+}
+
+impl Backoff {
+    /// How long to wait before the next try.
+    pub fn wait(&mut self) -> Duration {
+        let wait = self.next.mul_f64(rand::thread_rng().gen_range(0.5..=1.0));
+        self.next = (self.next * 2).min(LONGEST);
+        wait
+    }
+
+    /// Starts over, once a try has worked.
+    pub fn reset(&mut self) {
+        self.next = FIRST;
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+
+use super::Transport;
+
+/// Mailboxes for nodes (and clients) that share a process. Anyone who joins gets a receiver for
+/// the frames sent to them, and a [`ChannelTransport`] to send frames to anyone else who has
+/// joined; cloning a network hands out another handle to the same mailboxes.
+#[derive(Clone, Default)]
+pub struct Network {
+    mailboxes: Arc<Mutex<HashMap<String, Sender<String>>>>,
+    // where every frame goes instead, on a switched network
+    switch: Option<Sender<(String, String)>>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A network that delivers nothing itself: every frame sent on it, whoever it's for, comes
+    /// out of the receiver, with who it's for, and whoever holds the receiver delivers it as
+    /// they see fit. The simulator sits between its nodes on one, deciding when each frame
+    /// arrives and whether it does at all, and a test keeps what a node sent on one.
+    pub fn switched() -> (Self, Receiver<(String, String)>) {
+        let (switch, frames) = std::sync::mpsc::channel();
+        let network = Self {
+            mailboxes: Arc::default(),
+            switch: Some(switch),
+        };
+        (network, frames)
+    }
+
+    /// A transport for sending on the network without a mailbox of one's own on it.
+    pub fn transport(&self) -> ChannelTransport {
+        ChannelTransport {
+            network: self.clone(),
+        }
+    }
+
+    /// Opens a mailbox for `id`, taking over whichever one it had before.
+    pub fn join(&self, id: &str) -> (ChannelTransport, Receiver<String>) {
+        let (tx, rx) = std::sync::mpsc::channel();
+        self.mailboxes
+            .lock()
+            .expect("not poisoned")
+            .insert(id.to_string(), tx);
+        (self.transport(), rx)
+    }
+
+    /// Closes `id`'s mailbox. Once what's already in flight to it has been delivered, its
+    /// receiver runs dry, which for a node is the end of its input.
+    pub fn leave(&self, id: &str) {
+        self.mailboxes.lock().expect("not poisoned").remove(id);
+    }
+
+    /// Everyone with a mailbox.
+    pub fn members(&self) -> Vec<String> {
+        let mut members: Vec<_> = self
+            .mailboxes
+            .lock()
+            .expect("not poisoned")
+            .keys()
+            .cloned()
+            .collect();
+        members.sort();
+        members
+    }
+}
+
+/// Delivers frames straight to the mailboxes of a [`Network`], without serializing anything
+/// beyond the JSON every frame already is.
+pub struct ChannelTransport {
+    network: Network,
+}
+
+impl Transport for ChannelTransport {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+        if let Some(switch) = &self.network.switch {
+            // whoever's switching is gone, and nothing's getting anywhere anymore
+            let _ = switch.send((dst.to_string(), frame));
+            return Ok(());
+        }
+        let mut mailboxes = self.network.mailboxes.lock().expect("not poisoned");
+        let Some(mailbox) = mailboxes.get(dst) else {
+            log::warn!("no route to {}, dropping", dst);
+            return Ok(());
+        };
+        if mailbox.send(frame).is_err() {
+            // whoever had it is gone without leaving
+            mailboxes.remove(dst);
+        }
+        Ok(())
+    }
+}
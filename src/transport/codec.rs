@@ -0,0 +1,281 @@
+use std::io::{BufRead, Read, Write};
+
+use anyhow::Context;
+
+use super::mux;
+use crate::config;
+
+/// How messages are encoded on links between nodes. Nodes always serialize their messages to
+/// JSON, so a codec transcodes each frame on its way onto a link and back to JSON on the other
+/// end; what that buys is smaller frames on the wire, at the cost of the transcoding.
+pub trait Codec: Send + Sync {
+    /// What the handshake calls this codec.
+    fn name(&self) -> &'static str;
+
+    fn encode(&self, json: &[u8]) -> anyhow::Result<Vec<u8>>;
+
+    fn decode(&self, encoded: &[u8]) -> anyhow::Result<String>;
+}
+
+/// Frames as they are, the same JSON clients send.
+pub struct Json;
+
+impl Codec for Json {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn encode(&self, json: &[u8]) -> anyhow::Result<Vec<u8>> {
+        Ok(json.to_vec())
+    }
+
+    fn decode(&self, encoded: &[u8]) -> anyhow::Result<String> {
+        String::from_utf8(encoded.to_vec()).context("frame isn't utf-8")
+    }
+}
+
+/// MessagePack: numbers and short strings take a byte or two instead of their decimal digits
+/// and quotes, which adds up in big gossip and log-replication batches.
+pub struct MessagePack;
+
+impl Codec for MessagePack {
+    fn name(&self) -> &'static str {
+        "msgpack"
+    }
+
+    fn encode(&self, json: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(json.len());
+        let mut from = serde_json::Deserializer::from_slice(json);
+        let mut to = rmp_serde::Serializer::new(&mut encoded);
+        serde_transcode::transcode(&mut from, &mut to).context("json to msgpack")?;
+        Ok(encoded)
+    }
+
+    fn decode(&self, encoded: &[u8]) -> anyhow::Result<String> {
+        let mut json = Vec::with_capacity(encoded.len() * 2);
+        let mut from = rmp_serde::Deserializer::new(encoded);
+        let mut to = serde_json::Serializer::new(&mut json);
+        serde_transcode::transcode(&mut from, &mut to).context("msgpack to json")?;
+        String::from_utf8(json).context("transcoded frame isn't utf-8")
+    }
+}
+
+// tag 55799, "self-described CBOR": a frame that opens with it can't be mistaken for anything
+// else, whoever reads it
+const SELF_DESCRIBED: [u8; 3] = [0xd9, 0xd9, 0xf7];
+
+/// CBOR, for peers that already speak it. Every frame is tagged as self-described CBOR, and the
+/// payload variant is the body's `type` entry, the same as in JSON, so telling variants apart
+/// doesn't take a schema on either side.
+pub struct Cbor;
+
+impl Codec for Cbor {
+    fn name(&self) -> &'static str {
+        "cbor"
+    }
+
+    fn encode(&self, json: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let message: serde_json::Value = serde_json::from_slice(json).context("parse frame")?;
+        let mut encoded = SELF_DESCRIBED.to_vec();
+        ciborium::into_writer(&message, &mut encoded).context("json to cbor")?;
+        Ok(encoded)
+    }
+
+    fn decode(&self, encoded: &[u8]) -> anyhow::Result<String> {
+        let encoded = encoded.strip_prefix(&SELF_DESCRIBED[..]).unwrap_or(encoded);
+        let message: serde_json::Value = ciborium::from_reader(encoded).context("cbor to json")?;
+        serde_json::to_string(&message).context("serialize frame")
+    }
+}
+
+pub fn by_name(name: &str) -> anyhow::Result<Box<dyn Codec>> {
+    match name {
+        "json" => Ok(Box::new(Json)),
+        "msgpack" => Ok(Box::new(MessagePack)),
+        "cbor" => Ok(Box::new(Cbor)),
+        other => anyhow::bail!("unknown codec {:?}", other),
+    }
+}
+
+/// The codec `RUSTENGAN_CODEC` asks nodes to send each other messages in, JSON unless it says
+/// otherwise. A node decodes whatever codec a peer's handshake names, so the setting only has
+/// to agree with what the peers understand, not with what they send.
+pub fn from_env() -> anyhow::Result<Box<dyn Codec>> {
+    by_name(&config::var_or("RUSTENGAN_CODEC", "json".to_string())?)
+}
+
+// frames smaller than this gain too little from compression to be worth it
+const COMPRESS_MIN: usize = 256;
+// the biggest frame a link takes from a peer, however it's framed or compressed, before it
+// gives up on the peer rather than on its own memory
+pub(crate) const FRAME_MAX: usize = 64 * 1024 * 1024;
+
+// what a frame on a `zstd` link starts with
+const RAW: u8 = 0;
+const ZSTD: u8 = 1;
+
+/// Whether, and how, a link compresses what it carries: `none`, `zstd`, where every frame big
+/// enough to be worth it is compressed on its own and can be decoded on its own, or
+/// `zstd-stream`, where the
+/// whole link is one zstd stream, so what one message has in common with those before it (the
+/// same keys and node ids, over and over) costs next to nothing. The stream is flushed whenever
+/// the link has nothing more queued, so it never holds a message back waiting for more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Frame,
+    Stream,
+}
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Frame => "zstd",
+            Compression::Stream => "zstd-stream",
+        }
+    }
+
+    pub fn by_name(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Frame),
+            "zstd-stream" => Ok(Compression::Stream),
+            other => anyhow::bail!("unknown compression {:?}", other),
+        }
+    }
+
+    /// The compression `RUSTENGAN_COMPRESSION` asks for on links to peers, none unless it says
+    /// otherwise. Like the codec, it's the sender's choice, and named in the handshake.
+    pub fn from_env() -> anyhow::Result<Self> {
+        Self::by_name(&config::var_or(
+            "RUSTENGAN_COMPRESSION",
+            "none".to_string(),
+        )?)
+    }
+
+    /// An encoded frame as it goes onto a link: on a `zstd` link, flagged as compressed or not.
+    pub fn pack(self, encoded: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if self != Compression::Frame {
+            return Ok(encoded);
+        }
+        if encoded.len() < COMPRESS_MIN {
+            let mut packed = Vec::with_capacity(encoded.len() + 1);
+            packed.push(RAW);
+            packed.extend_from_slice(&encoded);
+            return Ok(packed);
+        }
+        let mut packed = vec![ZSTD];
+        zstd::stream::copy_encode(&encoded[..], &mut packed, 0).context("compress frame")?;
+        Ok(packed)
+    }
+
+    /// The encoded frame back out of what [`Compression::pack`] made of it.
+    pub fn unpack(self, packed: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        if self != Compression::Frame {
+            return Ok(packed);
+        }
+        match packed.split_first() {
+            Some((&RAW, encoded)) => Ok(encoded.to_vec()),
+            Some((&ZSTD, compressed)) => {
+                let mut decoded = Vec::new();
+                zstd::stream::read::Decoder::new(compressed)
+                    .and_then(|decoder| {
+                        decoder.take(FRAME_MAX as u64 + 1).read_to_end(&mut decoded)
+                    })
+                    .context("decompress frame")?;
+                anyhow::ensure!(
+                    decoded.len() <= FRAME_MAX,
+                    "frame decompresses to over {} bytes",
+                    FRAME_MAX
+                );
+                Ok(decoded)
+            }
+            _ => anyhow::bail!("frame isn't flagged as compressed or not"),
+        }
+    }
+
+    /// What to write a link's frames to, once its handshake has been written to `to`.
+    pub fn writer(self, to: impl Write + Send + 'static) -> anyhow::Result<Box<dyn Write + Send>> {
+        match self {
+            Compression::None | Compression::Frame => Ok(Box::new(to)),
+            Compression::Stream => Ok(Box::new(
+                zstd::stream::write::Encoder::new(to, 0).context("start zstd stream")?,
+            )),
+        }
+    }
+}
+
+// a link between nodes opens with this, the codec's name and, if it's compressed, how, on a
+// line of its own, and then carries frames the way a [`mux::Link`] sends them. Clients skip it
+// and stay newline-delimited JSON, so clients that only know JSON lines never notice.
+const HANDSHAKE: &str = "rustengan-codec ";
+
+/// What opens a link between nodes in `codec`, compressed with `compression`.
+pub fn handshake(codec: &dyn Codec, compression: Compression) -> Vec<u8> {
+    match compression {
+        Compression::None => format!("{}{}\n", HANDSHAKE, codec.name()).into_bytes(),
+        compression => {
+            format!("{}{} {}\n", HANDSHAKE, codec.name(), compression.name()).into_bytes()
+        }
+    }
+}
+
+/// Reads every frame off a connection as JSON, working out whether it's a link from a peer, and
+/// in which codec, from how it starts, until the connection closes or `each` returns false. A
+/// link's probes are answered on `answer`, the other way down the same connection.
+pub fn read_frames(
+    from: &mut impl BufRead,
+    answer: &mut dyn Write,
+    mut each: impl FnMut(String) -> bool,
+) {
+    let Some(first) = read_line(from) else {
+        return;
+    };
+    let Some(name) = first.strip_prefix(HANDSHAKE) else {
+        // newline-delimited JSON, and that was the first frame
+        if !each(first) {
+            return;
+        }
+        while let Some(line) = read_line(from) {
+            if !each(line) {
+                return;
+            }
+        }
+        return;
+    };
+    let (name, compression) = name.split_once(' ').unwrap_or((name, "none"));
+    let (codec, compression) =
+        match by_name(name).and_then(|codec| Ok((codec, Compression::by_name(compression)?))) {
+            Ok(negotiated) => negotiated,
+            Err(e) => {
+                log::warn!("closing link: {:#}", e);
+                return;
+            }
+        };
+    if compression == Compression::Stream {
+        match zstd::stream::read::Decoder::with_buffer(from) {
+            Ok(mut from) => mux::read_lanes(&mut from, answer, &*codec, Compression::None, each),
+            Err(e) => log::warn!("closing link: start zstd stream: {}", e),
+        }
+        return;
+    }
+    mux::read_lanes(from, answer, &*codec, compression, each);
+}
+
+// the next line off a connection, without its line ending. None once the connection closes, or
+// has sent more than a frame's worth without one.
+fn read_line(from: &mut impl BufRead) -> Option<String> {
+    let mut line = String::new();
+    match from.take(FRAME_MAX as u64 + 1).read_line(&mut line) {
+        Ok(0) | Err(_) => return None,
+        Ok(_) => {}
+    }
+    if line.len() > FRAME_MAX {
+        log::warn!("closing link: a line of over {} bytes", FRAME_MAX);
+        return None;
+    }
+    let len = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(len);
+    Some(line)
+}
@@ -0,0 +1,93 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use anyhow::Context;
+
+use super::backoff::Backoff;
+use crate::config;
+
+/// The members of a cluster, for when `RUSTENGAN_NODES` doesn't list them: from the TOML file
+/// `RUSTENGAN_PEERS_FILE` names, or by resolving the `host:port` in `RUSTENGAN_PEERS_DNS`. None
+/// if neither is set.
+pub fn from_env() -> anyhow::Result<Option<Vec<(String, String)>>> {
+    if let Some(path) = config::var::<String>("RUSTENGAN_PEERS_FILE")? {
+        return from_file(&path).map(Some);
+    }
+    if let Some(name) = config::var::<String>("RUSTENGAN_PEERS_DNS")? {
+        let expect = config::var_or("RUSTENGAN_PEERS_EXPECT", 1)?;
+        return from_dns(&name, expect).map(Some);
+    }
+    Ok(None)
+}
+
+/// The nodes a TOML file lists, in the order it lists them, which is the order `node_ids` has
+/// them in:
+///
+/// ```toml
+/// [nodes]
+/// n0 = "10.0.0.1:7000"
+/// n1 = "10.0.0.2:7000"
+/// ```
+pub fn from_file(path: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("read {}", path))?;
+    let file: toml::Table = text.parse().with_context(|| format!("parse {}", path))?;
+    let nodes = file
+        .get("nodes")
+        .and_then(toml::Value::as_table)
+        .with_context(|| format!("{} has no [nodes] table", path))?;
+    nodes
+        .iter()
+        .map(|(name, addr)| {
+            let addr = addr
+                .as_str()
+                .with_context(|| format!("{}: the address of {} isn't a string", path, name))?;
+            Ok((name.clone(), addr.to_string()))
+        })
+        .collect()
+}
+
+/// Every address `name`, a `host:port`, resolves to, as `n0`, `n1` and so on in order of
+/// address, so every node that resolves the same name names the nodes alike. A name that
+/// resolves to fewer than `expect` addresses is resolved again, backing off, until it doesn't:
+/// the members of a cluster that's starting up show up in DNS one at a time.
+pub fn from_dns(name: &str, expect: usize) -> anyhow::Result<Vec<(String, String)>> {
+    let mut backoff = Backoff::default();
+    let addrs = loop {
+        let mut addrs: Vec<SocketAddr> = match name.to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            // a name that doesn't resolve yet is the same as a name with nobody behind it
+            Err(e) => {
+                log::debug!("resolve {}: {}", name, e);
+                Vec::new()
+            }
+        };
+        addrs.sort();
+        addrs.dedup();
+        if addrs.len() >= expect.max(1) {
+            break addrs;
+        }
+        log::info!("{} resolves to {} of {} nodes", name, addrs.len(), expect);
+        std::thread::sleep(backoff.wait());
+    };
+    Ok(addrs
+        .into_iter()
+        .enumerate()
+        .map(|(i, addr)| (format!("n{}", i), addr.to_string()))
+        .collect())
+}
+
+/// Which of `nodes` is this one, for when `RUSTENGAN_NODE_ID` doesn't say: the only one whose
+/// address is an address of this host. None if there isn't exactly one, as when every node
+/// runs on the same host.
+pub fn local(nodes: &[(String, String)]) -> Option<String> {
+    let mut local = nodes.iter().filter(|(_, addr)| {
+        addr.to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            // only an address of this host can be bound to
+            .is_some_and(|addr| UdpSocket::bind((addr.ip(), 0)).is_ok())
+    });
+    match (local.next(), local.next()) {
+        (Some((name, _)), None) => Some(name.clone()),
+        _ => None,
+    }
+}
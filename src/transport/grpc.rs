@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, Sender};
+
+use anyhow::Context;
+use serde_json::{Map, Value};
+use tonic::transport::{Channel, Endpoint, Server};
+use tonic::{Request, Response, Status};
+
+use super::stream::StreamTransport;
+use super::{Cluster, Transport};
+use crate::protobuf::{self, Typed};
+
+pub mod proto {
+    tonic::include_proto!("rustengan");
+
+    pub use crate::protobuf::payloads;
+}
+
+use proto::body::Payload;
+
+use proto::node_client::NodeClient;
+use proto::node_server::{Node as NodeService, NodeServer};
+
+/// Peers over gRPC, clients over newline-delimited JSON. Every node serves the `Node` service
+/// from `proto/rustengan.proto` on its address, and calls `Deliver` on its peers for each
+/// message it sends them, so a node written in anything with a gRPC library can join the
+/// cluster. Clients still speak plain Maelstrom JSON, over TCP on `RUSTENGAN_CLIENT_ADDR` the
+/// way [`super::tcp::TcpTransport`] has them; without it, the node only talks to its peers.
+pub struct GrpcTransport {
+    node_id: String,
+    // the server and the calls to peers run on this
+    runtime: tokio::runtime::Runtime,
+    peers: HashMap<String, NodeClient<Channel>>,
+    edge: Option<StreamTransport<TcpStream>>,
+    loopback: Sender<String>,
+}
+
+struct Inbox {
+    tx: Sender<String>,
+}
+
+#[tonic::async_trait]
+impl NodeService for Inbox {
+    async fn deliver(
+        &self,
+        request: Request<proto::Envelope>,
+    ) -> Result<Response<proto::Delivered>, Status> {
+        let frame = from_envelope(request.into_inner())
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        self.tx
+            .send(frame)
+            .map_err(|_| Status::unavailable("node is shutting down"))?;
+        Ok(Response::new(proto::Delivered {}))
+    }
+}
+
+impl GrpcTransport {
+    /// Starts serving gRPC on this node's address, and JSON on `client_addr` if there is one.
+    /// Every message that arrives on either comes out of the returned receiver.
+    pub fn bind(
+        config: &Cluster,
+        client_addr: Option<&str>,
+    ) -> anyhow::Result<(Self, Receiver<String>)> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .context("start grpc runtime")?;
+        let (tx, rx) = std::sync::mpsc::channel();
+
+        let mut peers = HashMap::new();
+        let mut listen = None;
+        for (name, addr) in &config.nodes {
+            if *name == config.node_id {
+                listen = Some(addr.parse().with_context(|| format!("parse {}", addr))?);
+                continue;
+            }
+            // connects on first use, and again whenever the connection drops
+            let channel = {
+                let _rt = runtime.enter();
+                Endpoint::from_shared(format!("http://{}", addr))
+                    .with_context(|| format!("endpoint for {}", addr))?
+                    .connect_lazy()
+            };
+            peers.insert(name.clone(), NodeClient::new(channel));
+        }
+        let listen = listen.expect("Cluster includes us");
+        let server = Server::builder()
+            .add_service(NodeServer::new(Inbox { tx: tx.clone() }))
+            .serve(listen);
+        runtime.spawn(async move {
+            if let Err(e) = server.await {
+                log::error!("grpc server stopped: {}", e);
+            }
+        });
+        log::info!("{} serving grpc on {}", config.node_id, listen);
+
+        let edge = match client_addr {
+            Some(addr) => {
+                let edge_config = Cluster {
+                    node_id: config.node_id.clone(),
+                    nodes: vec![(config.node_id.clone(), addr.to_string())],
+                };
+                let (edge, lines) = StreamTransport::bind(&edge_config)?;
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for line in lines {
+                        if tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Some(edge)
+            }
+            None => None,
+        };
+
+        Ok((
+            Self {
+                node_id: config.node_id.clone(),
+                runtime,
+                peers,
+                edge,
+                loopback: tx,
+            },
+            rx,
+        ))
+    }
+}
+
+impl Transport for GrpcTransport {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        if dst == self.node_id {
+            let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+            let _ = self.loopback.send(frame);
+            return Ok(());
+        }
+        if let Some(peer) = self.peers.get(dst) {
+            let envelope = to_envelope(frame)?;
+            let mut peer = peer.clone();
+            let dst = dst.to_string();
+            self.runtime.spawn(async move {
+                if let Err(e) = peer.deliver(envelope).await {
+                    log::warn!("deliver to {}: {}", dst, e.message());
+                }
+            });
+            return Ok(());
+        }
+        match &mut self.edge {
+            Some(edge) => edge.send(dst, frame),
+            None => {
+                log::warn!("no route to {}, dropping", dst);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn to_envelope(frame: &[u8]) -> anyhow::Result<proto::Envelope> {
+    let mut message: Map<String, Value> =
+        serde_json::from_slice(frame).context("frame isn't a json object")?;
+    let text = |v: Option<Value>| match v {
+        Some(Value::String(s)) => s,
+        _ => String::new(),
+    };
+    let src = text(message.remove("src"));
+    let dest = text(message.remove("dest"));
+    let mut body = match message.remove("body") {
+        Some(Value::Object(body)) => body,
+        _ => anyhow::bail!("message without a body"),
+    };
+    let msg_id = body.remove("msg_id").and_then(|v| v.as_u64());
+    let in_reply_to = body.remove("in_reply_to").and_then(|v| v.as_u64());
+    let correlation_id = match body.remove("correlation_id") {
+        Some(Value::String(id)) => Some(id),
+        _ => None,
+    };
+    let kind = text(body.remove("type"));
+    // bodies that fit a schema go typed, anything else as its fields in JSON
+    let payload = match protobuf::encode(&kind, &body) {
+        Some(Typed::Broadcast(broadcast)) => Payload::Broadcast(broadcast),
+        Some(Typed::Kv(kv)) => Payload::Kv(kv),
+        None => Payload::Fields(serde_json::to_string(&body).context("serialize body fields")?),
+    };
+    Ok(proto::Envelope {
+        src,
+        dest,
+        body: Some(proto::Body {
+            msg_id,
+            in_reply_to,
+            r#type: kind,
+            payload: Some(payload),
+            correlation_id,
+        }),
+    })
+}
+
+fn from_envelope(envelope: proto::Envelope) -> anyhow::Result<String> {
+    let proto::Body {
+        msg_id,
+        in_reply_to,
+        r#type,
+        payload,
+        correlation_id,
+    } = envelope.body.context("envelope without a body")?;
+    let mut body: Map<String, Value> = match payload {
+        Some(Payload::Fields(fields)) if !fields.is_empty() => {
+            serde_json::from_str(&fields).context("fields aren't a json object")?
+        }
+        Some(Payload::Fields(_)) | None => Map::new(),
+        Some(Payload::Broadcast(broadcast)) => protobuf::decode(&Typed::Broadcast(broadcast)).1,
+        Some(Payload::Kv(kv)) => protobuf::decode(&Typed::Kv(kv)).1,
+    };
+    if let Some(msg_id) = msg_id {
+        body.insert("msg_id".to_string(), msg_id.into());
+    }
+    if let Some(in_reply_to) = in_reply_to {
+        body.insert("in_reply_to".to_string(), in_reply_to.into());
+    }
+    if let Some(correlation_id) = correlation_id {
+        body.insert("correlation_id".to_string(), correlation_id.into());
+    }
+    body.insert("type".to_string(), r#type.into());
+    let message = serde_json::json!({
+        "src": envelope.src,
+        "dest": envelope.dest,
+        "body": body,
+    });
+    Ok(message.to_string())
+}
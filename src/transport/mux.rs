@@ -0,0 +1,382 @@
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use super::backoff::Backoff;
+use super::codec::{Codec, Compression, FRAME_MAX};
+use super::quality::{self, Probes, LOST_AFTER};
+use crate::metrics;
+
+// frames up to this size (heartbeats, acks, most requests) are control traffic, and anything
+// bigger (snapshots, big gossip rounds) is bulk
+const CONTROL_MAX: usize = 4 * 1024;
+// bulk frames go out in chunks of this size, with control frames slipping in between
+const CHUNK: usize = 16 * 1024;
+// how much each lane may have waiting for a peer before frames for it are dropped
+const CONTROL_QUEUED: usize = 1024 * 1024;
+const BULK_QUEUED: usize = 16 * 1024 * 1024;
+// how often a link probes its connection, busy or not
+const PROBE_EVERY: Duration = Duration::from_millis(1000);
+// what stands in for a lane in a probe's header, which carries the probe's sequence number
+const PROBE: u8 = 2;
+
+#[derive(Clone, Copy)]
+enum Lane {
+    Control = 0,
+    Bulk = 1,
+}
+
+impl Lane {
+    fn name(self) -> &'static str {
+        match self {
+            Lane::Control => "control",
+            Lane::Bulk => "bulk",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Queue {
+    frames: VecDeque<Vec<u8>>,
+    bytes: usize,
+}
+
+impl Queue {
+    fn push(&mut self, frame: Vec<u8>, limit: usize) -> bool {
+        if self.bytes + frame.len() > limit {
+            return false;
+        }
+        self.bytes += frame.len();
+        self.frames.push_back(frame);
+        true
+    }
+
+    // puts back a frame that didn't make it out, whatever the budget says, since it was let in
+    fn push_front(&mut self, frame: Vec<u8>) {
+        self.bytes += frame.len();
+        self.frames.push_front(frame);
+    }
+
+    fn pop(&mut self) -> Option<Vec<u8>> {
+        let frame = self.frames.pop_front()?;
+        self.bytes -= frame.len();
+        Some(frame)
+    }
+}
+
+#[derive(Default)]
+struct Lanes {
+    control: Queue,
+    bulk: Queue,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    lanes: Mutex<Lanes>,
+    ready: Condvar,
+}
+
+/// The sending end of a connection to a peer, carrying control and bulk traffic on lanes of
+/// their own. A thread owns the connection and writes whatever is queued, control frames first
+/// and bulk frames a chunk at a time in between, so a snapshot on its way out holds up a
+/// heartbeat by one chunk at most. Each lane has a budget of its own: a lane that's backed up
+/// drops what's sent on it, and the other carries on.
+///
+/// The thread makes the connection too, and makes it again whenever it's lost, backing off
+/// while the peer can't be reached. Frames wait in their lanes meanwhile, within the same
+/// budgets, and a frame that was partway out when the connection went goes again, whole, on
+/// the next one; only what the connection had already taken can be lost with it.
+///
+/// Every second, the link also sends a probe, which the peer sends straight back on the same
+/// connection. The round trips and unanswered probes go to [`quality`], and a connection with a
+/// probe that's gone unanswered for two seconds is given up on and replaced, so a connection
+/// that's gone is noticed without waiting for a write to fail.
+///
+/// On the wire, every chunk is a lane byte, a byte that's 1 on a frame's last chunk, the
+/// chunk's length as a big-endian u32, and the chunk. A probe is a chunk like that too, with 2
+/// for its lane and a big-endian u64 sequence number for the chunk, and its answer is the same
+/// bytes.
+pub struct Link {
+    shared: Arc<Shared>,
+}
+
+impl Link {
+    /// A link to `peer`, over the connections `connect` makes: a writer that's had the link's
+    /// handshake written to it already, and a reader for the answers to probes, whose reads
+    /// should time out every so often.
+    pub fn new(
+        peer: &str,
+        connect: impl FnMut() -> anyhow::Result<Connection> + Send + 'static,
+    ) -> Self {
+        let shared = Arc::new(Shared::default());
+        let writer = shared.clone();
+        let peer = peer.to_string();
+        std::thread::spawn(move || write_lanes(&peer, connect, &writer));
+        Self { shared }
+    }
+
+    /// Queues an encoded frame.
+    pub fn send(&self, encoded: Vec<u8>) {
+        let lane = if encoded.len() <= CONTROL_MAX {
+            Lane::Control
+        } else {
+            Lane::Bulk
+        };
+        let mut lanes = self.shared.lanes.lock().expect("not poisoned");
+        let queued = match lane {
+            Lane::Control => lanes.control.push(encoded, CONTROL_QUEUED),
+            Lane::Bulk => lanes.bulk.push(encoded, BULK_QUEUED),
+        };
+        if !queued {
+            log::warn!("{} lane backed up, dropping a frame", lane.name());
+            metrics::count("rustengan_link_dropped_total", &[("lane", lane.name())], 1);
+        }
+        self.shared.ready.notify_one();
+    }
+}
+
+impl Drop for Link {
+    fn drop(&mut self) {
+        self.shared.lanes.lock().expect("not poisoned").closed = true;
+        self.shared.ready.notify_one();
+    }
+}
+
+/// Both ends of a connection a [`Link`] has made.
+pub type Connection = (Box<dyn Write + Send>, Box<dyn Read + Send>);
+
+// the connection a link is writing to, and the probes out on it
+type Open = (Box<dyn Write + Send>, Arc<Mutex<Probes>>);
+
+enum Next {
+    Control(Vec<u8>),
+    Bulk,
+    // nothing's queued, so whatever the writer is holding on to should go out
+    Flush,
+    // it's time to check the connection is still there, and how quickly it answers
+    Probe,
+}
+
+fn write_lanes(
+    peer: &str,
+    mut connect: impl FnMut() -> anyhow::Result<Connection>,
+    shared: &Shared,
+) {
+    let mut to: Option<Open> = None;
+    let mut backoff = Backoff::default();
+    // the bulk frame on its way out, and how much of it is
+    let mut bulk: Option<(Vec<u8>, usize)> = None;
+    let mut unflushed = false;
+    let mut probed = Instant::now();
+    loop {
+        let Some((writer, probes)) = to.as_mut() else {
+            match connect() {
+                Ok((writer, answers)) => {
+                    backoff.reset();
+                    let probes = Arc::new(Mutex::new(Probes::new(peer)));
+                    let reader = probes.clone();
+                    std::thread::spawn(move || read_answers(answers, &reader));
+                    to = Some((writer, probes));
+                    probed = Instant::now();
+                }
+                Err(e) => {
+                    log::warn!("can't reach {}: {:#}", peer, e);
+                    quality::lost(peer, 1);
+                    let lanes = shared.lanes.lock().expect("not poisoned");
+                    let (lanes, _) = shared
+                        .ready
+                        .wait_timeout_while(lanes, backoff.wait(), |lanes| !lanes.closed)
+                        .expect("not poisoned");
+                    if lanes.closed {
+                        return;
+                    }
+                }
+            }
+            continue;
+        };
+        let unanswered = probes.lock().expect("not poisoned").unanswered();
+        if unanswered.is_some_and(|waited| waited >= LOST_AFTER) {
+            log::warn!("no answer from {} in {:?}", peer, LOST_AFTER);
+            to = None;
+            unflushed = false;
+            if let Some((_, at)) = &mut bulk {
+                *at = 0;
+            }
+            continue;
+        }
+        let next = {
+            let mut lanes = shared.lanes.lock().expect("not poisoned");
+            loop {
+                if lanes.closed {
+                    return;
+                }
+                let due = PROBE_EVERY.saturating_sub(probed.elapsed());
+                if due.is_zero() {
+                    break Next::Probe;
+                }
+                if let Some(frame) = lanes.control.pop() {
+                    break Next::Control(frame);
+                }
+                if bulk.is_none() {
+                    bulk = lanes.bulk.pop().map(|frame| (frame, 0));
+                }
+                if bulk.is_some() {
+                    break Next::Bulk;
+                }
+                if unflushed {
+                    break Next::Flush;
+                }
+                lanes = shared
+                    .ready
+                    .wait_timeout(lanes, due)
+                    .expect("not poisoned")
+                    .0;
+            }
+        };
+        unflushed = !matches!(next, Next::Flush);
+        let written = match &next {
+            Next::Control(frame) => frame.chunks(CHUNK).enumerate().try_for_each(|(i, chunk)| {
+                let last = (i + 1) * CHUNK >= frame.len();
+                write_chunk(writer, Lane::Control, last, chunk)
+            }),
+            Next::Flush => writer.flush(),
+            Next::Probe => {
+                probed = Instant::now();
+                let seq = probes.lock().expect("not poisoned").send();
+                // a probe held back by a compressor would make the link look slower than it is
+                write_probe(writer, seq).and_then(|()| writer.flush())
+            }
+            Next::Bulk => {
+                let (frame, at) = bulk.as_mut().expect("picked a bulk frame");
+                let end = frame.len().min(*at + CHUNK);
+                let last = end == frame.len();
+                let written = write_chunk(writer, Lane::Bulk, last, &frame[*at..end]);
+                if written.is_ok() {
+                    *at = end;
+                    if last {
+                        bulk = None;
+                    }
+                }
+                written
+            }
+        };
+        if let Err(e) = written {
+            log::warn!("lost connection to {}: {}", peer, e);
+            to = None;
+            unflushed = false;
+            if let Next::Control(frame) = next {
+                shared
+                    .lanes
+                    .lock()
+                    .expect("not poisoned")
+                    .control
+                    .push_front(frame);
+            }
+            if let Some((_, at)) = &mut bulk {
+                *at = 0;
+            }
+        }
+    }
+}
+
+fn write_chunk(to: &mut dyn Write, lane: Lane, last: bool, chunk: &[u8]) -> std::io::Result<()> {
+    write_raw(to, lane as u8, last, chunk)
+}
+
+fn write_probe(to: &mut dyn Write, seq: u64) -> std::io::Result<()> {
+    write_raw(to, PROBE, true, &seq.to_be_bytes())
+}
+
+fn write_raw(to: &mut dyn Write, lane: u8, last: bool, chunk: &[u8]) -> std::io::Result<()> {
+    let len = u32::try_from(chunk.len()).expect("chunks are small");
+    let mut out = Vec::with_capacity(6 + chunk.len());
+    out.push(lane);
+    out.push(last as u8);
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(chunk);
+    to.write_all(&out)
+}
+
+// reads the answers to a link's probes off the connection it sent them on, until it closes or
+// the link has moved on to another one. Reads time out now and then, so it notices the latter.
+fn read_answers(mut from: Box<dyn Read + Send>, probes: &Arc<Mutex<Probes>>) {
+    let mut answer = [0; 14];
+    let mut read = 0;
+    while Arc::strong_count(probes) > 1 {
+        match from.read(&mut answer[read..]) {
+            Ok(0) => return,
+            Ok(n) => read += n,
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => continue,
+            Err(_) => return,
+        }
+        if read < answer.len() {
+            continue;
+        }
+        read = 0;
+        if answer[..6] != [PROBE, 1, 0, 0, 0, 8] {
+            log::warn!("that's no answer to a probe, ignoring the rest");
+            return;
+        }
+        let seq = u64::from_be_bytes(answer[6..].try_into().expect("eight bytes"));
+        probes.lock().expect("not poisoned").answer(seq);
+    }
+}
+
+/// Reads every frame off the receiving end of a [`Link`] as JSON, answering its probes on
+/// `answer`, until the connection closes or `each` returns false.
+pub fn read_lanes(
+    from: &mut impl Read,
+    answer: &mut dyn Write,
+    codec: &dyn Codec,
+    compression: Compression,
+    mut each: impl FnMut(String) -> bool,
+) {
+    // what's arrived so far of the frame in progress on each lane
+    let mut partial = [Vec::new(), Vec::new()];
+    loop {
+        let mut header = [0; 6];
+        if from.read_exact(&mut header).is_err() {
+            return;
+        }
+        if header[0] == PROBE {
+            let mut seq = [0; 8];
+            if header[1..] != [1, 0, 0, 0, 8]
+                || from.read_exact(&mut seq).is_err()
+                || write_probe(answer, u64::from_be_bytes(seq)).is_err()
+            {
+                return;
+            }
+            continue;
+        }
+        let Some(frame) = partial.get_mut(header[0] as usize) else {
+            log::warn!("closing link: no lane {}", header[0]);
+            return;
+        };
+        let len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+        if frame.len() + len > FRAME_MAX {
+            log::warn!("closing link: a frame of over {} bytes", FRAME_MAX);
+            return;
+        }
+        // as it arrives, rather than making room for however much the header claims up front
+        let want = frame.len() + len;
+        if from.by_ref().take(len as u64).read_to_end(frame).is_err() || frame.len() < want {
+            return;
+        }
+        if header[1] == 0 {
+            continue;
+        }
+        match compression
+            .unpack(std::mem::take(frame))
+            .and_then(|encoded| codec.decode(&encoded))
+        {
+            Ok(frame) => {
+                if !each(frame) {
+                    return;
+                }
+            }
+            Err(e) => log::warn!("dropping undecodable {} frame: {:#}", codec.name(), e),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use super::backoff::Backoff;
+use super::{Cluster, Transport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+type Link = Arc<Mutex<Option<TcpStream>>>;
+
+/// Messages as NATS publishes, through a server (or cluster of them) at `RUSTENGAN_NATS_URL`.
+/// Every node subscribes to a subject of its own and publishes to the subjects of whoever it
+/// sends to, so nodes only need to reach the server, not each other. A client does the same:
+/// it subscribes to `<prefix>.<its id>` and publishes to the node's subject, and replies come
+/// back on its subject.
+pub struct NatsTransport {
+    node_id: String,
+    prefix: String,
+    subjects: HashMap<String, String>,
+    // whatever connection to the server is current, if there is one
+    link: Link,
+    loopback: Sender<String>,
+}
+
+/// Parses `RUSTENGAN_NODES` for NATS: a list of node ids, each with `=subject` if it shouldn't
+/// be the default `<prefix>.<id>`.
+pub fn parse_nodes(nodes: &str, prefix: &str) -> anyhow::Result<Vec<(String, String)>> {
+    nodes
+        .split(',')
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once('=') {
+            Some((name, subject)) => {
+                anyhow::ensure!(!subject.is_empty(), "empty subject for {}", name);
+                Ok((name.to_string(), subject.to_string()))
+            }
+            None => Ok((entry.to_string(), format!("{}.{}", prefix, entry))),
+        })
+        .collect()
+}
+
+impl NatsTransport {
+    /// Connects to the server at `url` and subscribes to this node's subject, connecting again
+    /// whenever the connection drops. Every message published to the subject comes out of the
+    /// returned receiver.
+    pub fn bind(
+        config: &Cluster,
+        url: &str,
+        prefix: &str,
+    ) -> anyhow::Result<(Self, Receiver<String>)> {
+        let addr = server_addr(url);
+        let subjects: HashMap<_, _> = config.nodes.iter().cloned().collect();
+        let subject = subjects[&config.node_id].clone();
+        let (tx, rx) = std::sync::mpsc::channel();
+        let link: Link = Arc::new(Mutex::new(None));
+
+        // the first connection is made here, so a server that isn't there is an error up front
+        let stream = open(&addr, &config.node_id, &subject)?;
+        log::info!("{} subscribed to {} on {}", config.node_id, subject, addr);
+        let (reader_link, reader_tx, node_id) = (link.clone(), tx.clone(), config.node_id.clone());
+        std::thread::spawn(move || {
+            let mut stream = Some(stream);
+            let mut backoff = Backoff::default();
+            loop {
+                let connected = match stream.take() {
+                    Some(stream) => Ok(stream),
+                    None => open(&addr, &node_id, &subject),
+                };
+                match connected {
+                    Ok(stream) => {
+                        let Ok(writer) = stream.try_clone() else {
+                            std::thread::sleep(backoff.wait());
+                            continue;
+                        };
+                        backoff.reset();
+                        *reader_link.lock().expect("not poisoned") = Some(writer);
+                        if !read_messages(stream, &reader_link, &reader_tx) {
+                            return;
+                        }
+                        log::warn!("lost connection to nats server {}", addr);
+                        *reader_link.lock().expect("not poisoned") = None;
+                    }
+                    Err(e) => log::warn!("can't reach nats server: {:#}", e),
+                }
+                std::thread::sleep(backoff.wait());
+            }
+        });
+
+        Ok((
+            Self {
+                node_id: config.node_id.clone(),
+                prefix: prefix.to_string(),
+                subjects,
+                link,
+                loopback: tx,
+            },
+            rx,
+        ))
+    }
+}
+
+// `nats://host:port` or just `host:port`, with the default port if there isn't one
+fn server_addr(url: &str) -> String {
+    let addr = url.strip_prefix("nats://").unwrap_or(url);
+    let addr = addr.trim_end_matches('/');
+    if addr
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        addr.to_string()
+    } else {
+        format!("{}:4222", addr)
+    }
+}
+
+fn open(addr: &str, node_id: &str, subject: &str) -> anyhow::Result<TcpStream> {
+    let resolved = addr
+        .to_socket_addrs()
+        .with_context(|| format!("resolve {}", addr))?
+        .next()
+        .with_context(|| format!("{} resolves to nothing", addr))?;
+    let mut stream = TcpStream::connect_timeout(&resolved, CONNECT_TIMEOUT)
+        .with_context(|| format!("connect to {}", addr))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let connect = serde_json::json!({
+        "verbose": false,
+        "pedantic": false,
+        "name": node_id,
+        "lang": "rust",
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol": 0,
+    });
+    write!(stream, "CONNECT {}\r\nSUB {} 1\r\n", connect, subject).context("subscribe")?;
+    Ok(stream)
+}
+
+// hands every message published to us to `tx` until the connection drops, answering the
+// server's pings along the way. false if nobody is listening anymore.
+fn read_messages(stream: TcpStream, link: &Link, tx: &Sender<String>) -> bool {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return true,
+            Ok(_) => {}
+        }
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("MSG") => {
+                // MSG <subject> <sid> [reply-to] <#bytes>
+                let Some(Ok(len)) = words.last().map(str::parse::<usize>) else {
+                    log::warn!("malformed nats line {:?}", line.trim_end());
+                    return true;
+                };
+                let mut payload = vec![0; len + 2];
+                if reader.read_exact(&mut payload).is_err() {
+                    return true;
+                }
+                payload.truncate(len);
+                match String::from_utf8(payload) {
+                    Ok(frame) => {
+                        if tx.send(frame).is_err() {
+                            return false;
+                        }
+                    }
+                    Err(_) => log::warn!("dropping a message that isn't utf-8"),
+                }
+            }
+            Some("PING") => {
+                if let Some(stream) = link.lock().expect("not poisoned").as_mut() {
+                    let _ = stream.write_all(b"PONG\r\n");
+                }
+            }
+            Some("-ERR") => log::warn!("nats server: {}", line.trim_end()),
+            // INFO, +OK, PONG
+            _ => {}
+        }
+    }
+}
+
+impl Transport for NatsTransport {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        if dst == self.node_id {
+            let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+            let _ = self.loopback.send(frame);
+            return Ok(());
+        }
+        let subject = match self.subjects.get(dst) {
+            Some(subject) => subject.clone(),
+            None => format!("{}.{}", self.prefix, dst),
+        };
+        let mut link = self.link.lock().expect("not poisoned");
+        let Some(stream) = link.as_mut() else {
+            // reconnecting; dropped like anything else in flight would be
+            return Ok(());
+        };
+        let mut publish = format!("PUB {} {}\r\n", subject, frame.len()).into_bytes();
+        publish.extend_from_slice(frame);
+        publish.extend_from_slice(b"\r\n");
+        if let Err(e) = stream.write_all(&publish) {
+            log::warn!("publish to {}: {}", subject, e);
+            // the reader notices too, and connects again
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+            *link = None;
+        }
+        Ok(())
+    }
+}
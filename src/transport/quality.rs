@@ -0,0 +1,182 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+
+// a probe that's gone this long without an answer is counted as lost, and the connection it
+// went out on as gone
+pub(crate) const LOST_AFTER: Duration = Duration::from_millis(2000);
+// how much each new round trip and each probe's fate moves the estimates
+const RTT_GAIN: f64 = 1.0 / 8.0;
+const RTT_VAR_GAIN: f64 = 1.0 / 4.0;
+const LOSS_GAIN: f64 = 1.0 / 10.0;
+
+/// What probing the link to a peer has measured of it so far. The persistent transports (TCP,
+/// TLS, Unix sockets, WebSocket and QUIC) probe each peer they keep a connection to about once a
+/// second, whether or not there's traffic, so a failure detector can go by how long the link
+/// takes to answer and a gossip round can favour the peers that answer best, instead of
+/// guessing at either.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkQuality {
+    /// The smoothed round trip, the way TCP smooths it.
+    pub rtt: Duration,
+    /// How far round trips tend to stray from `rtt`.
+    pub rtt_var: Duration,
+    /// Roughly what fraction of recent probes went unanswered, from 0 to 1. While a peer can't
+    /// be connected to, it counts as losing a probe at every try.
+    pub loss: f64,
+    /// When the link last answered a probe.
+    pub answered: Instant,
+}
+
+static LINKS: Mutex<BTreeMap<String, Estimate>> = Mutex::new(BTreeMap::new());
+
+#[derive(Default)]
+struct Estimate {
+    rtt: Option<(Duration, Duration)>,
+    loss: f64,
+    answered: Option<Instant>,
+}
+
+impl Estimate {
+    fn round_trip(&mut self, rtt: Duration) {
+        self.rtt = Some(match self.rtt {
+            None => (rtt, rtt / 2),
+            Some((smoothed, var)) => {
+                let (smoothed, var) = (smoothed.as_secs_f64(), var.as_secs_f64());
+                let rtt = rtt.as_secs_f64();
+                let var = var + RTT_VAR_GAIN * ((smoothed - rtt).abs() - var);
+                let smoothed = smoothed + RTT_GAIN * (rtt - smoothed);
+                (
+                    Duration::from_secs_f64(smoothed),
+                    Duration::from_secs_f64(var),
+                )
+            }
+        });
+        self.answered = Some(Instant::now());
+    }
+
+    fn loss(&mut self, lost: f64) {
+        self.loss += LOSS_GAIN * (lost - self.loss);
+    }
+
+    fn quality(&self) -> Option<LinkQuality> {
+        let (rtt, rtt_var) = self.rtt?;
+        Some(LinkQuality {
+            rtt,
+            rtt_var,
+            loss: self.loss,
+            answered: self.answered?,
+        })
+    }
+}
+
+/// What's been measured of the link to `peer`. None until it's answered a probe, which is
+/// always the case on the transports that don't probe (stdio, UDP, NATS, gRPC).
+pub fn of(peer: &str) -> Option<LinkQuality> {
+    LINKS.lock().expect("not poisoned").get(peer)?.quality()
+}
+
+/// What's been measured of every link that's answered a probe, by peer.
+pub fn all() -> BTreeMap<String, LinkQuality> {
+    LINKS
+        .lock()
+        .expect("not poisoned")
+        .iter()
+        .filter_map(|(peer, estimate)| Some((peer.clone(), estimate.quality()?)))
+        .collect()
+}
+
+/// Records a probe to `peer` that was answered after `rtt`.
+pub(crate) fn answered(peer: &str, rtt: Duration) {
+    metrics::observe("rustengan_link_rtt_seconds", &[("peer", peer)], rtt);
+    let mut links = LINKS.lock().expect("not poisoned");
+    let estimate = links.entry(peer.to_string()).or_default();
+    estimate.round_trip(rtt);
+    estimate.loss(0.0);
+}
+
+/// Records `lost` probes to `peer` that never got an answer.
+pub(crate) fn lost(peer: &str, lost: u64) {
+    if lost == 0 {
+        return;
+    }
+    metrics::count("rustengan_link_probes_lost_total", &[("peer", peer)], lost);
+    let mut links = LINKS.lock().expect("not poisoned");
+    let estimate = links.entry(peer.to_string()).or_default();
+    for _ in 0..lost {
+        estimate.loss(1.0);
+    }
+}
+
+/// Records what a transport that probes connections itself (QUIC does) has measured of the link
+/// to `peer` lately: the round trip it gives, and how many of the last `sent` packets it lost.
+#[cfg(feature = "quic")]
+pub(crate) fn measured(peer: &str, rtt: Duration, lost: u64, sent: u64) {
+    metrics::observe("rustengan_link_rtt_seconds", &[("peer", peer)], rtt);
+    let mut links = LINKS.lock().expect("not poisoned");
+    let estimate = links.entry(peer.to_string()).or_default();
+    estimate.round_trip(rtt);
+    if sent > 0 {
+        estimate.loss((lost as f64 / sent as f64).min(1.0));
+    }
+}
+
+/// The probes out on one connection to a peer, waiting to be answered. Answers come back in
+/// the order the probes went out, so a probe that's answered answers for everything before it
+/// too, one way or the other.
+pub(crate) struct Probes {
+    peer: String,
+    next: u64,
+    waiting: VecDeque<(u64, Instant)>,
+}
+
+impl Probes {
+    pub(crate) fn new(peer: &str) -> Self {
+        Self {
+            peer: peer.to_string(),
+            next: 0,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    /// The sequence number of a probe that's going out now.
+    pub(crate) fn send(&mut self) -> u64 {
+        let seq = self.next;
+        self.next += 1;
+        self.waiting.push_back((seq, Instant::now()));
+        seq
+    }
+
+    /// Records the answer to probe `seq`.
+    pub(crate) fn answer(&mut self, seq: u64) {
+        let mut skipped = 0;
+        while let Some(&(waiting, sent)) = self.waiting.front() {
+            if waiting > seq {
+                break;
+            }
+            self.waiting.pop_front();
+            if waiting == seq {
+                lost(&self.peer, skipped);
+                answered(&self.peer, sent.elapsed());
+                return;
+            }
+            skipped += 1;
+        }
+        // an answer to nothing we're waiting on is late or made up
+        lost(&self.peer, skipped);
+    }
+
+    /// How long the oldest probe still waiting has been waiting.
+    pub(crate) fn unanswered(&self) -> Option<Duration> {
+        self.waiting.front().map(|(_, sent)| sent.elapsed())
+    }
+}
+
+impl Drop for Probes {
+    // whatever's still waiting when the connection goes won't be answered on it
+    fn drop(&mut self) {
+        lost(&self.peer, self.waiting.len() as u64);
+    }
+}
@@ -0,0 +1,439 @@
+use std::collections::{HashMap, VecDeque};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use serde::Deserialize;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+use super::backoff::Backoff;
+use super::codec::Compression;
+use super::quality;
+use super::tls::Tls;
+use super::{Cluster, Transport};
+
+// what nodes and their clients say they speak during the handshake: frames as they are, or
+// compressed the way a `zstd` link has them
+const ALPN: &[u8] = b"rustengan";
+const ALPN_ZSTD: &[u8] = b"rustengan-zstd";
+
+// a stream carries one message, and no message is bigger than this
+const MAX_FRAME: usize = 64 * 1024 * 1024;
+
+// a handshake that hasn't finished by now isn't going to
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+// how much waits for a peer while it can't be reached, before frames for it are dropped
+const PEER_QUEUED: usize = 16 * 1024 * 1024;
+
+// connections that have nothing to carry are pinged this often, to keep them open, and given up
+// on after going this long without hearing anything back
+const KEEP_ALIVE: Duration = Duration::from_millis(1000);
+const IDLE_TIMEOUT: Duration = Duration::from_millis(5000);
+// how often what QUIC has measured of a connection to a peer is passed on to `quality`
+const SAMPLE_EVERY: Duration = Duration::from_millis(1000);
+// how long a client's request waits to be answered on the stream it came on before the stream's
+// given up on, as it is as soon as the client's connection closes
+const REPLY_WITHIN: Duration = Duration::from_secs(30);
+
+#[derive(Deserialize)]
+struct Header {
+    src: String,
+    #[serde(default)]
+    body: HeaderBody,
+}
+
+#[derive(Default, Deserialize)]
+struct HeaderBody {
+    msg_id: Option<u64>,
+    in_reply_to: Option<u64>,
+}
+
+type Clients = Arc<Mutex<HashMap<String, Connection>>>;
+
+// by client and msg_id, the streams requests came on, for the replies to go back on, how their
+// connections compress them, and which connections they are
+type Waiting = Arc<Mutex<HashMap<(String, u64), (SendStream, Compression, usize)>>>;
+
+/// Messages over QUIC, each on a stream of its own, so one big message (a snapshot, a large
+/// gossip round) never holds up the ones behind it the way it would on a TCP connection. Every
+/// node listens on its address with the certificate and trust of [`Tls`], which QUIC can't do
+/// without, and keeps one connection to each peer, opened the first time it has something to
+/// send it, and again whenever it's lost, holding frames for the peer until it's back. QUIC
+/// pings a connection that has nothing to carry, so one that's gone is noticed within
+/// seconds, and the round trips and losses it measures go to [`super::quality`]. QUIC knows
+/// connections by id rather than by address, so one survives either end moving to another
+/// address, a client roaming networks or a NAT rebinding, without being set up again. Messages
+/// go as JSON, one per stream, and in no particular order.
+///
+/// Whether a connection's frames are compressed is settled in its handshake: a node with
+/// `RUSTENGAN_COMPRESSION` set asks for `rustengan-zstd`, which every node accepts, and frames
+/// on the connection, both ways, are then compressed one by one as on a `zstd`
+/// [`super::stream::StreamTransport`] link. A stream carries a single frame, so there's no
+/// stream for `zstd-stream` to compress across, and it means the same as `zstd` here.
+///
+/// Clients connect the same way, and are remembered under the `src` of the last message they
+/// sent. A request a client sends on a bidirectional stream is answered on that stream, which
+/// closes once the reply is on it; anything else for the client goes on a stream of its own. A
+/// request that isn't answered within 30 seconds, or before its client goes, isn't answered on
+/// its stream at all, and its reply goes the way anything else for the client does.
+pub struct QuicTransport {
+    node_id: String,
+    // the endpoint and every stream run on this
+    runtime: tokio::runtime::Runtime,
+    // by peer, what's queued for the task that keeps a connection to it
+    peers: HashMap<String, UnboundedSender<Vec<u8>>>,
+    clients: Clients,
+    waiting: Waiting,
+    loopback: Sender<String>,
+}
+
+#[derive(Clone)]
+struct Inbox {
+    tx: Sender<String>,
+    nodes: Arc<Vec<String>>,
+    clients: Clients,
+    waiting: Waiting,
+}
+
+impl QuicTransport {
+    /// Starts listening on this node's address. Every message that arrives from anyone comes
+    /// out of the returned receiver.
+    pub fn bind(config: &Cluster, tls: &Tls) -> anyhow::Result<(Self, Receiver<String>)> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .enable_all()
+            .build()
+            .context("start quic runtime")?;
+        let offer: &[&[u8]] = match Compression::from_env()? {
+            Compression::None => &[ALPN],
+            Compression::Frame | Compression::Stream => &[ALPN_ZSTD, ALPN],
+        };
+        let (server, client) = tls.with_alpn(&[ALPN_ZSTD, ALPN], offer);
+        let server = QuicServerConfig::try_from(server).context("tls settings for quic")?;
+        let client = QuicClientConfig::try_from(client).context("tls settings for quic")?;
+
+        let addrs: HashMap<_, _> = config.nodes.iter().cloned().collect();
+        let listen = resolve(&addrs[&config.node_id])?;
+        let mut transport = quinn::TransportConfig::default();
+        transport
+            .keep_alive_interval(Some(KEEP_ALIVE))
+            .max_idle_timeout(Some(IDLE_TIMEOUT.try_into().context("idle timeout")?));
+        let transport = Arc::new(transport);
+        let mut server = quinn::ServerConfig::with_crypto(Arc::new(server));
+        server.transport_config(transport.clone());
+        let mut client = quinn::ClientConfig::new(Arc::new(client));
+        client.transport_config(transport);
+        let mut endpoint = {
+            let _rt = runtime.enter();
+            Endpoint::server(server, listen).with_context(|| format!("listen on {}", listen))?
+        };
+        endpoint.set_default_client_config(client);
+        log::info!("{} listening on {} (quic)", config.node_id, listen);
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let inbox = Inbox {
+            tx: tx.clone(),
+            nodes: Arc::new(addrs.keys().cloned().collect()),
+            clients: Arc::new(Mutex::new(HashMap::new())),
+            waiting: Arc::new(Mutex::new(HashMap::new())),
+        };
+        runtime.spawn(inbox.clone().accept(endpoint.clone()));
+
+        let mut peers = HashMap::new();
+        for (name, addr) in &addrs {
+            if *name == config.node_id {
+                continue;
+            }
+            let (queue, queued) = tokio::sync::mpsc::unbounded_channel();
+            runtime.spawn(keep_peer(
+                endpoint.clone(),
+                name.clone(),
+                addr.clone(),
+                queued,
+                queue.clone(),
+            ));
+            peers.insert(name.clone(), queue);
+        }
+
+        Ok((
+            Self {
+                node_id: config.node_id.clone(),
+                runtime,
+                peers,
+                clients: inbox.clients,
+                waiting: inbox.waiting,
+                loopback: tx,
+            },
+            rx,
+        ))
+    }
+}
+
+fn resolve(addr: &str) -> anyhow::Result<SocketAddr> {
+    addr.to_socket_addrs()
+        .with_context(|| format!("resolve {}", addr))?
+        .next()
+        .with_context(|| format!("{} resolves to nothing", addr))
+}
+
+async fn connect(endpoint: &Endpoint, addr: &str) -> anyhow::Result<Connection> {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let connecting = endpoint
+        .connect(resolve(addr)?, host)
+        .context("start quic connection")?;
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, connecting)
+        .await
+        .context("handshake timed out")?
+        .context("quic handshake")
+}
+
+// how frames on `connection` are compressed, as its handshake settled
+fn compression(connection: &Connection) -> Compression {
+    let negotiated = connection
+        .handshake_data()
+        .and_then(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>().ok())
+        .and_then(|data| data.protocol);
+    match negotiated.as_deref() {
+        Some(ALPN_ZSTD) => Compression::Frame,
+        _ => Compression::None,
+    }
+}
+
+async fn send_on(connection: &Connection, frame: &[u8]) -> anyhow::Result<()> {
+    let frame = compression(connection).pack(frame.to_vec())?;
+    let mut stream = connection.open_uni().await.context("open stream")?;
+    stream.write_all(&frame).await.context("write")?;
+    stream.finish().context("finish stream")?;
+    Ok(())
+}
+
+// whatever is waiting for a peer, dropping what's over budget
+#[derive(Default)]
+struct Held {
+    frames: VecDeque<Vec<u8>>,
+    bytes: usize,
+}
+
+impl Held {
+    fn push(&mut self, frame: Vec<u8>, peer: &str) {
+        if self.bytes + frame.len() > PEER_QUEUED {
+            log::warn!("{} is backed up, dropping a frame", peer);
+            return;
+        }
+        self.bytes += frame.len();
+        self.frames.push_back(frame);
+    }
+}
+
+// sends the peer `name` everything queued for it, over one connection for as long as that lasts
+// and then over the next, holding frames while there isn't one. Frames that were on their way
+// out on a connection that closed under them are queued again, with `requeue`.
+async fn keep_peer(
+    endpoint: Endpoint,
+    name: String,
+    addr: String,
+    mut queued: UnboundedReceiver<Vec<u8>>,
+    requeue: UnboundedSender<Vec<u8>>,
+) {
+    let mut connection: Option<Connection> = None;
+    let mut backoff = Backoff::default();
+    let mut held = Held::default();
+    while let Some(frame) = queued.recv().await {
+        held.push(frame, &name);
+        let connection = loop {
+            while let Ok(frame) = queued.try_recv() {
+                held.push(frame, &name);
+            }
+            match &connection {
+                Some(open) if open.close_reason().is_none() => break open.clone(),
+                Some(_) => {
+                    log::warn!("lost connection to {}", name);
+                    connection = None;
+                }
+                None => match connect(&endpoint, &addr).await {
+                    Ok(connected) => {
+                        backoff.reset();
+                        tokio::spawn(sample(connected.clone(), name.clone()));
+                        connection = Some(connected);
+                    }
+                    Err(e) => {
+                        log::warn!("can't reach {}: {:#}", name, e);
+                        quality::lost(&name, 1);
+                        tokio::time::sleep(backoff.wait()).await;
+                    }
+                },
+            }
+        };
+        held.bytes = 0;
+        for frame in held.frames.drain(..) {
+            let (connection, name, requeue) = (connection.clone(), name.clone(), requeue.clone());
+            tokio::spawn(async move {
+                if let Err(e) = send_on(&connection, &frame).await {
+                    if connection.close_reason().is_some() {
+                        let _ = requeue.send(frame);
+                    } else {
+                        log::warn!("send to {}: {:#}", name, e);
+                    }
+                }
+            });
+        }
+    }
+}
+
+// passes on what QUIC has measured of the connection to `peer`, until it closes
+async fn sample(connection: Connection, peer: String) {
+    let (mut lost, mut sent) = (0, 0);
+    while connection.close_reason().is_none() {
+        tokio::time::sleep(SAMPLE_EVERY).await;
+        let path = connection.stats().path;
+        quality::measured(
+            &peer,
+            connection.rtt(),
+            path.lost_packets - lost,
+            path.sent_packets - sent,
+        );
+        (lost, sent) = (path.lost_packets, path.sent_packets);
+    }
+}
+
+impl Inbox {
+    async fn accept(self, endpoint: Endpoint) {
+        while let Some(incoming) = endpoint.accept().await {
+            let inbox = self.clone();
+            tokio::spawn(async move {
+                match incoming.await {
+                    Ok(connection) => inbox.serve(connection),
+                    Err(e) => log::warn!("incoming quic connection: {}", e),
+                }
+            });
+        }
+    }
+
+    fn serve(self, connection: Connection) {
+        let (inbox, uni) = (self.clone(), connection.clone());
+        tokio::spawn(async move {
+            while let Ok(recv) = uni.accept_uni().await {
+                let (inbox, connection) = (inbox.clone(), uni.clone());
+                tokio::spawn(async move { inbox.receive(connection, recv, None).await });
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((send, recv)) = connection.accept_bi().await {
+                let (inbox, connection) = (self.clone(), connection.clone());
+                tokio::spawn(async move { inbox.receive(connection, recv, Some(send)).await });
+            }
+        });
+    }
+
+    async fn receive(
+        &self,
+        connection: Connection,
+        mut recv: RecvStream,
+        reply: Option<SendStream>,
+    ) {
+        let frame = match recv.read_to_end(MAX_FRAME).await {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::warn!("read from {}: {}", connection.remote_address(), e);
+                return;
+            }
+        };
+        let frame = match compression(&connection).unpack(frame) {
+            Ok(frame) => frame,
+            Err(e) => {
+                log::warn!("frame from {}: {:#}", connection.remote_address(), e);
+                return;
+            }
+        };
+        let Ok(frame) = String::from_utf8(frame) else {
+            log::warn!("frame from {} isn't utf-8", connection.remote_address());
+            return;
+        };
+        // peers send on connections of their own, so only clients need remembering
+        if let Ok(Header { src, body }) = serde_json::from_str(&frame) {
+            if !self.nodes.contains(&src) {
+                if let (Some(reply), Some(msg_id)) = (reply, body.msg_id) {
+                    self.wait(&connection, (src.clone(), msg_id), reply);
+                }
+                let mut clients = self.clients.lock().expect("not poisoned");
+                clients.insert(src, connection);
+            }
+        }
+        let _ = self.tx.send(frame);
+    }
+
+    // keeps the stream `request` came on for its reply, until the reply's sent, the client's
+    // connection closes or it's waited too long
+    fn wait(&self, connection: &Connection, request: (String, u64), reply: SendStream) {
+        let (stream, on) = (reply.id(), connection.stable_id());
+        let mut waiting = self.waiting.lock().expect("not poisoned");
+        waiting.insert(request.clone(), (reply, compression(connection), on));
+        let (waiting, connection) = (Arc::clone(&self.waiting), connection.clone());
+        tokio::spawn(async move {
+            let _ = tokio::time::timeout(REPLY_WITHIN, connection.closed()).await;
+            let mut waiting = waiting.lock().expect("not poisoned");
+            // unless it's been answered, and the client's asked again under the same msg_id
+            if waiting
+                .get(&request)
+                .is_some_and(|(reply, _, id)| reply.id() == stream && *id == on)
+            {
+                waiting.remove(&request);
+            }
+        });
+    }
+}
+
+impl Transport for QuicTransport {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        if dst == self.node_id {
+            let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+            let _ = self.loopback.send(frame);
+            return Ok(());
+        }
+        if let Some(peer) = self.peers.get(dst) {
+            // the task only goes away with the runtime, which goes away with us
+            let _ = peer.send(frame.to_vec());
+            return Ok(());
+        }
+
+        let in_reply_to = serde_json::from_slice::<Header>(frame)
+            .ok()
+            .and_then(|header| header.body.in_reply_to);
+        let request = in_reply_to.and_then(|id| {
+            let mut waiting = self.waiting.lock().expect("not poisoned");
+            waiting.remove(&(dst.to_string(), id))
+        });
+        let frame = frame.to_vec();
+        if let Some((mut stream, compression, _)) = request {
+            let frame = compression.pack(frame)?;
+            self.runtime.spawn(async move {
+                if stream.write_all(&frame).await.is_ok() {
+                    let _ = stream.finish();
+                }
+            });
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().expect("not poisoned");
+        let Some(connection) = clients
+            .get(dst)
+            .filter(|connection| connection.close_reason().is_none())
+            .cloned()
+        else {
+            clients.remove(dst);
+            log::warn!("no route to {}, dropping", dst);
+            return Ok(());
+        };
+        let dst = dst.to_string();
+        self.runtime.spawn(async move {
+            if let Err(e) = send_on(&connection, &frame).await {
+                log::warn!("send to {}: {:#}", dst, e);
+            }
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::codec::{self, Codec, Compression};
+use super::mux::Link;
+use super::quality::LOST_AFTER;
+use super::{Cluster, Transport};
+
+/// A kind of connected byte stream a [`StreamTransport`] can run over.
+pub trait Socket: Read + Write + Send + Sized + 'static {
+    /// Whatever setting up a connection takes besides an address.
+    type Config: Clone + Send + 'static;
+
+    /// Starts listening on `addr`, handing every connection that comes in to `accept` from a
+    /// thread of its own.
+    fn serve(
+        addr: &str,
+        config: &Self::Config,
+        accept: impl FnMut(Self) + Send + 'static,
+    ) -> anyhow::Result<()>;
+
+    fn connect(addr: &str, config: &Self::Config) -> anyhow::Result<Self>;
+
+    fn try_clone(&self) -> std::io::Result<Self>;
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()>;
+}
+
+#[derive(Deserialize)]
+struct Src {
+    src: String,
+}
+
+/// Newline-delimited JSON over a stream socket. Every node listens on its address, and opens a
+/// [`Link`] to each peer the first time it has something to send it, in whichever [`Codec`]
+/// `RUSTENGAN_CODEC` names and compressed as `RUSTENGAN_COMPRESSION` says. Anyone else who
+/// connects is a client: the connection is remembered under the `src` of the first message sent
+/// on it, and replies to that name go back the same way, always as JSON.
+pub struct StreamTransport<S: Socket> {
+    node_id: String,
+    addrs: HashMap<String, String>,
+    peers: HashMap<String, Link>,
+    clients: Arc<Mutex<HashMap<String, S>>>,
+    codec: Box<dyn Codec>,
+    compression: Compression,
+    config: S::Config,
+    // frames we send ourselves skip the network
+    loopback: Sender<String>,
+}
+
+impl<S: Socket> StreamTransport<S> {
+    /// Starts listening on this node's address. Every line that arrives on any connection comes
+    /// out of the returned receiver.
+    pub fn bind(config: &Cluster) -> anyhow::Result<(Self, Receiver<String>)>
+    where
+        S::Config: Default,
+    {
+        Self::bind_with(config, S::Config::default())
+    }
+
+    /// The same, with `socket` for setting up connections.
+    pub fn bind_with(
+        config: &Cluster,
+        socket: S::Config,
+    ) -> anyhow::Result<(Self, Receiver<String>)> {
+        let addrs: HashMap<_, _> = config.nodes.iter().cloned().collect();
+        let addr = &addrs[&config.node_id];
+        let codec = codec::from_env()?;
+        let compression = Compression::from_env()?;
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+
+        let (accept_tx, accept_clients) = (tx.clone(), clients.clone());
+        let nodes: Vec<String> = addrs.keys().cloned().collect();
+        S::serve(addr, &socket, move |stream| {
+            let (tx, clients, nodes) = (accept_tx.clone(), accept_clients.clone(), nodes.clone());
+            std::thread::spawn(move || read_frames(stream, tx, clients, nodes));
+        })?;
+        log::info!("{} listening on {}", config.node_id, addr);
+
+        Ok((
+            Self {
+                node_id: config.node_id.clone(),
+                addrs,
+                peers: HashMap::new(),
+                clients,
+                codec,
+                compression,
+                config: socket,
+                loopback: tx,
+            },
+            rx,
+        ))
+    }
+
+    fn link(&mut self, dst: &str) -> &Link {
+        if !self.peers.contains_key(dst) {
+            let addr = self.addrs[dst].clone();
+            let config = self.config.clone();
+            let handshake = codec::handshake(&*self.codec, self.compression);
+            let compression = self.compression;
+            let link = Link::new(dst, move || {
+                let mut stream = S::connect(&addr, &config)?;
+                stream.write_all(&handshake).context("codec handshake")?;
+                let answers = stream.try_clone().context("clone connection")?;
+                answers.set_read_timeout(Some(LOST_AFTER))?;
+                Ok((compression.writer(stream)?, Box::new(answers)))
+            });
+            self.peers.insert(dst.to_string(), link);
+        }
+        &self.peers[dst]
+    }
+}
+
+fn read_frames<S: Socket>(
+    stream: S,
+    tx: Sender<String>,
+    clients: Arc<Mutex<HashMap<String, S>>>,
+    nodes: Vec<String>,
+) {
+    let (Ok(writer), Ok(mut answer)) = (stream.try_clone(), stream.try_clone()) else {
+        return;
+    };
+    let mut writer = Some(writer);
+    codec::read_frames(&mut BufReader::new(stream), &mut answer, |frame| {
+        // peers answer on connections of their own, so only clients need remembering
+        if let Some(writer) = writer.take() {
+            match serde_json::from_str::<Src>(&frame) {
+                Ok(Src { src }) if !nodes.contains(&src) => {
+                    clients.lock().expect("not poisoned").insert(src, writer);
+                }
+                _ => {}
+            }
+        }
+        tx.send(frame).is_ok()
+    });
+}
+
+impl<S: Socket> Transport for StreamTransport<S> {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        if dst == self.node_id {
+            let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+            let _ = self.loopback.send(frame);
+            return Ok(());
+        }
+        if self.addrs.contains_key(dst) {
+            let encoded = self.compression.pack(self.codec.encode(frame)?)?;
+            self.link(dst).send(encoded);
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().expect("not poisoned");
+        let Some(stream) = clients.get_mut(dst) else {
+            log::warn!("no route to {}, dropping", dst);
+            return Ok(());
+        };
+        if stream
+            .write_all(frame)
+            .and_then(|_| stream.write_all(b"\n"))
+            .is_err()
+        {
+            clients.remove(dst);
+        }
+        Ok(())
+    }
+}
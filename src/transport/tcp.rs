@@ -0,0 +1,51 @@
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use super::stream::{Socket, StreamTransport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Newline-delimited JSON over TCP, with `host:port` addresses.
+pub type TcpTransport = StreamTransport<TcpStream>;
+
+impl Socket for TcpStream {
+    type Config = ();
+
+    fn serve(
+        addr: &str,
+        _: &(),
+        mut accept: impl FnMut(Self) + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(addr).with_context(|| format!("listen on {}", addr))?;
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accept(stream);
+            }
+        });
+        Ok(())
+    }
+
+    fn connect(addr: &str, _: &()) -> anyhow::Result<Self> {
+        let resolved = addr
+            .to_socket_addrs()
+            .with_context(|| format!("resolve {}", addr))?
+            .next()
+            .with_context(|| format!("{} resolves to nothing", addr))?;
+        let stream = TcpStream::connect_timeout(&resolved, CONNECT_TIMEOUT)
+            .with_context(|| format!("connect to {}", addr))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
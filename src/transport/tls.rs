@@ -0,0 +1,262 @@
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig};
+
+use super::stream::{Socket, StreamTransport};
+use crate::config;
+
+// a handshake that hasn't finished by now isn't going to
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// [`super::tcp::TcpTransport`] with every connection, to peers and from clients alike, wrapped
+/// in TLS. Nodes present `RUSTENGAN_TLS_CERT` and trust whatever `RUSTENGAN_TLS_CA` signed;
+/// clients aren't asked for certificates.
+pub type TlsTransport = StreamTransport<TlsStream>;
+
+/// What a node presents, and who it trusts.
+#[derive(Clone)]
+pub struct Tls {
+    server: Arc<ServerConfig>,
+    client: Arc<ClientConfig>,
+}
+
+impl Tls {
+    /// From a PEM certificate chain, its PEM private key, and the PEM certificates of the
+    /// authorities whose peers to trust.
+    pub fn new(cert: &[u8], key: &[u8], ca: &[u8]) -> anyhow::Result<Self> {
+        let chain = CertificateDer::pem_slice_iter(cert)
+            .collect::<Result<Vec<_>, _>>()
+            .context("parse certificate chain")?;
+        anyhow::ensure!(!chain.is_empty(), "no certificate in the certificate chain");
+        let key = PrivateKeyDer::from_pem_slice(key).context("parse private key")?;
+        let mut roots = RootCertStore::empty();
+        for ca in CertificateDer::pem_slice_iter(ca) {
+            roots
+                .add(ca.context("parse ca certificate")?)
+                .context("trust ca certificate")?;
+        }
+        anyhow::ensure!(!roots.is_empty(), "no ca certificate to trust");
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(chain, key)
+            .context("certificate doesn't go with its key")?;
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self {
+            server: Arc::new(server),
+            client: Arc::new(client),
+        })
+    }
+
+    /// The same certificate and trust, with the application protocols to accept and to offer,
+    /// in order of preference.
+    #[cfg(feature = "quic")]
+    pub(super) fn with_alpn(
+        &self,
+        accept: &[&[u8]],
+        offer: &[&[u8]],
+    ) -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let mut server = (*self.server).clone();
+        server.alpn_protocols = accept.iter().map(|alpn| alpn.to_vec()).collect();
+        let mut client = (*self.client).clone();
+        client.alpn_protocols = offer.iter().map(|alpn| alpn.to_vec()).collect();
+        (Arc::new(server), Arc::new(client))
+    }
+
+    /// From the files `RUSTENGAN_TLS_CERT`, `RUSTENGAN_TLS_KEY` and `RUSTENGAN_TLS_CA` name, if
+    /// there's a certificate at all.
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(cert) = config::var::<String>("RUSTENGAN_TLS_CERT")? else {
+            return Ok(None);
+        };
+        let read = |name: &str| -> anyhow::Result<Vec<u8>> {
+            let path: String =
+                config::var(name)?.with_context(|| format!("RUSTENGAN_TLS_CERT needs {}", name))?;
+            std::fs::read(&path).with_context(|| format!("read {}", path))
+        };
+        let cert = std::fs::read(&cert).with_context(|| format!("read {}", cert))?;
+        Self::new(
+            &cert,
+            &read("RUSTENGAN_TLS_KEY")?,
+            &read("RUSTENGAN_TLS_CA")?,
+        )
+        .map(Some)
+    }
+}
+
+/// A TLS connection over TCP. Clones share the one TLS session, so one thread can be blocked
+/// reading it while another writes.
+pub struct TlsStream {
+    tcp: TcpStream,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            {
+                let mut conn = self.conn.lock().expect("not poisoned");
+                match conn.reader().read(buf) {
+                    Ok(n) => return Ok(n),
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e),
+                }
+            }
+            // waiting on the socket without the session locked, so writes can go on meanwhile
+            let mut raw = [0; 16 * 1024];
+            let n = self.tcp.read(&mut raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            let mut conn = self.conn.lock().expect("not poisoned");
+            let mut rest = &raw[..n];
+            while !rest.is_empty() {
+                conn.read_tls(&mut rest)?;
+                conn.process_new_packets()
+                    .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+            }
+            // the handshake and key updates don't wait for someone to write
+            while conn.wants_write() {
+                conn.write_tls(&mut self.tcp)?;
+            }
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut conn = self.conn.lock().expect("not poisoned");
+        let n = conn.writer().write(buf)?;
+        while conn.wants_write() {
+            conn.write_tls(&mut self.tcp)?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let mut conn = self.conn.lock().expect("not poisoned");
+        conn.writer().flush()?;
+        while conn.wants_write() {
+            conn.write_tls(&mut self.tcp)?;
+        }
+        Ok(())
+    }
+}
+
+impl Socket for TlsStream {
+    type Config = Tls;
+
+    fn serve(
+        addr: &str,
+        tls: &Tls,
+        mut accept: impl FnMut(Self) + Send + 'static,
+    ) -> anyhow::Result<()> {
+        let server = tls.server.clone();
+        <TcpStream as Socket>::serve(addr, &(), move |tcp| {
+            // the handshake happens as the first frame is read
+            match rustls::ServerConnection::new(server.clone()) {
+                Ok(conn) => accept(Self {
+                    tcp,
+                    conn: Arc::new(Mutex::new(conn.into())),
+                }),
+                Err(e) => log::warn!("tls session for a connection: {}", e),
+            }
+        })
+    }
+
+    fn connect(addr: &str, tls: &Tls) -> anyhow::Result<Self> {
+        let mut tcp = <TcpStream as Socket>::connect(addr, &())?;
+        let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+        let name = ServerName::try_from(host.to_string())
+            .with_context(|| format!("{} isn't a server name", host))?;
+        let mut conn =
+            ClientConnection::new(tls.client.clone(), name).context("start tls session")?;
+        // peers only ever write to the connections they open, so it has to be done up front
+        tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp)
+                .with_context(|| format!("tls handshake with {}", addr))?;
+        }
+        tcp.set_read_timeout(None)?;
+        Ok(Self {
+            tcp,
+            conn: Arc::new(Mutex::new(conn.into())),
+        })
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        Ok(Self {
+            tcp: self.tcp.try_clone()?,
+            conn: self.conn.clone(),
+        })
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        self.tcp.set_read_timeout(timeout)
+    }
+}
+
+/// A throwaway certificate authority, for clusters that need TLS but not real certificates:
+/// tests, and trying things out locally.
+pub struct TestCa {
+    params: rcgen::CertificateParams,
+    key: rcgen::KeyPair,
+    cert: rcgen::Certificate,
+}
+
+impl TestCa {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new())?;
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "rustengan test ca");
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::DigitalSignature,
+        ];
+        let key = rcgen::KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+        Ok(Self { params, key, cert })
+    }
+
+    /// The authority's own certificate, in PEM, for whoever is to trust it.
+    pub fn ca_pem(&self) -> String {
+        self.cert.pem()
+    }
+
+    /// A PEM certificate and its PEM private key for `names`, which are host names or IP
+    /// addresses.
+    pub fn issue(&self, names: &[&str]) -> anyhow::Result<(String, String)> {
+        let names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        let mut params = rcgen::CertificateParams::new(names)?;
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "rustengan node");
+        params.extended_key_usages = vec![
+            rcgen::ExtendedKeyUsagePurpose::ServerAuth,
+            rcgen::ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        let key = rcgen::KeyPair::generate()?;
+        let issuer = rcgen::Issuer::from_params(&self.params, &self.key);
+        let cert = params.signed_by(&key, &issuer)?;
+        Ok((cert.pem(), key.serialize_pem()))
+    }
+
+    /// A certificate for `names`, and trust in this authority.
+    pub fn tls(&self, names: &[&str]) -> anyhow::Result<Tls> {
+        let (cert, key) = self.issue(names)?;
+        Tls::new(cert.as_bytes(), key.as_bytes(), self.ca_pem().as_bytes())
+    }
+}
@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use super::{Cluster, Transport};
+
+// the most a single UDP datagram can carry over IPv4
+const MAX_DATAGRAM: usize = 65507;
+
+#[derive(Deserialize)]
+struct Src {
+    src: String,
+}
+
+/// One JSON message per UDP datagram. Nothing is done to make delivery reliable here: datagrams
+/// get lost, duplicated and reordered for real, and it's the nodes' own machinery (retrying
+/// until acked, asking a quorum again, anti-entropy) that has to cope, the same as it does with
+/// the faults Maelstrom injects. Clients are whoever sends us a datagram under a `src` that
+/// isn't a node; replies go back to the address it last came from.
+pub struct UdpTransport {
+    node_id: String,
+    socket: UdpSocket,
+    addrs: HashMap<String, SocketAddr>,
+    clients: Arc<Mutex<HashMap<String, SocketAddr>>>,
+    // frames we send ourselves skip the network
+    loopback: Sender<String>,
+}
+
+impl UdpTransport {
+    /// Binds this node's address. Every datagram that arrives comes out of the returned receiver.
+    pub fn bind(config: &Cluster) -> anyhow::Result<(Self, Receiver<String>)> {
+        let mut addrs = HashMap::new();
+        for (name, addr) in &config.nodes {
+            let resolved = addr
+                .to_socket_addrs()
+                .with_context(|| format!("resolve {}", addr))?
+                .next()
+                .with_context(|| format!("{} resolves to nothing", addr))?;
+            addrs.insert(name.clone(), resolved);
+        }
+        let addr = addrs[&config.node_id];
+        let socket = UdpSocket::bind(addr).with_context(|| format!("bind {}", addr))?;
+        log::info!("{} listening on udp {}", config.node_id, addr);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clients = Arc::new(Mutex::new(HashMap::new()));
+
+        let reader = socket.try_clone().context("clone udp socket")?;
+        let (reader_tx, reader_clients) = (tx.clone(), clients.clone());
+        let nodes: Vec<String> = addrs.keys().cloned().collect();
+        std::thread::spawn(move || {
+            let mut buf = vec![0; MAX_DATAGRAM];
+            loop {
+                let (len, from) = match reader.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    // on linux an earlier send to a closed port can surface here; it's not ours
+                    Err(_) => continue,
+                };
+                let Ok(frame) = std::str::from_utf8(&buf[..len]) else {
+                    continue;
+                };
+                let frame = frame.trim_end().to_string();
+                match serde_json::from_str::<Src>(&frame) {
+                    Ok(Src { src }) if !nodes.contains(&src) => {
+                        reader_clients
+                            .lock()
+                            .expect("not poisoned")
+                            .insert(src, from);
+                    }
+                    Ok(_) => {}
+                    Err(_) => continue,
+                }
+                if reader_tx.send(frame).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok((
+            Self {
+                node_id: config.node_id.clone(),
+                socket,
+                addrs,
+                clients,
+                loopback: tx,
+            },
+            rx,
+        ))
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        if dst == self.node_id {
+            let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+            let _ = self.loopback.send(frame);
+            return Ok(());
+        }
+        let addr = match self.addrs.get(dst) {
+            Some(addr) => *addr,
+            None => match self.clients.lock().expect("not poisoned").get(dst) {
+                Some(addr) => *addr,
+                None => {
+                    log::warn!("no route to {}, dropping", dst);
+                    return Ok(());
+                }
+            },
+        };
+        // retrying can't get this one through, so say so rather than drop it quietly
+        if frame.len() > MAX_DATAGRAM {
+            log::warn!(
+                "{} byte message to {} doesn't fit in a datagram, dropping",
+                frame.len(),
+                dst
+            );
+            return Ok(());
+        }
+        if let Err(e) = self.socket.send_to(frame, addr) {
+            log::warn!("send to {}: {}, dropping", dst, e);
+        }
+        Ok(())
+    }
+}
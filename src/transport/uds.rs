@@ -0,0 +1,50 @@
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use super::stream::{Socket, StreamTransport};
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+
+/// Newline-delimited JSON over Unix domain sockets, for a cluster on one machine. Addresses are
+/// socket paths, e.g. `n0=/tmp/cluster/n0.sock`.
+pub type UdsTransport = StreamTransport<UnixStream>;
+
+impl Socket for UnixStream {
+    type Config = ();
+
+    fn serve(
+        addr: &str,
+        _: &(),
+        mut accept: impl FnMut(Self) + Send + 'static,
+    ) -> anyhow::Result<()> {
+        // whatever a previous run of this node left behind would keep us from binding
+        match std::fs::remove_file(addr) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("remove stale socket {}", addr)),
+        }
+        let listener = UnixListener::bind(addr).with_context(|| format!("listen on {}", addr))?;
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                accept(stream);
+            }
+        });
+        Ok(())
+    }
+
+    fn connect(addr: &str, _: &()) -> anyhow::Result<Self> {
+        let stream = UnixStream::connect(addr).with_context(|| format!("connect to {}", addr))?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        Ok(stream)
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> std::io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::Deserialize;
+use tungstenite::{Message, WebSocket};
+
+use super::backoff::Backoff;
+use super::quality::{self, Probes, LOST_AFTER};
+use super::{Cluster, Transport};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(1000);
+// how often a connection to a peer pings it, each ping carrying a sequence number for the pong
+// to echo
+const PING_EVERY: Duration = Duration::from_millis(1000);
+// how many frames wait for a peer while it can't be reached, before any more are dropped
+const PEER_QUEUED: usize = 16 * 1024;
+// a socket can't be read and written from two threads at once, so each connection's thread
+// waits on reads this long at a time before checking whether it has anything to send
+const POLL: Duration = Duration::from_millis(5);
+
+type Routes = Arc<Mutex<HashMap<String, Sender<String>>>>;
+
+#[derive(Deserialize)]
+struct Src {
+    src: String,
+}
+
+/// The same JSON messages as ever, one per WebSocket text frame, so a browser can talk to a node
+/// directly. Every node serves WebSocket on its address, and peers reach each other as WebSocket
+/// clients of one another. Like [`super::tcp::TcpTransport`], a connection from anyone who isn't
+/// a node is remembered under the `src` of its first message, and a connection to a peer is
+/// made again, backing off, whenever it's lost, with frames for the peer waiting until it is.
+/// Connections to peers ping them every second, and what the pongs say of the link goes to
+/// [`quality`].
+pub struct WebSocketTransport {
+    node_id: String,
+    addrs: HashMap<String, String>,
+    // frames for a peer go to the thread that keeps a connection to it
+    peers: HashMap<String, SyncSender<String>>,
+    clients: Routes,
+    incoming: Sender<String>,
+}
+
+impl WebSocketTransport {
+    /// Starts serving WebSocket on this node's address. Every text frame that arrives on any
+    /// connection comes out of the returned receiver.
+    pub fn bind(config: &Cluster) -> anyhow::Result<(Self, Receiver<String>)> {
+        let addrs: HashMap<_, _> = config.nodes.iter().cloned().collect();
+        let addr = &addrs[&config.node_id];
+        let listener = TcpListener::bind(addr).with_context(|| format!("listen on {}", addr))?;
+        log::info!("{} serving websocket on {}", config.node_id, addr);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let clients: Routes = Arc::new(Mutex::new(HashMap::new()));
+
+        let (accept_tx, accept_clients) = (tx.clone(), clients.clone());
+        let nodes: Vec<String> = addrs.keys().cloned().collect();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else {
+                    continue;
+                };
+                let (tx, clients, nodes) =
+                    (accept_tx.clone(), accept_clients.clone(), nodes.clone());
+                std::thread::spawn(move || {
+                    let Ok(ws) = tungstenite::accept(stream) else {
+                        return;
+                    };
+                    let (out_tx, out_rx) = std::sync::mpsc::channel();
+                    // `out_tx` lives as long as the connection, so one from a peer, which nothing
+                    // goes back on, isn't taken for one whose client has gone
+                    let mut register = Some(out_tx.clone());
+                    pump(ws, &out_rx, &tx, &mut None, None, |src| {
+                        // peers answer on connections of their own, so only clients need
+                        // remembering
+                        if let Some(out_tx) = register.take() {
+                            if !nodes.iter().any(|n| n == src) {
+                                clients
+                                    .lock()
+                                    .expect("not poisoned")
+                                    .insert(src.to_string(), out_tx);
+                            }
+                        }
+                    });
+                });
+            }
+        });
+
+        Ok((
+            Self {
+                node_id: config.node_id.clone(),
+                addrs,
+                peers: HashMap::new(),
+                clients,
+                incoming: tx,
+            },
+            rx,
+        ))
+    }
+
+    fn peer(&mut self, dst: &str) -> &SyncSender<String> {
+        if !self.peers.contains_key(dst) {
+            let (out_tx, out_rx) = std::sync::mpsc::sync_channel(PEER_QUEUED);
+            let (peer, addr, incoming) = (
+                dst.to_string(),
+                self.addrs[dst].clone(),
+                self.incoming.clone(),
+            );
+            std::thread::spawn(move || keep_peer(&peer, &addr, &out_rx, &incoming));
+            self.peers.insert(dst.to_string(), out_tx);
+        }
+        &self.peers[dst]
+    }
+}
+
+// keeps a connection to the peer at `addr` for as long as there's anything to send it for
+fn keep_peer(peer: &str, addr: &str, outgoing: &Receiver<String>, incoming: &Sender<String>) {
+    let mut backoff = Backoff::default();
+    let mut held = None;
+    loop {
+        match open(addr) {
+            Ok(ws) => {
+                backoff.reset();
+                let probes = Some(Probes::new(peer));
+                if !pump(ws, outgoing, incoming, &mut held, probes, |_| {}) {
+                    return;
+                }
+                log::warn!("lost connection to {}", peer);
+            }
+            Err(e) => {
+                log::warn!("can't reach {}: {:#}", peer, e);
+                quality::lost(peer, 1);
+                std::thread::sleep(backoff.wait());
+            }
+        }
+    }
+}
+
+fn open(addr: &str) -> anyhow::Result<WebSocket<TcpStream>> {
+    let resolved = addr
+        .to_socket_addrs()
+        .with_context(|| format!("resolve {}", addr))?
+        .next()
+        .with_context(|| format!("{} resolves to nothing", addr))?;
+    let stream = TcpStream::connect_timeout(&resolved, CONNECT_TIMEOUT)
+        .with_context(|| format!("connect to {}", addr))?;
+    stream.set_read_timeout(Some(CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    let (ws, _) = tungstenite::client::client(format!("ws://{}/", addr), stream)
+        .map_err(|e| anyhow::anyhow!("websocket handshake with {}: {}", addr, e))?;
+    Ok(ws)
+}
+
+// owns one connection until either side goes away: sends `held`, if there's a frame in it, and
+// whatever turns up on `outgoing`, and hands every text frame read to `incoming`, telling `seen`
+// who it's from first. With `probes`, the connection is pinged every so often, and given up on
+// if a ping goes unanswered. A frame that couldn't be sent is left in `held`. False once
+// `outgoing` is gone, true if the connection went first.
+fn pump(
+    mut ws: WebSocket<TcpStream>,
+    outgoing: &Receiver<String>,
+    incoming: &Sender<String>,
+    held: &mut Option<String>,
+    mut probes: Option<Probes>,
+    mut seen: impl FnMut(&str),
+) -> bool {
+    if ws.get_mut().set_read_timeout(Some(POLL)).is_err()
+        || ws.get_mut().set_write_timeout(Some(WRITE_TIMEOUT)).is_err()
+    {
+        return true;
+    }
+    let mut pinged = Instant::now();
+    loop {
+        loop {
+            let frame = match held.take().map_or_else(|| outgoing.try_recv(), Ok) {
+                Ok(frame) => frame,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let _ = ws.close(None);
+                    return false;
+                }
+            };
+            if ws.write(Message::text(frame.as_str())).is_err() {
+                *held = Some(frame);
+                return true;
+            }
+        }
+        if let Some(probes) = &mut probes {
+            if probes
+                .unanswered()
+                .is_some_and(|waited| waited >= LOST_AFTER)
+            {
+                log::warn!("no answer to a ping in {:?}", LOST_AFTER);
+                return true;
+            }
+            if pinged.elapsed() >= PING_EVERY {
+                pinged = Instant::now();
+                let seq = probes.send().to_be_bytes();
+                if ws.write(Message::Ping(seq.to_vec().into())).is_err() {
+                    return true;
+                }
+            }
+        }
+        if ws.flush().is_err() {
+            return true;
+        }
+        match ws.read() {
+            Ok(message) => {
+                let frame = match message {
+                    Message::Text(frame) => frame,
+                    Message::Pong(seq) => {
+                        if let (Some(probes), Ok(seq)) = (&mut probes, <[u8; 8]>::try_from(&*seq)) {
+                            probes.answer(u64::from_be_bytes(seq));
+                        }
+                        continue;
+                    }
+                    _ => continue,
+                };
+                if let Ok(Src { src }) = serde_json::from_str(frame.as_str()) {
+                    seen(&src);
+                }
+                if incoming.send(frame.as_str().to_string()).is_err() {
+                    return false;
+                }
+            }
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(_) => return true,
+        }
+    }
+}
+
+impl Transport for WebSocketTransport {
+    fn send(&mut self, dst: &str, frame: &[u8]) -> anyhow::Result<()> {
+        let frame = String::from_utf8(frame.to_vec()).context("frame isn't utf-8")?;
+        if dst == self.node_id {
+            let _ = self.incoming.send(frame);
+            return Ok(());
+        }
+        if self.addrs.contains_key(dst) {
+            match self.peer(dst).try_send(frame) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    log::warn!("{} is backed up, dropping a frame", dst);
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    self.peers.remove(dst);
+                }
+            }
+            return Ok(());
+        }
+        let mut clients = self.clients.lock().expect("not poisoned");
+        let Some(client) = clients.get(dst) else {
+            log::warn!("no route to {}, dropping", dst);
+            return Ok(());
+        };
+        if client.send(frame).is_err() {
+            clients.remove(dst);
+        }
+        Ok(())
+    }
+}
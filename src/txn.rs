@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OpKind {
+    R,
+    W,
+}
+
+/// A single micro-operation of a maelstrom `txn` request, on the wire as `["r", key, null]` or
+/// `["w", key, value]`. Reads get their value filled in on the way back.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Op(pub OpKind, pub usize, pub Option<usize>);
+
+impl Op {
+    pub fn key(&self) -> usize {
+        self.1
+    }
+
+    pub fn is_write(&self) -> bool {
+        self.0 == OpKind::W
+    }
+}
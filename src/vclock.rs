@@ -0,0 +1,87 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use serde::{Deserialize, Serialize};
+
+/// Vector clock: for every node, how many of its events happened before this point. Nodes that
+/// aren't mentioned are at zero.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VClock(HashMap<String, u64>);
+
+impl VClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, node: &str) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    /// Records a new event on `node` and returns its position in that node's history.
+    pub fn increment(&mut self, node: &str) -> u64 {
+        let count = self.0.entry(node.to_string()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    /// How many events the clock has seen across all nodes. Anything that happened after has a
+    /// higher total, so ordering by it never contradicts causality.
+    pub fn total(&self) -> u64 {
+        self.0.values().sum()
+    }
+
+    /// Takes the entrywise maximum, leaving us with a clock that has seen everything either did.
+    pub fn merge(&mut self, other: &VClock) {
+        for (node, &count) in &other.0 {
+            let ours = self.0.entry(node.clone()).or_insert(0);
+            *ours = (*ours).max(count);
+        }
+    }
+
+    /// `Less` if we happened before `other`, `Greater` if after, `None` if the two are concurrent.
+    pub fn compare(&self, other: &VClock) -> Option<Ordering> {
+        let mut ordering = Ordering::Equal;
+        for node in self.0.keys().chain(other.0.keys()) {
+            match (ordering, self.get(node).cmp(&other.get(node))) {
+                (_, Ordering::Equal) => {}
+                (Ordering::Equal, entry) => ordering = entry,
+                (ordering, entry) if ordering != entry => return None,
+                _ => {}
+            }
+        }
+        Some(ordering)
+    }
+
+    /// Whether `other` has seen everything we have, and more.
+    pub fn happened_before(&self, other: &VClock) -> bool {
+        self.compare(other) == Some(Ordering::Less)
+    }
+
+    pub fn concurrent(&self, other: &VClock) -> bool {
+        self.compare(other).is_none()
+    }
+}
+
+impl PartialEq for VClock {
+    fn eq(&self, other: &Self) -> bool {
+        self.compare(other) == Some(Ordering::Equal)
+    }
+}
+
+impl Eq for VClock {}
+
+impl PartialOrd for VClock {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.compare(other)
+    }
+}
+
+// consistent with `Eq`, which treats nodes that aren't mentioned as being at zero
+impl Hash for VClock {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut entries: Vec<_> = self.0.iter().filter(|(_, &count)| count > 0).collect();
+        entries.sort();
+        entries.hash(state);
+    }
+}
@@ -0,0 +1,155 @@
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Serialize};
+
+// the data directory of the simulation stepping or starting a node on this thread
+thread_local! {
+    static SIMULATED: RefCell<Option<PathBuf>> = const { RefCell::new(None) };
+}
+
+// this process's own data directory, when nobody named one
+static SCRATCH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where a node keeps what has to survive it crashing. Under the simulator it's the
+/// simulation's own.
+///
+/// Otherwise `RUSTENGAN_DATA_DIR` names it, and a node started on one comes back to whatever it
+/// logged there before, whether Maelstrom is restarting it after a kill or it's another run
+/// altogether, so a run that should start from nothing wants a directory of its own. Without
+/// it, the process keeps its logs in a scratch directory under the system's temporary
+/// directory, named for the process and when it started, which nothing else ever opens and
+/// [`main_loop`](crate::main_loop) removes on the way out: a node that's killed and restarted
+/// comes back with nothing, so durability across restarts is opt-in.
+pub fn data_dir() -> PathBuf {
+    if let Some(dir) = SIMULATED.with_borrow(|dir| dir.clone()) {
+        return dir;
+    }
+    if let Some(dir) = std::env::var_os("RUSTENGAN_DATA_DIR") {
+        return PathBuf::from(dir);
+    }
+    SCRATCH
+        .get_or_init(|| {
+            let started = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            std::env::temp_dir().join(format!(
+                "rustengan-{}-{}",
+                std::process::id(),
+                started.as_nanos()
+            ))
+        })
+        .clone()
+}
+
+// removes the scratch directory, if this process made one
+pub(crate) fn remove_scratch() {
+    if let Some(dir) = SCRATCH.get() {
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
+
+// has `data_dir` answer with `dir` on this thread, until it's `None` again
+pub(crate) fn simulate(dir: Option<PathBuf>) {
+    SIMULATED.set(dir);
+}
+
+/// Append-only log of JSON records, one per line. Every append is fsync'd before it returns so a
+/// record that made it into the log is never lost to a crash.
+pub struct Wal<R> {
+    path: PathBuf,
+    file: File,
+    _record: PhantomData<R>,
+}
+
+impl<R> Wal<R>
+where
+    R: Serialize + DeserializeOwned,
+{
+    /// Opens (or creates) the log at `path` and returns it together with every record already in it.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<(Self, Vec<R>)> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("create wal directory {}", dir.display()))?;
+        }
+        let mut records = Vec::new();
+        let mut torn_tail = false;
+        if path.exists() {
+            let contents = std::fs::read_to_string(path)
+                .with_context(|| format!("read {}", path.display()))?;
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                // a crash in the middle of an append leaves a torn line behind. it can only ever
+                // be the last one, since we terminate it below before appending anything else.
+                match serde_json::from_str(line) {
+                    Ok(record) => records.push(record),
+                    Err(_) => log::warn!("ignoring torn wal record in {}", path.display()),
+                }
+            }
+            torn_tail = !contents.is_empty() && !contents.ends_with('\n');
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open {} for append", path.display()))?;
+        if torn_tail {
+            file.write_all(b"\n").context("terminate torn wal record")?;
+        }
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                file,
+                _record: PhantomData,
+            },
+            records,
+        ))
+    }
+
+    pub fn append(&mut self, record: &R) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record).context("serialize wal record")?;
+        line.push(b'\n');
+        self.file.write_all(&line).context("write wal record")?;
+        self.file.sync_data().context("sync wal")?;
+        Ok(())
+    }
+
+    /// Replaces everything in the log with `records`, which should be enough to recover the same
+    /// state from. The new log is written out beside the old one and renamed over it, so a crash
+    /// part way through leaves one or the other, never a mix.
+    pub fn rewrite(&mut self, records: &[R]) -> anyhow::Result<()> {
+        let mut name = self
+            .path
+            .file_name()
+            .expect("wal has a file name")
+            .to_owned();
+        name.push(".tmp");
+        let tmp = self.path.with_file_name(name);
+        let mut contents = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut contents, record).context("serialize wal record")?;
+            contents.push(b'\n');
+        }
+        let mut file = File::create(&tmp).with_context(|| format!("create {}", tmp.display()))?;
+        file.write_all(&contents).context("write rewritten wal")?;
+        file.sync_all().context("sync rewritten wal")?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("rename {} into place", tmp.display()))?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .context("sync wal directory")?;
+        }
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("open {} for append", self.path.display()))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+#[allow(dead_code)]
+#[path = "../src/bin/abd.rs"]
+mod abd;
+
+use std::time::Duration;
+
+use abd::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::history::linearizable::check;
+use rustengan::history::{Op, Type};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, abd::InjectedPayload>;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), abd::AbdNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(100), || abd::InjectedPayload::Tick);
+    sim
+}
+
+// clients reading and writing a couple of keys through any node, while the nodes lose, repeat and
+// reorder what they send each other, are partitioned from each other over and over, and one of
+// them crashes for good: the registers are in memory, so a replica that came back would have
+// forgotten what it stored. A client waits up to a second for an answer before it gives up and
+// asks for something else.
+fn abd_history(seed: u64) -> Vec<Op> {
+    let mut sim = cluster(seed);
+    sim.faults(Faults {
+        drop: 0.1,
+        duplicate: 0.1,
+        reorder: 0.1,
+        ..Faults::default()
+    });
+    sim.nemesis(Nemesis::partitions(
+        Split::Halves,
+        Duration::from_millis(2000),
+        Duration::from_millis(1500),
+    ));
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut up = NODES.to_vec();
+    let mut asked = [None; 3];
+    while sim.now() < Duration::from_secs(12) {
+        if up.len() == NODES.len() && sim.now() >= Duration::from_secs(4) {
+            let crashed = up.remove(rng.gen_range(0..up.len()));
+            sim.disrupt(Disruption::Kill(Target::Node(crashed.to_string())))
+                .expect("kills");
+        }
+        for (client, asked) in asked.iter_mut().enumerate() {
+            let client = format!("c{}", client);
+            if !sim.take_replies(&client).expect("replies parse").is_empty() {
+                *asked = None;
+            }
+            if asked.is_some_and(|at| sim.now() - at < Duration::from_secs(1)) {
+                continue;
+            }
+            *asked = Some(sim.now());
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..2) {
+                0 => Payload::Read { key },
+                _ => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+            };
+            let dst = up[rng.gen_range(0..up.len())];
+            sim.send(&client, dst, request).expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.history()
+}
+
+#[test]
+fn abd_stays_linearizable_through_a_lossy_network_partitions_and_a_crash() {
+    for seed in [1, 2, 3] {
+        let history = abd_history(seed);
+        assert_eq!(check(&history), Ok(()), "seed {}", seed);
+        assert!(
+            history.iter().any(|op| op.kind == Type::Ok),
+            "seed {}: nothing was answered",
+            seed
+        );
+    }
+}
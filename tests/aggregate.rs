@@ -0,0 +1,173 @@
+#[allow(dead_code)]
+#[path = "../src/bin/aggregate.rs"]
+mod aggregate;
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use aggregate::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Nemesis, Split};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, aggregate::InjectedPayload>;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+// the default window is a second, and events fall in the first few
+const WINDOWS: u64 = 5;
+
+// a window's count, sum, min and max, as a node answered or as the events sent add up to
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Totals {
+    count: u64,
+    sum: f64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+impl Totals {
+    fn new() -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            min: None,
+            max: None,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = Some(self.min.map_or(value, |m| m.min(value)));
+        self.max = Some(self.max.map_or(value, |m| m.max(value)));
+    }
+}
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), aggregate::AggregateNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(200), || {
+        aggregate::InjectedPayload::Gossip
+    });
+    sim
+}
+
+// what `via` makes of a window, with its percentiles, if it answers
+fn query(sim: &mut Cluster, via: &str, window: u64) -> Option<(Totals, [Option<f64>; 3])> {
+    sim.take_replies("reader").expect("replies parse");
+    let request = Payload::Query {
+        ts_ms: Some(window * 1000),
+    };
+    sim.send("reader", via, request).expect("request sends");
+    sim.run_for(Duration::from_millis(100)).expect("nodes step");
+    match sim
+        .take_replies("reader")
+        .expect("replies parse")
+        .pop()?
+        .body
+        .payload
+    {
+        Payload::QueryOk {
+            count,
+            sum,
+            min,
+            max,
+            p50,
+            p90,
+            p99,
+            ..
+        } => Some((
+            Totals {
+                count,
+                sum,
+                min,
+                max,
+            },
+            [p50, p90, p99],
+        )),
+        _ => None,
+    }
+}
+
+// a writer sending whole numbers stamped into the first few windows to any node, while the nodes
+// lose, repeat and reorder the partials they gossip, and are partitioned from each other. No query
+// can count an event twice, or one nobody sent; once the faults are over and the nodes have had a
+// while to gossip, every node has to answer for every window with exactly the events sent for it.
+#[test]
+fn every_node_counts_every_event_exactly_once_through_a_lossy_network_and_partitions() {
+    for seed in [1, 2, 3] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(1000),
+                Duration::from_millis(1500),
+            )
+            .until(Duration::from_secs(8)),
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut sent = BTreeMap::new();
+        while sim.now() < Duration::from_secs(8) {
+            let ts_ms = rng.gen_range(0..WINDOWS * 1000);
+            let value = rng.gen_range(1..100) as f64;
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send("writer", dst, Payload::Ingest { ts_ms, value })
+                .expect("request sends");
+            sent.entry(ts_ms / 1000)
+                .or_insert_with(Totals::new)
+                .add(value);
+            sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+                .expect("nodes step");
+
+            let via = NODES[rng.gen_range(0..NODES.len())];
+            let window = rng.gen_range(0..WINDOWS);
+            let Some((answered, _)) = query(&mut sim, via, window) else {
+                continue;
+            };
+            let sent = sent.get(&window).copied().unwrap_or_else(Totals::new);
+            assert!(
+                answered.count <= sent.count && answered.sum <= sent.sum,
+                "seed {}: {} counts {:?} in window {} of {:?} sent",
+                seed,
+                via,
+                answered,
+                window,
+                sent
+            );
+        }
+        let acked = sim
+            .take_replies("writer")
+            .expect("replies parse")
+            .into_iter()
+            .filter(|reply| matches!(reply.body.payload, Payload::IngestOk))
+            .count();
+        let total: u64 = sent.values().map(|t| t.count).sum();
+        assert_eq!(acked as u64, total, "seed {}: events taken in", seed);
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+        for (window, sent) in &sent {
+            let settled = query(&mut sim, "n0", *window).expect("n0 answers");
+            assert_eq!(settled.0, *sent, "seed {}: window {} on n0", seed, window);
+            for node in NODES {
+                assert_eq!(
+                    query(&mut sim, node, *window),
+                    Some(settled),
+                    "seed {}: window {} on {}",
+                    seed,
+                    window,
+                    node
+                );
+            }
+        }
+    }
+}
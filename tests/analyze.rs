@@ -0,0 +1,116 @@
+use std::path::PathBuf;
+use std::process::Command;
+
+const RESULTS: &str = r#"{:perf {:valid? true}
+ :stats {:valid? true, :count 3, :ok-count 2, :fail-count 1, :info-count 0}
+ :net {:all {:send-count 20, :recv-count 20, :msg-count 20, :msgs-per-op 6.6666665},
+       :clients {:send-count 6, :recv-count 6, :msg-count 6},
+       :servers {:send-count 14, :recv-count 14, :msg-count 14, :msgs-per-op 4.6666665}
+       :valid? true}
+ :workload {:valid? true}
+ :valid? true}
+"#;
+
+const JEPSEN_LOG: &str = r#"INFO [2024-01-01 12:00:00,000] jepsen worker 0 - maelstrom.net.journal :send {:id 1, :src "c1", :dest "n0", :body {:type "write", :key 0, :value 1, :msg_id 1}}
+INFO [2024-01-01 12:00:00,001] jepsen worker 0 - maelstrom.net.journal :recv {:id 1, :src "c1", :dest "n0", :body {:type "write", :key 0, :value 1, :msg_id 1}}
+INFO [2024-01-01 12:00:00,002] jepsen worker 0 - maelstrom.net.journal :send {:id 2, :src "n0", :dest "n1", :body {:type "replicate", :key 0, :value 1}}
+INFO [2024-01-01 12:00:00,003] jepsen worker 0 - maelstrom.net.journal :recv {:id 2, :src "n0", :dest "n1", :body {:type "replicate", :key 0, :value 1}}
+INFO [2024-01-01 12:00:00,004] jepsen worker 0 - maelstrom.net.journal :send {:id 3, :src "n0", :dest "c1", :body {:type "write_ok", :in_reply_to 1}}
+"#;
+
+// a write, a read of it, and a cas that fails, by two processes
+const HISTORY: &str = r#"{:type :invoke, :f :write, :value [0 1], :time 1000000, :process 0, :index 0}
+{:type :ok, :f :write, :value [0 1], :time 3000000, :process 0, :index 1}
+{:type :invoke, :f :read, :value [0 nil], :time 4000000, :process 1, :index 2}
+{:type :ok, :f :read, :value [0 1], :time 5000000, :process 1, :index 3}
+{:type :invoke, :f :cas, :value [0 [2 3]], :time 6000000, :process 0, :index 4}
+{:type :fail, :f :cas, :value [0 [2 3]], :time 8000000, :process 0, :index 5, :error [:precondition-failed "expected 2, but had 1"]}
+"#;
+
+// a store directory with `history` in it, and everything else a lin-kv run leaves
+fn store(name: &str, history: &str) -> PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("rustengan-analyze-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("node-logs")).expect("make store");
+    std::fs::write(dir.join("results.edn"), RESULTS).expect("write results");
+    std::fs::write(dir.join("jepsen.log"), JEPSEN_LOG).expect("write jepsen.log");
+    std::fs::write(dir.join("history.edn"), history).expect("write history");
+    std::fs::write(
+        dir.join("node-logs").join("n0.log"),
+        "INFO n0 up\nWARN slow replica n1\nsomething not logged through log\n",
+    )
+    .expect("write n0's log");
+    std::fs::write(dir.join("node-logs").join("n1.log"), "INFO n1 up\n").expect("write n1's log");
+    dir
+}
+
+fn analyze(store: &PathBuf) -> (bool, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_analyze"))
+        .arg(store)
+        .output()
+        .expect("analyze runs");
+    let _ = std::fs::remove_dir_all(store);
+    let stdout = String::from_utf8(output.stdout).expect("report is utf-8");
+    (output.status.success(), stdout)
+}
+
+#[test]
+fn sums_up_a_kv_run() {
+    let (ok, report) = analyze(&store("valid", HISTORY));
+    assert!(ok, "{}", report);
+    for line in [
+        "maelstrom: valid, 2/3 ok, 1 failed, 0 indefinite",
+        "  servers: 14 msgs, 4.7 msgs/op",
+        "6 history entries",
+        "3 kv ops, linearizable",
+    ] {
+        assert!(report.contains(line), "no {:?} in:\n{}", line, report);
+    }
+    let n0 = report
+        .lines()
+        .find(|line| line.starts_with("n0 "))
+        .expect("n0's line");
+    assert_eq!(
+        n0.split_whitespace().collect::<Vec<_>>(),
+        ["n0", "2", "1", "3", "INFO", "1,", "WARN", "1"]
+    );
+    let n1 = report
+        .lines()
+        .find(|line| line.starts_with("n1 "))
+        .expect("n1's line");
+    assert_eq!(
+        n1.split_whitespace().collect::<Vec<_>>(),
+        ["n1", "0", "1", "1", "INFO", "1"]
+    );
+    assert!(
+        !report.lines().any(|line| line.starts_with("c1 ")),
+        "{}",
+        report
+    );
+    let write = report
+        .lines()
+        .find(|line| line.starts_with("write "))
+        .expect("write latencies");
+    assert_eq!(
+        write.split_whitespace().collect::<Vec<_>>(),
+        ["write", "1", "2.0", "2.0", "2.0", "2.0"]
+    );
+    assert!(
+        report.contains("1 fail cas: precondition-failed"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn a_stale_read_fails_the_run() {
+    // the read comes after the write's done, but sees nothing
+    let stale = HISTORY.replace(
+        ":type :ok, :f :read, :value [0 1]",
+        ":type :ok, :f :read, :value [0 nil]",
+    );
+    let (ok, report) = analyze(&store("stale", &stale));
+    assert!(!ok, "{}", report);
+    assert!(report.contains("3 kv ops, NOT linearizable"), "{}", report);
+}
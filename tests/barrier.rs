@@ -0,0 +1,144 @@
+#[allow(dead_code)]
+#[path = "../src/bin/barrier.rs"]
+mod barrier;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use barrier::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::error;
+use rustengan::failure_detector::FdEvent;
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Target};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, barrier::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const COUNT: usize = 3;
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), barrier::BarrierNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        barrier::InjectedPayload::Fd(FdEvent::Heartbeat)
+    });
+    sim
+}
+
+fn enter(count: usize, timeout_ms: Option<u64>) -> Payload {
+    Payload::Enter {
+        barrier: "b".to_string(),
+        count,
+        timeout_ms,
+    }
+}
+
+fn answer(sim: &mut Cluster, client: &str) -> Option<Payload> {
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+#[test]
+fn a_participant_that_timed_out_no_longer_counts_towards_the_barrier() {
+    let mut sim = cluster(1);
+    sim.send("c1", "n1", enter(2, Some(500)))
+        .expect("enter sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    assert!(matches!(
+        answer(&mut sim, "c1"),
+        Some(Payload::Error { code, .. }) if code == error::TIMEOUT
+    ));
+
+    sim.send("c2", "n2", enter(2, None)).expect("enter sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    assert!(answer(&mut sim, "c2").is_none(), "c2 went through alone");
+
+    sim.send("c3", "n0", enter(2, None)).expect("enter sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    for client in ["c2", "c3"] {
+        assert!(matches!(
+            answer(&mut sim, client),
+            Some(Payload::EnterOk { generation: 1 })
+        ));
+    }
+}
+
+// five clients entering a barrier of three over and over, each through any node, while the nodes
+// repeat, hold up and reorder what they send each other and the leader crashes. A client whose node
+// doesn't answer in three seconds takes it to have failed and enters again elsewhere. Every release
+// is checked against the enters before it: a client let through generation g must have had at
+// least 3g enters ahead of it, and no generation lets through more than three.
+#[test]
+fn a_barrier_never_lets_anyone_through_early_through_a_flaky_network_and_a_leader_crash() {
+    for seed in [2, 3, 4] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            duplicate: 0.1,
+            delay: 0.1,
+            delay_by: Duration::from_millis(50),
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut entered = 0;
+        let mut released: HashMap<u64, usize> = HashMap::new();
+        let mut asked: [Option<Duration>; 5] = [None; 5];
+        let mut killed = false;
+        while sim.now() < Duration::from_secs(12) {
+            // for good: a node that fails never comes back
+            if !killed && sim.now() >= Duration::from_secs(4) {
+                sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+                    .expect("kills");
+                killed = true;
+            }
+            for (client, asked) in asked.iter_mut().enumerate() {
+                let client = format!("c{}", client);
+                if let Some(reply) = answer(&mut sim, &client) {
+                    let Payload::EnterOk { generation } = reply else {
+                        panic!("seed {}: {} got {:?}", seed, client, reply);
+                    };
+                    assert!(
+                        entered >= COUNT * generation as usize,
+                        "seed {}: {} let through generation {} after {} enters",
+                        seed,
+                        client,
+                        generation,
+                        entered
+                    );
+                    *released.entry(generation).or_default() += 1;
+                    *asked = None;
+                }
+                if asked.is_some_and(|at| sim.now() - at < Duration::from_secs(3)) {
+                    continue;
+                }
+                *asked = Some(sim.now());
+                entered += 1;
+                let dst = NODES[rng.gen_range(0..NODES.len())];
+                sim.send(&client, dst, enter(COUNT, None))
+                    .expect("enter sends");
+            }
+            sim.run_for(Duration::from_millis(rng.gen_range(5..100)))
+                .expect("nodes step");
+        }
+
+        assert!(
+            released.len() > 3,
+            "seed {}: only {} generations",
+            seed,
+            released.len()
+        );
+        for (generation, released) in released {
+            assert!(
+                released <= COUNT,
+                "seed {}: generation {} let {} through",
+                seed,
+                generation,
+                released
+            );
+        }
+    }
+}
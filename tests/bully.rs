@@ -0,0 +1,107 @@
+#[allow(dead_code)]
+#[path = "../src/bin/bully.rs"]
+mod bully;
+
+use std::time::Duration;
+
+use bully::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::failure_detector::FdEvent;
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, bully::InjectedPayload>;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), bully::BullyNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        bully::InjectedPayload::Fd(FdEvent::Heartbeat)
+    });
+    sim
+}
+
+// who `via` takes for the leader, if it answers within a second
+fn leader(sim: &mut Cluster, via: &str) -> Option<Option<String>> {
+    sim.take_replies("client").expect("replies parse");
+    sim.send("client", via, Payload::Read)
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies("client").expect("replies parse").pop()?;
+    match reply.body.payload {
+        Payload::ReadOk { leader } => Some(leader),
+        _ => None,
+    }
+}
+
+#[test]
+fn the_highest_node_takes_the_lead_back_when_it_restarts() {
+    let mut sim = cluster(1);
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    assert_eq!(leader(&mut sim, "n0"), Some(Some("n4".to_string())));
+
+    sim.disrupt(Disruption::Kill(Target::Node("n4".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    assert_eq!(leader(&mut sim, "n0"), Some(Some("n3".to_string())));
+
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    for node in NODES {
+        assert_eq!(
+            leader(&mut sim, node),
+            Some(Some("n4".to_string())),
+            "{}'s leader",
+            node
+        );
+    }
+}
+
+// the nodes lose, repeat and reorder what they send each other and are partitioned from each other
+// over and over, long enough for each side to elect a leader of its own, and then one of them
+// crashes for good. Once the faults are over and the nodes have had a while to settle,
+// every live node has to follow the highest live one.
+#[test]
+fn every_live_node_settles_on_the_highest_live_one_after_partitions_and_a_crash() {
+    for seed in [1, 2, 3] {
+        let mut sim = cluster(seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(2000),
+                Duration::from_millis(2500),
+            )
+            .until(Duration::from_secs(8)),
+        );
+        let crashed = NODES[rng.gen_range(0..NODES.len())];
+        sim.run_for(Duration::from_secs(9)).expect("nodes step");
+        sim.disrupt(Disruption::Kill(Target::Node(crashed.to_string())))
+            .expect("kills");
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+        let live: Vec<_> = NODES.into_iter().filter(|n| *n != crashed).collect();
+        let highest = live.last().map(|n| n.to_string());
+        for node in &live {
+            assert_eq!(
+                leader(&mut sim, node),
+                Some(highest.clone()),
+                "seed {}: {}'s leader",
+                seed,
+                node
+            );
+        }
+    }
+}
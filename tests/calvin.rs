@@ -0,0 +1,180 @@
+#[allow(dead_code)]
+#[path = "../src/bin/calvin.rs"]
+mod calvin;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use calvin::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Nemesis, Split};
+use rustengan::sim::Sim;
+use rustengan::txn::{Op, OpKind};
+
+type Cluster = Sim<Payload, calvin::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const KEYS: usize = 3;
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), calvin::CalvinNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(10), || calvin::InjectedPayload::Epoch);
+    sim
+}
+
+// every key as of one point in the log, read through `via`
+fn read_all(sim: &mut Cluster, via: &str) -> Option<Vec<Option<usize>>> {
+    let txn = (0..KEYS).map(|key| Op(OpKind::R, key, None)).collect();
+    sim.take_replies("reader").expect("replies parse");
+    sim.send("reader", via, Payload::Txn { txn })
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies("reader").expect("replies parse").pop()?;
+    match reply.body.payload {
+        Payload::TxnOk { txn } => Some(txn.into_iter().map(|op| op.2).collect()),
+        _ => None,
+    }
+}
+
+// clients running transactions through any node that each read two keys and write them a value
+// of the transaction's own, while the nodes lose, repeat and reorder the batches they send each
+// other and are partitioned from each other over and over. A client waits up to a second for an
+// answer before it moves on to another transaction; once the faults are over, every transaction
+// has to have run, and the ones that touched a key have to have done so one after another: each
+// one reading the value the one before it wrote, none of them reading the same one, and the last
+// one's value being what every replica ends up holding.
+#[test]
+fn every_transaction_runs_once_in_one_order_on_every_replica_through_faults() {
+    for seed in [1, 2, 3] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(1500),
+                Duration::from_millis(1000),
+            )
+            .until(Duration::from_secs(8)),
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        // by msg_id, the value the transaction writes
+        let mut sent = HashMap::new();
+        let mut asked = [None; 3];
+        while sim.now() < Duration::from_secs(10) {
+            for (client, asked) in asked.iter_mut().enumerate() {
+                let client = format!("c{}", client);
+                let replies = sim.replies(&client).expect("replies parse");
+                if asked.is_some_and(|(msg_id, _)| {
+                    replies.iter().any(|r| r.body.in_reply_to == Some(msg_id))
+                }) {
+                    *asked = None;
+                }
+                if asked.is_some_and(|(_, at)| sim.now() - at < Duration::from_secs(1)) {
+                    continue;
+                }
+                let value = sent.len();
+                let first = rng.gen_range(0..KEYS);
+                let second = (first + rng.gen_range(1..KEYS)) % KEYS;
+                let txn = vec![
+                    Op(OpKind::R, first, None),
+                    Op(OpKind::W, first, Some(value)),
+                    Op(OpKind::R, second, None),
+                    Op(OpKind::W, second, Some(value)),
+                ];
+                let dst = NODES[rng.gen_range(0..NODES.len())];
+                let msg_id = sim
+                    .send(&client, dst, Payload::Txn { txn })
+                    .expect("request sends");
+                sent.insert(msg_id, value);
+                *asked = Some((msg_id, sim.now()));
+            }
+            sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+                .expect("nodes step");
+        }
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+        // by value, the values its transaction read off each key
+        let mut read = HashMap::new();
+        for client in ["c0", "c1", "c2"] {
+            for reply in sim.replies(client).expect("replies parse") {
+                let Payload::TxnOk { txn } = reply.body.payload else {
+                    panic!("seed {}: {} got {:?}", seed, client, reply.body.payload);
+                };
+                let msg_id = reply.body.in_reply_to.expect("replies say what to");
+                let value = sent[&msg_id];
+                let reads: Vec<_> = txn
+                    .iter()
+                    .filter(|op| !op.is_write())
+                    .map(|op| (op.key(), op.2))
+                    .collect();
+                assert!(
+                    read.insert(value, reads).is_none(),
+                    "seed {}: {} was answered twice",
+                    seed,
+                    value
+                );
+            }
+        }
+        assert_eq!(read.len(), sent.len(), "seed {}: not every txn ran", seed);
+
+        // by key, the values read off it, each of which one transaction alone can have read
+        let mut followed = HashSet::new();
+        for (value, reads) in &read {
+            for (key, before) in reads {
+                let wrote = |b: &usize| read[b].iter().any(|(k, _)| k == key);
+                assert!(
+                    before.is_none_or(|b| wrote(&b)),
+                    "seed {}: {} read {:?} off {}, which nothing wrote there",
+                    seed,
+                    value,
+                    before,
+                    key
+                );
+                assert!(
+                    followed.insert((*key, *before)),
+                    "seed {}: {} read {:?} off {} after another txn did",
+                    seed,
+                    value,
+                    before,
+                    key
+                );
+            }
+        }
+        let finals = read_all(&mut sim, "n0").expect("n0 answers");
+        for node in NODES {
+            assert_eq!(
+                read_all(&mut sim, node),
+                Some(finals.clone()),
+                "seed {}: {}'s store",
+                seed,
+                node
+            );
+        }
+        // no transaction ran on top of the last value on each key, and every other value written
+        // to it was read by exactly the one that came next
+        for (key, last) in finals.iter().enumerate() {
+            assert!(
+                !followed.contains(&(key, *last)),
+                "seed {}: key {}",
+                seed,
+                key
+            );
+            let written = read
+                .values()
+                .filter(|reads| reads.iter().any(|(k, _)| *k == key))
+                .count();
+            let followers = followed.iter().filter(|(k, _)| *k == key).count();
+            assert_eq!(followers, written, "seed {}: key {}", seed, key);
+        }
+    }
+}
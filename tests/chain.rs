@@ -0,0 +1,203 @@
+#[allow(dead_code)]
+#[path = "../src/bin/chain.rs"]
+mod chain;
+
+use std::time::Duration;
+
+use chain::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::correlation;
+use rustengan::failure_detector::FdEvent;
+use rustengan::history::linearizable::check;
+use rustengan::history::{Op, Type};
+use rustengan::kv::service::{Conduct, Service};
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+use rustengan::testing::TestNode;
+
+type Cluster = Sim<Payload, chain::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.service(Service::lin_kv(Conduct::default()));
+    sim.start::<(), chain::ChainNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        chain::InjectedPayload::Fd(FdEvent::Heartbeat)
+    });
+    sim
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+fn read(sim: &mut Cluster, via: &str) -> Option<u64> {
+    match ask(sim, "reader", via, Payload::Read { key: 1 }) {
+        Some(Payload::ReadOk { value }) => value.as_u64(),
+        _ => None,
+    }
+}
+
+fn written(sim: &mut Cluster, via: &str, value: usize) -> bool {
+    let write = Payload::Write { key: 1, value };
+    matches!(ask(sim, "writer", via, write), Some(Payload::WriteOk))
+}
+
+#[test]
+fn the_chain_carries_on_without_a_crashed_tail() {
+    let mut sim = cluster(1);
+    assert!(written(&mut sim, "n0", 1));
+    sim.disrupt(Disruption::Kill(Target::Node("n2".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+
+    assert_eq!(read(&mut sim, "n0"), Some(1));
+    assert!(written(&mut sim, "n1", 2));
+    assert_eq!(read(&mut sim, "n0"), Some(2));
+}
+
+#[test]
+fn a_head_cut_off_from_the_chain_is_spliced_out_and_stays_out() {
+    let mut sim = cluster(2);
+    assert!(written(&mut sim, "n1", 1));
+    let alone = vec![
+        vec!["n0".to_string()],
+        vec!["n1".to_string(), "n2".to_string()],
+    ];
+    sim.disrupt(Disruption::Partition(Split::Components(alone)))
+        .expect("partitions");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+
+    // n1 and n2 go on as a chain of their own, and what n0 takes in while it's cut off never
+    // gets anywhere
+    assert!(written(&mut sim, "n1", 2));
+    assert!(!written(&mut sim, "n0", 3));
+    assert_eq!(read(&mut sim, "n2"), Some(2));
+
+    // once it hears about the new chain, n0 only forwards
+    sim.disrupt(Disruption::Heal).expect("heals");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    assert!(written(&mut sim, "n0", 4));
+    assert_eq!(read(&mut sim, "n0"), Some(4));
+}
+
+#[test]
+fn a_tail_cut_off_from_the_chain_doesnt_answer_reads_from_what_it_had() {
+    let mut sim = cluster(3);
+    assert!(written(&mut sim, "n0", 1));
+    let alone = vec![
+        vec!["n0".to_string(), "n1".to_string()],
+        vec!["n2".to_string()],
+    ];
+    sim.disrupt(Disruption::Partition(Split::Components(alone)))
+        .expect("partitions");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    assert!(written(&mut sim, "n0", 2));
+
+    // n2 is still cut off, and had 1, but lin-kv tells it it isn't the tail anymore
+    assert_ne!(read(&mut sim, "n2"), Some(1));
+}
+
+// clients reading, writing and cas-ing a couple of keys through any node, while the nodes are
+// partitioned from each other and one of them crashes. A client waits up to a second for an answer
+// before it gives up and asks for something else.
+fn chain_history(seed: u64) -> Vec<Op> {
+    let mut sim = cluster(seed);
+    sim.nemesis(
+        Nemesis::partitions(
+            Split::Halves,
+            Duration::from_millis(2500),
+            Duration::from_millis(2000),
+        )
+        .until(Duration::from_secs(10)),
+    );
+    sim.nemesis(
+        Nemesis::new()
+            .at(Duration::from_secs(6), Disruption::Kill(Target::Random))
+            .until(Duration::from_secs(10)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut asked = [None; 3];
+    while sim.now() < Duration::from_secs(15) {
+        for (client, asked) in asked.iter_mut().enumerate() {
+            let client = format!("c{}", client);
+            if !sim.take_replies(&client).expect("replies parse").is_empty() {
+                *asked = None;
+            }
+            if asked.is_some_and(|at| sim.now() - at < Duration::from_secs(1)) {
+                continue;
+            }
+            *asked = Some(sim.now());
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..3) {
+                0 => Payload::Read { key },
+                1 => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+                _ => Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..5),
+                    to: rng.gen_range(0..5),
+                },
+            };
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send(&client, dst, request).expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.history()
+}
+
+#[test]
+fn the_chain_stays_linearizable_through_partitions_and_a_crash() {
+    for seed in [4, 5, 6] {
+        let history = chain_history(seed);
+        assert_eq!(check(&history), Ok(()), "seed {}", seed);
+        assert!(
+            history.iter().any(|op| op.kind == Type::Ok),
+            "seed {}: nothing was answered",
+            seed
+        );
+    }
+}
+
+#[test]
+fn an_update_goes_on_under_its_own_correlation_id_whatever_step_sends_it() {
+    let mut node =
+        TestNode::<chain::ChainNode, Payload, chain::InjectedPayload>::start((), "n0", &NODES[..2])
+            .expect("node starts");
+    let write = Payload::Write { key: 1, value: 1 };
+    correlation::stepping(Some("c1:1".to_string()), || node.receive("c1", write))
+        .expect("write arrives");
+    let passed_on = node.sent_to("n1");
+    assert_eq!(passed_on.len(), 1, "{:?}", passed_on);
+    assert_eq!(passed_on[0].body.correlation_id.as_deref(), Some("c1:1"));
+
+    // n1 is spliced out before it acknowledges the write, which makes n0 the tail, and commits
+    // the write, in a step taken for somebody else
+    let config = chain::Config {
+        epoch: 1,
+        chain: vec!["n0".to_string()],
+    };
+    correlation::stepping(Some("c2:7".to_string()), || {
+        node.receive("n1", Payload::Reconfigure { config })
+    })
+    .expect("reconfiguration arrives");
+    let answered = node.sent_to("c1");
+    assert!(
+        matches!(answered.as_slice(), [reply] if matches!(reply.body.payload, Payload::WriteOk)),
+        "{:?}",
+        answered
+    );
+    assert_eq!(answered[0].body.correlation_id.as_deref(), Some("c1:1"));
+}
@@ -0,0 +1,173 @@
+use proptest::prelude::*;
+use rustengan::crdt::{LwwMap, LwwRegister, OrSet, Rga};
+use rustengan::hlc::Timestamp;
+
+const REPLICAS: [&str; 3] = ["n0", "n1", "n2"];
+
+// what the replicas do, in order: each acts on its own state, and a merge folds one replica's
+// state into another's, so the states the laws are checked on are ones replicas can end up in,
+// with every dot and id unique to the replica that made it
+#[derive(Debug, Clone)]
+enum Step {
+    Add(usize, u8),
+    Remove(usize, u8),
+    Merge(usize, usize),
+}
+
+fn steps() -> impl Strategy<Value = Vec<Step>> {
+    let step = prop_oneof![
+        (0..REPLICAS.len(), 0..4u8).prop_map(|(replica, e)| Step::Add(replica, e)),
+        (0..REPLICAS.len(), 0..4u8).prop_map(|(replica, e)| Step::Remove(replica, e)),
+        (0..REPLICAS.len(), 0..REPLICAS.len()).prop_map(|(from, to)| Step::Merge(from, to)),
+    ];
+    prop::collection::vec(step, 0..24)
+}
+
+fn or_sets(steps: &[Step]) -> Vec<OrSet<u8>> {
+    let mut sets = vec![OrSet::new(); REPLICAS.len()];
+    for step in steps {
+        match *step {
+            Step::Add(replica, e) => sets[replica].add(REPLICAS[replica], e),
+            Step::Remove(replica, e) => sets[replica].remove(&e),
+            Step::Merge(from, to) => {
+                let from = sets[from].clone();
+                sets[to].merge(&from);
+            }
+        }
+    }
+    sets
+}
+
+// every write is stamped with the step it's made at, so no two share a timestamp and a node
+fn lww_maps(steps: &[Step]) -> Vec<LwwMap<u8, usize>> {
+    let mut maps = vec![LwwMap::new(); REPLICAS.len()];
+    for (i, step) in steps.iter().enumerate() {
+        let ts = Timestamp {
+            wall_ms: i as u64,
+            logical: 0,
+        };
+        match *step {
+            Step::Add(replica, k) => maps[replica].insert(REPLICAS[replica], ts, k, i),
+            Step::Remove(replica, k) => maps[replica].remove(REPLICAS[replica], ts, k),
+            Step::Merge(from, to) => {
+                let from = maps[from].clone();
+                maps[to].merge(&from);
+            }
+        }
+    }
+    maps
+}
+
+// adds insert at, and removes delete from, wherever the element picks among what's there
+fn rgas(steps: &[Step]) -> Vec<Rga<u8>> {
+    let mut rgas = vec![Rga::new(); REPLICAS.len()];
+    for step in steps {
+        match *step {
+            Step::Add(replica, e) => {
+                let index = e as usize % (rgas[replica].len() + 1);
+                rgas[replica].insert(REPLICAS[replica], index, e);
+            }
+            Step::Remove(replica, e) => {
+                if !rgas[replica].is_empty() {
+                    let index = e as usize % rgas[replica].len();
+                    rgas[replica].delete(index);
+                }
+            }
+            Step::Merge(from, to) => {
+                let from = rgas[from].clone();
+                rgas[to].merge(&from);
+            }
+        }
+    }
+    rgas
+}
+
+fn register() -> impl Strategy<Value = LwwRegister<String>> {
+    (0..REPLICAS.len(), 0..8u64).prop_map(|(replica, wall_ms)| {
+        let node = REPLICAS[replica];
+        let ts = Timestamp {
+            wall_ms,
+            logical: 0,
+        };
+        // a write is the only one its node makes at its timestamp
+        LwwRegister::new(node, ts, format!("{}@{}", node, wall_ms))
+    })
+}
+
+fn merged<T: Clone>(a: &T, b: &T, merge: impl Fn(&mut T, &T)) -> T {
+    let mut merged = a.clone();
+    merge(&mut merged, b);
+    merged
+}
+
+// commutative, associative and idempotent
+fn obeys_merge_laws<T: Clone + PartialEq + std::fmt::Debug>(
+    replicas: &[T],
+    merge: impl Fn(&mut T, &T) + Copy,
+) -> Result<(), TestCaseError> {
+    let (a, b, c) = (&replicas[0], &replicas[1], &replicas[2]);
+    prop_assert_eq!(merged(a, b, merge), merged(b, a, merge));
+    prop_assert_eq!(
+        merged(&merged(a, b, merge), c, merge),
+        merged(a, &merged(b, c, merge), merge)
+    );
+    prop_assert_eq!(&merged(a, a, merge), a);
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn or_sets_merge_by_the_laws(steps in steps()) {
+        obeys_merge_laws(&or_sets(&steps), OrSet::merge)?;
+    }
+
+    #[test]
+    fn a_removed_element_stays_removed_whatever_stale_copies_come_along(
+        steps in steps(),
+        e in 0..4u8,
+    ) {
+        let sets = or_sets(&steps);
+        let mut set = sets.iter().fold(OrSet::new(), |set, other| merged(&set, other, OrSet::merge));
+        set.remove(&e);
+        // every replica still has the dots the remove tombstoned
+        for stale in &sets {
+            set.merge(stale);
+        }
+        prop_assert!(!set.contains(&e));
+    }
+
+    #[test]
+    fn an_add_concurrent_with_a_remove_survives_it(steps in steps(), e in 0..4u8) {
+        let sets = or_sets(&steps);
+        let mut removed = sets[0].clone();
+        removed.remove(&e);
+        let mut added = sets[1].clone();
+        added.add(REPLICAS[1], e);
+        prop_assert!(merged(&removed, &added, OrSet::merge).contains(&e));
+        prop_assert!(merged(&added, &removed, OrSet::merge).contains(&e));
+    }
+
+    #[test]
+    fn lww_registers_merge_by_the_laws(
+        a in register(),
+        b in register(),
+        c in register(),
+    ) {
+        obeys_merge_laws(&[a, b, c], LwwRegister::merge)?;
+    }
+
+    #[test]
+    fn lww_maps_merge_by_the_laws(steps in steps()) {
+        obeys_merge_laws(&lww_maps(&steps), LwwMap::merge)?;
+    }
+
+    #[test]
+    fn rgas_merge_by_the_laws(steps in steps()) {
+        let rgas = rgas(&steps);
+        obeys_merge_laws(&rgas, Rga::merge)?;
+        // and replicas that have merged the same things read the same
+        let everything = rgas.iter().fold(Rga::new(), |rga, other| merged(&rga, other, Rga::merge));
+        let reversed = rgas.iter().rev().fold(Rga::new(), |rga, other| merged(&rga, other, Rga::merge));
+        prop_assert_eq!(everything.values(), reversed.values());
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use rustengan::stats::Line;
+
+fn line(status: &[(&str, &str)]) -> Line {
+    Line {
+        over: Duration::from_millis(1000),
+        events: 120,
+        queued: 2,
+        sent: vec![("gossip".to_string(), 80), ("broadcast_ok".to_string(), 20)],
+        bytes: 9000,
+        status: status
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+#[test]
+fn a_stats_line_reads_back_as_it_was_written() {
+    let written = line(&[("leader", "n2"), ("messages", "42")]);
+    assert_eq!(
+        written.to_string(),
+        "stats over 1000ms: 120 events, 2 queued, 100 sent (gossip 80, broadcast_ok 20), \
+         9000 bytes written; leader n2, messages 42"
+    );
+    // after whatever the logger and the cluster put before it
+    let read = Line::parse(&format!("n1| INFO {}", written));
+    assert_eq!(read, Some(written));
+
+    let quiet = Line {
+        sent: Vec::new(),
+        ..line(&[])
+    };
+    assert_eq!(
+        quiet.to_string(),
+        "stats over 1000ms: 120 events, 2 queued, 0 sent, 9000 bytes written"
+    );
+    assert_eq!(Line::parse(&quiet.to_string()), Some(quiet));
+
+    assert_eq!(Line::parse("INFO taking over as leader"), None);
+    assert_eq!(
+        Line::parse("stats over 1000ms: 120 events, 2 queued, 7 sent (gossip 80), 9 bytes written"),
+        None
+    );
+}
+
+#[test]
+fn the_dashboard_shows_what_each_node_last_said() {
+    let mut dashboard = Command::new(env!("CARGO_BIN_EXE_dashboard"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("dashboard starts");
+    let mut stdin = dashboard.stdin.take().expect("piped");
+    for (node, leader) in [("n0", "n2"), ("n1", "n2"), ("n2", "n2")] {
+        writeln!(stdin, "{}| INFO {}", node, line(&[("leader", leader)])).expect("written");
+    }
+    writeln!(stdin, "n2| taking over as leader").expect("written");
+    // once the cluster stops, so does the dashboard, with everything as it last was
+    drop(stdin);
+    let output = dashboard.wait_with_output().expect("dashboard exits");
+    assert!(output.status.success());
+    let table = String::from_utf8(output.stdout).expect("utf-8");
+    let lines: Vec<&str> = table.lines().collect();
+    assert_eq!(lines[0], "leader n2 (3 of 3 nodes agree)", "{}", table);
+    assert_eq!(
+        lines[1], "node  events/s  sent/s  queued  kB/s  most sent    leader  last said",
+        "{}",
+        table
+    );
+    assert_eq!(
+        lines[2], "n0    120.0     100.0   2       9.0   gossip 80.0  n2",
+        "{}",
+        table
+    );
+    assert_eq!(
+        lines[4],
+        "n2    120.0     100.0   2       9.0   gossip 80.0  n2      taking over as leader",
+        "{}",
+        table
+    );
+}
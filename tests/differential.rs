@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split};
+use rustengan::sim::Sim;
+use rustengan::{Event, Init, Node, Output};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// the broadcast workload's nodes, built from the same sources as their own binaries
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+#[allow(dead_code)]
+#[path = "../src/bin/causal_broadcast.rs"]
+mod causal_broadcast;
+#[allow(dead_code)]
+#[path = "../src/bin/total_order_broadcast.rs"]
+mod total_order_broadcast;
+
+const NODES: usize = 5;
+// how long the cluster gets, once the last fault's over, to deliver everything it's going to
+const SETTLE: Duration = Duration::from_secs(10);
+
+/// One thing that happens to a cluster at a point in a run.
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Broadcast {
+        at: Duration,
+        node: usize,
+        message: usize,
+    },
+    /// `apart` on one side, everyone else on the other.
+    Partition {
+        at: Duration,
+        until: Duration,
+        apart: Vec<usize>,
+    },
+    /// Every link between nodes loses messages with a chance of `drop`.
+    Lossy {
+        at: Duration,
+        until: Duration,
+        drop: f64,
+    },
+}
+
+// by node, the messages its read came back with once the cluster had settled
+type Delivered = BTreeMap<String, BTreeSet<usize>>;
+
+type Run = fn(u64, &[Step]) -> anyhow::Result<Delivered>;
+
+// a way to serve the broadcast workload
+trait Strategy {
+    type Payload: Serialize + DeserializeOwned + Send + 'static;
+    type Injected: Send + 'static;
+
+    fn start(sim: &mut Sim<Self::Payload, Self::Injected>) -> anyhow::Result<()>;
+}
+
+struct Gossip;
+
+impl Strategy for Gossip {
+    type Payload = broadcast::Payload;
+    type Injected = broadcast::InjectedPayload;
+
+    fn start(sim: &mut Sim<Self::Payload, Self::Injected>) -> anyhow::Result<()> {
+        sim.start::<_, broadcast::BroadcastNode>(broadcast::Gossip::default())?;
+        sim.every(broadcast::GOSSIP_EVERY, || {
+            broadcast::InjectedPayload::Gossip
+        });
+        Ok(())
+    }
+}
+
+struct Causal;
+
+impl Strategy for Causal {
+    type Payload = causal_broadcast::Payload;
+    type Injected = causal_broadcast::InjectedPayload;
+
+    fn start(sim: &mut Sim<Self::Payload, Self::Injected>) -> anyhow::Result<()> {
+        sim.start::<(), causal_broadcast::CausalNode>(())?;
+        sim.every(causal_broadcast::RETRANSMIT_EVERY, || {
+            causal_broadcast::InjectedPayload::Retransmit
+        });
+        Ok(())
+    }
+}
+
+struct TotalOrder;
+
+impl Strategy for TotalOrder {
+    type Payload = total_order_broadcast::Payload;
+    type Injected = total_order_broadcast::InjectedPayload;
+
+    fn start(sim: &mut Sim<Self::Payload, Self::Injected>) -> anyhow::Result<()> {
+        sim.start::<(), total_order_broadcast::TobNode>(())?;
+        sim.every(total_order_broadcast::RETRANSMIT_EVERY, || {
+            total_order_broadcast::InjectedPayload::Retransmit
+        });
+        Ok(())
+    }
+}
+
+const STRATEGIES: [(&str, Run); 3] = [
+    ("gossip", run::<Gossip>),
+    ("causal", run::<Causal>),
+    ("total-order", run::<TotalOrder>),
+];
+
+fn node_ids() -> Vec<String> {
+    (0..NODES).map(|i| format!("n{}", i)).collect()
+}
+
+// the workload's requests are the same for every strategy, whatever else their payloads have
+fn payload<P: DeserializeOwned>(request: Value) -> anyhow::Result<P> {
+    Ok(serde_json::from_value(request)?)
+}
+
+// runs `schedule` against a cluster of `S`'s nodes, on a line topology so that messages have to
+// be passed along
+fn run<S: Strategy>(seed: u64, schedule: &[Step]) -> anyhow::Result<Delivered> {
+    let ids = node_ids();
+    let mut sim: Sim<S::Payload, S::Injected> =
+        Sim::new(seed, &ids.iter().map(String::as_str).collect::<Vec<_>>());
+    S::start(&mut sim)?;
+    sim.invariant("nothing delivered is taken back", only_grows);
+
+    let topology: HashMap<&str, Vec<&str>> = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let neighbours = [i.checked_sub(1), Some(i + 1).filter(|n| *n < NODES)];
+            let neighbours = neighbours.into_iter().flatten().map(|n| ids[n].as_str());
+            (id.as_str(), neighbours.collect())
+        })
+        .collect();
+    for id in &ids {
+        let request = json!({ "type": "topology", "topology": topology });
+        sim.send("c0", id, payload(request)?)?;
+    }
+
+    let mut nemesis = Nemesis::new();
+    let mut end = Duration::ZERO;
+    let mut broadcasts = Vec::new();
+    for step in schedule {
+        match step {
+            Step::Broadcast { at, node, message } => broadcasts.push((*at, *node, *message)),
+            Step::Partition { at, until, apart } => {
+                let apart = apart.iter().map(|n| ids[*n].clone()).collect();
+                let split = Split::Components(vec![apart]);
+                nemesis = nemesis
+                    .at(*at, Disruption::Partition(split))
+                    .at(*until, Disruption::Heal);
+                end = end.max(*until);
+            }
+            Step::Lossy { at, until, drop } => {
+                let lossy = Faults {
+                    drop: *drop,
+                    ..Faults::default()
+                };
+                sim.faults_after(*at, None, lossy);
+                sim.faults_after(*until, None, Faults::default());
+                end = end.max(*until);
+            }
+        }
+    }
+    sim.nemesis(nemesis);
+    broadcasts.sort();
+    for (at, node, message) in broadcasts {
+        sim.run_for(at.saturating_sub(sim.now()))?;
+        let request = json!({ "type": "broadcast", "message": message });
+        sim.send("c1", &ids[node], payload(request)?)?;
+        end = end.max(at);
+    }
+    sim.run_for((end + SETTLE).saturating_sub(sim.now()))?;
+
+    for id in &ids {
+        sim.send("c2", id, payload(json!({ "type": "read" }))?)?;
+    }
+    sim.run_for(Duration::from_secs(1))?;
+    let mut delivered = Delivered::new();
+    for reply in sim.replies("c2")? {
+        let read = serde_json::to_value(&reply.body.payload)?;
+        let messages = serde_json::from_value(read["messages"].clone())?;
+        delivered.insert(reply.src, messages);
+    }
+    Ok(delivered)
+}
+
+// a set of messages a node has only grows, and a log of them is only ever added to at the end
+fn only_grows(before: &Value, after: &Value) -> anyhow::Result<()> {
+    if let (Some(before), Some(after)) =
+        (before["messages"].as_array(), after["messages"].as_array())
+    {
+        if let Some(message) = before.iter().find(|message| !after.contains(message)) {
+            anyhow::bail!("{} went missing", message);
+        }
+    }
+    if let (Some(before), Some(after)) = (before["log"].as_array(), after["log"].as_array()) {
+        anyhow::ensure!(
+            after.starts_with(before),
+            "the log went from {:?} to {:?}",
+            before,
+            after
+        );
+    }
+    Ok(())
+}
+
+// a schedule of `broadcasts` messages over a few seconds, with a partition and a lossy spell
+// somewhere in the middle of it
+fn schedule(seed: u64, broadcasts: usize) -> Vec<Step> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let ms = |rng: &mut StdRng, range| Duration::from_millis(rng.gen_range(range));
+    let mut schedule: Vec<Step> = (0..broadcasts)
+        .map(|message| Step::Broadcast {
+            at: ms(&mut rng, 0..3_000),
+            node: rng.gen_range(0..NODES),
+            message,
+        })
+        .collect();
+    let at = ms(&mut rng, 0..2_000);
+    schedule.push(Step::Partition {
+        at,
+        until: at + ms(&mut rng, 500..2_000),
+        apart: (0..rng.gen_range(1..NODES)).collect(),
+    });
+    let at = ms(&mut rng, 0..2_000);
+    schedule.push(Step::Lossy {
+        at,
+        until: at + ms(&mut rng, 500..2_000),
+        drop: 0.3,
+    });
+    schedule
+}
+
+// what each strategy delivered, if they didn't all deliver the same
+fn divergence(
+    strategies: &[(&str, Run)],
+    seed: u64,
+    schedule: &[Step],
+) -> Option<Vec<(String, Delivered)>> {
+    let outcomes: Vec<(String, Delivered)> = strategies
+        .iter()
+        .map(|(name, run)| {
+            let delivered = run(seed, schedule)
+                .unwrap_or_else(|e| panic!("{} failed on seed {}: {:#}", name, seed, e));
+            (name.to_string(), delivered)
+        })
+        .collect();
+    let agree = outcomes.windows(2).all(|pair| pair[0].1 == pair[1].1);
+    (!agree).then_some(outcomes)
+}
+
+// drops steps from `schedule` one at a time for as long as the strategies still diverge without
+// them, so what's left is a schedule every step of which the divergence needs
+fn shrink(strategies: &[(&str, Run)], seed: u64, mut schedule: Vec<Step>) -> Vec<Step> {
+    let mut i = 0;
+    while i < schedule.len() {
+        let mut without = schedule.clone();
+        without.remove(i);
+        if divergence(strategies, seed, &without).is_some() {
+            schedule = without;
+        } else {
+            i += 1;
+        }
+    }
+    schedule
+}
+
+// panics with the smallest schedule it can find that the strategies disagree on, if they
+// disagree on `schedule`
+fn differ(strategies: &[(&str, Run)], seed: u64, schedule: Vec<Step>) {
+    if divergence(strategies, seed, &schedule).is_none() {
+        return;
+    }
+    let shrunk = shrink(strategies, seed, schedule);
+    let outcomes = divergence(strategies, seed, &shrunk).expect("still diverges");
+    let mut report = format!("seed {} diverges on {:#?}\n", seed, shrunk);
+    for (name, delivered) in outcomes {
+        report += &format!("{}: {:?}\n", name, delivered);
+    }
+    panic!("{}", report);
+}
+
+#[test]
+fn every_broadcast_strategy_delivers_the_same_messages_everywhere() {
+    for seed in 0..4 {
+        let schedule = schedule(seed, 20);
+        differ(&STRATEGIES, seed, schedule.clone());
+        // and what they agree on is everything, so agreeing isn't just all of them losing it
+        let (_, run) = STRATEGIES[0];
+        let delivered = run(seed, &schedule).expect("runs");
+        assert_eq!(delivered.len(), NODES);
+        let everything: BTreeSet<usize> = (0..20).collect();
+        assert!(delivered.values().all(|messages| *messages == everything));
+    }
+}
+
+// a node that keeps what it's told and tells nobody
+struct Hoard {
+    messages: BTreeSet<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum HoardPayload {
+    Broadcast {
+        message: usize,
+    },
+    BroadcastOk,
+    Read,
+    ReadOk {
+        messages: BTreeSet<usize>,
+    },
+    Topology {
+        topology: HashMap<String, Vec<String>>,
+    },
+    TopologyOk,
+}
+
+impl Node<(), HoardPayload> for Hoard {
+    fn from_init(
+        _state: (),
+        _init: Init,
+        _inject: std::sync::mpsc::Sender<Event<HoardPayload>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            messages: BTreeSet::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<HoardPayload>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            HoardPayload::Broadcast { message } => {
+                self.messages.insert(message);
+                HoardPayload::BroadcastOk
+            }
+            HoardPayload::Read => HoardPayload::ReadOk {
+                messages: self.messages.clone(),
+            },
+            HoardPayload::Topology { .. } => HoardPayload::TopologyOk,
+            _ => return Ok(()),
+        };
+        reply.send(output)
+    }
+}
+
+impl Strategy for Hoard {
+    type Payload = HoardPayload;
+    type Injected = ();
+
+    fn start(sim: &mut Sim<Self::Payload>) -> anyhow::Result<()> {
+        sim.start::<(), Hoard>(())
+    }
+}
+
+#[test]
+fn a_divergence_comes_with_the_smallest_schedule_that_shows_it() {
+    let strategies: [(&str, Run); 2] = [("gossip", run::<Gossip>), ("hoard", run::<Hoard>)];
+    let seed = 5;
+    let schedule = schedule(seed, 10);
+    assert!(divergence(&strategies, seed, &schedule).is_some());
+    // one broadcast is all it takes for a node that tells nobody to fall behind
+    let shrunk = shrink(&strategies, seed, schedule);
+    assert_eq!(shrunk.len(), 1, "{:#?}", shrunk);
+    assert!(matches!(shrunk[0], Step::Broadcast { .. }));
+}
@@ -0,0 +1,183 @@
+#[allow(dead_code)]
+#[path = "../src/bin/dynamo_kv.rs"]
+mod dynamo_kv;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use dynamo_kv::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::failure_detector::FdEvent;
+use rustengan::shard;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, dynamo_kv::InjectedPayload>;
+
+// by key, every value a client asked to have written to it
+type Written = HashMap<usize, HashSet<usize>>;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), dynamo_kv::DynamoNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        dynamo_kv::InjectedPayload::Fd(FdEvent::Heartbeat)
+    });
+    sim
+}
+
+// the key's preference list, the first N of which are its home replicas
+fn preference_list(key: usize) -> Vec<String> {
+    let placement = shard::from_env(NODES, dynamo_kv::VNODES).expect("placement");
+    placement
+        .replicas(shard::hash(&key), usize::MAX)
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+}
+
+fn components(components: &[&[&str]]) -> Disruption {
+    let components = components
+        .iter()
+        .map(|c| c.iter().map(|n| n.to_string()).collect())
+        .collect();
+    Disruption::Partition(Split::Components(components))
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+fn read(sim: &mut Cluster, via: &str, key: usize) -> Option<usize> {
+    match ask(sim, "reader", via, Payload::Read { key }) {
+        Some(Payload::ReadOk { value }) => Some(value),
+        _ => None,
+    }
+}
+
+#[test]
+fn a_write_taken_by_a_stand_in_makes_it_to_its_home_replica_once_the_partition_heals() {
+    let mut sim = cluster(1);
+    let key = 1;
+    let list = preference_list(key);
+    let [home, other, another, stand_in, outsider] = list.as_slice() else {
+        panic!("{:?} isn't all five nodes", list);
+    };
+
+    // `home` is cut off long enough to be taken for down, and the write goes to its stand-in
+    sim.disrupt(components(&[&[home]])).expect("partitions");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    let write = Payload::Write { key, value: 7 };
+    assert!(matches!(
+        ask(&mut sim, "writer", other, write),
+        Some(Payload::WriteOk)
+    ));
+    sim.disrupt(Disruption::Heal).expect("heals");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+
+    // with every other node that had the write cut off, `outsider` can only read it from `home`
+    sim.disrupt(components(&[
+        &[home, outsider],
+        &[other, another, stand_in],
+    ]))
+    .expect("partitions");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    assert_eq!(read(&mut sim, outsider, key), Some(7));
+}
+
+// clients writing values nobody else writes to a few keys through any node, while the nodes are
+// partitioned from each other and one of them crashes and comes back empty, and then every node's
+// answer for every key once the faults are over and anti-entropy has had time to go round. A client
+// waits up to a second for an answer before it gives up and writes something else.
+fn settled(seed: u64) -> (Written, Vec<(usize, Option<usize>)>) {
+    let mut sim = cluster(seed);
+    sim.nemesis(
+        Nemesis::partitions(
+            Split::Halves,
+            Duration::from_millis(2500),
+            Duration::from_millis(2000),
+        )
+        .until(Duration::from_secs(10)),
+    );
+    sim.nemesis(
+        Nemesis::new()
+            .at(Duration::from_secs(4), Disruption::Kill(Target::Random))
+            .at(Duration::from_secs(7), Disruption::Restart)
+            .until(Duration::from_secs(10)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut next = 0;
+    let mut written = Written::new();
+    let mut acked = HashSet::new();
+    let mut asked: [Option<(Duration, usize)>; 3] = [None; 3];
+    while sim.now() < Duration::from_secs(12) {
+        for (client, asked) in asked.iter_mut().enumerate() {
+            let client = format!("c{}", client);
+            let replies = sim.take_replies(&client).expect("replies parse");
+            if let Some(reply) = replies.last() {
+                if let (Payload::WriteOk, Some((_, key))) = (&reply.body.payload, *asked) {
+                    acked.insert(key);
+                }
+                *asked = None;
+            }
+            if asked.is_some_and(|(at, _)| sim.now() - at < Duration::from_secs(1)) {
+                continue;
+            }
+            let key = rng.gen_range(0..3);
+            next += 1;
+            written.entry(key).or_default().insert(next);
+            *asked = Some((sim.now(), key));
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send(&client, dst, Payload::Write { key, value: next })
+                .expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+    let mut reads = Vec::new();
+    for &key in &acked {
+        for node in NODES {
+            reads.push((key, read(&mut sim, node, key)));
+        }
+    }
+    (written, reads)
+}
+
+#[test]
+fn every_node_settles_on_the_same_written_value_after_partitions_and_a_crash() {
+    for seed in [2, 3, 4] {
+        let (written, reads) = settled(seed);
+        assert!(
+            !reads.is_empty(),
+            "seed {}: no write was acknowledged",
+            seed
+        );
+        for (key, read) in &reads {
+            let value = read.unwrap_or_else(|| panic!("seed {}: key {} was lost", seed, key));
+            assert!(
+                written[key].contains(&value),
+                "seed {}: {} was never written to key {}",
+                seed,
+                value,
+                key
+            );
+            let first = reads.iter().find(|(k, _)| k == key).expect("it's in there");
+            assert_eq!(
+                first.1, *read,
+                "seed {}: nodes disagree on key {}",
+                seed, key
+            );
+        }
+    }
+}
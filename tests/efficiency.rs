@@ -0,0 +1,163 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::Sim;
+
+// the broadcast workload's node, built from the same source as its own binary
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+use broadcast::{BroadcastNode, Gossip, InjectedPayload, Payload};
+
+// the efficient broadcast challenge's cluster: 25 nodes, every message taking 100ms
+const NODES: usize = 25;
+const LATENCY: Duration = Duration::from_millis(100);
+// broadcasts a second, and for how long, as Maelstrom's --rate and --time-limit would have it
+const RATE: u64 = 50;
+const LOAD_FOR: Duration = Duration::from_secs(10);
+// how often every node is read, which is how finely a broadcast's latency is measured
+const READ_EVERY: Duration = Duration::from_millis(50);
+
+// the limits of the challenge's second part
+const MSGS_PER_OP: f64 = 20.0;
+const MEDIAN_LATENCY: Duration = Duration::from_secs(1);
+const MAX_LATENCY: Duration = Duration::from_secs(2);
+
+struct Efficiency {
+    // messages between nodes for every broadcast a client made
+    msgs_per_op: f64,
+    // by message, how long from the client broadcasting it until every node had it
+    latencies: Vec<Duration>,
+}
+
+// the hub-and-spoke topology the node is given: n0 has everyone else as a neighbour, and
+// everyone else has only n0, so nothing's more than two hops from anywhere
+fn topology(ids: &[String]) -> HashMap<String, Vec<String>> {
+    ids.iter()
+        .map(|id| match id.as_str() {
+            "n0" => (id.clone(), ids[1..].to_vec()),
+            _ => (id.clone(), vec![ids[0].clone()]),
+        })
+        .collect()
+}
+
+fn run(seed: u64) -> Efficiency {
+    let ids: Vec<String> = (0..NODES).map(|i| format!("n{}", i)).collect();
+    let mut sim: Sim<Payload, InjectedPayload> =
+        Sim::new(seed, &ids.iter().map(String::as_str).collect::<Vec<_>>());
+    sim.latency(LATENCY..=LATENCY);
+    sim.start::<_, BroadcastNode>(Gossip::default())
+        .expect("nodes start");
+    sim.every(broadcast::GOSSIP_EVERY, || InjectedPayload::Gossip);
+    let mut requests = 0;
+    for id in &ids {
+        let topology = topology(&ids);
+        sim.send("c0", id, Payload::Topology { topology })
+            .expect("topology sends");
+        requests += 1;
+    }
+    sim.run_for(LATENCY * 2).expect("nodes step");
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    // by message, when it was broadcast
+    let mut broadcast_at = BTreeMap::new();
+    // by msg_id, when a read got to the node it was for
+    let mut read_at = HashMap::new();
+    // by message, when the read that found the last node to have it got there
+    let mut everywhere_at = BTreeMap::new();
+    // by message, the nodes that have had it in a read
+    let mut seen: BTreeMap<usize, Vec<String>> = BTreeMap::new();
+    let start = sim.now();
+    let gap = Duration::from_secs(1) / RATE as u32;
+    let mut next_broadcast = start;
+    let mut next_read = start;
+    let mut message = 0;
+    let settle = LOAD_FOR + MAX_LATENCY * 2;
+    while sim.now() < start + settle {
+        let now = sim.now();
+        if now >= next_broadcast {
+            let dst = &ids[rng.gen_range(0..NODES)];
+            sim.send("c1", dst, Payload::Broadcast { message })
+                .expect("broadcast sends");
+            requests += 1;
+            broadcast_at.insert(message, now);
+            message += 1;
+            next_broadcast += gap;
+            if next_broadcast >= start + LOAD_FOR {
+                // nothing more to broadcast, just reads until it's all got everywhere
+                next_broadcast = Duration::MAX;
+            }
+        }
+        if now >= next_read {
+            for id in &ids {
+                let id = sim.send("c2", id, Payload::Read).expect("read sends");
+                requests += 1;
+                read_at.insert(id, now + LATENCY);
+            }
+            next_read += READ_EVERY;
+        }
+        sim.run_for(next_broadcast.min(next_read).saturating_sub(sim.now()))
+            .expect("nodes step");
+        for reply in sim.take_replies("c2").expect("replies parse") {
+            let Payload::ReadOk { messages } = reply.body.payload else {
+                panic!("{:?} isn't a read_ok", reply.body.payload);
+            };
+            let at = read_at[&reply.body.in_reply_to.expect("a reply")];
+            for m in messages {
+                let seen = seen.entry(m).or_default();
+                if !seen.contains(&reply.src) {
+                    seen.push(reply.src.clone());
+                    if seen.len() == NODES {
+                        everywhere_at.insert(m, at);
+                    }
+                }
+            }
+        }
+    }
+
+    let between_nodes = sim.tally().sent - requests;
+    let latencies = broadcast_at
+        .iter()
+        .map(|(m, at)| match everywhere_at.get(m) {
+            Some(everywhere) => *everywhere - *at,
+            None => panic!("{} never got everywhere: only {:?}", m, seen.get(m)),
+        })
+        .collect();
+    Efficiency {
+        msgs_per_op: between_nodes as f64 / broadcast_at.len() as f64,
+        latencies,
+    }
+}
+
+#[test]
+fn efficient_broadcast_stays_within_the_challenges_budget() {
+    // fixed seeds, so how close a run comes to the budget doesn't change from one run to the next
+    for seed in [1, 2, 3] {
+        let efficiency = run(seed);
+        let mut latencies = efficiency.latencies;
+        latencies.sort();
+        let median = latencies[latencies.len() / 2];
+        let max = *latencies.last().expect("some broadcasts");
+        eprintln!(
+            "seed {}: {:.1} msgs per op, median latency {:?}, max latency {:?}",
+            seed, efficiency.msgs_per_op, median, max
+        );
+        assert!(
+            efficiency.msgs_per_op < MSGS_PER_OP,
+            "seed {}: {:.1} messages between nodes for every broadcast, over {}",
+            seed,
+            efficiency.msgs_per_op,
+            MSGS_PER_OP
+        );
+        assert!(
+            median < MEDIAN_LATENCY,
+            "seed {}: median latency {:?}",
+            seed,
+            median
+        );
+        assert!(max < MAX_LATENCY, "seed {}: max latency {:?}", seed, max);
+    }
+}
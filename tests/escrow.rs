@@ -0,0 +1,285 @@
+#[allow(dead_code)]
+#[path = "../src/bin/escrow.rs"]
+mod escrow;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use escrow::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::kv::service::{Conduct, Service};
+use rustengan::kv::SEQ_KV;
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Target};
+use rustengan::sim::Sim;
+use rustengan::testing::TestNode;
+use rustengan::{error, Body, Message};
+
+type Escrow = TestNode<escrow::EscrowNode, Payload, escrow::InjectedPayload>;
+type Cluster = Sim<Payload, escrow::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const CLIENTS: [&str; 4] = ["c0", "c1", "c2", "c3"];
+
+fn escrow_node() -> Escrow {
+    Escrow::start((), "n0", &["n0", "n1"]).expect("node starts")
+}
+
+// `payload` from `src`, as an answer to the node's msg `in_reply_to`
+fn reply(src: &str, in_reply_to: Option<usize>, payload: Payload) -> Message<Payload> {
+    Message {
+        src: src.to_string(),
+        dst: "n0".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to,
+            correlation_id: None,
+            payload,
+        },
+    }
+}
+
+// the one request the node has sent seq-kv since the last time, and its msg_id
+fn asked_seq_kv(node: &mut Escrow) -> (Option<usize>, String) {
+    let sent = node.sent_json_to(SEQ_KV);
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    let kind = sent[0].body.payload["type"].as_str().expect("has a type");
+    (sent[0].body.id, kind.to_string())
+}
+
+#[test]
+fn a_client_answering_in_seq_kvs_place_is_ignored() {
+    let mut node = escrow_node();
+    node.receive("c1", Payload::Take { amount: 1 })
+        .expect("take arrives");
+    let (read, kind) = asked_seq_kv(&mut node);
+    assert_eq!(kind, "read");
+
+    // c2 guesses the msg_id of the read, and answers it as seq-kv would never
+    node.deliver(reply("c2", read, Payload::TakeOk))
+        .expect("node carries on");
+    assert!(node.sent().is_empty());
+
+    // and the real answer still goes through
+    node.deliver(reply(SEQ_KV, read, Payload::ReadOk { value: 5 }))
+        .expect("read answer arrives");
+    let (_, kind) = asked_seq_kv(&mut node);
+    assert_eq!(kind, "cas");
+}
+
+#[test]
+fn an_unexpected_answer_from_seq_kv_fails_just_its_operation() {
+    let mut node = escrow_node();
+    node.receive("c1", Payload::Add { delta: 3 })
+        .expect("add arrives");
+    let (read, _) = asked_seq_kv(&mut node);
+    node.deliver(reply(SEQ_KV, read, Payload::AddOk))
+        .expect("node carries on");
+    let failed = node.sent_to("c1");
+    assert!(
+        matches!(
+            failed.as_slice(),
+            [Message { body: Body { payload: Payload::Error { code, .. }, .. }, .. }]
+                if *code == error::CRASH
+        ),
+        "{:?}",
+        failed
+    );
+
+    // and the node still serves everyone else
+    node.receive("c2", Payload::Read).expect("read arrives");
+    let (_, kind) = asked_seq_kv(&mut node);
+    assert_eq!(kind, "read");
+}
+
+fn cluster(seed: u64, conduct: Conduct) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.service(Service::seq_kv(conduct));
+    sim.start::<(), escrow::EscrowNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(300), || {
+        escrow::InjectedPayload::Rebalance
+    });
+    sim
+}
+
+// what `via` makes of the counter, asking again while seq-kv fails the read or loses the answer
+fn read(sim: &mut Cluster, via: &str) -> Option<u64> {
+    (0..5).find_map(|_| {
+        sim.take_replies("reader").expect("replies parse");
+        sim.send("reader", via, Payload::Read)
+            .expect("request sends");
+        sim.run_for(Duration::from_secs(1)).expect("nodes step");
+        match sim
+            .take_replies("reader")
+            .expect("replies parse")
+            .pop()?
+            .body
+            .payload
+        {
+            Payload::ReadOk { value } => Some(value),
+            _ => None,
+        }
+    })
+}
+
+// how the counter went, as the clients saw it
+#[derive(Default)]
+struct Counted {
+    // added for certain, and added as far as anyone can tell: answered or not, short of a
+    // definite error
+    added: u64,
+    maybe_added: u64,
+    taken: u64,
+    // by msg_id, what was asked and hasn't been answered yet
+    pending: HashMap<usize, Payload>,
+}
+
+impl Counted {
+    fn ask(&mut self, sim: &mut Cluster, client: &str, via: &str, request: Payload) -> usize {
+        if let Payload::Add { delta } = request {
+            self.maybe_added += delta;
+        }
+        let msg_id = sim
+            .send(client, via, request.clone())
+            .expect("request sends");
+        self.pending.insert(msg_id, request);
+        msg_id
+    }
+
+    // every take answered has to have been covered by what might have been added by then
+    fn answered(&mut self, sim: &mut Cluster, seed: u64) {
+        for client in CLIENTS {
+            for reply in sim.take_replies(client).expect("replies parse") {
+                let msg_id = reply.body.in_reply_to.expect("replies say what to");
+                let Some(request) = self.pending.remove(&msg_id) else {
+                    continue;
+                };
+                match (request, reply.body.payload) {
+                    (Payload::Add { delta }, Payload::AddOk) => self.added += delta,
+                    (Payload::Add { delta }, Payload::Error { code, .. })
+                        if error::is_definite(code) =>
+                    {
+                        self.maybe_added -= delta
+                    }
+                    (Payload::Take { amount }, Payload::TakeOk) => {
+                        self.taken += amount;
+                        assert!(
+                            self.taken <= self.maybe_added,
+                            "seed {}: {} taken out of at most {} added",
+                            seed,
+                            self.taken,
+                            self.maybe_added
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
+
+// clients adding to and taking from the counter through any node for ten seconds, while the nodes
+// lose, repeat and reorder the allotments they tell each other about, seq-kv behaves as `conduct`
+// has it, and `crashed` crashes for good four seconds in. A client waits up to a second for an
+// answer before it moves on to something else.
+fn counted(seed: u64, conduct: Conduct, crashed: Option<&str>) -> (Cluster, Counted) {
+    let mut sim = cluster(seed, conduct);
+    sim.faults(Faults {
+        drop: 0.1,
+        duplicate: 0.1,
+        reorder: 0.1,
+        ..Faults::default()
+    });
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut up = NODES.to_vec();
+    let mut counted = Counted::default();
+    let mut asked: HashMap<&str, (usize, Duration)> = HashMap::new();
+    while sim.now() < Duration::from_secs(10) {
+        if let Some(crashed) = crashed.filter(|_| sim.now() >= Duration::from_secs(4)) {
+            if up.contains(&crashed) {
+                up.retain(|n| *n != crashed);
+                sim.disrupt(Disruption::Kill(Target::Node(crashed.to_string())))
+                    .expect("kills");
+            }
+        }
+        counted.answered(&mut sim, seed);
+        for client in CLIENTS {
+            if asked.get(client).is_some_and(|(msg_id, at)| {
+                counted.pending.contains_key(msg_id) && sim.now() - *at < Duration::from_secs(1)
+            }) {
+                continue;
+            }
+            let request = match rng.gen_range(0..2) {
+                0 => Payload::Add {
+                    delta: rng.gen_range(1..5),
+                },
+                _ => Payload::Take {
+                    amount: rng.gen_range(1..8),
+                },
+            };
+            let dst = up[rng.gen_range(0..up.len())];
+            let msg_id = counted.ask(&mut sim, client, dst, request);
+            asked.insert(client, (msg_id, sim.now()));
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.faults(Faults::default());
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    counted.answered(&mut sim, seed);
+    (sim, counted)
+}
+
+#[test]
+fn nothing_is_taken_that_might_not_have_been_added_through_faults_and_a_crash() {
+    for seed in [1, 2, 3] {
+        let conduct = Conduct {
+            latency: Duration::ZERO..=Duration::from_millis(20),
+            unavailable: 0.1,
+            lost: 0.05,
+            ..Conduct::default()
+        };
+        let crashed = NODES[StdRng::seed_from_u64(seed).gen_range(0..NODES.len())];
+        let (mut sim, counted) = counted(seed, conduct, Some(crashed));
+        assert!(counted.taken > 0, "seed {}: nothing was taken", seed);
+        for node in NODES.into_iter().filter(|n| *n != crashed) {
+            let left = read(&mut sim, node).expect("answers");
+            assert!(
+                counted.taken + left <= counted.maybe_added,
+                "seed {}: {} reads {} with {} taken out of at most {} added",
+                seed,
+                node,
+                left,
+                counted.taken,
+                counted.maybe_added
+            );
+        }
+    }
+}
+
+// with every seq-kv answer getting through and no node crashing, nothing can go missing either:
+// what's left is exactly what was added less what was taken
+#[test]
+fn the_counter_adds_up_exactly_when_nothing_is_lost() {
+    for seed in [4, 5, 6] {
+        let conduct = Conduct {
+            latency: Duration::ZERO..=Duration::from_millis(20),
+            unavailable: 0.1,
+            ..Conduct::default()
+        };
+        let (mut sim, counted) = counted(seed, conduct, None);
+        assert!(counted.taken > 0, "seed {}: nothing was taken", seed);
+        assert_eq!(counted.added, counted.maybe_added, "seed {}", seed);
+        for node in NODES {
+            assert_eq!(
+                read(&mut sim, node),
+                Some(counted.added - counted.taken),
+                "seed {}: {}'s read",
+                seed,
+                node
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::{BTreeSet, HashMap};
+
+use rustengan::sim::explore::{Explorer, Reached};
+use rustengan::Node;
+
+// the broadcast workload's node, built from the same source as its own binary
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+use broadcast::{BroadcastNode, Gossip, InjectedPayload, Payload};
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+fn messages(node: &BroadcastNode) -> BTreeSet<usize> {
+    serde_json::from_value(node.status()["messages"].clone()).expect("status lists messages")
+}
+
+fn everyone_has_everything(state: &Reached<BroadcastNode>) -> bool {
+    state
+        .nodes()
+        .iter()
+        .all(|node| messages(node) == BTreeSet::from([1, 2]))
+}
+
+fn nothing_made_up(state: &Reached<BroadcastNode>) -> bool {
+    state
+        .nodes()
+        .iter()
+        .all(|node| messages(node).is_subset(&BTreeSet::from([1, 2])))
+}
+
+fn every_request_answered(state: &Reached<BroadcastNode>) -> bool {
+    state.replies(0).len() == 4
+}
+
+// a line, n0 - n1 - n2, so n0 and n2 only hear of each other's messages through n1
+fn topology() -> Payload {
+    let topology = HashMap::from([
+        ("n0".to_string(), vec!["n1".to_string()]),
+        ("n1".to_string(), vec!["n0".to_string(), "n2".to_string()]),
+        ("n2".to_string(), vec!["n1".to_string()]),
+    ]);
+    Payload::Topology { topology }
+}
+
+// the line's topology handed to the ends, a message broadcast to each of them, and the middle
+// node cut off from both for a while
+fn cluster() -> Explorer<Gossip, BroadcastNode, Payload, InjectedPayload> {
+    Explorer::new(&NODES, Gossip::default())
+        .client([
+            ("n0", topology()),
+            ("n2", topology()),
+            ("n0", Payload::Broadcast { message: 1 }),
+            ("n2", Payload::Broadcast { message: 2 }),
+        ])
+        .partition(&[&["n0", "n2"], &["n1"]])
+        .always("nothing made up", nothing_made_up)
+        .converges("every request answered", every_request_answered)
+        .converges("everyone has everything", everyone_has_everything)
+}
+
+#[test]
+fn gossip_gets_every_broadcast_everywhere_however_the_run_goes() {
+    // the middle node hears the topology too, so it passes on what the ends tell it
+    let explored = cluster()
+        .client([("n1", topology())])
+        .every(|| InjectedPayload::Gossip)
+        .in_flight(2)
+        .explore()
+        .expect("every property holds");
+    assert!(explored.states > 1000, "{:?}", explored);
+    assert!(explored.moves > explored.states);
+}
+
+#[test]
+fn without_gossip_nothing_gets_past_the_node_it_was_broadcast_to() {
+    let err = cluster()
+        .explore()
+        .expect_err("no node ever hears of another's messages");
+    let report = format!("{:#}", err);
+    assert!(
+        report.contains("everyone has everything can't come about"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn a_node_that_never_heard_the_topology_keeps_its_messages_to_itself() {
+    // n1's the only way between the ends, and it's never told who its neighbours are
+    let err = cluster()
+        .every(|| InjectedPayload::Gossip)
+        .in_flight(2)
+        .explore()
+        .expect_err("n0 and n2 only ever hear from each other through n1");
+    assert!(
+        format!("{:#}", err).contains("everyone has everything can't come about"),
+        "{:#}",
+        err
+    );
+}
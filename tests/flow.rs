@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+use rustengan::flow::{self, Hop};
+use rustengan::session::{Direction, Record};
+use serde_json::{json, Value};
+
+const MS: u64 = 1_000_000;
+
+fn message(src: &str, dst: &str, body: Value) -> Value {
+    json!({ "src": src, "dest": dst, "body": body })
+}
+
+fn at(ms: u64, direction: Direction, message: &Value) -> Record {
+    Record {
+        time: ms * MS,
+        direction,
+        message: message.clone(),
+    }
+}
+
+// c1 asks n1 to broadcast, n1 gossips it to n2 and n3 and acks c1, n3 acks the gossip but n2's
+// ack never gets to n1; then, separately, c2 reads from n2
+fn cluster() -> Vec<(String, Vec<Record>)> {
+    let broadcast = message(
+        "c1",
+        "n1",
+        json!({ "type": "broadcast", "msg_id": 1, "message": 7 }),
+    );
+    let to_n2 = message(
+        "n1",
+        "n2",
+        json!({ "type": "gossip", "msg_id": 1, "messages": [7] }),
+    );
+    let to_n3 = message(
+        "n1",
+        "n3",
+        json!({ "type": "gossip", "msg_id": 2, "messages": [7] }),
+    );
+    let ack = message(
+        "n1",
+        "c1",
+        json!({ "type": "broadcast_ok", "in_reply_to": 1 }),
+    );
+    let n2_ok = message("n2", "n1", json!({ "type": "gossip_ok", "in_reply_to": 1 }));
+    let n3_ok = message("n3", "n1", json!({ "type": "gossip_ok", "in_reply_to": 2 }));
+    let read = message("c2", "n2", json!({ "type": "read", "msg_id": 1 }));
+    let read_ok = message(
+        "n2",
+        "c2",
+        json!({ "type": "read_ok", "in_reply_to": 1, "messages": [7] }),
+    );
+    vec![
+        (
+            "n1".to_string(),
+            vec![
+                at(10, Direction::Received, &broadcast),
+                at(11, Direction::Sent, &to_n2),
+                at(11, Direction::Sent, &to_n3),
+                at(12, Direction::Sent, &ack),
+                at(15, Direction::Received, &n3_ok),
+            ],
+        ),
+        (
+            "n2".to_string(),
+            vec![
+                at(13, Direction::Received, &to_n2),
+                at(13, Direction::Sent, &n2_ok),
+                at(50, Direction::Received, &read),
+                at(51, Direction::Sent, &read_ok),
+            ],
+        ),
+        (
+            "n3".to_string(),
+            vec![
+                at(14, Direction::Received, &to_n3),
+                at(14, Direction::Sent, &n3_ok),
+            ],
+        ),
+    ]
+}
+
+fn kinds(hops: &[Hop]) -> Vec<String> {
+    hops.iter()
+        .map(|hop| format!("{}->{} {}", hop.src, hop.dst, hop.kind))
+        .collect()
+}
+
+#[test]
+fn each_message_is_one_hop_from_its_sender_to_its_receiver() {
+    let hops = flow::from_sessions(&cluster());
+    assert_eq!(
+        kinds(&hops),
+        [
+            "c1->n1 broadcast",
+            "n1->n2 gossip",
+            "n1->n3 gossip",
+            "n1->c1 broadcast_ok",
+            "n2->n1 gossip_ok",
+            "n3->n1 gossip_ok",
+            "c2->n2 read",
+            "n2->c2 read_ok",
+        ]
+    );
+    // the gossip to n2 was logged at both ends
+    assert_eq!(hops[1].sent, Some(11 * MS));
+    assert_eq!(hops[1].received, Some(13 * MS));
+    assert!(!hops[1].lost);
+    // a client keeps no log, so its request only arrived and its ack was only sent, and
+    // neither was lost
+    assert_eq!((hops[0].sent, hops[0].received), (None, Some(10 * MS)));
+    assert_eq!((hops[3].sent, hops[3].received), (Some(12 * MS), None));
+    assert!(!hops[0].lost && !hops[3].lost);
+    // but n1 kept a log, and never got n2's ack
+    assert!(hops[4].lost);
+    assert_eq!(hops.iter().filter(|hop| hop.lost).count(), 1);
+}
+
+#[test]
+fn a_window_keeps_what_started_within_it() {
+    let hops = flow::from_sessions(&cluster());
+    let window = flow::window(&hops, Duration::from_millis(2), Duration::from_millis(5));
+    assert_eq!(
+        kinds(&window),
+        [
+            "n1->c1 broadcast_ok",
+            "n2->n1 gossip_ok",
+            "n3->n1 gossip_ok"
+        ]
+    );
+    let late = flow::window(&hops, Duration::from_millis(30), Duration::from_secs(1));
+    assert_eq!(kinds(&late), ["c2->n2 read", "n2->c2 read_ok"]);
+}
+
+#[test]
+fn a_chain_follows_a_request_through_every_node_it_touched() {
+    let hops = flow::from_sessions(&cluster());
+    let chain = flow::chain(&hops, "c1", 1);
+    assert_eq!(
+        kinds(&chain),
+        [
+            "c1->n1 broadcast",
+            "n1->n2 gossip",
+            "n1->n3 gossip",
+            "n1->c1 broadcast_ok",
+            "n2->n1 gossip_ok",
+            "n3->n1 gossip_ok",
+        ]
+    );
+    // c2's read came after, and from somewhere else
+    assert_eq!(
+        kinds(&flow::chain(&hops, "c2", 1)),
+        ["c2->n2 read", "n2->c2 read_ok"]
+    );
+    assert!(flow::chain(&hops, "c1", 2).is_empty());
+}
+
+#[test]
+fn the_same_message_sent_twice_is_two_hops() {
+    let retry = message("n1", "n2", json!({ "type": "gossip", "messages": [7] }));
+    let logs = vec![
+        (
+            "n1".to_string(),
+            vec![
+                at(1, Direction::Sent, &retry),
+                at(5, Direction::Sent, &retry),
+            ],
+        ),
+        ("n2".to_string(), vec![at(6, Direction::Received, &retry)]),
+    ];
+    let hops = flow::from_sessions(&logs);
+    assert_eq!(hops.len(), 2);
+    // the first sent is taken to be the first received, and the second lost
+    assert_eq!(hops[0].received, Some(6 * MS));
+    assert!(!hops[0].lost);
+    assert!(hops[1].lost);
+}
+
+#[test]
+fn renders_as_graphviz_and_mermaid() {
+    let hops = flow::from_sessions(&cluster());
+    let dot = flow::to_dot(&hops);
+    assert!(dot.starts_with("digraph flow {"), "{}", dot);
+    assert!(dot.contains(r#""c1" [shape=box];"#), "{}", dot);
+    assert!(dot.contains(r#""n1" [shape=ellipse];"#), "{}", dot);
+    assert!(
+        dot.contains(r#""n2" -> "n1" [label="gossip_ok ×1 (1 lost)", style=dashed];"#),
+        "{}",
+        dot
+    );
+    assert!(
+        dot.contains(r#""n1" -> "n2" [label="gossip ×1", style=solid];"#),
+        "{}",
+        dot
+    );
+
+    let sequence = flow::to_sequence(&flow::chain(&hops, "c1", 1));
+    let lines: Vec<&str> = sequence.lines().collect();
+    assert_eq!(
+        lines,
+        [
+            "sequenceDiagram",
+            "  autonumber",
+            "  participant c1",
+            "  participant n1",
+            "  participant n2",
+            "  participant n3",
+            "  c1->>n1: broadcast #1 @0.000ms",
+            "  n1->>n2: gossip #1 @1.000ms",
+            "  n1->>n3: gossip #2 @1.000ms",
+            "  n1->>c1: broadcast_ok re #1 @2.000ms",
+            "  n2-xn1: gossip_ok re #1 @3.000ms",
+            "  n3->>n1: gossip_ok re #2 @4.000ms",
+        ]
+    );
+}
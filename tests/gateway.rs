@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rustengan::harness::Process;
+use rustengan::Message;
+use serde_json::{json, Value};
+
+// a cluster node as the gateway sees it: it echoes whatever comes in on a connection back on it,
+// and can drop every connection it has, as it would if it restarted
+struct Cluster {
+    addr: String,
+    connections: Arc<Mutex<Vec<TcpStream>>>,
+    // by connection, every client that sent something over it
+    senders: Arc<Mutex<Vec<Vec<String>>>>,
+}
+
+impl Cluster {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("binds");
+        let addr = listener.local_addr().expect("bound").to_string();
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let senders = Arc::new(Mutex::new(Vec::new()));
+        let (accepted, seen) = (Arc::clone(&connections), Arc::clone(&senders));
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let mut replies = stream.try_clone().expect("clones");
+                accepted
+                    .lock()
+                    .expect("not poisoned")
+                    .push(stream.try_clone().expect("clones"));
+                let seen = Arc::clone(&seen);
+                let connection = {
+                    let mut seen = seen.lock().expect("not poisoned");
+                    seen.push(Vec::new());
+                    seen.len() - 1
+                };
+                std::thread::spawn(move || {
+                    for line in BufReader::new(stream).lines() {
+                        let Ok(line) = line else { break };
+                        let request: Message<Value> =
+                            serde_json::from_str(&line).expect("requests are messages");
+                        seen.lock().expect("not poisoned")[connection].push(request.src.clone());
+                        let reply = json!({
+                            "src": request.dst,
+                            "dest": request.src,
+                            "body": {
+                                "type": "echo_ok",
+                                "echo": request.body.payload["echo"],
+                                "in_reply_to": request.body.id,
+                            },
+                        });
+                        if writeln!(replies, "{}", reply).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+        Self {
+            addr,
+            connections,
+            senders,
+        }
+    }
+
+    fn drop_connections(&self) {
+        for stream in self.connections.lock().expect("not poisoned").drain(..) {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+// clients echoing numbered requests through the gateway, each retrying a request that isn't
+// answered in time under a fresh msg_id, while the cluster node drops every connection a couple of
+// times along the way. Every reply has to reach the client that asked, carrying what it asked
+// with, every request has to be answered in the end, and no connection may carry more than one
+// client's requests.
+#[test]
+fn every_client_gets_its_own_answers_through_dropped_connections() {
+    let cluster = Cluster::start();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gateway"));
+    command.env("RUSTENGAN_NODES", format!("n0={}", cluster.addr));
+    let mut gateway = Process::command(command).expect("gateway starts");
+    gateway.init("n0", &["n0"]).expect("gateway inits");
+    gateway.within(Duration::from_millis(200));
+
+    // by client and msg_id, what each request asked to have echoed
+    let mut asked: HashMap<String, HashMap<usize, String>> = HashMap::new();
+    for round in 0..30 {
+        if round % 10 == 5 {
+            cluster.drop_connections();
+        }
+        for client in ["c0", "c1", "c2"] {
+            let echo = format!("{} {}", client, round);
+            let answered = (0..10).any(|_| {
+                let id = gateway
+                    .send(client, json!({ "type": "echo", "echo": echo }))
+                    .expect("request sends");
+                asked
+                    .entry(client.to_string())
+                    .or_default()
+                    .insert(id, echo.clone());
+                let Ok(reply) = gateway.reply_to::<Value>(client, id) else {
+                    return false;
+                };
+                assert_eq!(reply.body.payload["echo"], json!(echo));
+                true
+            });
+            assert!(answered, "{} never got an answer to {:?}", client, echo);
+        }
+    }
+    // answers to requests that were retried come in late, and have to be right all the same
+    while let Some(reply) = gateway.recv().expect("output parses") {
+        let asked = asked
+            .get(&reply.dst)
+            .map(|a| &a[&reply.body.in_reply_to.expect("a reply")]);
+        assert_eq!(
+            Some(&reply.body.payload["echo"]),
+            asked.map(|a| json!(a)).as_ref()
+        );
+    }
+
+    for senders in cluster.senders.lock().expect("not poisoned").iter() {
+        assert!(
+            senders.windows(2).all(|w| w[0] == w[1]),
+            "one connection carried {:?}",
+            senders
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::history::linearizable::{check, Violation};
+use rustengan::history::{Op, Type, F};
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::skew::Skew;
+use rustengan::sim::Sim;
+use rustengan::{error, Event, Init, Message, Node, Output};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+#[allow(dead_code)]
+#[path = "../src/bin/lease_kv.rs"]
+mod lease_kv;
+
+// builds histories an entry at a time, numbering them and keeping time
+#[derive(Default)]
+struct History(Vec<Op>);
+
+impl History {
+    fn push(&mut self, process: usize, kind: Type, f: F, value: Value) -> &mut Self {
+        let index = self.0.len();
+        self.0.push(Op {
+            index,
+            kind,
+            f,
+            value,
+            process,
+            time: index as u64,
+        });
+        self
+    }
+
+    fn invoke(&mut self, process: usize, f: F, value: Value) -> &mut Self {
+        self.push(process, Type::Invoke, f, value)
+    }
+
+    fn ok(&mut self, process: usize, f: F, value: Value) -> &mut Self {
+        self.push(process, Type::Ok, f, value)
+    }
+
+    fn check(&self) -> Result<(), Violation> {
+        check(&self.0)
+    }
+}
+
+#[test]
+fn a_read_sees_the_write_before_it() {
+    let mut history = History::default();
+    history
+        .invoke(0, F::Write, json!(["x", 1]))
+        .ok(0, F::Write, json!(["x", 1]))
+        .invoke(1, F::Read, json!(["x", null]))
+        .ok(1, F::Read, json!(["x", 1]));
+    assert_eq!(history.check(), Ok(()));
+}
+
+#[test]
+fn a_stale_read_isnt_linearizable() {
+    let mut history = History::default();
+    history
+        .invoke(0, F::Write, json!(["x", 1]))
+        .ok(0, F::Write, json!(["x", 1]))
+        .invoke(0, F::Write, json!(["x", 2]))
+        .ok(0, F::Write, json!(["x", 2]))
+        .invoke(1, F::Read, json!(["x", null]))
+        .ok(1, F::Read, json!(["x", 1]));
+    let violation = history.check().expect_err("the read is stale");
+    assert_eq!(violation.key, json!("x"));
+    assert_eq!(violation.linearized, vec![0, 2]);
+    assert_eq!(violation.stuck, 4);
+}
+
+#[test]
+fn a_read_concurrent_with_a_write_can_see_either_value() {
+    for read in [1, 2] {
+        let mut history = History::default();
+        history
+            .invoke(0, F::Write, json!(["x", 1]))
+            .ok(0, F::Write, json!(["x", 1]))
+            .invoke(0, F::Write, json!(["x", 2]))
+            .invoke(1, F::Read, json!(["x", null]))
+            .ok(1, F::Read, json!(["x", read]))
+            .ok(0, F::Write, json!(["x", 2]));
+        assert_eq!(history.check(), Ok(()), "reading {}", read);
+    }
+}
+
+#[test]
+fn reads_cant_go_back_once_a_later_read_has_seen_a_write() {
+    let mut history = History::default();
+    history
+        .invoke(0, F::Write, json!(["x", 1]))
+        .ok(0, F::Write, json!(["x", 1]))
+        .invoke(0, F::Write, json!(["x", 2]))
+        .invoke(1, F::Read, json!(["x", null]))
+        .ok(1, F::Read, json!(["x", 2]))
+        .invoke(2, F::Read, json!(["x", null]))
+        .ok(2, F::Read, json!(["x", 1]))
+        .ok(0, F::Write, json!(["x", 2]));
+    assert!(history.check().is_err());
+}
+
+#[test]
+fn an_indeterminate_write_may_or_may_not_have_happened() {
+    for read in [1, 2] {
+        let mut history = History::default();
+        history
+            .invoke(0, F::Write, json!(["x", 1]))
+            .ok(0, F::Write, json!(["x", 1]))
+            .invoke(0, F::Write, json!(["x", 2]))
+            .push(0, Type::Info, F::Write, json!(["x", 2]))
+            .invoke(1, F::Read, json!(["x", null]))
+            .ok(1, F::Read, json!(["x", read]))
+            .invoke(1, F::Read, json!(["x", null]))
+            .ok(1, F::Read, json!(["x", read]));
+        assert_eq!(history.check(), Ok(()), "reading {}", read);
+    }
+}
+
+#[test]
+fn a_failed_write_didnt_happen() {
+    let mut history = History::default();
+    history
+        .invoke(0, F::Write, json!(["x", 1]))
+        .push(0, Type::Fail, F::Write, json!(["x", 1]))
+        .invoke(1, F::Read, json!(["x", null]))
+        .ok(1, F::Read, json!(["x", 1]));
+    assert!(history.check().is_err());
+}
+
+#[test]
+fn a_cas_only_succeeds_from_the_value_its_given() {
+    let mut history = History::default();
+    history
+        .invoke(0, F::Write, json!(["x", 1]))
+        .ok(0, F::Write, json!(["x", 1]))
+        .invoke(0, F::Cas, json!(["x", [1, 3]]))
+        .ok(0, F::Cas, json!(["x", [1, 3]]))
+        .invoke(1, F::Cas, json!(["x", [1, 4]]))
+        .ok(1, F::Cas, json!(["x", [1, 4]]));
+    let violation = history.check().expect_err("the register was at 3");
+    assert_eq!(violation.stuck, 4);
+}
+
+#[test]
+fn keys_are_checked_on_their_own() {
+    let mut history = History::default();
+    history
+        .invoke(0, F::Write, json!(["x", 1]))
+        .invoke(1, F::Write, json!(["y", 1]))
+        .ok(0, F::Write, json!(["x", 1]))
+        .ok(1, F::Write, json!(["y", 1]))
+        .invoke(0, F::Read, json!(["x", null]))
+        .invoke(1, F::Read, json!(["y", null]))
+        .ok(0, F::Read, json!(["x", 1]))
+        .ok(1, F::Read, json!(["y", 2]));
+    let violation = history.check().expect_err("y was never 2");
+    assert_eq!(violation.key, json!("y"));
+}
+
+#[test]
+fn a_long_history_of_a_single_register_checks_quickly() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut history = History::default();
+    let mut register = None;
+    // a few processes at a time, each op invoked together and completing in some order, and
+    // taking effect in the order they complete
+    for _ in 0..500 {
+        let processes = rng.gen_range(1..=4);
+        let mut ops = Vec::new();
+        for process in 0..processes {
+            let (f, value) = match rng.gen_range(0..3) {
+                0 => (F::Read, json!(["x", null])),
+                1 => (F::Write, json!(["x", rng.gen_range(0..5)])),
+                _ => (
+                    F::Cas,
+                    json!(["x", [rng.gen_range(0..5), rng.gen_range(0..5)]]),
+                ),
+            };
+            history.invoke(process, f, value.clone());
+            ops.push((process, f, value));
+        }
+        for (process, f, value) in ops.into_iter().rev() {
+            match f {
+                F::Read => {
+                    let read = register.map_or(Value::Null, Value::from);
+                    history.ok(process, f, json!(["x", read]));
+                }
+                F::Write => {
+                    register = value[1].as_u64();
+                    history.ok(process, f, value);
+                }
+                F::Cas if register == value[1][0].as_u64() => {
+                    register = value[1][1].as_u64();
+                    history.ok(process, f, value);
+                }
+                F::Cas => {
+                    history.push(process, Type::Fail, f, value);
+                }
+            }
+        }
+    }
+    let started = std::time::Instant::now();
+    assert_eq!(history.check(), Ok(()));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Read { key: u64 },
+    ReadOk { value: u64 },
+    Write { key: u64, value: u64 },
+    WriteOk,
+    Cas { key: u64, from: u64, to: u64 },
+    CasOk,
+    Error { code: usize, text: String },
+    Replicate { key: u64, value: u64 },
+}
+
+// a key/value store whose nodes each answer from their own copy, telling everyone else about
+// writes after they've acknowledged them: linearizable on one node, and not on any more
+struct Kv {
+    node: String,
+    others: Vec<String>,
+    id: usize,
+    values: BTreeMap<u64, u64>,
+}
+
+impl Node<(), Payload> for Kv {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Payload>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            others: init
+                .node_ids
+                .iter()
+                .filter(|id| **id != init.node_id)
+                .cloned()
+                .collect(),
+            node: init.node_id,
+            id: 1,
+            values: BTreeMap::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(Some(&mut self.id));
+        let mut replicate = None;
+        reply.body.payload = match reply.body.payload {
+            Payload::Read { key } => match self.values.get(&key) {
+                Some(&value) => Payload::ReadOk { value },
+                None => Payload::Error {
+                    code: error::KEY_DOES_NOT_EXIST,
+                    text: "not found".to_string(),
+                },
+            },
+            Payload::Write { key, value } => {
+                self.values.insert(key, value);
+                replicate = Some((key, value));
+                Payload::WriteOk
+            }
+            Payload::Cas { key, from, to } => match self.values.get(&key) {
+                Some(&value) if value == from => {
+                    self.values.insert(key, to);
+                    replicate = Some((key, to));
+                    Payload::CasOk
+                }
+                Some(_) => Payload::Error {
+                    code: error::PRECONDITION_FAILED,
+                    text: "not from".to_string(),
+                },
+                None => Payload::Error {
+                    code: error::KEY_DOES_NOT_EXIST,
+                    text: "not found".to_string(),
+                },
+            },
+            Payload::Replicate { key, value } => {
+                self.values.insert(key, value);
+                return Ok(());
+            }
+            Payload::ReadOk { .. } | Payload::WriteOk | Payload::CasOk | Payload::Error { .. } => {
+                return Ok(())
+            }
+        };
+        reply.send(output)?;
+        if let Some((key, value)) = replicate {
+            for dst in &self.others {
+                let replicate = Message {
+                    src: self.node.clone(),
+                    dst: dst.clone(),
+                    body: rustengan::Body {
+                        id: None,
+                        in_reply_to: None,
+                        correlation_id: None,
+                        payload: Payload::Replicate { key, value },
+                    },
+                };
+                replicate.send(output)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// a few clients at a time reading, writing and cas-ing a couple of keys on `nodes`, each client
+// waiting on its answer before the next round
+fn kv_history(seed: u64, nodes: &[&str]) -> Vec<Op> {
+    let mut sim = Sim::new(seed, nodes);
+    sim.start::<(), Kv>(()).expect("nodes start");
+    let mut rng = StdRng::seed_from_u64(seed);
+    for _ in 0..200 {
+        for client in 0..3 {
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..3) {
+                0 => Payload::Read { key },
+                1 => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+                _ => Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..5),
+                    to: rng.gen_range(0..5),
+                },
+            };
+            let dst = nodes[rng.gen_range(0..nodes.len())];
+            sim.send(&format!("c{}", client), dst, request)
+                .expect("request sends");
+            sim.run_for(Duration::from_millis(rng.gen_range(0..5)))
+                .expect("nodes step");
+        }
+        sim.run_for(Duration::from_millis(30)).expect("nodes step");
+    }
+    sim.history()
+}
+
+#[test]
+fn a_single_copy_is_linearizable() {
+    let history = kv_history(3, &["n0"]);
+    assert!(history.len() > 1000);
+    assert_eq!(check(&history), Ok(()));
+}
+
+#[test]
+fn copies_that_catch_up_after_acknowledging_writes_arent() {
+    let history = kv_history(3, &["n0", "n1", "n2"]);
+    let violation = check(&history).expect_err("some read is stale");
+    assert!(violation.stuck > 0, "{}", violation);
+}
+
+// clients reading, writing and cas-ing a couple of keys on a cluster of lease_kv nodes, whose
+// clocks run as far apart as the lease allows for, through partitions, pauses and crashes. Along
+// with the history, the fencing tokens the answers came with.
+fn lease_kv_history(seed: u64) -> (Vec<Op>, Vec<u64>) {
+    let nodes = ["n0", "n1", "n2", "n3", "n4"];
+    let mut sim = Sim::new(seed, &nodes);
+    for (node, drift) in nodes.iter().zip([0.009, -0.009, 0.005, -0.005, 0.0]) {
+        sim.skew(
+            node,
+            Skew {
+                offset_ms: 0,
+                drift,
+            },
+        );
+    }
+    sim.start::<(), lease_kv::LeaseKvNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(50), || {
+        lease_kv::InjectedPayload::Tick
+    });
+    sim.nemesis(
+        Nemesis::partitions(
+            Split::Halves,
+            Duration::from_millis(2500),
+            Duration::from_millis(2000),
+        )
+        .until(Duration::from_secs(15)),
+    );
+    sim.nemesis(
+        Nemesis::new()
+            .at(Duration::from_secs(2), Disruption::Pause(Target::Random))
+            .at(Duration::from_millis(3500), Disruption::Resume)
+            .at(Duration::from_secs(6), Disruption::Kill(Target::Random))
+            .at(Duration::from_secs(8), Disruption::Restart)
+            .repeat_every(Duration::from_secs(8))
+            .until(Duration::from_secs(15)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    while sim.now() < Duration::from_secs(20) {
+        for client in 0..3 {
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..3) {
+                0 => lease_kv::Payload::Read { key },
+                1 => lease_kv::Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+                _ => lease_kv::Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..5),
+                    to: rng.gen_range(0..5),
+                },
+            };
+            let dst = nodes[rng.gen_range(0..nodes.len())];
+            sim.send(&format!("c{}", client), dst, request)
+                .expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    let mut tokens = Vec::new();
+    for client in 0..3 {
+        for reply in sim.replies(&format!("c{}", client)).expect("replies parse") {
+            match reply.body.payload {
+                lease_kv::Payload::ReadOk { token, .. }
+                | lease_kv::Payload::WriteOk { token }
+                | lease_kv::Payload::CasOk { token } => tokens.push(token),
+                _ => {}
+            }
+        }
+    }
+    (sim.history(), tokens)
+}
+
+#[test]
+fn a_leased_leader_stays_linearizable_through_faults_and_drifting_clocks() {
+    for seed in [5, 6, 7] {
+        let (history, tokens) = lease_kv_history(seed);
+        assert_eq!(check(&history), Ok(()), "seed {}", seed);
+        let answered = history
+            .iter()
+            .filter(|op| op.kind == Type::Ok && op.f == F::Read)
+            .count();
+        assert!(answered > 50, "seed {}: {} reads answered", seed, answered);
+        // the cluster went through more than one leader
+        let leaders: BTreeSet<u64> = tokens.into_iter().collect();
+        assert!(leaders.len() > 1, "seed {}: only {:?}", seed, leaders);
+    }
+}
@@ -0,0 +1,197 @@
+#[allow(dead_code)]
+#[path = "../src/bin/lock_service.rs"]
+mod lock_service;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use lock_service::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Target};
+use rustengan::sim::Sim;
+
+// n0 is the first node, so it's the lock server
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const CLIENTS: [&str; 4] = ["c0", "c1", "c2", "c3"];
+const LOCKS: [&str; 2] = ["a", "b"];
+const TTL: Duration = Duration::from_millis(300);
+
+// a lease as its holder saw it: from when it heard it was granted until it either asked to release
+// it or ran out the ttl of the last acquire or renew it sent that was answered
+#[derive(Debug, Clone)]
+struct Held {
+    lock: &'static str,
+    holder: &'static str,
+    token: u64,
+    from: Duration,
+    until: Duration,
+}
+
+// what a client is waiting to hear back about, and since when
+struct Asked {
+    msg_id: usize,
+    request: Payload,
+    at: Duration,
+}
+
+fn ms(duration: Duration) -> Option<u64> {
+    Some(duration.as_millis() as u64)
+}
+
+// clients taking, renewing and releasing short leases on a couple of locks through any node for
+// ten seconds, while the nodes lose, repeat, hold up and reorder what they send each other, and the
+// lock server crashes and comes back from its log. A client waits up to a second for an answer
+// before it gives up on it. What comes back is every lease the clients held.
+fn leases(seed: u64) -> Vec<Held> {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), lock_service::LockNode>(())
+        .expect("nodes start");
+    sim.faults(Faults {
+        drop: 0.1,
+        duplicate: 0.1,
+        delay: 0.1,
+        delay_by: Duration::from_millis(50),
+        reorder: 0.1,
+    });
+    sim.nemesis(
+        Nemesis::new()
+            .at(
+                Duration::from_secs(3),
+                Disruption::Kill(Target::Node("n0".to_string())),
+            )
+            .at(Duration::from_millis(4500), Disruption::Restart)
+            .until(Duration::from_secs(8)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut leases = Vec::new();
+    // by client, the lease it holds, as an index into `leases`
+    let mut holding: HashMap<&str, usize> = HashMap::new();
+    let mut asked: HashMap<&str, Asked> = HashMap::new();
+    while sim.now() < Duration::from_secs(10) {
+        let now = sim.now();
+        for client in CLIENTS {
+            for reply in sim.take_replies(client).expect("replies parse") {
+                let Some(Asked {
+                    msg_id,
+                    request,
+                    at,
+                }) = asked.get(client)
+                else {
+                    continue;
+                };
+                if reply.body.in_reply_to != Some(*msg_id) {
+                    continue;
+                }
+                match (request, reply.body.payload) {
+                    (Payload::Acquire { lock, .. }, Payload::AcquireOk { token, .. }) => {
+                        let lock = LOCKS.into_iter().find(|l| l == lock).expect("ours");
+                        holding.insert(client, leases.len());
+                        leases.push(Held {
+                            lock,
+                            holder: client,
+                            token,
+                            from: now,
+                            until: *at + TTL,
+                        });
+                    }
+                    (Payload::Renew { .. }, Payload::RenewOk { .. }) => {
+                        if let Some(&held) = holding.get(client) {
+                            leases[held].until = leases[held].until.max(*at + TTL);
+                        }
+                    }
+                    _ => {}
+                }
+                asked.remove(client);
+            }
+            if holding.get(client).is_some_and(|&h| leases[h].until <= now) {
+                holding.remove(client);
+            }
+            if asked
+                .get(client)
+                .is_some_and(|a| now - a.at < Duration::from_secs(1))
+            {
+                continue;
+            }
+            let request = match holding.get(client) {
+                Some(&held) => {
+                    let Held { lock, token, .. } = leases[held];
+                    match rng.gen_range(0..5) {
+                        0 | 1 => Payload::Renew {
+                            lock: lock.to_string(),
+                            token,
+                            ttl_ms: ms(TTL),
+                        },
+                        2 => {
+                            // it's not ours from the moment we let go of it
+                            leases[held].until = leases[held].until.min(now);
+                            holding.remove(client);
+                            Payload::Release {
+                                lock: lock.to_string(),
+                                token,
+                            }
+                        }
+                        _ => continue,
+                    }
+                }
+                None => Payload::Acquire {
+                    lock: LOCKS[rng.gen_range(0..LOCKS.len())].to_string(),
+                    ttl_ms: ms(TTL),
+                },
+            };
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            let msg_id = sim
+                .send(client, dst, request.clone())
+                .expect("request sends");
+            asked.insert(
+                client,
+                Asked {
+                    msg_id,
+                    request,
+                    at: now,
+                },
+            );
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    leases
+}
+
+// no two clients ever hold the same lock at once, and a lease granted after another one on the
+// same lock ran out carries a larger token, so fencing on it shuts out the earlier holder
+#[test]
+fn no_two_clients_hold_a_lock_at_once_through_faults_and_a_server_crash() {
+    for seed in [1, 2, 3] {
+        let leases = leases(seed);
+        assert!(
+            leases.iter().any(|l| l.from < l.until),
+            "seed {}: no lease was ever held",
+            seed
+        );
+        for a in &leases {
+            for b in &leases {
+                if a.lock != b.lock || a.holder == b.holder {
+                    continue;
+                }
+                assert!(
+                    a.until <= b.from || b.until <= a.from,
+                    "seed {}: {:?} and {:?} overlap",
+                    seed,
+                    a,
+                    b
+                );
+                if a.until <= b.from {
+                    assert!(
+                        a.token < b.token,
+                        "seed {}: {:?} came after {:?} with no larger a token",
+                        seed,
+                        b,
+                        a
+                    );
+                }
+            }
+        }
+    }
+}
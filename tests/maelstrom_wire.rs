@@ -0,0 +1,160 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+
+use rustengan::kv::{KvRequest, LIN_KV};
+use rustengan::txn::Op;
+use rustengan::Message;
+
+// a reply that isn't there by now isn't coming
+const REPLY_WITHIN: Duration = Duration::from_secs(5);
+
+// runs `binary` on the fixture `name` from tests/fixtures/maelstrom: every `> ` line goes to the
+// node's stdin, and every `< ` line has to come out of its stdout, byte for byte and in order
+fn golden(name: &str, binary: &str) {
+    let path = format!(
+        "{}/tests/fixtures/maelstrom/{}.txt",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let fixture = std::fs::read_to_string(&path).expect("fixture exists");
+    let mut requests = Vec::new();
+    let mut replies = Vec::new();
+    for line in fixture.lines() {
+        if let Some(request) = line.strip_prefix("> ") {
+            requests.push(request);
+        } else if let Some(reply) = line.strip_prefix("< ") {
+            replies.push(reply);
+        } else {
+            assert!(
+                line.is_empty() || line.starts_with('#'),
+                "{}: unexpected line {:?}",
+                path,
+                line
+            );
+        }
+    }
+
+    let data_dir =
+        std::env::temp_dir().join(format!("rustengan-golden-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    let mut node = Command::new(binary)
+        .env("RUSTENGAN_DATA_DIR", &data_dir)
+        .env_remove("RUSTENGAN_TRANSPORT")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("node starts");
+    let mut stdin = node.stdin.take().expect("piped");
+    for request in &requests {
+        writeln!(stdin, "{}", request).expect("node reads its input");
+    }
+
+    let stdout = node.stdout.take().expect("piped");
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            if line.map(|line| tx.send(line)).is_err() {
+                break;
+            }
+        }
+    });
+    let mut got = Vec::new();
+    for _ in &replies {
+        match rx.recv_timeout(REPLY_WITHIN) {
+            Ok(line) => got.push(line),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    // nodes that gossip never run out of things to do, so they're stopped rather than waited on
+    let _ = node.kill();
+    let _ = node.wait();
+    let _ = std::fs::remove_dir_all(&data_dir);
+
+    for (i, reply) in replies.iter().enumerate() {
+        assert_eq!(
+            got.get(i).map(String::as_str),
+            Some(*reply),
+            "{}: reply {}",
+            name,
+            i + 1
+        );
+    }
+}
+
+#[test]
+fn echo() {
+    golden("echo", env!("CARGO_BIN_EXE_echo"));
+}
+
+#[test]
+fn broadcast() {
+    golden("broadcast", env!("CARGO_BIN_EXE_broadcast"));
+}
+
+#[test]
+fn kv_errors() {
+    golden("lww_kv", env!("CARGO_BIN_EXE_lww_kv"));
+}
+
+#[test]
+fn lin_kv() {
+    golden("caspaxos", env!("CARGO_BIN_EXE_caspaxos"));
+}
+
+#[test]
+fn txn() {
+    golden("txn", env!("CARGO_BIN_EXE_txn"));
+}
+
+#[test]
+fn kv_requests_are_what_maelstrom_services_take() {
+    let mut out = Vec::new();
+    KvRequest::<String, usize>::Cas {
+        key: "counter".to_string(),
+        from: 1,
+        to: 2,
+        create_if_not_exists: true,
+    }
+    .send("n0", LIN_KV, 5, &mut out)
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "{\"src\":\"n0\",\"dest\":\"lin-kv\",\"body\":{\"msg_id\":5,\"in_reply_to\":null,\
+         \"type\":\"cas\",\"key\":\"counter\",\"from\":1,\"to\":2,\"create_if_not_exists\":true}}\n"
+    );
+}
+
+#[test]
+fn maelstrom_envelopes_parse_with_their_extra_fields() {
+    // maelstrom numbers every message it routes with a top-level `id` we don't use
+    let message: Message<KvRequest<usize, usize>> = serde_json::from_str(
+        r#"{"id":12,"src":"c3","dest":"lin-kv","body":{"type":"cas","key":1,"from":2,"to":3,"msg_id":4}}"#,
+    )
+    .unwrap();
+    assert_eq!(message.src, "c3");
+    assert_eq!(message.dst, "lin-kv");
+    assert_eq!(message.body.id, Some(4));
+    assert_eq!(message.body.in_reply_to, None);
+    assert!(matches!(
+        message.body.payload,
+        KvRequest::Cas {
+            key: 1,
+            from: 2,
+            to: 3,
+            create_if_not_exists: false
+        }
+    ));
+}
+
+#[test]
+fn txn_ops_round_trip() {
+    let wire = r#"[["r",1,null],["w",1,6],["r",2,7]]"#;
+    let ops: Vec<Op> = serde_json::from_str(wire).unwrap();
+    assert_eq!(ops.len(), 3);
+    assert!(!ops[0].is_write() && ops[1].is_write());
+    assert_eq!(ops[2].key(), 2);
+    assert_eq!(serde_json::to_string(&ops).unwrap(), wire);
+}
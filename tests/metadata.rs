@@ -0,0 +1,214 @@
+#[allow(dead_code)]
+#[path = "../src/bin/metadata.rs"]
+mod metadata;
+
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use metadata::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+use serde_json::Value;
+
+type Cluster = Sim<Payload, metadata::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const KEYS: [&str; 3] = ["schema", "shards", "flags"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), metadata::MetadataNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(300), || {
+        metadata::InjectedPayload::Gossip
+    });
+    sim
+}
+
+fn read(sim: &mut Cluster, via: &str) -> Option<BTreeMap<String, Value>> {
+    sim.take_replies("reader").expect("replies parse");
+    sim.send("reader", via, Payload::Read)
+        .expect("request sends");
+    sim.run_for(Duration::from_millis(100)).expect("nodes step");
+    match sim
+        .take_replies("reader")
+        .expect("replies parse")
+        .pop()?
+        .body
+        .payload
+    {
+        Payload::ReadOk { metadata } => Some(metadata),
+        _ => None,
+    }
+}
+
+fn get(sim: &mut Cluster, via: &str, key: &str) -> Option<Value> {
+    sim.take_replies("reader").expect("replies parse");
+    let request = Payload::Get {
+        key: key.to_string(),
+    };
+    sim.send("reader", via, request).expect("request sends");
+    sim.run_for(Duration::from_millis(100)).expect("nodes step");
+    match sim
+        .take_replies("reader")
+        .expect("replies parse")
+        .pop()?
+        .body
+        .payload
+    {
+        Payload::GetOk { value } => value,
+        _ => None,
+    }
+}
+
+// a writer setting one key over and over, each time through a random node once that node has
+// heard of the previous value, while the nodes lose, repeat and reorder what they send each other.
+// Every write is made after seeing the one before it, so the last has to be what everyone ends up
+// with.
+#[test]
+fn a_write_made_after_seeing_another_replaces_it_everywhere() {
+    for seed in [4, 5, 6] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut last = None;
+        for value in 0u64..20 {
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            let asked = sim.now();
+            while get(&mut sim, dst, "schema") != last {
+                assert!(
+                    sim.now() - asked < Duration::from_secs(5),
+                    "seed {}: {} never heard of {:?}",
+                    seed,
+                    dst,
+                    last
+                );
+            }
+            let request = Payload::Set {
+                key: "schema".to_string(),
+                value: value.into(),
+            };
+            sim.send("writer", dst, request).expect("request sends");
+            last = Some(value.into());
+        }
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(3)).expect("nodes step");
+        for node in NODES {
+            assert_eq!(
+                get(&mut sim, node, "schema"),
+                last,
+                "seed {}: {}'s value",
+                seed,
+                node
+            );
+        }
+    }
+}
+
+#[test]
+fn a_write_through_a_node_that_just_came_back_reaches_everyone() {
+    let mut sim = cluster(1);
+    let set = |value: u64| Payload::Set {
+        key: "schema".to_string(),
+        value: value.into(),
+    };
+    sim.send("writer", "n0", set(1)).expect("request sends");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.disrupt(Disruption::Restart).expect("restarts");
+
+    // it comes back knowing nothing, and makes the same first write to the key it made before
+    sim.send("writer", "n0", set(2)).expect("request sends");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    let settled = read(&mut sim, "n0").expect("n0 answers");
+    for node in NODES {
+        assert_eq!(
+            read(&mut sim, node).as_ref(),
+            Some(&settled),
+            "{}'s map",
+            node
+        );
+    }
+}
+
+// a writer setting a few keys through any node for ten seconds, while the nodes lose, repeat and
+// reorder what they send each other, are partitioned from each other, and crash and come back
+// knowing nothing. Once the faults are over and the nodes have had a while to gossip, every node
+// has to have the same map, of values somebody set.
+#[test]
+fn every_node_settles_on_the_same_map_through_faults_and_crashes() {
+    for seed in [1, 2, 3] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(1500),
+                Duration::from_millis(1500),
+            )
+            .until(Duration::from_secs(10)),
+        );
+        sim.nemesis(
+            Nemesis::new()
+                .at(Duration::from_secs(2), Disruption::Kill(Target::Random))
+                .at(Duration::from_secs(4), Disruption::Restart)
+                .at(Duration::from_secs(6), Disruption::Kill(Target::Random))
+                .at(Duration::from_secs(8), Disruption::Restart)
+                .until(Duration::from_secs(10)),
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut set = HashSet::new();
+        for value in 0u64.. {
+            if sim.now() >= Duration::from_secs(10) {
+                break;
+            }
+            let key = KEYS[rng.gen_range(0..KEYS.len())];
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            let request = Payload::Set {
+                key: key.to_string(),
+                value: value.into(),
+            };
+            sim.send("writer", dst, request).expect("request sends");
+            set.insert((key.to_string(), Value::from(value)));
+            sim.run_for(Duration::from_millis(rng.gen_range(20..100)))
+                .expect("nodes step");
+        }
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+        let settled = read(&mut sim, "n0").expect("n0 answers");
+        assert!(!settled.is_empty(), "seed {}: nothing was set", seed);
+        for (key, value) in &settled {
+            assert!(
+                set.contains(&(key.clone(), value.clone())),
+                "seed {}: {} is {}, which nobody set",
+                seed,
+                key,
+                value
+            );
+        }
+        for node in NODES {
+            assert_eq!(
+                read(&mut sim, node).as_ref(),
+                Some(&settled),
+                "seed {}: {}'s map",
+                seed,
+                node
+            );
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::collections::BTreeSet;
+
+use rustengan::sim::model::{Model, Participant};
+use rustengan::{Body, Event, Init, Message, Node, Output};
+use serde::{Deserialize, Serialize};
+use stateright::actor::{ActorModel, ActorModelState};
+use stateright::{Checker, Expectation, Model as _};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Broadcast { message: u64 },
+    BroadcastOk,
+    Gossip { messages: BTreeSet<u64> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Spread {
+    // pass on what's heard from other nodes, as well as from clients
+    Relay,
+    // pass on only what clients send
+    Tell,
+}
+
+// a broadcast node that passes each new message to the next node round a ring, and on a tick
+// tells the next node everything it has
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Ring {
+    node: String,
+    next: String,
+    spread: Spread,
+    messages: BTreeSet<u64>,
+}
+
+impl Ring {
+    fn tell(&self, messages: BTreeSet<u64>, output: &mut Output) -> anyhow::Result<()> {
+        let gossip = Message {
+            src: self.node.clone(),
+            dst: self.next.clone(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload: Payload::Gossip { messages },
+            },
+        };
+        gossip.send(output)
+    }
+}
+
+impl Node<Spread, Payload> for Ring {
+    fn from_init(
+        spread: Spread,
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Payload>>,
+    ) -> anyhow::Result<Self> {
+        let at = init
+            .node_ids
+            .iter()
+            .position(|id| *id == init.node_id)
+            .expect("node is in the cluster");
+        Ok(Self {
+            next: init.node_ids[(at + 1) % init.node_ids.len()].clone(),
+            node: init.node_id,
+            spread,
+            messages: BTreeSet::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(()) => return self.tell(self.messages.clone(), output),
+            Event::EOF => return Ok(()),
+        };
+        let mut reply = input.into_reply(None);
+        match reply.body.payload {
+            Payload::Broadcast { message } => {
+                reply.body.payload = Payload::BroadcastOk;
+                reply.send(output)?;
+                if self.messages.insert(message) {
+                    self.tell(BTreeSet::from([message]), output)?;
+                }
+            }
+            Payload::Gossip { messages } => {
+                let new: BTreeSet<u64> = messages.difference(&self.messages).copied().collect();
+                self.messages.extend(&new);
+                if !new.is_empty() && self.spread == Spread::Relay {
+                    self.tell(new, output)?;
+                }
+            }
+            Payload::BroadcastOk => {}
+        }
+        Ok(())
+    }
+}
+
+type Cluster = ActorModel<Participant<Spread, Ring, Payload>>;
+type Reached = ActorModelState<Participant<Spread, Ring, Payload>>;
+
+fn rings(state: &Reached) -> impl Iterator<Item = &Ring> {
+    state.actor_states.iter().filter_map(|state| state.node())
+}
+
+fn everyone_has_everything(_: &Cluster, state: &Reached) -> bool {
+    rings(state).all(|ring| ring.messages == BTreeSet::from([1, 2]))
+}
+
+fn nothing_made_up(_: &Cluster, state: &Reached) -> bool {
+    rings(state).all(|ring| ring.messages.is_subset(&BTreeSet::from([1, 2])))
+}
+
+fn every_broadcast_acknowledged(_: &Cluster, state: &Reached) -> bool {
+    state
+        .actor_states
+        .iter()
+        .filter_map(|state| state.replies())
+        .all(|replies| replies.len() == 1)
+}
+
+// three nodes, and a client broadcasting to each of two of them
+fn cluster(spread: Spread) -> Model<Spread, Ring, Payload> {
+    Model::new(&["n0", "n1", "n2"], spread)
+        .client([("n0", Payload::Broadcast { message: 1 })])
+        .client([("n1", Payload::Broadcast { message: 2 })])
+}
+
+fn properties(model: Cluster) -> Cluster {
+    model
+        .property(Expectation::Always, "nothing made up", nothing_made_up)
+        .property(
+            Expectation::Eventually,
+            "every broadcast acknowledged",
+            every_broadcast_acknowledged,
+        )
+        .property(
+            Expectation::Eventually,
+            "everyone has everything",
+            everyone_has_everything,
+        )
+}
+
+#[test]
+fn relaying_round_the_ring_reaches_everyone_in_every_interleaving() {
+    let checker = properties(cluster(Spread::Relay).into_actor_model())
+        .checker()
+        .spawn_bfs()
+        .join();
+    checker.assert_properties();
+    assert!(checker.unique_state_count() > 10);
+}
+
+#[test]
+fn telling_only_the_next_node_leaves_the_one_after_out() {
+    let checker = properties(cluster(Spread::Tell).into_actor_model())
+        .checker()
+        .spawn_bfs()
+        .join();
+    checker.assert_no_discovery("nothing made up");
+    checker.assert_no_discovery("every broadcast acknowledged");
+    let path = checker.assert_any_discovery("everyone has everything");
+    let last = path.last_state();
+    assert!(!everyone_has_everything(checker.model(), last));
+}
+
+#[test]
+fn ticks_fire_wherever_they_could_and_make_up_for_not_relaying() {
+    let model = cluster(Spread::Tell)
+        .every(|| ())
+        .into_actor_model()
+        .property(Expectation::Always, "nothing made up", nothing_made_up)
+        .property(
+            Expectation::Sometimes,
+            "everyone has everything",
+            everyone_has_everything,
+        );
+    // every tick adds to what's in flight, so there's no end of states without a bound
+    let checker = model.checker().target_max_depth(10).spawn_bfs().join();
+    checker.assert_properties();
+}
+
+#[test]
+fn clients_see_the_replies_as_frames() {
+    let model = Model::<_, Ring, _>::new(&["n0"], Spread::Relay)
+        .client([("n0", Payload::Broadcast { message: 1 })])
+        .into_actor_model();
+    let started = &model.init_states()[0];
+    assert_eq!(started.actor_states[1].replies(), Some(&BTreeSet::new()));
+    let checker = model
+        .property(Expectation::Sometimes, "acknowledged", |_, state| {
+            state.actor_states[1]
+                .replies()
+                .is_some_and(|replies| replies.iter().any(|r| r.contains("broadcast_ok")))
+        })
+        .checker()
+        .spawn_bfs()
+        .join();
+    checker.assert_properties();
+}
@@ -0,0 +1,98 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rustengan::harness::Process;
+use rustengan::Message;
+use serde_json::{json, Value};
+
+const PARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+// a collector that takes whatever it's sent and keeps the path it was sent to
+fn collector() -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind the collector");
+    let addr = listener.local_addr().expect("bound");
+    let posted = Arc::new(Mutex::new(Vec::new()));
+    let kept = Arc::clone(&posted);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { break };
+            let kept = Arc::clone(&kept);
+            std::thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone()?);
+                loop {
+                    let mut request = String::new();
+                    if reader.read_line(&mut request)? == 0 {
+                        return std::io::Result::Ok(());
+                    }
+                    let mut length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header)?;
+                        let header = header.trim_end().to_ascii_lowercase();
+                        if header.is_empty() {
+                            break;
+                        }
+                        if let Some(value) = header.strip_prefix("content-length:") {
+                            length = value.trim().parse().unwrap_or(0);
+                        }
+                    }
+                    reader.by_ref().take(length).read_to_end(&mut Vec::new())?;
+                    let path = request.split(' ').nth(1).unwrap_or_default().to_string();
+                    kept.lock().unwrap().push(path);
+                    (&stream).write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")?;
+                }
+            });
+        }
+    });
+    (format!("http://{}", addr), posted)
+}
+
+fn traceparent(message: &Message<Value>) -> Vec<String> {
+    let traceparent = message.body.payload["traceparent"]
+        .as_str()
+        .expect("the reply carries a traceparent");
+    traceparent.split('-').map(str::to_string).collect()
+}
+
+#[test]
+fn a_node_carries_on_the_trace_a_message_came_with_and_ships_its_spans() {
+    let (endpoint, posted) = collector();
+    let mut command = Command::new(env!("CARGO_BIN_EXE_echo"));
+    command.env("RUSTENGAN_OTLP_ENDPOINT", &endpoint);
+    let mut node = Process::command(command).expect("node starts");
+    node.init("n0", &["n0"]).expect("node inits");
+
+    let reply: Message<Value> = node
+        .request(
+            "c1",
+            json!({ "type": "echo", "echo": "traced", "traceparent": PARENT }),
+        )
+        .expect("echo answers");
+    let parent: Vec<&str> = PARENT.split('-').collect();
+    let child = traceparent(&reply);
+    // the same trace, under a span of the node's own
+    assert_eq!(child[1], parent[1]);
+    assert_ne!(child[2], parent[2]);
+
+    // and a message with no trace starts one
+    let reply: Message<Value> = node
+        .request("c1", json!({ "type": "echo", "echo": "untraced" }))
+        .expect("echo answers");
+    assert_ne!(traceparent(&reply)[1], parent[1]);
+
+    // the spans go before the node does
+    assert!(node.close().expect("node exits").success());
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !posted
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|path| path == "/v1/traces")
+    {
+        assert!(Instant::now() < deadline, "{:?}", posted.lock().unwrap());
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
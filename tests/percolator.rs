@@ -0,0 +1,101 @@
+#[allow(dead_code)]
+#[path = "../src/bin/percolator.rs"]
+mod percolator;
+
+use std::time::Duration;
+
+use percolator::{Conflict, Mutation, Payload, Row, KEPT_VERSIONS};
+use rustengan::kv::service::{Conduct, Service};
+use rustengan::sim::Sim;
+use rustengan::txn::{Op, OpKind};
+
+// prewrites `value` on `row` for the transaction that starts at `start_ts`, and commits it right
+// after
+fn commit(row: &Row, start_ts: u64, value: usize, pending: bool) -> Row {
+    let prewrite = Mutation::Prewrite {
+        start_ts,
+        primary: 0,
+        value,
+    };
+    let row = prewrite.apply(row).expect("prewrites").expect("locks");
+    let commit = Mutation::Commit {
+        start_ts,
+        commit_ts: start_ts + 1,
+        pending,
+    };
+    commit.apply(&row).expect("commits").expect("unlocks")
+}
+
+#[test]
+fn a_row_keeps_only_its_newest_versions() {
+    let mut row = Row::default();
+    for value in 0..100 {
+        row = commit(&row, value as u64 * 10, value, false);
+    }
+    assert_eq!(row.write.len(), KEPT_VERSIONS);
+    assert_eq!(row.data.len(), KEPT_VERSIONS);
+    let oldest = 100 - KEPT_VERSIONS;
+    assert_eq!(row.horizon, oldest as u64 * 10 + 1);
+    assert_eq!(row.visible(row.horizon), Some(oldest));
+    assert_eq!(row.visible(u64::MAX), Some(99));
+
+    // and a transaction from before the horizon can't write to it either
+    let prewrite = Mutation::Prewrite {
+        start_ts: row.horizon - 1,
+        primary: 0,
+        value: 100,
+    };
+    assert!(matches!(prewrite.apply(&row), Err(Conflict::NewerWrite)));
+}
+
+#[test]
+fn a_pending_primary_write_outlives_collection_until_its_settled() {
+    let mut row = commit(&Row::default(), 0, 0, true);
+    for value in 1..100 {
+        row = commit(&row, value as u64 * 10, value, false);
+    }
+    // whoever resolves a secondary's leftover lock can still tell the transaction committed
+    assert_eq!(row.committed_at(0), Some(1));
+    assert_eq!(row.write.len(), KEPT_VERSIONS + 1);
+
+    let settle = Mutation::Settle { commit_ts: 1 };
+    let row = settle.apply(&row).expect("settles").expect("changes");
+    assert_eq!(row.committed_at(0), None);
+    assert_eq!(row.write.len(), KEPT_VERSIONS);
+}
+
+#[test]
+fn rows_written_more_often_than_they_keep_versions_still_read_back_whole() {
+    let mut sim = Sim::new(45, &["n0", "n1", "n2"]);
+    sim.service(Service::lin_kv(Conduct::default()));
+    sim.start::<(), percolator::PercolatorNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(500), || {
+        percolator::InjectedPayload::Tick
+    });
+    let rounds = 3 * KEPT_VERSIONS;
+    for value in 0..rounds {
+        let txn = vec![Op(OpKind::W, 1, Some(value)), Op(OpKind::W, 2, Some(value))];
+        let dst = ["n0", "n1", "n2"][value % 3];
+        sim.send("c1", dst, Payload::Txn { txn })
+            .expect("request sends");
+        // long enough for each to finish, secondaries and all, before the next starts
+        sim.run_for(Duration::from_millis(300)).expect("nodes step");
+    }
+    let committed = sim.take_replies("c1").expect("replies parse");
+    assert!(committed
+        .iter()
+        .all(|reply| matches!(reply.body.payload, Payload::TxnOk { .. })));
+    assert_eq!(committed.len(), rounds);
+
+    let txn = vec![Op(OpKind::R, 1, None), Op(OpKind::R, 2, None)];
+    sim.send("c2", "n1", Payload::Txn { txn })
+        .expect("read sends");
+    sim.run_for(Duration::from_millis(100)).expect("nodes step");
+    let read = sim.take_replies("c2").expect("replies parse").pop();
+    let Some(Payload::TxnOk { txn }) = read.map(|reply| reply.body.payload) else {
+        panic!("the read didn't commit");
+    };
+    let last = Some(rounds - 1);
+    assert_eq!(txn, vec![Op(OpKind::R, 1, last), Op(OpKind::R, 2, last)]);
+}
@@ -0,0 +1,166 @@
+#[allow(dead_code)]
+#[path = "../src/bin/primary_backup.rs"]
+mod primary_backup;
+
+use std::time::Duration;
+
+use primary_backup::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::history::linearizable::check;
+use rustengan::history::{Op, Type};
+use rustengan::kv::service::{Conduct, Service};
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, primary_backup::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+// n0 is the first member, so it takes the lease once lin-kv tells it there isn't one yet
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.service(Service::lin_kv(Conduct::default()));
+    sim.start::<(), primary_backup::PrimaryBackupNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        primary_backup::InjectedPayload::Tick
+    });
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    sim
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+fn read(sim: &mut Cluster, via: &str) -> Option<u64> {
+    let read = Payload::Read {
+        key: 1,
+        max_staleness_ms: None,
+    };
+    match ask(sim, "reader", via, read) {
+        Some(Payload::ReadOk { value }) => value.as_u64(),
+        _ => None,
+    }
+}
+
+fn written(sim: &mut Cluster, via: &str, value: usize) -> bool {
+    let write = Payload::Write { key: 1, value };
+    matches!(ask(sim, "writer", via, write), Some(Payload::WriteOk))
+}
+
+#[test]
+fn a_backup_takes_over_from_a_crashed_primary_with_every_acknowledged_write() {
+    let mut sim = cluster(1);
+    assert!(written(&mut sim, "n1", 1));
+    assert!(written(&mut sim, "n2", 2));
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+
+    assert_eq!(read(&mut sim, "n1"), Some(2));
+    assert!(written(&mut sim, "n2", 3));
+    assert_eq!(read(&mut sim, "n1"), Some(3));
+}
+
+#[test]
+fn a_primary_cut_off_from_its_backups_goes_on_without_them_and_they_catch_up() {
+    let mut sim = cluster(2);
+    assert!(written(&mut sim, "n0", 1));
+    let alone = vec![
+        vec!["n0".to_string()],
+        vec!["n1".to_string(), "n2".to_string()],
+    ];
+    sim.disrupt(Disruption::Partition(Split::Components(alone)))
+        .expect("partitions");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+
+    // n0 still holds the lease, so the backups can't take over, and it drops them from the
+    // in-sync set rather than wait for them forever
+    assert!(written(&mut sim, "n0", 2));
+    assert_ne!(read(&mut sim, "n1"), Some(1));
+
+    sim.disrupt(Disruption::Heal).expect("heals");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    assert_eq!(read(&mut sim, "n1"), Some(2));
+
+    // and once they're back in sync, a crash of the primary loses nothing
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    assert_eq!(read(&mut sim, "n2"), Some(2));
+}
+
+// clients reading, writing and cas-ing a couple of keys through any node, while the nodes are
+// partitioned from each other and one of them crashes. A client waits up to a second for an answer
+// before it gives up and asks for something else.
+fn primary_backup_history(seed: u64) -> Vec<Op> {
+    let mut sim = cluster(seed);
+    sim.nemesis(
+        Nemesis::partitions(
+            Split::Halves,
+            Duration::from_millis(2500),
+            Duration::from_millis(2000),
+        )
+        .until(Duration::from_secs(10)),
+    );
+    sim.nemesis(
+        Nemesis::new()
+            .at(Duration::from_secs(6), Disruption::Kill(Target::Random))
+            .until(Duration::from_secs(10)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut asked = [None; 3];
+    while sim.now() < Duration::from_secs(15) {
+        for (client, asked) in asked.iter_mut().enumerate() {
+            let client = format!("c{}", client);
+            if !sim.take_replies(&client).expect("replies parse").is_empty() {
+                *asked = None;
+            }
+            if asked.is_some_and(|at| sim.now() - at < Duration::from_secs(1)) {
+                continue;
+            }
+            *asked = Some(sim.now());
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..3) {
+                0 => Payload::Read {
+                    key,
+                    max_staleness_ms: None,
+                },
+                1 => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+                _ => Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..5),
+                    to: rng.gen_range(0..5),
+                },
+            };
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send(&client, dst, request).expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.history()
+}
+
+#[test]
+fn primary_backup_stays_linearizable_through_partitions_and_a_crash() {
+    for seed in [4, 5, 6] {
+        let history = primary_backup_history(seed);
+        assert_eq!(check(&history), Ok(()), "seed {}", seed);
+        assert!(
+            history.iter().any(|op| op.kind == Type::Ok),
+            "seed {}: nothing was answered",
+            seed
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use rustengan::harness::Process;
+use rustengan::{Body, Message};
+use serde_json::{json, Value};
+
+fn started(binary: &str, node_ids: &[&str]) -> Process {
+    let mut node = Process::spawn(binary).expect("node starts");
+    node.init(node_ids[0], node_ids).expect("node inits");
+    node
+}
+
+fn payload(message: &Message<Value>) -> &Value {
+    &message.body.payload
+}
+
+#[test]
+fn echo_answers_each_request_before_it_gets_the_next() {
+    let mut node = started(env!("CARGO_BIN_EXE_echo"), &["n0"]);
+    // a reply left in a buffer holds up everything after it, so one request at a time
+    let mut ids = Vec::new();
+    for i in 0..20 {
+        let echo = format!("hello {}", i);
+        let reply: Message<Value> = node
+            .request("c1", json!({ "type": "echo", "echo": echo }))
+            .expect("echo answers");
+        assert_eq!(reply.src, "n0");
+        assert_eq!(payload(&reply), &json!({ "type": "echo_ok", "echo": echo }));
+        ids.push(reply.body.id.expect("replies have msg ids"));
+    }
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ids);
+}
+
+#[test]
+fn echo_exits_once_its_input_closes() {
+    let mut node = started(env!("CARGO_BIN_EXE_echo"), &["n0"]);
+    node.request::<_, Value>("c1", json!({ "type": "echo", "echo": "bye" }))
+        .expect("echo answers");
+    let status = node.close().expect("echo exits");
+    assert!(status.success(), "{}", status);
+}
+
+#[test]
+fn a_line_that_isnt_a_message_doesnt_stop_the_node() {
+    let mut node = started(env!("CARGO_BIN_EXE_echo"), &["n0"]);
+    node.write_line("{\"src\":\"c1\",").expect("node reads it");
+    node.write_line("").expect("node reads it");
+    let reply: Message<Value> = node
+        .request("c1", json!({ "type": "echo", "echo": "still here" }))
+        .expect("echo answers");
+    assert_eq!(payload(&reply)["echo"], "still here");
+}
+
+#[test]
+fn unique_ids_hands_out_distinct_ids_and_exits_once_its_input_closes() {
+    let mut node = started(env!("CARGO_BIN_EXE_unique_ids"), &["n0", "n1"]);
+    // all at once, so the node has a backlog to get through
+    let ids: Vec<usize> = (0..100)
+        .map(|_| node.send("c1", json!({ "type": "generate" })).unwrap())
+        .collect();
+    let mut seen = HashSet::new();
+    for id in ids {
+        let reply: Message<Value> = node.reply_to("c1", id).expect("node answers");
+        let generated = payload(&reply)["id"].to_string();
+        assert!(seen.insert(generated.clone()), "{} twice", generated);
+    }
+    assert!(node.close().expect("node exits").success());
+}
+
+#[test]
+fn broadcast_gossips_to_its_neighbours_over_stdout() {
+    let mut node = started(env!("CARGO_BIN_EXE_broadcast"), &["n0", "n1"]);
+    node.request::<_, Value>(
+        "c1",
+        json!({ "type": "topology", "topology": { "n0": ["n1"], "n1": ["n0"] } }),
+    )
+    .expect("node takes the topology");
+    node.request::<_, Value>("c1", json!({ "type": "broadcast", "message": 7 }))
+        .expect("node takes the broadcast");
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let gossip = loop {
+        assert!(Instant::now() < deadline, "n0 never gossiped 7 to n1");
+        let message = node.recv().expect("node runs").expect("node gossips");
+        if message.dst == "n1" && payload(&message)["seen"] == json!([7]) {
+            break message;
+        }
+    };
+    assert_eq!(gossip.src, "n0");
+
+    // and takes gossip back from its neighbour
+    node.deliver(&Message {
+        src: "n1".to_string(),
+        dst: "n0".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to: None,
+            correlation_id: None,
+            payload: json!({ "type": "gossip", "seen": [8] }),
+        },
+    })
+    .expect("node reads it");
+    let read: Message<Value> = node
+        .request("c1", json!({ "type": "read" }))
+        .expect("node answers the read");
+    let mut messages: Vec<u64> =
+        serde_json::from_value(payload(&read)["messages"].clone()).expect("read_ok has messages");
+    messages.sort();
+    assert_eq!(messages, vec![7, 8]);
+}
+
+#[test]
+fn a_node_that_never_answers_is_an_error_saying_so() {
+    let mut node = started(env!("CARGO_BIN_EXE_echo"), &["n0"]);
+    node.within(Duration::from_millis(200));
+    // echo doesn't answer echo_ok, since it's a reply itself
+    let id = node
+        .send("c1", json!({ "type": "echo_ok", "echo": "" }))
+        .expect("node reads it");
+    let error = node.reply_to::<Value>("c1", id).unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("didn't answer c1's msg"),
+        "{:#}",
+        error
+    );
+}
+
+#[test]
+fn a_node_asked_for_stats_writes_them_to_stderr_while_it_runs() {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_echo"));
+    command.env("RUSTENGAN_STATS_MS", "50");
+    let mut node = rustengan::harness::Process::command(command).expect("node starts");
+    node.init("n0", &["n0"]).expect("node inits");
+    for i in 0..3 {
+        node.request::<_, Value>("c1", json!({ "type": "echo", "echo": i.to_string() }))
+            .expect("echo answers");
+    }
+
+    // each line counts what was sent since the one before, so the three answers may be spread
+    // over a few of them
+    let answered = |stderr: &[String]| -> u64 {
+        stderr
+            .iter()
+            .filter_map(|line| {
+                line.split_once("echo_ok ")?
+                    .1
+                    .split(')')
+                    .next()?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .sum()
+    };
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while answered(&node.stderr()) < 3 {
+        assert!(Instant::now() < deadline, "{:?}", node.stderr());
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(answered(&node.stderr()), 3);
+    assert!(node
+        .stderr()
+        .iter()
+        .any(|line| line.contains("stats over 50ms: ")));
+    assert!(node.close().expect("node exits").success());
+}
+
+#[test]
+fn a_correlation_id_comes_back_on_the_reply_and_none_is_made_up() {
+    let mut node = started(env!("CARGO_BIN_EXE_echo"), &["n0"]);
+    node.deliver(&Message {
+        src: "c1".to_string(),
+        dst: "n0".to_string(),
+        body: Body {
+            id: Some(100),
+            in_reply_to: None,
+            correlation_id: Some("op-7".to_string()),
+            payload: json!({ "type": "echo", "echo": "hi" }),
+        },
+    })
+    .expect("node reads it");
+    let reply = node.reply_to::<Value>("c1", 100).expect("echo answers");
+    assert_eq!(reply.body.correlation_id.as_deref(), Some("op-7"));
+    // unless it's asked to, a node doesn't add anything to what Maelstrom sees
+    let reply: Message<Value> = node
+        .request("c1", json!({ "type": "echo", "echo": "hi" }))
+        .expect("echo answers");
+    assert_eq!(reply.body.correlation_id, None);
+}
+
+#[test]
+fn a_forwarded_request_keeps_its_correlation_id_all_the_way_back_to_the_client() {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_sharded_kv"));
+    command.env("RUSTENGAN_CORRELATE", "true");
+    let mut node = Process::command(command).expect("node starts");
+    node.init("n0", &["n0", "n1"]).expect("node inits");
+
+    // whichever keys n1 owns, n0 forwards the writes to
+    let mut forwarded = 0;
+    for key in 0..16 {
+        let id = node
+            .send("c1", json!({ "type": "write", "key": key, "value": 1 }))
+            .expect("node reads it");
+        let correlation_id = format!("c1:{}", id);
+        // what it sends the owner, or its answer, if it owns the key itself
+        let message = loop {
+            let message = node.recv().expect("node writes").expect("node answers");
+            if message.dst == "c1" || payload(&message)["type"] == "forward" {
+                break message;
+            }
+        };
+        let reply = match payload(&message)["type"] == "forward" {
+            false => message,
+            true => {
+                forwarded += 1;
+                assert_eq!(message.dst, "n1");
+                assert_eq!(message.body.correlation_id, Some(correlation_id.clone()));
+                // n1 would answer under the same id, since it came with the forward
+                node.deliver(&Message {
+                    src: "n1".to_string(),
+                    dst: "n0".to_string(),
+                    body: Body {
+                        id: None,
+                        in_reply_to: None,
+                        correlation_id: Some(correlation_id.clone()),
+                        payload: json!({
+                            "type": "done",
+                            "req_id": payload(&message)["req_id"],
+                            "reply": { "type": "write_ok" },
+                        }),
+                    },
+                })
+                .expect("node reads it");
+                node.reply_to::<Value>("c1", id).expect("node answers")
+            }
+        };
+        assert_eq!(reply.body.in_reply_to, Some(id));
+        assert_eq!(payload(&reply)["type"], "write_ok");
+        assert_eq!(reply.body.correlation_id, Some(correlation_id));
+    }
+    assert!(forwarded > 0, "n1 owns none of the keys");
+}
+
+#[test]
+fn a_node_without_a_data_dir_logs_to_a_scratch_one_of_its_own_and_removes_it_on_exit() {
+    let tmp = std::env::temp_dir().join(format!("rustengan-scratch-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&tmp);
+    std::fs::create_dir_all(&tmp).expect("temporary directory");
+    let scratch = || -> Vec<String> {
+        std::fs::read_dir(&tmp)
+            .expect("temporary directory")
+            .map(|entry| {
+                entry
+                    .expect("entry")
+                    .file_name()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect()
+    };
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_pubsub"));
+    command.env_remove("RUSTENGAN_DATA_DIR").env("TMPDIR", &tmp);
+    let mut node = Process::command(command).expect("node starts");
+    node.init("n0", &["n0"]).expect("node inits");
+    // it opens its log once it's answered the init
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while scratch().is_empty() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let running = scratch();
+    assert!(
+        matches!(running.as_slice(), [dir] if dir.starts_with(&format!("rustengan-{}-", node.id()))),
+        "{:?}",
+        running
+    );
+    assert!(node.close().expect("node exits").success());
+    assert_eq!(scratch(), Vec::<String>::new());
+    let _ = std::fs::remove_dir_all(&tmp);
+}
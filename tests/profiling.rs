@@ -0,0 +1,55 @@
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use rustengan::harness::Process;
+use rustengan::Message;
+use serde_json::{json, Value};
+
+#[test]
+fn sigusr2_has_a_node_write_a_flamegraph_of_what_it_was_doing() {
+    let dir = std::env::temp_dir().join(format!("rustengan-profiling-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut command = Command::new(env!("CARGO_BIN_EXE_echo"));
+    command
+        .env("RUSTENGAN_DATA_DIR", &dir)
+        .env("RUSTENGAN_PROFILE_SECS", "1");
+    let mut node = Process::command(command).expect("node starts");
+    node.init("n0", &["n0"]).expect("node inits");
+
+    let status = Command::new("kill")
+        .args(["-USR2", &node.id().to_string()])
+        .status()
+        .expect("kill runs");
+    assert!(status.success());
+    // something for the profile to see
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let profile = loop {
+        assert!(Instant::now() < deadline, "{:?}", node.stderr());
+        let reply: Message<Value> = node
+            .request("c1", json!({ "type": "echo", "echo": "busy" }))
+            .expect("echo answers");
+        assert_eq!(reply.body.payload["echo"], "busy");
+        let written = std::fs::read_dir(dir.join("profiles"))
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .find(|path| path.extension().is_some_and(|ext| ext == "svg"));
+        if let Some(written) = written {
+            if node
+                .stderr()
+                .iter()
+                .any(|line| line.contains("profile written"))
+            {
+                break written;
+            }
+        }
+    };
+    let name = profile.file_name().unwrap().to_string_lossy().to_string();
+    assert!(name.starts_with("n0-"), "{}", name);
+    let svg = std::fs::read_to_string(&profile).expect("profile is readable");
+    assert!(svg.contains("<svg"), "{}", svg);
+
+    assert!(node.close().expect("node exits").success());
+    let _ = std::fs::remove_dir_all(&dir);
+}
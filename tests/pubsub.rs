@@ -0,0 +1,286 @@
+#[allow(dead_code)]
+#[path = "../src/bin/pubsub.rs"]
+mod pubsub;
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use pubsub::Payload;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::shard;
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+
+type Cluster = Sim<Payload, pubsub::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const TOPICS: [&str; 3] = ["t0", "t1", "t2"];
+// each subscriber, and the node it's attached to
+const SUBSCRIBERS: [(&str, &str); 2] = [("s1", "n1"), ("s2", "n2")];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), pubsub::PubSubNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(300), || pubsub::InjectedPayload::Pull);
+    sim
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+fn owner(topic: &str) -> String {
+    let placement = shard::from_env(NODES, pubsub::VNODES).expect("placement");
+    placement
+        .owner(shard::hash(&topic))
+        .expect("every node is a member")
+        .to_string()
+}
+
+fn published(sim: &mut Cluster, via: &str, topic: &str, message: u64) -> bool {
+    let publish = Payload::Publish {
+        topic: topic.to_string(),
+        message: message.into(),
+    };
+    matches!(
+        ask(sim, "publisher", via, publish),
+        Some(Payload::PublishOk { .. })
+    )
+}
+
+#[test]
+fn a_restarted_node_hands_its_subscribers_what_was_published_while_it_was_down() {
+    let mut sim = cluster(1);
+    let topic = TOPICS
+        .into_iter()
+        .find(|t| owner(t) != "n1")
+        .expect("n1 doesn't own them all");
+    let owner = owner(topic);
+    let subscribe = Payload::Subscribe {
+        subscriber: "s1".to_string(),
+        topic: topic.to_string(),
+    };
+    assert!(matches!(
+        ask(&mut sim, "s1", "n1", subscribe),
+        Some(Payload::SubscribeOk)
+    ));
+    assert!(published(&mut sim, &owner, topic, 1));
+
+    sim.disrupt(Disruption::Kill(Target::Node("n1".to_string())))
+        .expect("kills");
+    assert!(published(&mut sim, &owner, topic, 2));
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    // pushed to n1 straight away, before it has pulled what it missed
+    assert!(published(&mut sim, &owner, topic, 3));
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+
+    let poll = Payload::Poll {
+        subscriber: "s1".to_string(),
+    };
+    let Some(Payload::PollOk { messages }) = ask(&mut sim, "s1", "n1", poll) else {
+        panic!("the poll wasn't answered");
+    };
+    let got: Vec<_> = messages[topic]
+        .iter()
+        .map(|(offset, m)| (*offset, m.as_u64()))
+        .collect();
+    assert_eq!(got, vec![(0, Some(1)), (1, Some(2)), (2, Some(3))]);
+}
+
+// what everyone saw of the topics
+#[derive(Default)]
+struct Seen {
+    // by topic and offset, every publication that was answered
+    published: HashMap<String, BTreeMap<u64, u64>>,
+    // by subscriber, topic and offset, every message a poll handed out
+    delivered: HashMap<String, HashMap<String, BTreeMap<u64, u64>>>,
+}
+
+// two subscribers following every topic through their own node, and a few publishers publishing
+// numbered messages through any node for ten seconds, while the nodes lose, repeat and reorder what
+// they send each other, are partitioned from each other, and one of them crashes and comes back
+// from its log. The subscribers poll throughout and ack what they got, and go on polling for a while
+// once the faults are over. Each client waits up to a second for an answer before it gives up.
+fn subscribed(seed: u64) -> Seen {
+    let mut sim = cluster(seed);
+    for (subscriber, node) in SUBSCRIBERS {
+        for topic in TOPICS {
+            let subscribe = Payload::Subscribe {
+                subscriber: subscriber.to_string(),
+                topic: topic.to_string(),
+            };
+            assert!(matches!(
+                ask(&mut sim, subscriber, node, subscribe),
+                Some(Payload::SubscribeOk)
+            ));
+        }
+    }
+
+    let start = sim.now();
+    sim.faults(Faults {
+        drop: 0.1,
+        duplicate: 0.1,
+        reorder: 0.1,
+        ..Faults::default()
+    });
+    sim.nemesis(
+        Nemesis::partitions(
+            Split::Halves,
+            Duration::from_millis(2500),
+            Duration::from_millis(2000),
+        )
+        .until(Duration::from_secs(10)),
+    );
+    sim.nemesis(
+        Nemesis::new()
+            .at(Duration::from_secs(4), Disruption::Kill(Target::Random))
+            .at(Duration::from_secs(6), Disruption::Restart)
+            .until(Duration::from_secs(10)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut seen = Seen::default();
+    let mut next = 0;
+    // by client, what it asked for, and when
+    let mut asked: HashMap<&str, (usize, Payload, Duration)> = HashMap::new();
+    while sim.now() < start + Duration::from_secs(20) {
+        if sim.now() >= start + Duration::from_secs(10) {
+            sim.faults(Faults::default());
+        }
+        for client in ["p1", "p2", "p3", "p4", "s1", "s2"] {
+            let mut follow_up = None;
+            for reply in sim.take_replies(client).expect("replies parse") {
+                let Some((msg_id, request, _)) = asked.get(client) else {
+                    continue;
+                };
+                if reply.body.in_reply_to != Some(*msg_id) {
+                    continue;
+                }
+                match (request, reply.body.payload) {
+                    (Payload::Publish { topic, message }, Payload::PublishOk { offset }) => {
+                        let message = message.as_u64().expect("messages are numbers");
+                        seen.published
+                            .entry(topic.clone())
+                            .or_default()
+                            .insert(offset, message);
+                    }
+                    (Payload::Poll { subscriber }, Payload::PollOk { messages }) => {
+                        let mut offsets = BTreeMap::new();
+                        for (topic, messages) in messages {
+                            let delivered = seen
+                                .delivered
+                                .entry(subscriber.clone())
+                                .or_default()
+                                .entry(topic.clone())
+                                .or_default();
+                            let mut previous = None;
+                            for (offset, message) in messages {
+                                assert!(
+                                    previous.is_none_or(|p| offset == p + 1),
+                                    "seed {}: {} got {} at {} after {:?}",
+                                    seed,
+                                    subscriber,
+                                    topic,
+                                    offset,
+                                    previous
+                                );
+                                previous = Some(offset);
+                                let message = message.as_u64().expect("messages are numbers");
+                                let before = delivered.insert(offset, message);
+                                assert!(
+                                    before.is_none_or(|b| b == message),
+                                    "seed {}: {} at {} was {:?} and then {}",
+                                    seed,
+                                    topic,
+                                    offset,
+                                    before,
+                                    message
+                                );
+                            }
+                            if let Some(last) = previous {
+                                offsets.insert(topic, last);
+                            }
+                        }
+                        if !offsets.is_empty() && rng.gen_bool(0.8) {
+                            follow_up = Some(Payload::Ack {
+                                subscriber: subscriber.clone(),
+                                offsets,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+                asked.remove(client);
+            }
+            if asked
+                .get(client)
+                .is_some_and(|(_, _, at)| sim.now() - *at < Duration::from_secs(1))
+            {
+                continue;
+            }
+            let (request, dst) = match SUBSCRIBERS.iter().find(|(s, _)| *s == client) {
+                Some((subscriber, node)) => {
+                    let poll = Payload::Poll {
+                        subscriber: subscriber.to_string(),
+                    };
+                    (follow_up.unwrap_or(poll), *node)
+                }
+                None => {
+                    if sim.now() >= start + Duration::from_secs(10) {
+                        continue;
+                    }
+                    next += 1;
+                    let publish = Payload::Publish {
+                        topic: TOPICS[rng.gen_range(0..TOPICS.len())].to_string(),
+                        message: next.into(),
+                    };
+                    (publish, NODES[rng.gen_range(0..NODES.len())])
+                }
+            };
+            let msg_id = sim
+                .send(client, dst, request.clone())
+                .expect("request sends");
+            asked.insert(client, (msg_id, request, sim.now()));
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    seen
+}
+
+#[test]
+fn every_subscriber_gets_every_acknowledged_publication_in_order_through_faults_and_a_crash() {
+    for seed in [1, 2, 3] {
+        let seen = subscribed(seed);
+        assert!(
+            !seen.published.is_empty(),
+            "seed {}: nothing was published",
+            seed
+        );
+        for (subscriber, _) in SUBSCRIBERS {
+            let delivered = seen.delivered.get(subscriber);
+            for (topic, published) in &seen.published {
+                let got = delivered.and_then(|d| d.get(topic));
+                for (offset, message) in published {
+                    assert_eq!(
+                        got.and_then(|g| g.get(offset)),
+                        Some(message),
+                        "seed {}: {} didn't get {} at {} of {}",
+                        seed,
+                        subscriber,
+                        message,
+                        offset,
+                        topic
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use rustengan::runtime::{self, Recv, Threads};
+use rustengan::{Body, Event, Message};
+
+// shuttle's threads and channels, so each test runs the runtime through many orderings of its
+// threads rather than whichever one the OS happens to pick
+struct Shuttle;
+
+impl Threads for Shuttle {
+    type Sender<T: Send + 'static> = shuttle::sync::mpsc::Sender<T>;
+    type Receiver<T: Send + 'static> = shuttle::sync::mpsc::Receiver<T>;
+    type JoinHandle<T: Send + 'static> = shuttle::thread::JoinHandle<T>;
+
+    fn send<T: Send + 'static>(tx: &Self::Sender<T>, value: T) -> bool {
+        tx.send(value).is_ok()
+    }
+
+    fn recv<T: Send + 'static>(rx: &Self::Receiver<T>, _: Option<Duration>) -> Recv<T> {
+        // shuttle doesn't model time, so nothing times out
+        rx.recv().map_or(Recv::Closed, Recv::Got)
+    }
+
+    fn spawn<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Self::JoinHandle<T> {
+        shuttle::thread::spawn(f)
+    }
+
+    fn join<T: Send + 'static>(handle: Self::JoinHandle<T>) -> Option<T> {
+        handle.join().ok()
+    }
+
+    fn sleep(_: Duration) {
+        // shuttle doesn't model time either, but a sleeping thread lets every other go first,
+        // as a timer that ticks far less often than the node steps would
+        shuttle::thread::yield_now()
+    }
+}
+
+const ITERATIONS: usize = 1000;
+
+type Input = Event<u64, &'static str>;
+
+fn message(n: u64) -> Input {
+    Event::Message(Message {
+        src: "c1".to_string(),
+        dst: "n1".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to: None,
+            correlation_id: None,
+            payload: n,
+        },
+    })
+}
+
+fn lines(lines: &[&str]) -> impl Iterator<Item = anyhow::Result<String>> + Send + 'static {
+    lines
+        .iter()
+        .map(|line| anyhow::Ok(line.to_string()))
+        .collect::<Vec<_>>()
+        .into_iter()
+}
+
+fn parse(line: String) -> Option<Input> {
+    line.parse().ok().map(message)
+}
+
+// what the node was given, other than ticks: messages by their payload, and EOF as "eof"
+fn handled(events: &[Input]) -> Vec<String> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Message(message) => Some(message.body.payload.to_string()),
+            Event::Injected(_) => None,
+            Event::EOF => Some("eof".to_string()),
+        })
+        .collect()
+}
+
+#[test]
+fn a_node_with_a_timer_stops_once_its_input_runs_out() {
+    let check = || {
+        let (tx, rx) = shuttle::sync::mpsc::channel();
+        let timer = runtime::timer::<Shuttle, _>(Duration::from_millis(1), tx.clone(), || {
+            Event::Injected("tick")
+        });
+        let mut events = Vec::new();
+        runtime::run::<Shuttle, _, _>(lines(&["1", "garbage", "2", "3"]), parse, tx, rx, None, {
+            |event| {
+                events.extend(event);
+                Ok(())
+            }
+        })
+        .expect("the node runs");
+        // every message, in order, and EOF after them all
+        assert_eq!(handled(&events), ["1", "2", "3", "eof"]);
+        assert!(matches!(events.last(), Some(Event::EOF)));
+        // and the timer finds nobody to tick for
+        timer.join().expect("the timer stops");
+    };
+    shuttle::check_random(check, ITERATIONS);
+    shuttle::check_pct(check, ITERATIONS, 3);
+}
+
+#[test]
+fn a_failed_step_stops_the_node_without_waiting_on_its_input() {
+    let check = || {
+        let (tx, rx) = shuttle::sync::mpsc::channel();
+        let timer = runtime::timer::<Shuttle, _>(Duration::from_millis(1), tx.clone(), || {
+            Event::Injected("tick")
+        });
+        // input that never runs out, like stdin nobody closes: a few lines, then a read that
+        // waits on a channel nothing's ever sent down
+        let (_never, stuck) = shuttle::sync::mpsc::channel::<String>();
+        let lines = (0..5)
+            .map(|n: u64| anyhow::Ok(n.to_string()))
+            .chain(std::iter::from_fn(move || {
+                stuck.recv().ok().map(anyhow::Ok)
+            }));
+        let mut seen = 0;
+        let result = runtime::run::<Shuttle, _, _>(lines, parse, tx, rx, None, |event| {
+            if let Some(Event::Message(_)) = event {
+                seen += 1;
+                anyhow::ensure!(seen < 3, "step failed");
+            }
+            Ok(())
+        });
+        let e = result.expect_err("the step's failure stops the node");
+        assert_eq!(e.to_string(), "step failed");
+        timer.join().expect("the timer stops");
+    };
+    shuttle::check_random(check, ITERATIONS);
+    shuttle::check_pct(check, ITERATIONS, 3);
+}
+
+#[test]
+fn input_that_cant_be_read_still_ends_in_eof() {
+    let check = || {
+        let (tx, rx) = shuttle::sync::mpsc::channel();
+        let timer = runtime::timer::<Shuttle, _>(Duration::from_millis(1), tx.clone(), || {
+            Event::Injected("tick")
+        });
+        let lines = vec![
+            anyhow::Ok("1".to_string()),
+            Err(anyhow::anyhow!("stdin went away")),
+            anyhow::Ok("2".to_string()),
+        ];
+        let mut events = Vec::new();
+        let result = runtime::run::<Shuttle, _, _>(lines.into_iter(), parse, tx, rx, None, {
+            |event| {
+                events.extend(event);
+                Ok(())
+            }
+        });
+        assert_eq!(handled(&events), ["1", "eof"]);
+        let e = result.expect_err("the read's failure comes out of the run");
+        assert!(format!("{:#}", e).contains("stdin went away"), "{:#}", e);
+        timer.join().expect("the timer stops");
+    };
+    shuttle::check_random(check, ITERATIONS);
+    shuttle::check_pct(check, ITERATIONS, 3);
+}
+
+// a panic anywhere fails a shuttle test, so this one's on real threads
+#[test]
+fn an_input_thread_that_panics_still_ends_in_eof() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    let timer = runtime::timer::<runtime::Std, _>(Duration::from_millis(1), tx.clone(), || {
+        Event::Injected("tick")
+    });
+    let parse = |line: String| match line.as_str() {
+        "boom" => panic!("the input thread panics"),
+        line => line.parse().ok().map(message),
+    };
+    let mut events = Vec::new();
+    let poll = Some(Duration::from_millis(1));
+    let result =
+        runtime::run::<runtime::Std, _, _>(lines(&["1", "boom", "2"]), parse, tx, rx, poll, {
+            |event| {
+                events.extend(event);
+                Ok(())
+            }
+        });
+    assert_eq!(handled(&events), ["1", "eof"]);
+    let e = result.expect_err("the panic comes out of the run");
+    assert_eq!(e.to_string(), "input thread panicked");
+    timer.join().expect("the timer stops");
+}
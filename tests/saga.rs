@@ -0,0 +1,206 @@
+#[allow(dead_code)]
+#[path = "../src/bin/saga.rs"]
+mod saga;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::Sim;
+use saga::{Payload, SagaState, Step};
+
+type Cluster = Sim<Payload, saga::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const KEYS: [&str; 2] = ["a", "b"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), saga::SagaNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(100), || saga::InjectedPayload::Tick);
+    sim
+}
+
+fn step(node: &str, key: &str, delta: i64) -> Step {
+    Step {
+        node: node.to_string(),
+        key: key.to_string(),
+        delta,
+    }
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+fn counter(sim: &mut Cluster, node: &str, key: &str) -> Option<i64> {
+    let read = Payload::Read {
+        key: key.to_string(),
+    };
+    match ask(sim, "reader", node, read) {
+        Some(Payload::ReadOk { value }) => Some(value),
+        _ => None,
+    }
+}
+
+fn state(sim: &mut Cluster, saga_id: &str) -> Option<SagaState> {
+    let status = Payload::Status {
+        saga_id: saga_id.to_string(),
+    };
+    match ask(sim, "watcher", "n0", status) {
+        Some(Payload::StatusOk { state, .. }) => Some(state),
+        _ => None,
+    }
+}
+
+#[test]
+fn a_step_whose_answer_never_arrives_is_compensated_rather_than_applied_twice() {
+    let mut sim = cluster(1);
+    // n1 applies the step, and goes on being asked for it, but n0 never hears back
+    sim.link_faults("n1", "n0", Faults::cut());
+    let steps = vec![step("n1", "a", 5), step("n2", "a", 1)];
+    let Some(Payload::BeginOk { saga_id }) = ask(&mut sim, "c1", "n0", Payload::Begin { steps })
+    else {
+        panic!("the saga didn't begin");
+    };
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    sim.link_faults("n1", "n0", Faults::default());
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+
+    assert_eq!(state(&mut sim, &saga_id), Some(SagaState::Aborted));
+    assert_eq!(counter(&mut sim, "n1", "a"), Some(0));
+    assert_eq!(counter(&mut sim, "n2", "a"), Some(0));
+}
+
+#[test]
+fn a_restarted_orchestrator_finishes_the_sagas_it_had_begun() {
+    let mut sim = cluster(2);
+    let steps = vec![step("n1", "a", 2), step("n2", "b", 3)];
+    sim.send("c1", "n0", Payload::Begin { steps })
+        .expect("begin sends");
+    sim.link_faults("n0", "n2", Faults::cut());
+    sim.run_for(Duration::from_millis(500)).expect("nodes step");
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.link_faults("n0", "n2", Faults::default());
+    sim.run_for(Duration::from_millis(500)).expect("nodes step");
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+
+    let replies = sim.take_replies("c1").expect("replies parse");
+    let Some(Payload::BeginOk { saga_id }) = replies.first().map(|r| r.body.payload.clone()) else {
+        panic!("the saga didn't begin: {:?}", replies);
+    };
+    assert_eq!(state(&mut sim, &saga_id), Some(SagaState::Completed));
+    assert_eq!(counter(&mut sim, "n1", "a"), Some(2));
+    assert_eq!(counter(&mut sim, "n2", "b"), Some(3));
+}
+
+// clients beginning sagas of a few steps each through any node, some of which take a counter below
+// zero and have to be rolled back, while the nodes lose, repeat and reorder what they send each
+// other, are partitioned from each other, and one of them crashes and comes back from its log. Once the faults are over every saga has to have finished, and
+// every counter has to hold exactly the steps of the sagas that completed.
+#[test]
+fn every_saga_completes_or_leaves_nothing_behind_through_a_lossy_network_partitions_and_a_crash() {
+    for seed in [3, 4, 5] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(2500),
+                Duration::from_millis(2000),
+            )
+            .until(Duration::from_secs(10)),
+        );
+        sim.nemesis(
+            Nemesis::new()
+                .at(Duration::from_secs(4), Disruption::Kill(Target::Random))
+                .at(Duration::from_secs(6), Disruption::Restart)
+                .until(Duration::from_secs(10)),
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut begun = HashMap::new();
+        while sim.now() < Duration::from_secs(10) {
+            let client = format!("c{}", begun.len());
+            let steps: Vec<_> = (0..rng.gen_range(1..4))
+                .map(|_| {
+                    step(
+                        NODES[rng.gen_range(0..NODES.len())],
+                        KEYS[rng.gen_range(0..KEYS.len())],
+                        rng.gen_range(-3..6),
+                    )
+                })
+                .collect();
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send(
+                &client,
+                dst,
+                Payload::Begin {
+                    steps: steps.clone(),
+                },
+            )
+            .expect("begin sends");
+            begun.insert(client, steps);
+            sim.run_for(Duration::from_millis(rng.gen_range(50..200)))
+                .expect("nodes step");
+        }
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(8)).expect("nodes step");
+
+        // a begin that got to its node was answered, crash or not, since the answer goes out
+        // with the step that logs the saga
+        let mut expected: HashMap<(String, String), i64> = HashMap::new();
+        let mut completed = 0;
+        for (client, steps) in &begun {
+            let replies = sim.replies(client).expect("replies parse");
+            let Some(Payload::BeginOk { saga_id }) = replies.first().map(|r| &r.body.payload)
+            else {
+                continue;
+            };
+            let saga_id = saga_id.clone();
+            match state(&mut sim, &saga_id) {
+                Some(SagaState::Completed) => {
+                    completed += 1;
+                    for step in steps {
+                        *expected
+                            .entry((step.node.clone(), step.key.clone()))
+                            .or_default() += step.delta;
+                    }
+                }
+                Some(SagaState::Aborted) => {}
+                other => panic!("seed {}: {} is {:?} after the faults", seed, saga_id, other),
+            }
+        }
+        assert!(completed > 0, "seed {}: no saga completed", seed);
+        for node in NODES {
+            for key in KEYS {
+                let want = expected
+                    .get(&(node.to_string(), key.to_string()))
+                    .copied()
+                    .unwrap_or_default();
+                assert_eq!(
+                    counter(&mut sim, node, key),
+                    Some(want),
+                    "seed {}: {} on {}",
+                    seed,
+                    key,
+                    node
+                );
+            }
+        }
+    }
+}
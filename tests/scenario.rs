@@ -0,0 +1,100 @@
+use rustengan::sim::scenario::Scenario;
+use rustengan::sim::Sim;
+
+// the broadcast workload's node, built from the same source as its own binary
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+use broadcast::{BroadcastNode, Gossip, InjectedPayload, Payload};
+
+fn broadcast(scenario: &Scenario) -> anyhow::Result<Sim<Payload, InjectedPayload>> {
+    let mut sim = scenario.sim()?;
+    sim.start::<_, BroadcastNode>(Gossip::default())?;
+    sim.every(broadcast::GOSSIP_EVERY, || InjectedPayload::Gossip);
+    scenario.run(&mut sim)?;
+    Ok(sim)
+}
+
+#[test]
+fn every_broadcast_scenario_plays_out_as_it_says() {
+    let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scenarios");
+    let mut ran = 0;
+    for entry in std::fs::read_dir(dir).expect("scenarios are there") {
+        let path = entry.expect("scenario lists").path();
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        if !name.starts_with("broadcast_") {
+            continue;
+        }
+        let path = path.to_str().expect("path is utf-8");
+        let scenario = Scenario::load(path).expect("scenario loads");
+        if let Err(e) = broadcast(&scenario) {
+            panic!("{}: {:#}", name, e);
+        }
+        ran += 1;
+    }
+    assert!(ran > 0, "no broadcast scenarios in {}", dir);
+}
+
+#[test]
+fn a_scenario_fails_at_the_first_expectation_that_doesnt_hold() {
+    let scenario = Scenario::from_toml(
+        r#"
+        nodes = ["n0", "n1"]
+        seed = 3
+
+        [[step]]
+        at_ms = 0
+        do = "partition"
+        components = [["n0"], ["n1"]]
+
+        [[step]]
+        at_ms = 10
+        do = "send"
+        from = "c1"
+        to = "n0"
+        body = { type = "broadcast", message = 5 }
+
+        [[step]]
+        at_ms = 1000
+        do = "expect_status"
+        node = "n1"
+        status = { messages = [5] }
+        "#,
+    )
+    .expect("scenario parses");
+    let e = broadcast(&scenario)
+        .err()
+        .expect("n1 can't have heard of 5");
+    let e = format!("{:#}", e);
+    assert!(e.starts_with("step 3 at 1000ms"), "{}", e);
+    assert!(e.contains("n1's status is {\"messages\":[]}"), "{}", e);
+}
+
+#[test]
+fn a_scenario_body_has_to_be_the_nodes_payload() {
+    let scenario = Scenario::from_toml(
+        r#"
+        nodes = ["n0"]
+
+        [[step]]
+        at_ms = 0
+        do = "send"
+        from = "c1"
+        to = "n0"
+        body = { type = "enqueue", item = 5 }
+        "#,
+    )
+    .expect("scenario parses");
+    let e = broadcast(&scenario)
+        .err()
+        .expect("enqueue isn't broadcast's");
+    assert!(
+        format!("{:#}", e).contains("body isn't a payload"),
+        "{:#}",
+        e
+    );
+}
@@ -0,0 +1,305 @@
+#[allow(dead_code)]
+#[path = "../src/bin/semaphore.rs"]
+mod semaphore;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::kv::service::{Conduct, Service};
+use rustengan::kv::SEQ_KV;
+use rustengan::sim::nemesis::{Disruption, Target};
+use rustengan::sim::Sim;
+use rustengan::testing::TestNode;
+use rustengan::{error, Body, Message};
+use semaphore::Payload;
+
+type Semaphore = TestNode<semaphore::SemaphoreNode, Payload>;
+type Cluster = Sim<Payload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const CLIENTS: [&str; 4] = ["c0", "c1", "c2", "c3"];
+// the default
+const PERMITS: u64 = 10;
+
+fn semaphore_node() -> Semaphore {
+    Semaphore::start((), "n0", &["n0", "n1"]).expect("node starts")
+}
+
+fn acquire(permits: u64) -> Payload {
+    Payload::Acquire {
+        name: "s".to_string(),
+        permits,
+    }
+}
+
+// `payload` from `src`, as an answer to the node's msg `in_reply_to`
+fn reply(src: &str, in_reply_to: Option<usize>, payload: Payload) -> Message<Payload> {
+    Message {
+        src: src.to_string(),
+        dst: "n0".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to,
+            correlation_id: None,
+            payload,
+        },
+    }
+}
+
+// the one request the node has sent seq-kv since the last time, and its msg_id
+fn asked_seq_kv(node: &mut Semaphore) -> (Option<usize>, String) {
+    let sent = node.sent_json_to(SEQ_KV);
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    let kind = sent[0].body.payload["type"].as_str().expect("has a type");
+    (sent[0].body.id, kind.to_string())
+}
+
+#[test]
+fn a_client_answering_in_seq_kvs_place_is_ignored() {
+    let mut node = semaphore_node();
+    node.receive("c1", acquire(1)).expect("acquire arrives");
+    let (read, kind) = asked_seq_kv(&mut node);
+    assert_eq!(kind, "read");
+
+    // c2 guesses the msg_id of the read, and answers it as seq-kv would never
+    node.deliver(reply("c2", read, Payload::AcquireOk))
+        .expect("node carries on");
+    assert!(node.sent().is_empty());
+
+    // and the real answer still goes through
+    node.deliver(reply(SEQ_KV, read, Payload::ReadOk { value: 3 }))
+        .expect("read answer arrives");
+    let (_, kind) = asked_seq_kv(&mut node);
+    assert_eq!(kind, "cas");
+}
+
+#[test]
+fn an_unexpected_answer_from_seq_kv_fails_just_its_operation() {
+    let mut node = semaphore_node();
+    node.receive("c1", acquire(1)).expect("acquire arrives");
+    let (read, _) = asked_seq_kv(&mut node);
+    node.deliver(reply(SEQ_KV, read, Payload::ReleaseOk))
+        .expect("node carries on");
+    let failed = node.sent_to("c1");
+    assert!(
+        matches!(
+            failed.as_slice(),
+            [Message { body: Body { payload: Payload::Error { code, .. }, .. }, .. }]
+                if *code == error::CRASH
+        ),
+        "{:?}",
+        failed
+    );
+
+    // and the node still serves everyone else
+    node.receive("c2", acquire(1)).expect("acquire arrives");
+    let (_, kind) = asked_seq_kv(&mut node);
+    assert_eq!(kind, "read");
+}
+
+#[test]
+fn an_acquire_too_big_to_add_up_is_refused() {
+    let mut node = semaphore_node();
+    node.receive("c1", acquire(u64::MAX))
+        .expect("acquire arrives");
+    let (read, _) = asked_seq_kv(&mut node);
+    node.deliver(reply(SEQ_KV, read, Payload::ReadOk { value: 1 }))
+        .expect("read answer arrives");
+    assert!(
+        node.sent_json_to(SEQ_KV).is_empty(),
+        "it never gets to the cas"
+    );
+    let refused = node.sent_to("c1");
+    assert!(
+        matches!(
+            refused.as_slice(),
+            [Message { body: Body { payload: Payload::Error { code, .. }, .. }, .. }]
+                if *code == error::TEMPORARILY_UNAVAILABLE
+        ),
+        "{:?}",
+        refused
+    );
+}
+
+fn cluster(seed: u64, conduct: Conduct) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.service(Service::seq_kv(conduct));
+    sim.start::<(), semaphore::SemaphoreNode>(())
+        .expect("nodes start");
+    sim
+}
+
+// the free permits as `via` reads them, asking again while seq-kv fails the read or loses the
+// answer
+fn free(sim: &mut Cluster, via: &str) -> Option<u64> {
+    (0..5).find_map(|_| {
+        sim.take_replies("reader").expect("replies parse");
+        let read = Payload::Read {
+            name: "s".to_string(),
+        };
+        sim.send("reader", via, read).expect("request sends");
+        sim.run_for(Duration::from_secs(1)).expect("nodes step");
+        match sim
+            .take_replies("reader")
+            .expect("replies parse")
+            .pop()?
+            .body
+            .payload
+        {
+            Payload::ReadOk { value } => Some(value),
+            _ => None,
+        }
+    })
+}
+
+// what the clients hold, as far as they can tell
+#[derive(Default)]
+struct Held {
+    // by client, the permits it was granted and hasn't asked to release since, which it holds for
+    // certain, whatever became of the acquires and releases that weren't answered
+    granted: HashMap<&'static str, u64>,
+    // and the permits it was granted less those it was certainly released from
+    held: HashMap<&'static str, u64>,
+    // by msg_id, what was asked and hasn't been answered yet
+    pending: HashMap<usize, (&'static str, Payload)>,
+}
+
+impl Held {
+    fn ask(&mut self, sim: &mut Cluster, client: &'static str, via: &str, request: Payload) {
+        if let Payload::Release { permits, .. } = request {
+            *self.granted.entry(client).or_default() -= permits;
+        }
+        let msg_id = sim
+            .send(client, via, request.clone())
+            .expect("request sends");
+        self.pending.insert(msg_id, (client, request));
+    }
+
+    // the permits held for certain never add up to more than there are
+    fn answered(&mut self, sim: &mut Cluster, seed: u64) {
+        for client in CLIENTS {
+            for reply in sim.take_replies(client).expect("replies parse") {
+                let msg_id = reply.body.in_reply_to.expect("replies say what to");
+                let Some((client, request)) = self.pending.remove(&msg_id) else {
+                    continue;
+                };
+                match (request, reply.body.payload) {
+                    (Payload::Acquire { permits, .. }, Payload::AcquireOk) => {
+                        *self.granted.entry(client).or_default() += permits;
+                        *self.held.entry(client).or_default() += permits;
+                    }
+                    (Payload::Release { permits, .. }, Payload::ReleaseOk) => {
+                        *self.held.entry(client).or_default() -= permits;
+                    }
+                    _ => {}
+                }
+                let granted: u64 = self.granted.values().sum();
+                assert!(
+                    granted <= PERMITS,
+                    "seed {}: {} permits held at once",
+                    seed,
+                    granted
+                );
+            }
+        }
+    }
+}
+
+// clients acquiring permits through any node and releasing what they hold for ten seconds, while
+// seq-kv behaves as `conduct` has it and `crashed` crashes for good four seconds in. A client
+// waits up to a second for an answer before it moves on to something else.
+fn held(seed: u64, conduct: Conduct, crashed: Option<&str>) -> (Cluster, Held) {
+    let mut sim = cluster(seed, conduct);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut up = NODES.to_vec();
+    let mut held = Held::default();
+    let mut asked: HashMap<&str, Duration> = HashMap::new();
+    while sim.now() < Duration::from_secs(10) {
+        if let Some(crashed) = crashed.filter(|_| sim.now() >= Duration::from_secs(4)) {
+            if up.contains(&crashed) {
+                up.retain(|n| *n != crashed);
+                sim.disrupt(Disruption::Kill(Target::Node(crashed.to_string())))
+                    .expect("kills");
+            }
+        }
+        held.answered(&mut sim, seed);
+        for client in CLIENTS {
+            let waiting = held.pending.values().any(|(c, _)| *c == client);
+            if waiting
+                && asked
+                    .get(client)
+                    .is_some_and(|at| sim.now() - *at < Duration::from_secs(1))
+            {
+                continue;
+            }
+            let granted = held.granted.get(client).copied().unwrap_or(0);
+            let name = "s".to_string();
+            let request = if granted > 0 && rng.gen_bool(0.3) {
+                Payload::Release {
+                    name,
+                    permits: rng.gen_range(1..=granted),
+                }
+            } else {
+                Payload::Acquire {
+                    name,
+                    permits: rng.gen_range(1..5),
+                }
+            };
+            let dst = up[rng.gen_range(0..up.len())];
+            held.ask(&mut sim, client, dst, request);
+            asked.insert(client, sim.now());
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    held.answered(&mut sim, seed);
+    (sim, held)
+}
+
+#[test]
+fn no_more_permits_are_held_than_there_are_through_a_flaky_seq_kv_and_a_crash() {
+    for seed in [1, 2, 3] {
+        let conduct = Conduct {
+            latency: Duration::ZERO..=Duration::from_millis(20),
+            unavailable: 0.1,
+            lost: 0.05,
+            stale: Duration::from_millis(100),
+        };
+        let crashed = NODES[StdRng::seed_from_u64(seed).gen_range(0..NODES.len())];
+        let (_, held) = held(seed, conduct, Some(crashed));
+        assert!(
+            !held.granted.is_empty(),
+            "seed {}: nothing was ever granted",
+            seed
+        );
+    }
+}
+
+// with every seq-kv answer getting through and no node crashing, every acquire and release is
+// answered, and once the reads have caught up the free permits are exactly those nobody holds
+#[test]
+fn the_free_permits_are_exactly_those_nobody_holds_when_nothing_is_lost() {
+    for seed in [4, 5, 6] {
+        let conduct = Conduct {
+            latency: Duration::ZERO..=Duration::from_millis(20),
+            unavailable: 0.1,
+            stale: Duration::from_millis(100),
+            ..Conduct::default()
+        };
+        let (mut sim, held) = held(seed, conduct, None);
+        assert!(held.pending.is_empty(), "seed {}: unanswered", seed);
+        let in_use: u64 = held.held.values().sum();
+        for node in NODES {
+            assert_eq!(
+                free(&mut sim, node),
+                Some(PERMITS - in_use),
+                "seed {}: {}'s read",
+                seed,
+                node
+            );
+        }
+    }
+}
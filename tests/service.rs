@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rustengan::error;
+use rustengan::harness::Process;
+use rustengan::history::workload::{self, Anomaly};
+use rustengan::kv::service::{Conduct, Service};
+use rustengan::kv::{KvRequest, SEQ_KV};
+use rustengan::sim::Sim;
+use rustengan::{Body, Event, Init, Message, Node, Output};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+// asks `service` on behalf of `client` at `now`, and what it answered
+fn ask(service: &mut Service, client: &str, now: u64, payload: Value, rng: &mut StdRng) -> Value {
+    let request = Message {
+        src: client.to_string(),
+        dst: service.name().to_string(),
+        body: Body {
+            id: Some(1),
+            in_reply_to: None,
+            correlation_id: None,
+            payload,
+        },
+    };
+    let now = Duration::from_millis(now);
+    let reply = service.handle(&request, now, rng).expect("answered");
+    assert_eq!(reply.body.in_reply_to, Some(1));
+    reply.body.payload
+}
+
+fn read(key: &str) -> Value {
+    json!({ "type": "read", "key": key })
+}
+
+fn write(key: &str, value: i64) -> Value {
+    json!({ "type": "write", "key": key, "value": value })
+}
+
+#[test]
+fn lin_kv_reads_the_last_write_and_cas_checks_against_it() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let mut kv = Service::lin_kv(Conduct::default());
+    let missing = ask(&mut kv, "n0", 0, read("x"), &mut rng);
+    assert_eq!(missing["code"], error::KEY_DOES_NOT_EXIST);
+
+    ask(&mut kv, "n0", 1, write("x", 1), &mut rng);
+    assert_eq!(ask(&mut kv, "n1", 2, read("x"), &mut rng)["value"], 1);
+
+    let cas = |from, to| json!({ "type": "cas", "key": "x", "from": from, "to": to });
+    let failed = ask(&mut kv, "n1", 3, cas(0, 2), &mut rng);
+    assert_eq!(failed["code"], error::PRECONDITION_FAILED);
+    assert_eq!(ask(&mut kv, "n1", 4, cas(1, 2), &mut rng)["type"], "cas_ok");
+    assert_eq!(kv.latest(&json!("x")), Some(&json!(2)));
+
+    let created =
+        json!({ "type": "cas", "key": "y", "from": 0, "to": 5, "create_if_not_exists": true });
+    assert_eq!(ask(&mut kv, "n0", 5, created, &mut rng)["type"], "cas_ok");
+    let unknown = ask(&mut kv, "n0", 6, json!({ "type": "txn" }), &mut rng);
+    assert_eq!(unknown["code"], error::NOT_SUPPORTED);
+}
+
+#[test]
+fn seq_kv_reads_can_be_behind_but_never_go_backwards() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let mut kv = Service::seq_kv(Conduct {
+        stale: Duration::from_millis(100),
+        ..Conduct::default()
+    });
+    for value in 0..10 {
+        ask(&mut kv, "n0", value as u64, write("x", value), &mut rng);
+    }
+    let reads: Vec<i64> = (0..50)
+        .map(|i| {
+            ask(&mut kv, "n1", 10 + i, read("x"), &mut rng)["value"]
+                .as_i64()
+                .unwrap()
+        })
+        .collect();
+    assert!(reads[0] < 9, "a read as fresh as it gets: {:?}", reads);
+    assert!(
+        reads.windows(2).all(|pair| pair[0] <= pair[1]),
+        "{:?}",
+        reads
+    );
+    // the writer sees its own write, and anyone sees it once it's old enough
+    assert_eq!(ask(&mut kv, "n0", 20, read("x"), &mut rng)["value"], 9);
+    assert_eq!(ask(&mut kv, "n2", 200, read("x"), &mut rng)["value"], 9);
+}
+
+#[test]
+fn lww_kv_reads_can_go_backwards() {
+    let mut rng = StdRng::seed_from_u64(3);
+    let mut kv = Service::lww_kv(Conduct {
+        stale: Duration::from_millis(100),
+        ..Conduct::default()
+    });
+    for value in 0..10 {
+        ask(&mut kv, "n0", value as u64, write("x", value), &mut rng);
+    }
+    let reads: Vec<i64> = (0..50)
+        .map(|_| {
+            ask(&mut kv, "n0", 10, read("x"), &mut rng)["value"]
+                .as_i64()
+                .unwrap()
+        })
+        .collect();
+    assert!(
+        reads.windows(2).any(|pair| pair[0] > pair[1]),
+        "{:?}",
+        reads
+    );
+}
+
+#[test]
+fn an_unavailable_service_fails_requests_without_doing_them() {
+    let mut rng = StdRng::seed_from_u64(4);
+    let mut kv = Service::lin_kv(Conduct {
+        unavailable: 1.0,
+        ..Conduct::default()
+    });
+    let failed = ask(&mut kv, "n0", 0, write("x", 1), &mut rng);
+    assert_eq!(failed["code"], error::TEMPORARILY_UNAVAILABLE);
+    assert_eq!(kv.latest(&json!("x")), None);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Add { delta: i64 },
+    AddOk,
+    Read,
+    ReadOk { value: i64 },
+    WriteOk,
+    CasOk,
+    Error { code: usize, text: String },
+}
+
+const COUNTER: &str = "counter";
+
+// a counter all the nodes keep in one key of seq-kv, which they add to by reading it and then
+// writing the new total back: with a cas if they're to `check` that nobody's added to it in
+// between, and blindly otherwise
+struct Shared {
+    node: String,
+    check: bool,
+    id: usize,
+    // by the msg_id of the request to seq-kv, the add it's for, or the client's read if it's
+    // for nothing
+    pending: HashMap<usize, (Message<Payload>, Option<i64>)>,
+}
+
+impl Shared {
+    fn ask(
+        &mut self,
+        request: KvRequest<&str, i64>,
+        waiting: (Message<Payload>, Option<i64>),
+        output: &mut Output,
+    ) -> anyhow::Result<()> {
+        self.id += 1;
+        self.pending.insert(self.id, waiting);
+        request.send(&self.node, SEQ_KV, self.id, output)
+    }
+}
+
+impl Node<bool, Payload> for Shared {
+    fn from_init(
+        check: bool,
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Payload>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            node: init.node_id,
+            check,
+            id: 0,
+            pending: HashMap::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let read = KvRequest::Read { key: COUNTER };
+        if input.src != SEQ_KV {
+            let delta = match input.body.payload {
+                Payload::Add { delta } => Some(delta),
+                _ => None,
+            };
+            return self.ask(read, (input.into_reply(None), delta), output);
+        }
+        let Some((mut reply, delta)) = input
+            .body
+            .in_reply_to
+            .and_then(|id| self.pending.remove(&id))
+        else {
+            return Ok(());
+        };
+        let seen = match input.body.payload {
+            Payload::ReadOk { value } => value,
+            Payload::Error { code, .. } if code == error::KEY_DOES_NOT_EXIST => 0,
+            // someone else added in between, so it's from the top
+            Payload::Error { code, .. } if code == error::PRECONDITION_FAILED => {
+                return self.ask(read, (reply, delta), output);
+            }
+            Payload::WriteOk | Payload::CasOk => {
+                reply.body.payload = Payload::AddOk;
+                return reply.send(output);
+            }
+            payload => anyhow::bail!("seq-kv said {:?}", payload),
+        };
+        let Some(delta) = delta else {
+            reply.body.payload = Payload::ReadOk { value: seen };
+            return reply.send(output);
+        };
+        let total = seen + delta;
+        let update = if self.check {
+            KvRequest::Cas {
+                key: COUNTER,
+                from: seen,
+                to: total,
+                create_if_not_exists: true,
+            }
+        } else {
+            KvRequest::Write {
+                key: COUNTER,
+                value: total,
+            }
+        };
+        self.ask(update, (reply, Some(delta)), output)
+    }
+}
+
+fn count(check: bool) -> Vec<Anomaly> {
+    let nodes = ["n0", "n1", "n2"];
+    let mut sim = Sim::new(8, &nodes);
+    sim.service(Service::seq_kv(Conduct {
+        latency: Duration::from_millis(1)..=Duration::from_millis(5),
+        stale: Duration::from_millis(20),
+        ..Conduct::default()
+    }));
+    sim.start::<bool, Shared>(check).expect("nodes start");
+    for round in 0..20 {
+        for (i, dst) in nodes.iter().enumerate() {
+            let delta = round * 3 + i as i64 + 1;
+            sim.send(&format!("c{}", i), dst, Payload::Add { delta })
+                .expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(5)).expect("nodes step");
+    }
+    // long enough for every add to go through, and for seq-kv to catch up
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    for dst in nodes {
+        sim.send("c9", dst, Payload::Read).expect("read sends");
+    }
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    workload::counter(&sim.sessions())
+}
+
+#[test]
+fn a_counter_on_seq_kv_that_adds_with_cas_counts_every_add() {
+    assert_eq!(count(true), vec![]);
+}
+
+#[test]
+fn a_counter_on_seq_kv_that_writes_blindly_loses_adds() {
+    let anomalies = count(false);
+    assert!(!anomalies.is_empty());
+    for anomaly in anomalies {
+        let Anomaly::Miscounted { read, least, .. } = anomaly else {
+            panic!("{} isn't a miscount", anomaly);
+        };
+        assert!(read < least);
+    }
+}
+
+#[test]
+fn the_semaphore_binary_hands_out_permits_from_a_mock_seq_kv() {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_semaphore"));
+    command.env("RUSTENGAN_SEMAPHORE_PERMITS", "2");
+    let mut node = Process::command(command).expect("node starts");
+    node.serve(Service::seq_kv(Conduct {
+        latency: Duration::ZERO..=Duration::from_millis(5),
+        ..Conduct::default()
+    }));
+    node.init("n0", &["n0"]).expect("node inits");
+
+    let mut ask = |payload: Value| {
+        let reply: Message<Value> = node.request("c1", payload).expect("node answers");
+        reply.body.payload
+    };
+    let acquire = |permits| json!({ "type": "acquire", "name": "s", "permits": permits });
+    assert_eq!(ask(acquire(2))["type"], "acquire_ok");
+    assert_eq!(ask(acquire(1))["type"], "error");
+    assert_eq!(
+        ask(json!({ "type": "release", "name": "s", "permits": 1 }))["type"],
+        "release_ok"
+    );
+    assert_eq!(ask(acquire(1))["type"], "acquire_ok");
+    assert_eq!(ask(json!({ "type": "read", "name": "s" }))["value"], 0);
+}
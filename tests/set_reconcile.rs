@@ -0,0 +1,114 @@
+#[allow(dead_code)]
+#[path = "../src/bin/set_reconcile.rs"]
+mod set_reconcile;
+
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Nemesis, Split};
+use rustengan::sim::Sim;
+use set_reconcile::Payload;
+
+type Cluster = Sim<Payload, set_reconcile::InjectedPayload>;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), set_reconcile::SetNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(300), || {
+        set_reconcile::InjectedPayload::Gossip
+    });
+    sim
+}
+
+fn read(sim: &mut Cluster, via: &str) -> Option<BTreeSet<u64>> {
+    sim.take_replies("reader").expect("replies parse");
+    sim.send("reader", via, Payload::Read)
+        .expect("request sends");
+    sim.run_for(Duration::from_millis(100)).expect("nodes step");
+    match sim
+        .take_replies("reader")
+        .expect("replies parse")
+        .pop()?
+        .body
+        .payload
+    {
+        Payload::ReadOk { elements } => Some(elements),
+        _ => None,
+    }
+}
+
+// a writer adding elements through any node for ten seconds, at a pace that lets the sets drift
+// far enough apart to need bigger sketches and whole sets, while the nodes lose, repeat, hold up
+// and reorder what they send each other and are partitioned from each other over and over. Every
+// so often a node is read: it can only ever have elements somebody added, and never fewer than
+// it had. Once the faults are over and the nodes have had a while to gossip, every node has to
+// have every element that was added.
+#[test]
+fn every_node_ends_up_with_exactly_the_added_elements_after_faults() {
+    for seed in [1, 2, 3] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            delay: 0.1,
+            delay_by: Duration::from_millis(50),
+            reorder: 0.1,
+        });
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(1500),
+                Duration::from_millis(2000),
+            )
+            .until(Duration::from_secs(10)),
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut added = BTreeSet::new();
+        let mut seen: HashMap<&str, BTreeSet<u64>> = HashMap::new();
+        while sim.now() < Duration::from_secs(10) {
+            for _ in 0..rng.gen_range(0..20) {
+                let element = rng.gen();
+                let dst = NODES[rng.gen_range(0..NODES.len())];
+                sim.send("writer", dst, Payload::Add { element })
+                    .expect("request sends");
+                added.insert(element);
+            }
+            let node = NODES[rng.gen_range(0..NODES.len())];
+            let elements = read(&mut sim, node).expect("reads are answered");
+            assert!(
+                elements.is_subset(&added),
+                "seed {}: {} has {:?}, which nobody added",
+                seed,
+                node,
+                elements.difference(&added).collect::<Vec<_>>()
+            );
+            let before = seen.entry(node).or_default();
+            assert!(
+                before.is_subset(&elements),
+                "seed {}: {} lost {:?}",
+                seed,
+                node,
+                before.difference(&elements).collect::<Vec<_>>()
+            );
+            *before = elements;
+        }
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+        for node in NODES {
+            assert_eq!(
+                read(&mut sim, node).as_ref(),
+                Some(&added),
+                "seed {}: {}'s elements",
+                seed,
+                node
+            );
+        }
+    }
+}
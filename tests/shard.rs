@@ -0,0 +1,97 @@
+use rustengan::shard::{self, HashRing, Placement, Rendezvous};
+
+const KEYS: u64 = 10_000;
+const VNODES: usize = 64;
+const MEMBERS: usize = 10;
+
+type New = fn() -> Box<dyn Placement>;
+
+// each placement, and how far off an even share of the keys a change of members can move: a
+// ring's shares are only as even as its virtual nodes make them, off by around one over the
+// square root of how many each member has, while rendezvous only moves what a member wins,
+// which is as near an even share as chance allows
+fn placements() -> Vec<(&'static str, New, f64)> {
+    vec![
+        ("ring", || Box::new(HashRing::new(VNODES)), 0.5),
+        ("rendezvous", || Box::new(Rendezvous::new()), 0.1),
+    ]
+}
+
+// `new` with members n0 up to but not including n<members>
+fn placed(new: New, members: usize) -> Box<dyn Placement> {
+    let mut placement = new();
+    for i in 0..members {
+        placement.add(&format!("n{}", i));
+    }
+    placement
+}
+
+fn key_hashes() -> impl Iterator<Item = u64> {
+    (0..KEYS).map(|key| shard::hash(&key))
+}
+
+// whether `moved` keys are within `off` of an even share of them among `MEMBERS`
+fn near_even_share(moved: usize, off: f64) -> bool {
+    let share = (KEYS as f64) / (MEMBERS as f64);
+    (moved as f64 - share).abs() <= share * off
+}
+
+fn owned_by(placement: &dyn Placement, member: &str) -> usize {
+    key_hashes()
+        .filter(|&key_hash| placement.owner(key_hash) == Some(member))
+        .count()
+}
+
+#[test]
+fn adding_a_member_only_moves_the_keys_it_takes_on() {
+    for (name, new, off) in placements() {
+        let before = placed(new, MEMBERS - 1);
+        let after = placed(new, MEMBERS);
+        let added = format!("n{}", MEMBERS - 1);
+        for key_hash in key_hashes() {
+            let (was, is) = (before.owner(key_hash), after.owner(key_hash));
+            assert!(
+                was == is || is == Some(added.as_str()),
+                "{}: {:?} to {:?}",
+                name,
+                was,
+                is
+            );
+        }
+        let moved = shard::moved(&*before, &*after, key_hashes());
+        assert_eq!(moved, owned_by(&*after, &added), "{}", name);
+        assert!(
+            near_even_share(moved, off),
+            "{}: {} keys moved",
+            name,
+            moved
+        );
+    }
+}
+
+#[test]
+fn removing_a_member_only_moves_the_keys_it_had() {
+    for (name, new, off) in placements() {
+        let before = placed(new, MEMBERS);
+        let mut after = placed(new, MEMBERS);
+        after.remove("n3");
+        for key_hash in key_hashes() {
+            let (was, is) = (before.owner(key_hash), after.owner(key_hash));
+            assert!(
+                was == is || was == Some("n3"),
+                "{}: {:?} to {:?}",
+                name,
+                was,
+                is
+            );
+        }
+        let moved = shard::moved(&*before, &*after, key_hashes());
+        assert_eq!(moved, owned_by(&*before, "n3"), "{}", name);
+        assert!(
+            near_even_share(moved, off),
+            "{}: {} keys moved",
+            name,
+            moved
+        );
+    }
+}
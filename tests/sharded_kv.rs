@@ -0,0 +1,169 @@
+#[allow(dead_code)]
+#[path = "../src/bin/sharded_kv.rs"]
+mod sharded_kv;
+
+use std::collections::{BTreeSet, HashSet};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::failure_detector::FdEvent;
+use rustengan::history::linearizable::check;
+use rustengan::history::{Op, Type};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Nemesis, Split};
+use rustengan::sim::Sim;
+use sharded_kv::Payload;
+
+type Cluster = Sim<Payload, sharded_kv::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const KEYS: usize = 6;
+const VALUES: usize = 3;
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), sharded_kv::ShardedKvNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        sharded_kv::InjectedPayload::Fd(FdEvent::Heartbeat)
+    });
+    sim
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+// clients reading, writing and cas-ing a few keys through any node, mostly not the key's owner,
+// while the nodes lose, repeat, hold up and reorder the requests they forward each other and the
+// answers they send back, but never for long enough to take each other for down. A client waits up
+// to a second for an answer before it gives up and asks for something else.
+fn sharded_kv_history(seed: u64) -> Vec<Op> {
+    let mut sim = cluster(seed);
+    sim.faults(Faults {
+        drop: 0.1,
+        duplicate: 0.1,
+        delay: 0.1,
+        delay_by: Duration::from_millis(50),
+        reorder: 0.1,
+    });
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut asked = [None; 3];
+    while sim.now() < Duration::from_secs(10) {
+        for (client, asked) in asked.iter_mut().enumerate() {
+            let client = format!("c{}", client);
+            if !sim.take_replies(&client).expect("replies parse").is_empty() {
+                *asked = None;
+            }
+            if asked.is_some_and(|at| sim.now() - at < Duration::from_secs(1)) {
+                continue;
+            }
+            *asked = Some(sim.now());
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..3) {
+                0 => Payload::Read { key },
+                1 => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+                _ => Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..5),
+                    to: rng.gen_range(0..5),
+                },
+            };
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send(&client, dst, request).expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.history()
+}
+
+#[test]
+fn sharded_kv_stays_linearizable_through_a_flaky_network() {
+    for seed in [1, 2, 3] {
+        let history = sharded_kv_history(seed);
+        assert_eq!(check(&history), Ok(()), "seed {}", seed);
+        assert!(
+            history.iter().any(|op| op.kind == Type::Ok),
+            "seed {}: nothing was answered",
+            seed
+        );
+    }
+}
+
+// clients writing and cas-ing a handful of keys through any node while the nodes are partitioned
+// from each other, long enough to take each other for down and move keys and index partitions
+// around and then back. Once the partitions are over, every value's index entries have to list
+// exactly the keys that hold it.
+#[test]
+fn the_index_settles_on_exactly_the_keys_holding_each_value_after_partitions() {
+    for seed in [4, 5, 6] {
+        let mut sim = cluster(seed);
+        sim.nemesis(
+            Nemesis::partitions(
+                Split::Halves,
+                Duration::from_millis(2000),
+                Duration::from_millis(2500),
+            )
+            .until(Duration::from_secs(10)),
+        );
+        let mut rng = StdRng::seed_from_u64(seed);
+        while sim.now() < Duration::from_secs(10) {
+            let key = rng.gen_range(0..KEYS);
+            let request = match rng.gen_range(0..2) {
+                0 => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..VALUES),
+                },
+                _ => Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..VALUES),
+                    to: rng.gen_range(0..VALUES),
+                },
+            };
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send("writer", dst, request).expect("request sends");
+            sim.run_for(Duration::from_millis(rng.gen_range(20..100)))
+                .expect("nodes step");
+        }
+        sim.run_for(Duration::from_secs(5)).expect("nodes step");
+
+        let mut held = vec![BTreeSet::new(); VALUES];
+        let mut stored = HashSet::new();
+        for key in 0..KEYS {
+            match ask(&mut sim, "reader", "n0", Payload::Read { key }) {
+                Some(Payload::ReadOk { value }) => {
+                    held[value].insert(key);
+                    stored.insert(key);
+                }
+                Some(Payload::Error { .. }) => {}
+                other => panic!("seed {}: reading {} got {:?}", seed, key, other),
+            }
+        }
+        assert!(!stored.is_empty(), "seed {}: nothing was written", seed);
+        for (value, held) in held.iter().enumerate() {
+            for node in NODES {
+                let Some(Payload::QueryIndexOk { keys }) =
+                    ask(&mut sim, "reader", node, Payload::QueryIndex { value })
+                else {
+                    panic!("seed {}: {} didn't answer the query", seed, node);
+                };
+                let listed: BTreeSet<_> = keys.into_iter().collect();
+                assert_eq!(
+                    &listed, held,
+                    "seed {}: {}'s index for {}",
+                    seed, node, value
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1138 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::Rng;
+use rustengan::hlc::{Hlc, Timestamp};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Split, Target};
+use rustengan::sim::profile::{Latency, Profile};
+use rustengan::sim::shrink::{self, Choice, Fault, Schedule};
+use rustengan::sim::skew::Skew;
+use rustengan::sim::trace::{Cause, Transition};
+use rustengan::sim::{self, Sim};
+use rustengan::txn::{Op, OpKind};
+use rustengan::wal::{self, Wal};
+use rustengan::{clock, rng, Event, Init, Message, Node, Output};
+use serde::{Deserialize, Serialize};
+
+#[allow(dead_code)]
+#[path = "../src/bin/txn.rs"]
+mod txn;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+const GOSSIP_EVERY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Broadcast { message: usize },
+    BroadcastOk,
+    Read,
+    ReadOk { messages: BTreeSet<usize> },
+    Gossip { seen: BTreeSet<usize> },
+}
+
+enum Injected {
+    Gossip,
+}
+
+// a broadcast node at its simplest: every tick, it tells one neighbour (the next node along)
+// everything it's seen
+struct Ring {
+    node: String,
+    next: String,
+    id: usize,
+    messages: BTreeSet<usize>,
+}
+
+impl Node<(), Payload, Injected> for Ring {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Payload, Injected>>,
+    ) -> anyhow::Result<Self> {
+        let at = init
+            .node_ids
+            .iter()
+            .position(|id| *id == init.node_id)
+            .expect("one of the nodes");
+        Ok(Self {
+            next: init.node_ids[(at + 1) % init.node_ids.len()].clone(),
+            node: init.node_id,
+            id: 1,
+            messages: BTreeSet::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload, Injected>, output: &mut Output) -> anyhow::Result<()> {
+        match input {
+            Event::Injected(Injected::Gossip) => {
+                let gossip = Message {
+                    src: self.node.clone(),
+                    dst: self.next.clone(),
+                    body: rustengan::Body {
+                        id: None,
+                        in_reply_to: None,
+                        correlation_id: None,
+                        payload: Payload::Gossip {
+                            seen: self.messages.clone(),
+                        },
+                    },
+                };
+                gossip.send(output)?;
+            }
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Payload::Broadcast { message } => {
+                        self.messages.insert(message);
+                        reply.body.payload = Payload::BroadcastOk;
+                        reply.send(output)?;
+                    }
+                    Payload::Read => {
+                        reply.body.payload = Payload::ReadOk {
+                            messages: self.messages.clone(),
+                        };
+                        reply.send(output)?;
+                    }
+                    Payload::Gossip { seen } => self.messages.extend(seen),
+                    Payload::BroadcastOk | Payload::ReadOk { .. } => {}
+                }
+            }
+            Event::EOF => {}
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> serde_json::Value {
+        serde_json::json!({ "messages": self.messages })
+    }
+}
+
+fn ring(seed: u64) -> Sim<Payload, Injected> {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), Ring>(()).expect("nodes start");
+    sim.every(GOSSIP_EVERY, || Injected::Gossip);
+    sim
+}
+
+// what each node has, by node
+fn read_all(sim: &mut Sim<Payload, Injected>) -> Vec<(String, BTreeSet<usize>)> {
+    for dst in NODES {
+        sim.send("reader", dst, Payload::Read).expect("read sends");
+    }
+    sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    let replies = sim.replies("reader").expect("replies parse");
+    let mut reads: Vec<_> = replies[replies.len() - NODES.len()..]
+        .iter()
+        .map(|read| match &read.body.payload {
+            Payload::ReadOk { messages } => (read.src.clone(), messages.clone()),
+            other => panic!("{:?} isn't a read_ok", other),
+        })
+        .collect();
+    reads.sort();
+    reads
+}
+
+// runs a cluster of `Ring`s, broadcasting a message to each node in turn, and returns what
+// every node reads once they've had time to gossip, and when the simulation got there
+fn broadcast(seed: u64) -> (Vec<Message<Payload>>, Duration) {
+    let mut sim = ring(seed);
+    for message in 0..20 {
+        let dst = NODES[message % NODES.len()];
+        sim.send("c1", dst, Payload::Broadcast { message })
+            .expect("request sends");
+        sim.run_for(Duration::from_millis(7)).expect("nodes step");
+    }
+    sim.run_for(GOSSIP_EVERY * 2 * NODES.len() as u32)
+        .expect("nodes step");
+    for dst in NODES {
+        sim.send("c2", dst, Payload::Read).expect("read sends");
+    }
+    sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    (sim.replies("c2").expect("replies parse"), sim.now())
+}
+
+#[test]
+fn gossip_reaches_every_node() {
+    let (reads, _) = broadcast(1);
+    assert_eq!(reads.len(), NODES.len());
+    let everything: BTreeSet<usize> = (0..20).collect();
+    for read in reads {
+        assert_eq!(
+            read.body.payload,
+            Payload::ReadOk {
+                messages: everything.clone()
+            },
+            "{} is missing messages",
+            read.src
+        );
+    }
+}
+
+#[test]
+fn the_same_seed_makes_the_same_run() {
+    let (first, first_at) = broadcast(7);
+    let (again, again_at) = broadcast(7);
+    assert_eq!(first_at, again_at);
+    // message for message, msg_ids and all
+    let json = |reads| serde_json::to_string(&reads).expect("replies serialize");
+    assert_eq!(json(first), json(again));
+}
+
+#[test]
+fn virtual_time_doesnt_wait_for_real_time() {
+    let started = std::time::Instant::now();
+    let mut sim = ring(3);
+    sim.run_for(Duration::from_secs(60)).expect("nodes step");
+    assert_eq!(sim.now(), Duration::from_secs(60));
+    assert!(started.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn gossip_gets_through_a_bad_network() {
+    let mut sim = ring(11);
+    sim.faults(Faults {
+        drop: 0.3,
+        duplicate: 0.2,
+        delay: 0.2,
+        delay_by: Duration::from_millis(300),
+        reorder: 0.2,
+    });
+    for message in 0..20 {
+        sim.send(
+            "c1",
+            NODES[message % NODES.len()],
+            Payload::Broadcast { message },
+        )
+        .expect("request sends");
+    }
+    sim.run_for(Duration::from_secs(10)).expect("nodes step");
+    let tally = sim.tally();
+    assert!(tally.dropped > 0 && tally.duplicated > 0, "{:?}", tally);
+    assert!(tally.delayed > 0 && tally.reordered > 0, "{:?}", tally);
+    let everything: BTreeSet<usize> = (0..20).collect();
+    for (node, messages) in read_all(&mut sim) {
+        assert_eq!(messages, everything, "{} is missing messages", node);
+    }
+}
+
+#[test]
+fn a_cut_link_holds_gossip_up_until_it_heals() {
+    let mut sim = ring(5);
+    // n0 only ever gossips to n1, so nobody else can hear about what's sent to n0
+    sim.link_faults("n0", "n1", Faults::cut());
+    sim.faults_after(
+        Duration::from_secs(2),
+        Some(("n0", "n1")),
+        Faults::default(),
+    );
+    sim.send("c1", "n0", Payload::Broadcast { message: 1 })
+        .expect("request sends");
+    sim.run_for(Duration::from_millis(1500))
+        .expect("nodes step");
+    for (node, messages) in read_all(&mut sim) {
+        assert_eq!(
+            messages.contains(&1),
+            node == "n0",
+            "{} read {:?}",
+            node,
+            messages
+        );
+    }
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    for (node, messages) in read_all(&mut sim) {
+        assert!(messages.contains(&1), "{} never heard of it", node);
+    }
+}
+
+#[test]
+fn a_link_is_as_slow_as_its_profile_says_and_only_the_one_way() {
+    let mut sim = ring(41);
+    let slow = Duration::from_millis(300);
+    sim.link(
+        "c1",
+        "n0",
+        Profile {
+            latency: Some(Latency::Uniform(slow..=slow)),
+            ..Profile::default()
+        },
+    );
+    sim.send("c1", "n0", Payload::Read).expect("read sends");
+    sim.run_for(slow - Duration::from_millis(1))
+        .expect("nodes step");
+    assert!(sim.replies("c1").expect("replies parse").is_empty());
+    // the way back keeps the simulation's latency, which is never more than 10ms
+    sim.run_for(Duration::from_millis(11)).expect("nodes step");
+    assert_eq!(sim.replies("c1").expect("replies parse").len(), 1);
+}
+
+#[test]
+fn a_link_with_a_bandwidth_cap_lets_one_message_through_at_a_time() {
+    let mut sim = ring(43);
+    // a read is some 60 bytes on the wire, so the link puts one on it every 100ms or so
+    sim.link(
+        "c1",
+        "n0",
+        Profile {
+            bandwidth: Some(600),
+            ..Profile::default()
+        },
+    );
+    for _ in 0..10 {
+        sim.send("c1", "n0", Payload::Read).expect("read sends");
+    }
+    sim.run_for(Duration::from_millis(500)).expect("nodes step");
+    let answered = sim.replies("c1").expect("replies parse").len();
+    assert!((3..=6).contains(&answered), "{} answered", answered);
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    assert_eq!(sim.replies("c1").expect("replies parse").len(), 10);
+}
+
+#[test]
+fn a_partition_keeps_gossip_on_its_own_side_until_it_heals() {
+    let mut sim = ring(13);
+    let side = |nodes: &[&str]| nodes.iter().map(|n| n.to_string()).collect();
+    sim.disrupt(Disruption::Partition(Split::Components(vec![
+        side(&["n0", "n1"]),
+        side(&["n2", "n3", "n4"]),
+    ])))
+    .expect("partitions");
+    sim.send("c1", "n0", Payload::Broadcast { message: 1 })
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    for (node, messages) in read_all(&mut sim) {
+        let near = node == "n0" || node == "n1";
+        assert_eq!(messages.contains(&1), near, "{} read {:?}", node, messages);
+    }
+    assert!(sim.tally().partitioned > 0);
+    sim.disrupt(Disruption::Heal).expect("heals");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    for (node, messages) in read_all(&mut sim) {
+        assert!(messages.contains(&1), "{} never heard of it", node);
+    }
+}
+
+#[test]
+fn a_paused_node_catches_up_once_its_resumed() {
+    let mut sim = ring(17);
+    sim.disrupt(Disruption::Pause(Target::Node("n2".to_string())))
+        .expect("pauses");
+    sim.send("c1", "n0", Payload::Broadcast { message: 1 })
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    // n2 passes gossip on to n3, so n3 and n4 are waiting on it as well
+    for dst in ["n1", "n3"] {
+        sim.send("reader", dst, Payload::Read).expect("read sends");
+    }
+    sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    let reads = sim.replies("reader").expect("replies parse");
+    for read in reads {
+        let heard = read.body.payload
+            == Payload::ReadOk {
+                messages: [1].into(),
+            };
+        assert_eq!(heard, read.src == "n1", "{:?}", read);
+    }
+    sim.disrupt(Disruption::Resume).expect("resumes");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    for (node, messages) in read_all(&mut sim) {
+        assert!(messages.contains(&1), "{} never heard of it", node);
+    }
+}
+
+#[test]
+fn a_killed_node_forgets_everything_and_hears_it_again_once_restarted() {
+    let mut sim = ring(19);
+    sim.send("c1", "n0", Payload::Broadcast { message: 1 })
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    sim.disrupt(Disruption::Kill(Target::Node("n2".to_string())))
+        .expect("kills");
+    sim.send("c1", "n0", Payload::Broadcast { message: 2 })
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.send("reader", "n2", Payload::Read).expect("read sends");
+    sim.run_for(Duration::from_millis(20)).expect("nodes step");
+    let reads = sim.replies("reader").expect("replies parse");
+    assert_eq!(
+        reads.last().map(|read| &read.body.payload),
+        Some(&Payload::ReadOk {
+            messages: BTreeSet::new()
+        })
+    );
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    let everything: BTreeSet<usize> = [1, 2].into();
+    for (node, messages) in read_all(&mut sim) {
+        assert_eq!(messages, everything, "{} is missing messages", node);
+    }
+}
+
+// a node that keeps what it's told in a write-ahead log, and tells nobody
+struct Logged {
+    id: usize,
+    wal: Wal<usize>,
+    messages: BTreeSet<usize>,
+}
+
+impl Node<(), Payload, Injected> for Logged {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Payload, Injected>>,
+    ) -> anyhow::Result<Self> {
+        let (wal, messages) = Wal::open(wal::data_dir().join(format!("{}.sim.wal", init.node_id)))?;
+        Ok(Self {
+            id: 1,
+            wal,
+            messages: messages.into_iter().collect(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload, Injected>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(Some(&mut self.id));
+        reply.body.payload = match reply.body.payload {
+            Payload::Broadcast { message } => {
+                self.wal.append(&message)?;
+                self.messages.insert(message);
+                Payload::BroadcastOk
+            }
+            Payload::Read => Payload::ReadOk {
+                messages: self.messages.clone(),
+            },
+            _ => return Ok(()),
+        };
+        reply.send(output)
+    }
+}
+
+fn read_logged(sim: &mut Sim<Payload, Injected>) -> Option<Payload> {
+    sim.send("reader", "n0", Payload::Read).expect("read sends");
+    sim.run_for(Duration::from_millis(20)).expect("nodes step");
+    let reads = sim.take_replies("reader").expect("replies parse");
+    reads.last().map(|read| read.body.payload.clone())
+}
+
+#[test]
+fn a_restarted_node_comes_back_to_its_wal_but_no_other_simulation_sees_it() {
+    let mut sim = Sim::new(23, &["n0"]);
+    sim.start::<(), Logged>(()).expect("nodes start");
+    sim.send("c1", "n0", Payload::Broadcast { message: 1 })
+        .expect("request sends");
+    sim.run_for(Duration::from_millis(20)).expect("nodes step");
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    let kept = Payload::ReadOk {
+        messages: [1].into(),
+    };
+    assert_eq!(read_logged(&mut sim), Some(kept));
+
+    let nothing = Payload::ReadOk {
+        messages: BTreeSet::new(),
+    };
+    let mut alongside = Sim::new(23, &["n0"]);
+    alongside.start::<(), Logged>(()).expect("nodes start");
+    assert_eq!(read_logged(&mut alongside), Some(nothing.clone()));
+    drop((sim, alongside));
+    let mut after = Sim::new(23, &["n0"]);
+    after.start::<(), Logged>(()).expect("nodes start");
+    assert_eq!(read_logged(&mut after), Some(nothing));
+}
+
+// keys 1 and 2 belong to n1 and n2, so a transaction writing both that goes to n0 has n0
+// coordinate it without being one of its participants
+fn coordinated(seed: u64) -> Sim<txn::Payload, txn::InjectedPayload> {
+    let mut sim = Sim::new(seed, &["n0", "n1", "n2"]);
+    sim.start::<(), txn::TxnNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(100), || txn::InjectedPayload::Tick);
+    sim
+}
+
+fn write_both(sim: &mut Sim<txn::Payload, txn::InjectedPayload>, value: usize) {
+    let txn = vec![Op(OpKind::W, 1, Some(value)), Op(OpKind::W, 2, Some(value))];
+    sim.send("c1", "n0", txn::Payload::Txn { txn })
+        .expect("request sends");
+}
+
+fn acknowledged(sim: &Sim<txn::Payload, txn::InjectedPayload>) -> bool {
+    let replies = sim.replies("c1").expect("replies parse");
+    replies
+        .iter()
+        .any(|reply| matches!(reply.body.payload, txn::Payload::TxnOk { .. }))
+}
+
+// both keys as of one snapshot, read through n1, which n0 has no part in. None while a
+// participant is still in doubt about the write, since it can't say which way it went.
+fn read_both(sim: &mut Sim<txn::Payload, txn::InjectedPayload>) -> Option<Vec<Option<usize>>> {
+    let txn = vec![Op(OpKind::R, 1, None), Op(OpKind::R, 2, None)];
+    sim.send("reader", "n1", txn::Payload::Txn { txn })
+        .expect("read sends");
+    sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    let read = sim.take_replies("reader").expect("replies parse").pop()?;
+    match read.body.payload {
+        txn::Payload::TxnOk { txn } => Some(txn.into_iter().map(|op| op.2).collect()),
+        _ => None,
+    }
+}
+
+#[test]
+fn participants_in_doubt_abort_with_a_2pc_coordinator_that_crashed_before_deciding() {
+    let mut sim = coordinated(41);
+    // the votes never make it back, so n0 is still waiting on them when it goes down
+    sim.link_faults("n1", "n0", Faults::cut())
+        .link_faults("n2", "n0", Faults::cut());
+    write_both(&mut sim, 10);
+    sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.link_faults("n1", "n0", Faults::default())
+        .link_faults("n2", "n0", Faults::default());
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    assert_eq!(read_both(&mut sim), None, "blocked without n0");
+
+    // it has nothing logged about the transaction, so it can't have committed
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    assert_eq!(read_both(&mut sim), Some(vec![None, None]));
+    assert!(!acknowledged(&sim));
+}
+
+#[test]
+fn a_2pc_decision_logged_before_the_coordinator_crashed_reaches_every_participant() {
+    let mut sim = coordinated(42);
+    write_both(&mut sim, 10);
+    // n0 answers the client in the step it decides in, and goes down right after, before its
+    // decision gets anywhere
+    while !acknowledged(&sim) {
+        assert!(sim.step().expect("nodes step"), "n0 decides");
+    }
+    let alone = Split::Components(vec![vec!["n0".to_string()]]);
+    sim.disrupt(Disruption::Partition(alone))
+        .expect("partitions");
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(2)).expect("nodes step");
+    assert_eq!(read_both(&mut sim), None, "blocked without n0");
+
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.disrupt(Disruption::Heal).expect("heals");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    assert_eq!(read_both(&mut sim), Some(vec![Some(10), Some(10)]));
+}
+
+#[test]
+fn wherever_a_2pc_coordinator_crashes_its_participants_end_up_agreeing() {
+    for steps in 0..12 {
+        let mut sim = coordinated(43);
+        write_both(&mut sim, 10);
+        for _ in 0..steps {
+            sim.step().expect("nodes step");
+        }
+        let acked = acknowledged(&sim);
+        sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+            .expect("kills");
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+        sim.disrupt(Disruption::Restart).expect("restarts");
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+        let read = read_both(&mut sim);
+        if acked {
+            assert_eq!(
+                read,
+                Some(vec![Some(10), Some(10)]),
+                "after {} steps",
+                steps
+            );
+        } else {
+            assert!(
+                read == Some(vec![None, None]) || read == Some(vec![Some(10), Some(10)]),
+                "after {} steps: {:?}",
+                steps,
+                read
+            );
+        }
+    }
+}
+
+#[test]
+fn a_compacted_txn_log_still_brings_back_what_was_committed() {
+    let mut sim = coordinated(44);
+    // enough transactions to end everywhere that every node compacts its log at least once
+    for value in 0..100 {
+        write_both(&mut sim, value);
+        sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    }
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    for node in ["n0", "n1", "n2"] {
+        sim.disrupt(Disruption::Kill(Target::Node(node.to_string())))
+            .expect("kills");
+    }
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.run_for(Duration::from_millis(500)).expect("nodes step");
+    assert_eq!(read_both(&mut sim), Some(vec![Some(99), Some(99)]));
+}
+
+#[test]
+fn gossip_gets_through_partitions_that_come_and_go() {
+    let mut sim = ring(23);
+    sim.nemesis(
+        Nemesis::partitions(
+            Split::Halves,
+            Duration::from_millis(300),
+            Duration::from_millis(700),
+        )
+        .until(Duration::from_secs(5)),
+    );
+    for message in 0..20 {
+        let dst = NODES[message % NODES.len()];
+        sim.send("c1", dst, Payload::Broadcast { message })
+            .expect("request sends");
+        sim.run_for(Duration::from_millis(200)).expect("nodes step");
+    }
+    assert!(sim.tally().partitioned > 0, "{:?}", sim.tally());
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    let everything: BTreeSet<usize> = (0..20).collect();
+    for (node, messages) in read_all(&mut sim) {
+        assert_eq!(messages, everything, "{} is missing messages", node);
+    }
+}
+
+// a trace written somewhere the test can read it back from
+#[derive(Clone, Default)]
+struct Traced(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Traced {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().expect("not poisoned").extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Traced {
+    fn transitions(&self) -> Vec<Transition> {
+        let trace = self.0.lock().expect("not poisoned");
+        trace
+            .split(|&b| b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| serde_json::from_slice(line).expect("a transition"))
+            .collect()
+    }
+}
+
+fn traced_broadcast(seed: u64) -> Vec<Transition> {
+    let traced = Traced::default();
+    let mut sim = ring(seed);
+    sim.trace(traced.clone());
+    for (message, dst) in NODES.iter().take(3).enumerate() {
+        sim.send("c1", dst, Payload::Broadcast { message })
+            .expect("request sends");
+    }
+    sim.run_for(GOSSIP_EVERY * 3).expect("nodes step");
+    traced.transitions()
+}
+
+#[test]
+fn a_trace_has_every_step_chained_from_the_state_before() {
+    let transitions = traced_broadcast(4);
+    let mut last: BTreeMap<&str, &str> = BTreeMap::new();
+    for transition in &transitions {
+        if let Some(after) = last.insert(&transition.node, &transition.after) {
+            assert_eq!(after, transition.before, "{:?}", transition);
+        }
+        assert_eq!(
+            transition.after,
+            rustengan::sim::trace::fingerprint(&transition.state)
+        );
+    }
+    let broadcast = transitions
+        .iter()
+        .find(|t| matches!(&t.event, Cause::Message { message } if message["body"]["type"] == "broadcast"))
+        .expect("a broadcast was traced");
+    assert_ne!(broadcast.before, broadcast.after);
+    assert!(transitions
+        .iter()
+        .any(|t| t.event == Cause::Tick { timer: 0 }));
+    assert_eq!(transitions, traced_broadcast(4));
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Time {
+    Stamp {
+        to: String,
+    },
+    Stamped {
+        at: Timestamp,
+    },
+    Read,
+    ReadOk {
+        wall_ms: u64,
+        monotonic_ms: u64,
+        ticks: usize,
+        stamps: Vec<Timestamp>,
+    },
+}
+
+struct Tick;
+
+// a node that says what its clocks read, and stamps messages with its HLC
+struct Clocks {
+    node: String,
+    id: usize,
+    started: std::time::Instant,
+    hlc: Hlc,
+    ticks: usize,
+    stamps: Vec<Timestamp>,
+}
+
+impl Node<(), Time, Tick> for Clocks {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Time, Tick>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            node: init.node_id,
+            id: 1,
+            started: clock::now(),
+            hlc: Hlc::new(),
+            ticks: 0,
+            stamps: Vec::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Time, Tick>, output: &mut Output) -> anyhow::Result<()> {
+        match input {
+            Event::Injected(Tick) => self.ticks += 1,
+            Event::Message(input) => {
+                let mut reply = input.into_reply(Some(&mut self.id));
+                match reply.body.payload {
+                    Time::Stamp { to } => {
+                        let stamped = Message {
+                            src: self.node.clone(),
+                            dst: to,
+                            body: rustengan::Body {
+                                id: None,
+                                in_reply_to: None,
+                                correlation_id: None,
+                                payload: Time::Stamped { at: self.hlc.now() },
+                            },
+                        };
+                        stamped.send(output)?;
+                    }
+                    Time::Stamped { at } => {
+                        self.hlc.observe(at);
+                        let ours = self.hlc.now();
+                        self.stamps.extend([at, ours]);
+                    }
+                    Time::Read => {
+                        reply.body.payload = Time::ReadOk {
+                            wall_ms: clock::wall_ms(),
+                            monotonic_ms: clock::since(self.started).as_millis() as u64,
+                            ticks: self.ticks,
+                            stamps: self.stamps.clone(),
+                        };
+                        reply.send(output)?;
+                    }
+                    Time::ReadOk { .. } => {}
+                }
+            }
+            Event::EOF => {}
+        }
+        Ok(())
+    }
+}
+
+fn clocks(seed: u64) -> Sim<Time, Tick> {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), Clocks>(()).expect("nodes start");
+    sim.every(GOSSIP_EVERY, || Tick);
+    sim
+}
+
+// what `node` says its clocks read
+fn read_clock(sim: &mut Sim<Time, Tick>, node: &str) -> (u64, u64, usize, Vec<Timestamp>) {
+    sim.send("reader", node, Time::Read).expect("read sends");
+    sim.run_for(Duration::from_millis(20)).expect("nodes step");
+    let replies = sim.replies("reader").expect("replies parse");
+    match replies.last().map(|read| read.body.payload.clone()) {
+        Some(Time::ReadOk {
+            wall_ms,
+            monotonic_ms,
+            ticks,
+            stamps,
+        }) => (wall_ms, monotonic_ms, ticks, stamps),
+        other => panic!("{:?} isn't a read_ok", other),
+    }
+}
+
+#[test]
+fn a_skewed_clock_is_off_by_its_offset_and_runs_at_its_own_rate() {
+    let mut sim = clocks(29);
+    sim.skew(
+        "n1",
+        Skew {
+            offset_ms: 5_000,
+            drift: 0.0,
+        },
+    );
+    sim.skew(
+        "n2",
+        Skew {
+            offset_ms: 0,
+            drift: 0.1,
+        },
+    );
+    // the step doesn't make n1's monotonic clock jump with it
+    sim.skew_after(
+        Duration::from_secs(5),
+        "n1",
+        Skew {
+            offset_ms: -5_000,
+            drift: 0.0,
+        },
+    );
+    sim.run_for(Duration::from_secs(10)).expect("nodes step");
+    // each read takes a few milliseconds of virtual time, hence the slack
+    let right = sim.clock_of("n0");
+    let (n0_wall, n0_monotonic, n0_ticks, _) = read_clock(&mut sim, "n0");
+    assert!(n0_wall.abs_diff(right.wall.as_millis() as u64) <= 20);
+    let (n1_wall, n1_monotonic, _, _) = read_clock(&mut sim, "n1");
+    assert!(
+        (n1_wall + 5_000).abs_diff(n0_wall) <= 40,
+        "{} {}",
+        n1_wall,
+        n0_wall
+    );
+    assert!(n1_monotonic.abs_diff(n0_monotonic) <= 40);
+    let (_, n2_monotonic, n2_ticks, _) = read_clock(&mut sim, "n2");
+    assert!(n2_monotonic.abs_diff(11_000) <= 100, "{}", n2_monotonic);
+    assert_eq!(n0_ticks, 100);
+    assert!(n2_ticks >= 109, "{}", n2_ticks);
+}
+
+#[test]
+fn hlc_stamps_stay_ordered_across_a_clock_thats_behind() {
+    let mut sim = clocks(31);
+    sim.skew(
+        "n0",
+        Skew {
+            offset_ms: 3_000,
+            drift: 0.0,
+        },
+    );
+    sim.skew(
+        "n1",
+        Skew {
+            offset_ms: -3_000,
+            drift: 0.0,
+        },
+    );
+    for _ in 0..10 {
+        let to = "n1".to_string();
+        sim.send("c1", "n0", Time::Stamp { to })
+            .expect("stamp sends");
+        sim.run_for(Duration::from_millis(100)).expect("nodes step");
+    }
+    let (n1_wall, _, _, stamps) = read_clock(&mut sim, "n1");
+    assert_eq!(stamps.len(), 20);
+    for pair in stamps.chunks(2) {
+        // n1 stamps what it does after hearing from n0 later than n0 did, its own clock
+        // notwithstanding
+        assert!(pair[1] > pair[0], "{:?}", pair);
+        assert!(pair[1].wall_ms > n1_wall, "{:?}", pair);
+    }
+    assert!(stamps.windows(2).all(|w| w[0] < w[1]), "{:?}", stamps);
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Dice {
+    Roll,
+    RollOk { name: String, value: u64 },
+}
+
+// a node that names itself with a ulid when it starts, and answers every roll with a random
+// number
+struct Die {
+    name: String,
+}
+
+impl Node<(), Dice> for Die {
+    fn from_init(
+        _state: (),
+        _init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Dice>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: rng::ulid().to_string(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Dice>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = Dice::RollOk {
+            name: self.name.clone(),
+            value: rng::thread().gen(),
+        };
+        reply.send(output)
+    }
+}
+
+fn rolls(seed: u64) -> Vec<Dice> {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), Die>(()).expect("nodes start");
+    for dst in NODES.iter().cycle().take(20) {
+        sim.send("c1", dst, Dice::Roll).expect("request sends");
+    }
+    sim.run().expect("nodes step");
+    let replies = sim.replies("c1").expect("replies parse");
+    replies
+        .into_iter()
+        .map(|reply| reply.body.payload)
+        .collect()
+}
+
+#[test]
+fn what_nodes_draw_at_random_comes_from_the_seed() {
+    let seed = sim::seed().expect("seed is a number");
+    assert_eq!(rolls(seed), rolls(seed));
+    assert_ne!(rolls(seed), rolls(seed.wrapping_add(1)));
+}
+
+// a ring node with a bug: it takes gossip for everything there is, forgetting whatever it had
+// that the gossip didn't mention
+struct Forgetful(Ring);
+
+impl Node<(), Payload, Injected> for Forgetful {
+    fn from_init(
+        state: (),
+        init: Init,
+        inject: std::sync::mpsc::Sender<Event<Payload, Injected>>,
+    ) -> anyhow::Result<Self> {
+        Ring::from_init(state, init, inject).map(Self)
+    }
+
+    fn step(&mut self, input: Event<Payload, Injected>, output: &mut Output) -> anyhow::Result<()> {
+        if let Event::Message(Message {
+            body:
+                rustengan::Body {
+                    payload: Payload::Gossip { seen },
+                    ..
+                },
+            ..
+        }) = &input
+        {
+            self.0.messages = seen.clone();
+            return Ok(());
+        }
+        self.0.step(input, output)
+    }
+
+    fn status(&self) -> serde_json::Value {
+        self.0.status()
+    }
+}
+
+fn messages_only_grow(before: &serde_json::Value, after: &serde_json::Value) -> anyhow::Result<()> {
+    let before: BTreeSet<usize> = serde_json::from_value(before["messages"].clone())?;
+    let after: BTreeSet<usize> = serde_json::from_value(after["messages"].clone())?;
+    match before.difference(&after).next() {
+        Some(message) => anyhow::bail!("{} went missing", message),
+        None => Ok(()),
+    }
+}
+
+#[test]
+fn a_step_that_breaks_an_invariant_fails_the_run_there_and_then() {
+    let mut sim = Sim::new(23, &NODES);
+    sim.start_node::<(), Forgetful>("n2", ())
+        .expect("n2 starts");
+    sim.start::<(), Ring>(()).expect("nodes start");
+    sim.every(GOSSIP_EVERY, || Injected::Gossip);
+    sim.invariant("messages only grow", messages_only_grow);
+    // n1 gossips nothing to n2 before it's heard of the message
+    sim.send("c1", "n2", Payload::Broadcast { message: 1 })
+        .expect("request sends");
+    let error = sim.run_for(Duration::from_secs(1)).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.starts_with("n2 failed a step"), "{}", error);
+    assert!(error.contains(r#""type":"gossip""#), "{}", error);
+    assert!(
+        error.ends_with("broke messages only grow: 1 went missing"),
+        "{}",
+        error
+    );
+    assert!(sim.now() < GOSSIP_EVERY * 2, "{:?}", sim.now());
+}
+
+// a node that counts the distinct messages it's been sent, and, with a bug, counts a message
+// sent to it twice, twice
+struct Counting {
+    messages: BTreeSet<usize>,
+    count: usize,
+}
+
+impl Node<(), Payload> for Counting {
+    fn from_init(
+        _state: (),
+        _init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Payload>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            messages: BTreeSet::new(),
+            count: 0,
+        })
+    }
+
+    fn step(&mut self, input: Event<Payload>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        if let Payload::Broadcast { message } = reply.body.payload {
+            self.messages.insert(message);
+            self.count += 1;
+        }
+        reply.body.payload = Payload::BroadcastOk;
+        reply.send(output)
+    }
+
+    fn invariants(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.count == self.messages.len(),
+            "counted {} of {} messages",
+            self.count,
+            self.messages.len()
+        );
+        Ok(())
+    }
+}
+
+#[test]
+fn a_node_that_breaks_its_own_invariants_fails_the_run_at_the_step_that_did() {
+    let mut sim = Sim::new(29, &NODES);
+    sim.start::<(), Counting>(()).expect("nodes start");
+    for (message, dst) in [(1, "n0"), (2, "n1"), (3, "n0")] {
+        sim.send("c1", dst, Payload::Broadcast { message })
+            .expect("request sends");
+        sim.run_for(Duration::from_millis(20)).expect("nodes step");
+    }
+    sim.send("c1", "n1", Payload::Broadcast { message: 2 })
+        .expect("request sends");
+    let error = sim.run_for(Duration::from_millis(20)).unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.starts_with("n1 failed a step at 6"), "{}", error);
+    assert!(error.contains(r#""type":"broadcast""#), "{}", error);
+    assert!(
+        error.ends_with("broke its own invariants: counted 2 of 1 messages"),
+        "{}",
+        error
+    );
+}
+
+// counting nodes, with every client's link to them as bad as it gets, broadcast to by c1: the
+// run fails once a message arrives twice
+fn count_on_bad_links(
+    seed: u64,
+    schedule: Option<&Schedule>,
+) -> (Sim<Payload>, anyhow::Result<()>) {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), Counting>(()).expect("nodes start");
+    let Some(schedule) = schedule else {
+        for dst in NODES {
+            sim.link_faults(
+                "c1",
+                dst,
+                Faults {
+                    drop: 0.2,
+                    duplicate: 0.05,
+                    delay: 0.3,
+                    delay_by: Duration::from_millis(50),
+                    reorder: 0.2,
+                },
+            );
+        }
+        sim.record_choices();
+        for message in 0..40 {
+            let dst = NODES[message % NODES.len()];
+            sim.send("c1", dst, Payload::Broadcast { message })
+                .expect("request sends");
+            if let Err(e) = sim.run_for(Duration::from_millis(10)) {
+                return (sim, Err(e));
+            }
+        }
+        let ran = sim.run_for(Duration::from_secs(1));
+        return (sim, ran);
+    };
+    sim.replay(schedule);
+    let ran = sim.run_for(Duration::from_secs(2));
+    (sim, ran)
+}
+
+#[test]
+fn a_failing_schedule_shrinks_to_the_choices_that_make_it_fail() {
+    let (sim, ran) = count_on_bad_links(31, None);
+    let error = format!("{:#}", ran.expect_err("some message arrives twice"));
+    assert!(error.contains("broke its own invariants"), "{}", error);
+    let recorded = sim.recorded();
+    assert!(recorded.choices.len() > 10, "{}", recorded);
+
+    let fails = |schedule: &Schedule| count_on_bad_links(31, Some(schedule)).1.is_err();
+    assert!(fails(&recorded), "the recorded schedule replays");
+    let shrunk = shrink::shrink(&recorded, fails);
+    // the request, and its second copy
+    let [Choice::Send { frame: sent, .. }, Choice::Fault { frame, fault, .. }] =
+        shrunk.choices.as_slice()
+    else {
+        panic!("didn't shrink to a request and a fault:\n{}", shrunk);
+    };
+    assert_eq!(*fault, Fault::Duplicate, "{}", shrunk);
+    assert_eq!(sent, frame, "{}", shrunk);
+    let (_, ran) = count_on_bad_links(31, Some(&shrunk));
+    // and a node that's been sent nothing else counts it twice
+    assert!(format!("{:#}", ran.unwrap_err()).ends_with("counted 2 of 1 messages"));
+}
+
+#[test]
+fn a_failing_test_says_the_smallest_schedule_it_fails_with() {
+    let start = || {
+        let mut sim = Sim::new(37, &NODES);
+        sim.start::<(), Counting>(()).expect("nodes start");
+        sim.link_faults(
+            "c1",
+            "n0",
+            Faults {
+                duplicate: 0.2,
+                ..Faults::default()
+            },
+        );
+        sim
+    };
+    let checked = shrink::check(start, |sim| {
+        for message in 0..20 {
+            sim.send("c1", "n0", Payload::Broadcast { message })?;
+            sim.run_for(Duration::from_millis(10))?;
+        }
+        Ok(())
+    });
+    let error = format!("{:#}", checked.expect_err("a message arrives twice"));
+    assert!(error.starts_with("fails with 2 of the run's"), "{}", error);
+    assert!(error.contains("Duplicate"), "{}", error);
+}
@@ -0,0 +1,111 @@
+#[allow(dead_code)]
+#[path = "../src/bin/swim.rs"]
+mod swim;
+
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Split, Target};
+use rustengan::sim::Sim;
+use swim::Payload;
+
+type Cluster = Sim<Payload, swim::InjectedPayload>;
+
+const NODES: [&str; 5] = ["n0", "n1", "n2", "n3", "n4"];
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), swim::SwimNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(50), || swim::InjectedPayload::Tick);
+    sim
+}
+
+// what `via` makes of the cluster, as its members and the ones it suspects, if it answers within
+// a second
+fn view(sim: &mut Cluster, via: &str) -> Option<(Vec<String>, Vec<String>)> {
+    sim.take_replies("client").expect("replies parse");
+    sim.send("client", via, Payload::Members)
+        .expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies("client").expect("replies parse").pop()?;
+    match reply.body.payload {
+        Payload::MembersOk { members, suspected } => Some((members, suspected)),
+        _ => None,
+    }
+}
+
+fn components(components: &[&[&str]]) -> Disruption {
+    let components = components
+        .iter()
+        .map(|c| c.iter().map(|n| n.to_string()).collect())
+        .collect();
+    Disruption::Partition(Split::Components(components))
+}
+
+#[test]
+fn a_node_that_comes_back_after_being_declared_dead_rejoins_everyones_view() {
+    let mut sim = cluster(1);
+    sim.disrupt(Disruption::Kill(Target::Node("n4".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(5)).expect("nodes step");
+    let (members, _) = view(&mut sim, "n0").expect("n0 answers");
+    assert_eq!(members, ["n0", "n1", "n2", "n3"]);
+
+    // it comes back knowing nothing, and has to refute being dead with a newer incarnation
+    sim.disrupt(Disruption::Restart).expect("restarts");
+    sim.run_for(Duration::from_secs(5)).expect("nodes step");
+    for node in NODES {
+        let (members, suspected) = view(&mut sim, node).expect("answers");
+        assert_eq!(members, NODES, "{}'s view", node);
+        assert!(suspected.is_empty(), "{} suspects {:?}", node, suspected);
+    }
+}
+
+// the nodes lose, repeat and reorder what they send each other, are partitioned from each other
+// a couple of times, and one of them crashes for good in the middle of it, with the rest then left
+// to settle. Every live node has to end up seeing all the others alive, and the crashed one gone.
+#[test]
+fn every_live_node_settles_on_the_live_members_through_a_lossy_network_partitions_and_a_crash() {
+    for seed in [2, 3, 4] {
+        let mut sim = cluster(seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        sim.faults(Faults {
+            drop: 0.1,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        let crashed = NODES[rng.gen_range(0..NODES.len())];
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+        sim.disrupt(components(&[&["n0", "n1"], &["n2", "n3", "n4"]]))
+            .expect("partitions");
+        sim.run_for(Duration::from_secs(1)).expect("nodes step");
+        sim.disrupt(Disruption::Kill(Target::Node(crashed.to_string())))
+            .expect("kills");
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+        sim.disrupt(Disruption::Heal).expect("heals");
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+        sim.disrupt(components(&[&["n0", "n2", "n4"], &["n1", "n3"]]))
+            .expect("partitions");
+        sim.run_for(Duration::from_secs(3)).expect("nodes step");
+        sim.disrupt(Disruption::Heal).expect("heals");
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(8)).expect("nodes step");
+
+        let live: Vec<_> = NODES.into_iter().filter(|n| *n != crashed).collect();
+        for node in &live {
+            let (members, suspected) = view(&mut sim, node)
+                .unwrap_or_else(|| panic!("seed {}: {} doesn't answer", seed, node));
+            assert_eq!(members, live, "seed {}: {}'s view", seed, node);
+            assert!(
+                suspected.is_empty(),
+                "seed {}: {} suspects {:?}",
+                seed,
+                node,
+                suspected
+            );
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use rustengan::testing::TestNode;
+use rustengan::{error, Body, Message};
+
+// the nodes under test, built from the same sources as their own binaries
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+#[allow(dead_code)]
+#[path = "../src/bin/echo.rs"]
+mod echo;
+#[allow(dead_code)]
+#[path = "../src/bin/lww_kv.rs"]
+mod lww_kv;
+#[allow(dead_code)]
+#[path = "../src/bin/unique_ids.rs"]
+mod unique_ids;
+
+#[test]
+fn echo_answers_with_what_it_was_sent() {
+    let mut node =
+        TestNode::<echo::EchoNode, echo::Payload>::start((), "n0", &["n0"]).expect("node starts");
+    let echo = "hello".to_string();
+    let reply = node
+        .request("c1", echo::Payload::Echo { echo })
+        .expect("echo answers");
+    assert_eq!((reply.src.as_str(), reply.dst.as_str()), ("n0", "c1"));
+    assert!(matches!(reply.body.payload, echo::Payload::EchoOk { echo } if echo == "hello"));
+    assert!(node.sent().is_empty());
+}
+
+#[test]
+fn unique_ids_never_hands_out_the_same_id_twice() {
+    let mut node =
+        TestNode::<unique_ids::UniqueNode, unique_ids::Payload>::start((), "n1", &["n0", "n1"])
+            .expect("node starts");
+    let mut ids = HashSet::new();
+    for _ in 0..100 {
+        let reply = node
+            .request("c1", unique_ids::Payload::Generate)
+            .expect("node answers");
+        let unique_ids::Payload::GenerateOk { guid } = reply.body.payload else {
+            panic!("{:?} isn't a generate_ok", reply.body.payload);
+        };
+        assert!(guid.starts_with("n1-"), "{}", guid);
+        assert!(ids.insert(guid.clone()), "{} twice", guid);
+    }
+}
+
+type Broadcast = TestNode<
+    broadcast::BroadcastNode,
+    broadcast::Payload,
+    broadcast::InjectedPayload,
+    broadcast::Gossip,
+>;
+
+fn broadcast_node() -> Broadcast {
+    let mut node = Broadcast::start(broadcast::Gossip::default(), "n0", &["n0", "n1", "n2"])
+        .expect("node starts");
+    let topology = HashMap::from([("n0".to_string(), vec!["n1".to_string()])]);
+    node.request("c1", broadcast::Payload::Topology { topology })
+        .expect("node takes the topology");
+    node
+}
+
+fn gossip(src: &str, seen: &[usize]) -> Message<broadcast::Payload> {
+    Message {
+        src: src.to_string(),
+        dst: "n0".to_string(),
+        body: Body {
+            id: None,
+            in_reply_to: None,
+            correlation_id: None,
+            payload: broadcast::Payload::Gossip {
+                seen: seen.iter().copied().collect(),
+            },
+        },
+    }
+}
+
+fn read(node: &mut Broadcast) -> BTreeSet<usize> {
+    match node.request("c1", broadcast::Payload::Read) {
+        Ok(Message {
+            body:
+                Body {
+                    payload: broadcast::Payload::ReadOk { messages },
+                    ..
+                },
+            ..
+        }) => messages,
+        other => panic!("{:?} isn't a read_ok", other),
+    }
+}
+
+#[test]
+fn broadcast_gossips_to_its_neighbours_only_as_its_timer_ticks() {
+    let mut node = broadcast_node();
+    node.request("c1", broadcast::Payload::Broadcast { message: 1 })
+        .expect("node takes the broadcast");
+    assert!(node.sent().is_empty(), "gossip before the timer ticked");
+
+    assert_eq!(
+        node.advance(broadcast::GOSSIP_EVERY).expect("node gossips"),
+        1
+    );
+    let gossip = node.sent();
+    assert_eq!(gossip.len(), 1, "{:?}", gossip);
+    assert_eq!(gossip[0].dst, "n1");
+    assert!(matches!(
+        &gossip[0].body.payload,
+        broadcast::Payload::Gossip { seen } if *seen == BTreeSet::from([1])
+    ));
+}
+
+#[test]
+fn broadcast_takes_in_what_its_neighbours_gossip() {
+    let mut node = broadcast_node();
+    node.deliver(gossip("n1", &[2, 3]))
+        .expect("node takes the gossip");
+    // gossip isn't a request, so there's nothing to answer
+    assert!(node.sent().is_empty());
+    assert_eq!(read(&mut node), BTreeSet::from([2, 3]));
+}
+
+#[test]
+fn broadcast_measures_how_much_of_each_neighbours_gossip_it_already_knew() {
+    rustengan::metrics::enable();
+    // neighbours of their own, since the metrics are everyone's in this process
+    let mut node = Broadcast::start(broadcast::Gossip::default(), "n0", &["n0", "g1", "g2"])
+        .expect("node starts");
+    node.deliver(gossip("g1", &[1, 2]))
+        .expect("node takes the gossip");
+    node.deliver(gossip("g1", &[1, 2, 3]))
+        .expect("node takes the gossip");
+    node.deliver(gossip("g2", &[2, 3]))
+        .expect("node takes the gossip");
+
+    let by_neighbour = |name| {
+        rustengan::metrics::gauges(name)
+            .into_iter()
+            .map(|(labels, value)| (labels[0].1.clone(), value))
+            .filter(|(from, _)| from.starts_with('g'))
+            .collect::<HashMap<_, _>>()
+    };
+    // g1 told it 5 messages, 2 of which it knew, and g2 only ones it knew
+    let ratios = by_neighbour("rustengan_gossip_redundancy_ratio");
+    assert_eq!(
+        ratios,
+        HashMap::from([("g1".to_string(), 0.4), ("g2".to_string(), 1.0)])
+    );
+    let elements = rustengan::metrics::counters("rustengan_gossip_elements_total");
+    let count = |from: &str, new: &str| {
+        let labels = vec![
+            ("from".to_string(), from.to_string()),
+            ("new".to_string(), new.to_string()),
+        ];
+        elements.get(&labels).copied()
+    };
+    assert_eq!(count("g1", "true"), Some(3));
+    assert_eq!(count("g1", "false"), Some(2));
+    assert_eq!(count("g2", "false"), Some(2));
+}
+
+#[test]
+fn broadcast_gossips_to_a_fanout_of_its_neighbours_a_batch_at_a_time() {
+    let gossip = broadcast::Gossip {
+        fanout: 1,
+        batch: 2,
+        ..broadcast::Gossip::default()
+    };
+    let mut node = Broadcast::start(gossip, "n0", &["n0", "n1", "n2"]).expect("node starts");
+    let topology = HashMap::from([("n0".to_string(), vec!["n1".to_string(), "n2".to_string()])]);
+    node.request("c1", broadcast::Payload::Topology { topology })
+        .expect("node takes the topology");
+    for message in 1..=5 {
+        node.request("c1", broadcast::Payload::Broadcast { message })
+            .expect("node takes the broadcast");
+    }
+
+    node.advance(gossip.every).expect("node gossips");
+    let sent = node.sent();
+    assert_eq!(sent.len(), 1, "{:?}", sent);
+    let broadcast::Payload::Gossip { seen } = &sent[0].body.payload else {
+        panic!("{:?} isn't gossip", sent[0].body.payload);
+    };
+    assert_eq!(seen.len(), 2, "{:?}", seen);
+    assert!(seen.is_subset(&BTreeSet::from([1, 2, 3, 4, 5])));
+}
+
+type LwwKv = TestNode<lww_kv::LwwKvNode, lww_kv::Payload, lww_kv::InjectedPayload>;
+
+#[test]
+fn lww_kv_reads_its_own_writes_and_gossips_its_store_to_every_peer() {
+    let mut node = LwwKv::start((), "n0", &["n0", "n1", "n2"]).expect("node starts");
+    let missing = node
+        .request("c1", lww_kv::Payload::Read { key: 1 })
+        .expect("node answers");
+    assert!(matches!(
+        missing.body.payload,
+        lww_kv::Payload::Error { code, .. } if code == error::KEY_DOES_NOT_EXIST
+    ));
+
+    node.request("c1", lww_kv::Payload::Write { key: 1, value: 5 })
+        .expect("node answers");
+    let read = node
+        .request("c2", lww_kv::Payload::Read { key: 1 })
+        .expect("node answers");
+    assert!(matches!(
+        read.body.payload,
+        lww_kv::Payload::ReadOk { value: 5 }
+    ));
+
+    node.advance(lww_kv::GOSSIP_EVERY).expect("node gossips");
+    for peer in ["n1", "n2"] {
+        let gossip = node.sent_to(peer);
+        assert_eq!(gossip.len(), 1, "{}: {:?}", peer, gossip);
+        let lww_kv::Payload::Gossip { state } = &gossip[0].body.payload else {
+            panic!("{:?} isn't gossip", gossip[0].body.payload);
+        };
+        assert_eq!(state.get(&1), Some(&5));
+    }
+    assert!(node.sent().is_empty());
+}
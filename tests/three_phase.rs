@@ -0,0 +1,96 @@
+#[allow(dead_code)]
+#[path = "../src/bin/txn.rs"]
+mod txn;
+
+use std::time::Duration;
+
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Target};
+use rustengan::sim::Sim;
+use rustengan::txn::{Op, OpKind};
+
+type Cluster = Sim<txn::Payload, txn::InjectedPayload>;
+
+// n0 coordinates a write to keys 1 and 2, which n1 and n2 own
+fn coordinated(seed: u64) -> Cluster {
+    // every test in here runs 3pc, so it's the same whichever sets it
+    std::env::set_var("TXN_PROTOCOL", "3pc");
+    let mut sim = Sim::new(seed, &["n0", "n1", "n2"]);
+    sim.start::<(), txn::TxnNode>(()).expect("nodes start");
+    sim.every(Duration::from_millis(100), || txn::InjectedPayload::Tick);
+    let txn = vec![Op(OpKind::W, 1, Some(10)), Op(OpKind::W, 2, Some(10))];
+    sim.send("c1", "n0", txn::Payload::Txn { txn })
+        .expect("request sends");
+    sim
+}
+
+fn acknowledged(sim: &Cluster) -> bool {
+    let replies = sim.replies("c1").expect("replies parse");
+    replies
+        .iter()
+        .any(|reply| matches!(reply.body.payload, txn::Payload::TxnOk { .. }))
+}
+
+// both keys as of one snapshot, read through n1. None while a participant is still in doubt.
+fn read_both(sim: &mut Cluster) -> Option<Vec<Option<usize>>> {
+    let txn = vec![Op(OpKind::R, 1, None), Op(OpKind::R, 2, None)];
+    sim.send("reader", "n1", txn::Payload::Txn { txn })
+        .expect("read sends");
+    sim.run_for(Duration::from_millis(50)).expect("nodes step");
+    let read = sim.take_replies("reader").expect("replies parse").pop()?;
+    match read.body.payload {
+        txn::Payload::TxnOk { txn } => Some(txn.into_iter().map(|op| op.2).collect()),
+        _ => None,
+    }
+}
+
+#[test]
+fn participants_finish_a_3pc_transaction_among_themselves_without_its_coordinator() {
+    let mut sim = coordinated(51);
+    // n0 goes down before it hears a single vote
+    sim.link_faults("n1", "n0", Faults::cut())
+        .link_faults("n2", "n0", Faults::cut());
+    sim.run_for(Duration::from_millis(30)).expect("nodes step");
+    sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+        .expect("kills");
+    sim.run_for(Duration::from_secs(3)).expect("nodes step");
+    assert_eq!(read_both(&mut sim), Some(vec![None, None]));
+}
+
+#[test]
+fn a_participant_slow_to_say_it_pre_committed_isnt_left_out_of_termination() {
+    // n0 crashes somewhere between pre-committing n1 and pre-committing n2, so the two are left
+    // in different states, and n1's answers to n2 are held up for longer than n2 waits for them
+    for steps in 0..16 {
+        let mut sim = coordinated(52);
+        for _ in 0..steps {
+            sim.step().expect("nodes step");
+        }
+        sim.link_faults("n0", "n2", Faults::cut());
+        sim.run_for(Duration::from_millis(30)).expect("nodes step");
+        let acked = acknowledged(&sim);
+        sim.disrupt(Disruption::Kill(Target::Node("n0".to_string())))
+            .expect("kills");
+        sim.link_faults("n1", "n2", Faults::cut());
+        sim.run_for(Duration::from_secs(3)).expect("nodes step");
+        sim.link_faults("n1", "n2", Faults::default());
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+
+        let read = read_both(&mut sim);
+        if acked {
+            assert_eq!(
+                read,
+                Some(vec![Some(10), Some(10)]),
+                "after {} steps",
+                steps
+            );
+        } else {
+            assert!(
+                read == Some(vec![None, None]) || read == Some(vec![Some(10), Some(10)]),
+                "after {} steps: {:?}",
+                steps,
+                read
+            );
+        }
+    }
+}
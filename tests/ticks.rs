@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::Duration;
+
+use rustengan::ticks::{self, Manual};
+use rustengan::transport::channel::Network;
+use rustengan::{Body, Event, Init, Message, Node, Output};
+
+// the broadcast workload's node, built from the same source as its own binary
+#[allow(dead_code)]
+#[path = "../src/bin/broadcast.rs"]
+mod broadcast;
+
+use broadcast::{BroadcastNode, Gossip, InjectedPayload, Payload};
+
+#[test]
+fn a_manual_clock_ticks_each_timer_as_often_as_it_falls_due() {
+    let clock = Manual::install();
+    let (tx, rx) = std::sync::mpsc::channel();
+    ticks::every(Duration::from_millis(100), tx.clone(), || 'a');
+    ticks::every(Duration::from_millis(250), tx, || 'b');
+
+    assert_eq!(clock.advance(Duration::from_millis(500)), 7);
+    assert_eq!(clock.now(), Duration::from_millis(500));
+    // timers due at the same time tick in the order they were set
+    let ticks: String = rx.try_iter().collect();
+    assert_eq!(ticks, "aabaaab");
+
+    assert_eq!(clock.advance(Duration::from_millis(50)), 0);
+    assert_eq!(clock.advance(Duration::from_millis(50)), 1);
+    assert_eq!(rx.try_iter().collect::<String>(), "a");
+}
+
+#[test]
+fn a_manual_clock_doesnt_tick_on_its_own_or_for_timers_nobody_hears() {
+    let clock = Manual::install();
+    let (tx, rx) = std::sync::mpsc::channel();
+    ticks::every(Duration::from_millis(1), tx, || ());
+    std::thread::sleep(Duration::from_millis(20));
+    assert_eq!(rx.try_iter().count(), 0);
+
+    drop(rx);
+    assert_eq!(clock.advance(Duration::from_millis(20)), 0);
+}
+
+struct Stepped {
+    node: BroadcastNode,
+    output: Output,
+    injected: Receiver<Event<Payload, InjectedPayload>>,
+}
+
+// broadcast nodes on a line, stepped by the test as their ticks and messages come in
+struct Line {
+    nodes: BTreeMap<String, Stepped>,
+    // what the nodes write, for the test to route
+    outbox: Receiver<(String, String)>,
+    // whatever came back for clients
+    replies: Vec<Message<Payload>>,
+}
+
+impl Line {
+    fn new(ids: &[&str]) -> Self {
+        let node_ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        let (network, outbox) = Network::switched();
+        let mut line = Self {
+            nodes: BTreeMap::new(),
+            outbox,
+            replies: Vec::new(),
+        };
+        for id in &node_ids {
+            let (tx, injected): (Sender<_>, _) = std::sync::mpsc::channel();
+            let init = Init {
+                node_id: id.clone(),
+                node_ids: node_ids.clone(),
+            };
+            let node = BroadcastNode::from_init(Gossip::default(), init, tx).expect("node starts");
+            let output = Output::routed(Box::new(network.transport()));
+            let stepped = Stepped {
+                node,
+                output,
+                injected,
+            };
+            line.nodes.insert(id.clone(), stepped);
+        }
+        let topology: HashMap<String, Vec<String>> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(i, id)| {
+                let neighbours = [i.checked_sub(1), Some(i + 1)];
+                let neighbours = neighbours.into_iter().flatten();
+                let neighbours = neighbours.filter_map(|n| node_ids.get(n).cloned());
+                (id.clone(), neighbours.collect())
+            })
+            .collect();
+        for id in &node_ids {
+            let topology = topology.clone();
+            line.request(id, Payload::Topology { topology });
+        }
+        line
+    }
+
+    fn request(&mut self, dst: &str, payload: Payload) {
+        let message = Message {
+            src: "c1".to_string(),
+            dst: dst.to_string(),
+            body: Body {
+                id: Some(1),
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        };
+        self.deliver(message);
+        self.route();
+    }
+
+    fn deliver(&mut self, message: Message<Payload>) {
+        let Some(stepped) = self.nodes.get_mut(&message.dst) else {
+            self.replies.push(message);
+            return;
+        };
+        stepped
+            .node
+            .step(Event::Message(message), &mut stepped.output)
+            .expect("node steps");
+    }
+
+    // steps every node through the ticks it's had, in order, and then delivers what they sent
+    fn tick(&mut self) {
+        for stepped in self.nodes.values_mut() {
+            for event in stepped.injected.try_iter() {
+                stepped
+                    .node
+                    .step(event, &mut stepped.output)
+                    .expect("node steps");
+            }
+        }
+        self.route();
+    }
+
+    fn route(&mut self) {
+        while let Ok((_, frame)) = self.outbox.try_recv() {
+            self.deliver(serde_json::from_str(&frame).expect("frame parses"));
+        }
+    }
+
+    fn read(&mut self, dst: &str) -> BTreeSet<usize> {
+        self.request(dst, Payload::Read);
+        match self.replies.pop().map(|reply| reply.body.payload) {
+            Some(Payload::ReadOk { messages }) => messages,
+            other => panic!("{:?} isn't a read_ok", other),
+        }
+    }
+}
+
+#[test]
+fn gossip_crosses_a_line_of_nodes_one_hop_a_tick() {
+    let clock = Manual::install();
+    let mut line = Line::new(&["n0", "n1", "n2", "n3"]);
+    line.request("n0", Payload::Broadcast { message: 7 });
+
+    let mut rounds = 0;
+    while !line.read("n3").contains(&7) {
+        assert!(rounds < 10, "7 never got to n3");
+        assert_eq!(clock.advance(broadcast::GOSSIP_EVERY), 4);
+        line.tick();
+        rounds += 1;
+    }
+    // one hop a round, since each node gossips before it's heard what it's yet to pass on
+    assert_eq!(rounds, 3);
+    assert_eq!(clock.now(), broadcast::GOSSIP_EVERY * 3);
+    for id in ["n0", "n1", "n2"] {
+        assert_eq!(line.read(id), BTreeSet::from([7]), "{}", id);
+    }
+}
@@ -0,0 +1,179 @@
+#[allow(dead_code)]
+#[path = "../src/bin/wal_shipping.rs"]
+mod wal_shipping;
+
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::history::linearizable::check;
+use rustengan::history::{Op, Type};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Nemesis, Target};
+use rustengan::sim::Sim;
+use wal_shipping::Payload;
+
+type Cluster = Sim<Payload, wal_shipping::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+
+// n0 is the first member, so it starts out as the primary
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), wal_shipping::ShippingNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        wal_shipping::InjectedPayload::Tick
+    });
+    sim
+}
+
+// `request` from `client` through `via`, and whatever came back for it within a second
+fn ask(sim: &mut Cluster, client: &str, via: &str, request: Payload) -> Option<Payload> {
+    sim.take_replies(client).expect("replies parse");
+    sim.send(client, via, request).expect("request sends");
+    sim.run_for(Duration::from_secs(1)).expect("nodes step");
+    let reply = sim.take_replies(client).expect("replies parse").pop()?;
+    Some(reply.body.payload)
+}
+
+fn status(sim: &mut Cluster, node: &str) -> Option<(u64, String, u64)> {
+    match ask(sim, "admin", node, Payload::Status) {
+        Some(Payload::StatusOk {
+            epoch,
+            primary,
+            lsn,
+        }) => Some((epoch, primary, lsn)),
+        _ => None,
+    }
+}
+
+// clients reading, writing and cas-ing a couple of keys through any node, while the nodes repeat,
+// hold up and reorder what they send each other, one of them crashes and comes back from its log,
+// and an admin hands the primary over to a standby twice. A client waits up to a second for an
+// answer before it gives up and asks for something else.
+fn wal_shipping_history(seed: u64) -> Vec<Op> {
+    let mut sim = cluster(seed);
+    sim.faults(Faults {
+        duplicate: 0.1,
+        delay: 0.1,
+        delay_by: Duration::from_millis(50),
+        reorder: 0.1,
+        ..Faults::default()
+    });
+    sim.nemesis(
+        Nemesis::new()
+            .at(Duration::from_secs(2), Disruption::Kill(Target::Random))
+            .at(Duration::from_secs(4), Disruption::Restart)
+            .until(Duration::from_secs(10)),
+    );
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut promotions = vec![Duration::from_secs(8), Duration::from_secs(5)];
+    let mut asked = [None; 3];
+    while sim.now() < Duration::from_secs(12) {
+        if promotions.last().is_some_and(|at| sim.now() >= *at) {
+            promotions.pop();
+            let standby = NODES[rng.gen_range(0..NODES.len())];
+            sim.send("admin", standby, Payload::Promote)
+                .expect("promote sends");
+        }
+        for (client, asked) in asked.iter_mut().enumerate() {
+            let client = format!("c{}", client);
+            if !sim.take_replies(&client).expect("replies parse").is_empty() {
+                *asked = None;
+            }
+            if asked.is_some_and(|at| sim.now() - at < Duration::from_secs(1)) {
+                continue;
+            }
+            *asked = Some(sim.now());
+            let key = rng.gen_range(0..2);
+            let request = match rng.gen_range(0..3) {
+                0 => Payload::Read { key },
+                1 => Payload::Write {
+                    key,
+                    value: rng.gen_range(0..5),
+                },
+                _ => Payload::Cas {
+                    key,
+                    from: rng.gen_range(0..5),
+                    to: rng.gen_range(0..5),
+                },
+            };
+            let dst = NODES[rng.gen_range(0..NODES.len())];
+            sim.send(&client, dst, request).expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    sim.history()
+}
+
+#[test]
+fn wal_shipping_stays_linearizable_through_a_crash_and_controlled_failovers() {
+    for seed in [1, 2, 3] {
+        let history = wal_shipping_history(seed);
+        assert_eq!(check(&history), Ok(()), "seed {}", seed);
+        assert!(
+            history.iter().any(|op| op.kind == Type::Ok),
+            "seed {}: nothing was answered",
+            seed
+        );
+    }
+}
+
+// a writer writing through the primary while the nodes lose, repeat and reorder the segments and
+// their answers, and a standby crashes and comes back from its log. Once the faults are over every
+// standby has to have the primary's whole log, and a standby promoted then to have every write.
+#[test]
+fn standbys_catch_up_on_the_whole_log_through_a_lossy_network_and_a_crash() {
+    for seed in [4, 5, 6] {
+        let mut sim = cluster(seed);
+        sim.faults(Faults {
+            drop: 0.2,
+            duplicate: 0.1,
+            reorder: 0.1,
+            ..Faults::default()
+        });
+        let mut rng = StdRng::seed_from_u64(seed);
+        let standby = NODES[rng.gen_range(1..NODES.len())];
+        let mut last = [None; 3];
+        while sim.now() < Duration::from_secs(8) {
+            if sim.now() >= Duration::from_secs(2) && sim.now() < Duration::from_secs(4) {
+                sim.disrupt(Disruption::Kill(Target::Node(standby.to_string())))
+                    .expect("kills");
+            } else {
+                sim.disrupt(Disruption::Restart).expect("restarts");
+            }
+            let key = rng.gen_range(0..last.len());
+            let value = rng.gen_range(0..100);
+            let write = Payload::Write { key, value };
+            if let Some(Payload::WriteOk) = ask(&mut sim, "writer", "n0", write) {
+                last[key] = Some(value);
+            }
+        }
+        sim.faults(Faults::default());
+        sim.run_for(Duration::from_secs(2)).expect("nodes step");
+
+        let primary = status(&mut sim, "n0").expect("n0 answers");
+        for node in NODES {
+            assert_eq!(
+                status(&mut sim, node),
+                Some(primary.clone()),
+                "seed {}: {}'s status",
+                seed,
+                node
+            );
+        }
+        assert!(matches!(
+            ask(&mut sim, "admin", standby, Payload::Promote),
+            Some(Payload::PromoteOk { .. })
+        ));
+        for (key, value) in last.iter().enumerate() {
+            let read = match ask(&mut sim, "reader", standby, Payload::Read { key }) {
+                Some(Payload::ReadOk { value }) => Some(value),
+                _ => None,
+            };
+            assert_eq!(read, *value, "seed {}: key {} on {}", seed, key, standby);
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+
+use proptest::prelude::*;
+use rustengan::crdt::{Dot, LwwRegister};
+use rustengan::kv::KvRequest;
+use rustengan::txn::{Op, OpKind};
+use rustengan::{hlc, lamport, Body, Message};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// payloads in the shapes the workloads use: tagged by `type`, unit and struct variants, renamed
+// fields, optional fields, lists, maps and txn ops
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Payload {
+    Echo {
+        echo: String,
+    },
+    Generate,
+    GenerateOk {
+        #[serde(rename = "id")]
+        guid: String,
+    },
+    Broadcast {
+        message: u64,
+    },
+    ReadOk {
+        messages: Vec<u64>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        value: Option<i64>,
+    },
+    Topology {
+        topology: BTreeMap<String, Vec<String>>,
+    },
+    Txn {
+        txn: Vec<Op>,
+    },
+    Error {
+        code: u32,
+        text: String,
+    },
+}
+
+fn node_id() -> impl Strategy<Value = String> {
+    prop_oneof!["n[0-9]{1,2}", "c[0-9]{1,3}", Just("lin-kv".to_string())]
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        any::<usize>().prop_map(|key| Op(OpKind::R, key, None)),
+        (any::<usize>(), any::<Option<usize>>()).prop_map(|(key, value)| Op(OpKind::R, key, value)),
+        (any::<usize>(), any::<usize>()).prop_map(|(key, value)| Op(OpKind::W, key, Some(value))),
+    ]
+}
+
+fn payload() -> impl Strategy<Value = Payload> {
+    prop_oneof![
+        any::<String>().prop_map(|echo| Payload::Echo { echo }),
+        Just(Payload::Generate),
+        any::<String>().prop_map(|guid| Payload::GenerateOk { guid }),
+        any::<u64>().prop_map(|message| Payload::Broadcast { message }),
+        (
+            prop::collection::vec(any::<u64>(), 0..8),
+            any::<Option<i64>>()
+        )
+            .prop_map(|(messages, value)| Payload::ReadOk { messages, value }),
+        prop::collection::btree_map(node_id(), prop::collection::vec(node_id(), 0..4), 0..4)
+            .prop_map(|topology| Payload::Topology { topology }),
+        prop::collection::vec(op(), 0..6).prop_map(|txn| Payload::Txn { txn }),
+        (any::<u32>(), any::<String>()).prop_map(|(code, text)| Payload::Error { code, text }),
+    ]
+}
+
+fn body<P: std::fmt::Debug>(payload: impl Strategy<Value = P>) -> impl Strategy<Value = Body<P>> {
+    (any::<Option<usize>>(), any::<Option<usize>>(), payload).prop_map(
+        |(id, in_reply_to, payload)| Body {
+            id,
+            in_reply_to,
+            correlation_id: None,
+            payload,
+        },
+    )
+}
+
+fn message<P: std::fmt::Debug>(
+    payload: impl Strategy<Value = P>,
+) -> impl Strategy<Value = Message<P>> {
+    (node_id(), node_id(), body(payload)).prop_map(|(src, dst, body)| Message { src, dst, body })
+}
+
+fn kv_request() -> impl Strategy<Value = KvRequest> {
+    let value = || {
+        prop_oneof![
+            Just(Value::Null),
+            any::<i64>().prop_map(Value::from),
+            any::<String>().prop_map(Value::from),
+            prop::collection::vec(any::<u64>(), 0..4).prop_map(Value::from),
+        ]
+    };
+    prop_oneof![
+        any::<String>().prop_map(|key| KvRequest::Read { key }),
+        (any::<String>(), value()).prop_map(|(key, value)| KvRequest::Write { key, value }),
+        (any::<String>(), value(), value(), any::<bool>()).prop_map(
+            |(key, from, to, create_if_not_exists)| KvRequest::Cas {
+                key,
+                from,
+                to,
+                create_if_not_exists,
+            }
+        ),
+    ]
+}
+
+fn round_trip<T: Serialize + DeserializeOwned>(value: &T) -> T {
+    let json = serde_json::to_string(value).expect("serializes");
+    serde_json::from_str(&json).unwrap_or_else(|e| panic!("{} doesn't parse back: {}", json, e))
+}
+
+// `message` serialized, with a field nobody knows about added to it and to its body, which is
+// where the payload's fields are as well
+fn with_unknown_fields<P: Serialize>(message: &Message<P>, name: &str) -> Value {
+    let mut json = serde_json::to_value(message).expect("serializes");
+    let unknown = Value::from("from a newer version");
+    json[name] = unknown.clone();
+    json["body"][name] = unknown;
+    json
+}
+
+proptest! {
+    #[test]
+    fn messages_round_trip(message in message(payload())) {
+        prop_assert_eq!(round_trip(&message), message);
+    }
+
+    #[test]
+    fn messages_ignore_fields_they_dont_know(
+        message in message(payload()),
+        name in "x_[a-z]{1,8}",
+    ) {
+        let json = with_unknown_fields(&message, &name);
+        let parsed: Message<Payload> = serde_json::from_value(json).expect("parses");
+        prop_assert_eq!(parsed, message);
+    }
+
+    #[test]
+    fn messages_keep_maelstroms_field_names(message in message(payload())) {
+        let json = serde_json::to_value(&message).expect("serializes");
+        prop_assert!(json.get("dest").is_some());
+        prop_assert!(json["body"].get("type").is_some());
+        prop_assert!(json["body"].get("msg_id").is_some());
+        prop_assert!(json["body"].get("in_reply_to").is_some());
+    }
+
+    #[test]
+    fn kv_requests_round_trip(message in message(kv_request())) {
+        let json = serde_json::to_value(&message).expect("serializes");
+        let parsed: Message<KvRequest> = serde_json::from_value(json.clone()).expect("parses");
+        prop_assert_eq!(serde_json::to_value(&parsed).expect("serializes"), json);
+    }
+
+    #[test]
+    fn ops_round_trip_as_maelstrom_triples(op in op()) {
+        let json = serde_json::to_value(&op).expect("serializes");
+        prop_assert_eq!(json[0].as_str(), Some(if op.is_write() { "w" } else { "r" }));
+        prop_assert_eq!(round_trip(&op), op);
+    }
+
+    #[test]
+    fn timestamps_round_trip(
+        wall_ms in any::<u64>(),
+        logical in any::<u16>(),
+        time in any::<u64>(),
+        node in node_id(),
+    ) {
+        let ts = hlc::Timestamp { wall_ms, logical };
+        prop_assert_eq!(round_trip(&ts), ts);
+        let ts = lamport::Timestamp { time, node: node.clone() };
+        prop_assert_eq!(round_trip(&ts), ts.clone());
+        let dot = Dot { node: node.clone(), counter: time };
+        prop_assert_eq!(round_trip(&dot), dot);
+        let register = LwwRegister {
+            value: Payload::Broadcast { message: time },
+            ts: hlc::Timestamp { wall_ms, logical },
+            node,
+        };
+        prop_assert_eq!(round_trip(&register), register);
+    }
+}
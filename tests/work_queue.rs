@@ -0,0 +1,204 @@
+#[allow(dead_code)]
+#[path = "../src/bin/work_queue.rs"]
+mod work_queue;
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rustengan::failure_detector::FdEvent;
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Target};
+use rustengan::sim::Sim;
+use work_queue::Payload;
+
+type Cluster = Sim<Payload, work_queue::InjectedPayload>;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const VISIBILITY_MS: u64 = 1000;
+
+fn cluster(seed: u64) -> Cluster {
+    let mut sim = Sim::new(seed, &NODES);
+    sim.start::<(), work_queue::QueueNode>(())
+        .expect("nodes start");
+    sim.every(Duration::from_millis(100), || {
+        work_queue::InjectedPayload::Fd(FdEvent::Heartbeat)
+    });
+    sim
+}
+
+// what every client saw of the queue
+#[derive(Default)]
+struct Seen {
+    // ids of enqueues that were answered
+    enqueued: HashMap<u64, u64>,
+    // every hand-out, as item id and delivery
+    delivered: Vec<(u64, u32)>,
+    // hand-outs that were acked, and answered
+    acked: HashSet<(u64, u32)>,
+}
+
+// a producer enqueueing numbered items for the first eight seconds, and two consumers dequeueing
+// them, acking most straight away, some only after a while, maybe too late, and dropping the rest
+// on the floor to be handed out again, while the nodes lose, repeat and reorder what they send each
+// other and the leader crashes, and then the one that took over. The queue is drained once the
+// faults are over. Each client waits up to a second for an answer before it gives up.
+fn worked(seed: u64) -> Seen {
+    let mut sim = cluster(seed);
+    sim.faults(Faults {
+        drop: 0.1,
+        duplicate: 0.1,
+        reorder: 0.1,
+        ..Faults::default()
+    });
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut seen = Seen::default();
+    let mut next = 0;
+    // by client, what it asked for, and when
+    let mut asked: HashMap<&str, (usize, Payload, Duration)> = HashMap::new();
+    // by consumer, an item it's working on, and when it'll be done with it
+    let mut working: HashMap<&str, (Duration, Payload)> = HashMap::new();
+    // the leader, and then the node that took over from it, fail for good
+    let mut crashes = vec![
+        (Duration::from_secs(6), "n1"),
+        (Duration::from_secs(3), "n0"),
+    ];
+    // clients stop asking a node once it has crashed
+    let mut up = NODES.to_vec();
+    while sim.now() < Duration::from_secs(25) {
+        if sim.now() >= Duration::from_secs(10) {
+            sim.faults(Faults::default());
+        }
+        if let Some(&(at, node)) = crashes.last() {
+            if sim.now() >= at {
+                sim.disrupt(Disruption::Kill(Target::Node(node.to_string())))
+                    .expect("kills");
+                up.retain(|n| *n != node);
+                crashes.pop();
+            }
+        }
+        for client in ["producer", "c1", "c2"] {
+            let mut follow_up = None;
+            for reply in sim.take_replies(client).expect("replies parse") {
+                let Some((msg_id, request, _)) = asked.get(client) else {
+                    continue;
+                };
+                if reply.body.in_reply_to != Some(*msg_id) {
+                    continue;
+                }
+                match (request, reply.body.payload) {
+                    (Payload::Enqueue { item }, Payload::EnqueueOk { id }) => {
+                        let item = item.as_u64().expect("items are numbers");
+                        seen.enqueued.insert(item, id);
+                    }
+                    (
+                        Payload::Dequeue { .. },
+                        Payload::DequeueOk {
+                            id: Some(id),
+                            delivery: Some(delivery),
+                            ..
+                        },
+                    ) => {
+                        seen.delivered.push((id, delivery));
+                        let ack = Payload::Ack { id, delivery };
+                        match rng.gen_range(0..10) {
+                            0..6 => follow_up = Some(ack),
+                            6..8 => {
+                                let took =
+                                    Duration::from_millis(rng.gen_range(0..2 * VISIBILITY_MS));
+                                working.insert(client, (sim.now() + took, ack));
+                            }
+                            _ => {}
+                        }
+                    }
+                    (Payload::Ack { id, delivery }, Payload::AckOk) => {
+                        seen.acked.insert((*id, *delivery));
+                    }
+                    _ => {}
+                }
+                asked.remove(client);
+            }
+            if asked
+                .get(client)
+                .is_some_and(|(_, _, at)| sim.now() - *at < Duration::from_secs(1))
+            {
+                continue;
+            }
+            if working
+                .get(client)
+                .is_some_and(|(until, _)| sim.now() < *until)
+            {
+                continue;
+            }
+            let done = working.remove(client).map(|(_, ack)| ack);
+            let request = match follow_up.or(done) {
+                Some(ack) => ack,
+                None if client == "producer" => {
+                    if sim.now() >= Duration::from_secs(8) {
+                        continue;
+                    }
+                    next += 1;
+                    Payload::Enqueue { item: next.into() }
+                }
+                None => Payload::Dequeue {
+                    visibility_ms: Some(VISIBILITY_MS),
+                },
+            };
+            let dst = up[rng.gen_range(0..up.len())];
+            let msg_id = sim
+                .send(client, dst, request.clone())
+                .expect("request sends");
+            asked.insert(client, (msg_id, request, sim.now()));
+        }
+        sim.run_for(Duration::from_millis(rng.gen_range(5..30)))
+            .expect("nodes step");
+    }
+    seen
+}
+
+#[test]
+fn no_item_is_lost_or_handed_out_again_once_acked_through_a_lossy_network_and_leader_crashes() {
+    for seed in [1, 2, 3] {
+        let seen = worked(seed);
+        assert!(!seen.acked.is_empty(), "seed {}: nothing was acked", seed);
+
+        let ids: HashSet<_> = seen.enqueued.values().collect();
+        assert_eq!(
+            ids.len(),
+            seen.enqueued.len(),
+            "seed {}: two items got the same id",
+            seed
+        );
+        let delivered: HashSet<_> = seen.delivered.iter().map(|(id, _)| *id).collect();
+        for (item, id) in &seen.enqueued {
+            assert!(
+                delivered.contains(id),
+                "seed {}: item {} was enqueued as {} and never handed out",
+                seed,
+                item,
+                id
+            );
+        }
+
+        let handed_out: HashSet<_> = seen.delivered.iter().collect();
+        assert_eq!(
+            handed_out.len(),
+            seen.delivered.len(),
+            "seed {}: the same delivery went to two consumers",
+            seed
+        );
+        for (id, acked) in &seen.acked {
+            assert!(
+                !seen
+                    .delivered
+                    .iter()
+                    .any(|(other, delivery)| other == id && delivery > acked),
+                "seed {}: {} was handed out again after delivery {} was acked",
+                seed,
+                id,
+                acked
+            );
+        }
+    }
+}
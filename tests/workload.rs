@@ -0,0 +1,441 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use rustengan::history::workload::{self, Anomaly};
+use rustengan::session::{Direction, Record};
+use rustengan::sim::faults::Faults;
+use rustengan::sim::nemesis::{Disruption, Target};
+use rustengan::sim::Sim;
+use rustengan::{Body, Event, Init, Message, Node, Output};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const NODES: [&str; 3] = ["n0", "n1", "n2"];
+const TICK: Duration = Duration::from_millis(100);
+
+struct Tick;
+
+// `payload` from `src` to each of `dsts`
+fn tell<P: Serialize>(src: &str, dsts: &[String], payload: &P, output: &mut Output) {
+    for dst in dsts {
+        let message = Message {
+            src: src.to_string(),
+            dst: dst.clone(),
+            body: Body {
+                id: None,
+                in_reply_to: None,
+                correlation_id: None,
+                payload,
+            },
+        };
+        message.send(&mut *output).expect("routed");
+    }
+}
+
+fn others(init: &Init) -> Vec<String> {
+    init.node_ids
+        .iter()
+        .filter(|id| **id != init.node_id)
+        .cloned()
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Gossiped {
+    Broadcast { message: u64 },
+    BroadcastOk,
+    Read,
+    ReadOk { messages: BTreeSet<u64> },
+    Gossip { messages: BTreeSet<u64> },
+}
+
+// tells everyone else about each message once as it comes in, and, if it's to `repair`,
+// everything it has on every tick
+struct Flood {
+    node: String,
+    others: Vec<String>,
+    repair: bool,
+    messages: BTreeSet<u64>,
+}
+
+impl Node<bool, Gossiped, Tick> for Flood {
+    fn from_init(
+        repair: bool,
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Gossiped, Tick>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            others: others(&init),
+            node: init.node_id,
+            repair,
+            messages: BTreeSet::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Gossiped, Tick>, output: &mut Output) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(Tick) if self.repair => {
+                let gossip = Gossiped::Gossip {
+                    messages: self.messages.clone(),
+                };
+                tell(&self.node, &self.others, &gossip, output);
+                return Ok(());
+            }
+            Event::Injected(Tick) | Event::EOF => return Ok(()),
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Gossiped::Broadcast { message } => {
+                self.messages.insert(message);
+                let gossip = Gossiped::Gossip {
+                    messages: BTreeSet::from([message]),
+                };
+                tell(&self.node, &self.others, &gossip, output);
+                Gossiped::BroadcastOk
+            }
+            Gossiped::Read => Gossiped::ReadOk {
+                messages: self.messages.clone(),
+            },
+            Gossiped::Gossip { messages } => {
+                self.messages.extend(messages);
+                return Ok(());
+            }
+            Gossiped::BroadcastOk | Gossiped::ReadOk { .. } => return Ok(()),
+        };
+        reply.send(output)
+    }
+}
+
+// broadcasts 50 messages over a lossy network, lets it settle and reads every node
+fn broadcast(repair: bool) -> Vec<Anomaly> {
+    let mut sim = Sim::new(5, &NODES);
+    sim.start::<bool, Flood>(repair).expect("nodes start");
+    sim.every(TICK, || Tick);
+    sim.faults(Faults {
+        drop: 0.2,
+        ..Faults::default()
+    });
+    for message in 0..50 {
+        let dst = NODES[message as usize % NODES.len()];
+        sim.send("c0", dst, Gossiped::Broadcast { message })
+            .expect("request sends");
+        sim.run_for(Duration::from_millis(10)).expect("nodes step");
+    }
+    // replies from the read get lost too, unless the faults are gone
+    sim.faults(Faults::default());
+    sim.run_for(TICK * 5).expect("nodes step");
+    for dst in NODES {
+        sim.send("c1", dst, Gossiped::Read).expect("read sends");
+    }
+    sim.run_for(TICK).expect("nodes step");
+    workload::broadcast(&sim.sessions())
+}
+
+#[test]
+fn broadcast_that_repairs_what_was_lost_has_every_acknowledged_message_everywhere() {
+    assert_eq!(broadcast(true), vec![]);
+}
+
+#[test]
+fn broadcast_that_tells_each_message_once_loses_some() {
+    let anomalies = broadcast(false);
+    assert!(!anomalies.is_empty());
+    for anomaly in anomalies {
+        assert!(matches!(anomaly, Anomaly::Missing { .. }), "{}", anomaly);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Ids {
+    Generate,
+    GenerateOk { id: String },
+}
+
+// hands out its node id and a counter, kept in memory
+struct Counting {
+    node: String,
+    next: u64,
+}
+
+impl Node<(), Ids> for Counting {
+    fn from_init(
+        _state: (),
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Ids>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            node: init.node_id,
+            next: 0,
+        })
+    }
+
+    fn step(&mut self, input: Event<Ids>, output: &mut Output) -> anyhow::Result<()> {
+        let Event::Message(input) = input else {
+            return Ok(());
+        };
+        let mut reply = input.into_reply(None);
+        self.next += 1;
+        reply.body.payload = Ids::GenerateOk {
+            id: format!("{}-{}", self.node, self.next),
+        };
+        reply.send(output)
+    }
+}
+
+fn generate(crash: bool) -> Vec<Anomaly> {
+    let mut sim = Sim::new(6, &NODES);
+    sim.start::<(), Counting>(()).expect("nodes start");
+    for round in 0..30 {
+        for dst in NODES {
+            sim.send("c0", dst, Ids::Generate).expect("request sends");
+        }
+        sim.run_for(Duration::from_millis(20)).expect("nodes step");
+        if crash && round == 15 {
+            sim.disrupt(Disruption::Kill(Target::Node("n1".to_string())))
+                .expect("n1 is killed");
+            sim.disrupt(Disruption::Restart).expect("n1 restarts");
+        }
+    }
+    workload::unique_ids(&sim.sessions())
+}
+
+#[test]
+fn ids_from_a_counter_are_unique_while_nothing_crashes() {
+    assert_eq!(generate(false), vec![]);
+}
+
+#[test]
+fn ids_from_a_counter_kept_in_memory_repeat_after_a_crash() {
+    let anomalies = generate(true);
+    assert!(!anomalies.is_empty());
+    assert!(anomalies.iter().all(|anomaly| matches!(
+        anomaly,
+        Anomaly::Duplicate { first, second, .. } if first == "n1" && second == "n1"
+    )));
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum Counter {
+    Add { delta: i64 },
+    AddOk,
+    Read,
+    ReadOk { value: i64 },
+    Counts { counts: BTreeMap<String, i64> },
+}
+
+#[derive(Clone, Copy)]
+enum Merge {
+    // each node's count is the most anyone's said it is
+    Max,
+    // what's heard is added on, as if it were news every time
+    Sum,
+}
+
+// a grow-only counter, each node counting its own adds, and telling everyone else its counts
+// on every tick
+struct GCounter {
+    node: String,
+    others: Vec<String>,
+    merge: Merge,
+    counts: BTreeMap<String, i64>,
+}
+
+impl Node<Merge, Counter, Tick> for GCounter {
+    fn from_init(
+        merge: Merge,
+        init: Init,
+        _inject: std::sync::mpsc::Sender<Event<Counter, Tick>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            others: others(&init),
+            node: init.node_id,
+            merge,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    fn step(&mut self, input: Event<Counter, Tick>, output: &mut Output) -> anyhow::Result<()> {
+        let input = match input {
+            Event::Message(input) => input,
+            Event::Injected(Tick) => {
+                let counts = Counter::Counts {
+                    counts: self.counts.clone(),
+                };
+                tell(&self.node, &self.others, &counts, output);
+                return Ok(());
+            }
+            Event::EOF => return Ok(()),
+        };
+        let mut reply = input.into_reply(None);
+        reply.body.payload = match reply.body.payload {
+            Counter::Add { delta } => {
+                *self.counts.entry(self.node.clone()).or_default() += delta;
+                Counter::AddOk
+            }
+            Counter::Read => Counter::ReadOk {
+                value: self.counts.values().sum(),
+            },
+            Counter::Counts { counts } => {
+                for (node, count) in counts.into_iter().filter(|(n, _)| *n != self.node) {
+                    let ours = self.counts.entry(node).or_default();
+                    *ours = match self.merge {
+                        Merge::Max => (*ours).max(count),
+                        Merge::Sum => *ours + count,
+                    };
+                }
+                return Ok(());
+            }
+            Counter::AddOk | Counter::ReadOk { .. } => return Ok(()),
+        };
+        reply.send(output)
+    }
+}
+
+fn count(merge: Merge) -> Vec<Anomaly> {
+    let mut sim = Sim::new(7, &NODES);
+    sim.start::<Merge, GCounter>(merge).expect("nodes start");
+    sim.every(TICK, || Tick);
+    for delta in 1..=20 {
+        let dst = NODES[delta as usize % NODES.len()];
+        sim.send("c0", dst, Counter::Add { delta })
+            .expect("request sends");
+        sim.run_for(Duration::from_millis(30)).expect("nodes step");
+    }
+    sim.run_for(TICK * 5).expect("nodes step");
+    for dst in NODES {
+        sim.send("c1", dst, Counter::Read).expect("read sends");
+    }
+    sim.run_for(Duration::from_millis(20)).expect("nodes step");
+    workload::counter(&sim.sessions())
+}
+
+#[test]
+fn a_counter_that_merges_by_max_adds_up() {
+    assert_eq!(count(Merge::Max), vec![]);
+}
+
+#[test]
+fn a_counter_that_adds_up_what_it_hears_counts_it_again_and_again() {
+    let anomalies = count(Merge::Sum);
+    assert_eq!(anomalies.len(), NODES.len());
+    for anomaly in anomalies {
+        let Anomaly::Miscounted { read, most, .. } = anomaly else {
+            panic!("{} isn't a miscount", anomaly);
+        };
+        assert!(read > most);
+    }
+}
+
+// a node's session log of a client's requests and the node's replies, a millisecond apart
+fn session(exchanges: &[(serde_json::Value, serde_json::Value)]) -> Vec<Record> {
+    let mut records = Vec::new();
+    for (msg_id, (request, reply)) in exchanges.iter().enumerate() {
+        let msg_id = msg_id as u64 + 1;
+        let mut request = request.clone();
+        request["msg_id"] = json!(msg_id);
+        let mut reply = reply.clone();
+        reply["in_reply_to"] = json!(msg_id);
+        let time = msg_id * 2_000_000;
+        records.push(Record {
+            time,
+            direction: Direction::Received,
+            message: json!({ "src": "c0", "dest": "n0", "body": request }),
+        });
+        records.push(Record {
+            time: time + 1_000_000,
+            direction: Direction::Sent,
+            message: json!({ "src": "n0", "dest": "c0", "body": reply }),
+        });
+    }
+    records
+}
+
+fn sends() -> Vec<(serde_json::Value, serde_json::Value)> {
+    (0..3)
+        .map(|offset| {
+            (
+                json!({ "type": "send", "key": "k", "msg": 10 + offset }),
+                json!({ "type": "send_ok", "offset": offset }),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn a_log_that_polls_back_everything_sent_is_fine() {
+    let mut exchanges = sends();
+    exchanges.push((
+        json!({ "type": "poll", "offsets": { "k": 0 } }),
+        json!({ "type": "poll_ok", "msgs": { "k": [[0, 10], [1, 11], [2, 12]] } }),
+    ));
+    exchanges.push((
+        json!({ "type": "commit_offsets", "offsets": { "k": 2 } }),
+        json!({ "type": "commit_offsets_ok" }),
+    ));
+    assert_eq!(
+        workload::kafka(&[("n0".to_string(), session(&exchanges))]),
+        vec![]
+    );
+}
+
+#[test]
+fn a_log_that_skips_an_acknowledged_send_has_lost_it() {
+    let mut exchanges = sends();
+    exchanges.push((
+        json!({ "type": "poll", "offsets": { "k": 0 } }),
+        json!({ "type": "poll_ok", "msgs": { "k": [[0, 10], [2, 12]] } }),
+    ));
+    assert_eq!(
+        workload::kafka(&[("n0".to_string(), session(&exchanges))]),
+        vec![Anomaly::Lost {
+            key: "k".to_string(),
+            offset: 1
+        }]
+    );
+}
+
+#[test]
+fn a_log_committed_past_what_was_polled_has_lost_the_rest() {
+    let mut exchanges = sends();
+    exchanges.push((
+        json!({ "type": "poll", "offsets": { "k": 0 } }),
+        json!({ "type": "poll_ok", "msgs": { "k": [[0, 10]] } }),
+    ));
+    exchanges.push((
+        json!({ "type": "commit_offsets", "offsets": { "k": 1 } }),
+        json!({ "type": "commit_offsets_ok" }),
+    ));
+    let anomalies = workload::kafka(&[("n0".to_string(), session(&exchanges))]);
+    // offset 2 is past the commit, and may yet be polled
+    assert_eq!(
+        anomalies,
+        vec![Anomaly::Lost {
+            key: "k".to_string(),
+            offset: 1
+        }]
+    );
+}
+
+#[test]
+fn a_log_with_two_messages_at_an_offset_is_inconsistent() {
+    let mut exchanges = sends();
+    exchanges.push((
+        json!({ "type": "poll", "offsets": { "k": 1 } }),
+        json!({ "type": "poll_ok", "msgs": { "k": [[1, 99]] } }),
+    ));
+    assert_eq!(
+        workload::kafka(&[("n0".to_string(), session(&exchanges))]),
+        vec![Anomaly::Inconsistent {
+            key: "k".to_string(),
+            offset: 1,
+            messages: (json!(11), json!(99)),
+        }]
+    );
+}